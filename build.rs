@@ -0,0 +1,159 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// The build script generates the rook and bishop attack tables, so the
+// engine doesn't have to fill them every time it starts. The tables are
+// written into a binary file, which the move generator embeds. It uses the
+// magic numbers and indexing of src/movegen/magics.rs, so the tables are
+// laid out exactly as the move generator would lay them out itself.
+//
+// File layout (all values are little-endian u64's):
+//   checksum, rook table (ROOK_TABLE_SIZE), bishop table (BISHOP_TABLE_SIZE)
+//
+// If the environment variable RUSTIC_NO_EMBEDDED_TABLES is set, an empty
+// file is written, and the engine generates the tables at startup.
+
+use std::{env, fs, path::Path};
+
+// magics.rs expects these definitions in the crate root.
+mod defs {
+    pub type Bitboard = u64;
+
+    pub struct NrOf;
+    impl NrOf {
+        pub const SQUARES: usize = 64;
+    }
+}
+
+#[allow(dead_code)]
+#[path = "src/movegen/magics.rs"]
+mod magics;
+
+use defs::Bitboard;
+use magics::{Magic, BISHOP_MAGIC_NRS, ROOK_MAGIC_NRS};
+
+const FILE_NAME: &str = "slider_attacks.bin";
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(-1, 1), (1, 1), (1, -1), (-1, -1)];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/movegen/magics.rs");
+    println!("cargo:rerun-if-env-changed=RUSTIC_NO_EMBEDDED_TABLES");
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set.");
+    let path = Path::new(&out_dir).join(FILE_NAME);
+
+    let bytes = if env::var_os("RUSTIC_NO_EMBEDDED_TABLES").is_some() {
+        Vec::new()
+    } else {
+        let rook = attack_table(&ROOK_DIRECTIONS, &ROOK_MAGIC_NRS);
+        let bishop = attack_table(&BISHOP_DIRECTIONS, &BISHOP_MAGIC_NRS);
+        let words: Vec<u64> = rook.into_iter().chain(bishop).collect();
+
+        let mut bytes = checksum(&words).to_le_bytes().to_vec();
+        for w in words.iter() {
+            bytes.extend_from_slice(&w.to_le_bytes());
+        }
+        bytes
+    };
+
+    fs::write(path, bytes).expect("Writing the attack tables failed.");
+}
+
+// Generates the attack table of a slider moving in the given directions.
+fn attack_table(directions: &[(i8, i8)], magic_nrs: &[u64; 64]) -> Vec<Bitboard> {
+    let mut table = Vec::new();
+    let mut offset = 0;
+
+    for (sq, nr) in magic_nrs.iter().enumerate() {
+        let mask = slide(sq, directions, 0, true);
+        let bits = mask.count_ones();
+        let permutations = 1usize << bits;
+
+        let magic = Magic {
+            mask,
+            shift: (64 - bits) as u8,
+            offset,
+            nr: *nr,
+        };
+
+        table.resize(table.len() + permutations, 0);
+
+        // Carry-Rippler: visit every subset of the mask.
+        let mut blockers: Bitboard = 0;
+        loop {
+            table[magic.get_index(blockers)] = slide(sq, directions, blockers, false);
+            blockers = blockers.wrapping_sub(mask) & mask;
+            if blockers == 0 {
+                break;
+            }
+        }
+
+        offset += permutations as u64;
+    }
+
+    table
+}
+
+// Walks from the square in each direction until a blocker or the edge of
+// the board is hit. For a mask, the last square before the edge is left
+// out, because a piece there can't block anything.
+fn slide(sq: usize, directions: &[(i8, i8)], blockers: Bitboard, mask: bool) -> Bitboard {
+    let mut result = 0;
+
+    for (df, dr) in directions.iter() {
+        let mut file = (sq % 8) as i8;
+        let mut rank = (sq / 8) as i8;
+
+        loop {
+            file += df;
+            rank += dr;
+            if !(0..8).contains(&file) || !(0..8).contains(&rank) {
+                break;
+            }
+
+            let next_file = file + df;
+            let next_rank = rank + dr;
+            let at_edge = !(0..8).contains(&next_file) || !(0..8).contains(&next_rank);
+            if mask && at_edge {
+                break;
+            }
+
+            let square = 1u64 << (rank * 8 + file);
+            result |= square;
+            if square & blockers > 0 {
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+// Must be the same as the checksum in src/movegen/tables.rs.
+fn checksum(words: &[u64]) -> u64 {
+    words.iter().fold(0xcbf2_9ce4_8422_2325, |acc, w| {
+        (acc ^ w).rotate_left(5).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
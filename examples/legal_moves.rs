@@ -0,0 +1,49 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Lists the legal moves in a position, in coordinate notation and in SAN.
+// This example only uses the board and the move generator, so it also
+// builds without the engine:
+//
+// cargo run --example legal_moves --no-default-features -- "<fen>"
+//
+// Without a FEN, the moves in the starting position are listed.
+
+use rustic_alpha::{format, Board, MoveGenerator};
+
+fn main() {
+    let fen = std::env::args().nth(1);
+    let mg = MoveGenerator::new();
+    let mut board = Board::new();
+
+    if board.fen_read(fen.as_deref()).is_err() {
+        println!("Invalid FEN.");
+        std::process::exit(1);
+    }
+
+    let moves: Vec<_> = board.legal_moves_iter(&mg).collect();
+    for m in moves.iter() {
+        println!("{:<6} {}", m.as_string(), format::move_san(&board, &mg, *m));
+    }
+    println!("{} legal moves", moves.len());
+}
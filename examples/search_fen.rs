@@ -0,0 +1,53 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Uses Rustic as a library: lists the legal moves in a position, and
+// searches it to depth 6. Run it with "cargo run --example search_fen",
+// optionally followed by a FEN-string between quotes.
+
+use rustic_alpha::{search, Board, MoveGenerator, SearchMode, SearchParams};
+
+fn main() {
+    let fen = std::env::args().nth(1);
+    let mut board = Board::new();
+    if let Err(e) = board.fen_read(fen.as_deref()) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    let mg = MoveGenerator::shared();
+    let moves: Vec<String> = board.legal_moves_iter(&mg).map(|m| m.as_string()).collect();
+    println!("{} legal moves: {}", moves.len(), moves.join(" "));
+
+    let mut params = SearchParams::new();
+    params.search_mode = SearchMode::Depth;
+    params.depth = 6;
+    let outcome = search(&board, params);
+    println!(
+        "depth {} score {} nodes {} best move {}",
+        outcome.depth,
+        outcome.score.value(),
+        outcome.nodes,
+        outcome.best_move.as_string()
+    );
+}
@@ -0,0 +1,44 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Fuzzes the FEN-reader. Reading an FEN-string must never panic. If the
+// string is accepted, writing the position back out and reading that
+// FEN-string again must result in the same position.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rustic_alpha::Board;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(fen) = std::str::from_utf8(data) {
+        let mut board = Board::new();
+
+        if board.fen_read(Some(fen)).is_ok() {
+            let written = board.fen_write();
+            let mut reread = Board::new();
+
+            assert!(reread.fen_read(Some(&written)).is_ok());
+            assert_eq!(reread.fen_write(), written);
+        }
+    }
+});
@@ -0,0 +1,41 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Fuzzes the parser for moves in coordinate notation, such as "e2e4" or
+// "e7e8q". Parsing must never panic, a parsed move must have its squares
+// on the board, and parsing is not case sensitive.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rustic_alpha::parse;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(m) = std::str::from_utf8(data) {
+        let result = parse::algebraic_move_to_number(m);
+
+        if let Ok((from, to, _)) = result {
+            assert!(from < 64 && to < 64);
+            assert!(parse::algebraic_move_to_number(&m.to_ascii_lowercase()) == result);
+        }
+    }
+});
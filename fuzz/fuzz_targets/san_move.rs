@@ -0,0 +1,61 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Fuzzes the parser for moves in Standard Algebraic Notation. The first
+// byte picks one of a few positions with castling, en-passant captures
+// and promotions; the rest is the move. Parsing must never panic, a
+// parsed move must be legal, and writing it back out as SAN must lead to
+// the same move.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rustic_alpha::{format, parse, Board, MoveGenerator};
+
+const POSITIONS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+];
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((first, rest)) = data.split_first() {
+        if let Ok(san) = std::str::from_utf8(rest) {
+            let mg = MoveGenerator::shared();
+            let mut board = Board::new();
+            let fen = POSITIONS[*first as usize % POSITIONS.len()];
+            board.fen_read(Some(fen)).expect("valid FEN");
+
+            if let Ok(m) = parse::san_to_move(&board, &mg, san) {
+                let legal = board
+                    .legal_moves_iter(&mg)
+                    .any(|l| l.get_move() == m.get_move());
+                assert!(legal);
+
+                let written = format::move_san(&board, &mg, m);
+                let reread = parse::san_to_move(&board, &mg, &written);
+                assert!(reread.map(|r| r.get_move()) == Ok(m.get_move()));
+            }
+        }
+    }
+});
@@ -0,0 +1,130 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// api.rs is the entry point for programs that use Rustic as a library.
+// It searches a position without starting the engine: there are no
+// threads, channels or GUI involved. The search runs on the calling
+// thread until it reaches the limit given in the search parameters.
+
+use crate::{
+    board::Board,
+    engine::defs::{Information, SearchData, TT},
+    evaluation::{defs::PawnTable, Evaluation},
+    movegen::{defs::Move, MoveGenerator},
+    search::{
+        defs::{
+            Score, SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchReport,
+            SearchShared,
+        },
+        Search,
+    },
+};
+
+pub const API_HASH: usize = 16; // MB of TT for each search
+
+// The result of a search, as found by the last completed depth.
+#[derive(PartialEq, Clone)]
+pub struct SearchOutcome {
+    pub best_move: Move,           // Null move if there are no legal moves
+    pub ponder_move: Option<Move>, // Expected reply to the best move
+    pub score: Score,              // Score (centipawns or mate)
+    pub depth: i8,                 // Depth of the last completed iteration
+    pub nodes: usize,              // Nodes searched
+    pub pv: Vec<Move>,             // Principal Variation
+}
+
+// Searches the position with a TT of its own, using the move generator
+// shared by the process. The search parameters must include a limit:
+// modes that wait for a "stop" command (Infinite, Ponder) can't receive
+// one here, so they search to the given depth instead. The search uses
+// a single thread; the number of threads in the parameters is ignored.
+pub fn search(board: &Board, params: SearchParams) -> SearchOutcome {
+    let tt = TT::<SearchData>::new(API_HASH);
+    run(board, &MoveGenerator::shared(), &tt, params)
+}
+
+// The evaluation of the position, from the point of view of the side to
+// move, as the search would see it.
+pub fn evaluate_position(board: &Board) -> Score {
+    let mut pawn_table = PawnTable::new();
+    Evaluation::evaluate_position(board, &MoveGenerator::shared(), &mut pawn_table)
+}
+
+// Runs a search on the calling thread, with the given move generator and
+// TT. The position on the board is not changed.
+pub(crate) fn run(
+    board: &Board,
+    mg: &std::sync::Arc<MoveGenerator>,
+    tt: &TT<SearchData>,
+    mut params: SearchParams,
+) -> SearchOutcome {
+    params.quiet = true;
+    params.threads = 1;
+    params.helper = 0;
+    params.search_mode = match params.search_mode {
+        SearchMode::Infinite | SearchMode::Ponder | SearchMode::Compare | SearchMode::Nothing => {
+            SearchMode::Depth
+        }
+        mode => mode,
+    };
+
+    // The control channel must exist, but nothing is ever sent on it.
+    let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+    let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+    let mut board = board.clone();
+    let mut search_info = SearchInfo::new();
+    let shared = SearchShared::new();
+    tt.new_generation();
+    let mut refs = SearchRefs {
+        board: &mut board,
+        mg,
+        tt,
+        search_params: &mut params,
+        search_info: &mut search_info,
+        shared: &shared,
+        control_rx: &control_rx,
+        report_tx: &report_tx,
+    };
+
+    let (best_move, ponder_move, _) = Search::iterative_deepening(&mut refs);
+    let mut outcome = SearchOutcome {
+        best_move,
+        ponder_move,
+        score: Score::new(0),
+        depth: 0,
+        nodes: 0,
+        pv: Vec::new(),
+    };
+
+    // The summary of the last completed depth holds the score.
+    while let Ok(information) = report_rx.try_recv() {
+        if let Information::Search(SearchReport::SearchSummary(s)) = information {
+            outcome.score = s.score;
+            outcome.depth = s.depth;
+            outcome.nodes = s.nodes;
+            outcome.pv = s.pv;
+        }
+    }
+
+    outcome
+}
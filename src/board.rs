@@ -23,25 +23,26 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod defs;
 mod fen;
-mod gamestate;
+pub mod gamestate;
 mod history;
 mod playmove;
 mod utils;
 mod zobrist;
 
 use self::{
-    defs::{Pieces, BB_SQUARES},
+    defs::{MaterialSignature, Pieces, BB_LIGHT_SQUARES, BB_SQUARES, CASTLING_ROOKS},
     gamestate::GameState,
     history::History,
     zobrist::{ZobristKey, ZobristRandoms},
 };
 use crate::{
-    defs::{Bitboard, NrOf, Piece, Side, Sides, Square, EMPTY},
+    defs::{Bitboard, Castling, NrOf, Piece, Side, Sides, Square, EMPTY},
     evaluation::{
         defs::{FLIP, PHASE_VALUES, PST_EG, PST_MG},
         Evaluation,
     },
     misc::bits,
+    movegen::{defs::Move, LegalMoves, MoveGenerator},
 };
 use std::sync::Arc;
 
@@ -54,9 +55,17 @@ pub struct Board {
     pub game_state: GameState,
     pub history: History,
     pub piece_list: [Piece; NrOf::SQUARES],
+    pub castling_mask: [u8; NrOf::SQUARES],
+    pub chess960: bool,
     zr: Arc<ZobristRandoms>,
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Public functions for use by other modules.
 impl Board {
     // Creates a new board with either the provided FEN, or the starting position.
@@ -67,6 +76,8 @@ impl Board {
             game_state: GameState::new(),
             history: History::new(),
             piece_list: [Pieces::NONE; NrOf::SQUARES],
+            castling_mask: [Castling::ALL; NrOf::SQUARES],
+            chess960: false,
             zr: Arc::new(ZobristRandoms::new()),
         }
     }
@@ -91,6 +102,154 @@ impl Board {
         (self.game_state.active_color ^ 1) as usize
     }
 
+    // Returns the number of pieces of each type for both sides.
+    pub fn material_signature(&self) -> MaterialSignature {
+        self.game_state.material
+    }
+
+    // Returns the number of pieces of the given type for one side.
+    pub fn piece_count(&self, side: Side, piece: Piece) -> u8 {
+        self.game_state.material.count(side, piece)
+    }
+
+    // Returns the number of bishops and knights for one side.
+    pub fn minor_count(&self, side: Side) -> u8 {
+        self.piece_count(side, Pieces::BISHOP) + self.piece_count(side, Pieces::KNIGHT)
+    }
+
+    // Returns the number of queens and rooks for one side.
+    pub fn major_count(&self, side: Side) -> u8 {
+        self.piece_count(side, Pieces::QUEEN) + self.piece_count(side, Pieces::ROOK)
+    }
+
+    // Returns true if the side has any pieces other than pawns and king.
+    pub fn has_non_pawn_material(&self, side: Side) -> bool {
+        self.minor_count(side) + self.major_count(side) > 0
+    }
+
+    // Returns true if neither side has any pawns.
+    pub fn is_pawnless(&self) -> bool {
+        self.piece_count(Sides::WHITE, Pieces::PAWN) + self.piece_count(Sides::BLACK, Pieces::PAWN)
+            == 0
+    }
+
+    // Returns true if the side has enough material to force mate against a
+    // lone king: a pawn, a rook or queen, bishops on both square colors, a
+    // bishop and a knight, or three knights. Any number of bishops on
+    // squares of the same color can't mate. Neither can two knights: a
+    // mate exists only if the defending side helps, so this counts as
+    // insufficient.
+    pub fn has_sufficient_material(&self, side: Side) -> bool {
+        let bishops = self.bb_pieces[side][Pieces::BISHOP];
+        let knights = self.piece_count(side, Pieces::KNIGHT);
+        let light_bishop = bishops & BB_LIGHT_SQUARES > 0;
+        let dark_bishop = bishops & !BB_LIGHT_SQUARES > 0;
+
+        self.piece_count(side, Pieces::PAWN) > 0
+            || self.major_count(side) > 0
+            || (light_bishop && dark_bishop)
+            || (bishops > 0 && knights > 0)
+            || knights > 2
+    }
+
+    // Returns true if neither side can force mate. This includes one minor
+    // piece against one minor piece, such as KB vs KN: mate is only
+    // possible if the losing side blunders into it.
+    pub fn is_insufficient_material(&self) -> bool {
+        !self.has_sufficient_material(Sides::WHITE) && !self.has_sufficient_material(Sides::BLACK)
+    }
+
+    // Counts how often the current position occurred before, in the game
+    // and in the positions pushed by the search.
+    pub fn occurrences(&self) -> u8 {
+        let mut count = 0;
+        let length = self.history.len();
+
+        // The current position was created by the last irreversible move
+        // (a capture or pawn move) at the latest "halfmove_clock" plies
+        // ago. Positions before that move can never be repeated, so the
+        // scan is bounded there, both for positions played in the game
+        // (below the history root) and positions created by the search.
+        let hmc = self.game_state.halfmove_clock as usize;
+        let bound = length.saturating_sub(hmc);
+
+        // Search the history list backwards.
+        let mut i = length;
+        while i > bound {
+            i -= 1;
+
+            // If the historic zobrist key is equal to the one of the
+            // current position, then we found a repetition.
+            if self.history.get_ref(i).zobrist_key == self.game_state.zobrist_key {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // Returns true if the current position is a repetition the search
+    // should score as a draw. The history root splits the scan: a position
+    // that occurred after the root was repeated by the search itself, so
+    // a single earlier occurrence is enough. A position from the game
+    // (up to and including the root) must have occurred twice, because
+    // that would make this the third occurrence. The scan stops at the
+    // last irreversible move, as positions before it can't be repeated.
+    pub fn is_repetition_draw(&self) -> bool {
+        let length = self.history.len();
+        let root = self.history.root();
+        let hmc = self.game_state.halfmove_clock as usize;
+        let bound = length.saturating_sub(hmc);
+        let mut in_game = 0;
+
+        let mut i = length;
+        while i > bound {
+            i -= 1;
+
+            if self.history.get_ref(i).zobrist_key == self.game_state.zobrist_key {
+                if i > root {
+                    return true;
+                }
+
+                in_game += 1;
+                if in_game >= 2 {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    // Returns true if only kings and pawns are left on the board.
+    pub fn is_pawn_endgame(&self) -> bool {
+        !self.has_non_pawn_material(Sides::WHITE) && !self.has_non_pawn_material(Sides::BLACK)
+    }
+
+    // Returns true if the current position was reached by a null move.
+    pub fn last_move_is_null(&self) -> bool {
+        let length = self.history.len();
+        length > 0 && self.history.get_ref(length - 1).next_move.is_null()
+    }
+
+    // Returns an iterator over the legal moves in the current position.
+    // The board itself is not changed.
+    pub fn legal_moves_iter<'a>(
+        &'a self,
+        mg: &'a MoveGenerator,
+    ) -> impl Iterator<Item = Move> + 'a {
+        LegalMoves::new(self, mg)
+    }
+
+    // Returns true if the side that is not to move is in check. Such a
+    // position is illegal: the side to move could capture the king. If a
+    // side doesn't have exactly one king, there's nothing to check.
+    pub fn opponent_in_check(&self, mg: &MoveGenerator) -> bool {
+        let opponent = self.opponent();
+        let has_one_king = self.bb_pieces[opponent][Pieces::KING].count_ones() == 1;
+
+        has_one_king && mg.square_attacked(self, self.us(), self.king_square(opponent))
+    }
+
     // Returns the square the king is currently on.
     pub fn king_square(&self, side: Side) -> Square {
         self.bb_pieces[side][Pieces::KING].trailing_zeros() as Square
@@ -105,7 +264,11 @@ impl Board {
 
         // Incremental updates
         // =============================================================
+        if piece == Pieces::PAWN {
+            self.game_state.pawn_key ^= self.zr.piece(side, piece, square);
+        }
         self.game_state.phase_value -= PHASE_VALUES[piece];
+        self.game_state.material.remove(side, piece);
 
         let flip = side == Sides::WHITE;
         let s = if flip { FLIP[square] } else { square };
@@ -122,7 +285,11 @@ impl Board {
 
         // Incremental updates
         // =============================================================
+        if piece == Pieces::PAWN {
+            self.game_state.pawn_key ^= self.zr.piece(side, piece, square);
+        }
         self.game_state.phase_value += PHASE_VALUES[piece];
+        self.game_state.material.add(side, piece);
 
         let flip = side == Sides::WHITE;
         let s = if flip { FLIP[square] } else { square };
@@ -159,9 +326,26 @@ impl Board {
 
     // Update castling permissions and take Zobrist-key into account.
     pub fn update_castling_permissions(&mut self, new_permissions: u8) {
-        self.game_state.zobrist_key ^= self.zr.castling(self.game_state.castling);
+        self.game_state.zobrist_key ^= self.castling_key(self.game_state.castling);
         self.game_state.castling = new_permissions;
-        self.game_state.zobrist_key ^= self.zr.castling(self.game_state.castling);
+        self.game_state.zobrist_key ^= self.castling_key(self.game_state.castling);
+    }
+
+    // Returns the Zobrist key for the castling permissions. In Chess960,
+    // a castling rook may start on another square than the corner. The
+    // square is then hashed as well, so positions that only differ in the
+    // rook that may castle don't share a key.
+    pub fn castling_key(&self, permissions: u8) -> ZobristKey {
+        let mut key = self.zr.castling(permissions);
+
+        for (i, right) in Castling::EACH.iter().enumerate() {
+            let rook = self.game_state.castling_rooks[i];
+            if permissions & right > 0 && rook != CASTLING_ROOKS[i] {
+                key ^= self.zr.castling_rook(i, rook as usize);
+            }
+        }
+
+        key
     }
 }
 
@@ -174,6 +358,7 @@ impl Board {
         self.game_state = GameState::new();
         self.history.clear();
         self.piece_list = [Pieces::NONE; NrOf::SQUARES];
+        self.castling_mask = [Castling::ALL; NrOf::SQUARES];
     }
 
     // Main initialization function. This is used to initialize the "other"
@@ -188,10 +373,13 @@ impl Board {
         // Set initial phase value
         self.game_state.phase_value = Evaluation::count_phase(&self);
 
-        // Initialize the piece list, zobrist key, and material count. These will
-        // later be updated incrementally.
+        // Initialize the piece list, zobrist keys, and material count. These
+        // will later be updated incrementally.
         self.piece_list = self.init_piece_list();
         self.game_state.zobrist_key = self.init_zobrist_key();
+        self.game_state.pawn_key = self.init_pawn_key();
+        self.game_state.material = self.init_material_signature();
+        self.castling_mask = self.init_castling_mask();
 
         // Set initial PST_MG values
         let pst_mg = Evaluation::pst_apply(&self, &PST_MG);
@@ -204,6 +392,23 @@ impl Board {
         self.game_state.pst_eg[Sides::BLACK] = pst_eg.1;
     }
 
+    // Moving a piece from or to one of these squares loses castling
+    // permissions: the king's square loses both permissions of its side,
+    // and a castling rook's square loses the permission for that rook.
+    fn init_castling_mask(&self) -> [u8; NrOf::SQUARES] {
+        let mut mask = [Castling::ALL; NrOf::SQUARES];
+
+        for (i, right) in Castling::EACH.iter().enumerate() {
+            let king = self.king_square(i / 2);
+            if self.game_state.castling & right > 0 && king < NrOf::SQUARES {
+                mask[king] &= !right;
+                mask[self.game_state.castling_rooks[i] as usize] &= !right;
+            }
+        }
+
+        mask
+    }
+
     // Gather the pieces for each side into their own bitboard.
     fn init_pieces_per_side_bitboards(&self) -> (Bitboard, Bitboard) {
         let mut bb_white: Bitboard = 0;
@@ -291,11 +496,182 @@ impl Board {
         }
 
         // Hash the castling, active color, and en-passant state into the key.
-        key ^= self.zr.castling(self.game_state.castling);
+        key ^= self.castling_key(self.game_state.castling);
         key ^= self.zr.side(self.game_state.active_color as usize);
         key ^= self.zr.en_passant(self.game_state.en_passant);
 
         // Done; return the key.
         key
     }
+
+    // The pawn key only hashes the pawns of both sides. It is used to look
+    // up the pawn structure evaluation in the pawn hash table.
+    fn init_pawn_key(&self) -> ZobristKey {
+        let mut key: u64 = 0;
+
+        for side in [Sides::WHITE, Sides::BLACK] {
+            let mut pawns = self.bb_pieces[side][Pieces::PAWN];
+            while pawns > 0 {
+                let square = bits::next(&mut pawns);
+                key ^= self.zr.piece(side, Pieces::PAWN, square);
+            }
+        }
+
+        key
+    }
+
+    // Count the pieces of each type for both sides.
+    fn init_material_signature(&self) -> MaterialSignature {
+        let mut signature = MaterialSignature::new();
+
+        for side in [Sides::WHITE, Sides::BLACK] {
+            for (piece, bitboard) in self.bb_pieces[side].iter().enumerate() {
+                for _ in 0..bitboard.count_ones() {
+                    signature.add(side, piece);
+                }
+            }
+        }
+
+        signature
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        defs::{FEN_KIWIPETE_POSITION, FEN_START_POSITION},
+        misc::random::EngineRandom,
+    };
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let draws = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",     // K vs K
+            "4k3/8/8/8/8/4B3/8/2B1K3 w - - 0 1", // KBB, same color vs K
+            "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",   // KB vs K
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",  // KB vs KB, same color
+            "2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", // KB vs KB, other color
+            "1n2k3/8/8/8/8/8/8/2B1K3 w - - 0 1", // KB vs KN
+            "4k3/8/8/8/8/8/8/1N2K1N1 w - - 0 1", // KNN vs K
+            "1n2k1n1/8/8/8/8/8/8/4K3 b - - 0 1", // K vs KNN
+        ];
+        for fen in draws.iter() {
+            assert!(board(fen).is_insufficient_material(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn sufficient_material() {
+        let wins = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",   // KP vs K
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",    // KR vs K
+            "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1",  // KBB, both colors vs K
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",  // KBN vs K
+            "4k3/8/8/8/8/8/8/1N2KNN1 w - - 0 1", // KNNN vs K
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 1",    // KQ vs K
+        ];
+        for fen in wins.iter() {
+            let board = board(fen);
+            assert!(!board.is_insufficient_material(), "{}", fen);
+            assert!(board.has_sufficient_material(Sides::WHITE), "{}", fen);
+            assert!(!board.has_sufficient_material(Sides::BLACK), "{}", fen);
+        }
+    }
+
+    // Recounts the material on the bitboards, and compares the counts
+    // kept in the material signature, and the helpers using them.
+    fn assert_material(board: &Board) {
+        let count = |side: Side, piece: Piece| board.bb_pieces[side][piece].count_ones() as u8;
+        for side in [Sides::WHITE, Sides::BLACK] {
+            for piece in 0..NrOf::PIECE_TYPES {
+                assert_eq!(board.piece_count(side, piece), count(side, piece));
+            }
+            let minors = count(side, Pieces::BISHOP) + count(side, Pieces::KNIGHT);
+            let majors = count(side, Pieces::QUEEN) + count(side, Pieces::ROOK);
+            assert_eq!(board.minor_count(side), minors);
+            assert_eq!(board.major_count(side), majors);
+            assert_eq!(board.has_non_pawn_material(side), minors + majors > 0);
+        }
+
+        let pieces = (board.bb_side[Sides::WHITE] | board.bb_side[Sides::BLACK]).count_ones();
+        let pawns = count(Sides::WHITE, Pieces::PAWN) + count(Sides::BLACK, Pieces::PAWN);
+        assert_eq!(board.is_pawn_endgame(), pieces == 2 + pawns as u32);
+    }
+
+    // Plays random games, taking moves back at random, until the end. The
+    // material signature must match the board after every move.
+    #[test]
+    fn material_signature_matches_the_board() {
+        let mg = MoveGenerator::shared();
+        let mut random = EngineRandom::new(Some(1513));
+        let positions = [
+            FEN_START_POSITION,
+            FEN_KIWIPETE_POSITION,
+            "8/PPP4k/8/8/8/8/4Kppp/8 w - - 0 1",
+            "4k3/pp3n2/8/3b4/8/8/PP1R4/4K3 w - - 0 1",
+        ];
+        let mut pawn_endgames = 0;
+
+        for game in 0..200 {
+            let mut board = board(positions[game % positions.len()]);
+            assert_material(&board);
+
+            for _ in 0..150 {
+                if !board.history.is_empty() && random.below(6) == 0 {
+                    board.unmake();
+                } else {
+                    let moves: Vec<Move> = board.legal_moves_iter(&mg).collect();
+                    if moves.is_empty() {
+                        break;
+                    }
+                    board.make(moves[random.below(moves.len())], &mg);
+                }
+                assert_material(&board);
+                pawn_endgames += board.is_pawn_endgame() as usize;
+            }
+
+            while board.unmake() {
+                assert_material(&board);
+            }
+        }
+        assert!(pawn_endgames > 0);
+    }
+
+    // Earlier occurrences of the position are counted back to the last
+    // pawn move or capture; positions before it can't come back.
+    #[test]
+    fn occurrences_since_the_last_irreversible_move() {
+        let mg = MoveGenerator::shared();
+        let mut board = board(FEN_START_POSITION);
+        let play = |board: &mut Board, moves: &[&str]| {
+            for m in moves.iter() {
+                let m = board
+                    .legal_moves_iter(&mg)
+                    .find(|l| l.as_string() == *m)
+                    .expect(m);
+                assert!(board.make(m, &mg));
+            }
+        };
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+
+        assert_eq!(board.occurrences(), 0);
+        play(&mut board, &shuffle);
+        assert_eq!(board.occurrences(), 1);
+        play(&mut board, &shuffle[..2]);
+        assert_eq!(board.occurrences(), 1);
+        play(&mut board, &shuffle[2..]);
+        assert_eq!(board.occurrences(), 2);
+
+        play(&mut board, &["e2e3"]);
+        assert_eq!(board.occurrences(), 0);
+        play(&mut board, &["g8f6", "g1f3", "f6g8", "f3g1"]);
+        assert_eq!(board.occurrences(), 1);
+    }
 }
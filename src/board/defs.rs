@@ -21,8 +21,8 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use crate::defs::{Bitboard, NrOf, Piece, Square};
-use std::ops::RangeInclusive;
+use crate::defs::{Bitboard, NrOf, Piece, Side, Sides, Square, MAX_GAME_MOVES, MAX_MOVE_RULE};
+use std::{error::Error, fmt, ops::RangeInclusive};
 
 // Exports
 pub use super::zobrist::{ZobristKey, ZobristRandoms};
@@ -38,6 +38,7 @@ pub const SQUARE_NAME: [&str; NrOf::SQUARES] = [
     "a7", "b7", "c7", "d7", "e7", "f7", "g7", "h7",
     "a8", "b8", "c8", "d8", "e8", "f8", "g8", "h8"
 ];
+pub const SIDE_NAME: [&str; Sides::BOTH] = ["white", "black"];
 pub const PIECE_NAME: [&str; NrOf::PIECE_TYPES + 1] =
     ["King", "Queen", "Rook", "Bishop", "Knight", "Pawn", "-"];
 pub const PIECE_CHAR_CAPS: [&str; NrOf::PIECE_TYPES + 1] = ["K", "Q", "R", "B", "N", "", "_"];
@@ -54,6 +55,51 @@ impl Pieces {
     pub const NONE: Piece = 6;
 }
 
+// The number of pieces of each type for both sides, packed into one
+// number: four bits per piece type, white first. This allows a quick look
+// at the material on the board without counting bits in the bitboards.
+// Four bits are enough: even with all pawns promoted, a side can't have
+// more than ten pieces of one type.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MaterialSignature(u64);
+
+impl MaterialSignature {
+    const BITS: usize = 4;
+    const MASK: u64 = 0xF;
+
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn add(&mut self, side: Side, piece: Piece) {
+        self.0 += 1 << MaterialSignature::shift(side, piece);
+    }
+
+    pub fn remove(&mut self, side: Side, piece: Piece) {
+        self.0 -= 1 << MaterialSignature::shift(side, piece);
+    }
+
+    pub fn count(self, side: Side, piece: Piece) -> u8 {
+        ((self.0 >> MaterialSignature::shift(side, piece)) & MaterialSignature::MASK) as u8
+    }
+
+    // The packed counts, for use as a key (for example into a table of
+    // endgame specializations).
+    pub fn key(self) -> u64 {
+        self.0
+    }
+
+    fn shift(side: Side, piece: Piece) -> usize {
+        (side * NrOf::PIECE_TYPES + piece) * MaterialSignature::BITS
+    }
+}
+
+impl Default for MaterialSignature {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Files;
 impl Files {
     pub const A: usize = 0;
@@ -74,24 +120,10 @@ impl Ranks {
 
 pub struct Squares;
 impl Squares {
-    // White side squares that are important for castling
+    // Corner squares where the rooks start in normal chess
     pub const A1: Square = 0;
-    pub const B1: Square = 1;
-    pub const C1: Square = 2;
-    pub const D1: Square = 3;
-    pub const E1: Square = 4;
-    pub const F1: Square = 5;
-    pub const G1: Square = 6;
     pub const H1: Square = 7;
-
-    // Black side squares that are important for castling
     pub const A8: Square = 56;
-    pub const B8: Square = 57;
-    pub const C8: Square = 58;
-    pub const D8: Square = 59;
-    pub const E8: Square = 60;
-    pub const F8: Square = 61;
-    pub const G8: Square = 62;
     pub const H8: Square = 63;
 
     // White EP-squares start/end
@@ -103,6 +135,15 @@ impl Squares {
     pub const H6: Square = 47;
 }
 
+// Starting squares of the castling rooks in normal chess, in the order of
+// Castling::EACH. In Chess960, these are set from the FEN-string.
+pub const CASTLING_ROOKS: [u8; 4] = [
+    Squares::H1 as u8,
+    Squares::A1 as u8,
+    Squares::H8 as u8,
+    Squares::A8 as u8,
+];
+
 pub struct RangeOf;
 impl RangeOf {
     pub const RANKS: RangeInclusive<u8> = (Ranks::R1 as u8)..=(Ranks::R8 as u8);
@@ -157,6 +198,9 @@ pub const BB_FILES: TBBFiles = init_bb_files();
 pub const BB_RANKS: TBBRanks = init_bb_ranks();
 pub const BB_SQUARES: TBBSquares = init_bb_squares();
 
+// All the light squares on the board (b1, d1, ..., a2, c2, ...).
+pub const BB_LIGHT_SQUARES: Bitboard = 0x55AA_55AA_55AA_55AA;
+
 // Piece location: (file, rank)
 pub type Location = (u8, u8);
 
@@ -172,3 +216,96 @@ pub enum Direction {
     DownRight,
     DownLeft,
 }
+
+// Reasons why a FEN-string can't be set up. The errors for the parts of
+// the FEN-string hold the part as it was received. The errors after
+// those are for FEN-strings that can be read, but describe a position
+// the engine can't play.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FenError {
+    WrongPartCount(usize), // Number of parts found
+    PiecePlacement(String),
+    ActiveColor(String),
+    Castling(String),
+    EnPassant(String),
+    HalfmoveClock(String),
+    FullmoveNumber(String),
+    OpponentInCheck,             // Side not to move is in check
+    KingCount(Side, u32),        // Number of kings of the side
+    TooManyPawns(Side, u32),     // Number of pawns of the side
+    TooManyPieces(Side, u32),    // Number of pieces of the side
+    PawnOnBackRank(Square),      // Square of the pawn
+    ImpossibleCastling(String),  // The permission, as in KQkq
+    ImpossibleEnPassant(Square), // The en passant square
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::WrongPartCount(n) => {
+                write!(f, "FEN: must have 4 or 6 parts, but has {}", n)
+            }
+            FenError::PiecePlacement(p) => write!(
+                f,
+                "FEN: piece placement '{}' must have 8 ranks of 8 squares, using kqrbnpKQRBNP and 1-8",
+                p
+            ),
+            FenError::ActiveColor(p) => {
+                write!(f, "FEN: active color '{}' must be w or b", p)
+            }
+            FenError::Castling(p) => write!(
+                f,
+                "FEN: castling rights '{}' must be -, or up to 4 of KQkq or rook files",
+                p
+            ),
+            FenError::EnPassant(p) => write!(
+                f,
+                "FEN: en passant square '{}' must be -, or a square on rank 3 or 6",
+                p
+            ),
+            FenError::HalfmoveClock(p) => write!(
+                f,
+                "FEN: half-move clock '{}' must be a number from 0 to {}",
+                p, MAX_MOVE_RULE
+            ),
+            FenError::FullmoveNumber(p) => write!(
+                f,
+                "FEN: full-move number '{}' must be a number from 0 to {}",
+                p, MAX_GAME_MOVES
+            ),
+            FenError::OpponentInCheck => write!(f, "FEN: side not to move is in check"),
+            FenError::KingCount(side, n) => write!(
+                f,
+                "FEN: {} must have exactly one king, but has {}",
+                SIDE_NAME[*side], n
+            ),
+            FenError::TooManyPawns(side, n) => write!(
+                f,
+                "FEN: {} can have at most 8 pawns, but has {}",
+                SIDE_NAME[*side], n
+            ),
+            FenError::TooManyPieces(side, n) => write!(
+                f,
+                "FEN: {} can have at most 16 pieces, but has {}",
+                SIDE_NAME[*side], n
+            ),
+            FenError::PawnOnBackRank(square) => write!(
+                f,
+                "FEN: pawn on {} can't be on the first or last rank",
+                SQUARE_NAME[*square]
+            ),
+            FenError::ImpossibleCastling(p) => write!(
+                f,
+                "FEN: castling permission '{}' needs the king and the castling rook on the back rank",
+                p
+            ),
+            FenError::ImpossibleEnPassant(square) => write!(
+                f,
+                "FEN: en passant square {} needs a pawn that just moved two squares past it",
+                SQUARE_NAME[*square]
+            ),
+        }
+    }
+}
+
+impl Error for FenError {}
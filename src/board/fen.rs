@@ -21,20 +21,26 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-// fen.rs reads an FEN-string and converts it into a board position.
-// If the procedure fails, the original position is not changed. Note that
-// checking position legality is not the responsibility of this module. It
-// is perfectly possible to set up a position with two white kings, both
-// kings in check at the same time, or with black in check but white to
-// move.
+// fen.rs reads an FEN-string and converts it into a board position, and
+// writes the board position back out as an FEN-string.
+// If reading the FEN-string fails, the original position is not changed.
+// After reading, the position is checked for things the engine can't
+// play with: each side must have one king, at most 8 pawns and 16
+// pieces, no pawns on the first or last rank, castling permissions and
+// the en passant square must fit the pieces, and the side not to move
+// can't be in check. Positions that can't be reached in a game, but
+// break none of these rules, are accepted.
 
 use super::{
-    defs::{Files, Pieces, Ranks, Squares, BB_SQUARES},
+    defs::{FenError, Files, Pieces, Ranks, Squares, BB_RANKS, BB_SQUARES, SQUARE_NAME},
     Board,
 };
 use crate::{
-    defs::{Castling, Sides, Square, FEN_START_POSITION, MAX_GAME_MOVES, MAX_MOVE_RULE},
-    misc::parse,
+    defs::{
+        Castling, NrOf, Side, Sides, Square, FEN_START_POSITION, MAX_GAME_MOVES, MAX_MOVE_RULE,
+    },
+    misc::{format, parse},
+    movegen::MoveGenerator,
 };
 use if_chain::if_chain;
 use std::ops::RangeInclusive;
@@ -46,18 +52,30 @@ const LIST_OF_PIECES: &str = "kqrbnpKQRBNP";
 const EP_SQUARES_WHITE: RangeInclusive<Square> = Squares::A3..=Squares::H3;
 const EP_SQUARES_BLACK: RangeInclusive<Square> = Squares::A6..=Squares::H6;
 const WHITE_OR_BLACK: &str = "wb";
-const CASTLING_RIGHTS: &str = "KQkq-";
 const SPLITTER: char = '/';
 const DASH: char = '-';
 const EM_DASH: char = '–';
 const SPACE: char = ' ';
+const MAX_PAWNS: u32 = 8;
+const MAX_PIECES: u32 = 16;
 
 type FenPartParser = fn(board: &mut Board, part: &str) -> bool;
-type FenResult = Result<(), u8>;
+type FenPartError = fn(part: String) -> FenError;
+pub type FenResult = Result<(), FenError>;
 
 impl Board {
     // This function reads a provided FEN-string or uses the default position.
     pub fn fen_read(&mut self, fen_string: Option<&str>) -> FenResult {
+        self.fen_read_checked(fen_string, false)
+    }
+
+    // Reads a FEN-string as fen_read() does, but also accepts positions in
+    // which the side not to move is in check. (For debugging.)
+    pub fn fen_read_allow_check(&mut self, fen_string: Option<&str>) -> FenResult {
+        self.fen_read_checked(fen_string, true)
+    }
+
+    fn fen_read_checked(&mut self, fen_string: Option<&str>, allow_check: bool) -> FenResult {
         // Split the string into parts. There should be 6 parts.
         let mut fen_parts: Vec<String> = match fen_string {
             Some(f) => f,
@@ -73,37 +91,129 @@ impl Board {
         }
 
         // Check the number of fen parts.
-        let nr_of_parts_ok = fen_parts.len() == NR_OF_FEN_PARTS;
-
-        // Set the initial result.
-        let mut result: FenResult = if nr_of_parts_ok { Ok(()) } else { Err(0) };
-
-        if nr_of_parts_ok {
-            // Create an array of function pointers; one parsing function per part.
-            let fen_parsers: [FenPartParser; 6] = [pieces, color, castling, ep, hmc, fmn];
-
-            // Create a new board so we don't destroy the original.
-            let mut new_board = self.clone();
-            new_board.reset();
-
-            // Parse all the parts and check if each one succeeds.
-            let mut i: usize = 0;
-            while i < NR_OF_FEN_PARTS && result == Ok(()) {
-                let parser = &fen_parsers[i];
-                let part = &fen_parts[i];
-                let part_ok = parser(&mut new_board, part);
-                result = if part_ok { Ok(()) } else { Err(i as u8 + 1) };
-                i += 1;
+        if fen_parts.len() != NR_OF_FEN_PARTS {
+            return Err(FenError::WrongPartCount(fen_parts.len()));
+        }
+
+        // Create arrays of function pointers; one parsing function per
+        // part, and the error to return if parsing that part fails.
+        let fen_parsers: [FenPartParser; 6] = [pieces, color, castling, ep, hmc, fmn];
+        let fen_errors: [FenPartError; 6] = [
+            FenError::PiecePlacement,
+            FenError::ActiveColor,
+            FenError::Castling,
+            FenError::EnPassant,
+            FenError::HalfmoveClock,
+            FenError::FullmoveNumber,
+        ];
+
+        // Create a new board so we don't destroy the original.
+        let mut new_board = self.clone();
+        new_board.reset();
+
+        // Parse all the parts and stop at the first one that fails.
+        for (i, part) in fen_parts.into_iter().enumerate() {
+            if !fen_parsers[i](&mut new_board, &part) {
+                return Err(fen_errors[i](part));
+            }
+        }
+
+        // Replace original board with new one, if the engine can play
+        // the position.
+        new_board.init();
+        validate(&new_board, allow_check)?;
+        *self = new_board;
+
+        Ok(())
+    }
+
+    // This function writes the current position as an FEN-string.
+    pub fn fen_write(&self) -> String {
+        let mut ranks: Vec<String> = Vec::new();
+
+        // Part 1: Piece setup, from rank 8 down to rank 1.
+        for rank in (Ranks::R1..=Ranks::R8).rev() {
+            let mut fen_rank = String::from("");
+            let mut empty = 0;
+
+            for file in Files::A..=Files::H {
+                let square = (rank * 8) + file;
+                let piece = self.piece_list[square];
+
+                if piece == Pieces::NONE {
+                    empty += 1;
+                    continue;
+                }
+
+                if empty > 0 {
+                    fen_rank.push_str(&empty.to_string());
+                    empty = 0;
+                }
+
+                // LIST_OF_PIECES has the black pieces first, in the order
+                // of the piece numbers, followed by the white pieces.
+                let is_white = self.bb_side[Sides::WHITE] & BB_SQUARES[square] > 0;
+                let index = if is_white {
+                    piece + NrOf::PIECE_TYPES
+                } else {
+                    piece
+                };
+                fen_rank.push(LIST_OF_PIECES.as_bytes()[index] as char);
             }
 
-            // Replace original board with new one if setup was successful.
-            if result == Ok(()) {
-                new_board.init();
-                *self = new_board;
+            if empty > 0 {
+                fen_rank.push_str(&empty.to_string());
             }
+
+            ranks.push(fen_rank);
         }
 
-        result
+        // Part 2: Color to move.
+        let is_white = (self.game_state.active_color as usize) == Sides::WHITE;
+        let color = if is_white { 'w' } else { 'b' };
+
+        // Part 3 and 4: Castling rights and en passant square.
+        let castling = self.castling_as_fen();
+        let ep = match self.game_state.en_passant {
+            Some(square) => SQUARE_NAME[square as usize],
+            None => "-",
+        };
+
+        // Part 5 and 6: Half-move clock and full move number.
+        format!(
+            "{} {} {} {} {} {}",
+            ranks.join(&SPLITTER.to_string()),
+            color,
+            castling,
+            ep,
+            self.game_state.halfmove_clock,
+            self.game_state.fullmove_number
+        )
+    }
+
+    // In Chess960, the castling rights are written as the files of the
+    // castling rooks (Shredder-FEN), because "KQkq" can't always tell
+    // which rook may castle.
+    fn castling_as_fen(&self) -> String {
+        let permissions = self.game_state.castling;
+        if !self.chess960 || permissions == 0 {
+            return format::castling_as_string(permissions);
+        }
+
+        let mut castling = String::new();
+        for (i, right) in Castling::EACH.iter().enumerate() {
+            if permissions & right > 0 {
+                let file = SQUARE_NAME[self.game_state.castling_rooks[i] as usize];
+                let file = &file[..1];
+                if i < 2 {
+                    castling.push_str(&file.to_uppercase());
+                } else {
+                    castling.push_str(file);
+                }
+            }
+        }
+
+        castling
     }
 }
 
@@ -119,6 +229,12 @@ fn pieces(board: &mut Board, part: &str) -> bool {
 
     // Parse each character; it should be a piece, square count, or splitter.
     for c in part.chars() {
+        // A piece can't be put beyond the last file of the rank.
+        if LIST_OF_PIECES.contains(c) && file > Files::H as u8 {
+            result = false;
+            break;
+        }
+
         let square = ((rank * 8) + file) as usize;
         match c {
             'k' => board.bb_pieces[Sides::BLACK][Pieces::KING] |= BB_SQUARES[square],
@@ -137,11 +253,17 @@ fn pieces(board: &mut Board, part: &str) -> bool {
                 if let Some(x) = c.to_digit(10) {
                     file += x as u8;
                 }
+
+                // Empty squares can't run past the end of the rank.
+                result = file <= 8;
             }
             SPLITTER => {
-                result = file == 8;
-                rank -= 1;
-                file = 0;
+                // The rank must be complete, and there must be a next rank.
+                result = file == 8 && rank > Ranks::R1 as u8;
+                if result {
+                    rank -= 1;
+                    file = 0;
+                }
             }
             // Unknown character: result becomes false.
             _ => result = false,
@@ -158,7 +280,8 @@ fn pieces(board: &mut Board, part: &str) -> bool {
         }
     }
 
-    result
+    // All ranks must have been set up completely.
+    result && rank == Ranks::R1 as u8 && file == 8
 }
 
 // Part 2: Parse color to move: White or Black
@@ -186,7 +309,10 @@ fn color(board: &mut Board, part: &str) -> bool {
     result
 }
 
-// Part 3: Parse castling rights.
+// Part 3: Parse castling rights. Besides "KQkq", the files of the
+// castling rooks are accepted, as used for Chess960 (Shredder-FEN, such
+// as "HAha"). For "K" and "Q", the outermost rook on that side of the
+// king is the castling rook (X-FEN).
 fn castling(board: &mut Board, part: &str) -> bool {
     let length = part.len();
     let mut char_ok = 0;
@@ -194,18 +320,22 @@ fn castling(board: &mut Board, part: &str) -> bool {
     // There should be 1 to 4 castling rights. If no player has castling
     // rights, the character is '-'.
     if (1..=4).contains(&length) {
-        // Accepts "-" for no castling rights in addition to leaving out letters.
         for c in part.chars() {
-            if CASTLING_RIGHTS.contains(c) {
-                // Count correct characters
+            if c == DASH {
                 char_ok += 1;
-                match c {
-                    'K' => board.game_state.castling |= Castling::WK,
-                    'Q' => board.game_state.castling |= Castling::WQ,
-                    'k' => board.game_state.castling |= Castling::BK,
-                    'q' => board.game_state.castling |= Castling::BQ,
-                    _ => (),
-                }
+                continue;
+            }
+
+            let side = if c.is_ascii_uppercase() {
+                Sides::WHITE
+            } else {
+                Sides::BLACK
+            };
+
+            if let Some((right, rook)) = castling_rook(board, side, c.to_ascii_lowercase()) {
+                char_ok += 1;
+                board.game_state.castling |= Castling::EACH[right];
+                board.game_state.castling_rooks[right] = rook as u8;
             }
         }
     }
@@ -215,6 +345,44 @@ fn castling(board: &mut Board, part: &str) -> bool {
     (length >= 1) && (char_ok == length)
 }
 
+// Finds the castling rook for one character of the castling rights. This
+// returns the index of the right in Castling::EACH, and the rook's square.
+// If there is no rook for "K" or "Q", the corner is assumed, as before.
+fn castling_rook(board: &Board, side: Side, c: char) -> Option<(usize, Square)> {
+    let back_rank = if side == Sides::WHITE {
+        Ranks::R1
+    } else {
+        Ranks::R8
+    } * 8;
+    let rooks = board.bb_pieces[side][Pieces::ROOK];
+    let king_file = board
+        .king_square(side)
+        .checked_sub(back_rank)
+        .filter(|file| *file <= Files::H);
+    let is_rook = |file: &usize| rooks & BB_SQUARES[back_rank + file] > 0;
+    let is_kingside = |file: &usize| king_file.is_none_or(|king| *file > king);
+
+    let file = match c {
+        'k' => (Files::A..=Files::H)
+            .rev()
+            .find(|f| is_kingside(f) && is_rook(f))
+            .unwrap_or(Files::H),
+        'q' => (Files::A..=Files::H)
+            .find(|f| !is_kingside(f) && is_rook(f))
+            .unwrap_or(Files::A),
+        'a'..='h' if king_file.is_some() => c as usize - 'a' as usize,
+        _ => return None,
+    };
+
+    let kingside = match c {
+        'k' | 'q' => c == 'k',
+        _ => is_kingside(&file),
+    };
+    let right = side * 2 + if kingside { 0 } else { 1 };
+
+    Some((right, back_rank + file))
+}
+
 // Part 4: Parse the en passant square
 fn ep(board: &mut Board, part: &str) -> bool {
     let length = part.len();
@@ -283,3 +451,284 @@ fn fmn(board: &mut Board, part: &str) -> bool {
 
     result
 }
+
+// ===== Validation of the position =====
+
+// Checks if the position that was read can be played by the engine. The
+// kings are checked first, as the other checks need them.
+fn validate(board: &Board, allow_check: bool) -> FenResult {
+    for side in [Sides::WHITE, Sides::BLACK] {
+        let bb = &board.bb_pieces[side];
+        let kings = bb[Pieces::KING].count_ones();
+        let pawns = bb[Pieces::PAWN].count_ones();
+        let pieces = board.bb_side[side].count_ones();
+
+        if kings != 1 {
+            return Err(FenError::KingCount(side, kings));
+        }
+        if pawns > MAX_PAWNS {
+            return Err(FenError::TooManyPawns(side, pawns));
+        }
+        if pieces > MAX_PIECES {
+            return Err(FenError::TooManyPieces(side, pieces));
+        }
+    }
+
+    // Pawns can't stand on the first or last rank.
+    let bb_pawns =
+        board.bb_pieces[Sides::WHITE][Pieces::PAWN] | board.bb_pieces[Sides::BLACK][Pieces::PAWN];
+    let bb_back_ranks = BB_RANKS[Ranks::R1] | BB_RANKS[Ranks::R8];
+    if bb_pawns & bb_back_ranks > 0 {
+        let square = (bb_pawns & bb_back_ranks).trailing_zeros() as Square;
+        return Err(FenError::PawnOnBackRank(square));
+    }
+
+    validate_castling(board)?;
+    validate_ep(board)?;
+
+    if !allow_check && board.opponent_in_check(&MoveGenerator::shared()) {
+        return Err(FenError::OpponentInCheck);
+    }
+
+    Ok(())
+}
+
+// Each castling permission needs the king on its back rank, and the
+// castling rook on the same rank, on the side of the king it castles to.
+fn validate_castling(board: &Board) -> FenResult {
+    for (i, right) in Castling::EACH.iter().enumerate() {
+        if board.game_state.castling & right == 0 {
+            continue;
+        }
+
+        let side = i / 2;
+        let back_rank = if side == Sides::WHITE {
+            Ranks::R1
+        } else {
+            Ranks::R8
+        };
+        let king = board.king_square(side);
+        let rook = board.game_state.castling_rooks[i] as usize;
+        let is_rook = board.bb_pieces[side][Pieces::ROOK] & BB_SQUARES[rook] > 0;
+        let is_kingside = i % 2 == 0;
+        let rook_on_side = if is_kingside {
+            rook > king
+        } else {
+            rook < king
+        };
+
+        if king / 8 != back_rank || rook / 8 != back_rank || !is_rook || !rook_on_side {
+            let permission = format::castling_as_string(*right);
+            return Err(FenError::ImpossibleCastling(permission));
+        }
+    }
+
+    Ok(())
+}
+
+// The en passant square must be behind a pawn of the side that just
+// moved; that pawn must have passed it, coming from the square behind
+// it. So both those squares are empty.
+fn validate_ep(board: &Board) -> FenResult {
+    let square = match board.game_state.en_passant {
+        Some(square) => square as Square,
+        None => return Ok(()),
+    };
+
+    let (on_rank, pawn, from) = if board.us() == Sides::WHITE {
+        (EP_SQUARES_BLACK.contains(&square), square - 8, square + 8)
+    } else {
+        (EP_SQUARES_WHITE.contains(&square), square + 8, square - 8)
+    };
+
+    if !on_rank {
+        return Err(FenError::ImpossibleEnPassant(square));
+    }
+
+    let is_pawn = board.bb_pieces[board.opponent()][Pieces::PAWN] & BB_SQUARES[pawn] > 0;
+    let occupancy = board.occupancy();
+    let is_empty = occupancy & (BB_SQUARES[square] | BB_SQUARES[from]) == 0;
+
+    if !is_pawn || !is_empty {
+        return Err(FenError::ImpossibleEnPassant(square));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::FEN_KIWIPETE_POSITION;
+
+    const START_PIECES: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+
+    fn read(fen: &str) -> FenResult {
+        Board::new().fen_read(Some(fen))
+    }
+
+    #[test]
+    fn fen_write_gives_the_fen_that_was_read() {
+        let fens = [
+            FEN_START_POSITION,
+            FEN_KIWIPETE_POSITION,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/8/4k3/8/2pP4/8/8/4K3 b - d3 0 40",
+            "r3k3/8/8/8/8/8/8/4K2R b Kq - 17 61",
+        ];
+
+        for fen in fens.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            assert_eq!(board.fen_write(), *fen);
+        }
+    }
+
+    // The FEN-string follows the moves played on the board, and goes back
+    // when they are taken back.
+    #[test]
+    fn fen_write_after_moves() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(None).expect("valid FEN");
+
+        let expected = [
+            (
+                "e2e4",
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            ),
+            (
+                "c7c5",
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            ),
+            (
+                "g1f3",
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+            ),
+            (
+                "d7d6",
+                "rnbqkbnr/pp2pppp/3p4/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3",
+            ),
+            (
+                "e1e2",
+                "rnbqkbnr/pp2pppp/3p4/2p5/4P3/5N2/PPPPKPPP/RNBQ1B1R b kq - 1 3",
+            ),
+        ];
+        let mut played = vec![board.fen_write()];
+        for (m, fen) in expected.iter() {
+            let legal = board
+                .legal_moves_iter(&mg)
+                .find(|legal| legal.as_string() == *m)
+                .expect("legal move");
+            assert!(board.make(legal, &mg));
+            assert_eq!(board.fen_write(), *fen);
+            played.push(board.fen_write());
+        }
+
+        while played.len() > 1 {
+            played.pop();
+            board.unmake();
+            assert_eq!(&board.fen_write(), played.last().expect("a FEN"));
+        }
+    }
+
+    #[test]
+    fn each_fen_part_has_its_own_error() {
+        let part = |s: &str| String::from(s);
+        let cases = [
+            (String::from("8/8/8 w -"), FenError::WrongPartCount(3)),
+            (
+                String::from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1"),
+                FenError::PiecePlacement(part("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX")),
+            ),
+            (
+                format!("{} x KQkq - 0 1", START_PIECES),
+                FenError::ActiveColor(part("x")),
+            ),
+            (
+                format!("{} w KQkx - 0 1", START_PIECES),
+                FenError::Castling(part("KQkx")),
+            ),
+            (
+                format!("{} w KQkq e5 0 1", START_PIECES),
+                FenError::EnPassant(part("e5")),
+            ),
+            (
+                format!("{} w KQkq - x 1", START_PIECES),
+                FenError::HalfmoveClock(part("x")),
+            ),
+            (
+                format!("{} w KQkq - 0 x", START_PIECES),
+                FenError::FullmoveNumber(part("x")),
+            ),
+        ];
+
+        for (fen, error) in cases.iter() {
+            assert_eq!(read(fen), Err(error.clone()), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn unplayable_positions_have_their_own_error() {
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/8/8 w - - 0 1",
+                FenError::KingCount(Sides::WHITE, 0),
+            ),
+            (
+                "4k3/8/8/8/8/P7/PPPPPPPP/4K3 w - - 0 1",
+                FenError::TooManyPawns(Sides::WHITE, 9),
+            ),
+            (
+                "4k3/8/8/8/nnnnnnnn/nnnnnnnn/8/4K3 w - - 0 1",
+                FenError::TooManyPieces(Sides::BLACK, 17),
+            ),
+            (
+                "P3k3/8/8/8/8/8/8/4K3 w - - 0 1",
+                FenError::PawnOnBackRank(Squares::A8),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+                FenError::ImpossibleCastling(String::from("K")),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 b - h3 0 1",
+                FenError::ImpossibleEnPassant(Squares::H3),
+            ),
+            ("4k3/8/8/8/8/8/8/4R2K w - - 0 1", FenError::OpponentInCheck),
+        ];
+
+        for (fen, error) in cases.iter() {
+            assert_eq!(read(fen), Err(error.clone()), "{}", fen);
+        }
+    }
+
+    // Positions that can't come from the initial position in a normal
+    // game, or only through promotions, are still playable.
+    #[test]
+    fn unusual_positions_are_accepted() {
+        let fens = [
+            "6qk/6qq/8/8/8/8/8/1QQQK3 w - - 0 1",         // Several queens
+            "6k1/8/8/8/8/8/8/B1B1K1B1 w - - 0 1",         // Bishops on one color
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",          // Kings at home, ep square
+            "4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1",          // The same for black
+            "4k2r/8/8/8/8/8/8/R3K3 w Qk - 0 1",           // One rook per side
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",       // Only kings and rooks
+            "rnbqkbnr/8/8/8/8/8/8/RNBQKBNR w KQkq - 0 1", // No pawns
+        ];
+
+        for fen in fens.iter() {
+            assert_eq!(read(fen), Ok(()), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn valid_fen_strings_are_read() {
+        assert_eq!(read(FEN_START_POSITION), Ok(()));
+        assert_eq!(read(&format!("{} w KQkq -", START_PIECES)), Ok(()));
+        assert_eq!(
+            Board::new().fen_read_allow_check(Some("4k3/8/8/8/8/8/8/4R2K w - - 0 1")),
+            Ok(())
+        );
+    }
+}
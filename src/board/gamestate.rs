@@ -22,9 +22,9 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use crate::{
-    board::defs::{Pieces, PIECE_NAME, SQUARE_NAME},
+    board::defs::{MaterialSignature, Pieces, CASTLING_ROOKS, PIECE_NAME, SQUARE_NAME},
     defs::Sides,
-    misc::print,
+    misc::format,
     movegen::defs::Move,
 };
 
@@ -37,13 +37,16 @@ use crate::{
 pub struct GameState {
     pub active_color: u8,
     pub castling: u8,
+    pub castling_rooks: [u8; 4],
     pub halfmove_clock: u8,
     pub en_passant: Option<u8>,
     pub fullmove_number: u16,
     pub zobrist_key: u64,
+    pub pawn_key: u64,
     pub phase_value: i16,
     pub pst_mg: [i16; Sides::BOTH],
     pub pst_eg: [i16; Sides::BOTH],
+    pub material: MaterialSignature,
     pub next_move: Move,
 }
 
@@ -52,13 +55,16 @@ impl GameState {
         Self {
             active_color: 0,
             castling: 0,
+            castling_rooks: CASTLING_ROOKS,
             en_passant: None,
             halfmove_clock: 0,
             fullmove_number: 0,
             zobrist_key: 0,
+            pawn_key: 0,
             phase_value: 0,
             pst_mg: [0; Sides::BOTH],
             pst_eg: [0; Sides::BOTH],
+            material: MaterialSignature::new(),
             next_move: Move::new(0),
         }
     }
@@ -80,7 +86,7 @@ impl GameState {
             "zk: {:x} ac: {} cperm: {} ep: {} hmc: {} fmn: {}, pst_mg: {}/{}, pst_eg: {}/{} next: {}{}{}",
             self.zobrist_key,
             self.active_color,
-            print::castling_as_string(self.castling),
+            format::castling_as_string(self.castling),
             ep,
             self.halfmove_clock,
             self.fullmove_number,
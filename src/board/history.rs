@@ -35,10 +35,16 @@ use crate::defs::MAX_GAME_MOVES;
 //   necessary, such as during console play: the chess engine will always have
 //   one push for every pop during search.)
 
+// The history also keeps a "root" marker. This is the index at which the
+// game history ends and the search starts pushing its own game states.
+// Everything below the root belongs to the actual game; everything at or
+// above it was pushed by the search and will be popped again.
+
 #[derive(Clone)]
 pub struct History {
     list: [GameState; MAX_GAME_MOVES as usize],
     count: usize,
+    root: usize,
 }
 
 impl History {
@@ -47,6 +53,7 @@ impl History {
         Self {
             list: [GameState::new(); MAX_GAME_MOVES as usize],
             count: 0,
+            root: 0,
         }
     }
 
@@ -54,6 +61,7 @@ impl History {
     pub fn clear(&mut self) {
         self.list = [GameState::new(); MAX_GAME_MOVES as usize];
         self.count = 0;
+        self.root = 0;
     }
 
     // Put a new game state into the array.
@@ -77,4 +85,25 @@ impl History {
     pub fn len(&self) -> usize {
         self.count
     }
+
+    // Returns true if there is no game state to go back to.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Returns true if no more game states can be pushed.
+    pub fn is_full(&self) -> bool {
+        self.count >= MAX_GAME_MOVES
+    }
+
+    // Mark the current end of the history as the boundary between the
+    // game and the search.
+    pub fn set_root(&mut self) {
+        self.root = self.count;
+    }
+
+    // Returns the index of the game/search boundary.
+    pub fn root(&self) -> usize {
+        self.root
+    }
 }
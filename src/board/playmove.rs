@@ -24,11 +24,11 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 // playmove.rs contains make() and unamke() for move execution and reversal.
 
 use super::{
-    defs::{Pieces, Squares, BB_SQUARES},
+    defs::{Pieces, BB_SQUARES},
     Board,
 };
 use crate::{
-    defs::{Castling, NrOf, Piece, Side, Sides, Square},
+    defs::{Piece, Side, Sides, Square},
     evaluation::{
         defs::{PST_EG, PST_MG},
         Evaluation,
@@ -36,28 +36,6 @@ use crate::{
     movegen::{defs::Move, MoveGenerator},
 };
 
-// Castling Permissions Per Square
-type CPSquare = [u8; NrOf::SQUARES];
-const CASTLING_PERMS: CPSquare = castling_permissions_per_square();
-const fn castling_permissions_per_square() -> CPSquare {
-    // First set all squares grant all castling permissions. This means
-    // moving a piece on such square doesn't have any effect on castling
-    // permissions.
-    let mut cp: CPSquare = [Castling::ALL; NrOf::SQUARES];
-
-    // Now disable castling permissions when moving pieces on certain
-    // squares. For example, when the piece (rook) on A1 moves, disable
-    // white castling to the queenside.
-    cp[Squares::A1] &= !Castling::WQ;
-    cp[Squares::E1] &= !Castling::WK & !Castling::WQ;
-    cp[Squares::H1] &= !Castling::WK;
-    cp[Squares::A8] &= !Castling::BQ;
-    cp[Squares::E8] &= !Castling::BK & !Castling::BQ;
-    cp[Squares::H8] &= !Castling::BK;
-
-    cp
-}
-
 /*** ================================================================================ ***/
 
 // Make() executes the given move and checks if it is legal. If it's not legal,
@@ -103,14 +81,20 @@ impl Board {
         if is_capture {
             self.remove_piece(opponent, captured, to);
             self.game_state.halfmove_clock = 0;
-            // Change castling permissions on rook capture in the corner.
+            // Change castling permissions on capturing a castling rook.
             if captured == Pieces::ROOK && has_permissions {
-                self.update_castling_permissions(self.game_state.castling & CASTLING_PERMS[to]);
+                self.update_castling_permissions(self.game_state.castling & self.castling_mask[to]);
             }
         }
 
-        // Make the move. Just move the piece if it's not a pawn.
-        if piece != Pieces::PAWN {
+        // Make the move. When castling, the king "captures" its own rook.
+        // The rook is taken off first, because in Chess960 the king or the
+        // rook may end up on the square where the other one started.
+        if castling {
+            self.remove_piece(us, Pieces::ROOK, to);
+            self.move_piece(us, piece, from, m.castling_king_to());
+            self.put_piece(us, Pieces::ROOK, m.castling_rook_to());
+        } else if piece != Pieces::PAWN {
             self.move_piece(us, piece, from, to);
         } else {
             // It's a pawn move. Take promotion into account and reset halfmove_clock.
@@ -132,18 +116,7 @@ impl Board {
         // Remove castling permissions if king/rook leaves from starting square.
         // (This will also adjust permissions when castling, because the king moves.)
         if (piece == Pieces::KING || piece == Pieces::ROOK) && has_permissions {
-            self.update_castling_permissions(self.game_state.castling & CASTLING_PERMS[from]);
-        }
-
-        // If the king is castling, then also move the rook.
-        if castling {
-            match to {
-                Squares::G1 => self.move_piece(us, Pieces::ROOK, Squares::H1, Squares::F1),
-                Squares::C1 => self.move_piece(us, Pieces::ROOK, Squares::A1, Squares::D1),
-                Squares::G8 => self.move_piece(us, Pieces::ROOK, Squares::H8, Squares::F8),
-                Squares::C8 => self.move_piece(us, Pieces::ROOK, Squares::A8, Squares::D8),
-                _ => panic!("Error moving rook during castling."),
-            }
+            self.update_castling_permissions(self.game_state.castling & self.castling_mask[from]);
         }
 
         // Swap the side to move.
@@ -157,7 +130,8 @@ impl Board {
         /*** Validating move: see if "us" is in check. If so, undo everything. ***/
         let is_legal = !mg.square_attacked(self, opponent, self.king_square(us));
         if !is_legal {
-            self.unmake();
+            let taken_back = self.unmake();
+            debug_assert!(taken_back);
         }
 
         // When running in debug mode, check the incrementally updated
@@ -172,19 +146,35 @@ impl Board {
 /*** ================================================================================ ***/
 
 // Unmake() reverses the last move. The game state is restored by popping it
-// from the history array, all variables at once.
+// from the history array, all variables at once. If there is no move to take
+// back, the board is left alone and false is returned.
 impl Board {
     #[cfg_attr(debug_assertions, inline(never))]
     #[cfg_attr(not(debug_assertions), inline(always))]
-    pub fn unmake(&mut self) {
+    pub fn unmake(&mut self) -> bool {
+        // Nothing to take back: the position is as it was set up.
+        if self.history.is_empty() {
+            return false;
+        }
+
         self.game_state = self.history.pop();
 
+        // The move to undo is only kept in the history. The current game
+        // state has no next move, so the restored one is exactly the same
+        // as before the move was made.
+        let m = self.game_state.next_move;
+        self.game_state.next_move = Move::new(0);
+
+        // A null move has no pieces to put back.
+        if m.is_null() {
+            return true;
+        }
+
         // Set "us" and "opponent"
         let us = self.us();
         let opponent = us ^ 1;
 
         // Dissect the move to undo
-        let m = self.game_state.next_move;
         let piece = m.piece();
         let from = m.from();
         let to = m.to();
@@ -193,26 +183,19 @@ impl Board {
         let castling = m.castling();
         let en_passant = m.en_passant();
 
-        // Moving backwards...
-        if promoted == Pieces::NONE {
+        // Moving backwards... When castling, the rook is taken off first
+        // for the same reason as in make().
+        if castling {
+            remove_piece(self, us, Pieces::ROOK, m.castling_rook_to());
+            reverse_move(self, us, piece, m.castling_king_to(), from);
+            put_piece(self, us, Pieces::ROOK, to);
+        } else if promoted == Pieces::NONE {
             reverse_move(self, us, piece, to, from);
         } else {
             remove_piece(self, us, promoted, to);
             put_piece(self, us, Pieces::PAWN, from);
         }
 
-        // The king's move was already undone as a normal move.
-        // Now undo the correct castling rook move.
-        if castling {
-            match to {
-                Squares::G1 => reverse_move(self, us, Pieces::ROOK, Squares::F1, Squares::H1),
-                Squares::C1 => reverse_move(self, us, Pieces::ROOK, Squares::D1, Squares::A1),
-                Squares::G8 => reverse_move(self, us, Pieces::ROOK, Squares::F8, Squares::H8),
-                Squares::C8 => reverse_move(self, us, Pieces::ROOK, Squares::D8, Squares::A8),
-                _ => panic!("Error: Reversing castling rook move."),
-            };
-        }
-
         // If a piece was captured, put it back onto the to-square
         if captured != Pieces::NONE {
             put_piece(self, opponent, captured, to);
@@ -222,6 +205,52 @@ impl Board {
         if en_passant {
             put_piece(self, opponent, Pieces::PAWN, to ^ 8);
         }
+
+        // The pieces must be back where they were when the restored Zobrist
+        // key was stored.
+        debug_assert!(check_incrementals(self));
+
+        true
+    }
+
+    // Make a null move: the side to move passes, without moving a piece.
+    // The game state is pushed onto the history like a normal move, so
+    // the history keeps one entry per ply and unmake_null_move() restores
+    // the Zobrist key exactly. The halfmove clock is reset, so repetition
+    // detection never looks back beyond the null move.
+    pub fn make_null_move(&mut self) {
+        let mut current_game_state = self.game_state;
+        current_game_state.next_move = Move::new(0);
+        self.history.push(current_game_state);
+
+        if self.game_state.en_passant.is_some() {
+            self.clear_ep_square();
+        }
+
+        self.game_state.halfmove_clock = 0;
+
+        if self.us() == Sides::BLACK {
+            self.game_state.fullmove_number += 1;
+        }
+
+        self.swap_side();
+
+        debug_assert!(check_incrementals(self));
+    }
+
+    // Take back the null move, by restoring the previous game state.
+    pub fn unmake_null_move(&mut self) {
+        self.game_state = self.history.pop();
+        self.game_state.next_move = Move::new(0);
+    }
+
+    // Unmake all the moves that were made after the history root was set.
+    // This restores the board to the game position the search started
+    // from, even if the search was aborted somewhere down the tree.
+    pub fn unmake_to_root(&mut self) {
+        while self.history.len() > self.history.root() {
+            self.unmake();
+        }
     }
 }
 
@@ -264,9 +293,13 @@ fn reverse_move(board: &mut Board, side: Side, piece: Piece, remove: Square, put
 
 fn check_incrementals(board: &Board) -> bool {
     let from_scratch_key = board.init_zobrist_key();
+    let from_scratch_pawn_key = board.init_pawn_key();
     let from_scratch_phase_value = Evaluation::count_phase(board);
     let from_scratch_pst_mg = Evaluation::pst_apply(board, &PST_MG);
     let from_scratch_pst_eg = Evaluation::pst_apply(board, &PST_EG);
+    let from_scratch_material = board.init_material_signature();
+    let from_scratch_piece_list = board.init_piece_list();
+    let from_scratch_bb_side = board.init_pieces_per_side_bitboards();
     let mut result = true;
 
     // Waterfall: only report first error encountered and skip any others.
@@ -275,6 +308,11 @@ fn check_incrementals(board: &Board) -> bool {
         result = false;
     };
 
+    if result && from_scratch_pawn_key != board.game_state.pawn_key {
+        println!("Check Incrementals: Error in pawn key.");
+        result = false;
+    };
+
     if result && from_scratch_phase_value != board.game_state.phase_value {
         println!("Check Incrementals: Error in phase value.");
         result = false;
@@ -300,5 +338,179 @@ fn check_incrementals(board: &Board) -> bool {
         result = false;
     };
 
+    if result && from_scratch_material != board.game_state.material {
+        println!("Check Incrementals: Error in material signature.");
+        result = false;
+    };
+
+    // Promotions, en-passant captures and castling move pieces on more
+    // than one square. The piece list and side bitboards must still agree
+    // with the piece bitboards.
+    if result && from_scratch_piece_list != board.piece_list {
+        println!("Check Incrementals: Error in piece list.");
+        result = false;
+    };
+
+    if result
+        && (from_scratch_bb_side.0 != board.bb_side[Sides::WHITE]
+            || from_scratch_bb_side.1 != board.bb_side[Sides::BLACK])
+    {
+        println!("Check Incrementals: Error in side bitboards.");
+        result = false;
+    };
+
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::check_incrementals;
+    use crate::{
+        board::{defs::Pieces, Board},
+        misc::random::EngineRandom,
+        movegen::{defs::Move, MoveGenerator},
+    };
+
+    // A null move passes the turn and clears the en-passant square. The
+    // incrementally updated zobrist key must be the one computed from
+    // scratch, and taking the null moves back restores the position.
+    #[test]
+    fn null_move_keeps_the_zobrist_key() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/ppp1pppp/8/8/2Pp4/5P2/PP1PP1PP/RNBQKBNR b KQkq c3 0 2",
+        ];
+
+        for fen in fens.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let before = (board.fen_write(), board.game_state.as_string());
+            let us = board.us();
+
+            board.make_null_move();
+            assert!(check_incrementals(&board), "{}", fen);
+            assert!(board.game_state.en_passant.is_none());
+            assert_eq!(board.us(), us ^ 1);
+            assert_eq!(board.game_state.zobrist_key, board.init_zobrist_key());
+
+            board.make_null_move();
+            assert!(check_incrementals(&board), "{}", fen);
+            assert_eq!(board.us(), us);
+
+            board.unmake_null_move();
+            board.unmake_null_move();
+            assert_eq!((board.fen_write(), board.game_state.as_string()), before);
+        }
+    }
+
+    // Positions full of promotions, en passant and castling. The last one
+    // is a Chess960 position.
+    const RANDOM_GAME_POSITIONS: [(&str, bool); 5] = [
+        ("r3k2r/1P4P1/8/2pP4/8/8/1p4p1/R3K2R w KQkq c6 0 1", false),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            false,
+        ),
+        (
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            false,
+        ),
+        ("8/PPP4k/8/8/8/8/4Kppp/8 w - - 0 1", false),
+        (
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            true,
+        ),
+    ];
+
+    fn is_special(m: &Move) -> bool {
+        m.promoted() != Pieces::NONE || m.en_passant() || m.castling()
+    }
+
+    // Plays random games, choosing promotions, en-passant captures and
+    // castling whenever it can in two out of three moves, and taking moves
+    // back at random. After every make() and unmake(), the material must
+    // match a recount, and so must everything else that is kept up to
+    // date incrementally. Taking back the whole game restores the
+    // position it started from.
+    #[test]
+    fn random_games_keep_the_material() {
+        let mg = MoveGenerator::shared();
+        let mut random = EngineRandom::new(Some(1533));
+        let mut special_moves = [0; 3];
+
+        for game in 0..300 {
+            let (fen, chess960) = RANDOM_GAME_POSITIONS[game % RANDOM_GAME_POSITIONS.len()];
+            let mut board = Board::new();
+            board.chess960 = chess960;
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let start = (board.fen_write(), board.game_state.zobrist_key);
+
+            for _ in 0..80 {
+                if !board.history.is_empty() && random.below(8) == 0 {
+                    assert!(board.unmake());
+                    assert_eq!(board.game_state.material, board.init_material_signature());
+                    assert!(check_incrementals(&board), "{}", fen);
+                    continue;
+                }
+
+                let moves: Vec<Move> = board.legal_moves_iter(&mg).collect();
+                let special: Vec<Move> = moves.iter().copied().filter(is_special).collect();
+                let m = match (moves.len(), special.len()) {
+                    (0, _) => break,
+                    (_, s) if s > 0 && random.below(3) > 0 => special[random.below(s)],
+                    (n, _) => moves[random.below(n)],
+                };
+
+                let kinds = [m.promoted() != Pieces::NONE, m.en_passant(), m.castling()];
+                for (count, kind) in special_moves.iter_mut().zip(kinds.iter()) {
+                    *count += *kind as usize;
+                }
+
+                assert!(board.make(m, &mg));
+                assert_eq!(board.game_state.material, board.init_material_signature());
+                assert!(check_incrementals(&board), "{} {}", fen, m.as_string());
+            }
+
+            while board.unmake() {
+                assert_eq!(board.game_state.material, board.init_material_signature());
+            }
+            assert_eq!((board.fen_write(), board.game_state.zobrist_key), start);
+        }
+
+        // Promotions, en-passant captures and castling moves were played.
+        assert!(
+            special_moves.iter().all(|count| *count > 50),
+            "{:?}",
+            special_moves
+        );
+    }
+
+    // unmake() only takes back moves that were made on this board. A fresh
+    // board and a freshly loaded FEN-string have nothing to take back.
+    #[test]
+    fn unmake_without_make() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        let empty = board.fen_write();
+        assert!(!board.unmake());
+        assert_eq!(board.fen_write(), empty);
+
+        // After exactly one move, unmake() takes it back once.
+        board.fen_read(None).expect("start position");
+        let start = (board.fen_write(), board.game_state.zobrist_key);
+        let m = board.legal_moves_iter(&mg).next().expect("legal move");
+        assert!(board.make(m, &mg));
+        assert!(board.unmake());
+        assert_eq!((board.fen_write(), board.game_state.zobrist_key), start);
+        assert!(!board.unmake());
+        assert_eq!((board.fen_write(), board.game_state.zobrist_key), start);
+
+        // Loading a mid-game FEN-string resets the history.
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        assert!(board.make(m, &mg));
+        board.fen_read(Some(fen)).expect("valid FEN");
+        assert!(!board.unmake());
+        assert_eq!(board.fen_write(), fen);
+    }
+}
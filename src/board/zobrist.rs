@@ -30,6 +30,7 @@ type PieceRandoms = [[[u64; NrOf::SQUARES]; NrOf::PIECE_TYPES]; Sides::BOTH];
 type CastlingRandoms = [u64; NrOf::CASTLING_PERMISSIONS];
 type SideRandoms = [u64; Sides::BOTH];
 type EpRandoms = [u64; NrOf::SQUARES + 1];
+type CastlingRookRandoms = [[u64; NrOf::SQUARES]; 4];
 
 pub type ZobristKey = u64;
 
@@ -41,6 +42,7 @@ pub struct ZobristRandoms {
     rnd_castling: CastlingRandoms,
     rnd_sides: SideRandoms,
     rnd_en_passant: EpRandoms,
+    rnd_castling_rooks: CastlingRookRandoms,
 }
 
 impl ZobristRandoms {
@@ -51,6 +53,7 @@ impl ZobristRandoms {
             rnd_castling: [EMPTY; NrOf::CASTLING_PERMISSIONS],
             rnd_sides: [EMPTY; Sides::BOTH],
             rnd_en_passant: [EMPTY; NrOf::SQUARES + 1],
+            rnd_castling_rooks: [[EMPTY; NrOf::SQUARES]; 4],
         };
 
         zobrist_randoms.rnd_pieces.iter_mut().for_each(|side| {
@@ -76,6 +79,16 @@ impl ZobristRandoms {
             .iter_mut()
             .for_each(|ep| *ep = random.gen::<u64>());
 
+        // These are generated last, so the other numbers stay the same.
+        zobrist_randoms
+            .rnd_castling_rooks
+            .iter_mut()
+            .for_each(|right| {
+                right
+                    .iter_mut()
+                    .for_each(|square| *square = random.gen::<u64>())
+            });
+
         zobrist_randoms
     }
 
@@ -87,6 +100,10 @@ impl ZobristRandoms {
         self.rnd_castling[castling_permissions as usize]
     }
 
+    pub fn castling_rook(&self, right: usize, square: Square) -> ZobristKey {
+        self.rnd_castling_rooks[right][square]
+    }
+
     pub fn side(&self, side: Side) -> u64 {
         self.rnd_sides[side]
     }
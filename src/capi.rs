@@ -0,0 +1,406 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements a small C interface to the engine, so it can be
+// embedded in another program instead of being run as a separate process.
+// It is only compiled with the "capi" feature.
+//
+// Threads: an engine handle holds its own board and TT, and there is no
+// global state. The move generator is shared, but it is never changed.
+// Different handles can be used on different threads at the same time. One handle may be moved to
+// another thread, but it must never be used by two threads at once.
+// rustic_search() runs on the calling thread and blocks until the search
+// is done.
+//
+// Strings are NUL-terminated UTF-8. Output strings are written into a
+// buffer provided by the caller; if it is too small, nothing is written
+// and RUSTIC_ERR_BUFFER is returned. The required size (without the NUL)
+// is always reported, so the call can be repeated with a larger buffer.
+//
+// Panics are caught and reported as RUSTIC_ERR_PANIC. This needs
+// panic = "unwind": build the library with the "capi" profile in
+// Cargo.toml, which is the release profile with unwinding. With the
+// release profile itself, a panic aborts the process.
+
+use crate::{
+    api,
+    board::Board,
+    defs::MAX_PLY,
+    engine::defs::{SearchData, TT},
+    movegen::MoveGenerator,
+    search::defs::{SearchMode, SearchParams},
+};
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::Arc,
+};
+
+pub const RUSTIC_OK: c_int = 0;
+pub const RUSTIC_ERR_NULL: c_int = 1; // A pointer argument was NULL
+pub const RUSTIC_ERR_UTF8: c_int = 2; // A string argument was not UTF-8
+pub const RUSTIC_ERR_FEN: c_int = 3; // The FEN-string was invalid
+pub const RUSTIC_ERR_ILLEGAL: c_int = 4; // The move is not legal
+pub const RUSTIC_ERR_BUFFER: c_int = 5; // The output buffer is too small
+pub const RUSTIC_ERR_NO_MOVES: c_int = 6; // No legal moves to search
+pub const RUSTIC_ERR_LIMIT: c_int = 7; // No depth or time limit given
+pub const RUSTIC_ERR_PANIC: c_int = 8; // The engine panicked
+
+// The engine handle. C only sees an opaque pointer to it.
+pub struct RusticEngine {
+    board: Board,
+    mg: Arc<MoveGenerator>,
+    tt: TT<SearchData>,
+}
+
+/// Creates an engine with a TT of the given size in MB, set up in the
+/// starting position. Returns NULL if this fails.
+#[no_mangle]
+pub extern "C" fn rustic_engine_new(hash_mb: usize) -> *mut RusticEngine {
+    let result = panic::catch_unwind(|| {
+        let mut board = Board::new();
+        board.fen_read(None).ok()?;
+        let engine = RusticEngine {
+            board,
+            mg: MoveGenerator::shared(),
+            tt: TT::<SearchData>::new(hash_mb),
+        };
+        Some(Box::into_raw(Box::new(engine)))
+    });
+
+    result.ok().flatten().unwrap_or(ptr::null_mut())
+}
+
+/// Destroys an engine created by rustic_engine_new(). NULL is ignored.
+///
+/// # Safety
+/// The handle must come from rustic_engine_new(), and must not be used
+/// after this call.
+#[no_mangle]
+pub unsafe extern "C" fn rustic_engine_free(engine: *mut RusticEngine) {
+    if !engine.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(engine))));
+    }
+}
+
+/// Sets up the position from a FEN-string. Positions where the side not to
+/// move is in check are rejected. On error, the board is in the starting
+/// position.
+///
+/// # Safety
+/// The handle must be valid, and fen must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustic_set_fen(engine: *mut RusticEngine, fen: *const c_char) -> c_int {
+    guard(|| {
+        let engine = engine.as_mut().ok_or(RUSTIC_ERR_NULL)?;
+        let fen = to_str(fen)?;
+
+        let fen_ok = engine.board.fen_read(Some(fen)).is_ok();
+        if !fen_ok || engine.board.opponent_in_check(&engine.mg) {
+            engine.board.fen_read(None).map_err(|_| RUSTIC_ERR_FEN)?;
+            return Err(RUSTIC_ERR_FEN);
+        }
+        Ok(())
+    })
+}
+
+/// Writes the legal moves in the position, one per line, into the buffer.
+/// The required size is stored in needed, if it isn't NULL.
+///
+/// # Safety
+/// The handle must be valid, and buffer must point to at least
+/// buffer_size writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustic_legal_moves(
+    engine: *mut RusticEngine,
+    buffer: *mut c_char,
+    buffer_size: usize,
+    needed: *mut usize,
+) -> c_int {
+    guard(|| {
+        let engine = engine.as_mut().ok_or(RUSTIC_ERR_NULL)?;
+        let moves: Vec<String> = engine
+            .board
+            .legal_moves_iter(&engine.mg)
+            .map(|m| m.as_string())
+            .collect();
+
+        write_str(&moves.join("\n"), buffer, buffer_size, needed)
+    })
+}
+
+/// Plays a move given in long algebraic notation, such as "e2e4" or
+/// "e7e8q", if it is legal.
+///
+/// # Safety
+/// The handle must be valid, and coordinates must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn rustic_make_move(
+    engine: *mut RusticEngine,
+    coordinates: *const c_char,
+) -> c_int {
+    guard(|| {
+        let engine = engine.as_mut().ok_or(RUSTIC_ERR_NULL)?;
+        let wanted = to_str(coordinates)?.to_ascii_lowercase();
+        let m = engine
+            .board
+            .legal_moves_iter(&engine.mg)
+            .find(|m| m.as_string() == wanted)
+            .ok_or(RUSTIC_ERR_ILLEGAL)?;
+
+        engine.board.make(m, &engine.mg);
+        Ok(())
+    })
+}
+
+/// Searches the position and writes the best move into the buffer. The
+/// search stops at the given depth or after the given number of
+/// milliseconds, whichever comes first; 0 means no limit, but at least one
+/// of them must be given. The score is stored in centipawns, from the point
+/// of view of the side to move. If a mate was found, mate_in holds the
+/// number of moves (negative if the side to move is mated), otherwise 0.
+/// Any of the output pointers may be NULL.
+///
+/// # Safety
+/// The handle must be valid, and buffer must point to at least
+/// buffer_size writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustic_search(
+    engine: *mut RusticEngine,
+    depth: c_int,
+    move_time_ms: u64,
+    buffer: *mut c_char,
+    buffer_size: usize,
+    needed: *mut usize,
+    score_cp: *mut c_int,
+    mate_in: *mut c_int,
+) -> c_int {
+    guard(|| {
+        let engine = engine.as_mut().ok_or(RUSTIC_ERR_NULL)?;
+        if depth <= 0 && move_time_ms == 0 {
+            return Err(RUSTIC_ERR_LIMIT);
+        }
+        if engine.board.legal_moves_iter(&engine.mg).next().is_none() {
+            return Err(RUSTIC_ERR_NO_MOVES);
+        }
+
+        let mut search_params = SearchParams::new();
+        search_params.quiet = true;
+        search_params.depth = if depth > 0 {
+            depth.min(MAX_PLY as c_int) as i8
+        } else {
+            MAX_PLY
+        };
+        if move_time_ms > 0 {
+            search_params.search_mode = SearchMode::MoveTime;
+            search_params.move_time = move_time_ms as u128;
+        } else {
+            search_params.search_mode = SearchMode::Depth;
+        }
+
+        let outcome = api::run(&engine.board, &engine.mg, &engine.tt, search_params);
+        if !score_cp.is_null() {
+            *score_cp = outcome.score.value() as c_int;
+        }
+        if !mate_in.is_null() {
+            *mate_in = outcome.score.mate_in().unwrap_or(0) as c_int;
+        }
+
+        write_str(&outcome.best_move.as_string(), buffer, buffer_size, needed)
+    })
+}
+
+// Runs an API function, turning a panic into an error code.
+fn guard<F: FnOnce() -> Result<(), c_int>>(f: F) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => RUSTIC_OK,
+        Ok(Err(e)) => e,
+        Err(_) => RUSTIC_ERR_PANIC,
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(RUSTIC_ERR_NULL);
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| RUSTIC_ERR_UTF8)
+}
+
+// Copies the string into the buffer, followed by a NUL.
+unsafe fn write_str(
+    s: &str,
+    buffer: *mut c_char,
+    buffer_size: usize,
+    needed: *mut usize,
+) -> Result<(), c_int> {
+    if !needed.is_null() {
+        *needed = s.len();
+    }
+    if buffer.is_null() {
+        return Err(RUSTIC_ERR_NULL);
+    }
+    if s.len() >= buffer_size {
+        return Err(RUSTIC_ERR_BUFFER);
+    }
+
+    ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, buffer, s.len());
+    *buffer.add(s.len()) = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    const BUFFER_SIZE: usize = 2048;
+
+    // Calls an API function that writes a string, and returns the result
+    // and the string.
+    fn output<F>(f: F) -> (c_int, String)
+    where
+        F: FnOnce(*mut c_char, usize, *mut usize) -> c_int,
+    {
+        let mut buffer = vec![0 as c_char; BUFFER_SIZE];
+        let mut needed = 0;
+        let result = f(buffer.as_mut_ptr(), BUFFER_SIZE, &mut needed);
+        let s = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+        let s = s.to_string_lossy().into_owned();
+        if result == RUSTIC_OK {
+            assert_eq!(s.len(), needed);
+        }
+        (result, s)
+    }
+
+    fn legal_moves(engine: *mut RusticEngine) -> Vec<String> {
+        let (result, moves) = output(|b, s, n| unsafe { rustic_legal_moves(engine, b, s, n) });
+        assert_eq!(result, RUSTIC_OK);
+        moves.lines().map(String::from).collect()
+    }
+
+    fn set_fen(engine: *mut RusticEngine, fen: &str) -> c_int {
+        let fen = CString::new(fen).expect("no NUL");
+        unsafe { rustic_set_fen(engine, fen.as_ptr()) }
+    }
+
+    fn make_move(engine: *mut RusticEngine, m: &str) -> c_int {
+        let m = CString::new(m).expect("no NUL");
+        unsafe { rustic_make_move(engine, m.as_ptr()) }
+    }
+
+    #[test]
+    fn plays_and_searches_through_the_c_interface() {
+        let engine = rustic_engine_new(1);
+        assert!(!engine.is_null());
+        assert_eq!(legal_moves(engine).len(), 20);
+
+        assert_eq!(make_move(engine, "e2e4"), RUSTIC_OK);
+        assert_eq!(make_move(engine, "E7E5"), RUSTIC_OK);
+        assert_eq!(make_move(engine, "e4e5"), RUSTIC_ERR_ILLEGAL);
+        assert_eq!(legal_moves(engine).len(), 29);
+
+        // Mate in one: Qxf7#.
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        assert_eq!(set_fen(engine, fen), RUSTIC_OK);
+        let (mut score, mut mate) = (0, 0);
+        let (result, best) = output(|b, s, n| unsafe {
+            rustic_search(engine, 4, 0, b, s, n, &mut score, &mut mate)
+        });
+        assert_eq!((result, best.as_str(), mate), (RUSTIC_OK, "h5f7", 1));
+        assert!(score > 0);
+
+        // A move time alone is a limit as well.
+        let (result, best) = output(|b, s, n| unsafe {
+            rustic_search(engine, 0, 50, b, s, n, ptr::null_mut(), ptr::null_mut())
+        });
+        assert_eq!((result, best.as_str()), (RUSTIC_OK, "h5f7"));
+
+        unsafe { rustic_engine_free(engine) };
+    }
+
+    #[test]
+    fn errors_are_reported_as_codes() {
+        let engine = rustic_engine_new(0);
+        assert!(!engine.is_null());
+
+        // A bad FEN, and one where the side not to move is in check, leave
+        // the starting position.
+        assert_eq!(set_fen(engine, "not a fen"), RUSTIC_ERR_FEN);
+        assert_eq!(legal_moves(engine).len(), 20);
+        assert_eq!(set_fen(engine, "4k3/8/8/8/8/8/8/4KR2 w - - 0 1"), RUSTIC_OK);
+        assert_eq!(
+            set_fen(engine, "4k3/8/8/8/8/8/8/4R1K1 w - - 0 1"),
+            RUSTIC_ERR_FEN
+        );
+        assert_eq!(legal_moves(engine).len(), 20);
+
+        // Invalid strings.
+        let not_utf8 = [0xFFu8, 0];
+        let result = unsafe { rustic_make_move(engine, not_utf8.as_ptr() as *const c_char) };
+        assert_eq!(result, RUSTIC_ERR_UTF8);
+        let result = unsafe { rustic_set_fen(engine, ptr::null()) };
+        assert_eq!(result, RUSTIC_ERR_NULL);
+
+        // The buffer is too small, but the needed size is reported.
+        let mut buffer = [0 as c_char; 4];
+        let mut needed = 0;
+        let result = unsafe { rustic_legal_moves(engine, buffer.as_mut_ptr(), 4, &mut needed) };
+        assert_eq!((result, needed), (RUSTIC_ERR_BUFFER, 20 * 5 - 1));
+        let result = unsafe { rustic_legal_moves(engine, ptr::null_mut(), 0, &mut needed) };
+        assert_eq!(result, RUSTIC_ERR_NULL);
+
+        // Searches without a limit, or without legal moves.
+        let (result, _) = output(|b, s, n| unsafe {
+            rustic_search(engine, 0, 0, b, s, n, ptr::null_mut(), ptr::null_mut())
+        });
+        assert_eq!(result, RUSTIC_ERR_LIMIT);
+        assert_eq!(set_fen(engine, "k7/8/1K6/8/8/8/8/1R6 b - - 0 1"), RUSTIC_OK);
+        let (result, _) = output(|b, s, n| unsafe {
+            rustic_search(engine, 3, 0, b, s, n, ptr::null_mut(), ptr::null_mut())
+        });
+        assert_eq!(result, RUSTIC_OK);
+        assert_eq!(set_fen(engine, "k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), RUSTIC_OK);
+        let (result, _) = output(|b, s, n| unsafe {
+            rustic_search(engine, 3, 0, b, s, n, ptr::null_mut(), ptr::null_mut())
+        });
+        assert_eq!(result, RUSTIC_ERR_NO_MOVES);
+
+        // A NULL handle.
+        assert_eq!(
+            set_fen(ptr::null_mut(), "8/8/8/8/8/8/8/8 w - - 0 1"),
+            RUSTIC_ERR_NULL
+        );
+        unsafe { rustic_engine_free(ptr::null_mut()) };
+        unsafe { rustic_engine_free(engine) };
+    }
+
+    #[test]
+    fn panics_are_reported_as_codes() {
+        assert_eq!(guard(|| panic!("in the engine")), RUSTIC_ERR_PANIC);
+        assert_eq!(guard(|| Err(RUSTIC_ERR_FEN)), RUSTIC_ERR_FEN);
+        assert_eq!(guard(|| Ok(())), RUSTIC_OK);
+    }
+}
@@ -22,17 +22,28 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 pub mod uci;
-// pub mod xboard;
+pub mod xboard;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
 
 use crate::{
     board::Board,
-    engine::defs::{EngineOption, Information},
+    defs::Side,
+    engine::defs::{EngineOption, ErrFatal, GameEnd, Information, MoveError},
+    evaluation::defs::EvalTrace,
     movegen::defs::Move,
     search::defs::{SearchCurrentMove, SearchStats, SearchSummary},
 };
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, RecvError, Sender};
 use std::sync::{Arc, Mutex};
+
+#[cfg(any(test, feature = "testing"))]
+use crossbeam_channel::RecvTimeoutError;
+#[cfg(any(test, feature = "testing"))]
+use std::time::{Duration, Instant};
 use uci::UciReport;
+use xboard::XBoardReport;
 
 // These are the types of communication the engine is capable of.
 pub struct CommType;
@@ -54,34 +65,161 @@ pub trait IComm {
     fn get_protocol_name(&self) -> &'static str;
 }
 
-#[derive(PartialEq)]
 pub enum CommControl {
     // Reactions of engine to incoming commands.
     Update,                            // Request Comm module to update its state.
     Quit,                              // Quit the Comm module.
     Identify,                          // Transmit identification of the engine.
     Ready,                             // Transmit that the engine is ready.
+    Registration,                      // Transmit that registration is ok.
     SearchSummary(SearchSummary),      // Transmit search information.
     SearchCurrMove(SearchCurrentMove), // Transmit currently considered move.
     SearchStats(SearchStats),          // Transmit search Statistics.
     InfoString(String),                // Transmit general information.
-    BestMove(Move),                    // Transmit the engine's best move.
+    BestMove(Move, Option<Move>),      // Transmit the engine's best and ponder move.
+    IllegalMove(String, MoveError),    // Transmit that a move was illegal, and why.
+    CommandError(CommandError),        // Transmit that a command can't be carried out.
+    Pong(isize),                       // Answer a ping from the GUI.
+    Hint(Move),                        // Transmit the move expected from the user.
+    GameResult(GameEnd, Side),         // Transmit the end of the game (side to move).
 
-    // Output to screen when running in a terminal window.
-    PrintBoard,
-    PrintHistory,
+    // Output to screen when running in a terminal window. The engine
+    // thread sends a snapshot of the board along, so Comm never has to
+    // wait for the board mutex. The history and PGN need the move
+    // generator, so the engine formats them before sending.
+    PrintBoard(Box<Board>),
+    PrintHistory(String),
+    PrintPgn(String),
+    PrintBench(String),
+    PrintCompare(String),
+    PrintExchange(String),
+    PrintEval(Box<EvalTrace>),
     PrintHelp,
 }
 
+// Reasons why the engine can't carry out a command from the GUI.
+#[derive(PartialEq, Clone)]
+pub enum CommandError {
+    Unknown(String),         // The command, which the engine doesn't know
+    InvalidArgument(String), // The command, which has invalid arguments
+    NoTakeBack(String),      // The command, which takes back more moves than played
+    IllegalPosition(String), // Why the position can't be set up
+}
+
+impl CommControl {
+    // Intermediate search output is only useful while it's fresh. Each
+    // kind has a slot of its own, where a new message replaces the one
+    // the control thread hasn't picked up yet. Returns None for all the
+    // other messages, which are never lost.
+    fn latest_slot(&self) -> Option<usize> {
+        match self {
+            CommControl::SearchStats(_) => Some(0),
+            CommControl::SearchCurrMove(_) => Some(1),
+            _ => None,
+        }
+    }
+}
+
+// The slots holding the latest intermediate search output.
+type LatestControl = Arc<Mutex<[Option<CommControl>; 2]>>;
+
+// What travels through the control channel: a message, or a wakeup for
+// the control thread to pick up the message in one of the slots.
+enum Envelope {
+    Control(CommControl),
+    Latest(usize),
+}
+
+// Creates the channel from the engine thread to the control thread of a
+// Comm module. Sending never waits, even if the Comm module can't write
+// its output because nobody reads it. Intermediate search output can't
+// pile up: there is at most one message of each kind waiting.
+pub fn control_channel() -> (ControlSender, ControlReceiver) {
+    let (tx, rx) = crossbeam_channel::unbounded::<Envelope>();
+    let latest: LatestControl = Arc::new(Mutex::new([None, None]));
+    let sender = ControlSender {
+        tx,
+        latest: Arc::clone(&latest),
+    };
+
+    (sender, ControlReceiver { rx, latest })
+}
+
+pub struct ControlSender {
+    tx: Sender<Envelope>,
+    latest: LatestControl,
+}
+
+impl ControlSender {
+    // Sends a message to the control thread. A wakeup is only sent if the
+    // slot was empty; otherwise, one is already on its way. Returns false
+    // if the control thread is gone.
+    pub fn send(&self, msg: CommControl) -> bool {
+        match msg.latest_slot() {
+            Some(slot) => {
+                let mut latest = self.latest.lock().expect(ErrFatal::LOCK);
+                let was_empty = latest[slot].replace(msg).is_none();
+                !was_empty || self.tx.send(Envelope::Latest(slot)).is_ok()
+            }
+            None => self.tx.send(Envelope::Control(msg)).is_ok(),
+        }
+    }
+}
+
+pub struct ControlReceiver {
+    rx: Receiver<Envelope>,
+    latest: LatestControl,
+}
+
+impl ControlReceiver {
+    // Waits for the next message from the engine thread.
+    pub fn recv(&self) -> Result<CommControl, RecvError> {
+        loop {
+            if let Some(control) = self.open(self.rx.recv()?) {
+                return Ok(control);
+            }
+        }
+    }
+
+    // Waits for the next message, for at most the given time.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<CommControl, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if let Some(control) = self.open(self.rx.recv_timeout(left)?) {
+                return Ok(control);
+            }
+        }
+    }
+
+    fn open(&self, envelope: Envelope) -> Option<CommControl> {
+        match envelope {
+            Envelope::Control(control) => Some(control),
+            Envelope::Latest(slot) => self.latest.lock().expect(ErrFatal::LOCK)[slot].take(),
+        }
+    }
+}
+
 // These are the commands a Comm module can create and send back to the
 // engine in the main thread.
 #[derive(PartialEq, Clone)]
 pub enum CommReport {
     Uci(UciReport),
+    XBoard(XBoardReport),
 }
 
 impl CommReport {
     pub fn is_valid(&self) -> bool {
         true
     }
+
+    // Returns true if this report asks the engine to quit.
+    pub fn is_quit(&self) -> bool {
+        match self {
+            CommReport::Uci(u) => *u == UciReport::Quit,
+            CommReport::XBoard(x) => *x == XBoardReport::Quit,
+        }
+    }
 }
@@ -0,0 +1,433 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements a mock communication module. Instead of reading
+// from STDIN and printing to STDOUT, it is driven through in-memory
+// channels. This makes it possible to run the real engine and search
+// threads, send them CommReports, and inspect the CommControls the engine
+// sends back. It is only compiled for tests and with the "testing"
+// feature, and the engine itself never uses it; it is there for whoever
+// drives the engine. The mock stands in for a UCI or XBoard GUI: the
+// engine behaves as it would with the protocol the mock reports.
+#![allow(dead_code)]
+
+use super::{control_channel, CommControl, CommReport, ControlReceiver, ControlSender, IComm};
+use crate::{
+    board::Board,
+    engine::{
+        defs::{EngineOption, ErrFatal, Information},
+        Engine,
+    },
+    misc::cmdline::CmdLine,
+    movegen::defs::Move,
+};
+use crossbeam_channel::{self, Receiver, RecvTimeoutError, Sender};
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// This struct is handed to the engine as its Comm module.
+pub struct Mock {
+    report_handle: Option<JoinHandle<()>>,
+    incoming_rx: Option<Receiver<CommReport>>,
+    control_tx: ControlSender,
+    protocol: &'static str,
+}
+
+// This struct is kept by the test driving the engine. Reports sent
+// through it arrive at the engine as if they were typed into the
+// terminal; everything the engine sends to Comm can be received here.
+pub struct MockHandle {
+    incoming_tx: Sender<CommReport>,
+    control_rx: ControlReceiver,
+}
+
+impl Mock {
+    // Create the mock Comm module and the handle to drive it with. The
+    // protocol is one of the CommTypes.
+    pub fn new(protocol: &'static str) -> (Self, MockHandle) {
+        let (incoming_tx, incoming_rx) = crossbeam_channel::unbounded::<CommReport>();
+        let (control_tx, control_rx) = control_channel();
+
+        let mock = Self {
+            report_handle: None,
+            incoming_rx: Some(incoming_rx),
+            control_tx,
+            protocol,
+        };
+        let handle = MockHandle {
+            incoming_tx,
+            control_rx,
+        };
+
+        (mock, handle)
+    }
+
+    // Run an engine on a thread of its own, connected to a new mock. The
+    // engine is created with the given command line arguments (without
+    // the name of the executable), and runs until it receives "quit".
+    pub fn start_engine(
+        protocol: &'static str,
+        args: &'static [&'static str],
+    ) -> (JoinHandle<()>, MockHandle) {
+        let (mock, handle) = Mock::new(protocol);
+        let engine = thread::spawn(move || {
+            let mut all_args = vec!["rustic-alpha"];
+            all_args.extend_from_slice(args);
+            let cmdline = CmdLine::from_args(&all_args);
+            let mut engine = Engine::with_comm(cmdline, Box::new(mock));
+            engine.run().expect(ErrFatal::THREAD);
+        });
+
+        (engine, handle)
+    }
+}
+
+impl IComm for Mock {
+    fn init(
+        &mut self,
+        report_tx: Sender<Information>,
+        _board: Arc<Mutex<Board>>,
+        _options: Arc<Vec<EngineOption>>,
+    ) {
+        self.report_thread(report_tx);
+    }
+
+    // Everything the engine sends is passed on to the handle, through the
+    // same channel as a real Comm module uses: if the test doesn't
+    // receive, only the latest intermediate search output is kept.
+    fn send(&self, msg: CommControl) {
+        // The test may already have dropped its handle; that's fine.
+        self.control_tx.send(msg);
+    }
+
+    fn wait_for_shutdown(&mut self) {
+        if let Some(h) = self.report_handle.take() {
+            h.join().expect(ErrFatal::THREAD);
+        }
+    }
+
+    fn get_protocol_name(&self) -> &'static str {
+        self.protocol
+    }
+}
+
+impl Mock {
+    // The report thread forwards the reports the test sends, to the
+    // engine thread. It stops after forwarding a Quit report, or when
+    // the test drops its handle.
+    fn report_thread(&mut self, report_tx: Sender<Information>) {
+        let incoming_rx = self.incoming_rx.take().expect(ErrFatal::NO_INFO_RX);
+
+        let report_handle = thread::spawn(move || {
+            let mut quit = false;
+
+            while !quit {
+                match incoming_rx.recv() {
+                    Ok(report) => {
+                        quit = report.is_quit();
+                        report_tx
+                            .send(Information::Comm(report))
+                            .expect(ErrFatal::HANDLE);
+                    }
+                    Err(_) => quit = true,
+                }
+            }
+        });
+
+        self.report_handle = Some(report_handle);
+    }
+}
+
+impl MockHandle {
+    // Send a report to the engine.
+    pub fn send(&self, report: CommReport) {
+        self.incoming_tx.send(report).expect(ErrFatal::CHANNEL);
+    }
+
+    // Wait for the next control message sent by the engine. Returns None
+    // if nothing arrived within the given time.
+    pub fn recv(&self, timeout: Duration) -> Option<CommControl> {
+        match self.control_rx.recv_timeout(timeout) {
+            Ok(control) => Some(control),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    // Keep receiving control messages until one matches the predicate.
+    // All messages received before that one are discarded. Returns None
+    // if no match was found within the given time.
+    pub fn recv_until<F>(&self, timeout: Duration, predicate: F) -> Option<CommControl>
+    where
+        F: Fn(&CommControl) -> bool,
+    {
+        let deadline = std::time::Instant::now() + timeout;
+
+        while let Some(left) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match self.recv(left) {
+                Some(control) if predicate(&control) => return Some(control),
+                Some(_) => (),
+                None => return None,
+            }
+        }
+
+        None
+    }
+
+    // Wait for the engine's best move and the move to ponder on.
+    pub fn recv_best_move(&self, timeout: Duration) -> Option<(Move, Option<Move>)> {
+        match self.recv_until(timeout, |c| matches!(c, CommControl::BestMove(..))) {
+            Some(CommControl::BestMove(m, ponder)) => Some((m, ponder)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comm::{uci::Uci, xboard::XBoard, CommType},
+        defs::FEN_START_POSITION,
+        movegen::MoveGenerator,
+        search::defs::{SearchCurrentMove, SearchStats},
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+    const ARGS: &[&str] = &["--hash", "1", "--quiet"];
+
+    fn best_move(handle: &MockHandle) -> Move {
+        handle.recv_best_move(TIMEOUT).expect("best move").0
+    }
+
+    // Returns true if the move is legal in the position.
+    fn is_legal(fen: &str, m: Move) -> bool {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let is_legal = board
+            .legal_moves_iter(&mg)
+            .any(|legal| legal.get_move() == m.get_move());
+        is_legal
+    }
+
+    // Nobody reads the engine's output while it floods the channel. The
+    // engine must never wait for the reader; of the intermediate search
+    // output, only the latest of each kind is kept, and nothing else is
+    // lost or reordered.
+    #[test]
+    fn flooded_channel_keeps_latest_stats() {
+        let (mock, handle) = Mock::new(CommType::UCI);
+
+        for i in 0..100_000 {
+            mock.send(CommControl::SearchStats(SearchStats::new(
+                i, i as usize, 0, 0,
+            )));
+            mock.send(CommControl::SearchCurrMove(SearchCurrentMove::new(
+                Move::new(0),
+                (i % 200) as u8,
+                200,
+            )));
+            if i % 100 == 0 {
+                mock.send(CommControl::InfoString(i.to_string()));
+            }
+        }
+
+        let mut stats = Vec::new();
+        let mut curr_moves = 0;
+        let mut info = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_millis(100)) {
+            match control {
+                CommControl::SearchStats(s) => stats.push(s.time),
+                CommControl::SearchCurrMove(_) => curr_moves += 1,
+                CommControl::InfoString(msg) => info.push(msg),
+                _ => panic!("unexpected control message"),
+            }
+        }
+
+        let expected: Vec<String> = (0..1000).map(|i| (i * 100).to_string()).collect();
+        assert_eq!(info, expected);
+        assert_eq!(stats, vec![99_999]);
+        assert_eq!(curr_moves, 1);
+    }
+
+    #[test]
+    fn uci_position_go_bestmove() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos moves e2e4 e7e5"));
+        handle.send(Uci::create_report("go depth 3"));
+
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2";
+        assert!(is_legal(fen, best_move(&handle)));
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // Waits until the search has sent its first summary, and returns its
+    // main line.
+    fn search_started(handle: &MockHandle) -> Vec<Move> {
+        match handle.recv_until(TIMEOUT, |c| matches!(c, CommControl::SearchSummary(..))) {
+            Some(CommControl::SearchSummary(summary)) => summary.pv,
+            _ => panic!("no search summary"),
+        }
+    }
+
+    // Returns everything the engine sends until it stays silent.
+    fn recv_all(handle: &MockHandle) -> Vec<CommControl> {
+        let mut controls = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_secs(1)) {
+            controls.push(control);
+        }
+        controls
+    }
+
+    fn best_moves(controls: &[CommControl]) -> Vec<Move> {
+        controls
+            .iter()
+            .filter_map(|c| match c {
+                CommControl::BestMove(m, _) => Some(*m),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uci_go_stop() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go infinite"));
+        search_started(&handle);
+
+        // An infinite search doesn't end by itself.
+        let controls = recv_all(&handle);
+        assert!(best_moves(&controls).is_empty());
+
+        handle.send(Uci::create_report("stop"));
+        let moves = best_moves(&recv_all(&handle));
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(FEN_START_POSITION, moves[0]));
+
+        // A stop without a search is ignored.
+        handle.send(Uci::create_report("stop"));
+        assert!(best_moves(&recv_all(&handle)).is_empty());
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // The engine answers while it is searching; the answer doesn't wait
+    // for the best move.
+    #[test]
+    fn ping_during_search() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go infinite"));
+        search_started(&handle);
+        handle.send(Uci::create_report("isready"));
+        let controls = recv_all(&handle);
+        assert!(controls.iter().any(|c| matches!(c, CommControl::Ready)));
+        assert!(best_moves(&controls).is_empty());
+        handle.send(Uci::create_report("stop"));
+        assert_eq!(best_moves(&recv_all(&handle)).len(), 1);
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        handle.send(XBoard::create_report("new"));
+        handle.send(XBoard::create_report("st 2"));
+        handle.send(XBoard::create_report("go"));
+        search_started(&handle);
+        handle.send(XBoard::create_report("ping 7"));
+        let is_reply =
+            |c: &CommControl| matches!(c, CommControl::Pong(..) | CommControl::BestMove(..));
+        match handle.recv_until(TIMEOUT, is_reply) {
+            Some(CommControl::Pong(7)) => (),
+            _ => panic!("no pong before the move"),
+        }
+        best_move(&handle);
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // Each new position restarts the analysis. Its lines are for the new
+    // position, and so is the only best move, when it is stopped.
+    #[test]
+    fn analysis_restarts_in_new_positions() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go infinite"));
+        search_started(&handle);
+
+        let positions = [
+            (
+                "e2e4",
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            ),
+            (
+                "e2e4 c7c5",
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            ),
+        ];
+        for (moves, fen) in positions.iter() {
+            let cmd = format!("position startpos moves {}", moves);
+            handle.send(Uci::create_report(&cmd));
+            handle.send(Uci::create_report("isready"));
+            assert!(handle
+                .recv_until(TIMEOUT, |c| matches!(c, CommControl::Ready))
+                .is_some());
+
+            let pv = search_started(&handle);
+            assert!(is_legal(fen, pv[0]), "{}", fen);
+        }
+
+        handle.send(Uci::create_report("stop"));
+        let moves = best_moves(&recv_all(&handle));
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(positions[1].1, moves[0]));
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    #[test]
+    fn xboard_usermove_gets_reply() {
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        handle.send(XBoard::create_report("new"));
+        handle.send(XBoard::create_report("sd 3"));
+        handle.send(XBoard::create_report("usermove e2e4"));
+
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert!(is_legal(fen, best_move(&handle)));
+
+        // The engine played its reply on its own board, so the game goes
+        // on from there. No reply to 1.e4 gives check, so Nf3 is legal.
+        handle.send(XBoard::create_report("usermove g1f3"));
+        best_move(&handle);
+
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+}
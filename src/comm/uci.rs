@@ -23,15 +23,16 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 // This file implements the UCI communication module.
 
-use super::{CommControl, CommReport, CommType, IComm};
+use super::{control_channel, CommControl, CommReport, CommType, ControlSender, IComm};
 use crate::{
     board::Board,
     defs::{About, FEN_START_POSITION},
     engine::defs::{EngineOption, EngineOptionName, ErrFatal, Information, UiElement},
-    misc::print,
+    misc::{parse, print},
     movegen::defs::Move,
     search::defs::{
-        GameTime, SearchCurrentMove, SearchStats, SearchSummary, CHECKMATE, CHECKMATE_THRESHOLD,
+        GameTime, Score, ScoreBound, SearchCurrentMove, SearchStats, SearchSummary, BENCH_DEPTH,
+        COMPARE_DEPTH,
     },
 };
 use crossbeam_channel::{self, Sender};
@@ -55,25 +56,45 @@ pub enum UciReport {
     GoDepth(i8),
     GoMoveTime(u128),
     GoNodes(usize),
+    GoMate(u8),
     GoGameTime(GameTime),
+    GoPonder(GameTime),
+    PonderHit,
     Stop,
     Quit,
+    Debug(bool),
+    Register,
 
     // Custom commands
     Board,
-    History,
+    ShowBoard(bool),
+    History(bool),
+    Pgn,
     Eval,
+    MateIn(u8),
+    Bench(i8),
+    Compare(i8),
+    See(String),
+    Attackers(String),
+    Clock(u128, u128),
+    MyTime(u128),
+    YourTime(u128),
+    SaveHash(String),
+    LoadHash(String),
+    TakeBack,
     Help,
 
-    // Empty or unknown command.
+    // Empty or unknown command. Commands that are not recognized at all
+    // are kept, so they can be reported in debug mode.
     Unknown,
+    UnknownCommand(String),
 }
 
 // This struct is used to instantiate the Comm Console module.
 pub struct Uci {
     control_handle: Option<JoinHandle<()>>,
     report_handle: Option<JoinHandle<()>>,
-    control_tx: Option<Sender<CommControl>>,
+    control_tx: Option<ControlSender>,
 }
 
 // Public functions
@@ -88,24 +109,32 @@ impl Uci {
     }
 }
 
+impl Default for Uci {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Any communication module must implement the trait IComm.
 impl IComm for Uci {
     fn init(
         &mut self,
         report_tx: Sender<Information>,
-        board: Arc<Mutex<Board>>,
+        _board: Arc<Mutex<Board>>,
         options: Arc<Vec<EngineOption>>,
     ) {
         // Start threads
         self.report_thread(report_tx);
-        self.control_thread(board, options);
+        self.control_thread(options);
     }
 
     // The creator of the Comm module can use this function to send
     // messages or commands into the Control thread.
     fn send(&self, msg: CommControl) {
         if let Some(tx) = &self.control_tx {
-            tx.send(msg).expect(ErrFatal::CHANNEL);
+            if !tx.send(msg) {
+                panic!("{}", ErrFatal::CHANNEL);
+            }
         }
     }
 
@@ -158,7 +187,7 @@ impl Uci {
                         .expect(ErrFatal::HANDLE);
 
                     // Terminate the reporting thread if "Quit" was detected.
-                    quit = new_report == CommReport::Uci(UciReport::Quit);
+                    quit = new_report.is_quit();
                 }
 
                 // Clear for next input
@@ -174,14 +203,13 @@ impl Uci {
 // Implement the control thread
 impl Uci {
     // The control thread receives commands from the engine thread.
-    fn control_thread(&mut self, board: Arc<Mutex<Board>>, options: Arc<Vec<EngineOption>>) {
+    fn control_thread(&mut self, options: Arc<Vec<EngineOption>>) {
         // Create an incoming channel for the control thread.
-        let (control_tx, control_rx) = crossbeam_channel::unbounded::<CommControl>();
+        let (control_tx, control_rx) = control_channel();
 
         // Create the control thread.
         let control_handle = thread::spawn(move || {
             let mut quit = false;
-            let t_board = Arc::clone(&board);
             let t_options = Arc::clone(&options);
 
             // Keep running as long as Quit is not received.
@@ -190,26 +218,34 @@ impl Uci {
 
                 // Perform command as sent by the engine thread.
                 match control {
-                    CommControl::Identify => {
-                        Uci::id();
-                        Uci::options(&t_options);
-                        Uci::uciok();
-                    }
+                    CommControl::Identify => print!("{}", Uci::identification(&t_options)),
                     CommControl::Ready => Uci::readyok(),
+                    CommControl::Registration => Uci::registration(),
                     CommControl::Quit => quit = true,
                     CommControl::SearchSummary(summary) => Uci::search_summary(&summary),
                     CommControl::SearchCurrMove(current) => Uci::search_currmove(&current),
                     CommControl::SearchStats(stats) => Uci::search_stats(&stats),
                     CommControl::InfoString(msg) => Uci::info_string(&msg),
-                    CommControl::BestMove(bm) => Uci::best_move(&bm),
+                    CommControl::BestMove(bm, ponder) => Uci::best_move(&bm, ponder),
+                    CommControl::IllegalMove(m, _) => Uci::illegal_move(&m),
 
                     // Custom prints for use in the console.
-                    CommControl::PrintBoard => Uci::print_board(&t_board),
-                    CommControl::PrintHistory => Uci::print_history(&t_board),
+                    CommControl::PrintBoard(board) => Uci::print_board(&board),
+                    CommControl::PrintHistory(history) => Uci::print_history(&history),
+                    CommControl::PrintPgn(pgn) => print!("{}", pgn),
+                    CommControl::PrintBench(report) => print!("{}", report),
+                    CommControl::PrintCompare(table) => print!("{}", table),
+                    CommControl::PrintExchange(text) => print!("{}", text),
+                    CommControl::PrintEval(trace) => print::eval_trace(&trace),
                     CommControl::PrintHelp => Uci::print_help(),
 
-                    // Comm Control commands that are not (yet) used.
-                    CommControl::Update => (),
+                    // Comm Control commands that are not (yet) used, or
+                    // don't exist in the UCI protocol.
+                    CommControl::Update
+                    | CommControl::Pong(_)
+                    | CommControl::Hint(_)
+                    | CommControl::GameResult(..)
+                    | CommControl::CommandError(_) => (),
                 }
             }
         });
@@ -224,9 +260,10 @@ impl Uci {
 impl Uci {
     // This function turns the incoming data into UciReports which the
     // engine is able to understand and react to.
-    fn create_report(input: &str) -> CommReport {
-        // Trim CR/LF so only the usable characters remain.
-        let i = input.trim_end().to_string();
+    pub fn create_report(input: &str) -> CommReport {
+        // Remove CR/LF, BOM's and extra whitespace so only the usable
+        // characters remain.
+        let i = parse::normalize_input(input);
 
         // Convert to &str for matching the command.
         match i {
@@ -235,19 +272,67 @@ impl Uci {
             cmd if cmd == "ucinewgame" => CommReport::Uci(UciReport::UciNewGame),
             cmd if cmd == "isready" => CommReport::Uci(UciReport::IsReady),
             cmd if cmd == "stop" => CommReport::Uci(UciReport::Stop),
+            cmd if cmd == "ponderhit" => CommReport::Uci(UciReport::PonderHit),
             cmd if cmd == "quit" || cmd == "exit" => CommReport::Uci(UciReport::Quit),
             cmd if cmd.starts_with("setoption") => Uci::parse_setoption(&cmd),
             cmd if cmd.starts_with("position") => Uci::parse_position(&cmd),
             cmd if cmd.starts_with("go") => Uci::parse_go(&cmd),
+            cmd if cmd.starts_with("debug") => Uci::parse_debug(&cmd),
+            cmd if cmd.starts_with("register") => CommReport::Uci(UciReport::Register),
 
             // Custom commands
             cmd if cmd == "board" => CommReport::Uci(UciReport::Board),
-            cmd if cmd == "history" => CommReport::Uci(UciReport::History),
+            cmd if cmd.starts_with("showboard") => Uci::parse_showboard(&cmd),
+            cmd if cmd.starts_with("history") => Uci::parse_history(&cmd),
+            cmd if cmd == "pgn" => CommReport::Uci(UciReport::Pgn),
             cmd if cmd == "eval" => CommReport::Uci(UciReport::Eval),
+            cmd if cmd.starts_with("matein") => Uci::parse_matein(&cmd),
+            cmd if cmd.starts_with("bench") => Uci::parse_bench(&cmd),
+            cmd if cmd.starts_with("compare") => Uci::parse_compare(&cmd),
+            cmd if cmd.starts_with("see ") => Uci::parse_exchange(&cmd),
+            cmd if cmd.starts_with("attackers") => Uci::parse_exchange(&cmd),
+            cmd if cmd.starts_with("clock") => Uci::parse_clock(&cmd),
+            cmd if cmd.starts_with("mytime") => Uci::parse_time(&cmd),
+            cmd if cmd.starts_with("yourtime") => Uci::parse_time(&cmd),
+            cmd if cmd.starts_with("savehash") => Uci::parse_hash_file(&cmd),
+            cmd if cmd.starts_with("loadhash") => Uci::parse_hash_file(&cmd),
+            cmd if cmd == "takeback" => CommReport::Uci(UciReport::TakeBack),
             cmd if cmd == "help" => CommReport::Uci(UciReport::Help),
 
             // Everything else is ignored.
-            _ => CommReport::Uci(UciReport::Unknown),
+            cmd if cmd.trim().is_empty() => CommReport::Uci(UciReport::Unknown),
+            cmd => CommReport::Uci(UciReport::UnknownCommand(cmd)),
+        }
+    }
+
+    // Parses "showboard on" and "showboard off".
+    fn parse_showboard(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["showboard", "on"] => CommReport::Uci(UciReport::ShowBoard(true)),
+            ["showboard", "off"] => CommReport::Uci(UciReport::ShowBoard(false)),
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
+
+    fn parse_history(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["history"] => CommReport::Uci(UciReport::History(false)),
+            ["history", "raw"] => CommReport::Uci(UciReport::History(true)),
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
+
+    fn parse_debug(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["debug", "on"] => CommReport::Uci(UciReport::Debug(true)),
+            ["debug", "off"] => CommReport::Uci(UciReport::Debug(false)),
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
         }
     }
 
@@ -292,6 +377,7 @@ impl Uci {
             Nothing,
             Depth,
             Nodes,
+            Mate,
             MoveTime,
             WTime,
             BTime,
@@ -304,14 +390,34 @@ impl Uci {
         let mut report = CommReport::Uci(UciReport::Unknown);
         let mut token = Tokens::Nothing;
         let mut game_time = GameTime::new(0, 0, 0, 0, None);
+        let mut wtime: Option<u128> = None;
+        let mut btime: Option<u128> = None;
+        let mut winc: Option<u128> = None;
+        let mut binc: Option<u128> = None;
+        let mut ponder = false;
+
+        // Some GUIs send a negative time when a clock has run out, so
+        // times are parsed as signed numbers and clamped to 0. A value
+        // that isn't a number is ignored, as if it wasn't sent.
+        let mut clamped = false;
+        let mut parse_time = |p: &str| match p.parse::<i64>() {
+            Ok(x) if x < 0 => {
+                clamped = true;
+                Some(0)
+            }
+            Ok(x) => Some(x as u128),
+            Err(_) => None,
+        };
 
         for p in parts {
             match p {
                 t if t == "go" => report = CommReport::Uci(UciReport::GoInfinite),
                 t if t == "infinite" => break, // Already Infinite; nothing more to do.
+                t if t == "ponder" => ponder = true,
                 t if t == "depth" => token = Tokens::Depth,
                 t if t == "movetime" => token = Tokens::MoveTime,
                 t if t == "nodes" => token = Tokens::Nodes,
+                t if t == "mate" => token = Tokens::Mate,
                 t if t == "wtime" => token = Tokens::WTime,
                 t if t == "btime" => token = Tokens::BTime,
                 t if t == "winc" => token = Tokens::WInc,
@@ -334,32 +440,49 @@ impl Uci {
                         report = CommReport::Uci(UciReport::GoNodes(nodes));
                         break; // break for-loop: nothing more to do.
                     }
-                    Tokens::WTime => game_time.wtime = p.parse::<u128>().unwrap_or(0),
-                    Tokens::BTime => game_time.btime = p.parse::<u128>().unwrap_or(0),
-                    Tokens::WInc => game_time.winc = p.parse::<u128>().unwrap_or(0),
-                    Tokens::BInc => game_time.binc = p.parse::<u128>().unwrap_or(0),
+                    Tokens::Mate => {
+                        let moves = p.parse::<u8>().unwrap_or(1).max(1);
+                        report = CommReport::Uci(UciReport::GoMate(moves));
+                        break; // break for-loop: nothing more to do.
+                    }
+                    Tokens::WTime => wtime = parse_time(&p),
+                    Tokens::BTime => btime = parse_time(&p),
+                    Tokens::WInc => winc = parse_time(&p),
+                    Tokens::BInc => binc = parse_time(&p),
+                    // "movestogo 0" makes no sense; treat it as sudden death.
                     Tokens::MovesToGo => {
-                        game_time.moves_to_go = if let Ok(x) = p.parse::<usize>() {
-                            Some(x)
-                        } else {
-                            None
-                        }
+                        game_time.moves_to_go = p.parse::<usize>().ok().filter(|x| *x > 0)
                     }
                 }, // end match token
             } // end match p
         } // end for
 
+        // If only one side's clock or increment was sent, assume both
+        // sides have the same time left and the same increment.
+        game_time.wtime = wtime.or(btime).unwrap_or(0);
+        game_time.btime = btime.or(wtime).unwrap_or(0);
+        game_time.winc = winc.or(binc).unwrap_or(0);
+        game_time.binc = binc.or(winc).unwrap_or(0);
+        game_time.clamped = clamped;
+
         // If we are still in the default "go infinite" mode, we must
         // switch to GameTime mode if at least one parameter of "go wtime
-        // btime winc binc" was set to something else but 0.
+        // btime winc binc" was sent. A clock of 0 is valid: with an
+        // increment, the engine uses (most of) the increment; without one,
+        // it has no time left and must move immediately. If everything is
+        // 0, the engine searches for a short fixed time.
         let is_default_mode = report == CommReport::Uci(UciReport::GoInfinite);
-        let has_time = game_time.wtime > 0 || game_time.btime > 0;
-        let has_inc = game_time.winc > 0 || game_time.binc > 0;
-        let is_game_time = has_time || has_inc;
+        let is_game_time = wtime.is_some() || btime.is_some() || winc.is_some() || binc.is_some();
         if is_default_mode && is_game_time {
             report = CommReport::Uci(UciReport::GoGameTime(game_time));
         }
 
+        // Pondering searches until "ponderhit" or "stop" is received. The
+        // time parameters will be used after a ponderhit.
+        if is_default_mode && ponder {
+            report = CommReport::Uci(UciReport::GoPonder(game_time));
+        }
+
         report
     } // end parse_go()
 
@@ -383,100 +506,200 @@ impl Uci {
                 t if t == "value" => token = Tokens::Value,
                 _ => match token {
                     Tokens::Name => name = format!("{} {}", name, p),
-                    Tokens::Value => value = p.to_lowercase(),
+                    Tokens::Value => value = format!("{} {}", value, p.to_lowercase()),
                     Tokens::Nothing => (),
                 },
             }
         }
 
-        // Determine which engine option name to send.
-        if !name.is_empty() {
-            name = name.to_lowercase().trim().to_string();
-            match &name[..] {
-                "hash" => eon = EngineOptionName::Hash(value),
-                "clear hash" => eon = EngineOptionName::ClearHash,
-                _ => (),
-            }
+        // Determine which engine option name to send. Only the options
+        // in the engine's registry can be set.
+        if let Some(option) = EngineOption::find(name.trim()) {
+            eon = (option.setting)(value.trim().to_string());
         }
 
         // Send the engine option name with value to the engine thread.
         CommReport::Uci(UciReport::SetOption(eon))
     }
-}
 
-// Implements UCI responses to send to the G(UI).
-impl Uci {
-    fn id() {
-        println!("id name {} {}", About::ENGINE, About::VERSION);
-        println!("id author {}", About::AUTHOR);
+    // Parses "savehash <path>" and "loadhash <path>". The path is kept as
+    // it was typed, so it may contain (single) spaces.
+    fn parse_hash_file(cmd: &str) -> CommReport {
+        match cmd.split_once(' ') {
+            Some(("savehash", path)) => CommReport::Uci(UciReport::SaveHash(path.to_string())),
+            Some(("loadhash", path)) => CommReport::Uci(UciReport::LoadHash(path.to_string())),
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
+
+    // Parses "matein <moves>". A mate in zero moves can't be verified.
+    fn parse_matein(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["matein", n] => match n.parse::<u8>() {
+                Ok(moves) if moves > 0 => CommReport::Uci(UciReport::MateIn(moves)),
+                _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+            },
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
+
+    // Parses "bench" and "bench <depth>".
+    fn parse_bench(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["bench"] => CommReport::Uci(UciReport::Bench(BENCH_DEPTH)),
+            ["bench", d] => match d.parse::<i8>() {
+                Ok(depth) if depth > 0 => CommReport::Uci(UciReport::Bench(depth)),
+                _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+            },
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
     }
 
-    fn options(options: &Arc<Vec<EngineOption>>) {
-        for o in options.iter() {
-            let name = format!("option name {}", o.name);
+    // Parses "compare" and "compare <depth>".
+    fn parse_compare(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["compare"] => CommReport::Uci(UciReport::Compare(COMPARE_DEPTH)),
+            ["compare", d] => match d.parse::<i8>() {
+                Ok(depth) if depth > 0 => CommReport::Uci(UciReport::Compare(depth)),
+                _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+            },
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
 
-            let ui_element = match o.ui_element {
-                UiElement::Spin => String::from("type spin"),
-                UiElement::Button => String::from("type button"),
-            };
+    // Parses "see <move>" and "attackers <square>". Whether the move or
+    // the square exist is up to the engine.
+    fn parse_exchange(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
 
-            let value_default = if let Some(v) = &o.default {
-                format!("default {}", (*v).clone())
-            } else {
-                String::from("")
-            };
+        match parts[..] {
+            ["see", m] => CommReport::Uci(UciReport::See(m.to_string())),
+            ["attackers", square] => CommReport::Uci(UciReport::Attackers(square.to_string())),
+            _ => CommReport::Uci(UciReport::UnknownCommand(cmd.to_string())),
+        }
+    }
 
-            let value_min = if let Some(v) = &o.min {
-                format!("min {}", (*v).clone())
-            } else {
-                String::from("")
-            };
+    // Parses "clock <base minutes> <increment seconds>". Times that don't
+    // fit in milliseconds are rejected, like any other invalid number.
+    fn parse_clock(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+        let mut report = CommReport::Uci(UciReport::Unknown);
+
+        if parts.len() == 3 {
+            let base = parts[1].parse::<u128>().ok();
+            let increment = parts[2].parse::<u128>().ok();
+            let base_ms = base.and_then(|b| b.checked_mul(60_000));
+            let inc_ms = increment.and_then(|i| i.checked_mul(1000));
+
+            if let (Some(b), Some(i)) = (base_ms, inc_ms) {
+                report = CommReport::Uci(UciReport::Clock(b, i));
+            }
+        }
 
-            let value_max = if let Some(v) = &o.max {
-                format!("max {}", (*v).clone())
-            } else {
-                String::from("")
-            };
+        report
+    }
 
-            let option = format!(
-                "{} {} {} {} {}",
-                name, ui_element, value_default, value_min, value_max
-            )
-            .trim()
-            .to_string();
+    // Parses "mytime <seconds>" and "yourtime <seconds>".
+    fn parse_time(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+        let mut report = CommReport::Uci(UciReport::Unknown);
 
-            println!("{}", option);
+        if parts.len() == 2 {
+            let msecs = parts[1].parse::<u128>().ok();
+            if let Some(t) = msecs.and_then(|t| t.checked_mul(1000)) {
+                report = match parts[0] {
+                    "mytime" => CommReport::Uci(UciReport::MyTime(t)),
+                    "yourtime" => CommReport::Uci(UciReport::YourTime(t)),
+                    _ => report,
+                };
+            }
         }
+
+        report
+    }
+}
+
+// Implements UCI responses to send to the G(UI).
+impl Uci {
+    // The reply to "uci": the engine's name and author, followed by the
+    // options in the order of the engine's option registry, which is
+    // sorted by name.
+    fn identification(options: &[EngineOption]) -> String {
+        let mut lines = vec![
+            format!("id name {} {}", About::ENGINE, About::VERSION),
+            format!("id author {}", About::AUTHOR),
+        ];
+
+        lines.extend(options.iter().map(Uci::option));
+        lines.push(String::from("uciok"));
+
+        lines.iter().map(|l| format!("{}\n", l)).collect()
     }
 
-    fn uciok() {
-        println!("uciok");
+    // Creates the "option" line for one engine option. Values that the
+    // option doesn't have are left out, so there are no double spaces.
+    fn option(o: &EngineOption) -> String {
+        let ui_element = match o.ui_element {
+            UiElement::Spin => "type spin",
+            UiElement::Button => "type button",
+            UiElement::Check => "type check",
+        };
+
+        let mut parts = vec![format!("option name {}", o.name), ui_element.to_string()];
+
+        if let Some(v) = &o.default {
+            parts.push(format!("default {}", v));
+        }
+
+        if let Some(v) = &o.min {
+            parts.push(format!("min {}", v));
+        }
+
+        if let Some(v) = &o.max {
+            parts.push(format!("max {}", v));
+        }
+
+        parts.join(" ")
     }
 
     fn readyok() {
         println!("readyok");
     }
 
-    fn search_summary(s: &SearchSummary) {
-        // If mate found, report this; otherwise report normal score.
-        let score = if (s.cp.abs() >= CHECKMATE_THRESHOLD) && (s.cp.abs() < CHECKMATE) {
-            // Number of plies to mate.
-            let ply = CHECKMATE - s.cp.abs();
+    // The engine doesn't need to be registered. If the GUI tries to
+    // register it anyway, confirm that everything is fine.
+    fn registration() {
+        println!("registration checking");
+        println!("registration ok");
+    }
 
-            // Check if the number of ply's is odd
-            let is_odd = ply % 2 == 1;
+    fn search_summary(s: &SearchSummary) {
+        println!("{}", Uci::summary_line(s));
+    }
 
-            // Calculate number of moves to mate
-            let moves = if is_odd { (ply + 1) / 2 } else { ply / 2 };
+    // If mate found, report this; otherwise report normal score. The
+    // number of moves is negative if the engine is being mated.
+    fn score(score: Score) -> String {
+        match score.mate_in() {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", score.value()),
+        }
+    }
 
-            // If the engine is being mated itself, flip the score.
-            let flip = if s.cp < 0 { -1 } else { 1 };
+    fn summary_line(s: &SearchSummary) -> String {
+        let score = Uci::score(s.score);
 
-            // Report the mate
-            format!("mate {}", moves * flip)
-        } else {
-            // Report the normal score if there's no mate detected.
-            format!("cp {}", s.cp)
+        // Mark the score if the PV turned out to be unstable.
+        let score = match s.bound {
+            ScoreBound::Exact => score,
+            ScoreBound::Lower => format!("{} lowerbound", score),
+            ScoreBound::Upper => format!("{} upperbound", score),
         };
 
         // Report depth and seldepth (if available).
@@ -493,14 +716,19 @@ impl Uci {
             String::from(" ")
         };
 
-        let pv = s.pv_as_string();
+        // Only display the line number in MultiPV mode.
+        let multipv = if s.multipv > 0 {
+            format!("multipv {} ", s.multipv)
+        } else {
+            String::from("")
+        };
 
-        let info = format!(
-            "info score {} {} time {} nodes {} nps {}{}pv {}",
-            score, depth, s.time, s.nodes, s.nps, hash_full, pv,
-        );
+        let pv = s.pv_as_string();
 
-        println!("{}", info);
+        format!(
+            "info {}score {} {} time {} nodes {} nps {}{}pv {}",
+            multipv, score, depth, s.time, s.nodes, s.nps, hash_full, pv,
+        )
     }
 
     fn search_currmove(c: &SearchCurrentMove) {
@@ -512,48 +740,52 @@ impl Uci {
     }
 
     fn search_stats(s: &SearchStats) {
+        println!("{}", Uci::stats_line(s));
+    }
+
+    // The NPS in the stats is already smoothed by the search.
+    fn stats_line(s: &SearchStats) -> String {
         let hash_full = if s.hash_full > 0 {
             format!(" hashfull {}", s.hash_full)
         } else {
             String::from("")
         };
 
-        println!(
+        format!(
             "info time {} nodes {} nps {}{}",
             s.time, s.nodes, s.nps, hash_full
-        );
+        )
     }
 
     fn info_string(msg: &str) {
         println!("info string {}", msg);
     }
 
-    fn best_move(m: &Move) {
-        println!("bestmove {}", m.as_string());
+    fn illegal_move(m: &str) {
+        println!("info string Illegal move: {}", m);
+    }
+
+    // If the search was stopped before it could complete even one depth,
+    // or there are no legal moves in the position, the best move is the
+    // null move. It is deliberately sent as "bestmove 0000", because the
+    // GUI is always waiting for a bestmove after "go".
+    fn best_move(m: &Move, ponder: Option<Move>) {
+        match ponder {
+            Some(p) => println!("bestmove {} ponder {}", m.as_string(), p.as_string()),
+            None => println!("bestmove {}", m.as_string()),
+        }
     }
 }
 
 // implements handling of custom commands. These are mostly used when using
 // the UCI protocol directly in a terminal window.
 impl Uci {
-    fn print_board(board: &Arc<Mutex<Board>>) {
-        print::position(&board.lock().expect(ErrFatal::LOCK), None);
+    fn print_board(board: &Board) {
+        print::position(board, None);
     }
 
-    fn print_history(board: &Arc<Mutex<Board>>) {
-        let mtx_board = board.lock().expect(ErrFatal::LOCK);
-        let length = mtx_board.history.len();
-
-        if length == 0 {
-            println!("No history available.");
-        }
-
-        for i in 0..length {
-            let h = mtx_board.history.get_ref(i);
-            println!("{:<3}| ply: {} {}", i, i + 1, h.as_string());
-        }
-
-        std::mem::drop(mtx_board);
+    fn print_history(history: &str) {
+        print!("{}", history);
     }
 
     fn print_help() {
@@ -565,9 +797,400 @@ impl Uci {
         println!("================================================================");
         println!("help      :   This help information.");
         println!("board     :   Print the current board state.");
-        println!("history   :   Print a list of past board states.");
-        println!("eval      :   Print evaluation for side to move.");
+        println!("showboard :   \"showboard on\" prints the board after every move.");
+        println!("              \"showboard off\" switches this off again.");
+        println!("history   :   Print the moves of the game in SAN.");
+        println!("              \"history raw\" prints the past board states.");
+        println!("pgn       :   Print the game as a PGN.");
+        println!("eval      :   Print the evaluation, term by term.");
+        println!("matein n  :   Verify that the position is a mate in exactly n moves.");
+        println!("bench d   :   Search the bench positions to depth d (default 8).");
+        println!("compare d :   List all moves by score, searched to depth d (default 6).");
+        println!("              \"stop\" cancels the comparison.");
+        println!("see m     :   Print the static exchange after move m (e4xd5, exd5).");
+        println!("attackers :   \"attackers sq\" prints the pieces attacking square sq.");
+        println!("clock m s :   Start a clock with m minutes and s seconds increment.");
+        println!("              After this, \"go\" searches using the clock.");
+        println!("mytime s  :   Set the engine's clock to s seconds.");
+        println!("yourtime s:   Set your own clock to s seconds.");
+        println!("savehash f:   Save the hash table to file f.");
+        println!("loadhash f:   Load the hash table from file f.");
+        println!("takeback  :   Take back the last move on the board.");
         println!("exit      :   Quit/Exit the engine.");
         println!();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api,
+        search::defs::{SearchMode, SearchParams},
+    };
+
+    fn report(input: &str) -> UciReport {
+        match Uci::create_report(input) {
+            CommReport::Uci(report) => report,
+            _ => panic!("not a UCI report"),
+        }
+    }
+
+    // Searches the position to the given depth, and returns the best move
+    // and the score as reported to the GUI.
+    fn search(fen: &str, depth: i8) -> (String, String) {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let mut params = SearchParams::new();
+        params.depth = depth;
+        params.search_mode = SearchMode::Depth;
+        let outcome = api::search(&board, params);
+
+        (outcome.best_move.as_string(), Uci::score(outcome.score))
+    }
+
+    // Best moves and scores at a fixed depth, as reported to the GUI. A
+    // change in the way scores are handled must not change them.
+    #[test]
+    fn fixed_depth_snapshot() {
+        let snapshot = [
+            (FEN_START_POSITION, 5, "d2d4", "cp 41"),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                4,
+                "e2a6",
+                "cp 52",
+            ),
+            ("8/8/4k3/8/2p5/8/B2K4/8 w - - 0 1", 6, "d2c3", "cp 184"),
+            (
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+                3,
+                "h5f7",
+                "mate 1",
+            ),
+        ];
+
+        for (fen, depth, best_move, score) in snapshot.iter() {
+            let expected = (best_move.to_string(), score.to_string());
+            assert_eq!(search(fen, *depth), expected, "{}", fen);
+        }
+    }
+
+    // Mates found at odd plies are mates for the engine, and the ones at
+    // even plies are mates against it. Both are counted in moves.
+    #[test]
+    fn mate_scores() {
+        let cases = [
+            (
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+                "mate 1",
+            ),
+            ("k7/8/1K6/8/8/8/8/7R b - - 0 1", "mate -1"),
+            ("k7/8/2K5/8/8/8/8/7R w - - 0 1", "mate 2"),
+            ("7k/8/5K2/8/8/8/8/R7 b - - 0 1", "mate -2"),
+        ];
+
+        for (fen, score) in cases.iter() {
+            assert_eq!(search(fen, 6).1, *score, "{}", fen);
+        }
+    }
+
+    fn clamped(mut game_time: GameTime) -> GameTime {
+        game_time.clamped = true;
+        game_time
+    }
+
+    // "go" commands as sent by real GUIs, including the malformed ones.
+    #[test]
+    fn go_commands() {
+        let cases = [
+            (
+                "go wtime 300000 btime 300000 winc 2000 binc 2000",
+                UciReport::GoGameTime(GameTime::new(300_000, 300_000, 2000, 2000, None)),
+            ),
+            (
+                "go wtime 60000 btime 60000 movestogo 40",
+                UciReport::GoGameTime(GameTime::new(60_000, 60_000, 0, 0, Some(40))),
+            ),
+            (
+                "go btime 12000 wtime 15000 binc 100 winc 200",
+                UciReport::GoGameTime(GameTime::new(15_000, 12_000, 200, 100, None)),
+            ),
+            (
+                "go wtime -1500 btime 30000 winc 0 binc 0",
+                UciReport::GoGameTime(clamped(GameTime::new(0, 30_000, 0, 0, None))),
+            ),
+            (
+                "go wtime 1000 btime 1000 movestogo 0",
+                UciReport::GoGameTime(GameTime::new(1000, 1000, 0, 0, None)),
+            ),
+            (
+                "go wtime 1000 btime 1000 movestogo -5",
+                UciReport::GoGameTime(GameTime::new(1000, 1000, 0, 0, None)),
+            ),
+            (
+                "go wtime 5000",
+                UciReport::GoGameTime(GameTime::new(5000, 5000, 0, 0, None)),
+            ),
+            (
+                "go winc 1000 binc 1000",
+                UciReport::GoGameTime(GameTime::new(0, 0, 1000, 1000, None)),
+            ),
+            (
+                "go winc 500",
+                UciReport::GoGameTime(GameTime::new(0, 0, 500, 500, None)),
+            ),
+            (
+                "go wtime 5000 winc 100",
+                UciReport::GoGameTime(GameTime::new(5000, 5000, 100, 100, None)),
+            ),
+            (
+                "go wtime 0 btime 0 winc 0 binc 0",
+                UciReport::GoGameTime(GameTime::new(0, 0, 0, 0, None)),
+            ),
+            (
+                "go wtime abc btime 1000",
+                UciReport::GoGameTime(GameTime::new(1000, 1000, 0, 0, None)),
+            ),
+            (
+                "go  wtime 1000\tbtime  2000 \r\n",
+                UciReport::GoGameTime(GameTime::new(1000, 2000, 0, 0, None)),
+            ),
+            (
+                "go ponder wtime 1000 btime 2000",
+                UciReport::GoPonder(GameTime::new(1000, 2000, 0, 0, None)),
+            ),
+            ("go depth 8", UciReport::GoDepth(8)),
+            ("go movetime 2500", UciReport::GoMoveTime(2500)),
+            ("go nodes 100000", UciReport::GoNodes(100_000)),
+            ("go mate 0", UciReport::GoMate(1)),
+            ("go infinite", UciReport::GoInfinite),
+            ("go", UciReport::GoInfinite),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{}", input);
+        }
+    }
+
+    // Lines as a GUI on Windows sends them: the first one may start with a
+    // byte order mark, and all of them end in CRLF.
+    #[test]
+    fn windows_input() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let moves = vec![String::from("e2a6"), String::from("b4c3")];
+        let cases = [
+            ("\u{feff}uci\r\n", UciReport::Uci),
+            ("\u{feff}isready\r\n", UciReport::IsReady),
+            ("ucinewgame\r\n", UciReport::UciNewGame),
+            (
+                "setoption name Hash value 64\r\n",
+                UciReport::SetOption(EngineOptionName::Hash(String::from("64"))),
+            ),
+            (
+                "setoption name Clear Hash\r\n",
+                UciReport::SetOption(EngineOptionName::ClearHash),
+            ),
+            (
+                "savehash C:\\My Files\\game.hash \r\n",
+                UciReport::SaveHash(String::from("C:\\My Files\\game.hash")),
+            ),
+            ("go depth 8\r\n", UciReport::GoDepth(8)),
+            ("stop\r\n", UciReport::Stop),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{:?}", input);
+        }
+
+        let input = format!("position fen {} \r moves e2a6\rb4c3\r\n", fen);
+        assert!(report(&input) == UciReport::Position(fen.to_string(), moves));
+    }
+
+    // The exact reply to "uci". GUIs remember option settings by name and
+    // position, so a change to this output must be deliberate.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn identification_snapshot() {
+        let expected = [
+            format!("id name {} {}", About::ENGINE, About::VERSION),
+            format!("id author {}", About::AUTHOR),
+            String::from("option name Adaptive Search type check default false"),
+            String::from("option name Clear Hash type button"),
+            String::from("option name CompleteIterations type check default false"),
+            String::from("option name Hash type spin default 32 min 0 max 65536"),
+            String::from("option name MaxPVLength type spin default 0 min 0 max 125"),
+            String::from("option name MultiPV type spin default 1 min 1 max 218"),
+            String::from("option name Persist Hash type check default false"),
+            String::from("option name Ponder type check default false"),
+            String::from("option name ReportInterval type spin default 5 min 0 max 60"),
+            String::from("option name SingularMargin type spin default 150 min 0 max 1000"),
+            String::from("option name Threads type spin default 1 min 1 max 64"),
+            String::from("option name UCI_Chess960 type check default false"),
+            String::from("uciok"),
+        ];
+        let identification = Uci::identification(&EngineOption::registry());
+
+        assert_eq!(identification.lines().collect::<Vec<&str>>(), expected);
+        assert!(identification.ends_with('\n'));
+    }
+
+    // Every option announced to the GUI can be set, and only those.
+    #[test]
+    fn options_are_set_through_the_registry() {
+        for option in EngineOption::registry().iter() {
+            let cmd = format!("setoption name {} value 1", option.name.to_lowercase());
+            let expected = UciReport::SetOption((option.setting)(String::from("1")));
+            assert!(report(&cmd) == expected, "{}", cmd);
+            assert!(expected != UciReport::SetOption(EngineOptionName::Nothing));
+        }
+
+        let cases = [
+            (
+                "setoption name Hash value 128",
+                EngineOptionName::Hash(String::from("128")),
+            ),
+            (
+                "setoption name Persist Hash value TRUE",
+                EngineOptionName::PersistHash(String::from("true")),
+            ),
+            ("setoption name Clear Hash", EngineOptionName::ClearHash),
+            (
+                "setoption name Contempt value 10",
+                EngineOptionName::Nothing,
+            ),
+            ("setoption value 10", EngineOptionName::Nothing),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(
+                report(input) == UciReport::SetOption(expected.clone()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    // Registration is accepted in any form. Debug mode only knows "on"
+    // and "off"; anything the engine doesn't know is kept for reporting.
+    #[test]
+    fn register_debug_and_unknown_commands() {
+        let cases = [
+            ("register later", UciReport::Register),
+            ("register name Rustic code 1234", UciReport::Register),
+            ("debug on", UciReport::Debug(true)),
+            ("debug off\r\n", UciReport::Debug(false)),
+            (
+                "debug maybe",
+                UciReport::UnknownCommand(String::from("debug maybe")),
+            ),
+            (
+                "foo bar",
+                UciReport::UnknownCommand(String::from("foo bar")),
+            ),
+            ("", UciReport::Unknown),
+            ("  \r\n", UciReport::Unknown),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn history_commands() {
+        assert!(report("history") == UciReport::History(false));
+        assert!(report("history raw") == UciReport::History(true));
+        assert!(report("history all") == UciReport::UnknownCommand(String::from("history all")));
+    }
+
+    #[test]
+    fn matein_commands() {
+        assert!(report("matein 3") == UciReport::MateIn(3));
+        assert!(report("matein 0") == UciReport::UnknownCommand(String::from("matein 0")));
+        assert!(report("matein x") == UciReport::UnknownCommand(String::from("matein x")));
+        assert!(report("matein") == UciReport::UnknownCommand(String::from("matein")));
+    }
+
+    #[test]
+    fn compare_commands() {
+        assert!(report("compare") == UciReport::Compare(COMPARE_DEPTH));
+        assert!(report("compare 3") == UciReport::Compare(3));
+        assert!(report("compare 0") == UciReport::UnknownCommand(String::from("compare 0")));
+    }
+
+    #[test]
+    fn exchange_commands() {
+        assert!(report("see d3e5") == UciReport::See(String::from("d3e5")));
+        assert!(report("attackers e5") == UciReport::Attackers(String::from("e5")));
+        assert!(report("see") == UciReport::UnknownCommand(String::from("see")));
+    }
+
+    #[test]
+    fn clock_times_in_milliseconds() {
+        assert!(report("clock 5 3") == UciReport::Clock(300_000, 3000));
+        assert!(report("mytime 10") == UciReport::MyTime(10_000));
+        assert!(report("yourtime 0") == UciReport::YourTime(0));
+    }
+
+    #[test]
+    fn clock_times_that_overflow_are_rejected() {
+        let max = u128::MAX.to_string();
+        let huge = (u128::MAX / 1000 + 1).to_string();
+
+        assert!(report(&format!("clock {} 0", max)) == UciReport::Unknown);
+        assert!(report(&format!("clock {} 0", huge)) == UciReport::Unknown);
+        assert!(report(&format!("clock 1 {}", huge)) == UciReport::Unknown);
+        assert!(report(&format!("mytime {}", huge)) == UciReport::Unknown);
+        assert!(report(&format!("yourtime {}", huge)) == UciReport::Unknown);
+    }
+
+    // An unstable score is marked as a bound after the score.
+    #[test]
+    fn summary_line_bounds() {
+        let mut summary = SearchSummary {
+            depth: 7,
+            seldepth: 12,
+            time: 250,
+            score: Score::new(17),
+            nodes: 50000,
+            nps: 200000,
+            hash_full: 0,
+            pv: Vec::new(),
+            multipv: 0,
+            bound: ScoreBound::Exact,
+            pv_truncated: false,
+        };
+
+        let expected = [
+            (ScoreBound::Exact, "score cp 17 depth"),
+            (ScoreBound::Lower, "score cp 17 lowerbound depth"),
+            (ScoreBound::Upper, "score cp 17 upperbound depth"),
+        ];
+        for (bound, score) in expected.iter() {
+            summary.bound = *bound;
+            assert_eq!(
+                Uci::summary_line(&summary),
+                format!(
+                    "info {} 7 seldepth 12 time 250 nodes 50000 nps 200000 pv ",
+                    score
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn stats_line_snapshot() {
+        let stats = SearchStats::new(4000, 2_000_000, 520_000, 0).with_interval(1_100_000, 2000);
+        assert_eq!(
+            Uci::stats_line(&stats),
+            "info time 4000 nodes 2000000 nps 520000"
+        );
+
+        let stats = SearchStats::new(4000, 2_000_000, 520_000, 12);
+        assert_eq!(
+            Uci::stats_line(&stats),
+            "info time 4000 nodes 2000000 nps 520000 hashfull 12"
+        );
+    }
+}
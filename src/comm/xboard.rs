@@ -21,19 +21,1044 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use super::IComm;
-// use crate::{board::Board, movegen::MoveGenerator};
+// This file implements the XBoard communication module.
 
-pub struct Xboard;
+use super::{
+    control_channel, CommControl, CommReport, CommType, CommandError, ControlSender, IComm,
+};
+use crate::{
+    board::Board,
+    defs::{About, Side, Sides},
+    engine::defs::{
+        EngineOption, EngineOptionName, ErrFatal, GameEnd, Information, MoveError, UiElement,
+    },
+    misc::{parse, print},
+    movegen::defs::Move,
+    search::defs::{Score, SearchSummary},
+};
+use crossbeam_channel::{self, Sender};
+use std::{
+    io::{self},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
 
-impl Xboard {
+// These are the features the engine asks the GUI to enable, in response
+// to the "protover" command. Keep this list sorted alphabetically, so the
+// output doesn't change when features are added or moved around.
+const FEATURES: [&str; 8] = [
+    "colors=0",
+    "pause=1",
+    "ping=1",
+    "reuse=1",
+    "setboard=1",
+    "sigint=0",
+    "sigterm=0",
+    "usermove=1",
+];
+
+// Commands the GUI may send, which the engine has no use for. These are
+// ignored without complaint; other unknown commands get an error.
+const IGNORED: [&str; 10] = [
+    "accepted", "computer", "easy", "hard", "name", "nopost", "post", "random", "rating",
+    "rejected",
+];
+
+// XBoard expects mate scores as 100000 + moves to mate.
+const MATE_SCORE: i32 = 100_000;
+
+// Input will be turned into a report, which wil be sent to the engine. The
+// main engine thread will react accordingly.
+#[derive(PartialEq, Clone)]
+pub enum XBoardReport {
+    // XBoard commands
+    XBoard,
+    ProtoVer(u8),
+    New,
+    Force,
+    Go,
+    PlayOther,
+    Result(String),
+    Undo,
+    Remove,
+    Hint,
+    Ping(isize),
+    SetBoard(String),
+    UserMove(String),
+    Level {
+        moves: usize,
+        base_ms: u128,
+        inc_ms: u128,
+    },
+    SetTime(u128),
+    SetDepth(u8),
+    Time(u128),
+    OTim(u128),
+    Ics(Option<String>),
+    SetOption(EngineOptionName),
+    Pause,
+    Resume,
+    Quit,
+
+    // Custom commands
+    ShowBoard(bool),
+    Eval,
+    Pgn,
+    Bench,
+    See(String),
+    Attackers(String),
+
+    // Empty or ignored command.
+    Unknown,
+
+    // Commands the engine can't carry out.
+    UnknownCommand(String),
+    InvalidArgument(String),
+}
+
+// This struct is used to instantiate the Comm XBoard module.
+pub struct XBoard {
+    control_handle: Option<JoinHandle<()>>,
+    report_handle: Option<JoinHandle<()>>,
+    control_tx: Option<ControlSender>,
+}
+
+// Public functions
+impl XBoard {
+    // Create a new XBoard module.
     pub fn new() -> Self {
-        Self {}
+        Self {
+            control_handle: None,
+            report_handle: None,
+            control_tx: None,
+        }
+    }
+}
+
+impl Default for XBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Any communication module must implement the trait IComm.
+impl IComm for XBoard {
+    fn init(
+        &mut self,
+        report_tx: Sender<Information>,
+        _board: Arc<Mutex<Board>>,
+        options: Arc<Vec<EngineOption>>,
+    ) {
+        // Start threads
+        self.report_thread(report_tx);
+        self.control_thread(options);
+    }
+
+    // The creator of the Comm module can use this function to send
+    // messages or commands into the Control thread.
+    fn send(&self, msg: CommControl) {
+        if let Some(tx) = &self.control_tx {
+            if !tx.send(msg) {
+                panic!("{}", ErrFatal::CHANNEL);
+            }
+        }
+    }
+
+    // After the engine sends 'quit' to the control thread, it will call
+    // wait_for_shutdown() and then wait here until shutdown is completed.
+    fn wait_for_shutdown(&mut self) {
+        if let Some(h) = self.report_handle.take() {
+            h.join().expect(ErrFatal::THREAD);
+        }
+
+        if let Some(h) = self.control_handle.take() {
+            h.join().expect(ErrFatal::THREAD);
+        }
+    }
+
+    // This function just returns the name of the communication protocol.
+    fn get_protocol_name(&self) -> &'static str {
+        CommType::XBOARD
+    }
+}
+
+// Implement the report thread
+impl XBoard {
+    // The Report thread sends incoming data to the engine thread.
+    fn report_thread(&mut self, report_tx: Sender<Information>) {
+        // Create thread-local variables
+        let mut t_incoming_data = String::from("");
+        let t_report_tx = report_tx; // Report sender
+
+        // Actual thread creation.
+        let report_handle = thread::spawn(move || {
+            let mut quit = false;
+
+            // Keep running as long as 'quit' is not detected.
+            while !quit {
+                // Get data from stdin.
+                io::stdin()
+                    .read_line(&mut t_incoming_data)
+                    .expect(ErrFatal::READ_IO);
+
+                // Create a report from the incoming data.
+                let new_report = XBoard::create_report(&t_incoming_data);
+
+                // Check if the created report is valid, so it is something
+                // the engine will understand.
+                if new_report.is_valid() {
+                    // Send it to the engine thread.
+                    t_report_tx
+                        .send(Information::Comm(new_report.clone()))
+                        .expect(ErrFatal::HANDLE);
+
+                    // Terminate the reporting thread if "Quit" was detected.
+                    quit = new_report.is_quit();
+                }
+
+                // Clear for next input
+                t_incoming_data = String::from("");
+            }
+        });
+
+        // Store the handle.
+        self.report_handle = Some(report_handle);
+    }
+}
+
+// Implement the control thread
+impl XBoard {
+    // The control thread receives commands from the engine thread.
+    fn control_thread(&mut self, options: Arc<Vec<EngineOption>>) {
+        // Create an incoming channel for the control thread.
+        let (control_tx, control_rx) = control_channel();
+
+        // Create the control thread.
+        let control_handle = thread::spawn(move || {
+            let mut quit = false;
+            let t_options = Arc::clone(&options);
+
+            // Keep running as long as Quit is not received.
+            while !quit {
+                let control = control_rx.recv().expect(ErrFatal::CHANNEL);
+
+                // Perform command as sent by the engine thread.
+                match control {
+                    CommControl::Identify => print!("{}", XBoard::features(&t_options)),
+                    CommControl::Quit => quit = true,
+                    CommControl::Pong(n) => XBoard::pong(n),
+                    CommControl::Hint(m) => XBoard::hint(&m),
+                    CommControl::SearchSummary(summary) => XBoard::search_summary(&summary),
+                    CommControl::InfoString(msg) => XBoard::info_string(&msg),
+                    CommControl::BestMove(bm, _) => XBoard::best_move(&bm),
+                    CommControl::IllegalMove(m, e) => XBoard::illegal_move(&m, e),
+                    CommControl::CommandError(e) => XBoard::command_error(&e),
+                    CommControl::GameResult(end, side) => XBoard::game_result(end, side),
+
+                    // Custom prints for use in the console.
+                    CommControl::PrintBoard(board) => print::position(&board, None),
+                    CommControl::PrintEval(trace) => print::eval_trace(&trace),
+                    CommControl::PrintPgn(pgn) => print!("{}", pgn),
+                    CommControl::PrintBench(report) => print!("{}", report),
+                    CommControl::PrintCompare(table) => print!("{}", table),
+                    CommControl::PrintExchange(text) => print!("{}", text),
+
+                    // Comm Control commands that are not (yet) used, or
+                    // don't exist in the XBoard protocol.
+                    CommControl::Update
+                    | CommControl::Ready
+                    | CommControl::Registration
+                    | CommControl::SearchCurrMove(_)
+                    | CommControl::SearchStats(_)
+                    | CommControl::PrintHistory(_)
+                    | CommControl::PrintHelp => (),
+                }
+            }
+        });
+
+        // Store handle and control sender.
+        self.control_handle = Some(control_handle);
+        self.control_tx = Some(control_tx);
+    }
+}
+
+// Private functions for this module.
+impl XBoard {
+    // This function turns the incoming data into XBoardReports which the
+    // engine is able to understand and react to.
+    pub fn create_report(input: &str) -> CommReport {
+        // Remove CR/LF, BOM's and extra whitespace so only the usable
+        // characters remain.
+        let i = parse::normalize_input(input);
+
+        // Convert to &str for matching the command.
+        match i {
+            // XBoard commands
+            cmd if cmd == "xboard" => CommReport::XBoard(XBoardReport::XBoard),
+            cmd if cmd.starts_with("protover") => XBoard::parse_protover(&cmd),
+            cmd if cmd == "new" => CommReport::XBoard(XBoardReport::New),
+            cmd if cmd == "force" => CommReport::XBoard(XBoardReport::Force),
+            cmd if cmd == "go" => CommReport::XBoard(XBoardReport::Go),
+            cmd if cmd == "playother" => CommReport::XBoard(XBoardReport::PlayOther),
+            cmd if cmd == "undo" => CommReport::XBoard(XBoardReport::Undo),
+            cmd if cmd == "remove" => CommReport::XBoard(XBoardReport::Remove),
+            cmd if cmd == "hint" => CommReport::XBoard(XBoardReport::Hint),
+            cmd if cmd.starts_with("result ") => XBoard::parse_result(&cmd),
+            cmd if cmd.starts_with("ping") => XBoard::parse_ping(&cmd),
+            cmd if cmd.starts_with("setboard") => XBoard::parse_setboard(&cmd),
+            cmd if cmd.starts_with("usermove") => XBoard::parse_usermove(&cmd),
+            cmd if cmd.starts_with("level ") => XBoard::parse_level(&cmd),
+            cmd if cmd.starts_with("st ") => XBoard::parse_st(&cmd),
+            cmd if cmd.starts_with("sd ") => XBoard::parse_sd(&cmd),
+            cmd if cmd.starts_with("time ") || cmd.starts_with("otim ") => {
+                XBoard::parse_clock(&cmd)
+            }
+            cmd if cmd.starts_with("ics ") => XBoard::parse_ics(&cmd),
+            cmd if cmd.starts_with("option ") => XBoard::parse_option(&cmd),
+            cmd if cmd == "pause" => CommReport::XBoard(XBoardReport::Pause),
+            cmd if cmd == "resume" => CommReport::XBoard(XBoardReport::Resume),
+            cmd if cmd == "quit" || cmd == "exit" => CommReport::XBoard(XBoardReport::Quit),
+
+            // Custom commands
+            cmd if cmd == "showboard on" => CommReport::XBoard(XBoardReport::ShowBoard(true)),
+            cmd if cmd == "showboard off" => CommReport::XBoard(XBoardReport::ShowBoard(false)),
+            cmd if cmd == "eval" => CommReport::XBoard(XBoardReport::Eval),
+            cmd if cmd == "pgn" => CommReport::XBoard(XBoardReport::Pgn),
+            cmd if cmd == "bench" => CommReport::XBoard(XBoardReport::Bench),
+            cmd if cmd.starts_with("see ") => XBoard::parse_exchange(&cmd),
+            cmd if cmd.starts_with("attackers") => XBoard::parse_exchange(&cmd),
+
+            // If the GUI didn't accept "usermove", moves come in as they are.
+            cmd if XBoard::is_move(&cmd) => CommReport::XBoard(XBoardReport::UserMove(cmd)),
+
+            // Empty lines and commands without use are ignored.
+            cmd if cmd.is_empty() => CommReport::XBoard(XBoardReport::Unknown),
+            cmd if IGNORED.contains(&cmd.split_whitespace().next().unwrap_or_default()) => {
+                CommReport::XBoard(XBoardReport::Unknown)
+            }
+            cmd => CommReport::XBoard(XBoardReport::UnknownCommand(cmd)),
+        }
+    }
+
+    // A known command, but its arguments can't be used.
+    fn invalid_argument(cmd: &str) -> CommReport {
+        CommReport::XBoard(XBoardReport::InvalidArgument(cmd.to_string()))
+    }
+
+    fn parse_protover(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["protover", v] => match v.parse::<u8>() {
+                Ok(version) => CommReport::XBoard(XBoardReport::ProtoVer(version)),
+                Err(_) => XBoard::invalid_argument(cmd),
+            },
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    fn parse_ping(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["ping", n] => match n.parse::<isize>() {
+                Ok(n) => CommReport::XBoard(XBoardReport::Ping(n)),
+                Err(_) => XBoard::invalid_argument(cmd),
+            },
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    // Parses "ics HOSTNAME". The hostname is "-" when the engine is not
+    // playing on an internet chess server.
+    fn parse_ics(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["ics", "-"] => CommReport::XBoard(XBoardReport::Ics(None)),
+            ["ics", host] => CommReport::XBoard(XBoardReport::Ics(Some(host.to_string()))),
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    // Parses "result RESULT {COMMENT}". Only the result itself is kept;
+    // the comment explains it to the user, such as "{White mates}".
+    fn parse_result(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["result", r, ..] if ["1-0", "0-1", "1/2-1/2", "*"].contains(&r) => {
+                CommReport::XBoard(XBoardReport::Result(r.to_string()))
+            }
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    // Parses "see <move>" and "attackers <square>".
+    fn parse_exchange(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["see", m] => CommReport::XBoard(XBoardReport::See(m.to_string())),
+            ["attackers", square] => {
+                CommReport::XBoard(XBoardReport::Attackers(square.to_string()))
+            }
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    fn parse_setboard(cmd: &str) -> CommReport {
+        let fen = cmd.trim_start_matches("setboard").trim();
+
+        if fen.is_empty() {
+            XBoard::invalid_argument(cmd)
+        } else {
+            CommReport::XBoard(XBoardReport::SetBoard(fen.to_string()))
+        }
+    }
+
+    // Parses "usermove <move>". Whether the move is legal is up to the
+    // engine; this only makes sure there is exactly one move.
+    fn parse_usermove(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        match parts[..] {
+            ["usermove", m] => CommReport::XBoard(XBoardReport::UserMove(m.to_string())),
+            _ => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    // Parses "level MPS BASE INC". MPS is the number of moves per time
+    // control (0 means the entire game), BASE is given either in minutes
+    // ("5") or as minutes and seconds ("2:30"), and INC is the increment
+    // in seconds, which may have a fraction ("0.1").
+    fn parse_level(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        let report = match parts[..] {
+            ["level", mps, base, inc] => {
+                let moves = mps.parse::<usize>().ok();
+                let base_ms = XBoard::parse_base_time(base);
+                let inc_ms = XBoard::parse_seconds(inc);
+
+                match (moves, base_ms, inc_ms) {
+                    (Some(moves), Some(base_ms), Some(inc_ms)) => XBoardReport::Level {
+                        moves,
+                        base_ms,
+                        inc_ms,
+                    },
+                    _ => XBoardReport::InvalidArgument(cmd.to_string()),
+                }
+            }
+            _ => XBoardReport::InvalidArgument(cmd.to_string()),
+        };
+
+        CommReport::XBoard(report)
+    }
+
+    // Parses "st SECONDS": a fixed time per move.
+    fn parse_st(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        let report = match parts[..] {
+            ["st", s] => match XBoard::parse_seconds(s) {
+                Some(ms) => XBoardReport::SetTime(ms),
+                None => XBoardReport::InvalidArgument(cmd.to_string()),
+            },
+            _ => XBoardReport::InvalidArgument(cmd.to_string()),
+        };
+
+        CommReport::XBoard(report)
+    }
+
+    // Parses "sd DEPTH": the maximum search depth.
+    fn parse_sd(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+
+        let report = match parts[..] {
+            ["sd", d] => match d.parse::<u8>() {
+                Ok(depth) => XBoardReport::SetDepth(depth),
+                Err(_) => XBoardReport::InvalidArgument(cmd.to_string()),
+            },
+            _ => XBoardReport::InvalidArgument(cmd.to_string()),
+        };
+
+        CommReport::XBoard(report)
+    }
+
+    // Parses "time CS" and "otim CS", which hold the engine's and the
+    // opponent's clock in centiseconds. A GUI may send a negative value
+    // if the clock ran out; this is treated as no time left.
+    fn parse_clock(cmd: &str) -> CommReport {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+        let centiseconds = |cs: &str| cs.parse::<i64>().ok().map(|cs| cs.max(0) as u128);
+
+        let report = match parts[..] {
+            ["time", cs] => centiseconds(cs).map(XBoardReport::Time),
+            ["otim", cs] => centiseconds(cs).map(XBoardReport::OTim),
+            _ => None,
+        };
+
+        CommReport::XBoard(report.unwrap_or_else(|| XBoardReport::InvalidArgument(cmd.to_string())))
+    }
+
+    // Parses "option NAME=VALUE", or "option NAME" for a button. Only the
+    // options in the engine's registry can be set. XBoard sets a check
+    // box with 1 or 0, where the engine expects true or false.
+    fn parse_option(cmd: &str) -> CommReport {
+        let setting = cmd.trim_start_matches("option ");
+        let (name, value) = setting.split_once('=').unwrap_or((setting, ""));
+
+        match EngineOption::find(name.trim()) {
+            Some(option) => {
+                let value = match (&option.ui_element, value.trim()) {
+                    (UiElement::Check, "1") => "true",
+                    (UiElement::Check, "0") => "false",
+                    (_, v) => v,
+                };
+                let setting = (option.setting)(value.to_string());
+                CommReport::XBoard(XBoardReport::SetOption(setting))
+            }
+            None => XBoard::invalid_argument(cmd),
+        }
+    }
+
+    // Converts a base time of "minutes" or "minutes:seconds" into msecs.
+    // A time too large to be represented is rejected.
+    fn parse_base_time(base: &str) -> Option<u128> {
+        let (minutes, seconds) = match base.split_once(':') {
+            Some((m, s)) => (m.parse::<u128>().ok()?, s.parse::<u128>().ok()?),
+            None => (base.parse::<u128>().ok()?, 0),
+        };
+
+        minutes
+            .checked_mul(60)?
+            .checked_add(seconds)?
+            .checked_mul(1000)
+    }
+
+    // Converts a (possibly fractional) number of seconds into msecs.
+    // Negative, infinite and out of range values are rejected.
+    fn parse_seconds(seconds: &str) -> Option<u128> {
+        let s = seconds.parse::<f64>().ok().filter(|s| *s >= 0.0)?;
+        let ms = (s * 1000.0).round();
+
+        if ms.is_finite() && ms < u128::MAX as f64 {
+            Some(ms as u128)
+        } else {
+            None
+        }
+    }
+
+    // Returns true if the input looks like a move in coordinate notation,
+    // such as "e2e4" or "e7e8q".
+    fn is_move(cmd: &str) -> bool {
+        let c: Vec<char> = cmd.chars().collect();
+        let is_file = |c: char| ('a'..='h').contains(&c);
+        let is_rank = |c: char| ('1'..='8').contains(&c);
+        let is_promotion = |c: char| "qrbn".contains(c);
+
+        (c.len() == 4 || c.len() == 5)
+            && is_file(c[0])
+            && is_rank(c[1])
+            && is_file(c[2])
+            && is_rank(c[3])
+            && (c.len() == 4 || is_promotion(c[4]))
+    }
+}
+
+// Implements XBoard responses to send to the G(UI).
+impl XBoard {
+    // "done=0" and "done=1" enclose the features, so the GUI waits until
+    // all of them have been sent. The engine's name and version come
+    // first, followed by the features in alphabetical order, and the
+    // options in the order of the engine's option registry.
+    fn features(options: &[EngineOption]) -> String {
+        let mut lines = vec![
+            String::from("done=0"),
+            format!("myname=\"{} {}\"", About::ENGINE, About::VERSION),
+        ];
+
+        lines.extend(FEATURES.iter().map(|f| f.to_string()));
+        lines.extend(
+            options
+                .iter()
+                .map(|o| format!("option=\"{}\"", XBoard::option(o))),
+        );
+        lines.push(String::from("done=1"));
+
+        lines.iter().map(|l| format!("feature {}\n", l)).collect()
+    }
+
+    // Describes one engine option for the "option" feature. XBoard shows
+    // a check box as 1 or 0, instead of true or false.
+    fn option(o: &EngineOption) -> String {
+        let ui_element = match o.ui_element {
+            UiElement::Spin => "-spin",
+            UiElement::Button => "-button",
+            UiElement::Check => "-check",
+        };
+
+        let mut parts = vec![o.name.to_string(), ui_element.to_string()];
+
+        if let Some(v) = &o.default {
+            parts.push(match &v[..] {
+                "true" => String::from("1"),
+                "false" => String::from("0"),
+                _ => v.clone(),
+            });
+        }
+
+        if let Some(v) = &o.min {
+            parts.push(v.clone());
+        }
+
+        if let Some(v) = &o.max {
+            parts.push(v.clone());
+        }
+
+        parts.join(" ")
+    }
+
+    fn pong(n: isize) {
+        println!("pong {}", n);
+    }
+
+    fn hint(m: &Move) {
+        println!("Hint: {}", m.as_string());
+    }
+
+    // Thinking output: depth, score, time in centiseconds, nodes, PV.
+    fn search_summary(s: &SearchSummary) {
+        println!("{}", XBoard::summary_line(s));
+    }
+
+    // Mate scores are sent as 100000 + N when the engine mates in N
+    // moves, and -100000 - N when it is mated in N moves.
+    fn score(score: Score) -> i32 {
+        match score.mate_in() {
+            Some(moves) if moves > 0 => MATE_SCORE + moves as i32,
+            Some(moves) => -MATE_SCORE + moves as i32,
+            None => score.value() as i32,
+        }
+    }
+
+    // The thinking output: depth, score, time in centiseconds, nodes, PV.
+    fn summary_line(s: &SearchSummary) -> String {
+        format!(
+            "{} {} {} {} {}",
+            s.depth,
+            XBoard::score(s.score),
+            s.time / 10,
+            s.nodes,
+            s.pv_as_string()
+        )
+    }
+
+    fn info_string(msg: &str) {
+        println!("# {}", msg);
+    }
+
+    // XBoard has no null move. If the engine has no move, it sends
+    // nothing at all; the GUI will see the game has ended.
+    fn best_move(m: &Move) {
+        if !m.is_null() {
+            println!("move {}", m.as_string());
+        }
+    }
+
+    fn illegal_move(m: &str, e: MoveError) {
+        println!("{}", XBoard::illegal_move_reply(m, e));
+    }
+
+    fn command_error(e: &CommandError) {
+        println!("{}", XBoard::command_error_reply(e));
+    }
+
+    // Formats the reply to a move the engine can't play, as the protocol
+    // describes it: "Illegal move (REASON): MOVE". The reason is left out
+    // if the move is simply not legal.
+    fn illegal_move_reply(m: &str, e: MoveError) -> String {
+        let reason = match e {
+            MoveError::NotLegal => None,
+            MoveError::NoPromotionPiece => Some("no promotion piece"),
+            MoveError::Ambiguous => Some("ambiguous"),
+            MoveError::InCheck => Some("in check"),
+            MoveError::NotYourMove => Some("not your move"),
+        };
+
+        match reason {
+            Some(reason) => format!("Illegal move ({}): {}", reason, m),
+            None => format!("Illegal move: {}", m),
+        }
+    }
+
+    // Formats the reply to a command the engine can't carry out: "Error
+    // (TYPE): COMMAND". A position that can't be set up is shown to the
+    // user with the reason, as the GUI doesn't check positions itself.
+    fn command_error_reply(e: &CommandError) -> String {
+        match e {
+            CommandError::Unknown(cmd) => format!("Error (unknown command): {}", cmd),
+            CommandError::InvalidArgument(cmd) => format!("Error (invalid argument): {}", cmd),
+            CommandError::NoTakeBack(cmd) => format!("Error (no move to take back): {}", cmd),
+            CommandError::IllegalPosition(reason) => {
+                format!("tellusererror Illegal position: {}", reason)
+            }
+        }
+    }
+
+    // Claims the result of a game that ended on the board or the clock.
+    fn game_result(end: GameEnd, side_to_move: Side) {
+        println!("{}", XBoard::result_line(end, side_to_move));
+    }
+
+    // The result in the protocol's format. The side to move is the side
+    // that was checkmated or stalemated.
+    fn result_line(end: GameEnd, side_to_move: Side) -> &'static str {
+        let white_to_move = side_to_move == Sides::WHITE;
+        match end {
+            GameEnd::Checkmate if white_to_move => "0-1 {Black mates}",
+            GameEnd::Checkmate => "1-0 {White mates}",
+            GameEnd::Stalemate => "1/2-1/2 {Stalemate}",
+            GameEnd::TimeForfeit(loser) if loser == Sides::WHITE => "0-1 {White loses on time}",
+            GameEnd::TimeForfeit(_) => "1-0 {Black loses on time}",
+        }
     }
 }
 
-impl IComm for Xboard {
-    fn start(&self) {
-        println!("XBoard communication.");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api,
+        comm::mock::{Mock, MockHandle},
+        search::defs::{SearchMode, SearchParams},
+    };
+    use std::time::Duration;
+
+    fn report(input: &str) -> XBoardReport {
+        match XBoard::create_report(input) {
+            CommReport::XBoard(report) => report,
+            _ => panic!("not an XBoard report"),
+        }
+    }
+
+    // The exact reply to "protover 2". GUIs remember option settings, so
+    // a change to this output must be deliberate.
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn features_snapshot() {
+        let expected = [
+            String::from("feature done=0"),
+            format!("feature myname=\"{} {}\"", About::ENGINE, About::VERSION),
+            String::from("feature colors=0"),
+            String::from("feature pause=1"),
+            String::from("feature ping=1"),
+            String::from("feature reuse=1"),
+            String::from("feature setboard=1"),
+            String::from("feature sigint=0"),
+            String::from("feature sigterm=0"),
+            String::from("feature usermove=1"),
+            String::from("feature option=\"Adaptive Search -check 0\""),
+            String::from("feature option=\"Clear Hash -button\""),
+            String::from("feature option=\"CompleteIterations -check 0\""),
+            String::from("feature option=\"Hash -spin 32 0 65536\""),
+            String::from("feature option=\"MaxPVLength -spin 0 0 125\""),
+            String::from("feature option=\"MultiPV -spin 1 1 218\""),
+            String::from("feature option=\"Persist Hash -check 0\""),
+            String::from("feature option=\"Ponder -check 0\""),
+            String::from("feature option=\"ReportInterval -spin 5 0 60\""),
+            String::from("feature option=\"SingularMargin -spin 150 0 1000\""),
+            String::from("feature option=\"Threads -spin 1 1 64\""),
+            String::from("feature option=\"UCI_Chess960 -check 0\""),
+            String::from("feature done=1"),
+        ];
+        let features = XBoard::features(&EngineOption::registry());
+
+        assert_eq!(features.lines().collect::<Vec<&str>>(), expected);
+        assert!(features.ends_with('\n'));
+    }
+
+    // Every option announced to the GUI can be set, and only those.
+    #[test]
+    fn options_are_set_through_the_registry() {
+        for option in EngineOption::registry().iter() {
+            let cmd = format!("option {}=7", option.name);
+            let expected = XBoardReport::SetOption((option.setting)(String::from("7")));
+            assert!(report(&cmd) == expected, "{}", cmd);
+        }
+
+        let cases = [
+            (
+                "option Hash=128",
+                XBoardReport::SetOption(EngineOptionName::Hash(String::from("128"))),
+            ),
+            (
+                "option Ponder=1",
+                XBoardReport::SetOption(EngineOptionName::Ponder(String::from("true"))),
+            ),
+            (
+                "option persist hash=0",
+                XBoardReport::SetOption(EngineOptionName::PersistHash(String::from("false"))),
+            ),
+            (
+                "option Clear Hash",
+                XBoardReport::SetOption(EngineOptionName::ClearHash),
+            ),
+            (
+                "option Contempt=10",
+                XBoardReport::InvalidArgument(String::from("option Contempt=10")),
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{}", input);
+        }
+    }
+
+    // XBoard expects 100000 + N when the engine mates in N moves, and
+    // -100000 - N when it gets mated: mates at odd and even plies.
+    #[test]
+    fn mate_scores() {
+        let cases = [
+            (
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+                100_001,
+            ),
+            ("k7/8/1K6/8/8/8/8/7R b - - 0 1", -100_001),
+            ("k7/8/2K5/8/8/8/8/7R w - - 0 1", 100_002),
+            ("7k/8/5K2/8/8/8/8/R7 b - - 0 1", -100_002),
+        ];
+
+        for (fen, score) in cases.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = 6;
+            params.search_mode = SearchMode::Depth;
+
+            let outcome = api::search(&board, params);
+            assert_eq!(XBoard::score(outcome.score), *score, "{}", fen);
+        }
+    }
+
+    fn level(moves: usize, base_ms: u128, inc_ms: u128) -> XBoardReport {
+        XBoardReport::Level {
+            moves,
+            base_ms,
+            inc_ms,
+        }
+    }
+
+    // Lines as a GUI on Windows sends them: the first one may start with a
+    // byte order mark, and all of them end in CRLF.
+    #[test]
+    fn windows_input() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let cases = [
+            ("\u{feff}xboard\r\n", XBoardReport::XBoard),
+            ("protover 2\r\n", XBoardReport::ProtoVer(2)),
+            ("new\r\n", XBoardReport::New),
+            ("ping 3\r\n", XBoardReport::Ping(3)),
+            ("level 40 5 0\r\n", level(40, 300_000, 0)),
+            (
+                "usermove e2e4\r\n",
+                XBoardReport::UserMove(String::from("e2e4")),
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{:?}", input);
+        }
+
+        let input = format!("setboard {}\r\n", fen.replace(' ', "\r "));
+        assert!(report(&input) == XBoardReport::SetBoard(fen.to_string()));
+    }
+
+    #[test]
+    fn result_lines() {
+        let cases = [
+            (GameEnd::Checkmate, Sides::WHITE, "0-1 {Black mates}"),
+            (GameEnd::Checkmate, Sides::BLACK, "1-0 {White mates}"),
+            (GameEnd::Stalemate, Sides::BLACK, "1/2-1/2 {Stalemate}"),
+            (
+                GameEnd::TimeForfeit(Sides::WHITE),
+                Sides::WHITE,
+                "0-1 {White loses on time}",
+            ),
+            (
+                GameEnd::TimeForfeit(Sides::BLACK),
+                Sides::WHITE,
+                "1-0 {Black loses on time}",
+            ),
+        ];
+
+        for (end, side_to_move, expected) in cases.iter() {
+            assert_eq!(XBoard::result_line(*end, *side_to_move), *expected);
+        }
+    }
+
+    #[test]
+    fn ics_pause_and_resume() {
+        let cases = [
+            (
+                "ics freechess.org",
+                XBoardReport::Ics(Some(String::from("freechess.org"))),
+            ),
+            ("ics -", XBoardReport::Ics(None)),
+            (
+                "ics a b",
+                XBoardReport::InvalidArgument(String::from("ics a b")),
+            ),
+            ("pause", XBoardReport::Pause),
+            ("resume\r\n", XBoardReport::Resume),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{:?}", input);
+        }
+        assert!(FEATURES.contains(&"pause=1"));
+    }
+
+    #[test]
+    fn time_controls_in_milliseconds() {
+        let cases = [
+            ("level 40 5 0", level(40, 300_000, 0)),
+            ("level 0 2:30 12", level(0, 150_000, 12_000)),
+            ("level 0 0:05 0.1", level(0, 5000, 100)),
+            ("st 2.5", XBoardReport::SetTime(2500)),
+            ("time 1234", XBoardReport::Time(1234)),
+            ("otim -50", XBoardReport::OTim(0)),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert!(report(input) == *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn time_controls_that_overflow_are_rejected() {
+        let max = u128::MAX.to_string();
+        let huge = (u128::MAX / 60_000 + 1).to_string();
+        let cases = [
+            format!("level 0 {} 0", max),
+            format!("level 0 {} 0", huge),
+            format!("level 0 {}:00 0", huge),
+            format!("level 0 0:{} 0", max),
+            String::from("level 0 5 1e400"),
+            String::from("level 0 5 inf"),
+            String::from("level 0 5 -1"),
+            String::from("st 1e40"),
+            String::from("st NaN"),
+        ];
+
+        for input in cases.iter() {
+            let expected = XBoardReport::InvalidArgument(input.clone());
+            assert!(report(input) == expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn error_reply_snapshot() {
+        let moves = [
+            (MoveError::NotLegal, "Illegal move: e2e5"),
+            (
+                MoveError::NoPromotionPiece,
+                "Illegal move (no promotion piece): e2e5",
+            ),
+            (MoveError::Ambiguous, "Illegal move (ambiguous): e2e5"),
+            (MoveError::InCheck, "Illegal move (in check): e2e5"),
+            (MoveError::NotYourMove, "Illegal move (not your move): e2e5"),
+        ];
+        for (error, expected) in moves.iter() {
+            assert_eq!(XBoard::illegal_move_reply("e2e5", *error), *expected);
+        }
+
+        let commands = [
+            (
+                CommandError::Unknown(String::from("foo bar")),
+                "Error (unknown command): foo bar",
+            ),
+            (
+                CommandError::InvalidArgument(String::from("sd x")),
+                "Error (invalid argument): sd x",
+            ),
+            (
+                CommandError::IllegalPosition(String::from("no kings")),
+                "tellusererror Illegal position: no kings",
+            ),
+        ];
+        for (error, expected) in commands.iter() {
+            assert_eq!(XBoard::command_error_reply(error), *expected);
+        }
+    }
+
+    // Returns the error replies the engine sends, in order, until it has
+    // sent the given number of them.
+    fn error_replies(handle: &MockHandle, count: usize) -> Vec<String> {
+        let is_error = |c: &CommControl| {
+            matches!(
+                c,
+                CommControl::IllegalMove(..) | CommControl::CommandError(_)
+            )
+        };
+
+        (0..count)
+            .map(
+                |_| match handle.recv_until(Duration::from_secs(60), is_error) {
+                    Some(CommControl::IllegalMove(m, e)) => XBoard::illegal_move_reply(&m, e),
+                    Some(CommControl::CommandError(e)) => XBoard::command_error_reply(&e),
+                    _ => panic!("no error reply"),
+                },
+            )
+            .collect()
+    }
+
+    // A scripted session with bad input. Every reply is locked down, as
+    // GUIs act on the exact syntax.
+    #[test]
+    fn scripted_error_replies() {
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, &["--hash", "1", "--quiet"]);
+        let script = [
+            "new",
+            "force",
+            "usermove e2e5",
+            "setboard 4k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+            "usermove a7a8",
+            "setboard 4k3/8/8/8/8/8/8/4K2r w - - 0 1",
+            "usermove e1d1",
+            "setboard 4k3/8/8/8/8/8/8/1N3N1K w - - 0 1",
+            "usermove Nd2",
+            "foo bar",
+            "post",
+            "sd x",
+            "level 40 x 0",
+            "setboard 8/8/8/8/8/8/8/8 w - - 0 1",
+            "new",
+            "sd 30",
+            "go",
+            "usermove e7e5",
+            "?",
+        ];
+        for line in script.iter() {
+            handle.send(XBoard::create_report(line));
+        }
+
+        let replies = error_replies(&handle, 9);
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+
+        let expected = [
+            "Illegal move: e2e5",
+            "Illegal move (no promotion piece): a7a8",
+            "Illegal move (in check): e1d1",
+            "Illegal move (ambiguous): Nd2",
+            "Error (unknown command): foo bar",
+            "Error (invalid argument): sd x",
+            "Error (invalid argument): level 40 x 0",
+            "tellusererror Illegal position: FEN: white must have exactly one king, but has 0",
+            "Illegal move (not your move): e7e5",
+        ];
+        assert_eq!(replies, expected);
     }
 }
@@ -67,6 +67,7 @@ impl Castling {
     pub const BK: u8 = 4;
     pub const BQ: u8 = 8;
     pub const ALL: u8 = 15;
+    pub const EACH: [u8; 4] = [Castling::WK, Castling::WQ, Castling::BK, Castling::BQ];
 }
 
 pub const EMPTY: u64 = 0;
@@ -74,16 +75,3 @@ pub const MAX_GAME_MOVES: usize = 2048;
 pub const MAX_LEGAL_MOVES: u8 = 255;
 pub const MAX_PLY: i8 = 125;
 pub const MAX_MOVE_RULE: u8 = 100; // 50/75 move rule
-
-// Define errors
-pub type EngineRunResult = Result<(), u8>;
-pub const ENGINE_RUN_ERRORS: [&str; 8] = [
-    "FEN: Must have six parts",
-    "FEN: Pieces and squares incorrect",
-    "FEN: Color selection incorrect",
-    "FEN: Castling permissions incorrect",
-    "FEN: En-passant square incorrect",
-    "FEN: Half-move clock incorrect",
-    "FEN: Full-move number incorrect",
-    "XBoard not yet implemented.",
-];
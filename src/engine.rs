@@ -22,33 +22,44 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 mod about;
+mod analysis;
+mod clock;
 mod comm_reports;
 pub mod defs;
 mod main_loop;
 mod search_reports;
+mod speedtest;
+mod time_watch;
 mod transposition;
 mod utils;
 
 use crate::{
     board::Board,
-    comm::{uci::Uci, CommControl, CommType, IComm},
-    defs::EngineRunResult,
+    comm::{uci::Uci, xboard::XBoard, CommControl, CommReport, CommType, IComm},
     engine::defs::{
-        EngineOption, EngineOptionDefaults, EngineOptionName, ErrFatal, Information, Settings,
-        UiElement,
+        EngineOption, EngineOptionDefaults, EngineRunError, EngineRunResult, ErrFatal, Information,
+        SearchTracker, Settings, TTChange, XBoardSpecifics, PERSIST_HASH_FILE,
     },
-    misc::{cmdline::CmdLine, perft},
-    movegen::MoveGenerator,
+    misc::{affinity, cmdline::CmdLine, perft, random::EngineRandom},
+    movegen::{defs::Move, MoveGenerator},
     search::{defs::SearchControl, Search},
 };
+use clock::GameClock;
 use crossbeam_channel::Receiver;
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, RwLock},
+};
+use time_watch::TimeWatch;
 use transposition::{PerftData, SearchData, TT};
 
 #[cfg(feature = "extra")]
 use crate::{
     board::defs::Pieces,
-    extra::{testsuite, wizardry},
+    extra::{
+        epdtest::{self, EpdLimit},
+        perftsuite, selfplay, testsuite, tuner, wizardry,
+    },
 };
 
 // This struct holds the chess engine and its functions, so they are not
@@ -60,72 +71,64 @@ pub struct Engine {
     cmdline: CmdLine,                       // Command line interpreter.
     comm: Box<dyn IComm>,                   // Communications (active).
     board: Arc<Mutex<Board>>,               // This is the main engine board.
-    tt_perft: Arc<Mutex<TT<PerftData>>>,    // TT for running perft.
-    tt_search: Arc<Mutex<TT<SearchData>>>,  // TT for search information.
+    tt_perft: Arc<RwLock<TT<PerftData>>>,   // TT for running perft.
+    tt_search: Arc<RwLock<TT<SearchData>>>, // TT for search information.
     mg: Arc<MoveGenerator>,                 // Move Generator.
     info_rx: Option<Receiver<Information>>, // Receiver for incoming information.
     search: Search,                         // Search object (active).
-    tmp_no_xboard: bool,                    // Temporary variable to disable xBoard
+    searches: SearchTracker,                // Searches started and discarded.
+    deferred: VecDeque<CommReport>,         // Reports received while waiting.
+    last_pv: Vec<Move>,                     // Main line of the latest search.
+    clock: GameClock,                       // Simulated clock for console play.
+    xboard: XBoardSpecifics,                // State used by XBoard only.
+    tt_changes: Vec<TTChange>,              // TT changes waiting for the search.
+    random: EngineRandom,                   // Source of all random decisions.
+    time_watch: TimeWatch,                  // Checks the time used on the clock.
 }
 
 impl Engine {
-    // Create e new engine.
-    pub fn new() -> Self {
-        // Determine if the compiled engine is 32 or 64-bit
-        let is_64_bit = std::mem::size_of::<usize>() == 8;
-
+    // Create a new engine, using the Comm module selected on the command
+    // line. If that selection is unknown, return the error.
+    pub fn new() -> Result<Self, EngineRunError> {
         // Create the command-line object.
         let cmdline = CmdLine::new();
-        let mut is_xboard = false;
 
         // Create the communication interface
-        let comm: Box<dyn IComm> = match &cmdline.comm()[..] {
-            CommType::XBOARD => {
-                is_xboard = true;
-                Box::new(Uci::new())
-            }
-            CommType::UCI => Box::new(Uci::new()),
-            _ => panic!("{}", ErrFatal::CREATE_COMM),
-        };
+        let comm = Engine::create_comm(&cmdline.comm())?;
+
+        Ok(Engine::with_comm(cmdline, comm))
+    }
+
+    // Creates the Comm module for the given protocol.
+    fn create_comm(protocol: &str) -> Result<Box<dyn IComm>, EngineRunError> {
+        match protocol {
+            CommType::XBOARD => Ok(Box::new(XBoard::new())),
+            CommType::UCI => Ok(Box::new(Uci::new())),
+            comm => Err(EngineRunError::UnknownComm(String::from(comm))),
+        }
+    }
+}
 
+impl Engine {
+    // Create a new engine using the given command line and Comm module,
+    // instead of the ones determined by the engine's own arguments. This
+    // makes it possible to drive the engine through a mock Comm module.
+    pub fn with_comm(cmdline: CmdLine, comm: Box<dyn IComm>) -> Self {
         // Get engine settings from the command-line.
-        let threads = cmdline.threads();
+        let (threads, _) = Engine::resolve_threads(cmdline.threads());
+        let affinity = cmdline.affinity() && affinity::is_supported();
         let quiet = cmdline.has_quiet();
         let tt_size = cmdline.hash();
-        let tt_max = if is_64_bit {
-            EngineOptionDefaults::HASH_MAX_64_BIT
-        } else {
-            EngineOptionDefaults::HASH_MAX_32_BIT
-        };
+        let max_pv_length = cmdline.max_pv_length();
+        let random = EngineRandom::new(cmdline.seed());
 
         // List of options that should be announced to the GUI.
-        let options = vec![
-            EngineOption::new(
-                EngineOptionName::HASH,
-                UiElement::Spin,
-                Some(EngineOptionDefaults::HASH_DEFAULT.to_string()),
-                Some(EngineOptionDefaults::HASH_MIN.to_string()),
-                Some(tt_max.to_string()),
-            ),
-            EngineOption::new(
-                EngineOptionName::CLEAR_HASH,
-                UiElement::Button,
-                None,
-                None,
-                None,
-            ),
-        ];
-
-        // Initialize correct TT.
-        let tt_perft: Arc<Mutex<TT<PerftData>>>;
-        let tt_search: Arc<Mutex<TT<SearchData>>>;
-        if cmdline.perft() > 0 {
-            tt_perft = Arc::new(Mutex::new(TT::<PerftData>::new(tt_size)));
-            tt_search = Arc::new(Mutex::new(TT::<SearchData>::new(0)));
-        } else {
-            tt_perft = Arc::new(Mutex::new(TT::<PerftData>::new(0)));
-            tt_search = Arc::new(Mutex::new(TT::<SearchData>::new(tt_size)));
-        };
+        let options = EngineOption::registry();
+
+        // The TTs are empty until run() knows which one it needs. This
+        // keeps creating an engine cheap, even with a large hash size.
+        let tt_perft = Arc::new(RwLock::new(TT::<PerftData>::new(0)));
+        let tt_search = Arc::new(RwLock::new(TT::<SearchData>::new(0)));
 
         // Create the engine itself.
         Self {
@@ -134,26 +137,58 @@ impl Engine {
                 threads,
                 quiet,
                 tt_size,
+                report_interval: EngineOptionDefaults::REPORT_INTERVAL_DEFAULT
+                    .parse()
+                    .unwrap_or(0),
+                debug: false,
+                show_board: false,
+                ponder: EngineOptionDefaults::PONDER_DEFAULT
+                    .parse()
+                    .unwrap_or(false),
+                multipv: EngineOptionDefaults::MULTIPV_DEFAULT.parse().unwrap_or(1),
+                singular_margin: EngineOptionDefaults::SINGULAR_MARGIN_DEFAULT
+                    .parse()
+                    .unwrap_or(150),
+                persist_hash: EngineOptionDefaults::PERSIST_HASH_DEFAULT
+                    .parse()
+                    .unwrap_or(false),
+                adaptive_search: EngineOptionDefaults::ADAPTIVE_SEARCH_DEFAULT
+                    .parse()
+                    .unwrap_or(false),
+                complete_iterations: EngineOptionDefaults::COMPLETE_ITERATIONS_DEFAULT
+                    .parse()
+                    .unwrap_or(false),
+                max_pv_length,
+                affinity,
             },
             options: Arc::new(options),
             cmdline,
             comm,
             board: Arc::new(Mutex::new(Board::new())),
-            mg: Arc::new(MoveGenerator::new()),
+            mg: MoveGenerator::shared(),
             tt_perft,
             tt_search,
             info_rx: None,
             search: Search::new(),
-            tmp_no_xboard: is_xboard,
+            searches: SearchTracker::new(),
+            deferred: VecDeque::new(),
+            last_pv: Vec::new(),
+            clock: GameClock::new(),
+            xboard: XBoardSpecifics::new(),
+            tt_changes: Vec::new(),
+            random,
+            time_watch: TimeWatch::new(),
         }
     }
 
     // Run the engine.
     pub fn run(&mut self) -> EngineRunResult {
-        // This is temporary. Quit the engine immediately if anyone tries
-        // to start it in XBoard mode, as this is not implemented yet.
-        if self.tmp_no_xboard {
-            return Err(7);
+        self.allocate_tt();
+
+        // A single search with JSON output prints nothing but the result,
+        // so this is handled before anything else is printed.
+        if self.cmdline.has_json() {
+            return self.analysis();
         }
 
         self.print_ascii_logo();
@@ -169,22 +204,40 @@ impl Engine {
         // Run perft if requested.
         if self.cmdline.perft() > 0 {
             action_requested = true;
-            perft::run(
+            let run = if self.cmdline.has_divide() {
+                perft::run_divide
+            } else {
+                perft::run
+            };
+            run(
                 self.board.clone(),
                 self.cmdline.perft(),
                 Arc::clone(&self.mg),
                 Arc::clone(&self.tt_perft),
                 self.settings.tt_size > 0,
+                self.settings.threads,
             );
         }
 
+        // Run the speed test if requested.
+        if self.cmdline.has_speedtest() {
+            action_requested = true;
+            self.speedtest();
+        }
+
+        // Run the bench if requested.
+        if let Some(depth) = self.cmdline.bench() {
+            action_requested = true;
+            print!("{}", self.bench_report(depth));
+        }
+
         // === Only available with "extra" features enabled. ===
         #[cfg(feature = "extra")]
         // Generate magic numbers if requested.
         if self.cmdline.has_wizardry() {
             action_requested = true;
-            wizardry::find_magics(Pieces::ROOK);
-            wizardry::find_magics(Pieces::BISHOP);
+            wizardry::find_magics(Pieces::ROOK, &mut self.random);
+            wizardry::find_magics(Pieces::BISHOP, &mut self.random);
         };
 
         #[cfg(feature = "extra")]
@@ -194,15 +247,67 @@ impl Engine {
         // not available in a non-extra compilation, so it cannot be
         // checked there. Just fix the issue by resizing both the perft and
         // search TT's appropriately for running the EPD suite.
-        if self.cmdline.has_test() {
+        if self.cmdline.has_test() && self.cmdline.epd_test_file().is_none() {
             action_requested = true;
             self.tt_perft
-                .lock()
+                .write()
                 .expect(ErrFatal::LOCK)
                 .resize(self.settings.tt_size);
-            self.tt_search.lock().expect(ErrFatal::LOCK).resize(0);
+            self.tt_search.write().expect(ErrFatal::LOCK).resize(0);
             testsuite::run(Arc::clone(&self.tt_perft), self.settings.tt_size > 0);
         }
+
+        #[cfg(feature = "extra")]
+        // Run the best move test in the given EPD file if requested. This
+        // searches, so it uses the search TT.
+        if let Some(file) = self.cmdline.epd_test_file() {
+            action_requested = true;
+            let limit = match self.cmdline.depth() {
+                Some(d) => EpdLimit::Depth(d),
+                None => EpdLimit::MoveTime(self.cmdline.movetime()),
+            };
+            epdtest::run(
+                &file,
+                Arc::clone(&self.mg),
+                Arc::clone(&self.tt_search),
+                limit,
+            );
+        }
+
+        #[cfg(feature = "extra")]
+        // Run the perft suite from the given EPD file if requested.
+        if let Some(file) = self.cmdline.perft_suite() {
+            action_requested = true;
+            perftsuite::run(
+                &file,
+                Arc::clone(&self.mg),
+                Arc::clone(&self.tt_perft),
+                self.settings.tt_size > 0,
+            );
+        }
+
+        #[cfg(feature = "extra")]
+        // Play a selfplay match if requested.
+        if self.cmdline.selfplay() > 0 {
+            action_requested = true;
+            self.tt_search.write().expect(ErrFatal::LOCK).resize(0);
+            selfplay::run(
+                self.cmdline.selfplay(),
+                self.cmdline.selfplay_depths(),
+                &self.cmdline.fen(),
+                Arc::clone(&self.mg),
+                self.settings.tt_size,
+                self.cmdline.selfplay_variety(),
+                &mut self.random,
+            );
+        }
+
+        #[cfg(feature = "extra")]
+        // Tune the evaluation on the positions in the given file.
+        if let Some(file) = self.cmdline.tune() {
+            action_requested = true;
+            tuner::run(&file, Arc::clone(&self.mg));
+        }
         // =====================================================
 
         // In the main loop, the engine manages its resources so it will be
@@ -218,8 +323,30 @@ impl Engine {
         Ok(())
     }
 
+    // Give the TT that is going to be used its full size: the perft TT
+    // when running perft, or the search TT otherwise.
+    fn allocate_tt(&mut self) {
+        let tt_size = self.settings.tt_size;
+        if self.cmdline.perft() > 0 || self.cmdline.perft_suite().is_some() {
+            self.tt_perft.write().expect(ErrFatal::LOCK).resize(tt_size);
+        } else {
+            self.tt_search
+                .write()
+                .expect(ErrFatal::LOCK)
+                .resize(tt_size);
+        }
+    }
+
+    // In JSON mode, errors are reported as JSON by the engine itself.
+    pub fn has_json_output(&self) -> bool {
+        self.cmdline.has_json()
+    }
+
     // This function quits Commm, Search, and then the engine thread itself.
     pub fn quit(&mut self) {
+        if self.settings.persist_hash {
+            self.save_tt_search(PERSIST_HASH_FILE);
+        }
         self.search.send(SearchControl::Quit);
         self.comm.send(CommControl::Quit);
         self.quit = true;
@@ -22,7 +22,11 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::Engine;
-use crate::{defs::About, engine::defs::Settings};
+use crate::{
+    defs::About,
+    engine::defs::Settings,
+    misc::affinity::{self, ThreadCount},
+};
 
 // This notice is displayed if the engine is a debug binary. (Debug
 // binaries are unoptimized and slower than release binaries.)
@@ -51,14 +55,36 @@ impl Engine {
         println!("{:<10} {}", "Website:", About::WEBSITE);
         println!("{:<10} {}-bit", "Type:", bits);
         println!("{:<10} {} MB", "TT size:", s.tt_size);
+        self.print_threads(s);
+        println!("{:<10} {}", "Seed:", self.random.seed());
 
-        if s.threads == 1 {
-            println!("{:<10} {}", "Threads:", s.threads)
+        #[cfg(debug_assertions)]
+        println!("{}", NOTICE_DEBUG_MODE);
+    }
+
+    // Print the number of threads, and where they run if they are pinned
+    // to CPUs. A request for too many threads is reported.
+    fn print_threads(&self, s: &Settings) {
+        let requested = self.cmdline.threads();
+        let auto = if requested == ThreadCount::Auto {
+            " (auto)"
         } else {
-            println!("{:<10} {} (unused, always 1)", "Threads:", s.threads)
+            ""
         };
+        println!("{:<10} {}{}", "Threads:", s.threads, auto);
 
-        #[cfg(debug_assertions)]
-        println!("{}", NOTICE_DEBUG_MODE);
+        if let (_, Some(warning)) = Engine::resolve_threads(requested) {
+            println!("{:<10} {}", "Warning:", warning);
+        }
+
+        if s.affinity {
+            let cpus: Vec<String> = affinity::placement(s.threads)
+                .iter()
+                .map(|cpu| cpu.to_string())
+                .collect();
+            println!("{:<10} CPUs {}", "Affinity:", cpus.join(", "));
+        } else if self.cmdline.affinity() {
+            println!("{:<10} not supported by this build", "Affinity:");
+        }
     }
 }
@@ -0,0 +1,208 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements a single search from the command line, for use by
+// other programs. The result of the search is printed as exactly one JSON
+// object, and nothing else is written to stdout. An error, such as an
+// invalid FEN-string, is printed as a JSON object with an "error" field.
+
+use super::{
+    defs::{EngineRunError, EngineRunResult, ErrFatal, Information},
+    Engine,
+};
+use crate::{
+    movegen::defs::Move,
+    search::{
+        defs::{
+            SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchReport,
+            SearchShared, SearchSummary,
+        },
+        Search,
+    },
+};
+
+impl Engine {
+    // Runs one search on the position given on the command line, and
+    // prints the result as JSON.
+    pub fn analysis(&mut self) -> EngineRunResult {
+        let playable = match self.setup_position() {
+            Ok(()) => self
+                .game_end()
+                .map_or(Ok(()), |end| Err(EngineRunError::NoLegalMoves(end))),
+            Err(e) => Err(EngineRunError::from(e)),
+        };
+
+        if let Err(e) = playable {
+            println!("{}", json_error(&e.to_string()));
+            return Err(e);
+        }
+
+        let mut search_params = SearchParams::new();
+        search_params.search_mode = SearchMode::MoveTime;
+        search_params.move_time = self.cmdline.movetime();
+        search_params.quiet = true;
+
+        // The search runs on this thread. The control channel must exist,
+        // but nothing is ever sent on it.
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut board = self.board.lock().expect(ErrFatal::LOCK).clone();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let tt = self.tt_search.read().expect(ErrFatal::LOCK);
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &self.mg,
+            tt: &tt,
+            search_params: &mut search_params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let (best_move, ponder_move, _) = Search::iterative_deepening(&mut refs);
+
+        // Only the summary of the last completed depth is reported.
+        let mut summary = None;
+        while let Ok(information) = report_rx.try_recv() {
+            if let Information::Search(SearchReport::SearchSummary(s)) = information {
+                summary = Some(s);
+            }
+        }
+
+        let max_pv_length = self.settings.max_pv_length;
+        println!(
+            "{}",
+            json_result(best_move, ponder_move, summary, max_pv_length)
+        );
+        Ok(())
+    }
+}
+
+// The result of the search as a JSON object. The PV is capped at the
+// given length; the best move and the ponder move are not affected.
+fn json_result(
+    best_move: Move,
+    ponder_move: Option<Move>,
+    summary: Option<SearchSummary>,
+    max_pv_length: usize,
+) -> String {
+    let mut fields = vec![
+        format!("\"bestmove\":{}", json_move(Some(best_move))),
+        format!("\"ponder\":{}", json_move(ponder_move)),
+    ];
+
+    if let Some(mut s) = summary {
+        s.cap_pv(max_pv_length);
+        let score = match s.score.mate_in() {
+            Some(moves) => format!("\"score_mate\":{}", moves),
+            None => format!("\"score_cp\":{}", s.score.value()),
+        };
+        let pv: Vec<String> = s.pv.iter().map(|m| json_move(Some(*m))).collect();
+
+        fields.push(score);
+        fields.push(format!("\"depth\":{}", s.depth));
+        fields.push(format!("\"seldepth\":{}", s.seldepth));
+        fields.push(format!("\"nodes\":{}", s.nodes));
+        fields.push(format!("\"nps\":{}", s.nps));
+        fields.push(format!("\"time_ms\":{}", s.time));
+        fields.push(format!("\"pv\":[{}]", pv.join(",")));
+        fields.push(format!("\"pv_truncated\":{}", s.pv_truncated));
+    }
+
+    format!("{{{}}}", fields.join(","))
+}
+
+// Moves are written as strings in long algebraic notation. A missing or
+// null move is written as null.
+fn json_move(m: Option<Move>) -> String {
+    match m.filter(|m| !m.is_null()) {
+        Some(m) => format!("\"{}\"", m.as_string()),
+        None => String::from("null"),
+    }
+}
+
+fn json_error(message: &str) -> String {
+    let escaped = message.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("{{\"error\":\"{}\"}}", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::Board,
+        movegen::MoveGenerator,
+        search::defs::{Score, ScoreBound},
+    };
+
+    // The moves of the line e4 e5 Nf3 Nc6 from the start position.
+    fn line() -> Vec<Move> {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+        ["e2e4", "e7e5", "g1f3", "b8c6"]
+            .iter()
+            .map(|s| {
+                let m = board
+                    .legal_moves_iter(&mg)
+                    .find(|m| m.as_string() == *s)
+                    .expect(s);
+                assert!(board.make(m, &mg));
+                m
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_pv_is_capped() {
+        let pv = line();
+        let summary = SearchSummary {
+            depth: 4,
+            seldepth: 6,
+            time: 10,
+            score: Score::new(25),
+            nodes: 1000,
+            nps: 100000,
+            hash_full: 0,
+            pv: pv.clone(),
+            multipv: 0,
+            bound: ScoreBound::Exact,
+            pv_truncated: false,
+        };
+        let start = "{\"bestmove\":\"e2e4\",\"ponder\":\"e7e5\",\"score_cp\":25,\"depth\":4,\
+                     \"seldepth\":6,\"nodes\":1000,\"nps\":100000,\"time_ms\":10,";
+
+        let full = json_result(pv[0], Some(pv[1]), Some(summary.clone()), 0);
+        let expected = "\"pv\":[\"e2e4\",\"e7e5\",\"g1f3\",\"b8c6\"],\"pv_truncated\":false}";
+        assert_eq!(full, format!("{}{}", start, expected));
+
+        let capped = json_result(pv[0], Some(pv[1]), Some(summary), 1);
+        let expected = "\"pv\":[\"e2e4\"],\"pv_truncated\":true}";
+        assert_eq!(capped, format!("{}{}", start, expected));
+
+        let no_summary = json_result(pv[0], None, None, 1);
+        assert_eq!(no_summary, "{\"bestmove\":\"e2e4\",\"ponder\":null}");
+    }
+}
@@ -0,0 +1,311 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// clock.rs implements a simulated chess clock. It is used when playing
+// against the engine in a terminal window, where there is no GUI to keep
+// track of the time. The clock runs on real elapsed time for both the
+// engine and the user.
+
+use super::Engine;
+use crate::{
+    comm::CommControl,
+    defs::{Side, Sides},
+    engine::defs::{ErrFatal, ErrNormal, GameEnd},
+    search::defs::{GameTime, SearchMode, SearchParams},
+};
+use std::time::Instant;
+
+// The two sides of the clock.
+#[derive(PartialEq, Copy, Clone)]
+pub enum ClockSide {
+    Engine,
+    User,
+}
+
+pub struct GameClock {
+    enabled: bool,              // Clock is in use.
+    engine_time: u128,          // Time left for the engine (msecs).
+    user_time: u128,            // Time left for the user (msecs).
+    increment: u128,            // Increment per move for both sides (msecs).
+    running: Option<ClockSide>, // Side whose clock is currently running.
+    started: Option<Instant>,   // When the running clock was started.
+    paused: Option<Instant>,    // When the running clock was paused.
+    forfeit: Option<Side>,      // Side that lost the game on time.
+}
+
+impl GameClock {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            engine_time: 0,
+            user_time: 0,
+            increment: 0,
+            running: None,
+            started: None,
+            paused: None,
+            forfeit: None,
+        }
+    }
+
+    // Set both clocks to the given base time and increment and enable the
+    // clock. Neither clock will run until the first move is made.
+    pub fn set(&mut self, base: u128, increment: u128) {
+        self.enabled = true;
+        self.engine_time = base;
+        self.user_time = base;
+        self.increment = increment;
+        self.running = None;
+        self.started = None;
+        self.paused = None;
+        self.forfeit = None;
+    }
+
+    pub fn set_time(&mut self, side: ClockSide, time: u128) {
+        match side {
+            ClockSide::Engine => self.engine_time = time,
+            ClockSide::User => self.user_time = time,
+        }
+    }
+
+    // The given side lost on time. Both clocks stop, and the game stays
+    // over until the clock is set again or a new game starts.
+    pub fn flag(&mut self, loser: Side) {
+        self.running = None;
+        self.started = None;
+        self.paused = None;
+        self.forfeit = Some(loser);
+    }
+
+    pub fn clear_forfeit(&mut self) {
+        self.forfeit = None;
+    }
+
+    pub fn forfeit(&self) -> Option<Side> {
+        self.forfeit
+    }
+
+    // Stop the running clock, without switching sides.
+    pub fn pause(&mut self) {
+        if self.started.is_some() && self.paused.is_none() {
+            self.paused = Some(Instant::now());
+        }
+    }
+
+    // Restart the running clock. The time it was paused is not deducted.
+    pub fn resume(&mut self) {
+        if let (Some(paused), Some(started)) = (self.paused.take(), self.started) {
+            self.started = Some(started + paused.elapsed());
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn time(&self, side: ClockSide) -> u128 {
+        match side {
+            ClockSide::Engine => self.engine_time,
+            ClockSide::User => self.user_time,
+        }
+    }
+
+    pub fn increment(&self) -> u128 {
+        self.increment
+    }
+
+    pub fn running(&self) -> Option<ClockSide> {
+        self.running
+    }
+
+    // Stop the running clock and start the clock of the given side, as
+    // if pressing the button on a chess clock. The elapsed time is
+    // deducted from the side whose clock was running, which then
+    // receives its increment. If that side ran out of time, it has lost
+    // on time, and this side is returned.
+    pub fn press(&mut self, next: ClockSide) -> Option<ClockSide> {
+        let mut flagged = None;
+
+        // Pressing the clock also ends a pause.
+        self.resume();
+
+        if let (Some(side), Some(started)) = (self.running, self.started) {
+            let elapsed = started.elapsed().as_millis();
+            let time = self.time(side);
+
+            if elapsed >= time {
+                self.set_time(side, 0);
+                flagged = Some(side);
+            } else {
+                self.set_time(side, time - elapsed + self.increment);
+            }
+        }
+
+        self.running = Some(next);
+        self.started = Some(Instant::now());
+
+        flagged
+    }
+
+    // Print the clock as "engine m:ss.t, user m:ss.t".
+    pub fn as_string(&self) -> String {
+        format!(
+            "Clock: engine {}, user {}",
+            GameClock::time_as_string(self.engine_time),
+            GameClock::time_as_string(self.user_time)
+        )
+    }
+
+    fn time_as_string(msecs: u128) -> String {
+        let minutes = msecs / 60_000;
+        let seconds = (msecs % 60_000) / 1000;
+        let tenths = (msecs % 1000) / 100;
+
+        format!("{}:{:02}.{}", minutes, seconds, tenths)
+    }
+}
+
+// These functions tie the simulated clock to the engine's search.
+impl Engine {
+    // Start a search using the simulated clock. The user's clock is
+    // stopped, and the engine's clock starts running.
+    pub fn clock_start_search(&mut self, mut sp: SearchParams) {
+        if self.clock.forfeit().is_some() {
+            let msg = String::from(ErrNormal::TIME_FORFEIT);
+            self.comm.send(CommControl::InfoString(msg));
+            return;
+        }
+
+        if let Some(side) = self.clock.press(ClockSide::Engine) {
+            self.clock_flagged(side);
+            return;
+        }
+
+        let engine_time = self.clock.time(ClockSide::Engine);
+        let user_time = self.clock.time(ClockSide::User);
+        let inc = self.clock.increment();
+        let white = self.board.lock().expect(ErrFatal::LOCK).us() == Sides::WHITE;
+        let (wtime, btime) = if white {
+            (engine_time, user_time)
+        } else {
+            (user_time, engine_time)
+        };
+
+        sp.game_time = GameTime::new(wtime, btime, inc, inc, None);
+        sp.search_mode = SearchMode::GameTime;
+        self.start_search(sp);
+    }
+
+    // The engine has sent its best move. Stop its clock and start the
+    // clock of the user.
+    pub fn clock_search_finished(&mut self) {
+        if self.clock.running() == Some(ClockSide::Engine) {
+            if let Some(side) = self.clock.press(ClockSide::User) {
+                self.clock_flagged(side);
+            } else {
+                self.comm
+                    .send(CommControl::InfoString(self.clock.as_string()));
+            }
+        }
+    }
+
+    // One of the sides has run out of time. This ends the game like a
+    // checkmate would: the result is sent, and no more moves are played
+    // until a new game starts. The engine's move is never played on the
+    // board before the clock is pressed, so the engine is the side to move.
+    fn clock_flagged(&mut self, side: ClockSide) {
+        let us = self.board.lock().expect(ErrFatal::LOCK).us();
+        let (loser, msg) = match side {
+            ClockSide::Engine => (us, "Time forfeit: the engine lost on time."),
+            ClockSide::User => (us ^ 1, "Time forfeit: you lost on time."),
+        };
+
+        self.clock.flag(loser);
+        self.comm.send(CommControl::InfoString(msg.to_string()));
+        self.comm
+            .send(CommControl::GameResult(GameEnd::TimeForfeit(loser), us));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comm::{mock::Mock, uci::Uci, CommType};
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    // While paused, the running clock stands still. Without the pause,
+    // the user would have lost on time.
+    #[test]
+    fn paused_clock_stands_still() {
+        let mut clock = GameClock::new();
+        clock.set(1000, 0);
+        clock.press(ClockSide::User);
+        clock.pause();
+        std::thread::sleep(Duration::from_millis(1200));
+        clock.resume();
+
+        assert!(clock.press(ClockSide::Engine).is_none());
+        assert!(clock.time(ClockSide::User) > 500);
+
+        // Pressing the clock while paused ends the pause.
+        clock.pause();
+        std::thread::sleep(Duration::from_millis(1200));
+        assert!(clock.press(ClockSide::User).is_none());
+        assert!(clock.time(ClockSide::Engine) > 500);
+    }
+
+    #[test]
+    fn flag_ends_the_game() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, &["--hash", "1", "--quiet"]);
+        let is_result = |c: &CommControl| matches!(c, CommControl::GameResult(..));
+        let is_forfeit_info = |c: &CommControl| match c {
+            CommControl::InfoString(msg) => msg == ErrNormal::TIME_FORFEIT,
+            _ => false,
+        };
+
+        // Without any time on the clock, the engine loses on its move.
+        handle.send(Uci::create_report("clock 0 0"));
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go"));
+        match handle.recv_until(TIMEOUT, is_result) {
+            Some(CommControl::GameResult(end, _)) => {
+                assert!(end == GameEnd::TimeForfeit(Sides::WHITE));
+                assert_eq!(end.result(Sides::BLACK), "0-1");
+            }
+            _ => panic!("no game result"),
+        }
+
+        // The game is over, so the engine won't search on the clock.
+        handle.send(Uci::create_report("go"));
+        assert!(handle.recv_until(TIMEOUT, is_forfeit_info).is_some());
+
+        // A new clock starts a new game.
+        handle.send(Uci::create_report("clock 1 0"));
+        handle.send(Uci::create_report("go"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+}
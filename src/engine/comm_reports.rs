@@ -22,16 +22,18 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::{
-    defs::{ErrFatal, ErrNormal},
+    clock::ClockSide,
+    defs::{ErrFatal, ErrNormal, MoveError, TTChange, PERSIST_HASH_FILE, XBOARD_MOVE_TIME},
     Engine,
 };
 use crate::{
-    comm::{uci::UciReport, CommControl, CommReport},
-    defs::FEN_START_POSITION,
-    engine::defs::EngineOptionName,
-    evaluation::Evaluation,
-    search::defs::{SearchControl, SearchMode, SearchParams, OVERHEAD},
+    comm::{uci::UciReport, xboard::XBoardReport, CommControl, CommReport, CommandError},
+    defs::{Sides, FEN_START_POSITION, MAX_PLY},
+    engine::defs::{EngineOptionDefaults, EngineOptionName},
+    misc::{affinity::ThreadCount, format},
+    search::defs::{GameTime, SearchControl, SearchMode, SearchParams, BENCH_DEPTH, OVERHEAD},
 };
+use std::path::Path;
 
 // This block implements handling of incoming information, which will be in
 // the form of either Comm or Search reports.
@@ -40,6 +42,7 @@ impl Engine {
         // Split out the comm reports according to their source.
         match comm_report {
             CommReport::Uci(u) => self.comm_reports_uci(u),
+            CommReport::XBoard(x) => self.comm_reports_xboard(x),
         }
     }
 
@@ -48,104 +51,1589 @@ impl Engine {
         // Setup default variables.
         let mut sp = SearchParams::new();
         sp.quiet = self.settings.quiet;
+        sp.report_interval = self.settings.report_interval * 1000;
+        sp.debug = self.settings.debug;
+        sp.multipv = self.settings.multipv;
+        sp.singular_margin = self.settings.singular_margin;
+        sp.adaptive = self.settings.adaptive_search;
+        sp.complete_iterations = self.settings.complete_iterations;
+        sp.threads = self.settings.threads;
+        sp.affinity = self.settings.affinity;
 
         match u {
             UciReport::Uci => self.comm.send(CommControl::Identify),
 
+            // A search that is still running when the position changes
+            // is stopped and its result dropped. An analysis goes on in
+            // the new position.
             UciReport::UciNewGame => {
+                let stopped = self.stop_search_and_wait();
                 self.board
                     .lock()
                     .expect(ErrFatal::LOCK)
                     .fen_read(Some(FEN_START_POSITION))
                     .expect(ErrFatal::NEW_GAME);
-                self.tt_search.lock().expect(ErrFatal::LOCK).clear();
+                self.age_tt_search();
+                self.time_watch_new_game();
+                self.clock.clear_forfeit();
+                self.restart_analysis(stopped);
             }
 
             UciReport::IsReady => self.comm.send(CommControl::Ready),
 
-            UciReport::SetOption(option) => {
-                match option {
-                    EngineOptionName::Hash(value) => {
-                        if let Ok(v) = value.parse::<usize>() {
-                            self.tt_search.lock().expect(ErrFatal::LOCK).resize(v);
-                        } else {
-                            let msg = String::from(ErrNormal::NOT_INT);
-                            self.comm.send(CommControl::InfoString(msg));
-                        }
-                    }
-
-                    EngineOptionName::ClearHash => {
-                        self.tt_search.lock().expect(ErrFatal::LOCK).clear()
-                    }
-
-                    EngineOptionName::Nothing => (),
-                };
-            }
+            UciReport::SetOption(option) => self.set_option(option),
 
             UciReport::Position(fen, moves) => {
-                let fen_result = self.board.lock().expect(ErrFatal::LOCK).fen_read(Some(fen));
+                let stopped = self.stop_search_and_wait();
+                let fen_result = self.fen_setup(fen);
 
                 if fen_result.is_ok() {
                     for m in moves.iter() {
-                        let ok = self.execute_move(m.clone());
-                        if !ok {
-                            let msg = format!("{}: {}", m, ErrNormal::NOT_LEGAL);
+                        // Don't play on after the game has ended. The GUI
+                        // may send moves past the end of the game.
+                        if let Some(end) = self.game_end() {
+                            let msg =
+                                format!("{}: {} ({}).", m, ErrNormal::GAME_OVER, end.as_str());
+                            self.comm.send(CommControl::InfoString(msg));
+                            break;
+                        }
+
+                        if let Err(e) = self.execute_move(m.clone()) {
+                            let msg = format!("{}: {}", m, e.as_str());
                             self.comm.send(CommControl::InfoString(msg));
                             break;
                         }
                     }
                 }
 
-                if fen_result.is_err() {
-                    let msg = ErrNormal::FEN_FAILED.to_string();
+                if let Err(e) = &fen_result {
+                    let msg = format!("{} {}.", ErrNormal::FEN_FAILED, e);
                     self.comm.send(CommControl::InfoString(msg));
                 }
+
+                // Show the position after the user's moves, if requested.
+                if fen_result.is_ok() && !moves.is_empty() {
+                    self.show_board(None);
+                }
+                self.restart_analysis(stopped);
             }
 
             UciReport::GoInfinite => {
-                sp.search_mode = SearchMode::Infinite;
-                self.search.send(SearchControl::Start(sp));
+                // A plain "go" searches using the clock, if there is one.
+                if self.clock.is_enabled() {
+                    self.clock_start_search(sp);
+                } else {
+                    sp.search_mode = SearchMode::Infinite;
+                    self.start_search(sp);
+                }
             }
 
             UciReport::GoDepth(depth) => {
                 sp.depth = *depth;
                 sp.search_mode = SearchMode::Depth;
-                self.search.send(SearchControl::Start(sp));
+                self.start_search(sp);
             }
 
             UciReport::GoMoveTime(msecs) => {
-                sp.move_time = *msecs - (OVERHEAD as u128);
+                // Don't subtract the overhead if nothing would be left.
+                sp.move_time = if *msecs > OVERHEAD {
+                    msecs - OVERHEAD
+                } else {
+                    *msecs
+                };
                 sp.search_mode = SearchMode::MoveTime;
-                self.search.send(SearchControl::Start(sp));
+                self.start_search(sp);
             }
 
             UciReport::GoNodes(nodes) => {
                 sp.nodes = *nodes;
                 sp.search_mode = SearchMode::Nodes;
-                self.search.send(SearchControl::Start(sp));
+                self.start_search(sp);
+            }
+
+            // A mate in N moves takes 2N - 1 plies. The search stops early
+            // if it finds the mate before reaching that depth.
+            UciReport::GoMate(moves) => {
+                sp.mate = *moves;
+                sp.depth = (2 * (*moves as i16) - 1).min(MAX_PLY as i16) as i8;
+                sp.search_mode = SearchMode::Mate;
+                self.start_search(sp);
             }
 
             UciReport::GoGameTime(gt) => {
+                if gt.clamped {
+                    let msg = String::from(ErrNormal::NEGATIVE_TIME);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
                 sp.game_time = *gt;
                 sp.search_mode = SearchMode::GameTime;
-                self.search.send(SearchControl::Start(sp));
+                self.time_watch_start(gt);
+                self.start_search(sp);
+            }
+
+            // Time spent pondering can't be compared to the clock, so
+            // the next search on the clock starts without expectations.
+            UciReport::GoPonder(gt) => {
+                self.time_watch.forget();
+                sp.game_time = *gt;
+                sp.search_mode = SearchMode::Ponder;
+                self.start_search(sp);
             }
 
+            UciReport::PonderHit => self.search.send(SearchControl::PonderHit),
             UciReport::Stop => self.search.send(SearchControl::Stop),
             UciReport::Quit => self.quit(),
+            UciReport::Debug(on) => self.settings.debug = *on,
+            UciReport::Register => self.comm.send(CommControl::Registration),
 
             // Custom commands
-            UciReport::Board => self.comm.send(CommControl::PrintBoard),
-            UciReport::History => self.comm.send(CommControl::PrintHistory),
-            UciReport::Eval => {
-                let mtx_board = &self.board.lock().expect(ErrFatal::LOCK);
-                let eval = Evaluation::evaluate_position(&mtx_board);
-                let p_v = mtx_board.game_state.phase_value;
-                let msg = format!("Evaluation: {} centipawns, phase value: {}", eval, p_v);
-                self.comm.send(CommControl::InfoString(msg));
+            UciReport::Board => {
+                self.comm
+                    .send(CommControl::PrintBoard(self.board_snapshot()));
+                if self.clock.is_enabled() {
+                    self.comm
+                        .send(CommControl::InfoString(self.clock.as_string()));
+                }
+            }
+            UciReport::ShowBoard(on) => self.settings.show_board = *on,
+            UciReport::History(raw) => {
+                let board = self.board_snapshot();
+                let history = if *raw {
+                    format::history(&board)
+                } else {
+                    let result = match self.game_end() {
+                        Some(end) => format!("{} ({})", end.result(board.us()), end.as_str()),
+                        None => String::from("*"),
+                    };
+                    format::history_san(&board, &self.mg, &result)
+                };
+                self.comm.send(CommControl::PrintHistory(history));
+            }
+            UciReport::Pgn => self.print_pgn(),
+            UciReport::Eval => self.print_eval(),
+            UciReport::See(m) => self.print_see(m),
+            UciReport::Attackers(square) => self.print_attackers(square),
+            UciReport::MateIn(moves) => self.verify_mate(*moves),
+            UciReport::Bench(depth) => self.print_bench(*depth),
+
+            // The comparison runs on the search thread, so it can be
+            // stopped. One thread suffices for the shallow searches.
+            UciReport::Compare(depth) => {
+                sp.depth = *depth;
+                sp.search_mode = SearchMode::Compare;
+                sp.quiet = true;
+                sp.threads = 1;
+                self.start_search(sp);
             }
+            UciReport::Clock(base, increment) => {
+                self.clock.set(*base, *increment);
+                self.comm
+                    .send(CommControl::InfoString(self.clock.as_string()));
+            }
+            UciReport::MyTime(t) => self.clock.set_time(ClockSide::Engine, *t),
+            UciReport::YourTime(t) => self.clock.set_time(ClockSide::User, *t),
+            UciReport::SaveHash(path) => self.save_tt_search(path),
+            UciReport::LoadHash(path) => self.change_tt_search(TTChange::Load(path.clone())),
+            UciReport::TakeBack => {
+                let stopped = self.stop_search_and_wait();
+                if self.take_back(1) {
+                    self.show_board(None);
+                } else {
+                    let msg = String::from(ErrNormal::NO_TAKE_BACK);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+                self.restart_analysis(stopped);
+            }
+
             UciReport::Help => self.comm.send(CommControl::PrintHelp),
             UciReport::Unknown => (),
+
+            // The UCI protocol says unknown commands must be ignored. In
+            // debug mode, report them so the user can see what happened.
+            UciReport::UnknownCommand(cmd) => {
+                if self.settings.debug {
+                    let msg = format!("unknown command: {}", cmd);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+        }
+    }
+
+    // Handles "XBoard" Comm reports sent by the XBoard-module.
+    fn comm_reports_xboard(&mut self, x: &XBoardReport) {
+        match x {
+            XBoardReport::XBoard => (),
+            XBoardReport::ProtoVer(_) => self.comm.send(CommControl::Identify),
+
+            // Start a new game: the engine plays black, both clocks are
+            // back at the base time of the time control, and any depth
+            // limit is gone. A search that is still running is dropped.
+            XBoardReport::New => {
+                self.xboard_stop_thinking();
+                self.board
+                    .lock()
+                    .expect(ErrFatal::LOCK)
+                    .fen_read(Some(FEN_START_POSITION))
+                    .expect(ErrFatal::NEW_GAME);
+                self.age_tt_search();
+                self.time_watch_new_game();
+                self.xboard.force = false;
+                self.xboard.result = None;
+                self.xboard.depth = None;
+                self.xboard.time = self.xboard.base;
+                self.xboard.otim = self.xboard.base;
+            }
+
+            // In force mode, the engine plays the moves it receives for
+            // both sides, without thinking. The GUI uses this to set up
+            // a game, or to take back moves.
+            XBoardReport::Force => {
+                self.xboard_stop_thinking();
+                self.xboard.force = true;
+            }
+
+            // The engine now plays the side to move, and starts thinking.
+            XBoardReport::Go => {
+                self.xboard.force = false;
+                if !self.xboard.thinking {
+                    self.xboard_think();
+                }
+            }
+
+            // The engine plays the side not to move; it replies to the
+            // next move it receives.
+            XBoardReport::PlayOther => self.xboard.force = false,
+
+            // Take back one move, or one move of each side. The GUI is
+            // supposed to be in force mode, but a search for a move that
+            // is now taken back is dropped anyway. Undoing the last move
+            // of a game that ended makes it go on.
+            XBoardReport::Undo | XBoardReport::Remove => {
+                let (plies, cmd) = match x {
+                    XBoardReport::Undo => (1, "undo"),
+                    _ => (2, "remove"),
+                };
+
+                self.xboard_stop_thinking();
+                if self.take_back(plies) {
+                    self.xboard.result = None;
+                    self.show_board(None);
+                } else {
+                    let error = CommandError::NoTakeBack(cmd.to_string());
+                    self.comm.send(CommControl::CommandError(error));
+                }
+            }
+
+            // Suggest a move to the user. Without a hint, the engine
+            // stays silent.
+            XBoardReport::Hint => {
+                if let Some(m) = self.hint() {
+                    self.comm.send(CommControl::Hint(m));
+                }
+            }
+
+            // The game is over. The engine stops thinking and doesn't
+            // accept any more moves until the next game.
+            XBoardReport::Result(r) => {
+                self.xboard_stop_thinking();
+                self.xboard.result = Some(r.clone());
+            }
+
+            XBoardReport::Ping(n) => self.comm.send(CommControl::Pong(*n)),
+
+            XBoardReport::SetBoard(fen) => {
+                self.xboard_stop_thinking();
+                self.xboard.result = None;
+                if let Err(e) = self.fen_setup(fen) {
+                    let error = CommandError::IllegalPosition(e.to_string());
+                    self.comm.send(CommControl::CommandError(error));
+                }
+            }
+
+            // Play the user's move. Unless in force mode, the engine then
+            // starts thinking about its reply. If the game has ended on
+            // the board, the result is repeated instead.
+            XBoardReport::UserMove(m) => {
+                let game_end = self.game_end().filter(|_| !self.xboard.thinking);
+                if let Some(end) = game_end {
+                    let side_to_move = self.board.lock().expect(ErrFatal::LOCK).us();
+                    self.comm.send(CommControl::GameResult(end, side_to_move));
+                    return;
+                }
+
+                let result = if self.xboard.thinking {
+                    Err(MoveError::NotYourMove)
+                } else if self.xboard.result.is_some() {
+                    Err(MoveError::NotLegal)
+                } else {
+                    self.execute_move(m.clone())
+                };
+
+                if let Err(e) = result {
+                    self.comm.send(CommControl::IllegalMove(m.clone(), e));
+                } else {
+                    self.show_board(None);
+                    self.announce_position(false);
+                    if !self.xboard.force {
+                        self.xboard_think();
+                    }
+                }
+            }
+
+            // A new time control replaces a fixed time per move, and both
+            // clocks start out with the base time.
+            XBoardReport::Level {
+                moves,
+                base_ms,
+                inc_ms,
+            } => {
+                self.xboard.moves_per_session = *moves;
+                self.xboard.base = Some(*base_ms);
+                self.xboard.increment = *inc_ms;
+                self.xboard.move_time = None;
+                self.xboard.time = Some(*base_ms);
+                self.xboard.otim = Some(*base_ms);
+            }
+
+            XBoardReport::SetTime(ms) => self.xboard.move_time = Some(*ms),
+            XBoardReport::SetDepth(d) => self.xboard.depth = Some((*d).min(MAX_PLY as u8) as i8),
+            XBoardReport::Time(cs) => self.xboard.time = Some(cs * 10),
+            XBoardReport::OTim(cs) => self.xboard.otim = Some(cs * 10),
+            XBoardReport::Ics(host) => self.xboard.ics = host.clone(),
+            XBoardReport::SetOption(option) => self.set_option(option),
+
+            // While paused, the engine doesn't think and its clock stands
+            // still. A pause while the engine is not searching only stops
+            // the clock; the search thread ignores it.
+            XBoardReport::Pause => {
+                self.search.send(SearchControl::Pause);
+                self.clock.pause();
+            }
+
+            XBoardReport::Resume => {
+                self.search.send(SearchControl::Resume);
+                self.clock.resume();
+            }
+            XBoardReport::Quit => self.quit(),
+
+            // Custom commands
+            XBoardReport::ShowBoard(on) => self.settings.show_board = *on,
+            XBoardReport::Eval => self.print_eval(),
+            XBoardReport::Pgn => self.print_pgn(),
+            XBoardReport::Bench => self.print_bench(BENCH_DEPTH),
+            XBoardReport::See(m) => self.print_see(m),
+            XBoardReport::Attackers(square) => self.print_attackers(square),
+            XBoardReport::Unknown => (),
+
+            XBoardReport::UnknownCommand(cmd) => {
+                let error = CommandError::Unknown(cmd.clone());
+                self.comm.send(CommControl::CommandError(error));
+            }
+
+            XBoardReport::InvalidArgument(cmd) => {
+                let error = CommandError::InvalidArgument(cmd.clone());
+                self.comm.send(CommControl::CommandError(error));
+            }
+        }
+    }
+
+    // Start a search for the side to move, unless the game is over.
+    fn xboard_think(&mut self) {
+        if self.xboard.result.is_some() || self.game_end().is_some() {
+            return;
+        }
+
+        let mut sp = SearchParams::new();
+        sp.quiet = self.settings.quiet;
+        sp.report_interval = self.settings.report_interval * 1000;
+        sp.debug = self.settings.debug;
+        sp.singular_margin = self.settings.singular_margin;
+        sp.adaptive = self.settings.adaptive_search;
+        sp.complete_iterations = self.settings.complete_iterations;
+        sp.threads = self.settings.threads;
+        sp.affinity = self.settings.affinity;
+
+        if let Some(depth) = self.xboard.depth {
+            sp.depth = depth;
+        }
+
+        // A fixed time per move overrides the time control. Without
+        // either of them, search to the requested depth, or use the
+        // default time per move.
+        if let Some(msecs) = self.xboard.move_time {
+            sp.move_time = msecs.saturating_sub(OVERHEAD).max(1);
+            sp.search_mode = SearchMode::MoveTime;
+        } else if let Some(time) = self.xboard.time {
+            sp.game_time = self.xboard_game_time(time);
+            sp.search_mode = SearchMode::GameTime;
+        } else if self.xboard.depth.is_some() {
+            sp.search_mode = SearchMode::Depth;
+        } else {
+            sp.move_time = XBOARD_MOVE_TIME - OVERHEAD;
+            sp.search_mode = SearchMode::MoveTime;
+        }
+
+        if sp.is_game_time() {
+            self.time_watch_start(&sp.game_time);
+        } else {
+            self.time_watch.forget();
+        }
+
+        self.xboard.thinking = true;
+        self.start_search(sp);
+    }
+
+    // Stop the search for the engine's move, if there is one, and wait
+    // for it to finish. The move it found is not played.
+    fn xboard_stop_thinking(&mut self) {
+        if self.xboard.thinking {
+            self.xboard.thinking = false;
+            self.stop_search_and_wait();
+        }
+    }
+
+    // Applies an option, set by the GUI in either protocol. A value that
+    // can't be used is reported back; nothing changes.
+    fn set_option(&mut self, option: &EngineOptionName) {
+        match option {
+            EngineOptionName::Hash(value) => {
+                if let Ok(v) = value.parse::<usize>() {
+                    self.settings.tt_size = v;
+                    self.change_tt_search(TTChange::Resize(v));
+                } else {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            EngineOptionName::ClearHash => self.change_tt_search(TTChange::Clear),
+
+            // The interval is converted to milliseconds for each
+            // search, so it's kept within the option's range.
+            EngineOptionName::ReportInterval(value) => {
+                if let Ok(v) = value.parse::<u128>() {
+                    let min = EngineOptionDefaults::REPORT_INTERVAL_MIN
+                        .parse()
+                        .unwrap_or(0);
+                    let max = EngineOptionDefaults::REPORT_INTERVAL_MAX
+                        .parse()
+                        .unwrap_or(0);
+                    self.settings.report_interval = v.clamp(min, max);
+                } else {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // The GUI decides when to ponder. The engine only
+            // keeps track of whether pondering is allowed.
+            EngineOptionName::Ponder(value) => {
+                if let Ok(v) = value.parse::<bool>() {
+                    self.settings.ponder = v;
+                } else {
+                    let msg = String::from(ErrNormal::NOT_BOOL);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // Values outside the range given to the GUI are
+            // brought back into that range.
+            EngineOptionName::MultiPV(value) => {
+                if let Ok(v) = value.parse::<usize>() {
+                    let min = EngineOptionDefaults::MULTIPV_MIN.parse().unwrap_or(1);
+                    let max = EngineOptionDefaults::MULTIPV_MAX.parse().unwrap_or(1);
+                    self.settings.multipv = v.clamp(min, max);
+                } else {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            EngineOptionName::SingularMargin(value) => {
+                if let Ok(v) = value.parse::<i16>() {
+                    let min = EngineOptionDefaults::SINGULAR_MARGIN_MIN
+                        .parse()
+                        .unwrap_or(0);
+                    let max = EngineOptionDefaults::SINGULAR_MARGIN_MAX
+                        .parse()
+                        .unwrap_or(0);
+                    self.settings.singular_margin = v.clamp(min, max);
+                } else {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // Besides a number, "auto" is accepted: one thread
+            // per CPU. More threads than CPUs are not used.
+            EngineOptionName::Threads(value) => match ThreadCount::parse(value) {
+                Some(requested) => {
+                    let (threads, warning) = Engine::resolve_threads(requested);
+                    self.settings.threads = threads;
+                    if let Some(msg) = warning {
+                        self.comm.send(CommControl::InfoString(msg));
+                    }
+                }
+                None => {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            },
+
+            // Switching Persist Hash on loads the hash saved at
+            // the end of the previous session, if there is one.
+            EngineOptionName::PersistHash(value) => {
+                if let Ok(v) = value.parse::<bool>() {
+                    let load = v && !self.settings.persist_hash;
+                    self.settings.persist_hash = v;
+                    if load && Path::new(PERSIST_HASH_FILE).exists() {
+                        let file = String::from(PERSIST_HASH_FILE);
+                        self.change_tt_search(TTChange::Load(file));
+                    }
+                } else {
+                    let msg = String::from(ErrNormal::NOT_BOOL);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // Adjusts the search to the class of the position.
+            // This is meant for testing; it is off by default.
+            EngineOptionName::AdaptiveSearch(value) => {
+                if let Ok(v) = value.parse::<bool>() {
+                    self.settings.adaptive_search = v;
+                } else {
+                    let msg = String::from(ErrNormal::NOT_BOOL);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // On soft time, finish the running iteration instead
+            // of aborting it; don't start one that won't finish.
+            EngineOptionName::CompleteIterations(value) => {
+                if let Ok(v) = value.parse::<bool>() {
+                    self.settings.complete_iterations = v;
+                } else {
+                    let msg = String::from(ErrNormal::NOT_BOOL);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            EngineOptionName::MaxPVLength(value) => {
+                if let Ok(v) = value.parse::<usize>() {
+                    let min = EngineOptionDefaults::MAX_PV_LENGTH_MIN.parse().unwrap_or(0);
+                    let max = EngineOptionDefaults::MAX_PV_LENGTH_MAX.parse().unwrap_or(0);
+                    self.settings.max_pv_length = v.clamp(min, max);
+                } else {
+                    let msg = String::from(ErrNormal::NOT_INT);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            // Chess960 positions can always be set up. The option
+            // only switches castling to king-takes-rook notation,
+            // and castling rights in FEN-strings to rook files.
+            EngineOptionName::UciChess960(value) => {
+                if let Ok(v) = value.parse::<bool>() {
+                    self.board.lock().expect(ErrFatal::LOCK).chess960 = v;
+                } else {
+                    let msg = String::from(ErrNormal::NOT_BOOL);
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+
+            EngineOptionName::Nothing => (),
+        }
+    }
+
+    // XBoard sends the clocks as "engine" and "opponent". The engine is
+    // always the side to move when it starts thinking.
+    fn xboard_game_time(&self, time: u128) -> GameTime {
+        let otim = self.xboard.otim.unwrap_or(time);
+        let inc = self.xboard.increment;
+        let board = self.board.lock().expect(ErrFatal::LOCK);
+        let (wtime, btime) = if board.us() == Sides::WHITE {
+            (time, otim)
+        } else {
+            (otim, time)
+        };
+
+        // With a number of moves per session, count the moves left until
+        // the next time control, starting from the current move number.
+        let mps = self.xboard.moves_per_session;
+        let moves_to_go = if mps > 0 {
+            let played = (board.game_state.fullmove_number as usize).saturating_sub(1);
+            Some(mps - played % mps)
+        } else {
+            None
+        };
+
+        GameTime::new(wtime, btime, inc, inc, moves_to_go)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api,
+        board::{defs::Pieces, Board},
+        comm::{
+            mock::{Mock, MockHandle},
+            uci::Uci,
+            xboard::XBoard,
+            CommControl, CommType, CommandError,
+        },
+        defs::Sides,
+        engine::{
+            defs::{EngineRunError, ErrFatal, ErrNormal, GameEnd, MoveError},
+            Engine,
+        },
+        misc::{cmdline::CmdLine, format, parse},
+        movegen::defs::Move,
+        search::defs::{SearchMode, SearchParams},
+    };
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+    const ARGS: &[&str] = &["--hash", "1", "--quiet"];
+
+    // Creates an engine without starting it. Reports can be handed to it
+    // directly; what it sends back goes to the mock.
+    fn engine(protocol: &'static str) -> (Engine, MockHandle) {
+        let (mock, handle) = Mock::new(protocol);
+        let cmdline = CmdLine::from_args(&["rustic-alpha", "--hash", "1", "--quiet"]);
+        (Engine::with_comm(cmdline, Box::new(mock)), handle)
+    }
+
+    fn legal_moves(engine: &Engine, board: &Board) -> Vec<u32> {
+        let mut moves: Vec<u32> = board
+            .legal_moves_iter(&engine.mg)
+            .map(|m: Move| m.get_move())
+            .collect();
+        moves.sort_unstable();
+        moves
+    }
+
+    #[test]
+    fn usermove_plays_the_move() {
+        let (mut engine, _handle) = engine(CommType::XBOARD);
+        engine.comm_reports(&XBoard::create_report("new"));
+        engine.comm_reports(&XBoard::create_report("force"));
+        let before = engine.board.lock().expect(ErrFatal::LOCK).clone();
+
+        engine.comm_reports(&XBoard::create_report("usermove e2e4"));
+        let after = engine.board.lock().expect(ErrFatal::LOCK).clone();
+
+        // The position after 1.e4, set up from scratch.
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let mut expected = Board::new();
+        expected.fen_read(Some(fen)).expect(ErrFatal::NEW_GAME);
+
+        assert_ne!(after.game_state.zobrist_key, before.game_state.zobrist_key);
+        assert_eq!(
+            after.game_state.zobrist_key,
+            expected.game_state.zobrist_key
+        );
+        assert_eq!(
+            legal_moves(&engine, &after),
+            legal_moves(&engine, &expected)
+        );
+        assert_ne!(legal_moves(&engine, &after), legal_moves(&engine, &before));
+    }
+
+    #[test]
+    fn illegal_usermove_keeps_the_position() {
+        let (mut engine, handle) = engine(CommType::XBOARD);
+        engine.comm_reports(&XBoard::create_report("new"));
+        engine.comm_reports(&XBoard::create_report("force"));
+        let before = engine.board.lock().expect(ErrFatal::LOCK).clone();
+
+        engine.comm_reports(&XBoard::create_report("usermove e2e5"));
+        let after = engine.board.lock().expect(ErrFatal::LOCK).clone();
+
+        assert_eq!(after.game_state.zobrist_key, before.game_state.zobrist_key);
+        assert_eq!(after.history.len(), before.history.len());
+
+        let is_illegal = |c: &CommControl| matches!(c, CommControl::IllegalMove(..));
+        match handle.recv_until(Duration::from_secs(1), is_illegal) {
+            Some(CommControl::IllegalMove(m, _)) => assert_eq!(m, "e2e5"),
+            _ => panic!("no illegal move reply"),
+        }
+    }
+
+    // A move after the end of the game is answered with the result, as
+    // XBoard requires, and not with "Illegal move".
+    #[test]
+    fn usermove_after_the_end_repeats_the_result() {
+        let (mut engine, handle) = engine(CommType::XBOARD);
+        engine.comm_reports(&XBoard::create_report("new"));
+        engine.comm_reports(&XBoard::create_report("force"));
+        engine.comm_reports(&XBoard::create_report(
+            "setboard 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+        ));
+        let before = engine.board_snapshot();
+
+        engine.comm_reports(&XBoard::create_report("usermove h8g8"));
+        let after = engine.board_snapshot();
+        assert_eq!(after.game_state.zobrist_key, before.game_state.zobrist_key);
+
+        let is_reply = |c: &CommControl| {
+            matches!(
+                c,
+                CommControl::GameResult(..) | CommControl::IllegalMove(..)
+            )
+        };
+        match handle.recv_until(Duration::from_secs(1), is_reply) {
+            Some(CommControl::GameResult(end, side)) => {
+                assert!(end == GameEnd::Stalemate);
+                assert_eq!(side, Sides::BLACK);
+            }
+            _ => panic!("no result"),
         }
+
+        // The same after checkmate.
+        engine.comm_reports(&XBoard::create_report(
+            "setboard 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1",
+        ));
+        engine.comm_reports(&XBoard::create_report("usermove h8h7"));
+        match handle.recv_until(Duration::from_secs(1), is_reply) {
+            Some(CommControl::GameResult(end, side)) => {
+                assert!(end == GameEnd::Checkmate);
+                assert_eq!(side, Sides::BLACK);
+            }
+            _ => panic!("no result"),
+        }
+    }
+
+    // Taking back all moves restores everything about the position, also
+    // the en passant square, the castling rights and the material.
+    #[test]
+    fn undo_restores_the_position() {
+        let (mut engine, handle) = engine(CommType::XBOARD);
+        let fen = "r3k2r/ppp2ppp/8/3pP3/8/8/PPP2PPP/R3K2R w KQkq d6 0 10";
+        engine.comm_reports(&XBoard::create_report("force"));
+        engine.comm_reports(&XBoard::create_report(&format!("setboard {}", fen)));
+        let start = engine.board_snapshot();
+
+        for m in ["e5d6", "c7d6", "e1g1", "e8c8", "f1e1"].iter() {
+            engine.comm_reports(&XBoard::create_report(&format!("usermove {}", m)));
+        }
+        assert_eq!(engine.board_snapshot().history.len(), 5);
+
+        for cmd in ["undo", "remove", "undo", "undo"].iter() {
+            engine.comm_reports(&XBoard::create_report(cmd));
+        }
+        let board = engine.board_snapshot();
+        assert!(board.history.is_empty());
+        assert_eq!(board.fen_write(), fen);
+        assert_eq!(board.game_state.zobrist_key, start.game_state.zobrist_key);
+        assert_eq!(board.game_state.pawn_key, start.game_state.pawn_key);
+        assert_eq!(board.game_state.material, start.game_state.material);
+        assert_eq!(legal_moves(&engine, &board), legal_moves(&engine, &start));
+
+        // Nothing is left to take back.
+        engine.comm_reports(&XBoard::create_report("undo"));
+        let is_error = |c: &CommControl| matches!(c, CommControl::CommandError(..));
+        match handle.recv_until(Duration::from_secs(1), is_error) {
+            Some(CommControl::CommandError(CommandError::NoTakeBack(cmd))) => {
+                assert_eq!(cmd, "undo")
+            }
+            _ => panic!("no error"),
+        }
+        assert_eq!(engine.board_snapshot().fen_write(), fen);
+    }
+
+    // The board and the history are sent as they are when the command
+    // arrives. Printing them doesn't need the board, so it works while
+    // the board is locked, and later changes to the board don't show up.
+    #[test]
+    fn board_and_history_are_snapshots() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        engine.comm_reports(&Uci::create_report("position startpos moves e2e4"));
+        engine.comm_reports(&Uci::create_report("board"));
+        engine.comm_reports(&Uci::create_report("history"));
+
+        // A long game of knight moves.
+        let moves = ["g8f6", "g1f3", "f6g8", "f3g1"];
+        let moves: Vec<&str> = moves.iter().cycle().take(200).copied().collect();
+        let cmd = format!("position startpos moves e2e4 {}", moves.join(" "));
+        engine.comm_reports(&Uci::create_report(&cmd));
+        assert_eq!(engine.board_snapshot().history.len(), 201);
+
+        let _locked = engine.board.lock().expect(ErrFatal::LOCK);
+        match handle.recv(Duration::from_secs(1)) {
+            Some(CommControl::PrintBoard(board)) => {
+                assert_eq!(board.history.len(), 1);
+                let e4 = parse::algebraic_square_to_number("e4").expect("a square");
+                assert_eq!(board.piece_list[e4], Pieces::PAWN);
+                assert!(format::position(&board, None).contains(&board.fen_write()));
+            }
+            _ => panic!("no board"),
+        }
+        match handle.recv(Duration::from_secs(1)) {
+            Some(CommControl::PrintHistory(history)) => {
+                assert_eq!(history, "1. e4\nResult: *\n")
+            }
+            _ => panic!("no history"),
+        }
+    }
+
+    // The GUI may send moves past the end of the game. The engine plays
+    // up to the mate, and reports the move it didn't play.
+    #[test]
+    fn position_moves_stop_at_mate() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        engine.comm_reports(&Uci::create_report(
+            "position startpos moves f2f3 e7e5 g2g4 d8h4 e1f2",
+        ));
+
+        let board = engine.board_snapshot();
+        assert_eq!(board.history.len(), 4);
+        assert!(engine.game_end() == Some(GameEnd::Checkmate));
+
+        let is_info = |c: &CommControl| matches!(c, CommControl::InfoString(..));
+        match handle.recv_until(Duration::from_secs(1), is_info) {
+            Some(CommControl::InfoString(msg)) => {
+                assert!(msg.starts_with("e1f2: "), "{}", msg);
+                assert!(msg.contains(ErrNormal::GAME_OVER), "{}", msg);
+            }
+            _ => panic!("no info string"),
+        }
+    }
+
+    // Collects the best moves the engine sends, until it stays silent.
+    fn best_moves(handle: &MockHandle) -> Vec<Move> {
+        let mut moves = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_secs(1)) {
+            if let CommControl::BestMove(m, _) = control {
+                moves.push(m);
+            }
+        }
+        moves
+    }
+
+    fn wait_for_search(handle: &MockHandle) {
+        let is_summary = |c: &CommControl| matches!(c, CommControl::SearchSummary(..));
+        assert!(handle.recv_until(TIMEOUT, is_summary).is_some());
+    }
+
+    fn is_legal(fen: &str, m: Move) -> bool {
+        let (engine, _handle) = engine(CommType::UCI);
+        engine.fen_setup(fen).expect("valid FEN");
+        let board = engine.board_snapshot();
+        legal_moves(&engine, &board).contains(&m.get_move())
+    }
+
+    // An analysis goes on in the new position. When it is stopped, only
+    // its best move is sent; the one for the old position is dropped.
+    #[test]
+    fn position_during_analysis_gives_one_best_move() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go infinite"));
+        wait_for_search(&handle);
+
+        handle.send(Uci::create_report("position startpos moves e2e4"));
+        handle.send(Uci::create_report("isready"));
+        let is_ready = |c: &CommControl| matches!(c, CommControl::Ready);
+        assert!(handle.recv_until(TIMEOUT, is_ready).is_some());
+        wait_for_search(&handle);
+        handle.send(Uci::create_report("stop"));
+
+        let moves = best_moves(&handle);
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(fen, moves[0]));
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // A search with a time limit is not restarted: nothing is sent for
+    // it, and the next search is the only one to give a best move.
+    #[test]
+    fn position_during_search_drops_its_best_move() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go movetime 60000"));
+        wait_for_search(&handle);
+
+        handle.send(Uci::create_report("position startpos moves e2e4"));
+        assert!(best_moves(&handle).is_empty());
+
+        handle.send(Uci::create_report("go depth 3"));
+        let moves = best_moves(&handle);
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(fen, moves[0]));
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // In XBoard, setboard and new while the engine thinks about its move
+    // end the thinking. No move is played for the old position.
+    #[test]
+    fn setboard_and_new_during_search_drop_the_move() {
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+
+        for command in [format!("setboard {}", fen), String::from("new")].iter() {
+            handle.send(XBoard::create_report("new"));
+            handle.send(XBoard::create_report("st 60"));
+            handle.send(XBoard::create_report("go"));
+            wait_for_search(&handle);
+
+            handle.send(XBoard::create_report(command));
+            assert!(best_moves(&handle).is_empty(), "{}", command);
+        }
+
+        // The engine plays on in the position it was given.
+        handle.send(XBoard::create_report(&format!("setboard {}", fen)));
+        handle.send(XBoard::create_report("sd 3"));
+        handle.send(XBoard::create_report("go"));
+        let moves = best_moves(&handle);
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(fen, moves[0]));
+
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // In force mode, the engine plays the moves of both sides without
+    // thinking. After "go", it plays a move for the side to move; after
+    // "result", it stops and accepts no more moves.
+    #[test]
+    fn force_moves_and_go() {
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        handle.send(XBoard::create_report("new"));
+        handle.send(XBoard::create_report("force"));
+        for m in ["e2e4", "e7e5", "g1f3", "b8c6"].iter() {
+            handle.send(XBoard::create_report(&format!("usermove {}", m)));
+        }
+        assert!(best_moves(&handle).is_empty());
+
+        handle.send(XBoard::create_report("sd 3"));
+        handle.send(XBoard::create_report("go"));
+        let moves = best_moves(&handle);
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        assert_eq!(moves.len(), 1);
+        assert!(is_legal(fen, moves[0]));
+
+        // The engine now plays white, and replies to black's move.
+        handle.send(XBoard::create_report("usermove a7a6"));
+        assert_eq!(best_moves(&handle).len(), 1);
+
+        // After the result, a search is stopped without a move, and moves
+        // are refused.
+        handle.send(XBoard::create_report("st 60"));
+        handle.send(XBoard::create_report("usermove a6a5"));
+        wait_for_search(&handle);
+        handle.send(XBoard::create_report("result 1/2-1/2 {Draw}"));
+        handle.send(XBoard::create_report("usermove b7b6"));
+        let mut replies = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_secs(1)) {
+            replies.push(control);
+        }
+        assert!(!replies
+            .iter()
+            .any(|c| matches!(c, CommControl::BestMove(..))));
+        assert!(replies
+            .iter()
+            .any(|c| matches!(c, CommControl::IllegalMove(m, _) if m == "b7b6")));
+
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // The hint is the move the engine expects as the reply to its own
+    // move: the second move of the last PV it sent, which would be the
+    // ponder move. Before any search, there is no hint.
+    #[test]
+    fn hint_is_the_ponder_move() {
+        let (engine, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        let is_hint_or_pong =
+            |c: &CommControl| matches!(c, CommControl::Hint(..) | CommControl::Pong(..));
+
+        handle.send(XBoard::create_report("new"));
+        handle.send(XBoard::create_report("hint"));
+        handle.send(XBoard::create_report("ping 1"));
+        match handle.recv_until(TIMEOUT, is_hint_or_pong) {
+            Some(CommControl::Pong(1)) => (),
+            _ => panic!("a hint without a search"),
+        }
+
+        handle.send(XBoard::create_report("sd 4"));
+        handle.send(XBoard::create_report("go"));
+        let mut last_pv = Vec::new();
+        loop {
+            match handle.recv(TIMEOUT) {
+                Some(CommControl::SearchSummary(s)) => last_pv = s.pv,
+                Some(CommControl::BestMove(m, _)) => {
+                    assert_eq!(m.get_move(), last_pv[0].get_move());
+                    break;
+                }
+                Some(_) => (),
+                None => panic!("no best move"),
+            }
+        }
+        let ponder = last_pv[1];
+
+        handle.send(XBoard::create_report("hint"));
+        match handle.recv_until(TIMEOUT, is_hint_or_pong) {
+            Some(CommControl::Hint(m)) => assert_eq!(m.get_move(), ponder.get_move()),
+            _ => panic!("no hint"),
+        }
+
+        handle.send(XBoard::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // What a GUI probing the engine gets back: a registration handshake,
+    // and unknown commands ignored unless debug mode is on.
+    #[test]
+    fn uci_handshake_snapshot() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        let input = [
+            "uci",
+            "register later",
+            "register name Rustic code 1234",
+            "isready",
+            "foo",
+            "debug on",
+            "foo bar",
+            "",
+            "debug off",
+            "foo",
+            "isready",
+        ];
+        for cmd in input.iter() {
+            engine.comm_reports(&Uci::create_report(cmd));
+        }
+
+        let mut output = vec![];
+        while let Some(msg) = handle.recv(Duration::from_millis(100)) {
+            output.push(match msg {
+                CommControl::Identify => String::from("identify"),
+                CommControl::Registration => String::from("registration"),
+                CommControl::Ready => String::from("ready"),
+                CommControl::InfoString(s) => format!("info string {}", s),
+                _ => String::from("other"),
+            });
+        }
+
+        let expected = [
+            "identify",
+            "registration",
+            "registration",
+            "ready",
+            "info string unknown command: foo bar",
+            "ready",
+        ];
+        assert_eq!(output, expected);
+    }
+
+    // The null move can be parsed, but it is never a legal move.
+    #[test]
+    fn null_move_is_not_played() {
+        let (mut engine, _handle) = engine(CommType::UCI);
+        engine.comm_reports(&Uci::create_report("position startpos"));
+        let result = engine.execute_move(String::from("0000"));
+        assert!(matches!(result, Err(MoveError::NotLegal)));
+        assert!(engine.board_snapshot().history.is_empty());
+    }
+
+    // Plays the moves on an XBoard engine in force mode, and returns the
+    // announcements after the last one: check warnings and results.
+    fn announcements(engine: &mut Engine, handle: &MockHandle, moves: &[&str]) -> Vec<String> {
+        engine.comm_reports(&XBoard::create_report("new"));
+        engine.comm_reports(&XBoard::create_report("force"));
+        for m in moves.iter() {
+            while handle.recv(Duration::from_millis(10)).is_some() {}
+            engine.comm_reports(&XBoard::create_report(&format!("usermove {}", m)));
+        }
+
+        let mut output = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_millis(100)) {
+            match control {
+                CommControl::InfoString(msg) => output.push(msg),
+                CommControl::GameResult(end, side) => {
+                    output.push(format!("{} {}", end.result(side), end.as_str()))
+                }
+                CommControl::IllegalMove(m, _) => output.push(format!("illegal {}", m)),
+                _ => (),
+            }
+        }
+        output
+    }
+
+    // A game played in the terminal: checks are announced, and the game
+    // ends as soon as the position is checkmate or stalemate.
+    #[test]
+    fn scripted_game_ending_in_mate() {
+        let (mut engine, handle) = engine(CommType::XBOARD);
+
+        assert!(announcements(&mut engine, &handle, &["e2e4", "f7f6"]).is_empty());
+        let output = announcements(&mut engine, &handle, &["e2e4", "f7f6", "d1h5"]);
+        assert_eq!(output, vec!["Check!"]);
+
+        let fools_mate = ["f2f3", "e7e5", "g2g4", "d8h4"];
+        let output = announcements(&mut engine, &handle, &fools_mate);
+        assert_eq!(output, vec!["0-1 checkmate"]);
+
+        // After the mate, the game is over: moves are not played.
+        engine.comm_reports(&XBoard::create_report("usermove e1f2"));
+        assert!(matches!(
+            handle.recv(Duration::from_millis(100)),
+            Some(CommControl::GameResult(GameEnd::Checkmate, Sides::WHITE))
+        ));
+
+        engine.comm_reports(&XBoard::create_report(
+            "setboard 7k/5Q2/8/6K1/8/8/8/8 w - - 0 1",
+        ));
+        engine.comm_reports(&XBoard::create_report("force"));
+        while handle.recv(Duration::from_millis(10)).is_some() {}
+        engine.comm_reports(&XBoard::create_report("usermove g5g6"));
+        let mut output = Vec::new();
+        while let Some(control) = handle.recv(Duration::from_millis(100)) {
+            if let CommControl::GameResult(end, side) = control {
+                output.push(format!("{} {}", end.result(side), end.as_str()));
+            }
+        }
+        assert_eq!(output, vec!["1/2-1/2 stalemate"]);
+    }
+
+    // The engine's own mating move ends the game as well; a check by the
+    // engine is announced to the user.
+    #[test]
+    fn engine_move_is_announced() {
+        let (thread, handle) = Mock::start_engine(CommType::XBOARD, ARGS);
+        let cases = [
+            ("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "1-0 checkmate"),
+            ("r6k/8/8/8/8/8/5PPP/6K1 b - - 0 1", "0-1 checkmate"),
+        ];
+
+        for (fen, expected) in cases.iter() {
+            handle.send(XBoard::create_report("new"));
+            handle.send(XBoard::create_report(&format!("setboard {}", fen)));
+            handle.send(XBoard::create_report("sd 3"));
+            handle.send(XBoard::create_report("go"));
+            match handle.recv_until(TIMEOUT, |c| matches!(c, CommControl::GameResult(..))) {
+                Some(CommControl::GameResult(end, side)) => {
+                    assert_eq!(format!("{} {}", end.result(side), end.as_str()), *expected)
+                }
+                _ => panic!("no result: {}", fen),
+            }
+        }
+
+        handle.send(XBoard::create_report("quit"));
+        thread.join().expect("engine thread");
+
+        // After a check by the engine, the user is in check.
+        let (engine, handle) = engine(CommType::XBOARD);
+        engine
+            .fen_setup("4k3/8/8/8/8/8/8/4RK2 b - - 0 1")
+            .expect("valid FEN");
+        engine.announce_position(true);
+        assert!(matches!(
+            handle.recv(Duration::from_millis(100)),
+            Some(CommControl::InfoString(msg)) if msg == "You are in check!"
+        ));
+    }
+
+    // The GUI waits for a best move after "go", also when there are no
+    // moves. It gets the null move, without a ponder move.
+    #[test]
+    fn no_legal_moves_give_the_null_move() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        let positions = [
+            "7k/6Q1/6K1/8/8/8/8/8 b - - 0 1",
+            "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+        ];
+
+        for fen in positions.iter() {
+            handle.send(Uci::create_report(&format!("position fen {}", fen)));
+            handle.send(Uci::create_report("go depth 3"));
+            match handle.recv_best_move(TIMEOUT) {
+                Some((m, None)) => assert_eq!(m.as_string(), "0000", "{}", fen),
+                _ => panic!("no null move: {}", fen),
+            }
+        }
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // A pawn move to the last rank needs the promotion piece. Without it,
+    // the user is told so; a king can't be promoted to.
+    #[test]
+    fn promotions_need_a_promotion_piece() {
+        let (mut engine, _handle) = engine(CommType::UCI);
+        engine
+            .fen_setup("7k/4P3/8/8/8/8/8/K7 w - - 0 1")
+            .expect("valid FEN");
+
+        let result = engine.execute_move(String::from("e7e8"));
+        assert!(matches!(result, Err(MoveError::NoPromotionPiece)));
+        let result = engine.execute_move(String::from("e7e8k"));
+        assert!(matches!(result, Err(MoveError::NotLegal)));
+        let result = engine.execute_move(String::from("a1a2q"));
+        assert!(matches!(result, Err(MoveError::NotLegal)));
+        assert!(engine.board_snapshot().history.is_empty());
+
+        assert!(engine.execute_move(String::from("e7e8q")).is_ok());
+        let board = engine.board_snapshot();
+        let e8 = parse::algebraic_square_to_number("e8").expect("a square");
+        assert_eq!(board.piece_list[e8], Pieces::QUEEN);
+    }
+
+    #[test]
+    fn huge_report_interval_is_bounded() {
+        let (engine, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        let interval = u128::MAX.to_string();
+        let cmd = format!("setoption name ReportInterval value {}", interval);
+
+        // The interval in milliseconds would overflow.
+        handle.send(Uci::create_report(&cmd));
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go depth 2"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    // A slow search in a complex position, with stats otherwise only
+    // sent every SEND_STATS nodes. With a report interval of one second,
+    // the GUI hears from the search at least once a second: the stats,
+    // and the root move being searched.
+    #[test]
+    fn heartbeat_every_report_interval() {
+        const MOVETIME: u128 = 4500;
+        const INTERVAL: u128 = 1000;
+        const SLACK: u128 = 500;
+        let (engine, handle) = Mock::start_engine(CommType::UCI, &["--hash", "1"]);
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        handle.send(Uci::create_report("setoption name ReportInterval value 1"));
+        handle.send(Uci::create_report(&format!("position fen {}", fen)));
+        handle.send(Uci::create_report(&format!("go movetime {}", MOVETIME)));
+
+        let mut stats = vec![0];
+        let mut root_moves = 0;
+        loop {
+            match handle.recv(TIMEOUT) {
+                Some(CommControl::SearchStats(s)) => stats.push(s.time),
+                Some(CommControl::SearchCurrMove(c)) => {
+                    assert!(is_legal(fen, c.curr_move));
+                    root_moves += 1;
+                }
+                Some(CommControl::BestMove(..)) => break,
+                Some(_) => (),
+                None => panic!("no best move"),
+            }
+        }
+
+        let heartbeats = (MOVETIME / INTERVAL) as usize;
+        assert!(stats.len() > heartbeats, "{:?}", stats);
+        assert!(root_moves >= heartbeats, "{}", root_moves);
+        for gap in stats.windows(2) {
+            assert!(gap[1] - gap[0] <= INTERVAL + SLACK, "{:?}", stats);
+        }
+
+        handle.send(Uci::create_report("quit"));
+        engine.join().expect("engine thread");
+    }
+
+    #[test]
+    fn clear_hash_empties_the_tt() {
+        let (mut engine, _handle) = engine(CommType::UCI);
+        engine.allocate_tt();
+        engine.comm_reports(&Uci::create_report("position startpos"));
+        let board = engine.board.lock().expect(ErrFatal::LOCK).clone();
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+
+        // Deeper searches fill more of the 1 MB TT.
+        let mut previous = 0;
+        for depth in [4, 6, 8].iter() {
+            params.depth = *depth;
+            let tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+            api::run(&board, &engine.mg, &tt, params);
+            let hash_full = tt.hash_full();
+            assert!(hash_full > previous, "depth {}: {}", depth, hash_full);
+            previous = hash_full;
+        }
+
+        engine.comm_reports(&Uci::create_report("setoption name Clear Hash"));
+        let tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+        assert_eq!(tt.hash_full(), 0);
+    }
+
+    // Returns the next info string the engine sends.
+    fn info_string(handle: &MockHandle) -> String {
+        let is_info = |c: &CommControl| matches!(c, CommControl::InfoString(..));
+        match handle.recv_until(TIMEOUT, is_info) {
+            Some(CommControl::InfoString(msg)) => msg,
+            _ => panic!("no info string"),
+        }
+    }
+
+    // While the TT is held (as a running search does), changes to it are
+    // queued. Asking for the same kind of change twice stops the search.
+    // The queue is applied in order once the TT is free.
+    #[test]
+    fn tt_changes_wait_for_the_search() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        engine.allocate_tt();
+        let tt = std::sync::Arc::clone(&engine.tt_search);
+        let search = tt.read().expect(ErrFatal::LOCK);
+
+        engine.comm_reports(&Uci::create_report("setoption name Hash value 2"));
+        assert!(info_string(&handle).starts_with("Hash: applied when the search"));
+        engine.comm_reports(&Uci::create_report("setoption name Clear Hash"));
+        assert!(info_string(&handle).starts_with("Clear Hash: applied when the search"));
+        engine.comm_reports(&Uci::create_report("setoption name Hash value 4"));
+        assert!(info_string(&handle).starts_with("Hash: search stopped"));
+        assert_eq!(engine.tt_changes.len(), 3);
+        assert_eq!(search.megabytes(), 1);
+
+        drop(search);
+        engine.apply_tt_changes();
+        assert!(engine.tt_changes.is_empty());
+        assert_eq!(engine.settings.tt_size, 4);
+        assert_eq!(tt.read().expect(ErrFatal::LOCK).megabytes(), 4);
+    }
+
+    // Resizing the TT during an infinite search doesn't disturb it. The
+    // new size is used by the next search.
+    #[test]
+    fn hash_resize_during_infinite_search() {
+        let (thread, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go infinite"));
+        handle.recv_until(TIMEOUT, |c| matches!(c, CommControl::SearchSummary(..)));
+
+        handle.send(Uci::create_report("setoption name Hash value 2"));
+        assert!(info_string(&handle).starts_with("Hash: applied when the search"));
+        handle.send(Uci::create_report("isready"));
+        assert!(handle
+            .recv_until(TIMEOUT, |c| matches!(c, CommControl::Ready))
+            .is_some());
+
+        handle.send(Uci::create_report("stop"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+        handle.send(Uci::create_report("go depth 4"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+
+        handle.send(Uci::create_report("quit"));
+        thread.join().expect("engine thread");
+    }
+
+    // Clearing the TT while pondering is deferred as well. After the
+    // ponderhit, the search finishes normally.
+    #[test]
+    fn clear_hash_during_ponder() {
+        let (thread, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("setoption name Ponder value true"));
+        handle.send(Uci::create_report("position startpos moves e2e4"));
+        handle.send(Uci::create_report(
+            "go ponder wtime 10000 btime 10000 winc 0 binc 0",
+        ));
+        handle.recv_until(TIMEOUT, |c| matches!(c, CommControl::SearchSummary(..)));
+
+        handle.send(Uci::create_report("setoption name Clear Hash"));
+        assert!(info_string(&handle).starts_with("Clear Hash: applied when the search"));
+        handle.send(Uci::create_report("ponderhit"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+
+        handle.send(Uci::create_report("go depth 4"));
+        assert!(handle.recv_best_move(TIMEOUT).is_some());
+        handle.send(Uci::create_report("quit"));
+        thread.join().expect("engine thread");
+    }
+
+    // Only UCI and XBoard can be selected; anything else is an error, not
+    // a panic.
+    #[test]
+    fn unknown_comm_is_an_error() {
+        assert!(Engine::create_comm(CommType::UCI).is_ok());
+        assert!(Engine::create_comm(CommType::XBOARD).is_ok());
+
+        match Engine::create_comm("winboard") {
+            Err(e) => {
+                assert!(matches!(&e, EngineRunError::UnknownComm(comm) if comm == "winboard"));
+                assert_eq!(e.to_string(), "Unknown communication protocol: winboard");
+            }
+            Ok(_) => panic!("winboard is not a protocol"),
+        }
+    }
+
+    // Creating an engine doesn't allocate the TTs, however large the hash
+    // size. Only the one the engine needs is allocated when it runs.
+    #[test]
+    fn tts_are_allocated_when_needed() {
+        let (mock, _handle) = Mock::new(CommType::UCI);
+        let args = ["rustic-alpha", "--hash", "2048", "--quiet"];
+        let mut engine = Engine::with_comm(CmdLine::from_args(&args), Box::new(mock));
+        {
+            let perft_tt = engine.tt_perft.read().expect(ErrFatal::LOCK);
+            let search_tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+            assert_eq!((perft_tt.megabytes(), search_tt.megabytes()), (0, 0));
+        }
+
+        engine.settings.tt_size = 2;
+        engine.allocate_tt();
+        let perft_tt = engine.tt_perft.read().expect(ErrFatal::LOCK);
+        let search_tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+        assert_eq!((perft_tt.megabytes(), search_tt.megabytes()), (0, 2));
+    }
+
+    // With --perft, the TT given by --hash is the perft TT.
+    #[test]
+    fn perft_uses_the_hash_size() {
+        let (mock, _handle) = Mock::new(CommType::UCI);
+        let args = ["rustic-alpha", "--perft", "1", "--hash", "2", "--quiet"];
+        let mut engine = Engine::with_comm(CmdLine::from_args(&args), Box::new(mock));
+        engine.allocate_tt();
+
+        let perft_tt = engine.tt_perft.read().expect(ErrFatal::LOCK);
+        let search_tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+        assert_eq!((perft_tt.megabytes(), search_tt.megabytes()), (2, 0));
+    }
+
+    // UCI_Chess960 only changes the engine's own board. Castling is then
+    // written as king takes rook, in moves and in the FEN-string.
+    #[test]
+    fn chess960_is_set_on_the_engine_board() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let castling = |board: &Board, mg| -> Vec<String> {
+            let mut moves: Vec<String> = board
+                .legal_moves_iter(mg)
+                .filter(|m| m.castling())
+                .map(|m| m.as_string())
+                .collect();
+            moves.sort();
+            moves
+        };
+
+        let (other, _other_handle) = engine(CommType::UCI);
+        let (mut engine, _handle) = engine(CommType::UCI);
+        engine.comm_reports(&Uci::create_report(
+            "setoption name UCI_Chess960 value true",
+        ));
+        engine.comm_reports(&Uci::create_report(&format!("position fen {}", fen)));
+        other.fen_setup(fen).expect("valid FEN");
+
+        let board = engine.board_snapshot();
+        assert!(board.chess960);
+        assert_eq!(castling(&board, &engine.mg), ["e1a1", "e1h1"]);
+        assert_eq!(board.fen_write(), "r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1");
+
+        // The other engine still plays normal chess.
+        let board = other.board_snapshot();
+        assert!(!board.chess960);
+        assert_eq!(castling(&board, &other.mg), ["e1c1", "e1g1"]);
+        assert_eq!(board.fen_write(), fen);
+
+        // Castling is played in the notation of the board.
+        engine.comm_reports(&Uci::create_report(&format!(
+            "position fen {} moves e1h1",
+            fen
+        )));
+        let board = engine.board_snapshot();
+        assert_eq!(board.history.get_ref(0).next_move.as_string(), "e1h1");
+        assert_eq!(board.fen_write(), "r3k2r/8/8/8/8/8/8/R4RK1 b ha - 1 1");
+    }
+
+    #[test]
+    fn loaded_hash_sets_the_hash_size() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        let file = format!("rustic_loadhash_{}.hash", std::process::id());
+        let path = std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .into_owned();
+
+        engine.comm_reports(&Uci::create_report("setoption name Hash value 1"));
+        engine.comm_reports(&Uci::create_report(&format!("savehash {}", path)));
+        engine.comm_reports(&Uci::create_report("setoption name Hash value 2"));
+        engine.comm_reports(&Uci::create_report(&format!("loadhash {}", path)));
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(engine.settings.tt_size, 1);
+        let tt = engine.tt_search.read().expect(ErrFatal::LOCK);
+        assert_eq!(tt.megabytes(), 1);
+        let loaded = (0..4)
+            .filter_map(|_| handle.recv(TIMEOUT))
+            .any(|c| match c {
+                CommControl::InfoString(s) => s.starts_with("Hash loaded") && s.ends_with("1 MB."),
+                _ => false,
+            });
+        assert!(loaded);
+    }
+
+    fn printed_history(engine: &mut Engine, handle: &MockHandle, cmd: &str) -> String {
+        engine.comm_reports(&Uci::create_report(cmd));
+        let is_history = |c: &CommControl| matches!(c, CommControl::PrintHistory(..));
+        match handle.recv_until(TIMEOUT, is_history) {
+            Some(CommControl::PrintHistory(history)) => history,
+            _ => panic!("no history"),
+        }
+    }
+
+    // The history of a finished game ends with its result. The raw
+    // history is the dump of the game states.
+    #[test]
+    fn history_in_san_and_raw() {
+        let (mut engine, handle) = engine(CommType::UCI);
+        engine.comm_reports(&Uci::create_report("position startpos moves e2e4"));
+        assert_eq!(
+            printed_history(&mut engine, &handle, "history"),
+            "1. e4\nResult: *\n"
+        );
+
+        let fools_mate = "position startpos moves f2f3 e7e5 g2g4 d8h4";
+        engine.comm_reports(&Uci::create_report(fools_mate));
+        assert_eq!(
+            printed_history(&mut engine, &handle, "history"),
+            "1. f3 e5 2. g4 Qh4#\nResult: 0-1 (checkmate)\n"
+        );
+
+        let board = engine.board_snapshot();
+        assert_eq!(
+            printed_history(&mut engine, &handle, "history raw"),
+            format::history(&board)
+        );
+    }
+
+    // MaxPVLength caps the PV in the summaries sent to the GUI. The search
+    // keeps the full PV, so there still is a move to ponder on.
+    #[test]
+    fn max_pv_length_caps_the_summaries() {
+        let (thread, handle) = Mock::start_engine(CommType::UCI, ARGS);
+        handle.send(Uci::create_report("setoption name MaxPVLength value 1"));
+        handle.send(Uci::create_report("setoption name Ponder value true"));
+        handle.send(Uci::create_report("position startpos"));
+        handle.send(Uci::create_report("go depth 5"));
+
+        let mut summaries = Vec::new();
+        let ponder = loop {
+            match handle.recv(TIMEOUT) {
+                Some(CommControl::SearchSummary(s)) => summaries.push(s),
+                Some(CommControl::BestMove(_, ponder)) => break ponder,
+                Some(_) => (),
+                None => panic!("no best move"),
+            }
+        };
+
+        assert!(ponder.is_some());
+        assert!(summaries.iter().any(|s| s.pv_truncated));
+        for s in summaries.iter() {
+            assert_eq!(s.pv.len(), 1);
+            assert_eq!(s.pv_as_string().ends_with(" ..."), s.pv_truncated);
+        }
+
+        handle.send(Uci::create_report("quit"));
+        thread.join().expect("engine thread");
     }
 }
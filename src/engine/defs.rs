@@ -21,15 +21,20 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-pub use crate::engine::transposition::{HashFlag, IHashData, PerftData, SearchData, TT};
-use crate::{comm::CommReport, search::defs::SearchReport};
+pub use crate::engine::transposition::{HashFlag, PerftData, SearchData, TT};
+use crate::{
+    board::defs::FenError,
+    comm::CommReport,
+    defs::{Side, Sides},
+    search::defs::{SearchParams, SearchReport},
+};
+use std::fmt;
 
 // This struct holds messages that are reported on fatal engine errors.
 // These should never happen; if they do the engine is in an unknown state,
 // and it will panic without trying any recovery whatsoever.
 pub struct ErrFatal;
 impl ErrFatal {
-    pub const CREATE_COMM: &'static str = "Comm creation failed.";
     pub const NEW_GAME: &'static str = "Setting up new game failed.";
     pub const LOCK: &'static str = "Lock failed.";
     pub const READ_IO: &'static str = "Reading I/O failed.";
@@ -43,7 +48,229 @@ pub struct ErrNormal;
 impl ErrNormal {
     pub const NOT_LEGAL: &'static str = "This is not a legal move in this position.";
     pub const NOT_INT: &'static str = "The value given was not an integer.";
+    pub const NOT_BOOL: &'static str = "The value given was not true or false.";
     pub const FEN_FAILED: &'static str = "Setting up FEN failed. Board not changed.";
+    pub const GAME_OVER: &'static str = "The game is already over. Move not applied";
+    pub const NEGATIVE_TIME: &'static str = "Negative clock value received. Using 0 instead.";
+    pub const TIME_FORFEIT: &'static str = "The game was lost on time. Use ucinewgame or clock.";
+    pub const NO_PROMOTION_PIECE: &'static str = "Promotion piece required, e.g. e7e8q.";
+    pub const AMBIGUOUS_MOVE: &'static str = "More than one piece can make this move, e.g. Nbd2.";
+    pub const IN_CHECK: &'static str = "This move leaves the king in check.";
+    pub const NOT_YOUR_MOVE: &'static str = "The engine is thinking about its own move.";
+    pub const NOT_SQUARE: &'static str = "This is not a square, e.g. d5.";
+    pub const NO_TAKE_BACK: &'static str = "There is no move to take back.";
+    pub const SEARCH_NOT_STOPPED: &'static str =
+        "The search didn't stop in time. Its result will be ignored.";
+}
+
+// Reasons why a move received from the user or GUI can't be played.
+#[derive(PartialEq, Copy, Clone)]
+pub enum MoveError {
+    NotLegal,
+    NoPromotionPiece,
+    Ambiguous,
+    InCheck,     // The move leaves the own king in check
+    NotYourMove, // The move came in while the engine was to move
+}
+
+impl MoveError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MoveError::NotLegal => ErrNormal::NOT_LEGAL,
+            MoveError::NoPromotionPiece => ErrNormal::NO_PROMOTION_PIECE,
+            MoveError::Ambiguous => ErrNormal::AMBIGUOUS_MOVE,
+            MoveError::InCheck => ErrNormal::IN_CHECK,
+            MoveError::NotYourMove => ErrNormal::NOT_YOUR_MOVE,
+        }
+    }
+}
+
+// Ways in which a game can end: on the board, because the side to move
+// has no legal moves left, or on the clock, by the side that ran out of
+// time.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum GameEnd {
+    Checkmate,
+    Stalemate,
+    TimeForfeit(Side),
+}
+
+impl GameEnd {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameEnd::Checkmate => "checkmate",
+            GameEnd::Stalemate => "stalemate",
+            GameEnd::TimeForfeit(_) => "time forfeit",
+        }
+    }
+
+    // The result of the game in PGN notation. The side to move is the side
+    // that was checkmated or stalemated.
+    pub fn result(&self, side_to_move: Side) -> &'static str {
+        match self {
+            GameEnd::Checkmate if side_to_move == Sides::WHITE => "0-1",
+            GameEnd::Checkmate => "1-0",
+            GameEnd::Stalemate => "1/2-1/2",
+            GameEnd::TimeForfeit(loser) if *loser == Sides::WHITE => "0-1",
+            GameEnd::TimeForfeit(_) => "1-0",
+        }
+    }
+}
+
+// Reasons why the engine can't run what was asked on the command line.
+#[derive(PartialEq, Clone, Debug)]
+pub enum EngineRunError {
+    Fen(FenError),         // The FEN-string can't be set up
+    NoLegalMoves(GameEnd), // Nothing to search in the position
+    UnknownComm(String),   // The protocol, as received
+}
+
+pub type EngineRunResult = Result<(), EngineRunError>;
+
+impl From<FenError> for EngineRunError {
+    fn from(e: FenError) -> Self {
+        EngineRunError::Fen(e)
+    }
+}
+
+impl fmt::Display for EngineRunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineRunError::Fen(e) => write!(f, "{}", e),
+            EngineRunError::NoLegalMoves(end) => {
+                write!(f, "No legal moves in this position: {}", end.as_str())
+            }
+            EngineRunError::UnknownComm(comm) => {
+                write!(f, "Unknown communication protocol: {}", comm)
+            }
+        }
+    }
+}
+
+// Changes to the search TT. While a search is running, the search holds
+// the TT, so these changes are queued until the search has finished.
+#[derive(PartialEq, Clone)]
+pub enum TTChange {
+    Resize(usize),
+    Clear,
+    Load(String),
+}
+
+impl TTChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TTChange::Resize(_) => "Hash",
+            TTChange::Clear => "Clear Hash",
+            TTChange::Load(_) => "Load Hash",
+        }
+    }
+}
+
+// With Persist Hash on, the search TT is saved to this file when the
+// engine quits, and loaded from it when the option is switched on.
+pub const PERSIST_HASH_FILE: &str = "rustic.hash";
+
+// Capacity of the channel carrying Information to the engine thread.
+// When it is full, Comm and Search wait before sending. Intermediate
+// search stats are the exception; they are dropped instead.
+pub const INFORMATION_CAPACITY: usize = 256;
+
+// Until time controls are received, XBoard searches use this time per move.
+pub const XBOARD_MOVE_TIME: u128 = 5_000; // msecs
+
+// A position change stops the running search, and waits at most this
+// long for it to finish before going ahead.
+pub const STOP_SEARCH_TIMEOUT: u64 = 2_000; // msecs
+
+// Keeps track of the searches the engine starts. Each search gets an id,
+// which it returns in the report that ends it. A search that is stopped
+// because the position changed is discarded: its result is dropped,
+// also when it arrives after the engine stopped waiting for it.
+pub struct SearchTracker {
+    last_id: usize,                // Id given to the latest search
+    running: Option<SearchParams>, // Parameters of the running search
+    discarded: Vec<usize>,         // Searches whose result is dropped
+}
+
+impl SearchTracker {
+    pub fn new() -> Self {
+        Self {
+            last_id: 0,
+            running: None,
+            discarded: Vec::new(),
+        }
+    }
+
+    // Gives the search that is about to start its id.
+    pub fn start(&mut self, sp: &mut SearchParams) {
+        self.last_id += 1;
+        sp.id = self.last_id;
+        self.running = Some(*sp);
+    }
+
+    pub fn running(&self) -> Option<SearchParams> {
+        self.running
+    }
+
+    // The result of the running search isn't wanted anymore. Returns the
+    // parameters of that search.
+    pub fn discard(&mut self) -> Option<SearchParams> {
+        let sp = self.running.take()?;
+        self.discarded.push(sp.id);
+        Some(sp)
+    }
+
+    pub fn is_discarded(&self, id: usize) -> bool {
+        self.discarded.contains(&id)
+    }
+
+    // The search with this id has finished. Returns false if its result
+    // was discarded.
+    pub fn finish(&mut self, id: usize) -> bool {
+        if let Some(i) = self.discarded.iter().position(|&d| d == id) {
+            self.discarded.swap_remove(i);
+            return false;
+        }
+
+        if self.running.map(|sp| sp.id) == Some(id) {
+            self.running = None;
+        }
+        true
+    }
+}
+
+// This struct holds the state that only exists in the XBoard protocol.
+// All times are in milliseconds.
+pub struct XBoardSpecifics {
+    pub force: bool,              // In force mode, the engine doesn't move by itself.
+    pub thinking: bool,           // A search for the engine's move is running.
+    pub result: Option<String>,   // Result of the game, once the GUI has sent it
+    pub moves_per_session: usize, // Moves per time control (0 = entire game)
+    pub base: Option<u128>,       // Base time of the time control
+    pub increment: u128,          // Increment per move
+    pub move_time: Option<u128>,  // Fixed time per move, set by "st"
+    pub depth: Option<i8>,        // Maximum search depth, set by "sd"
+    pub time: Option<u128>,       // Engine's clock
+    pub otim: Option<u128>,       // Opponent's clock
+    pub ics: Option<String>,      // Internet chess server (None = local)
+}
+
+impl XBoardSpecifics {
+    pub fn new() -> Self {
+        Self {
+            force: false,
+            thinking: false,
+            result: None,
+            moves_per_session: 0,
+            base: None,
+            increment: 0,
+            move_time: None,
+            depth: None,
+            time: None,
+            otim: None,
+            ics: None,
+        }
+    }
 }
 
 // This struct holds the engine's settings.
@@ -51,6 +278,17 @@ pub struct Settings {
     pub threads: usize,
     pub quiet: bool,
     pub tt_size: usize,
+    pub report_interval: u128,
+    pub debug: bool,
+    pub show_board: bool,
+    pub ponder: bool,
+    pub multipv: usize,
+    pub singular_margin: i16,
+    pub persist_hash: bool,
+    pub adaptive_search: bool,
+    pub complete_iterations: bool,
+    pub max_pv_length: usize,
+    pub affinity: bool, // Pin search threads to CPUs
 }
 
 // This enum provides information to the engine, with regard to incoming
@@ -64,6 +302,7 @@ pub enum Information {
 pub enum UiElement {
     Spin,
     Button,
+    Check,
 }
 
 pub struct EngineOption {
@@ -72,6 +311,7 @@ pub struct EngineOption {
     pub default: Option<String>,
     pub min: Option<String>,
     pub max: Option<String>,
+    pub setting: fn(String) -> EngineOptionName, // Value from the GUI for the engine
 }
 
 impl EngineOption {
@@ -81,6 +321,7 @@ impl EngineOption {
         default: Option<String>,
         min: Option<String>,
         max: Option<String>,
+        setting: fn(String) -> EngineOptionName,
     ) -> Self {
         Self {
             name,
@@ -88,19 +329,158 @@ impl EngineOption {
             default,
             min,
             max,
+            setting,
         }
     }
+
+    // This is the list of all the options that are announced to the GUI;
+    // an option that is not in this list doesn't exist for the GUI. The
+    // options are sorted by name (ignoring case), so the order in which
+    // the GUI receives them doesn't depend on the order in this list.
+    // Some GUIs store option settings by their position in the list. The
+    // protocols also use this list to turn an option set by the GUI into
+    // a setting for the engine.
+    pub fn registry() -> Vec<EngineOption> {
+        let mut options = vec![
+            EngineOption::new(
+                EngineOptionName::HASH,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::HASH_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::HASH_MIN.to_string()),
+                Some(EngineOptionDefaults::hash_max().to_string()),
+                EngineOptionName::Hash,
+            ),
+            EngineOption::new(
+                EngineOptionName::CLEAR_HASH,
+                UiElement::Button,
+                None,
+                None,
+                None,
+                |_| EngineOptionName::ClearHash,
+            ),
+            EngineOption::new(
+                EngineOptionName::REPORT_INTERVAL,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::REPORT_INTERVAL_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::REPORT_INTERVAL_MIN.to_string()),
+                Some(EngineOptionDefaults::REPORT_INTERVAL_MAX.to_string()),
+                EngineOptionName::ReportInterval,
+            ),
+            EngineOption::new(
+                EngineOptionName::PONDER,
+                UiElement::Check,
+                Some(EngineOptionDefaults::PONDER_DEFAULT.to_string()),
+                None,
+                None,
+                EngineOptionName::Ponder,
+            ),
+            EngineOption::new(
+                EngineOptionName::MAX_PV_LENGTH,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::MAX_PV_LENGTH_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::MAX_PV_LENGTH_MIN.to_string()),
+                Some(EngineOptionDefaults::MAX_PV_LENGTH_MAX.to_string()),
+                EngineOptionName::MaxPVLength,
+            ),
+            EngineOption::new(
+                EngineOptionName::MULTIPV,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::MULTIPV_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::MULTIPV_MIN.to_string()),
+                Some(EngineOptionDefaults::MULTIPV_MAX.to_string()),
+                EngineOptionName::MultiPV,
+            ),
+            EngineOption::new(
+                EngineOptionName::SINGULAR_MARGIN,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::SINGULAR_MARGIN_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::SINGULAR_MARGIN_MIN.to_string()),
+                Some(EngineOptionDefaults::SINGULAR_MARGIN_MAX.to_string()),
+                EngineOptionName::SingularMargin,
+            ),
+            EngineOption::new(
+                EngineOptionName::ADAPTIVE_SEARCH,
+                UiElement::Check,
+                Some(EngineOptionDefaults::ADAPTIVE_SEARCH_DEFAULT.to_string()),
+                None,
+                None,
+                EngineOptionName::AdaptiveSearch,
+            ),
+            EngineOption::new(
+                EngineOptionName::COMPLETE_ITERATIONS,
+                UiElement::Check,
+                Some(EngineOptionDefaults::COMPLETE_ITERATIONS_DEFAULT.to_string()),
+                None,
+                None,
+                EngineOptionName::CompleteIterations,
+            ),
+            EngineOption::new(
+                EngineOptionName::PERSIST_HASH,
+                UiElement::Check,
+                Some(EngineOptionDefaults::PERSIST_HASH_DEFAULT.to_string()),
+                None,
+                None,
+                EngineOptionName::PersistHash,
+            ),
+            EngineOption::new(
+                EngineOptionName::UCI_CHESS960,
+                UiElement::Check,
+                Some(EngineOptionDefaults::UCI_CHESS960_DEFAULT.to_string()),
+                None,
+                None,
+                EngineOptionName::UciChess960,
+            ),
+            EngineOption::new(
+                EngineOptionName::THREADS,
+                UiElement::Spin,
+                Some(EngineOptionDefaults::THREADS_DEFAULT.to_string()),
+                Some(EngineOptionDefaults::THREADS_MIN.to_string()),
+                Some(EngineOptionDefaults::THREADS_MAX.to_string()),
+                EngineOptionName::Threads,
+            ),
+        ];
+
+        options.sort_by_key(|o| o.name.to_lowercase());
+        options
+    }
+
+    // Finds an option in the registry by its name, ignoring case.
+    pub fn find(name: &str) -> Option<EngineOption> {
+        EngineOption::registry()
+            .into_iter()
+            .find(|o| o.name.eq_ignore_ascii_case(name))
+    }
 }
 
 #[derive(PartialEq, Clone)]
 pub enum EngineOptionName {
     Hash(String),
     ClearHash,
+    ReportInterval(String),
+    Ponder(String),
+    MultiPV(String),
+    SingularMargin(String),
+    Threads(String),
+    PersistHash(String),
+    AdaptiveSearch(String),
+    CompleteIterations(String),
+    MaxPVLength(String),
+    UciChess960(String),
     Nothing,
 }
 impl EngineOptionName {
     pub const HASH: &'static str = "Hash";
     pub const CLEAR_HASH: &'static str = "Clear Hash";
+    pub const REPORT_INTERVAL: &'static str = "ReportInterval";
+    pub const PONDER: &'static str = "Ponder";
+    pub const MULTIPV: &'static str = "MultiPV";
+    pub const SINGULAR_MARGIN: &'static str = "SingularMargin";
+    pub const THREADS: &'static str = "Threads";
+    pub const PERSIST_HASH: &'static str = "Persist Hash";
+    pub const ADAPTIVE_SEARCH: &'static str = "Adaptive Search";
+    pub const COMPLETE_ITERATIONS: &'static str = "CompleteIterations";
+    pub const MAX_PV_LENGTH: &'static str = "MaxPVLength";
+    pub const UCI_CHESS960: &'static str = "UCI_Chess960";
 }
 
 pub struct EngineOptionDefaults;
@@ -109,4 +489,61 @@ impl EngineOptionDefaults {
     pub const HASH_MIN: &'static str = "0";
     pub const HASH_MAX_64_BIT: &'static str = "65536";
     pub const HASH_MAX_32_BIT: &'static str = "2048";
+    pub const REPORT_INTERVAL_DEFAULT: &'static str = "5"; // seconds; 0 = off
+    pub const REPORT_INTERVAL_MIN: &'static str = "0";
+    pub const REPORT_INTERVAL_MAX: &'static str = "60";
+    pub const PONDER_DEFAULT: &'static str = "false";
+    pub const MULTIPV_DEFAULT: &'static str = "1";
+    pub const MULTIPV_MIN: &'static str = "1";
+    pub const MULTIPV_MAX: &'static str = "218"; // Most legal moves in any position
+    pub const SINGULAR_MARGIN_DEFAULT: &'static str = "150"; // centipawns
+    pub const SINGULAR_MARGIN_MIN: &'static str = "0";
+    pub const SINGULAR_MARGIN_MAX: &'static str = "1000";
+    pub const THREADS_DEFAULT: &'static str = "1";
+    pub const THREADS_MIN: &'static str = "1";
+    pub const PERSIST_HASH_DEFAULT: &'static str = "false";
+    pub const ADAPTIVE_SEARCH_DEFAULT: &'static str = "false"; // developer option
+    pub const COMPLETE_ITERATIONS_DEFAULT: &'static str = "false";
+    pub const MAX_PV_LENGTH_DEFAULT: &'static str = "0"; // 0 = no limit
+    pub const MAX_PV_LENGTH_MIN: &'static str = "0";
+    pub const MAX_PV_LENGTH_MAX: &'static str = "125"; // MAX_PLY
+    pub const UCI_CHESS960_DEFAULT: &'static str = "false";
+    pub const THREADS_MAX: &'static str = "64";
+
+    // The largest TT, in MB, depends on the size of the address space.
+    pub fn hash_max() -> &'static str {
+        if std::mem::size_of::<usize>() == 8 {
+            EngineOptionDefaults::HASH_MAX_64_BIT
+        } else {
+            EngineOptionDefaults::HASH_MAX_32_BIT
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A search stopped for a position change is dropped when it finishes,
+    // also after the next search has started. The next one is kept.
+    #[test]
+    fn discarded_searches_are_dropped() {
+        let mut searches = SearchTracker::new();
+        let mut old = SearchParams::new();
+        let mut new = SearchParams::new();
+
+        searches.start(&mut old);
+        assert_eq!(searches.discard().map(|sp| sp.id), Some(old.id));
+        assert_eq!(searches.discard().map(|sp| sp.id), None);
+        searches.start(&mut new);
+
+        assert_ne!(old.id, new.id);
+        assert!(searches.is_discarded(old.id));
+        assert!(!searches.is_discarded(new.id));
+        assert!(!searches.finish(old.id));
+        assert!(!searches.is_discarded(old.id));
+        assert_eq!(searches.running().map(|sp| sp.id), Some(new.id));
+        assert!(searches.finish(new.id));
+        assert!(searches.running().is_none());
+    }
 }
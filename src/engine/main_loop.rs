@@ -22,7 +22,7 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::{
-    defs::{ErrFatal, Information},
+    defs::{ErrFatal, Information, INFORMATION_CAPACITY},
     Engine,
 };
 use crate::comm::CommControl;
@@ -31,7 +31,7 @@ use std::sync::Arc;
 impl Engine {
     pub fn main_loop(&mut self) {
         // Set up a channel for incoming information.
-        let (info_tx, info_rx) = crossbeam_channel::unbounded::<Information>();
+        let (info_tx, info_rx) = crossbeam_channel::bounded::<Information>(INFORMATION_CAPACITY);
 
         // Store the information receiver in the engine for use in other functions.
         self.info_rx = Some(info_rx);
@@ -52,6 +52,13 @@ impl Engine {
         // Update the Comm interface screen output (if any).
         self.comm.send(CommControl::Update);
 
+        // If the engine chose its own seed, report it, so a game can be
+        // replayed by passing it back with --seed.
+        if self.random.is_from_entropy() {
+            let msg = format!("seed {}", self.random.seed());
+            self.comm.send(CommControl::InfoString(msg));
+        }
+
         // Keep looping forever until 'quit' received.
         while !self.quit {
             let information = &self.info_rx();
@@ -67,8 +74,14 @@ impl Engine {
         self.search.wait_for_shutdown();
     }
 
-    // This is the main engine thread Information receiver.
+    // This is the main engine thread Information receiver. Comm reports
+    // that arrived while the engine waited for the search to stop come
+    // first, in the order they were received.
     fn info_rx(&mut self) -> Information {
+        if let Some(cr) = self.deferred.pop_front() {
+            return Information::Comm(cr);
+        }
+
         match &self.info_rx {
             Some(i) => i.recv().expect(ErrFatal::CHANNEL),
             None => panic!("{}", ErrFatal::NO_INFO_RX),
@@ -21,14 +21,54 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use super::Engine;
-use crate::{comm::CommControl, search::defs::SearchReport};
+use super::{defs::ErrFatal, Engine};
+use crate::{
+    comm::{CommControl, CommType},
+    search::defs::SearchReport,
+};
 
 impl Engine {
     pub fn search_reports(&mut self, search_report: &SearchReport) {
+        // The result of a discarded search is dropped: the position it
+        // was searching has changed. The engine only cleans up after it if
+        // no newer search is using the TT and the time watch.
+        let ended = match search_report {
+            SearchReport::Finished(id, _, _) | SearchReport::Compared(id, _) => Some(*id),
+            _ => None,
+        };
+        if let Some(id) = ended {
+            if !self.searches.finish(id) {
+                if self.searches.running().is_none() {
+                    self.apply_tt_changes();
+                    self.time_watch.forget();
+                }
+                self.comm.send(CommControl::Update);
+                return;
+            }
+        }
+
         match search_report {
-            SearchReport::Finished(m) => {
-                self.comm.send(CommControl::BestMove(*m));
+            SearchReport::Finished(_, m, ponder) => {
+                // Only suggest a move to ponder on if pondering is enabled.
+                let ponder = if self.settings.ponder { *ponder } else { None };
+                self.comm.send(CommControl::BestMove(*m, ponder));
+                self.apply_tt_changes();
+
+                // In XBoard, the GUI doesn't send the engine's own move
+                // back to it, so the engine plays it on its board itself.
+                // In UCI, the move arrives with the next position command.
+                if self.comm.get_protocol_name() == CommType::XBOARD {
+                    self.xboard.thinking = false;
+                    if !m.is_null() {
+                        self.board.lock().expect(ErrFatal::LOCK).make(*m, &self.mg);
+                    }
+                    self.show_board(None);
+                    self.announce_position(true);
+                } else {
+                    self.show_board(Some(*m));
+                }
+                self.clock_search_finished();
+                self.time_watch_finished();
                 self.comm.send(CommControl::Update);
             }
 
@@ -36,13 +76,33 @@ impl Engine {
                 self.comm.send(CommControl::SearchCurrMove(*curr_move));
             }
 
+            // Only the PV shown to the user is capped; the best move
+            // and the ponder move come from the search itself.
+            // The main line is kept after the search, to give hints.
             SearchReport::SearchSummary(summary) => {
-                self.comm.send(CommControl::SearchSummary(summary.clone()));
+                if summary.multipv <= 1 {
+                    self.last_pv = summary.pv.clone();
+                }
+                let mut summary = summary.clone();
+                summary.cap_pv(self.settings.max_pv_length);
+                self.comm.send(CommControl::SearchSummary(summary));
             }
 
             SearchReport::SearchStats(stats) => {
                 self.comm.send(CommControl::SearchStats(*stats));
             }
+
+            SearchReport::InfoString(msg) => {
+                self.comm.send(CommControl::InfoString(msg.clone()));
+            }
+
+            SearchReport::TimeBudget(budget) => self.time_watch.set_budget(*budget),
+
+            SearchReport::Compared(_, table) => {
+                self.comm.send(CommControl::PrintCompare(table.clone()));
+                self.apply_tt_changes();
+                self.comm.send(CommControl::Update);
+            }
         }
     }
 }
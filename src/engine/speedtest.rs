@@ -0,0 +1,369 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements the speed test. It searches a few positions with
+// different hash sizes and numbers of threads, and prints how fast and
+// how deep the engine searches in each configuration. This helps to choose
+// the Hash and Threads options for a computer. The searches are run one by
+// one by the engine's search thread, exactly as they would be in a game.
+
+use super::{
+    defs::{ErrFatal, Information, INFORMATION_CAPACITY},
+    Engine,
+};
+use crate::{
+    defs::{FEN_KIWIPETE_POSITION, FEN_START_POSITION},
+    engine::defs::EngineOptionDefaults,
+    search::{
+        defs::{SearchControl, SearchMode, SearchParams, SearchReport, SearchSummary},
+        Search,
+    },
+};
+use std::{fs, sync::Arc};
+
+// The positions to search: the opening, a middle game, and an endgame.
+const SPEEDTEST_POSITIONS: [&str; 3] = [
+    FEN_START_POSITION,
+    FEN_KIWIPETE_POSITION,
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+// The hash sizes to test, in MB.
+const SPEEDTEST_HASH_SIZES: [usize; 4] = [16, 64, 256, 1024];
+
+// The hash size is large enough if the TT is no more than half full
+// after searching a position.
+const SPEEDTEST_HASH_FULL_LIMIT: u16 = 500;
+
+// The result of searching all the positions in one configuration.
+struct SpeedTestResult {
+    hash: usize,
+    threads: usize,
+    nodes: usize,
+    time: u128,
+    depth: i8,      // Lowest depth reached in any position
+    hash_full: u16, // Highest TT use in any position
+}
+
+impl SpeedTestResult {
+    fn nps(&self) -> usize {
+        Search::nodes_per_second(self.nodes, self.time)
+    }
+}
+
+impl Engine {
+    pub fn speedtest(&mut self) {
+        let move_time = self.cmdline.speedtest_time();
+        let max_threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(EngineOptionDefaults::THREADS_MAX.parse().unwrap_or(1));
+
+        println!(
+            "Speed test: {} positions, {} ms per search, 1 to {} threads.",
+            SPEEDTEST_POSITIONS.len(),
+            move_time,
+            max_threads
+        );
+        println!();
+
+        let results = self.speedtest_table(
+            &SPEEDTEST_HASH_SIZES,
+            max_threads,
+            move_time,
+            available_memory_mb(),
+            |line| println!("{}", line),
+        );
+
+        println!();
+        if let Some(msg) = recommendation(&results) {
+            println!("{}", msg);
+        }
+    }
+
+    // Searches the positions in every configuration that fits into the
+    // available memory. Each row of the table is printed as soon as it is
+    // measured. Afterwards, the hash size and the position the engine was
+    // configured with are restored.
+    fn speedtest_table(
+        &mut self,
+        hash_sizes: &[usize],
+        max_threads: usize,
+        move_time: u128,
+        memory: Option<usize>,
+        mut print: impl FnMut(String),
+    ) -> Vec<SpeedTestResult> {
+        let (info_tx, info_rx) = crossbeam_channel::bounded::<Information>(INFORMATION_CAPACITY);
+        self.search.init(
+            info_tx,
+            Arc::clone(&self.board),
+            Arc::clone(&self.mg),
+            Arc::clone(&self.tt_search),
+        );
+
+        let mut results: Vec<SpeedTestResult> = Vec::new();
+        print(table_header());
+
+        for &hash in hash_sizes.iter() {
+            if memory.is_some_and(|available| hash > available) {
+                print(format!(
+                    "{:>5} MB  skipped: not enough memory available",
+                    hash
+                ));
+                continue;
+            }
+
+            self.tt_search.write().expect(ErrFatal::LOCK).resize(hash);
+
+            for threads in 1..=max_threads {
+                let mut result = SpeedTestResult {
+                    hash,
+                    threads,
+                    nodes: 0,
+                    time: 0,
+                    depth: i8::MAX,
+                    hash_full: 0,
+                };
+
+                for fen in SPEEDTEST_POSITIONS.iter() {
+                    let summary = self.speedtest_search(fen, threads, move_time, &info_rx);
+                    if let Some(s) = summary {
+                        result.nodes += s.nodes;
+                        result.time += s.time;
+                        result.depth = result.depth.min(s.depth);
+                        result.hash_full = result.hash_full.max(s.hash_full);
+                    }
+                }
+
+                print(table_row(&result));
+                results.push(result);
+            }
+        }
+
+        // Put back what the speed test changed.
+        self.search.send(SearchControl::Quit);
+        self.search.wait_for_shutdown();
+        self.tt_search
+            .write()
+            .expect(ErrFatal::LOCK)
+            .resize(self.settings.tt_size);
+        self.setup_position().expect(ErrFatal::NEW_GAME);
+
+        results
+    }
+
+    // Searches one position with a clean TT, and returns the summary of
+    // the last completed depth. The search reports until it is finished.
+    fn speedtest_search(
+        &mut self,
+        fen: &str,
+        threads: usize,
+        move_time: u128,
+        info_rx: &crossbeam_channel::Receiver<Information>,
+    ) -> Option<SearchSummary> {
+        self.board
+            .lock()
+            .expect(ErrFatal::LOCK)
+            .fen_read(Some(fen))
+            .expect(ErrFatal::NEW_GAME);
+        self.tt_search.read().expect(ErrFatal::LOCK).clear();
+
+        let mut sp = SearchParams::new();
+        sp.search_mode = SearchMode::MoveTime;
+        sp.move_time = move_time;
+        sp.threads = threads;
+        sp.quiet = true;
+        self.search.send(SearchControl::Start(sp));
+
+        let mut summary = None;
+        loop {
+            match info_rx.recv().expect(ErrFatal::CHANNEL) {
+                Information::Search(SearchReport::SearchSummary(s)) if s.multipv <= 1 => {
+                    summary = Some(s)
+                }
+                Information::Search(SearchReport::Finished(..)) => break,
+                _ => (),
+            }
+        }
+        summary
+    }
+}
+
+// The header of the table: one column per measurement.
+fn table_header() -> String {
+    format!(
+        "{:>8} {:>8} {:>12} {:>6} {:>9}",
+        "Hash", "Threads", "NPS", "Depth", "Hashfull"
+    )
+}
+
+// Each column is aligned with the right end of its header.
+fn table_row(result: &SpeedTestResult) -> String {
+    format!(
+        "{:>5} MB {:>8} {:>12} {:>6} {:>9}",
+        result.hash,
+        result.threads,
+        result.nps(),
+        result.depth,
+        result.hash_full
+    )
+}
+
+// Recommends the number of threads that searched the most nodes per
+// second, and the smallest hash size that was large enough for it.
+fn recommendation(results: &[SpeedTestResult]) -> Option<String> {
+    let fastest = results.iter().max_by_key(|r| r.nps())?;
+    let hash = results
+        .iter()
+        .filter(|r| r.threads == fastest.threads)
+        .filter(|r| r.hash_full <= SPEEDTEST_HASH_FULL_LIMIT)
+        .map(|r| r.hash)
+        .min()
+        .unwrap_or_else(|| {
+            let tested = results.iter().map(|r| r.hash);
+            tested.max().unwrap_or(fastest.hash)
+        });
+
+    Some(format!(
+        "Recommendation: Threads {}, Hash {} MB.",
+        fastest.threads, hash
+    ))
+}
+
+// Returns the memory that is available, in MB. This is only known on
+// Linux. Elsewhere, no hash size is skipped.
+fn available_memory_mb() -> Option<usize> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kilobytes: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kilobytes / 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{comm::mock::Mock, misc::cmdline::CmdLine};
+
+    // Returns the right end of each column in a line of the table.
+    fn column_ends(line: &str) -> Vec<usize> {
+        let mut ends = Vec::new();
+        let chars: Vec<char> = line.chars().collect();
+        for i in 0..chars.len() {
+            let next = chars.get(i + 1).copied().unwrap_or(' ');
+            if chars[i] != ' ' && next == ' ' {
+                ends.push(i);
+            }
+        }
+        ends
+    }
+
+    // A speed test with a tiny time budget. Every configuration gets a row
+    // whose numbers line up with the header; hash sizes larger than the
+    // available memory are skipped. The engine's hash size and position
+    // are restored afterwards.
+    #[test]
+    fn table_format() {
+        let (mock, _handle) = Mock::new("uci");
+        let cmdline = CmdLine::from_args(&["rustic-alpha", "--hash", "1", "--quiet"]);
+        let mut engine = Engine::with_comm(cmdline, Box::new(mock));
+        engine.setup_position().expect("start position");
+        let before = engine.board.lock().expect(ErrFatal::LOCK).fen_write();
+
+        let mut lines = Vec::new();
+        let results = engine.speedtest_table(&[1, 2, 64], 2, 20, Some(4), |line| lines.push(line));
+
+        assert_eq!(lines.len(), 1 + 2 * 2 + 1);
+        assert_eq!(lines[0], table_header());
+        let header_ends = column_ends(&lines[0]);
+        let mut configurations = Vec::new();
+
+        for (row, result) in lines[1..5].iter().zip(results.iter()) {
+            // Hash and "MB" are one column.
+            let mut ends = column_ends(row);
+            ends.remove(0);
+            assert_eq!(ends, header_ends, "{}", row);
+
+            let fields: Vec<usize> = row
+                .split_whitespace()
+                .filter(|field| *field != "MB")
+                .map(|field| field.parse().expect("number"))
+                .collect();
+            assert_eq!(
+                fields,
+                vec![
+                    result.hash,
+                    result.threads,
+                    result.nps(),
+                    result.depth as usize,
+                    result.hash_full as usize
+                ]
+            );
+            assert!(result.nodes > 0 && result.depth >= 1);
+            configurations.push((result.hash, result.threads));
+        }
+
+        assert_eq!(configurations, vec![(1, 1), (1, 2), (2, 1), (2, 2)]);
+        assert_eq!(lines[5], "   64 MB  skipped: not enough memory available");
+        assert_eq!(
+            engine.tt_search.read().expect(ErrFatal::LOCK).megabytes(),
+            1
+        );
+        assert_eq!(
+            engine.board.lock().expect(ErrFatal::LOCK).fen_write(),
+            before
+        );
+    }
+
+    #[test]
+    fn recommends_the_fastest_threads_with_enough_hash() {
+        let result = |hash, threads, nodes, hash_full| SpeedTestResult {
+            hash,
+            threads,
+            nodes,
+            time: 1000,
+            depth: 10,
+            hash_full,
+        };
+        let results = [
+            result(16, 1, 1_000_000, 900),
+            result(16, 2, 1_900_000, 950),
+            result(64, 1, 1_000_000, 400),
+            result(64, 2, 1_950_000, 600),
+            result(256, 1, 1_000_000, 100),
+            result(256, 2, 2_000_000, 300),
+        ];
+
+        assert_eq!(
+            recommendation(&results).as_deref(),
+            Some("Recommendation: Threads 2, Hash 256 MB.")
+        );
+
+        // If no hash size was large enough, the largest one is recommended.
+        assert_eq!(
+            recommendation(&results[..4]).as_deref(),
+            Some("Recommendation: Threads 2, Hash 64 MB.")
+        );
+        assert_eq!(recommendation(&[]), None);
+    }
+}
@@ -0,0 +1,305 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// time_watch.rs keeps an eye on the engine's own clock while it plays
+// with a time control set by the GUI. A search may use more time than it
+// was allowed, or the GUI may count more time than the engine measured,
+// because of lag between the two. Both can lose a game on time without
+// any trace in the log, so they are reported as warnings. The model of
+// the clock is simple: after a move, the clock should show what it did
+// before, minus the time used, plus the increment.
+
+use super::Engine;
+use crate::{
+    comm::CommControl,
+    engine::defs::ErrFatal,
+    search::defs::{GameTime, OVERHEAD},
+};
+use std::time::Instant;
+
+const CLOCK_DEVIATION: u128 = 1_000; // Msecs the clock may be lower than expected
+const WARNING_INTERVAL: u128 = 10_000; // Minimum msecs between two warnings
+
+pub struct TimeWatch {
+    started: Option<Instant>,      // When the watched search started.
+    clock: u128,                   // Engine's clock at the start (msecs).
+    increment: u128,               // Increment after the move (msecs).
+    budget: Option<u128>,          // Most time the search may use.
+    expected: Option<u128>,        // Expected clock at the next search.
+    last_warning: Option<Instant>, // When the last warning was sent.
+    moves: usize,                  // Searches watched in this game.
+    overruns: usize,               // Searches that used too much time.
+    deviations: usize,             // Clocks much lower than expected.
+}
+
+impl TimeWatch {
+    pub fn new() -> Self {
+        Self {
+            started: None,
+            clock: 0,
+            increment: 0,
+            budget: None,
+            expected: None,
+            last_warning: None,
+            moves: 0,
+            overruns: 0,
+            deviations: 0,
+        }
+    }
+
+    // A search on the given clock is started. If that clock is a lot
+    // lower than the model expected, a warning is returned.
+    pub fn start(&mut self, clock: u128, increment: u128) -> Option<String> {
+        let deviation = self
+            .expected
+            .filter(|&expected| clock + CLOCK_DEVIATION < expected)
+            .map(|expected| {
+                self.deviations += 1;
+                format!(
+                    "clock {} ms, expected {} ms (-{} ms)",
+                    clock,
+                    expected,
+                    expected - clock
+                )
+            });
+
+        self.started = Some(Instant::now());
+        self.clock = clock;
+        self.increment = increment;
+        self.budget = None;
+        self.expected = None;
+        self.moves += 1;
+
+        deviation.and_then(|msg| self.warning(msg))
+    }
+
+    // The search reports the most time it is going to use.
+    pub fn set_budget(&mut self, budget: u128) {
+        if self.started.is_some() {
+            self.budget = Some(budget);
+        }
+    }
+
+    // Returns the msecs since the watched search started, if any.
+    pub fn elapsed(&self) -> Option<u128> {
+        self.started.map(|s| s.elapsed().as_millis())
+    }
+
+    // The watched search has finished after using the given time. If it
+    // overran its budget by more than the overhead, a warning is
+    // returned. The clock expected for the next search is set.
+    pub fn finish(&mut self, used: u128) -> Option<String> {
+        self.started.take()?;
+
+        self.expected = Some(self.clock.saturating_sub(used) + self.increment);
+        let overrun = self
+            .budget
+            .filter(|&budget| used > budget + OVERHEAD)
+            .map(|budget| {
+                self.overruns += 1;
+                format!(
+                    "used {} ms, budget {} ms, overhead {} ms",
+                    used, budget, OVERHEAD
+                )
+            });
+
+        overrun.and_then(|msg| self.warning(msg))
+    }
+
+    // The next search can't be compared to the last one; for example,
+    // because it isn't a search on the clock.
+    pub fn forget(&mut self) {
+        self.started = None;
+        self.expected = None;
+    }
+
+    // Summarizes the game and starts watching a new one. There is no
+    // summary if no search was watched.
+    pub fn new_game(&mut self) -> Option<String> {
+        let summary = if self.moves > 0 {
+            Some(format!(
+                "time watch: {} moves on the clock, {} overruns, {} clock deviations",
+                self.moves, self.overruns, self.deviations
+            ))
+        } else {
+            None
+        };
+
+        *self = TimeWatch::new();
+        summary
+    }
+
+    // Turns the message into a warning, unless a warning was sent only
+    // a short while ago. The event is counted anyway.
+    fn warning(&mut self, msg: String) -> Option<String> {
+        let recent = self
+            .last_warning
+            .is_some_and(|w| w.elapsed().as_millis() < WARNING_INTERVAL);
+
+        if recent {
+            None
+        } else {
+            self.last_warning = Some(Instant::now());
+            Some(format!("WARNING: time overrun: {}", msg))
+        }
+    }
+}
+
+// These functions tie the time watch to the engine's searches.
+impl Engine {
+    // Start watching a search in GameTime mode.
+    pub fn time_watch_start(&mut self, game_time: &GameTime) {
+        let us = self.board.lock().expect(ErrFatal::LOCK).us();
+        let (clock, increment) = game_time.for_side(us);
+
+        if let Some(msg) = self.time_watch.start(clock, increment) {
+            self.comm.send(CommControl::InfoString(msg));
+        }
+    }
+
+    // The search has finished, so check the time it took.
+    pub fn time_watch_finished(&mut self) {
+        if let Some(used) = self.time_watch.elapsed() {
+            if let Some(msg) = self.time_watch.finish(used) {
+                self.comm.send(CommControl::InfoString(msg));
+            }
+        }
+    }
+
+    // A new game starts. Show the diagnostic of the last one.
+    pub fn time_watch_new_game(&mut self) {
+        if let Some(msg) = self.time_watch.new_game() {
+            self.comm.send(CommControl::InfoString(msg));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Makes the last warning old enough for the next one to be sent.
+    fn age_last_warning(watch: &mut TimeWatch) {
+        let old = Instant::now().checked_sub(Duration::from_millis(WARNING_INTERVAL as u64 + 1));
+        watch.last_warning = old;
+    }
+
+    #[test]
+    fn searches_on_budget() {
+        let mut watch = TimeWatch::new();
+
+        // The clock drops by the time used, and gains the increment. A
+        // clock somewhat lower than that is still within the deviation.
+        assert_eq!(watch.start(60_000, 1000), None);
+        watch.set_budget(2000);
+        assert_eq!(watch.finish(2000 + OVERHEAD), None);
+        assert_eq!(watch.expected, Some(59_000 - OVERHEAD));
+        assert_eq!(watch.start(59_000 - OVERHEAD - CLOCK_DEVIATION, 1000), None);
+        watch.set_budget(2000);
+        assert_eq!(watch.finish(500), None);
+
+        assert_eq!((watch.moves, watch.overruns, watch.deviations), (2, 0, 0));
+    }
+
+    #[test]
+    fn overrun_and_lagging_clock() {
+        let mut watch = TimeWatch::new();
+
+        watch.start(10_000, 0);
+        watch.set_budget(1000);
+        assert_eq!(
+            watch.finish(1001 + OVERHEAD),
+            Some(format!(
+                "WARNING: time overrun: used {} ms, budget 1000 ms, overhead {} ms",
+                1001 + OVERHEAD,
+                OVERHEAD
+            ))
+        );
+
+        // The GUI counted more time than the engine measured.
+        age_last_warning(&mut watch);
+        let expected = 10_000 - 1001 - OVERHEAD;
+        assert_eq!(
+            watch.start(expected - 1500, 0),
+            Some(format!(
+                "WARNING: time overrun: clock {} ms, expected {} ms (-1500 ms)",
+                expected - 1500,
+                expected
+            ))
+        );
+        assert_eq!((watch.moves, watch.overruns, watch.deviations), (2, 1, 1));
+    }
+
+    // Warnings follow each other no faster than the interval, but every
+    // event is counted.
+    #[test]
+    fn warnings_are_rate_limited() {
+        let mut watch = TimeWatch::new();
+        let overrun = |watch: &mut TimeWatch| {
+            watch.start(60_000, 0);
+            watch.set_budget(100);
+            watch.finish(100 + OVERHEAD + 1)
+        };
+
+        assert!(overrun(&mut watch).is_some());
+        assert!(overrun(&mut watch).is_none());
+        age_last_warning(&mut watch);
+        assert!(overrun(&mut watch).is_some());
+        assert_eq!(watch.overruns, 3);
+    }
+
+    // Without a started search, nothing is checked. After a search that
+    // isn't on the clock, the next clock isn't compared to the model.
+    #[test]
+    fn searches_off_the_clock_are_ignored() {
+        let mut watch = TimeWatch::new();
+        watch.set_budget(100);
+        assert_eq!(watch.finish(60_000), None);
+        assert_eq!(watch.budget, None);
+
+        watch.start(60_000, 0);
+        watch.finish(100);
+        watch.forget();
+        assert_eq!(watch.start(1000, 0), None);
+        assert_eq!(watch.deviations, 0);
+    }
+
+    #[test]
+    fn game_summary() {
+        let mut watch = TimeWatch::new();
+        assert_eq!(watch.new_game(), None);
+
+        watch.start(60_000, 0);
+        watch.set_budget(100);
+        watch.finish(100 + OVERHEAD + 1);
+        watch.start(1000, 0);
+        assert_eq!(
+            watch.new_game(),
+            Some(String::from(
+                "time watch: 2 moves on the clock, 1 overruns, 1 clock deviations"
+            ))
+        );
+        assert_eq!(watch.new_game(), None);
+    }
+}
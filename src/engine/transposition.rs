@@ -21,23 +21,55 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use crate::{board::defs::ZobristKey, movegen::defs::ShortMove, search::defs::CHECKMATE_THRESHOLD};
+use crate::{
+    board::defs::ZobristKey, engine::defs::EngineOptionDefaults, movegen::defs::ShortMove,
+    search::defs::Score,
+};
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
 
 const MEGABYTE: usize = 1024 * 1024;
 const ENTRIES_PER_BUCKET: usize = 2;
 const HIGH_FOUR_BYTES: u64 = 0xFF_FF_FF_FF_00_00_00_00;
-const LOW_FOUR_BYTES: u64 = 0x00_00_00_00_FF_FF_FF_FF;
 const SHIFT_TO_LOWER: u64 = 32;
+const SHIFT_GENERATION: u64 = 56;
+const DATA_ONLY: u64 = 0x00_FF_FF_FF_FF_FF_FF_FF;
+
+// PerftData packs the leaf node count into 48 bits.
+const MAX_PERFT_LEAF_NODES: u64 = (1 << 48) - 1;
+
+// An entry from an older search loses this much depth when the bucket
+// decides which entry to replace.
+const AGE_PENALTY: i32 = 8;
+
+// A saved TT file starts with this header: the magic bytes, the size of an
+// entry (u32), the generation (u8), the size of the TT in MB (u64) and the
+// number of entries (u64). Only used entries are saved. They follow as
+// pairs of the key and the data (u64 each). All numbers are little endian.
+const FILE_MAGIC: &[u8; 8] = b"RUSTICTT";
+const FILE_HEADER_SIZE: usize = 8 + 4 + 1 + 8 + 8;
+const FILE_ENTRY_SIZE: usize = 8 + 8;
 
 /* ===== Data ========================================================= */
 
+// Data stored in the TT must fit into the lower 56 bits of a u64, so it
+// can be written and read by one atomic operation. (See Entry for the
+// reason.) The TT uses the upper byte to store the entry's generation.
 pub trait IHashData {
-    fn new() -> Self;
     fn depth(&self) -> i8;
+    fn pack(&self) -> u64;
+    fn unpack(data: u64) -> Self;
 }
 
 // The PerftData struct holds the information the engine needs while using
-// the transposition table during a Perft run.
+// the transposition table during a Perft run. The TT only finds it for the
+// full Zobrist key it was stored with, and get() only returns the count
+// for the depth it was stored with.
 #[derive(Copy, Clone)]
 pub struct PerftData {
     depth: i8,
@@ -45,21 +77,32 @@ pub struct PerftData {
 }
 
 impl IHashData for PerftData {
-    fn new() -> Self {
-        Self {
-            depth: 0,
-            leaf_nodes: 0,
-        }
-    }
-
     fn depth(&self) -> i8 {
         self.depth
     }
+
+    // The depth goes into the lowest byte. The leaf node count gets the
+    // next 48 bits, which is enough for any perft the engine can run.
+    fn pack(&self) -> u64 {
+        (self.depth as u8 as u64) | (self.leaf_nodes << 8)
+    }
+
+    fn unpack(data: u64) -> Self {
+        Self {
+            depth: data as u8 as i8,
+            leaf_nodes: (data & DATA_ONLY) >> 8,
+        }
+    }
 }
 
 impl PerftData {
-    pub fn create(depth: i8, leaf_nodes: u64) -> Self {
-        Self { depth, leaf_nodes }
+    // Returns None if the leaf node count doesn't fit into the TT entry.
+    pub fn create(depth: i8, leaf_nodes: u64) -> Option<Self> {
+        if leaf_nodes <= MAX_PERFT_LEAF_NODES {
+            Some(Self { depth, leaf_nodes })
+        } else {
+            None
+        }
     }
 
     pub fn get(&self, depth: i8) -> Option<u64> {
@@ -82,88 +125,84 @@ pub enum HashFlag {
     Beta,
 }
 
+impl HashFlag {
+    fn from_u8(flag: u8) -> Self {
+        match flag {
+            1 => HashFlag::Exact,
+            2 => HashFlag::Alpha,
+            3 => HashFlag::Beta,
+            _ => HashFlag::Nothing,
+        }
+    }
+}
+
 // The SearchData struct holds all the data the engine needs when using the
 // transposition table during a normal search.
 #[derive(Copy, Clone)]
 pub struct SearchData {
     depth: i8,
     flag: HashFlag,
-    value: i16,
+    value: Score,
     best_move: ShortMove,
 }
 
 impl IHashData for SearchData {
-    fn new() -> Self {
-        Self {
-            depth: 0,
-            flag: HashFlag::Nothing,
-            value: 0,
-            best_move: ShortMove::new(0),
-        }
-    }
-
     fn depth(&self) -> i8 {
         self.depth
     }
-}
 
-impl SearchData {
-    pub fn create(depth: i8, ply: i8, flag: HashFlag, value: i16, best_move: ShortMove) -> Self {
-        // This is the value we're going to save into the TT.
-        let mut v = value;
-
-        // If we're dealing with checkmate, the value must be adjusted so
-        // they take the number of plies at which they were found into
-        // account. (These if's can be combined in a match expression, but
-        // this is slower because the compiler doesn't inline them.)
-        if v > CHECKMATE_THRESHOLD {
-            v += ply as i16;
-        }
+    // From LSB: depth (8 bits), flag (8 bits), value (16 bits) and the
+    // best move (24 bits).
+    fn pack(&self) -> u64 {
+        (self.depth as u8 as u64)
+            | ((self.flag as u8 as u64) << 8)
+            | ((self.value.value() as u16 as u64) << 16)
+            | ((self.best_move.get_move() as u64) << 32)
+    }
 
-        if v < CHECKMATE_THRESHOLD {
-            v -= ply as i16;
+    fn unpack(data: u64) -> Self {
+        Self {
+            depth: data as u8 as i8,
+            flag: HashFlag::from_u8((data >> 8) as u8),
+            value: Score::new((data >> 16) as u16 as i16),
+            best_move: ShortMove::new(((data & DATA_ONLY) >> 32) as u32),
         }
-
+    }
+}
+impl SearchData {
+    pub fn create(depth: i8, ply: i8, flag: HashFlag, value: Score, best_move: ShortMove) -> Self {
+        // If we're dealing with checkmate, the value must be adjusted so
+        // it takes the number of plies at which it was found into account.
         Self {
             depth,
             flag,
-            value: v,
+            value: value.tt_encode(ply),
             best_move,
         }
     }
 
-    pub fn get(&self, depth: i8, ply: i8, alpha: i16, beta: i16) -> (Option<i16>, ShortMove) {
+    pub fn get(&self, depth: i8, ply: i8, alpha: Score, beta: Score) -> (Option<Score>, ShortMove) {
         // We either do, or don't have a value to return from the TT.
-        let mut value: Option<i16> = None;
+        let mut value: Option<Score> = None;
 
         if self.depth >= depth {
+            // Adjust for the number of plies from where this data is
+            // probed, if we're dealing with checkmate. This must also be
+            // done before comparing bounds; otherwise a stored mate score
+            // is compared as if it was found at a different distance.
+            let stored = self.value.tt_decode(ply);
+
             match self.flag {
                 HashFlag::Exact => {
-                    // Get the value from the data. We don't want to change
-                    // the value that is in the TT.
-                    let mut v = self.value;
-
-                    // Adjust for the number of plies from where this data
-                    // is probed, if we're dealing with checkmate. Same as
-                    // above: no comparative match expression.
-                    if v > CHECKMATE_THRESHOLD {
-                        v -= ply as i16;
-                    }
-
-                    if v < CHECKMATE_THRESHOLD {
-                        v += ply as i16;
-                    }
-
-                    // This is the value that will be returned.
-                    value = Some(v);
+                    value = Some(stored);
                 }
                 HashFlag::Alpha => {
-                    if self.value <= alpha {
+                    if stored <= alpha {
                         value = Some(alpha);
                     }
                 }
                 HashFlag::Beta => {
-                    if self.value >= beta {
+                    if stored >= beta {
                         value = Some(beta);
                     }
                 }
@@ -172,62 +211,121 @@ impl SearchData {
         }
         (value, self.best_move)
     }
+
+    pub fn best_move(&self) -> ShortMove {
+        self.best_move
+    }
 }
 
 /* ===== Entry ======================================================== */
 
-#[derive(Copy, Clone)]
-struct Entry<D> {
-    verification: u32,
-    data: D,
+// Several search threads use the TT at the same time, without locking it.
+// One thread may read an entry while another one is writing it, and end up
+// with the key of one position and the data of another. To detect this,
+// the key is stored XOR'ed with the data: if either of them was changed by
+// a different write, XOR'ing them again won't give the key that was probed
+// for, and the entry is treated as a miss. (This is the "lockless hashing"
+// trick by Robert Hyatt.) An entry that was never used holds two zeros.
+struct Entry {
+    key: AtomicU64,
+    data: AtomicU64,
 }
 
-impl<D: IHashData> Entry<D> {
+impl Entry {
     pub fn new() -> Self {
         Self {
-            verification: 0,
-            data: D::new(),
+            key: AtomicU64::new(0),
+            data: AtomicU64::new(0),
         }
     }
+
+    pub fn load(&self) -> (ZobristKey, u64) {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        (key, data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.key.load(Ordering::Relaxed) == 0 && self.data.load(Ordering::Relaxed) == 0
+    }
 }
 
 /* ===== Bucket ======================================================= */
 
-#[derive(Clone)]
 struct Bucket<D> {
-    bucket: [Entry<D>; ENTRIES_PER_BUCKET],
+    bucket: [Entry; ENTRIES_PER_BUCKET],
+    data: PhantomData<D>,
 }
 
 impl<D: IHashData + Copy> Bucket<D> {
     pub fn new() -> Self {
         Self {
-            bucket: [Entry::new(); ENTRIES_PER_BUCKET],
+            bucket: [Entry::new(), Entry::new()],
+            data: PhantomData,
         }
     }
 
-    // Store a position in the bucket. Replace the position with the stored
-    // lowest depth, as positions with higher depth are more valuable.
-    pub fn store(&mut self, verification: u32, data: D, used_entries: &mut usize) {
-        // Pick the bucket having the lowest depth.
-        let first = self.bucket[0].data.depth() < self.bucket[1].data.depth();
-        let low = if first { 0 } else { 1 };
+    // Store a position in the bucket. If the position is already in the
+    // bucket, it is overwritten. Otherwise, replace the least valuable
+    // entry: positions with a higher depth are more valuable, but entries
+    // left over from older searches lose value with every generation. The
+    // data is already packed, including its generation.
+    pub fn store(
+        &self,
+        zobrist_key: ZobristKey,
+        data: u64,
+        generation: u8,
+        used_entries: &AtomicUsize,
+    ) {
+        let mut replace = 0;
+        let mut lowest = i32::MAX;
+
+        for (i, entry) in self.bucket.iter().enumerate() {
+            let (key, word) = entry.load();
+
+            if key == zobrist_key && !entry.is_empty() {
+                replace = i;
+                break;
+            }
+
+            let value = if entry.is_empty() {
+                i32::MIN
+            } else {
+                let age = generation.wrapping_sub((word >> SHIFT_GENERATION) as u8);
+                D::unpack(word).depth() as i32 - AGE_PENALTY * age as i32
+            };
 
-        // If the verification in the entry is 0, then it was never used
-        // before. Count the use of this entry.
-        if self.bucket[low].verification == 0 {
-            *used_entries += 1;
+            if value < lowest {
+                lowest = value;
+                replace = i;
+            }
+        }
+
+        let entry = &self.bucket[replace];
+
+        // Count the use of this entry if it was never used before.
+        if entry.is_empty() {
+            used_entries.fetch_add(1, Ordering::Relaxed);
         }
 
         // Store.
-        self.bucket[low] = Entry { verification, data }
+        entry.data.store(data, Ordering::Relaxed);
+        entry.key.store(zobrist_key ^ data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for entry in self.bucket.iter() {
+            entry.key.store(0, Ordering::Relaxed);
+            entry.data.store(0, Ordering::Relaxed);
+        }
     }
 
-    // Find a position in the bucket, where both the stored verification and
-    // depth match the requested verification and depth.
-    pub fn find(&self, verification: u32) -> Option<&D> {
+    // Find a position in the bucket by its Zobrist key.
+    pub fn find(&self, zobrist_key: ZobristKey) -> Option<D> {
         for entry in self.bucket.iter() {
-            if entry.verification == verification {
-                return Some(&(entry.data));
+            let (key, data) = entry.load();
+            if key == zobrist_key {
+                return Some(D::unpack(data));
             }
         }
         None
@@ -236,11 +334,15 @@ impl<D: IHashData + Copy> Bucket<D> {
 
 /* ===== TT =================================================== */
 
-// Transposition Table
+// Transposition Table. Only resizing needs an exclusive reference; the
+// search threads can insert and probe at the same time. The generation is
+// increased for each new search, so entries stored by earlier searches can
+// be recognized as such and replaced first.
 pub struct TT<D> {
     tt: Vec<Bucket<D>>,
     megabytes: usize,
-    used_entries: usize,
+    generation: AtomicU8,
+    used_entries: AtomicUsize,
     total_buckets: usize,
     total_entries: usize,
 }
@@ -254,9 +356,10 @@ impl<D: IHashData + Copy + Clone> TT<D> {
         let (total_buckets, total_entries) = Self::calculate_init_values(megabytes);
 
         Self {
-            tt: vec![Bucket::<D>::new(); total_buckets],
+            tt: Self::create_buckets(total_buckets),
             megabytes,
-            used_entries: 0,
+            generation: AtomicU8::new(0),
+            used_entries: AtomicUsize::new(0),
             total_buckets,
             total_entries,
         }
@@ -269,51 +372,161 @@ impl<D: IHashData + Copy + Clone> TT<D> {
     pub fn resize(&mut self, megabytes: usize) {
         let (total_buckets, total_entries) = TT::<D>::calculate_init_values(megabytes);
 
-        self.tt = vec![Bucket::<D>::new(); total_buckets];
+        // Free the old table before the new one is created.
+        self.tt = Vec::new();
+        self.tt = Self::create_buckets(total_buckets);
         self.megabytes = megabytes;
-        self.used_entries = 0;
+        self.generation = AtomicU8::new(0);
+        self.used_entries = AtomicUsize::new(0);
         self.total_buckets = total_buckets;
         self.total_entries = total_entries;
     }
 
     // Insert a position at the calculated index, by storing it in the
     // index's bucket.
-    pub fn insert(&mut self, zobrist_key: ZobristKey, data: D) {
+    pub fn insert(&self, zobrist_key: ZobristKey, data: D) {
         if self.megabytes > 0 {
             let index = self.calculate_index(zobrist_key);
-            let verification = self.calculate_verification(zobrist_key);
-            self.tt[index].store(verification, data, &mut self.used_entries);
+            let generation = self.generation.load(Ordering::Relaxed);
+            let data = data.pack() | ((generation as u64) << SHIFT_GENERATION);
+            self.tt[index].store(zobrist_key, data, generation, &self.used_entries);
         }
     }
 
-    // Probe the TT by both verification and depth. Both have to
-    // match for the position to be the correct one we're looking for.
-    pub fn probe(&self, zobrist_key: ZobristKey) -> Option<&D> {
+    // Probe the TT for the position with the given Zobrist key.
+    pub fn probe(&self, zobrist_key: ZobristKey) -> Option<D> {
         if self.megabytes > 0 {
             let index = self.calculate_index(zobrist_key);
-            let verification = self.calculate_verification(zobrist_key);
-
-            self.tt[index].find(verification)
+            self.tt[index].find(zobrist_key)
         } else {
             None
         }
     }
 
-    // Clear TT by replacing it with a new one.
-    pub fn clear(&mut self) {
+    // Clear TT by emptying all of its entries.
+    pub fn clear(&self) {
         if self.megabytes > 0 {
-            for bucket in self.tt.iter_mut() {
-                *bucket = Bucket::new();
+            for bucket in self.tt.iter() {
+                bucket.clear();
             }
-            self.used_entries = 0;
+            self.generation.store(0, Ordering::Relaxed);
+            self.used_entries.store(0, Ordering::Relaxed);
+        }
+    }
+
+    // Start a new generation. Entries stored from now on are younger than
+    // all entries already in the TT. The generation wraps around after
+    // 256 searches; the age of an entry is calculated with wrapping
+    // arithmetic, so this only matters for entries that are very old.
+    pub fn new_generation(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Save all the used entries to a file. Searches may continue to write
+    // into the TT meanwhile; an entry that changes while it is being saved
+    // gets a key that doesn't match any position, so it is never found.
+    pub fn save(&self, path: &str) -> Result<usize, String> {
+        let used: Vec<(ZobristKey, u64)> = self
+            .tt
+            .iter()
+            .flat_map(|bucket| bucket.bucket.iter())
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| entry.load())
+            .collect();
+
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_MAGIC);
+        header.extend_from_slice(&(std::mem::size_of::<Entry>() as u32).to_le_bytes());
+        header.push(self.generation.load(Ordering::Relaxed));
+        header.extend_from_slice(&(self.megabytes as u64).to_le_bytes());
+        header.extend_from_slice(&(used.len() as u64).to_le_bytes());
+        writer.write_all(&header).map_err(|e| e.to_string())?;
+
+        for (key, data) in used.iter() {
+            writer
+                .write_all(&key.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+            writer
+                .write_all(&data.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+
+        Ok(used.len())
+    }
+
+    // Load a TT from a file written by save(). The TT is resized to the
+    // size it had when it was saved, so each entry ends up in the bucket
+    // it came from. The whole file is checked before the TT is touched,
+    // so a file that can't be used leaves the TT as it was.
+    pub fn load(&mut self, path: &str) -> Result<usize, String> {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| e.to_string())?;
+
+        if bytes.len() < FILE_HEADER_SIZE || &bytes[0..8] != FILE_MAGIC {
+            return Err(String::from("not a saved hash file"));
+        }
+
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap_or([0; 8]));
+        let entry_size = u32::from_le_bytes(bytes[8..12].try_into().unwrap_or([0; 4]));
+        let generation = bytes[12];
+        let megabytes = u64_at(13) as usize;
+        let count = u64_at(21) as usize;
+
+        if entry_size as usize != std::mem::size_of::<Entry>() {
+            return Err(String::from("saved by an incompatible version"));
+        }
+
+        let max = EngineOptionDefaults::hash_max()
+            .parse::<usize>()
+            .unwrap_or(0);
+        if megabytes > max {
+            return Err(format!("the saved hash size is larger than {} MB", max));
+        }
+
+        let expected = count
+            .checked_mul(FILE_ENTRY_SIZE)
+            .and_then(|size| size.checked_add(FILE_HEADER_SIZE));
+        let (_, total_entries) = Self::calculate_init_values(megabytes);
+        if expected != Some(bytes.len()) || count > total_entries {
+            return Err(String::from("the file is damaged"));
         }
+
+        if megabytes == self.megabytes {
+            self.clear();
+        } else {
+            self.resize(megabytes);
+        }
+        for i in 0..count {
+            let start = FILE_HEADER_SIZE + i * FILE_ENTRY_SIZE;
+            let key = u64_at(start);
+            let data = u64_at(start + 8);
+            let index = self.calculate_index(key);
+            self.tt[index].store(key, data, generation, &self.used_entries);
+        }
+        self.generation.store(generation, Ordering::Relaxed);
+
+        Ok(count)
+    }
+
+    // The size of the TT in MB.
+    pub fn megabytes(&self) -> usize {
+        self.megabytes
     }
 
     // Provides TT usage in permille (1 per 1000, as opposed to percent,
-    // which is 1 per 100.)
+    // which is 1 per 100.) An entry is counted as used when it is written
+    // while it is still empty. Two threads writing the same empty entry
+    // at the same time can both count it, so the usage is capped at 1000.
     pub fn hash_full(&self) -> u16 {
         if self.megabytes > 0 {
-            ((self.used_entries as f64 / self.total_entries as f64) * 1000f64).floor() as u16
+            let used_entries = self.used_entries.load(Ordering::Relaxed);
+            let permille = (used_entries as f64 / self.total_entries as f64) * 1000f64;
+            (permille.floor() as u16).min(1000)
         } else {
             0
         }
@@ -323,8 +536,8 @@ impl<D: IHashData + Copy + Clone> TT<D> {
 // Private functions
 impl<D: IHashData + Copy + Clone> TT<D> {
     // Calculate the index (bucket) where the data is going to be stored.
-    // Use only the upper half of the Zobrist key for this, so the lower
-    // half can be used to calculate a verification.
+    // Use only the upper half of the Zobrist key for this. The entire key
+    // is stored in the entry to find the position in the bucket.
     fn calculate_index(&self, zobrist_key: ZobristKey) -> usize {
         let key = (zobrist_key & HIGH_FOUR_BYTES) >> SHIFT_TO_LOWER;
         let total = self.total_buckets as u64;
@@ -332,17 +545,15 @@ impl<D: IHashData + Copy + Clone> TT<D> {
         (key % total) as usize
     }
 
-    // Many positions will end up at the same index, and thus in the same
-    // bucket. Calculate a verification for the position so it can later be
-    // found in the bucket. Use the other half of the Zobrist key for this.
-    fn calculate_verification(&self, zobrist_key: ZobristKey) -> u32 {
-        (zobrist_key & LOW_FOUR_BYTES) as u32
+    // Atomics can't be cloned, so the buckets are created one by one.
+    fn create_buckets(total_buckets: usize) -> Vec<Bucket<D>> {
+        (0..total_buckets).map(|_| Bucket::new()).collect()
     }
 
     // This function calculates the values for total_buckets and
     // total_entries. These depend on the requested TT size.
     fn calculate_init_values(megabytes: usize) -> (usize, usize) {
-        let entry_size = std::mem::size_of::<Entry<D>>();
+        let entry_size = std::mem::size_of::<Entry>();
         let bucket_size = entry_size * ENTRIES_PER_BUCKET;
         let total_buckets = MEGABYTE / bucket_size * megabytes;
         let total_entries = total_buckets * ENTRIES_PER_BUCKET;
@@ -350,3 +561,304 @@ impl<D: IHashData + Copy + Clone> TT<D> {
         (total_buckets, total_entries)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        HashFlag, IHashData, PerftData, SearchData, AGE_PENALTY, FILE_HEADER_SIZE,
+        MAX_PERFT_LEAF_NODES, TT,
+    };
+    use crate::{movegen::defs::ShortMove, search::defs::Score};
+    use std::{
+        fs,
+        hint::black_box,
+        sync::{atomic::Ordering::Relaxed, Mutex},
+        thread,
+        time::Instant,
+    };
+
+    // Spreads the keys over the whole table: the index is taken from the
+    // upper half of the key. (This is SplitMix64, so each i gives a
+    // different key.)
+    fn key(i: u64) -> u64 {
+        let mut z = i.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn data(depth: i8) -> SearchData {
+        SearchData::create(depth, 0, HashFlag::Exact, Score::new(0), ShortMove::new(0))
+    }
+
+    #[test]
+    fn hash_full_grows_and_resets_after_clear() {
+        let tt = TT::<SearchData>::new(1);
+        let mut previous = tt.hash_full();
+        assert_eq!(previous, 0);
+
+        for round in 1..=8u64 {
+            for i in (round - 1) * 10_000..round * 10_000 {
+                tt.insert(key(i), data(1));
+            }
+            let hash_full = tt.hash_full();
+            assert!(hash_full > previous, "round {}: {}", round, hash_full);
+            assert!(hash_full <= 1000);
+            previous = hash_full;
+        }
+
+        tt.clear();
+        assert_eq!(tt.hash_full(), 0);
+        assert!(tt.probe(key(0)).is_none());
+    }
+
+    fn temp_file(name: &str) -> String {
+        let file = format!("rustic_{}_{}.hash", name, std::process::id());
+        std::env::temp_dir()
+            .join(file)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // A TT of 1 MB with entries of different depths, two generations old.
+    fn filled_tt() -> TT<SearchData> {
+        let tt = TT::<SearchData>::new(1);
+        tt.new_generation();
+        tt.new_generation();
+        for i in 0..20_000 {
+            let m = ShortMove::new(i as u32 & 0xFF_FFFF);
+            let d = SearchData::create((i % 50) as i8, 0, HashFlag::Beta, Score::new(i as i16), m);
+            tt.insert(key(i), d);
+        }
+        tt
+    }
+
+    fn assert_same_entries(a: &TT<SearchData>, b: &TT<SearchData>) {
+        assert_eq!(a.megabytes(), b.megabytes());
+        assert_eq!(a.hash_full(), b.hash_full());
+        for i in 0..30_000 {
+            let (x, y) = (a.probe(key(i)), b.probe(key(i)));
+            assert_eq!(x.map(|d| d.pack()), y.map(|d| d.pack()), "key {}", i);
+        }
+    }
+
+    #[test]
+    fn load_restores_the_saved_tt() {
+        let path = temp_file("round_trip");
+        let saved = filled_tt();
+        let used = saved.save(&path).expect("saved");
+        assert!(used > 0);
+
+        // Into a larger TT, a smaller one, and one that has no entries.
+        for &megabytes in [2, 0, 1].iter() {
+            let mut tt = TT::<SearchData>::new(megabytes);
+            tt.insert(key(99_999), data(1));
+            assert_eq!(tt.load(&path), Ok(used));
+            assert_same_entries(&saved, &tt);
+            assert!(tt.probe(key(99_999)).is_none());
+
+            // The generation is restored as well.
+            let (a, b) = (saved.generation.load(Relaxed), tt.generation.load(Relaxed));
+            assert_eq!(a, b);
+        }
+
+        // Saving the loaded TT gives the same file.
+        let mut tt = TT::<SearchData>::new(4);
+        tt.load(&path).expect("loaded");
+        let again = temp_file("round_trip_again");
+        tt.save(&again).expect("saved");
+        assert_eq!(fs::read(&path).ok(), fs::read(&again).ok());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&again);
+    }
+
+    #[test]
+    fn damaged_files_leave_the_tt_as_it_was() {
+        let path = temp_file("damaged");
+        filled_tt().save(&path).expect("saved");
+        let bytes = fs::read(&path).expect("read");
+
+        let mut too_large = bytes.clone();
+        too_large[13..21].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut too_many = bytes.clone();
+        too_many[13..21].copy_from_slice(&0u64.to_le_bytes());
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        let damaged = [
+            bytes[..bytes.len() - 1].to_vec(),
+            bytes[..FILE_HEADER_SIZE - 1].to_vec(),
+            too_large,
+            too_many,
+            magic,
+        ];
+
+        for file in damaged.iter() {
+            fs::write(&path, file).expect("written");
+            let mut tt = TT::<SearchData>::new(2);
+            tt.insert(key(0), data(7));
+            assert!(tt.load(&path).is_err());
+            assert_eq!(tt.megabytes(), 2);
+            assert_eq!(tt.probe(key(0)).map(|d| d.depth()), Some(7));
+        }
+
+        let _ = fs::remove_file(&path);
+        let mut tt = TT::<SearchData>::new(1);
+        assert!(tt.load(&path).is_err());
+    }
+
+    // Keys with the same upper half end up in the same bucket.
+    fn same_bucket_key(i: u64) -> u64 {
+        (0xABCD_1234 << 32) | (i + 1)
+    }
+
+    #[test]
+    fn old_entries_are_replaced_first() {
+        let tt = TT::<SearchData>::new(1);
+        let (deep, shallow, new) = (same_bucket_key(0), same_bucket_key(1), same_bucket_key(2));
+        let deep_depth = AGE_PENALTY as i8 - 2;
+
+        // In the same generation, the shallowest entry is replaced.
+        tt.insert(deep, data(deep_depth));
+        tt.insert(shallow, data(2));
+        tt.insert(new, data(1));
+        assert!(tt.probe(deep).is_some());
+        assert!(tt.probe(shallow).is_none());
+
+        // After a new search has started, the deep entry from the old
+        // search is worth less than a shallow entry from the current one.
+        tt.clear();
+        tt.insert(deep, data(deep_depth));
+        tt.new_generation();
+        tt.insert(shallow, data(2));
+        tt.insert(new, data(1));
+        assert!(tt.probe(deep).is_none());
+        assert_eq!(tt.probe(shallow).map(|d| d.depth()), Some(2));
+        assert_eq!(tt.probe(new).map(|d| d.depth()), Some(1));
+
+        // Storing a position that is already in the bucket overwrites it,
+        // whatever its depth.
+        tt.insert(shallow, data(1));
+        tt.insert(new, data(3));
+        assert_eq!(tt.probe(shallow).map(|d| d.depth()), Some(1));
+        assert_eq!(tt.probe(new).map(|d| d.depth()), Some(3));
+    }
+
+    #[test]
+    fn hash_full_is_capped_at_1000() {
+        let tt = TT::<SearchData>::new(1);
+        for i in 0..1_000_000 {
+            tt.insert(key(i), data(1));
+        }
+        assert_eq!(tt.hash_full(), 1000);
+    }
+
+    #[test]
+    fn perft_data_is_found_by_key_and_depth() {
+        let tt = TT::<PerftData>::new(1);
+        let counts = [(key(1), 3, 8_902), (key(2), 5, MAX_PERFT_LEAF_NODES)];
+        for (k, depth, leaf_nodes) in counts.iter() {
+            let data = PerftData::create(*depth, *leaf_nodes).expect("fits");
+            tt.insert(*k, data);
+        }
+
+        for (k, depth, leaf_nodes) in counts.iter() {
+            let found = tt.probe(*k).expect("stored");
+            assert_eq!(found.get(*depth), Some(*leaf_nodes));
+            assert_eq!(found.get(depth - 1), None);
+            assert_eq!(found.get(depth + 1), None);
+        }
+        assert!(tt.probe(key(3)).is_none());
+        assert!(PerftData::create(6, MAX_PERFT_LEAF_NODES + 1).is_none());
+    }
+
+    // Each thread stores entries whose data can be derived from the key,
+    // into the same part of the TT. An entry written by two threads at
+    // once must never be found with the data of another position.
+    #[test]
+    fn torn_writes_are_not_found() {
+        let tt = TT::<SearchData>::new(1);
+        let data_for = |k: u64| data((k % 100) as i8);
+
+        thread::scope(|s| {
+            for t in 0..4u64 {
+                let tt = &tt;
+                s.spawn(move || {
+                    for i in 0..200_000u64 {
+                        let k = key((i * 7 + t) % 5_000);
+                        tt.insert(k, data_for(k));
+                        if let Some(found) = tt.probe(key(i % 5_000)) {
+                            assert_eq!(found.depth(), data_for(key(i % 5_000)).depth());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    // Runs the stores and then the probes on the given number of threads,
+    // and returns the throughput of both in millions per second. Half of
+    // the probes are misses.
+    fn throughput<S, P>(threads: u64, store: S, probe: P) -> (f64, f64)
+    where
+        S: Fn(u64) + Sync,
+        P: Fn(u64) -> bool + Sync,
+    {
+        const OPERATIONS: u64 = 4_000_000;
+        let per_thread = OPERATIONS / threads;
+        let run = |f: &(dyn Fn(u64) + Sync)| {
+            let start = Instant::now();
+            thread::scope(|s| {
+                for t in 0..threads {
+                    s.spawn(move || {
+                        for i in t * per_thread..(t + 1) * per_thread {
+                            f(i);
+                        }
+                    });
+                }
+            });
+            OPERATIONS as f64 / start.elapsed().as_secs_f64() / 1_000_000f64
+        };
+
+        let stores = run(&|i| store(key(i)));
+        let probes = run(&|i| {
+            black_box(probe(key(i * 2)));
+        });
+        (stores, probes)
+    }
+
+    // Compares the TT as the engine used it before it became lockless,
+    // with a Mutex locked for every store and probe, to using it directly.
+    // This is a benchmark, not a test. Run it with:
+    // cargo test --release tt_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn tt_throughput() {
+        println!(
+            "{:<4} {:<8} {:>27} {:>27}",
+            "MB", "threads", "store M/s mutex / lockless", "probe M/s mutex / lockless"
+        );
+        for &megabytes in [1, 32, 256].iter() {
+            for &threads in [1, 4].iter() {
+                let locked = Mutex::new(TT::<SearchData>::new(megabytes));
+                let (m_store, m_probe) = throughput(
+                    threads,
+                    |k| locked.lock().unwrap().insert(k, data(1)),
+                    |k| locked.lock().unwrap().probe(k).is_some(),
+                );
+
+                let tt = TT::<SearchData>::new(megabytes);
+                let (l_store, l_probe) = throughput(
+                    threads,
+                    |k| tt.insert(k, data(1)),
+                    |k| tt.probe(k).is_some(),
+                );
+
+                println!(
+                    "{:<4} {:<8} {:>16.1} / {:<8.1} {:>16.1} / {:.1}",
+                    megabytes, threads, m_store, l_store, m_probe, l_probe
+                );
+            }
+        }
+    }
+}
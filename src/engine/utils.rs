@@ -21,62 +21,521 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use super::{defs::ErrFatal, Engine};
+use super::{
+    defs::{
+        EngineOptionDefaults, ErrFatal, ErrNormal, GameEnd, Information, MoveError, SearchData,
+        TTChange, STOP_SEARCH_TIMEOUT, TT,
+    },
+    Engine,
+};
 use crate::{
-    board::Board,
-    defs::{EngineRunResult, FEN_KIWIPETE_POSITION},
+    board::{
+        defs::{FenError, Pieces},
+        Board,
+    },
+    comm::CommControl,
+    defs::FEN_KIWIPETE_POSITION,
+    evaluation::Evaluation,
+    misc::affinity::{self, ThreadCount},
+    misc::format,
     misc::parse,
-    misc::parse::PotentialMove,
+    misc::parse::{PotentialMove, SanError},
     movegen::{
         defs::{Move, MoveList, MoveType},
         MoveGenerator,
     },
+    search::{
+        bench::BENCH_POSITIONS,
+        defs::{SearchControl, SearchMode, SearchParams},
+        Search,
+    },
+};
+use std::{
+    mem::discriminant,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use if_chain::if_chain;
-use std::sync::Mutex;
 
 impl Engine {
+    // Changes the search TT. The search holds on to the TT while it runs,
+    // so during a search, the change is queued and made when the search
+    // has finished. If the same kind of change is requested again while
+    // the first one is still queued, the user insists: the search is
+    // stopped, so the queue is handled right away.
+    pub fn change_tt_search(&mut self, change: TTChange) {
+        if let Ok(mut tt) = self.tt_search.try_write() {
+            if let Some(msg) = Engine::apply_tt_change(&mut tt, change) {
+                self.comm.send(CommControl::InfoString(msg));
+            }
+            self.settings.tt_size = tt.megabytes();
+            return;
+        }
+
+        let name = change.as_str();
+        let insists = self
+            .tt_changes
+            .iter()
+            .any(|c| discriminant(c) == discriminant(&change));
+        self.tt_changes.push(change);
+
+        let msg = if insists {
+            self.search.send(SearchControl::Stop);
+            format!("{}: search stopped to apply the change now.", name)
+        } else {
+            format!(
+                "{}: applied when the search has finished. Send again to stop the search.",
+                name
+            )
+        };
+        self.comm.send(CommControl::InfoString(msg));
+    }
+
+    // Makes the queued TT changes, in the order they were requested. The
+    // search thread lets go of the TT before it reports that it has
+    // finished, so the TT is free when this is called.
+    pub fn apply_tt_changes(&mut self) {
+        if !self.tt_changes.is_empty() {
+            let mut tt = self.tt_search.write().expect(ErrFatal::LOCK);
+            for change in self.tt_changes.drain(..) {
+                if let Some(msg) = Engine::apply_tt_change(&mut tt, change) {
+                    self.comm.send(CommControl::InfoString(msg));
+                }
+            }
+            self.settings.tt_size = tt.megabytes();
+        }
+    }
+
+    // Entries from the previous game can still be useful in a new one,
+    // so the TT isn't cleared. It is aged by two generations instead,
+    // which makes the old entries the first ones to be replaced. This
+    // doesn't need to wait for a running search to finish.
+    pub fn age_tt_search(&self) {
+        let tt = self.tt_search.read().expect(ErrFatal::LOCK);
+        tt.new_generation();
+        tt.new_generation();
+    }
+
+    // Saves the search TT to a file. This only reads the TT, so it
+    // doesn't need to wait for a running search to finish.
+    pub fn save_tt_search(&self, path: &str) {
+        let tt = self.tt_search.read().expect(ErrFatal::LOCK);
+        let msg = match tt.save(path) {
+            Ok(entries) => format!("Hash saved to {}: {} entries.", path, entries),
+            Err(e) => format!("Hash not saved to {}: {}.", path, e),
+        };
+        self.comm.send(CommControl::InfoString(msg));
+    }
+
+    // Makes one change to the TT. Returns a message for the user if there
+    // is something to report. Loading a TT can change its size; the
+    // callers keep the Hash setting in line with it.
+    fn apply_tt_change(tt: &mut TT<SearchData>, change: TTChange) -> Option<String> {
+        match change {
+            TTChange::Resize(megabytes) => tt.resize(megabytes),
+            TTChange::Clear => tt.clear(),
+            TTChange::Load(path) => {
+                return Some(match tt.load(&path) {
+                    Ok(entries) => format!(
+                        "Hash loaded from {}: {} entries, {} MB.",
+                        path,
+                        entries,
+                        tt.megabytes()
+                    ),
+                    Err(e) => format!("Hash not loaded from {}: {}.", path, e),
+                });
+            }
+        }
+        None
+    }
+
+    // Sends the evaluation of the current position, term by term.
+    pub fn print_eval(&self) {
+        let board = self.board_snapshot();
+        let trace = Evaluation::evaluate_position_trace(&board, &self.mg);
+        self.comm.send(CommControl::PrintEval(Box::new(trace)));
+    }
+
+    // Sends the static exchange on the to-square of the given move. The
+    // move may be written with an "x" for a capture, as in e4xd5.
+    pub fn print_see(&self, m: &str) {
+        let coordinates = m.replacen('x', "", 1);
+        let m = match parse::algebraic_move_to_number(&coordinates) {
+            Ok(_) => coordinates.as_str(),
+            Err(_) => m,
+        };
+
+        match self.find_move(m) {
+            Ok(found) => {
+                let board = self.board_snapshot();
+                let exchange = self.mg.see(&board, found);
+                self.comm
+                    .send(CommControl::PrintExchange(format::exchange(&exchange)));
+            }
+            Err(e) => {
+                let msg = format!("{}: {}", m, e.as_str());
+                self.comm.send(CommControl::InfoString(msg));
+            }
+        }
+    }
+
+    // Sends the pieces of both sides that attack the given square.
+    pub fn print_attackers(&self, square: &str) {
+        match parse::algebraic_square_to_number(square) {
+            Some(sq) => {
+                let board = self.board_snapshot();
+                let bb_attackers = self.mg.attackers_to(&board, sq, board.occupancy());
+                let text = format::attackers(&board, sq, bb_attackers);
+                self.comm.send(CommControl::PrintExchange(text));
+            }
+            None => {
+                let msg = format!("{}: {}", square, ErrNormal::NOT_SQUARE);
+                self.comm.send(CommControl::InfoString(msg));
+            }
+        }
+    }
+
+    // Sends the game played so far as a PGN. The engine doesn't know who
+    // played which side, or in which event, so those tags are unknown.
+    pub fn print_pgn(&self) {
+        let board = self.board_snapshot();
+        let result = match self.game_end() {
+            Some(end) => end.result(board.us()),
+            None => "*",
+        };
+        let site = self.xboard.ics.clone().unwrap_or(String::from("?"));
+        let mut tags = vec![
+            ("Event", String::from("?")),
+            ("Site", site),
+            ("Date", Engine::pgn_date()),
+            ("Round", String::from("-")),
+            ("White", String::from("?")),
+            ("Black", String::from("?")),
+            ("Result", result.to_string()),
+        ];
+        if board.chess960 {
+            tags.push(("Variant", String::from("Chess960")));
+        }
+
+        let pgn = format::pgn(&board, &self.mg, &tags, result);
+        self.comm.send(CommControl::PrintPgn(pgn));
+    }
+
+    // Verifies that the current position is a mate in exactly the given
+    // number of moves. The search runs on the engine thread, so the engine
+    // doesn't respond to other commands until it is done.
+    pub fn verify_mate(&self, moves: u8) {
+        let board = self.board_snapshot();
+        let verification = Search::verify_mate(&board, &self.mg, moves);
+        self.comm
+            .send(CommControl::InfoString(verification.as_string()));
+    }
+
+    // Runs the bench on the engine thread and sends the report. The engine
+    // doesn't respond to other commands until it is done.
+    pub fn print_bench(&self, depth: i8) {
+        let report = self.bench_report(depth);
+        self.comm.send(CommControl::PrintBench(report));
+    }
+
+    // Runs the bench and returns the report: the nodes per position, and
+    // the totals. The node total is the signature of the search.
+    pub fn bench_report(&self, depth: i8) -> String {
+        let result = Search::bench(&self.mg, depth);
+        let mut report = String::new();
+
+        for (i, nodes) in result.nodes.iter().enumerate() {
+            report.push_str(&format!(
+                "Position {:>2}/{}: {:>10} nodes\n",
+                i + 1,
+                BENCH_POSITIONS.len(),
+                nodes
+            ));
+        }
+        report.push_str(&format!("\nDepth: {}\n", depth));
+        report.push_str(&format!("Seed: {}\n", self.random.seed()));
+        report.push_str(&format!("Total time (ms): {}\n", result.time));
+        report.push_str(&format!("Nodes searched: {}\n", result.total_nodes()));
+        report.push_str(&format!("Nodes/second: {}\n", result.nps()));
+        report
+    }
+
+    // Today's date in the format used by PGN: YYYY.MM.DD. The conversion
+    // from days since 1970 to a calendar date is the one described by
+    // Howard Hinnant in "chrono-Compatible Low-Level Date Algorithms".
+    fn pgn_date() -> String {
+        let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(_) => return String::from("????.??.??"),
+        };
+        let z = seconds / 86_400 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}.{:02}.{:02}", year, month, day)
+    }
+
+    // Resolves the requested number of threads for this machine, within
+    // the limits of the Threads option.
+    pub fn resolve_threads(requested: ThreadCount) -> (usize, Option<String>) {
+        let max = EngineOptionDefaults::THREADS_MAX.parse().unwrap_or(1);
+        requested.resolve(affinity::available_cpus(), max)
+    }
+
     // This function sets up a position using a given FEN-string.
-    pub fn setup_position(&mut self) -> EngineRunResult {
+    pub fn setup_position(&mut self) -> Result<(), FenError> {
         // Get either the provided FEN-string or KiwiPete. If both are
         // provided, the KiwiPete position takes precedence.
         let f = &self.cmdline.fen()[..];
         let kp = self.cmdline.has_kiwipete();
         let fen = if kp { FEN_KIWIPETE_POSITION } else { f };
 
-        // Lock the board, setup the FEN-string, and drop the lock.
-        self.board
-            .lock()
-            .expect(ErrFatal::LOCK)
-            .fen_read(Some(fen))?;
+        self.fen_setup(fen)
+    }
+
+    // Sets up the given FEN-string on the engine's board. Positions where
+    // the side not to move is in check are rejected, unless the engine
+    // was started with --allow-illegal-fen. If setup fails, the board is
+    // not changed.
+    pub fn fen_setup(&self, fen: &str) -> Result<(), FenError> {
+        let mut new_board = self.board_snapshot();
+        if self.cmdline.allow_illegal_fen() {
+            new_board.fen_read_allow_check(Some(fen))?;
+        } else {
+            new_board.fen_read(Some(fen))?;
+        }
+
+        *self.board.lock().expect(ErrFatal::LOCK) = *new_board;
 
         Ok(())
     }
 
     // This function executes a move on the internal board, if it legal to
-    // do so in the given position.
-    pub fn execute_move(&mut self, m: String) -> bool {
-        // Prepare shorthand variables.
-        let empty = (0usize, 0usize, 0usize);
-        let potential_move = parse::algebraic_move_to_number(&m[..]).unwrap_or(empty);
-        let is_pseudo_legal = self.pseudo_legal(potential_move, &self.board, &self.mg);
-        let mut is_legal = false;
+    // do so in the given position. If not, it returns the reason. The move
+    // can be in coordinate notation (e2e4), or else in SAN (e4).
+    pub fn execute_move(&mut self, m: String) -> Result<(), MoveError> {
+        let pseudo_legal = self.find_move(&m)?;
 
-        if let Ok(ips) = is_pseudo_legal {
-            is_legal = self.board.lock().expect(ErrFatal::LOCK).make(ips, &self.mg);
+        if self
+            .board
+            .lock()
+            .expect(ErrFatal::LOCK)
+            .make(pseudo_legal, &self.mg)
+        {
+            Ok(())
+        } else {
+            Err(MoveError::InCheck)
+        }
+    }
+
+    // Finds a move in coordinate notation (e2e4), or else in SAN (e4), in
+    // the engine's position. The move is pseudo-legal; only make() can
+    // tell if it leaves the king in check.
+    fn find_move(&self, m: &str) -> Result<Move, MoveError> {
+        match parse::algebraic_move_to_number(m) {
+            Ok(potential_move) => self.pseudo_legal(potential_move, &self.board, &self.mg),
+            Err(_) => self.san_move(m),
+        }
+    }
+
+    // Starts a search with the given parameters. There is only one search
+    // at a time: one that is still running is stopped, and its result is
+    // dropped.
+    pub fn start_search(&mut self, mut sp: SearchParams) {
+        self.stop_search_and_wait();
+        self.last_pv.clear();
+        self.searches.start(&mut sp);
+        self.search.send(SearchControl::Start(sp));
+    }
+
+    // Stops the running search, so the position can be changed without
+    // the search's result being taken for the best move in the new one.
+    // The engine waits until the search has finished, handling its
+    // reports; commands from the GUI are kept until afterwards. The result
+    // is discarded, also when it arrives too late to wait for. Returns
+    // the parameters of the search that was stopped.
+    pub fn stop_search_and_wait(&mut self) -> Option<SearchParams> {
+        let sp = self.searches.discard()?;
+        let info_rx = match &self.info_rx {
+            Some(rx) => rx.clone(),
+            None => return Some(sp),
+        };
+
+        self.search.send(SearchControl::Stop);
+        let deadline = Instant::now() + Duration::from_millis(STOP_SEARCH_TIMEOUT);
+        while self.searches.is_discarded(sp.id) {
+            match info_rx.recv_deadline(deadline) {
+                Ok(Information::Search(sr)) => self.search_reports(&sr),
+                Ok(Information::Comm(cr)) => self.deferred.push_back(cr),
+                Err(_) => {
+                    let msg = String::from(ErrNormal::SEARCH_NOT_STOPPED);
+                    self.comm.send(CommControl::InfoString(msg));
+                    break;
+                }
+            }
+        }
+
+        Some(sp)
+    }
+
+    // An analysis (infinite search) that was stopped by a position change
+    // goes on in the new position. The GUI expects a best move only when
+    // it stops the analysis itself.
+    pub fn restart_analysis(&mut self, stopped: Option<SearchParams>) {
+        if let Some(sp) = stopped {
+            if sp.search_mode == SearchMode::Infinite {
+                self.start_search(sp);
+            }
+        }
+    }
+
+    // The move the engine expects the user to play. This is the reply to
+    // the engine's move in the last principal variation, if that move was
+    // the last one played. Otherwise, it is the best move in the TT for
+    // this position. Only a legal move is given as a hint.
+    pub fn hint(&self) -> Option<Move> {
+        let mut board = self.board_snapshot();
+        let last_played = match board.history.len() {
+            0 => None,
+            n => Some(board.history.get_ref(n - 1).next_move.to_short_move()),
+        };
+
+        let reply = match self.last_pv.get(..2) {
+            Some([m, reply]) if Some(m.to_short_move()) == last_played => {
+                Some(reply.to_short_move())
+            }
+            _ => None,
+        };
+        let wanted = reply.or_else(|| {
+            let tt = self.tt_search.read().expect(ErrFatal::LOCK);
+            tt.probe(board.game_state.zobrist_key)
+                .map(|data| data.best_move())
+        })?;
+
+        let mut ml = MoveList::new();
+        self.mg.generate_moves(&board, &mut ml, MoveType::All);
+        let m = (0..ml.len())
+            .map(|i| ml.get_move(i))
+            .find(|m| m.to_short_move() == wanted)?;
+
+        if board.make(m, &self.mg) {
+            Some(m)
+        } else {
+            None
+        }
+    }
+
+    // Takes back the given number of moves (plies) on the engine's board.
+    // If fewer moves were played since the position was set up, nothing
+    // is taken back and false is returned.
+    pub fn take_back(&mut self, plies: usize) -> bool {
+        let mut board = self.board.lock().expect(ErrFatal::LOCK);
+        if board.history.len() < plies {
+            return false;
+        }
+
+        for _ in 0..plies {
+            board.unmake();
+        }
+
+        true
+    }
+
+    // Determines if the game on the engine's board has ended, because the
+    // side to move has no legal moves left.
+    pub fn game_end(&self) -> Option<GameEnd> {
+        // A game lost on time is over, whatever the position.
+        if let Some(loser) = self.clock.forfeit() {
+            return Some(GameEnd::TimeForfeit(loser));
+        }
+
+        let mtx_board = self.board.lock().expect(ErrFatal::LOCK);
+
+        // As soon as one legal move is found, the game is not over.
+        if mtx_board.legal_moves_iter(&self.mg).next().is_some() {
+            return None;
+        }
+
+        // No legal moves. If the king is in check, it's checkmate.
+        let king_square = mtx_board.king_square(mtx_board.us());
+        let opponent = mtx_board.opponent();
+        if self.mg.square_attacked(&mtx_board, opponent, king_square) {
+            Some(GameEnd::Checkmate)
+        } else {
+            Some(GameEnd::Stalemate)
+        }
+    }
+
+    // Tells the user about the position after a move in XBoard: the result
+    // if the move ended the game, or a warning if it gave check. XBoard
+    // GUIs ignore the warning, but it is useful in a terminal.
+    pub fn announce_position(&self, engine_moved: bool) {
+        let board = self.board_snapshot();
+        let side_to_move = board.us();
+
+        if let Some(end) = self.game_end() {
+            self.comm.send(CommControl::GameResult(end, side_to_move));
+        } else if self
+            .mg
+            .square_attacked(&board, board.opponent(), board.king_square(side_to_move))
+        {
+            let msg = if engine_moved {
+                "You are in check!"
+            } else {
+                "Check!"
+            };
+            self.comm.send(CommControl::InfoString(msg.to_string()));
+        }
+    }
+
+    // Returns a copy of the engine's board. This is sent to Comm along
+    // with print commands, so Comm can print the board without locking it.
+    pub fn board_snapshot(&self) -> Box<Board> {
+        Box::new(self.board.lock().expect(ErrFatal::LOCK).clone())
+    }
+
+    // If "showboard" is on, print the board. If the engine's own move is
+    // given, it is shown on the board; in UCI, that move is only played on
+    // the engine's board when the GUI sends the next position.
+    pub fn show_board(&self, engine_move: Option<Move>) {
+        if !self.settings.show_board {
+            return;
         }
-        is_legal
+
+        let mut board = self.board_snapshot();
+        if let Some(m) = engine_move.filter(|m| !m.is_null()) {
+            board.make(m, &self.mg);
+        }
+
+        self.comm.send(CommControl::PrintBoard(board));
+    }
+
+    // Finds the move for a move in Standard Algebraic Notation.
+    fn san_move(&self, m: &str) -> Result<Move, MoveError> {
+        let board = self.board.lock().expect(ErrFatal::LOCK);
+        parse::san_to_move(&board, &self.mg, m).map_err(|e| match e {
+            SanError::Ambiguous => MoveError::Ambiguous,
+            SanError::Invalid | SanError::NotLegal => MoveError::NotLegal,
+        })
     }
 
     // After the engine receives an incoming move, it checks if this move
-    // is actually in the list of pseudo-legal moves for this position.
+    // is actually in the list of pseudo-legal moves for this position. A
+    // pawn move to the last rank only matches if the promotion piece is
+    // given, and a move that doesn't promote only matches without one.
     pub fn pseudo_legal(
         &self,
         m: PotentialMove,
         board: &Mutex<Board>,
         mg: &MoveGenerator,
-    ) -> Result<Move, ()> {
-        let mut result = Err(());
+    ) -> Result<Move, MoveError> {
+        let mut result = Err(MoveError::NotLegal);
+        let promoted = m.2.unwrap_or(Pieces::NONE);
 
         // Get the pseudo-legal move list for this position.
         let mut ml = MoveList::new();
@@ -88,14 +547,17 @@ impl Engine {
         // determine final legality when executing the move.
         for i in 0..ml.len() {
             let current = ml.get_move(i);
-            if_chain! {
-                if m.0 == current.from();
-                if m.1 == current.to();
-                if m.2 == current.promoted();
-                then {
-                    result = Ok(current);
-                    break;
-                }
+            if m.0 != current.from() || m.1 != current.notation_to() {
+                continue;
+            }
+
+            if promoted == current.promoted() {
+                return Ok(current);
+            }
+
+            // The move exists, but only as a promotion.
+            if m.2.is_none() {
+                result = Err(MoveError::NoPromotionPiece);
             }
         }
         result
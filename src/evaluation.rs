@@ -22,23 +22,60 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 pub mod defs;
+mod kingsafety;
+mod mobility;
+mod params;
+mod pawns;
 mod phase;
 mod pst;
+mod scaling;
+mod trace;
 
-use crate::{board::Board, defs::Sides};
+use crate::{board::Board, defs::Sides, movegen::MoveGenerator};
+use defs::{EvalParams, PawnTable, MOBILITY_ENABLED};
+
+#[cfg(feature = "engine")]
+use crate::search::defs::Score;
 
 pub struct Evaluation;
 impl Evaluation {
-    pub fn evaluate_position(board: &Board) -> i16 {
+    // The evaluation as a score for the search. The terms themselves don't
+    // need the search, so the board can keep its incremental PST and phase
+    // values up to date without it.
+    #[cfg(feature = "engine")]
+    pub fn evaluate_position(
+        board: &Board,
+        mg: &MoveGenerator,
+        pawn_table: &mut PawnTable,
+    ) -> Score {
         // Determine the side which is evaluating.
         let side = board.game_state.active_color as usize;
+        let params = &EvalParams::DEFAULT;
+
+        // When adding a term, add it to evaluate_position_trace() and to
+        // evaluate() in the tuner as well.
 
         // Establish base evaluation value by PST score.
         let mut value = Evaluation::pst_score(board);
 
+        // Penalize an unsafe king, and reward pawns storming the enemy king.
+        value += Evaluation::king_safety(board, mg, params);
+        value += Evaluation::pawn_storm(board, params);
+
+        // Reward pieces that have many safe squares to go to.
+        if MOBILITY_ENABLED {
+            value += Evaluation::mobility(board, mg, params);
+        }
+
+        // Doubled, isolated and passed pawns.
+        value += Evaluation::pawn_structure(board, pawn_table);
+
+        // Scale the evaluation down in drawish endings.
+        value = Evaluation::scale_endgame(board, value);
+
         // Flip point of view if black is evaluating.
         value = if side == Sides::BLACK { -value } else { value };
 
-        value
+        Score::new(value)
     }
 }
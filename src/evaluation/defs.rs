@@ -21,7 +21,152 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-pub use super::{pst::FLIP, pst::PST_EG, pst::PST_MG, Evaluation};
-pub const PHASE_VALUES: [i16; 6] = [0, 1050, 405, 305, 155, 0];
-pub const PHASE_MIN: i16 = 435;
-pub const PHASE_MAX: i16 = 5255;
+pub use super::{
+    params::EvalParams, pawns::PawnTable, pst::PstCollection, pst::FLIP, pst::PST_EG, pst::PST_MG,
+    trace::EvalTrace, Evaluation,
+};
+use crate::{
+    board::defs::{BB_FILES, BB_RANKS},
+    defs::{Bitboard, NrOf, Sides},
+};
+
+// Game phase: the classic 24-point scheme. Knights and bishops count 1,
+// rooks 2, and queens 4, so the starting position has 24 points and a
+// pawn ending has 0. Extra queens after promotion can push the count above
+// 24; determine_phase() clamps it, so this counts as the middlegame.
+pub const PHASE_VALUES: [i16; 6] = [0, 4, 2, 1, 1, 0];
+pub const PHASE_MIN: i16 = 0;
+pub const PHASE_MAX: i16 = 24;
+
+// Scale factors for drawish endings. The evaluation is multiplied by the
+// factor and then divided by NORMAL, so NORMAL leaves it untouched and 0
+// turns it into a draw score.
+pub struct EndgameScale;
+impl EndgameScale {
+    pub const NORMAL: i32 = 128;
+    pub const EQUAL_PIECES: i32 = 16; // R vs R, Q vs Q, R+B vs R+B...
+    pub const MINOR_UP: i32 = 32; // R+B vs R, R+N vs R, Q+B vs Q...
+    pub const ROOK_VS_MINOR: i32 = 32; // R vs B, R vs N
+    pub const NO_MATING_MATERIAL: i32 = 0; // Lone minor or two knights
+}
+
+// Pawn storm values, used when the kings are on opposite wings. The bonus
+// is indexed by the rank of the storming pawn, counted from its own side
+// of the board. Pawns on the second rank haven't started storming yet.
+pub struct PawnStorm;
+impl PawnStorm {
+    pub const MIN_KING_DISTANCE: i8 = 3; // Minimum files between kings
+    pub const RANK_BONUS: [i16; 8] = [0, 0, 5, 15, 30, 45, 50, 0];
+}
+
+// King safety values. These only matter in the middlegame, so the score
+// fades out as material comes off the board. The pawn shield looks at the
+// own pawns in front of the king, on the king's file and the files next
+// to it. The closest pawn on each file is penalized by its rank, counted
+// from its own side of the board; a missing pawn is penalized most.
+// The attack units count the attacks of enemy pieces on the king zone
+// (the king's square and the squares around it). A lone attacker rarely
+// mates, so the penalty is scaled by the number of attacking pieces.
+pub struct KingSafety;
+impl KingSafety {
+    pub const SHIELD_RANK: [i16; 8] = [0, 0, -10, -20, -25, -30, -30, -30];
+    pub const SHIELD_MISSING: i16 = -35;
+    pub const ATTACK_UNITS: [i32; 6] = [0, 5, 3, 2, 2, 0]; // By piece type
+    pub const ATTACK_UNIT_VALUE: i32 = 10;
+    pub const ATTACKER_PERCENT: [i32; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+    pub const MAX_ATTACK: i32 = 500;
+}
+
+// Mobility bonuses for the middlegame and endgame, indexed by the number
+// of safe squares a piece can move to: squares not occupied by its own
+// pieces, and not attacked by enemy pawns. A piece with few squares is
+// penalized; a piece with many gets a bonus. Set MOBILITY_ENABLED to
+// false to switch the term off, e.g. for testing against an engine
+// without it.
+pub const MOBILITY_ENABLED: bool = true;
+
+pub struct Mobility;
+impl Mobility {
+    pub const KNIGHT_MG: [i16; 9] = [-30, -20, -8, -2, 2, 6, 10, 14, 18];
+    pub const KNIGHT_EG: [i16; 9] = [-40, -28, -14, -6, 2, 8, 12, 16, 18];
+    pub const BISHOP_MG: [i16; 14] = [-25, -12, 0, 6, 12, 16, 20, 22, 24, 26, 28, 30, 32, 34];
+    pub const BISHOP_EG: [i16; 14] = [-35, -18, -6, 4, 10, 16, 22, 26, 30, 32, 34, 36, 38, 40];
+    pub const ROOK_MG: [i16; 15] = [-25, -14, -4, 0, 2, 4, 6, 9, 12, 14, 16, 18, 20, 22, 24];
+    pub const ROOK_EG: [i16; 15] = [-40, -20, -8, 2, 8, 14, 20, 26, 32, 36, 40, 44, 48, 50, 52];
+    pub const QUEEN_MG: [i16; 28] = [
+        -15, -10, -6, -3, 0, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 14, 15, 15, 16, 16, 17,
+        17, 18, 18, 18,
+    ];
+    pub const QUEEN_EG: [i16; 28] = [
+        -25, -16, -10, -5, -1, 2, 5, 8, 11, 13, 15, 17, 19, 21, 23, 25, 26, 27, 28, 29, 30, 31, 32,
+        33, 34, 35, 36, 36,
+    ];
+}
+
+// Pawn structure values as (middlegame, endgame) pairs. Doubled and
+// isolated pawns are penalized; passed pawns get a bonus indexed by their
+// rank, counted from their own side of the board. Passed pawns become
+// much more valuable in the endgame, when there are fewer pieces left to
+// stop them.
+pub struct PawnStructure;
+impl PawnStructure {
+    pub const DOUBLED: (i16, i16) = (-10, -20); // Per extra pawn on a file
+    pub const ISOLATED: (i16, i16) = (-10, -15);
+    pub const PASSED_MG: [i16; 8] = [0, 5, 10, 15, 25, 40, 60, 0];
+    pub const PASSED_EG: [i16; 8] = [0, 10, 15, 25, 45, 70, 110, 0];
+}
+
+// Number of entries in the pawn hash table. Must be a power of two.
+pub const PAWN_TABLE_ENTRIES: usize = 16 * 1024;
+
+type TBBAdjacentFiles = [Bitboard; NrOf::FILES];
+type TBBPassedSpan = [[Bitboard; NrOf::SQUARES]; Sides::BOTH];
+
+// For each file, the files directly next to it.
+const fn init_bb_adjacent_files() -> TBBAdjacentFiles {
+    let mut bb_adjacent: TBBAdjacentFiles = [0; NrOf::FILES];
+    let mut file = 0;
+
+    while file < NrOf::FILES {
+        if file > 0 {
+            bb_adjacent[file] |= BB_FILES[file - 1];
+        }
+        if file < NrOf::FILES - 1 {
+            bb_adjacent[file] |= BB_FILES[file + 1];
+        }
+        file += 1;
+    }
+
+    bb_adjacent
+}
+
+// For each side and square, all the squares in front of a pawn on that
+// square, on its own file and the files next to it. If there are no enemy
+// pawns within this span, the pawn is passed.
+const fn init_bb_passed_span() -> TBBPassedSpan {
+    let mut bb_span: TBBPassedSpan = [[0; NrOf::SQUARES]; Sides::BOTH];
+    let mut square = 0;
+
+    while square < NrOf::SQUARES {
+        let file = square % 8;
+        let rank = square / 8;
+        let files = BB_FILES[file] | BB_ADJACENT_FILES[file];
+        let mut r = 0;
+
+        while r < NrOf::RANKS {
+            if r > rank {
+                bb_span[Sides::WHITE][square] |= files & BB_RANKS[r];
+            }
+            if r < rank {
+                bb_span[Sides::BLACK][square] |= files & BB_RANKS[r];
+            }
+            r += 1;
+        }
+        square += 1;
+    }
+
+    bb_span
+}
+
+pub const BB_ADJACENT_FILES: TBBAdjacentFiles = init_bb_adjacent_files();
+pub const BB_PASSED_SPAN: TBBPassedSpan = init_bb_passed_span();
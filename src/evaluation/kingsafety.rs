@@ -0,0 +1,310 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements king safety terms. The king's own pawn shield and
+// the enemy pieces attacking the squares around the king are scored for
+// both sides. When the kings are castled on opposite wings, both sides can
+// also throw their pawns forward against the opponent's king without
+// weakening their own. Pawns that have advanced on the files around the
+// enemy king are rewarded; the further they are, the more dangerous the
+// storm. This only matters in the middlegame, so the scores fade out as
+// material comes off the board.
+
+use super::{
+    defs::{EvalParams, KingSafety, PawnStorm, BB_PASSED_SPAN, PHASE_MAX, PHASE_MIN},
+    Evaluation,
+};
+use crate::{
+    board::{
+        defs::{Pieces, BB_FILES, BB_SQUARES},
+        Board,
+    },
+    defs::{NrOf, Side, Sides},
+    misc::bits,
+    movegen::MoveGenerator,
+};
+use std::ops::RangeInclusive;
+
+impl Evaluation {
+    // Returns the king safety score (pawn shield and king attacks) from
+    // white's point of view.
+    pub fn king_safety(board: &Board, mg: &MoveGenerator, params: &EvalParams) -> i16 {
+        let (shield, attack) = Evaluation::king_safety_parts(board, mg, params);
+
+        shield + attack
+    }
+
+    // Returns the (pawn shield, king attack) scores from white's point of
+    // view, weighed by the middlegame phase.
+    pub fn king_safety_parts(board: &Board, mg: &MoveGenerator, params: &EvalParams) -> (i16, i16) {
+        let (w_shield, w_attack) = Evaluation::king_safety_side(board, mg, Sides::WHITE, params);
+        let (b_shield, b_attack) = Evaluation::king_safety_side(board, mg, Sides::BLACK, params);
+
+        let v = board.game_state.phase_value;
+        let phase = Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+        let shield = ((w_shield - b_shield) as f32 * phase).round() as i16;
+        let attack = ((w_attack - b_attack) as f32 * phase).round() as i16;
+
+        (shield, attack)
+    }
+
+    // Returns the (pawn shield, king attack) penalties for the king of the
+    // given side, before weighing them by the game phase.
+    pub fn king_safety_side(
+        board: &Board,
+        mg: &MoveGenerator,
+        side: Side,
+        params: &EvalParams,
+    ) -> (i16, i16) {
+        let shield = Evaluation::pawn_shield(board, side, params);
+        let attack = Evaluation::king_attack(board, mg, side);
+
+        (shield, attack)
+    }
+
+    // Penalizes missing and advanced pawns in front of the king.
+    fn pawn_shield(board: &Board, side: Side, params: &EvalParams) -> i16 {
+        let king_square = board.king_square(side);
+        let (king_file, _) = Board::square_on_file_rank(king_square);
+        let in_front = board.get_pieces(Pieces::PAWN, side) & BB_PASSED_SPAN[side][king_square];
+        let mut score = 0;
+
+        for file in king_files(king_file as usize) {
+            let pawns = in_front & BB_FILES[file];
+
+            if pawns == 0 {
+                score += params.shield_missing;
+                continue;
+            }
+
+            // The pawn closest to the king; ranks are counted from the
+            // king's own back rank.
+            let rank = if side == Sides::WHITE {
+                (pawns.trailing_zeros() / 8) as usize
+            } else {
+                NrOf::RANKS - 1 - (63 - pawns.leading_zeros() as usize) / 8
+            };
+
+            score += params.shield_rank[rank];
+        }
+
+        score
+    }
+
+    // Counts the attack units of the enemy pieces that attack the king zone.
+    fn king_attack(board: &Board, mg: &MoveGenerator, side: Side) -> i16 {
+        let king_square = board.king_square(side);
+        let zone = mg.get_non_slider_attacks(Pieces::KING, king_square) | BB_SQUARES[king_square];
+        let occupancy = board.occupancy();
+        let mut attackers = 0;
+        let mut units = 0;
+
+        for piece in [Pieces::QUEEN, Pieces::ROOK, Pieces::BISHOP, Pieces::KNIGHT] {
+            let mut bb_pieces = board.get_pieces(piece, side ^ 1);
+
+            while bb_pieces > 0 {
+                let square = bits::next(&mut bb_pieces);
+                let attacks = if piece == Pieces::KNIGHT {
+                    mg.get_non_slider_attacks(piece, square)
+                } else {
+                    mg.get_slider_attacks(piece, square, occupancy)
+                };
+
+                let hits = (attacks & zone).count_ones() as i32;
+                if hits > 0 {
+                    attackers += 1;
+                    units += KingSafety::ATTACK_UNITS[piece] * hits;
+                }
+            }
+        }
+
+        let percent = KingSafety::ATTACKER_PERCENT[usize::min(attackers, 7)];
+        let penalty = units * KingSafety::ATTACK_UNIT_VALUE * percent / 100;
+
+        -i32::min(penalty, KingSafety::MAX_ATTACK) as i16
+    }
+
+    // Returns the pawn storm score from white's point of view.
+    pub fn pawn_storm(board: &Board, params: &EvalParams) -> i16 {
+        let (white, black) = Evaluation::pawn_storm_sides(board, params);
+
+        // Weigh the score by the middlegame phase.
+        let v = board.game_state.phase_value;
+        let phase = Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+
+        ((white - black) as f32 * phase).round() as i16
+    }
+
+    // Returns the (white, black) pawn storm bonuses, before weighing them
+    // by the game phase.
+    pub fn pawn_storm_sides(board: &Board, params: &EvalParams) -> (i16, i16) {
+        let (w_king_file, _) = Board::square_on_file_rank(board.king_square(Sides::WHITE));
+        let (b_king_file, _) = Board::square_on_file_rank(board.king_square(Sides::BLACK));
+
+        // Pawn storms only make sense if the kings are on opposite wings.
+        let distance = (w_king_file as i8 - b_king_file as i8).abs();
+        if distance < PawnStorm::MIN_KING_DISTANCE {
+            return (0, 0);
+        }
+
+        // Score the storm of each side against the other side's king.
+        let white = Evaluation::storm_side(board, Sides::WHITE, b_king_file as usize, params);
+        let black = Evaluation::storm_side(board, Sides::BLACK, w_king_file as usize, params);
+
+        (white, black)
+    }
+
+    // Scores the pawns of the given side, on the king file of the enemy
+    // king and the files next to it.
+    fn storm_side(board: &Board, side: Side, king_file: usize, params: &EvalParams) -> i16 {
+        let storm_files = king_files(king_file).fold(0, |mask, file| mask | BB_FILES[file]);
+        let mut pawns = board.get_pieces(Pieces::PAWN, side) & storm_files;
+        let mut score = 0;
+
+        while pawns > 0 {
+            let square = bits::next(&mut pawns);
+            let (_, rank) = Board::square_on_file_rank(square);
+
+            // Ranks are counted from the storming side's own back rank.
+            let rank = if side == Sides::WHITE {
+                rank as usize
+            } else {
+                NrOf::RANKS - 1 - rank as usize
+            };
+
+            score += params.storm_rank[rank];
+        }
+
+        score
+    }
+}
+
+// Returns the given file, and the files next to it.
+fn king_files(king_file: usize) -> RangeInclusive<usize> {
+    let first = king_file.saturating_sub(1);
+    let last = usize::min(king_file + 1, NrOf::FILES - 1);
+
+    first..=last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASTLED: &str = "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 w - - 0 1";
+
+    // Returns the (pawn shield, king attack) scores of the position.
+    fn safety(fen: &str) -> (i16, i16) {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        Evaluation::king_safety_parts(&board, &MoveGenerator::shared(), &EvalParams::DEFAULT)
+    }
+
+    #[test]
+    fn pawn_shield() {
+        assert_eq!(safety(CASTLED), (0, 0));
+
+        // White weakens the shield in front of its castled king: first by
+        // advancing the g-pawn, then by losing it, then by losing the
+        // f- and h-pawns as well.
+        let weakened = [
+            "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P1P1/2N2N2/PPPP1P1P/R1BQ1RK1 w - - 0 1",
+            "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP1P1P/R1BQ1RK1 w - - 0 1",
+            "r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/2N2N2/PPPP4/R1BQ1RK1 w - - 0 1",
+        ];
+        let mut previous = 0;
+        for fen in weakened.iter() {
+            let (shield, _) = safety(fen);
+            assert!(shield < previous, "{}: {}", fen, shield);
+            previous = shield;
+        }
+
+        // The stripped king costs white all three missing pawns.
+        let missing = 3 * (KingSafety::SHIELD_MISSING - KingSafety::SHIELD_RANK[1]);
+        assert_eq!(previous, missing);
+
+        // The same for black.
+        let (shield, _) =
+            safety("r1bq1rk1/pppp4/2n2n2/2b1p3/4P3/2N2N2/PPPPBPPP/R1BQ1RK1 w - - 0 1");
+        assert_eq!(shield, -missing);
+    }
+
+    #[test]
+    fn king_attacks() {
+        // The white queen and knight attack the castled black king.
+        let attacked = "r1bq1rk1/pppp1ppp/2n2n2/2b1p1NQ/2B1P3/2N5/PPPP1PPP/R1B2RK1 w - - 0 1";
+        let (_, attack) = safety(attacked);
+        assert!(attack > 0, "{}", attack);
+
+        // A lone attacker rarely mates: the queen alone is not counted.
+        let lone = "r1bq1rk1/pppp1ppp/2n2n2/2b1p2Q/4P3/2N5/PPPP1PPP/R1B2RK1 w - - 0 1";
+        assert_eq!(safety(lone).1, 0);
+
+        // Without pieces, nothing is left of king safety.
+        assert_eq!(safety("6k1/8/8/8/8/8/8/6K1 w - - 0 1"), (0, 0));
+        assert_eq!(safety("6k1/8/8/8/8/8/5P1P/6K1 w - - 0 1"), (0, 0));
+    }
+
+    fn storm(fen: &str) -> (i16, i16, i16) {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let (white, black) = Evaluation::pawn_storm_sides(&board, &EvalParams::DEFAULT);
+        (
+            white,
+            black,
+            Evaluation::pawn_storm(&board, &EvalParams::DEFAULT),
+        )
+    }
+
+    // The kings are castled on opposite wings. The side whose pawns have
+    // advanced further against the enemy king is preferred.
+    #[test]
+    fn pawn_storms() {
+        let bonus = PawnStorm::RANK_BONUS;
+        let before = "2kr1bnr/ppp2ppp/2nq4/4p3/4P3/2NQ4/PPP2PPP/R1B2RK1 w - - 0 1";
+        assert_eq!(storm(before), (0, 0, 0));
+
+        // White storms the queenside with the a-, b- and c-pawns. Only
+        // the b- and c-pawns are on the files around the black king.
+        let white = "2kr1bnr/ppp2ppp/2nq4/1PP1p3/P3P3/2NQ4/5PPP/R1B2RK1 w - - 0 1";
+        let (white_storm, black_storm, score) = storm(white);
+        assert_eq!((white_storm, black_storm), (2 * bonus[4], 0));
+        assert!(score > 0);
+
+        // Black storms the kingside; its pawn on g3 is the most dangerous.
+        let black = "2kr1bnr/ppp5/2nq4/4p2p/4P3/2NQ2p1/PPP2P1P/R1B2RK1 w - - 0 1";
+        let (_, black_storm, score) = storm(black);
+        assert_eq!(black_storm, bonus[5] + bonus[3]);
+        assert!(score < 0);
+
+        // With both kings on the same wing, there is no storm.
+        let same_wing = "r1b2rk1/ppp2ppp/2nq4/4p3/4P3/2NQ4/5PPP/R1B2RK1 w - - 0 1";
+        assert_eq!(storm(same_wing), (0, 0, 0));
+
+        // The storm fades out when the pieces come off the board.
+        let endgame = "2k5/ppp5/8/1PP5/P7/8/5PPP/6K1 w - - 0 1";
+        let (white_storm, _, score) = storm(endgame);
+        assert!(white_storm > 0);
+        assert_eq!(score, 0);
+    }
+}
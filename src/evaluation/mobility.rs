@@ -0,0 +1,182 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements the mobility term. For each knight, bishop, rook
+// and queen, the squares it attacks are counted, except for squares
+// occupied by its own pieces and squares attacked by enemy pawns; moving
+// there would just lose the piece. The count is looked up in a bonus
+// table for the piece type, and the middlegame and endgame bonuses are
+// tapered by the game phase.
+
+use super::{
+    defs::{EvalParams, PHASE_MAX, PHASE_MIN},
+    Evaluation,
+};
+use crate::{
+    board::{defs::Pieces, Board},
+    defs::{Bitboard, Piece, Side, Sides},
+    misc::bits,
+    movegen::MoveGenerator,
+};
+
+impl Evaluation {
+    // Returns the mobility score from white's point of view.
+    pub fn mobility(board: &Board, mg: &MoveGenerator, params: &EvalParams) -> i16 {
+        let (w_mg, w_eg) = Evaluation::mobility_side(board, mg, Sides::WHITE, params);
+        let (b_mg, b_eg) = Evaluation::mobility_side(board, mg, Sides::BLACK, params);
+
+        let v = board.game_state.phase_value;
+        let phase = Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+        let mg_score = (w_mg - b_mg) as f32;
+        let eg_score = (w_eg - b_eg) as f32;
+
+        (mg_score * phase + eg_score * (1.0 - phase)).round() as i16
+    }
+
+    // Returns the (middlegame, endgame) mobility bonus for one side.
+    pub fn mobility_side(
+        board: &Board,
+        mg: &MoveGenerator,
+        side: Side,
+        params: &EvalParams,
+    ) -> (i16, i16) {
+        let mut mg_score = 0;
+        let mut eg_score = 0;
+
+        safe_squares(board, mg, side, |piece, count| {
+            let (bonus_mg, bonus_eg) = bonus(piece, count, params);
+            mg_score += bonus_mg;
+            eg_score += bonus_eg;
+        });
+
+        (mg_score, eg_score)
+    }
+
+    // Returns the total number of safe squares the pieces of the given
+    // side can move to, without looking up any bonuses.
+    pub fn mobility_squares(board: &Board, mg: &MoveGenerator, side: Side) -> usize {
+        let mut total = 0;
+        safe_squares(board, mg, side, |_, count| total += count);
+        total
+    }
+}
+
+// Counts the safe squares for each knight, bishop, rook and queen of the
+// given side, and passes the piece type and the count to "f".
+fn safe_squares<F>(board: &Board, mg: &MoveGenerator, side: Side, mut f: F)
+where
+    F: FnMut(Piece, usize),
+{
+    let safe = !board.bb_side[side] & !pawn_attacks(board, mg, side ^ 1);
+    let occupancy = board.occupancy();
+
+    for piece in [Pieces::QUEEN, Pieces::ROOK, Pieces::BISHOP, Pieces::KNIGHT] {
+        let mut bb_pieces = board.get_pieces(piece, side);
+
+        while bb_pieces > 0 {
+            let square = bits::next(&mut bb_pieces);
+            let attacks = if piece == Pieces::KNIGHT {
+                mg.get_non_slider_attacks(piece, square)
+            } else {
+                mg.get_slider_attacks(piece, square, occupancy)
+            };
+
+            f(piece, (attacks & safe).count_ones() as usize);
+        }
+    }
+}
+
+// All the squares attacked by the pawns of the given side.
+fn pawn_attacks(board: &Board, mg: &MoveGenerator, side: Side) -> Bitboard {
+    let mut pawns = board.get_pieces(Pieces::PAWN, side);
+    let mut attacks = 0;
+
+    while pawns > 0 {
+        let square = bits::next(&mut pawns);
+        attacks |= mg.get_pawn_attacks(side, square);
+    }
+
+    attacks
+}
+
+// Looks up the mobility bonus for a piece type and number of safe squares.
+fn bonus(piece: Piece, count: usize, params: &EvalParams) -> (i16, i16) {
+    let p = params;
+    match piece {
+        Pieces::QUEEN => (p.queen_mobility_mg[count], p.queen_mobility_eg[count]),
+        Pieces::ROOK => (p.rook_mobility_mg[count], p.rook_mobility_eg[count]),
+        Pieces::BISHOP => (p.bishop_mobility_mg[count], p.bishop_mobility_eg[count]),
+        Pieces::KNIGHT => (p.knight_mobility_mg[count], p.knight_mobility_eg[count]),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    #[test]
+    fn safe_squares_are_counted() {
+        let mg = MoveGenerator::shared();
+        let cases = [
+            // A bishop in the center of an empty board.
+            ("4k3/8/8/8/3B4/8/8/4K3 w - - 0 1", 13),
+            // The enemy pawn on e7 attacks f6.
+            ("4k3/4p3/8/8/3B4/8/8/4K3 w - - 0 1", 12),
+            // A bishop buried behind its own pawns.
+            ("4k3/8/8/8/8/8/1P1P4/2B1K3 w - - 0 1", 0),
+            // Knights in the corner and in the center, and a rook that is
+            // blocked by its own king.
+            ("4k3/8/8/8/4N3/8/8/N3K2R w - - 0 1", 2 + 8 + 2 + 7),
+        ];
+
+        for (fen, squares) in cases.iter() {
+            let board = board(fen);
+            let count = Evaluation::mobility_squares(&board, &mg, Sides::WHITE);
+            assert_eq!(count, *squares, "{}", fen);
+        }
+    }
+
+    // An open bishop is better than a bad one, for either side.
+    #[test]
+    fn open_bishop_beats_buried_bishop() {
+        let mg = MoveGenerator::shared();
+        let params = &EvalParams::DEFAULT;
+        let fen = "2b1k3/1p1p4/8/8/3B4/8/8/4K3 w - - 0 1";
+        let score = Evaluation::mobility(&board(fen), &mg, params);
+        assert!(score > 0, "{}", fen);
+
+        let mirrored = "4k3/8/8/3b4/8/8/1P1P4/2B1K3 b - - 0 1";
+        assert_eq!(Evaluation::mobility(&board(mirrored), &mg, params), -score);
+
+        // In the middlegame, it's the same.
+        let fen = "rnbqkbnr/pppppppp/8/8/3B4/8/PPP1PPPP/RN1QKBNR w KQkq - 0 1";
+        assert!(Evaluation::mobility(&board(fen), &mg, params) > 0);
+    }
+}
@@ -0,0 +1,74 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file collects the tunable evaluation parameters into one struct,
+// so they can be passed into the evaluation instead of being read from
+// constants. The engine always uses EvalParams::DEFAULT, which is built
+// from the constants in defs.rs and the PST's; the tuner (in the "extra"
+// module) evaluates positions with modified copies. Tuned values are put
+// back into the constants by hand.
+
+use super::defs::{KingSafety, Mobility, PawnStorm, PawnStructure, PstCollection, PST_EG, PST_MG};
+
+#[derive(Clone, PartialEq)]
+pub struct EvalParams {
+    pub pst_mg: PstCollection,
+    pub pst_eg: PstCollection,
+    pub doubled: [i16; 2], // Middlegame, endgame
+    pub isolated: [i16; 2],
+    pub passed_mg: [i16; 8],
+    pub passed_eg: [i16; 8],
+    pub shield_rank: [i16; 8],
+    pub shield_missing: i16,
+    pub storm_rank: [i16; 8],
+    pub knight_mobility_mg: [i16; 9],
+    pub knight_mobility_eg: [i16; 9],
+    pub bishop_mobility_mg: [i16; 14],
+    pub bishop_mobility_eg: [i16; 14],
+    pub rook_mobility_mg: [i16; 15],
+    pub rook_mobility_eg: [i16; 15],
+    pub queen_mobility_mg: [i16; 28],
+    pub queen_mobility_eg: [i16; 28],
+}
+
+impl EvalParams {
+    pub const DEFAULT: EvalParams = EvalParams {
+        pst_mg: PST_MG,
+        pst_eg: PST_EG,
+        doubled: [PawnStructure::DOUBLED.0, PawnStructure::DOUBLED.1],
+        isolated: [PawnStructure::ISOLATED.0, PawnStructure::ISOLATED.1],
+        passed_mg: PawnStructure::PASSED_MG,
+        passed_eg: PawnStructure::PASSED_EG,
+        shield_rank: KingSafety::SHIELD_RANK,
+        shield_missing: KingSafety::SHIELD_MISSING,
+        storm_rank: PawnStorm::RANK_BONUS,
+        knight_mobility_mg: Mobility::KNIGHT_MG,
+        knight_mobility_eg: Mobility::KNIGHT_EG,
+        bishop_mobility_mg: Mobility::BISHOP_MG,
+        bishop_mobility_eg: Mobility::BISHOP_EG,
+        rook_mobility_mg: Mobility::ROOK_MG,
+        rook_mobility_eg: Mobility::ROOK_EG,
+        queen_mobility_mg: Mobility::QUEEN_MG,
+        queen_mobility_eg: Mobility::QUEEN_EG,
+    };
+}
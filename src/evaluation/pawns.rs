@@ -0,0 +1,287 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements the pawn structure terms: doubled, isolated and
+// passed pawns. The pawn structure only changes when a pawn moves or is
+// captured, so the result is cached in a small pawn hash table, keyed by
+// the pawn-only zobrist key. The table stores the middlegame and endgame
+// scores separately; they are tapered by the game phase after the lookup,
+// because the phase depends on the pieces and not on the pawns.
+
+use super::{
+    defs::{
+        EvalParams, BB_ADJACENT_FILES, BB_PASSED_SPAN, PAWN_TABLE_ENTRIES, PHASE_MAX, PHASE_MIN,
+    },
+    Evaluation,
+};
+use crate::{
+    board::{
+        defs::{Pieces, ZobristKey, BB_FILES},
+        Board,
+    },
+    defs::{NrOf, Side, Sides},
+    misc::bits,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+struct PawnEntry {
+    key: ZobristKey,
+    mg: i16,
+    eg: i16,
+}
+
+// Each search thread keeps its own pawn hash table, so it doesn't need
+// any locking. Entries are always replaced on a miss.
+#[derive(PartialEq)]
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        let empty = PawnEntry {
+            key: 0,
+            mg: 0,
+            eg: 0,
+        };
+
+        Self {
+            entries: vec![empty; PAWN_TABLE_ENTRIES],
+        }
+    }
+
+    // Returns the (middlegame, endgame) pawn structure score from white's
+    // point of view. The key 0 belongs to positions without pawns, which
+    // score 0; this is also what empty entries hold.
+    fn probe(&mut self, board: &Board) -> (i16, i16) {
+        let key = board.game_state.pawn_key;
+        let index = (key as usize) & (PAWN_TABLE_ENTRIES - 1);
+        let entry = &mut self.entries[index];
+
+        if entry.key != key {
+            let (mg, eg) = Evaluation::pawn_structure_raw(board, &EvalParams::DEFAULT);
+            *entry = PawnEntry { key, mg, eg };
+        }
+
+        (entry.mg, entry.eg)
+    }
+}
+
+impl Evaluation {
+    // Returns the pawn structure score from white's point of view.
+    pub fn pawn_structure(board: &Board, pawn_table: &mut PawnTable) -> i16 {
+        let scores = pawn_table.probe(board);
+
+        Evaluation::pawn_taper(board, scores)
+    }
+
+    // Tapers a (middlegame, endgame) pawn structure score by the phase.
+    pub fn pawn_taper(board: &Board, (mg, eg): (i16, i16)) -> i16 {
+        let v = board.game_state.phase_value;
+        let phase = Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+
+        (mg as f32 * phase + eg as f32 * (1.0 - phase)).round() as i16
+    }
+
+    // Computes the pawn structure score from scratch, as a (middlegame,
+    // endgame) pair from white's point of view.
+    pub fn pawn_structure_raw(board: &Board, params: &EvalParams) -> (i16, i16) {
+        let (w_mg, w_eg) = Evaluation::pawn_side(board, Sides::WHITE, params);
+        let (b_mg, b_eg) = Evaluation::pawn_side(board, Sides::BLACK, params);
+
+        (w_mg - b_mg, w_eg - b_eg)
+    }
+
+    // Returns the (middlegame, endgame) pawn structure score for one side.
+    pub fn pawn_side(board: &Board, side: Side, params: &EvalParams) -> (i16, i16) {
+        let own = board.get_pieces(Pieces::PAWN, side);
+        let enemy = board.get_pieces(Pieces::PAWN, side ^ 1);
+        let mut mg = 0;
+        let mut eg = 0;
+
+        // Doubled pawns: every pawn beyond the first on a file.
+        for bb_file in BB_FILES.iter() {
+            let count = (own & bb_file).count_ones() as i16;
+            if count > 1 {
+                mg += params.doubled[0] * (count - 1);
+                eg += params.doubled[1] * (count - 1);
+            }
+        }
+
+        let mut pawns = own;
+        while pawns > 0 {
+            let square = bits::next(&mut pawns);
+            let (file, rank) = Board::square_on_file_rank(square);
+            let file = file as usize;
+
+            // Isolated: no friendly pawns on the files next to this one.
+            if own & BB_ADJACENT_FILES[file] == 0 {
+                mg += params.isolated[0];
+                eg += params.isolated[1];
+            }
+
+            // Passed: no enemy pawns in front, on this file or the files
+            // next to it. Of doubled pawns, only the front one counts.
+            let span = BB_PASSED_SPAN[side][square];
+            if enemy & span == 0 && own & span & BB_FILES[file] == 0 {
+                // Ranks are counted from the pawn's own back rank.
+                let rank = if side == Sides::WHITE {
+                    rank as usize
+                } else {
+                    NrOf::RANKS - 1 - rank as usize
+                };
+
+                mg += params.passed_mg[rank];
+                eg += params.passed_eg[rank];
+            }
+        }
+
+        (mg, eg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluation::defs::PawnStructure, movegen::MoveGenerator};
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    fn raw(fen: &str) -> (i16, i16) {
+        Evaluation::pawn_structure_raw(&board(fen), &EvalParams::DEFAULT)
+    }
+
+    #[test]
+    fn terms_of_known_structures() {
+        let (doubled, isolated) = (PawnStructure::DOUBLED, PawnStructure::ISOLATED);
+        let (passed_mg, passed_eg) = (PawnStructure::PASSED_MG, PawnStructure::PASSED_EG);
+
+        // Tripled, isolated pawns. Only the front one is passed.
+        assert_eq!(
+            raw("4k3/8/8/8/4P3/4P3/4P3/4K3 w - - 0 1"),
+            (
+                2 * doubled.0 + 3 * isolated.0 + passed_mg[3],
+                2 * doubled.1 + 3 * isolated.1 + passed_eg[3]
+            )
+        );
+
+        // A protected passer on the sixth rank. Its protector is stopped
+        // by the black pawn on f7, which is isolated.
+        assert_eq!(
+            raw("4k3/5p2/3P4/4P3/8/8/8/4K3 w - - 0 1"),
+            (passed_mg[5] - isolated.0, passed_eg[5] - isolated.1)
+        );
+
+        // The same for black, mirrored.
+        assert_eq!(
+            raw("4k3/4p3/4p3/4p3/8/8/8/4K3 b - - 0 1"),
+            (
+                -(2 * doubled.0 + 3 * isolated.0 + passed_mg[3]),
+                -(2 * doubled.1 + 3 * isolated.1 + passed_eg[3])
+            )
+        );
+
+        // Pawns facing each other on the same and the next files are not
+        // passed, and a pawn chain has no weaknesses.
+        assert_eq!(raw("4k3/8/3ppp2/3PPP2/8/8/8/4K3 w - - 0 1"), (0, 0));
+    }
+
+    // Pairs of positions, of which white's pawns are better in the first.
+    #[test]
+    fn better_structures_score_higher() {
+        let pairs = [
+            // Passed pawn further up the board.
+            (
+                "4k3/8/3P4/8/8/8/8/4K3 w - - 0 1",
+                "4k3/8/8/8/8/3P4/8/4K3 w - - 0 1",
+            ),
+            // Connected instead of doubled.
+            (
+                "4k3/8/8/8/8/3PP3/8/4K3 w - - 0 1",
+                "4k3/8/8/8/4P3/4P3/8/4K3 w - - 0 1",
+            ),
+            // Not isolated.
+            (
+                "4k3/pp6/8/8/8/8/PP6/4K3 w - - 0 1",
+                "4k3/pp6/8/8/8/8/P1P5/4K3 w - - 0 1",
+            ),
+        ];
+
+        for (better, worse) in pairs.iter() {
+            let (better, worse) = (raw(better), raw(worse));
+            assert!(
+                better.0 > worse.0 && better.1 > worse.1,
+                "{:?} {:?}",
+                better,
+                worse
+            );
+        }
+    }
+
+    // The pawn key only depends on the pawns: it matches the key of the
+    // same position set up from scratch, and it comes back after unmake.
+    // Positions with the same pawns share a cached score.
+    #[test]
+    fn pawn_key_follows_the_pawns() {
+        let mg = MoveGenerator::shared();
+        let mut board =
+            board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let start = board.game_state.pawn_key;
+
+        for m in ["e5c6", "d7c6", "a2a4", "b4a3", "e1g1"].iter() {
+            let m = board
+                .legal_moves_iter(&mg)
+                .find(|l| l.as_string() == *m)
+                .expect(m);
+            let before = board.game_state.pawn_key;
+            board.make(m, &mg);
+
+            let key = board.game_state.pawn_key;
+            assert_eq!(key, self::board(&board.fen_write()).game_state.pawn_key);
+            let moves_pawns = m.piece() == Pieces::PAWN || m.captured() == Pieces::PAWN;
+            assert_eq!(key != before, moves_pawns, "{}", m.as_string());
+        }
+
+        while board.unmake() {}
+        assert_eq!(board.game_state.pawn_key, start);
+
+        let mut table = PawnTable::new();
+        let fens = [
+            "4k3/5p2/3P4/4P3/8/8/8/4K3 w - - 0 1",
+            "3qk3/5p2/3P4/4P3/8/8/8/3QK3 w - - 0 1",
+        ];
+        for fen in fens.iter() {
+            let board = self::board(fen);
+            let expected = Evaluation::pawn_taper(&board, raw(fen));
+            assert_eq!(Evaluation::pawn_structure(&board, &mut table), expected);
+            assert_eq!(
+                board.game_state.pawn_key,
+                self::board(fens[0]).game_state.pawn_key
+            );
+        }
+    }
+}
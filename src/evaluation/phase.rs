@@ -61,3 +61,108 @@ impl Evaluation {
         f32::min(1.0, f32::max(0.0, result))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        board::{defs::Pieces, Board},
+        evaluation::{
+            defs::{PHASE_MAX, PHASE_MIN},
+            Evaluation,
+        },
+        misc::random::EngineRandom,
+        movegen::{defs::Move, MoveGenerator},
+    };
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    #[test]
+    fn phase_of_known_positions() {
+        let positions = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                24,
+            ),
+            ("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1", 0),
+            ("8/8/4k3/8/3P4/4K3/8/8 w - - 0 1", 0),
+            ("4k3/8/8/8/8/8/8/R2QK3 w - - 0 1", 6),
+            ("1n2k3/8/8/8/8/8/8/2B1K3 w - - 0 1", 2),
+        ];
+
+        for (fen, phase) in positions.iter() {
+            let board = board(fen);
+            assert_eq!(Evaluation::count_phase(&board), *phase, "{}", fen);
+            assert_eq!(board.game_state.phase_value, *phase, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn phase_is_clamped() {
+        // Promotions can push the phase value above 24: here, white has
+        // five extra queens.
+        let board = board("rnbqkbnr/pppppppp/8/8/8/8/QQQQQPPP/RNBQKBNR w KQkq - 0 1");
+        let v = board.game_state.phase_value;
+        assert_eq!(v, 44);
+
+        let phase = |v| Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+        assert!((phase(v) - 1.0).abs() < f32::EPSILON);
+        assert!((phase(12) - 0.5).abs() < f32::EPSILON);
+        assert!(phase(0).abs() < f32::EPSILON);
+    }
+
+    // Plays random games, preferring captures and promotions because
+    // those change the phase. After every make and unmake, the phase that
+    // is kept up to date incrementally must be the one counted from
+    // scratch.
+    #[test]
+    fn incremental_phase_matches_a_recount() {
+        let mg = MoveGenerator::shared();
+        let mut random = EngineRandom::new(Some(1523));
+
+        for _ in 0..40 {
+            let mut board =
+                board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+            for _ in 0..120 {
+                let moves: Vec<Move> = board.legal_moves_iter(&mg).collect();
+                if moves.is_empty() {
+                    break;
+                }
+                let forcing: Vec<Move> = moves
+                    .iter()
+                    .copied()
+                    .filter(|m| m.captured() != Pieces::NONE || m.promoted() != Pieces::NONE)
+                    .collect();
+                let m = if !forcing.is_empty() && random.below(3) > 0 {
+                    forcing[random.below(forcing.len())]
+                } else {
+                    moves[random.below(moves.len())]
+                };
+
+                assert!(board.make(m, &mg));
+                assert_eq!(
+                    board.game_state.phase_value,
+                    Evaluation::count_phase(&board)
+                );
+
+                // Take the move back and play it again.
+                assert!(board.unmake());
+                assert_eq!(
+                    board.game_state.phase_value,
+                    Evaluation::count_phase(&board)
+                );
+                assert!(board.make(m, &mg));
+            }
+
+            while board.unmake() {
+                assert_eq!(
+                    board.game_state.phase_value,
+                    Evaluation::count_phase(&board)
+                );
+            }
+        }
+    }
+}
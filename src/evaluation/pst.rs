@@ -36,7 +36,7 @@ use crate::{
 };
 
 type Pst = [i16; NrOf::SQUARES];
-type PstCollection = [Pst; NrOf::PIECE_TYPES];
+pub type PstCollection = [Pst; NrOf::PIECE_TYPES];
 
 // === MG Piece-Square Tables ===
 
@@ -301,10 +301,18 @@ impl Evaluation {
     // and endgame.
     pub fn pst_score(board: &Board) -> i16 {
         // Get current PST values. These are kept incrementally during play.
-        let pst_w_mg = board.game_state.pst_mg[Sides::WHITE] as f32;
-        let pst_b_mg = board.game_state.pst_mg[Sides::BLACK] as f32;
-        let pst_w_eg = board.game_state.pst_eg[Sides::WHITE] as f32;
-        let pst_b_eg = board.game_state.pst_eg[Sides::BLACK] as f32;
+        let gs = &board.game_state;
+        let mg = (gs.pst_mg[Sides::WHITE], gs.pst_mg[Sides::BLACK]);
+        let eg = (gs.pst_eg[Sides::WHITE], gs.pst_eg[Sides::BLACK]);
+
+        Evaluation::pst_taper(board, mg, eg)
+    }
+
+    // Tapers the given (white, black) middlegame and endgame PST values by
+    // the game phase, and returns the score from white's point of view.
+    pub fn pst_taper(board: &Board, mg: (i16, i16), eg: (i16, i16)) -> i16 {
+        let (pst_w_mg, pst_b_mg) = (mg.0 as f32, mg.1 as f32);
+        let (pst_w_eg, pst_b_eg) = (eg.0 as f32, eg.1 as f32);
 
         // Get the game phase, from 1 (opening/midgame) to 0 (endgame)
         let v = board.game_state.phase_value;
@@ -0,0 +1,219 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements endgame scaling. Some pawnless endings, such as
+// rook versus rook, are practically drawn even though one side may have
+// a nominal advantage in the evaluation. In those endings, the score is
+// scaled down toward zero, so the engine doesn't burn time trying to win
+// them, and doesn't avoid simplifying into them when it is behind.
+
+use super::{defs::EndgameScale, Evaluation};
+use crate::{
+    board::{defs::Pieces, Board},
+    defs::{Side, Sides},
+};
+
+// Number of pieces of each type for one side, except king and pawns.
+#[derive(PartialEq, Copy, Clone)]
+struct PieceCount {
+    queens: u8,
+    rooks: u8,
+    bishops: u8,
+    knights: u8,
+}
+
+impl PieceCount {
+    fn new(board: &Board, side: Side) -> Self {
+        Self {
+            queens: board.piece_count(side, Pieces::QUEEN),
+            rooks: board.piece_count(side, Pieces::ROOK),
+            bishops: board.piece_count(side, Pieces::BISHOP),
+            knights: board.piece_count(side, Pieces::KNIGHT),
+        }
+    }
+
+    fn minors(&self) -> u8 {
+        self.bishops + self.knights
+    }
+
+    fn majors(&self) -> u8 {
+        self.queens + self.rooks
+    }
+
+    // Rough material value, only used to find out which side is stronger.
+    fn material(&self) -> u32 {
+        u32::from(self.queens) * 9 + u32::from(self.rooks) * 5 + u32::from(self.minors()) * 3
+    }
+}
+
+impl Evaluation {
+    // Returns the scale factor for the given position. The evaluation is
+    // multiplied by this factor and then divided by EndgameScale::NORMAL.
+    pub fn endgame_scale(board: &Board) -> i32 {
+        // Only pawnless endings are scaled.
+        if !board.is_pawnless() {
+            return EndgameScale::NORMAL;
+        }
+
+        let white = PieceCount::new(board, Sides::WHITE);
+        let black = PieceCount::new(board, Sides::BLACK);
+        let white_is_strong = white.material() >= black.material();
+        let (strong, weak, strong_side) = if white_is_strong {
+            (white, black, Sides::WHITE)
+        } else {
+            (black, white, Sides::BLACK)
+        };
+
+        // The stronger side can't force mate: only one minor piece, two
+        // knights, or bishops on squares of the same color.
+        if !board.has_sufficient_material(strong_side) {
+            return EndgameScale::NO_MATING_MATERIAL;
+        }
+
+        // Both sides have exactly the same pieces: R vs R, Q vs Q...
+        if strong == weak {
+            return EndgameScale::EQUAL_PIECES;
+        }
+
+        // The stronger side is only one minor piece up over the same
+        // major pieces: R+B vs R, R+N vs R, Q+B vs Q...
+        let same_majors = strong.queens == weak.queens && strong.rooks == weak.rooks;
+        if same_majors && weak.majors() > 0 && strong.minors() == weak.minors() + 1 {
+            return EndgameScale::MINOR_UP;
+        }
+
+        // A lone rook against a lone minor piece.
+        let lone_rook = strong.queens == 0 && strong.rooks == 1 && strong.minors() == 0;
+        if lone_rook && weak.majors() == 0 && weak.minors() == 1 {
+            return EndgameScale::ROOK_VS_MINOR;
+        }
+
+        EndgameScale::NORMAL
+    }
+
+    // Apply the endgame scale factor to the given evaluation.
+    pub fn scale_endgame(board: &Board, value: i16) -> i16 {
+        let scale = Evaluation::endgame_scale(board);
+
+        if scale == EndgameScale::NORMAL {
+            value
+        } else {
+            ((value as i32 * scale) / EndgameScale::NORMAL) as i16
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::MoveGenerator;
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    #[test]
+    fn drawish_endings_are_scaled() {
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/8/R3K2r w - - 0 1",
+                EndgameScale::EQUAL_PIECES,
+            ),
+            (
+                "3qk3/8/8/8/8/8/8/3QK3 w - - 0 1",
+                EndgameScale::EQUAL_PIECES,
+            ),
+            (
+                "2b1k2r/8/8/8/8/8/8/R1B1K3 w - - 0 1",
+                EndgameScale::EQUAL_PIECES,
+            ),
+            ("4k2r/8/8/8/8/8/8/R1B1K3 w - - 0 1", EndgameScale::MINOR_UP),
+            ("4k2r/8/8/8/8/8/8/R3KN2 w - - 0 1", EndgameScale::MINOR_UP),
+            ("3qk1n1/8/8/8/8/8/8/3QK3 b - - 0 1", EndgameScale::MINOR_UP),
+            (
+                "4k1n1/8/8/8/8/8/8/R3K3 w - - 0 1",
+                EndgameScale::ROOK_VS_MINOR,
+            ),
+            (
+                "4k3/8/8/8/8/8/7r/2B1K3 b - - 0 1",
+                EndgameScale::ROOK_VS_MINOR,
+            ),
+            (
+                "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+                EndgameScale::NO_MATING_MATERIAL,
+            ),
+            (
+                "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+                EndgameScale::NO_MATING_MATERIAL,
+            ),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", EndgameScale::NORMAL),
+            ("4k2r/8/8/8/8/8/8/Q3K3 w - - 0 1", EndgameScale::NORMAL),
+            ("4k2r/8/8/8/8/8/8/R1B1KN2 w - - 0 1", EndgameScale::NORMAL),
+        ];
+
+        for (fen, scale) in cases.iter() {
+            assert_eq!(Evaluation::endgame_scale(&board(fen)), *scale, "{}", fen);
+        }
+    }
+
+    // With pawns on the board, the same endings are played out.
+    #[test]
+    fn endings_with_pawns_are_not_scaled() {
+        let fens = [
+            "4k3/8/8/8/8/8/P7/R3K2r w - - 0 1",
+            "4k2r/p7/8/8/8/8/8/R1B1K3 w - - 0 1",
+            "4k1n1/8/8/8/8/8/7P/R3K3 w - - 0 1",
+            "4k3/8/8/8/8/8/1P6/2B1K3 w - - 0 1",
+        ];
+
+        for fen in fens.iter() {
+            let board = board(fen);
+            assert_eq!(Evaluation::endgame_scale(&board), EndgameScale::NORMAL);
+            assert_eq!(Evaluation::scale_endgame(&board, 150), 150, "{}", fen);
+        }
+    }
+
+    // Scaling moves the evaluation toward zero, without changing its sign.
+    #[test]
+    fn scaled_evaluations_move_toward_zero() {
+        let mg = MoveGenerator::shared();
+        let fens = [
+            "4k2r/8/8/8/8/8/8/R1B1K3 w - - 0 1",
+            "4k1n1/8/8/8/8/8/8/R3K3 w - - 0 1",
+            "4k3/8/8/8/8/8/7r/2B1K3 b - - 0 1",
+        ];
+
+        for fen in fens.iter() {
+            let board = board(fen);
+            let trace = Evaluation::evaluate_position_trace(&board, &mg);
+            let unscaled = trace.terms.iter().fold(0, |sum, term| sum + term.total);
+            let scaled = Evaluation::scale_endgame(&board, unscaled);
+
+            assert_ne!(unscaled, 0, "{}", fen);
+            assert!(scaled.abs() < unscaled.abs(), "{}", fen);
+            assert_eq!(scaled.signum(), unscaled.signum(), "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements the evaluation trace: a breakdown of the evaluation
+// into its terms, for white and black, used by the "eval" console command.
+// The total of each term is computed by the same function the evaluation
+// uses, so the trace always adds up to the evaluation itself. The white
+// and black columns are only for display; they are rounded separately and
+// may not add up to the total exactly.
+
+use super::{
+    defs::{EvalParams, PawnTable, MOBILITY_ENABLED, PHASE_MAX, PHASE_MIN},
+    Evaluation,
+};
+use crate::{
+    board::Board,
+    defs::{Side, Sides},
+    movegen::MoveGenerator,
+};
+
+// One term of the evaluation, and its contribution to the total.
+pub struct EvalTerm {
+    pub name: &'static str,
+    pub white: i16,
+    pub black: i16,
+    pub total: i16, // White's point of view
+}
+
+pub struct EvalTrace {
+    pub terms: Vec<EvalTerm>,
+    pub phase_value: i16,
+    pub scale: i32,         // Endgame scale factor
+    pub total: i16,         // White's point of view, after scaling
+    pub side_to_move: Side, // The evaluation is for this side
+}
+
+impl EvalTrace {
+    // The evaluation from the point of view of the side to move, which is
+    // what evaluate_position() returns.
+    pub fn score(&self) -> i16 {
+        if self.side_to_move == Sides::BLACK {
+            -self.total
+        } else {
+            self.total
+        }
+    }
+}
+
+impl Evaluation {
+    pub fn evaluate_position_trace(board: &Board, mg: &MoveGenerator) -> EvalTrace {
+        let v = board.game_state.phase_value;
+        let phase = Evaluation::determine_phase(PHASE_MIN, PHASE_MAX, v);
+        let weigh = |x: i16| (x as f32 * phase).round() as i16;
+        let taper = |(x_mg, x_eg): (i16, i16)| {
+            (x_mg as f32 * phase + x_eg as f32 * (1.0 - phase)).round() as i16
+        };
+        let params = &EvalParams::DEFAULT;
+        let mut terms = Vec::new();
+
+        let gs = &board.game_state;
+        terms.push(EvalTerm {
+            name: "Material + PST",
+            white: taper((gs.pst_mg[Sides::WHITE], gs.pst_eg[Sides::WHITE])),
+            black: taper((gs.pst_mg[Sides::BLACK], gs.pst_eg[Sides::BLACK])),
+            total: Evaluation::pst_score(board),
+        });
+
+        let (w_shield, w_attack) = Evaluation::king_safety_side(board, mg, Sides::WHITE, params);
+        let (b_shield, b_attack) = Evaluation::king_safety_side(board, mg, Sides::BLACK, params);
+        let (shield, attack) = Evaluation::king_safety_parts(board, mg, params);
+        terms.push(EvalTerm {
+            name: "King pawn shield",
+            white: weigh(w_shield),
+            black: weigh(b_shield),
+            total: shield,
+        });
+        terms.push(EvalTerm {
+            name: "King attack",
+            white: weigh(w_attack),
+            black: weigh(b_attack),
+            total: attack,
+        });
+
+        let (w_storm, b_storm) = Evaluation::pawn_storm_sides(board, params);
+        terms.push(EvalTerm {
+            name: "Pawn storm",
+            white: weigh(w_storm),
+            black: weigh(b_storm),
+            total: Evaluation::pawn_storm(board, params),
+        });
+
+        if MOBILITY_ENABLED {
+            terms.push(EvalTerm {
+                name: "Mobility",
+                white: taper(Evaluation::mobility_side(board, mg, Sides::WHITE, params)),
+                black: taper(Evaluation::mobility_side(board, mg, Sides::BLACK, params)),
+                total: Evaluation::mobility(board, mg, params),
+            });
+        }
+
+        terms.push(EvalTerm {
+            name: "Pawn structure",
+            white: taper(Evaluation::pawn_side(board, Sides::WHITE, params)),
+            black: taper(Evaluation::pawn_side(board, Sides::BLACK, params)),
+            total: Evaluation::pawn_structure(board, &mut PawnTable::new()),
+        });
+
+        // Add up the terms in the same way as evaluate_position() does.
+        let value = terms.iter().fold(0, |sum, term| sum + term.total);
+
+        EvalTrace {
+            terms,
+            phase_value: v,
+            scale: Evaluation::endgame_scale(board),
+            total: Evaluation::scale_endgame(board, value),
+            side_to_move: board.us(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::{FEN_KIWIPETE_POSITION, FEN_START_POSITION};
+
+    // The trace adds up to the evaluation, for either side to move, in
+    // the middlegame and in endings that are scaled down.
+    #[test]
+    fn trace_adds_up_to_the_evaluation() {
+        let mg = MoveGenerator::shared();
+        let fens = [
+            FEN_START_POSITION,
+            FEN_KIWIPETE_POSITION,
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R b KQ - 3 8",
+            "6k1/5ppp/8/8/8/8/1R3PPP/6K1 w - - 0 40",
+            "8/5k2/8/4b3/8/3B4/2P5/4K3 b - - 0 50",
+            "8/8/4k3/8/8/3NK3/8/8 w - - 0 60",
+            "2kr3r/ppp2ppp/8/8/8/8/PPP2PPP/2KR3R w - - 0 20",
+        ];
+
+        for fen in fens.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let trace = Evaluation::evaluate_position_trace(&board, &mg);
+            let score = Evaluation::evaluate_position(&board, &mg, &mut PawnTable::new());
+
+            let sum = trace.terms.iter().fold(0, |sum, term| sum + term.total);
+            assert_eq!(
+                trace.total,
+                Evaluation::scale_endgame(&board, sum),
+                "{}",
+                fen
+            );
+            assert_eq!(trace.score(), score.value(), "{}", fen);
+        }
+    }
+}
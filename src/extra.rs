@@ -21,6 +21,12 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
+pub mod epd;
 pub mod epds;
+pub mod epdtest;
+pub mod r#match;
+pub mod perftsuite;
+pub mod selfplay;
 pub mod testsuite;
+pub mod tuner;
 pub mod wizardry;
@@ -0,0 +1,217 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file parses EPD (Extended Position Description) records. A record
+// holds the first four fields of a FEN-string, followed by operations,
+// each ending with a semicolon:
+//
+// <placement> <side> <castling> <ep> bm Qg6; am Qxa7; id "WAC.001";
+//
+// An operation has an opcode and zero or more operands. Operands in
+// double quotes are kept together, even if they contain spaces or
+// semicolons. Many EPD files in the wild use a full six-part FEN-string;
+// the move counters are accepted as well. The parser doesn't know what
+// the opcodes mean, so any tool reading EPD files can use it.
+
+const SEMI_COLON: char = ';';
+const QUOTE: char = '"';
+const EPD_FEN_PARTS: usize = 4;
+
+pub struct EpdOperation {
+    pub opcode: String,
+    pub operands: Vec<String>,
+}
+
+pub struct EpdRecord {
+    pub fen: String,
+    pub operations: Vec<EpdOperation>,
+}
+
+impl EpdRecord {
+    // Returns the operands of the first operation with the given opcode.
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|o| o.opcode == opcode)
+            .map(|o| &o.operands[..])
+    }
+
+    // Returns the first operand of the given opcode, such as the name
+    // given by "id".
+    pub fn operand(&self, opcode: &str) -> Option<&str> {
+        self.operands(opcode)
+            .and_then(|o| o.first())
+            .map(|o| o.as_str())
+    }
+}
+
+// Parses one EPD record.
+pub fn parse_line(line: &str) -> Result<EpdRecord, String> {
+    let line = line.trim();
+    let mut fields: Vec<&str> = Vec::new();
+    let mut rest = line;
+
+    // Split off the position fields, and the move counters if present.
+    while let Some((field, remainder)) = split_field(rest) {
+        let is_counter = field.parse::<u16>().is_ok();
+        let counters_done = fields.len() == EPD_FEN_PARTS + 2;
+        if counters_done || (fields.len() >= EPD_FEN_PARTS && !is_counter) {
+            break;
+        }
+        fields.push(field);
+        rest = remainder;
+    }
+
+    if fields.len() < EPD_FEN_PARTS {
+        return Err(format!("Invalid position: '{}'", line));
+    }
+
+    // Without both move counters, the position is a short FEN-string.
+    if fields.len() == EPD_FEN_PARTS + 1 {
+        return Err(format!("Incomplete move counters: '{}'", line));
+    }
+
+    let fen = fields.join(" ");
+    let operations = parse_operations(rest)?;
+
+    Ok(EpdRecord { fen, operations })
+}
+
+// Returns the first field of the text, and the text after it. A field
+// directly followed by a semicolon is not a position field, but an
+// operation without operands.
+fn split_field(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let field = &text[..end];
+
+    if field.is_empty() || field.contains(SEMI_COLON) {
+        return None;
+    }
+
+    Some((field, &text[end..]))
+}
+
+// Splits the text into operations. Semicolons and spaces within double
+// quotes are part of the operand.
+fn parse_operations(text: &str) -> Result<Vec<EpdOperation>, String> {
+    let mut operations: Vec<EpdOperation> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in text.chars() {
+        match c {
+            QUOTE => {
+                quoted = !quoted;
+                if !quoted {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c if quoted => word.push(c),
+            c if c == SEMI_COLON || c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if c == SEMI_COLON {
+                    operations.push(new_operation(std::mem::take(&mut words))?);
+                }
+            }
+            c => word.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(String::from("Unterminated quoted operand."));
+    }
+
+    // Be lenient about a missing semicolon after the last operation.
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if !words.is_empty() {
+        operations.push(new_operation(words)?);
+    }
+
+    Ok(operations)
+}
+
+fn new_operation(mut words: Vec<String>) -> Result<EpdOperation, String> {
+    if words.is_empty() {
+        return Err(String::from("Empty operation."));
+    }
+
+    let opcode = words.remove(0);
+    Ok(EpdOperation {
+        opcode,
+        operands: words,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_are_parsed() {
+        let record = parse_line(
+            r#"7k/p7/1R5K/6r1/6p1/6P1/8/8 w - - bm Rb7 Rb8+; am Rxa7; id "WAC 006; rook";"#,
+        )
+        .expect("valid");
+
+        assert_eq!(record.fen, "7k/p7/1R5K/6r1/6p1/6P1/8/8 w - -");
+        assert_eq!(record.operations.len(), 3);
+        assert_eq!(record.operands("bm").expect("bm"), ["Rb7", "Rb8+"]);
+        assert_eq!(record.operand("am"), Some("Rxa7"));
+        assert_eq!(record.operand("id"), Some("WAC 006; rook"));
+        assert!(record.operands("ce").is_none());
+    }
+
+    // The move counters are part of the position, and an operation
+    // without operands or a final semicolon is accepted.
+    #[test]
+    fn counters_and_lenient_operations() {
+        let record = parse_line("8/8/8/8/8/8/8/K6k w - - 0 1 draw; bm Kb2").expect("valid");
+        assert_eq!(record.fen, "8/8/8/8/8/8/8/K6k w - - 0 1");
+        assert!(record.operands("draw").expect("draw").is_empty());
+        assert_eq!(record.operand("bm"), Some("Kb2"));
+
+        let record = parse_line("8/8/8/8/8/8/8/K6k w - -").expect("valid");
+        assert!(record.operations.is_empty());
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let invalid = [
+            "",
+            "8/8/8/8/8/8/8/K6k w; bm Kb2;",
+            "8/8/8/8/8/8/8/K6k w - - 0 bm Kb2;",
+            r#"8/8/8/8/8/8/8/K6k w - - id "WAC.001;"#,
+            "8/8/8/8/8/8/8/K6k w - - bm Kb2;;",
+        ];
+
+        for line in invalid.iter() {
+            assert!(parse_line(line).is_err(), "{}", line);
+        }
+    }
+}
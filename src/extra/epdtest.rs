@@ -0,0 +1,304 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file runs tactical test suites such as WAC (Win At Chess) or STS
+// (Strategic Test Suite) from an EPD file. Each position is searched to a
+// fixed depth or for a fixed time, and the best move is compared to the
+// "bm" (best move) and "am" (avoid move) operations of the record. The
+// moves in the file are in SAN. Both the expected moves and the move the
+// engine found are converted to the engine's own SAN before comparing
+// them, so a superfluous disambiguation or check sign in the file
+// doesn't make a position fail. Empty lines and lines starting with '#'
+// are skipped.
+
+use crate::{
+    board::Board,
+    engine::defs::{ErrFatal, Information, SearchData, TT},
+    extra::epd::{self, EpdRecord},
+    misc::{format, parse},
+    movegen::MoveGenerator,
+    search::{
+        defs::{SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchShared},
+        Search,
+    },
+};
+use std::{
+    fs,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+const COMMENT: char = '#';
+const BEST_MOVE: &str = "bm";
+const AVOID_MOVE: &str = "am";
+const ID: &str = "id";
+
+// Each position is searched either to a fixed depth, or for a fixed
+// number of milliseconds.
+#[derive(Copy, Clone)]
+pub enum EpdLimit {
+    Depth(i8),
+    MoveTime(u128),
+}
+
+// The outcome of running a single EPD record.
+enum EpdResult {
+    Pass(String),         // The move found
+    Fail(String, String), // The move found, and what was expected
+    Error(String),        // The record could not be set up
+}
+
+// Runs all the positions in the given EPD file.
+pub fn run(file: &str, mg: Arc<MoveGenerator>, tt: Arc<RwLock<TT<SearchData>>>, limit: EpdLimit) {
+    let contents = match fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Can't read EPD file '{}': {}", file, e);
+            return;
+        }
+    };
+
+    let tt = tt.read().expect(ErrFatal::LOCK);
+    let mut board = Board::new();
+    let mut passed = 0;
+    let mut total = 0;
+    let now = Instant::now();
+
+    match limit {
+        EpdLimit::Depth(d) => println!("Running EPD test: {} (depth {})", file, d),
+        EpdLimit::MoveTime(t) => println!("Running EPD test: {} ({} ms per move)", file, t),
+    }
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with(COMMENT) {
+            continue;
+        }
+
+        total += 1;
+        let record = epd::parse_line(line);
+        let name = match &record {
+            Ok(r) => r.operand(ID).map(|id| id.to_string()),
+            Err(_) => None,
+        }
+        .unwrap_or(format!("Line {}", i + 1));
+
+        let result = match record {
+            Ok(r) => run_record(&mut board, &r, &mg, &tt, limit),
+            Err(e) => EpdResult::Error(e),
+        };
+
+        match result {
+            EpdResult::Pass(found) => {
+                passed += 1;
+                println!("{}: OK ({})", name, found);
+            }
+            EpdResult::Fail(found, expected) => {
+                println!("{}: FAIL: found {}, expected {}", name, found, expected);
+            }
+            EpdResult::Error(e) => println!("{}: ERROR: {}", name, e),
+        }
+    }
+
+    println!();
+    println!("Score: {} / {}", passed, total);
+    println!("Total time spent: {} ms", now.elapsed().as_millis());
+}
+
+// Searches one position and compares the best move to the "bm" and "am"
+// operations. A record may list more than one move for each of them.
+fn run_record(
+    board: &mut Board,
+    record: &EpdRecord,
+    mg: &Arc<MoveGenerator>,
+    tt: &TT<SearchData>,
+    limit: EpdLimit,
+) -> EpdResult {
+    if board.fen_read(Some(&record.fen)).is_err() {
+        return EpdResult::Error(format!("Invalid FEN-string: {}", record.fen));
+    }
+
+    let best_moves = match expected_moves(board, mg, record, BEST_MOVE) {
+        Ok(moves) => moves,
+        Err(e) => return EpdResult::Error(e),
+    };
+    let avoid_moves = match expected_moves(board, mg, record, AVOID_MOVE) {
+        Ok(moves) => moves,
+        Err(e) => return EpdResult::Error(e),
+    };
+
+    if best_moves.is_none() && avoid_moves.is_none() {
+        return EpdResult::Error(String::from("No bm or am operation found."));
+    }
+
+    let found = search(board, mg, tt, limit);
+    let is_best = best_moves.as_ref().is_none_or(|bm| bm.contains(&found));
+    let is_avoided = avoid_moves.as_ref().is_some_and(|am| am.contains(&found));
+
+    if is_best && !is_avoided {
+        return EpdResult::Pass(found);
+    }
+
+    let expected = match (best_moves, avoid_moves) {
+        (Some(bm), _) if !is_best => format!("{} {}", BEST_MOVE, bm.join(" ")),
+        (_, Some(am)) => format!("not {} {}", AVOID_MOVE, am.join(" ")),
+        _ => String::new(),
+    };
+
+    EpdResult::Fail(found, expected)
+}
+
+// Returns the moves of the given operation in the engine's SAN, or None
+// if the record doesn't have the operation.
+fn expected_moves(
+    board: &Board,
+    mg: &MoveGenerator,
+    record: &EpdRecord,
+    opcode: &str,
+) -> Result<Option<Vec<String>>, String> {
+    let operands = match record.operands(opcode) {
+        Some(o) => o,
+        None => return Ok(None),
+    };
+
+    let mut moves: Vec<String> = Vec::new();
+    for san in operands.iter() {
+        match parse::san_to_move(board, mg, san) {
+            Ok(m) => moves.push(format::move_san(board, mg, m)),
+            Err(_) => return Err(format!("Invalid move in {}: '{}'", opcode, san)),
+        }
+    }
+
+    if moves.is_empty() {
+        return Err(format!("No moves in {}.", opcode));
+    }
+
+    Ok(Some(moves))
+}
+
+// Searches the position on this thread, and returns the best move in
+// SAN. Every position starts with an empty TT, so the result doesn't
+// depend on the order of the positions in the file.
+fn search(
+    board: &mut Board,
+    mg: &Arc<MoveGenerator>,
+    tt: &TT<SearchData>,
+    limit: EpdLimit,
+) -> String {
+    let mut search_params = SearchParams::new();
+    search_params.quiet = true;
+    match limit {
+        EpdLimit::Depth(d) => {
+            search_params.search_mode = SearchMode::Depth;
+            search_params.depth = d;
+        }
+        EpdLimit::MoveTime(t) => {
+            search_params.search_mode = SearchMode::MoveTime;
+            search_params.move_time = t;
+        }
+    }
+
+    // Nothing is sent on the control channel, and the reports are not
+    // needed; only the best move is.
+    let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+    let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+    let mut search_info = SearchInfo::new();
+    let shared = SearchShared::new();
+
+    tt.clear();
+    let mut refs = SearchRefs {
+        board,
+        mg,
+        tt,
+        search_params: &mut search_params,
+        search_info: &mut search_info,
+        shared: &shared,
+        control_rx: &control_rx,
+        report_tx: &report_tx,
+    };
+
+    let (best_move, _, _) = Search::iterative_deepening(&mut refs);
+    format::move_san(board, mg, best_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_line(line: &str, depth: i8) -> EpdResult {
+        let record = epd::parse_line(line).expect(line);
+        let tt = TT::<SearchData>::new(1);
+        run_record(
+            &mut Board::new(),
+            &record,
+            &MoveGenerator::shared(),
+            &tt,
+            EpdLimit::Depth(depth),
+        )
+    }
+
+    // Every record of the bundled file can be set up and searched. The
+    // moves found at these depths are not asserted.
+    #[test]
+    fn bundled_file_runs() {
+        let lines = include_str!("epdtest.epd")
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with(COMMENT));
+
+        for line in lines {
+            for depth in 1..=2 {
+                let result = run_line(line, depth);
+                assert!(!matches!(result, EpdResult::Error(_)), "{}", line);
+            }
+        }
+    }
+
+    // The moves in the file are compared in the engine's SAN, and any of
+    // several best moves passes. A move to avoid fails the position.
+    #[test]
+    fn moves_are_compared_in_san() {
+        let fen = "7k/8/6K1/8/8/8/8/R7 w - -";
+
+        let result = run_line(&format!("{} bm Ra8+ Rb1;", fen), 2);
+        assert!(matches!(result, EpdResult::Pass(ref m) if m == "Ra8#"));
+
+        let result = run_line(&format!("{} bm a1a2 Rb1;", fen), 2);
+        assert!(matches!(result, EpdResult::Error(_)));
+
+        let result = run_line(&format!("{} am Ra8;", fen), 2);
+        assert!(matches!(
+            result,
+            EpdResult::Fail(ref m, ref e) if m == "Ra8#" && e == "not am Ra8#"
+        ));
+
+        let result = run_line(&format!("{} bm Rb1;", fen), 2);
+        assert!(matches!(
+            result,
+            EpdResult::Fail(ref m, ref e) if m == "Ra8#" && e == "bm Rb1"
+        ));
+
+        let result = run_line(&format!("{} id \"mate\";", fen), 2);
+        assert!(matches!(result, EpdResult::Error(_)));
+    }
+}
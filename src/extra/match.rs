@@ -0,0 +1,411 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// match.rs implements the games of a match between two players. Each
+// player has its own search parameters and TT, and collects statistics
+// on the moves it plays. Every move is written to a CSV file, with the
+// columns in CSV_HEADER. The match itself (the number of games, colors
+// and openings) is run by selfplay.rs.
+
+use crate::{
+    board::Board,
+    defs::{Sides, MAX_GAME_MOVES, MAX_MOVE_RULE},
+    engine::defs::{Information, SearchData, TT},
+    movegen::{defs::Move, MoveGenerator},
+    search::{
+        defs::{
+            Score, SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchReport,
+            SearchShared, SearchSummary,
+        },
+        Search,
+    },
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::Arc,
+};
+
+// Column names of the CSV file written for each game.
+pub const CSV_HEADER: &str = "move_number,side,player,move,depth,seldepth,nodes,time,score";
+
+// If a player's score drops by this many centipawns between two of its
+// own moves, the move before the drop is counted as a blunder.
+const BLUNDER_THRESHOLD: i16 = 200;
+
+// A game is adjudicated as a draw when it reaches this number of plies.
+const MAX_GAME_PLIES: usize = 400;
+
+const ERR_NO_SUMMARY: &str = "Search finished without a summary";
+const SIDE_NAMES: [&str; 2] = ["white", "black"];
+
+// One player in the match.
+pub struct Player {
+    name: &'static str,
+    search_params: SearchParams,
+    tt: TT<SearchData>,
+    stats: PlayerStats,
+}
+
+// Statistics collected for each player during the match.
+#[derive(Default)]
+struct PlayerStats {
+    wins: usize,
+    losses: usize,
+    draws: usize,
+    moves: usize,
+    total_depth: usize,
+    total_nodes: usize,
+    blunders: usize,
+    last_score: Option<Score>,
+}
+
+// The data recorded for every move played.
+pub struct MoveRecord {
+    pub move_number: u16,
+    pub side: usize,
+    pub player: &'static str,
+    pub played: Move,
+    pub summary: SearchSummary,
+}
+
+impl MoveRecord {
+    // Returns the move as a CSV line, with the columns in CSV_HEADER.
+    pub fn as_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.move_number,
+            SIDE_NAMES[self.side],
+            self.player,
+            self.played.as_string(),
+            self.summary.depth,
+            self.summary.seldepth,
+            self.summary.nodes,
+            self.summary.time,
+            self.summary.score.value()
+        )
+    }
+}
+
+// The way a game can end, seen from the side to move.
+enum GameOver {
+    Checkmate,
+    Draw(&'static str),
+}
+
+// Plays one game, after the opening moves, and writes it into a CSV file.
+// Returns a description of the game result.
+pub fn play_game(
+    players: &mut [Player; 2],
+    white: usize,
+    fen: &str,
+    opening: &[Move],
+    mg: &Arc<MoveGenerator>,
+    file_name: &str,
+) -> Result<String, String> {
+    let mut board = Board::new();
+    board
+        .fen_read(Some(fen))
+        .map_err(|e| format!("Invalid FEN-string: {}", e))?;
+    for &m in opening.iter() {
+        board.make(m, mg);
+    }
+
+    let file = File::create(file_name).map_err(|e| e.to_string())?;
+    let mut csv = BufWriter::new(file);
+    writeln!(csv, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+
+    // New game: clear what the players remember from the last one.
+    for player in players.iter_mut() {
+        player.tt.clear();
+        player.stats.last_score = None;
+    }
+
+    let mut plies = opening.len();
+    let game_over = loop {
+        if let Some(g) = game_over(&board, mg, plies) {
+            break g;
+        }
+
+        // Find out which player is to move.
+        let side = board.us();
+        let current = if side == Sides::WHITE {
+            white
+        } else {
+            white ^ 1
+        };
+        let player = &mut players[current];
+        let (best_move, summary) = player.think(&mut board, mg)?;
+
+        let record = MoveRecord {
+            move_number: board.game_state.fullmove_number,
+            side,
+            player: player.name,
+            played: best_move,
+            summary,
+        };
+        writeln!(csv, "{}", record.as_csv()).map_err(|e| e.to_string())?;
+        player.record(&record);
+
+        if !board.make(best_move, mg) {
+            return Err(format!("Illegal move played: {}", best_move.as_string()));
+        }
+        plies += 1;
+    };
+
+    csv.flush().map_err(|e| e.to_string())?;
+
+    // The side to move in the final position has lost, if mated.
+    let result = match game_over {
+        GameOver::Checkmate => {
+            let loser = if board.us() == Sides::WHITE {
+                white
+            } else {
+                white ^ 1
+            };
+            players[loser].stats.losses += 1;
+            players[loser ^ 1].stats.wins += 1;
+            format!("{} wins by checkmate", players[loser ^ 1].name)
+        }
+        GameOver::Draw(reason) => {
+            players[0].stats.draws += 1;
+            players[1].stats.draws += 1;
+            format!("draw by {}", reason)
+        }
+    };
+
+    Ok(result)
+}
+
+// Determines if the game has ended in the current position.
+fn game_over(board: &Board, mg: &MoveGenerator, plies: usize) -> Option<GameOver> {
+    // Find out if there is at least one legal move.
+    if board.legal_moves_iter(mg).next().is_none() {
+        let king_square = board.king_square(board.us());
+        return if mg.square_attacked(board, board.opponent(), king_square) {
+            Some(GameOver::Checkmate)
+        } else {
+            Some(GameOver::Draw("stalemate"))
+        };
+    }
+
+    if board.occurrences() >= 2 {
+        return Some(GameOver::Draw("threefold repetition"));
+    }
+
+    if board.game_state.halfmove_clock >= MAX_MOVE_RULE {
+        return Some(GameOver::Draw("fifty move rule"));
+    }
+
+    if plies >= MAX_GAME_PLIES || board.history.len() >= MAX_GAME_MOVES - 1 {
+        return Some(GameOver::Draw("adjudication"));
+    }
+
+    None
+}
+
+impl Player {
+    pub fn new(name: &'static str, depth: i8, tt_size: usize) -> Self {
+        let mut search_params = SearchParams::new();
+        search_params.depth = depth;
+        search_params.search_mode = SearchMode::Depth;
+        search_params.quiet = true;
+
+        Self {
+            name,
+            search_params,
+            tt: TT::<SearchData>::new(tt_size),
+            stats: PlayerStats::default(),
+        }
+    }
+
+    // Runs a search on the board, and returns the best move and the
+    // summary of the deepest completed iteration. A search that doesn't
+    // complete any iteration has no summary; that's an error.
+    fn think(
+        &mut self,
+        board: &mut Board,
+        mg: &Arc<MoveGenerator>,
+    ) -> Result<(Move, SearchSummary), String> {
+        let (control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut search_params = self.search_params;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+
+        // Every move is a new search, with its own TT generation.
+        self.tt.new_generation();
+
+        let mut refs = SearchRefs {
+            board,
+            mg,
+            tt: &self.tt,
+            search_params: &mut search_params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let (best_move, _, _) = Search::iterative_deepening(&mut refs);
+        std::mem::drop(control_tx);
+
+        // Only the last summary is of interest.
+        let mut summary = None;
+        while let Ok(information) = report_rx.try_recv() {
+            if let Information::Search(SearchReport::SearchSummary(s)) = information {
+                summary = Some(s);
+            }
+        }
+
+        match summary {
+            Some(s) => Ok((best_move, s)),
+            None => Err(String::from(ERR_NO_SUMMARY)),
+        }
+    }
+
+    // Adds the move to the player's statistics.
+    fn record(&mut self, record: &MoveRecord) {
+        let score = record.summary.score;
+
+        self.stats.moves += 1;
+        self.stats.total_depth += record.summary.depth as usize;
+        self.stats.total_nodes += record.summary.nodes;
+
+        if let Some(last) = self.stats.last_score {
+            if last.value() as i32 - score.value() as i32 >= BLUNDER_THRESHOLD as i32 {
+                self.stats.blunders += 1;
+            }
+        }
+        self.stats.last_score = Some(score);
+    }
+
+    pub fn print_stats(&self) {
+        let s = &self.stats;
+        let moves = usize::max(s.moves, 1) as f64;
+
+        println!(
+            "Player {} (depth {}): +{} -{} ={}",
+            self.name, self.search_params.depth, s.wins, s.losses, s.draws
+        );
+        println!(
+            "    Moves: {}, average depth: {:.2}, average nodes: {:.0}, blunders: {}",
+            s.moves,
+            s.total_depth as f64 / moves,
+            s.total_nodes as f64 / moves,
+            s.blunders
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::defs::ScoreBound;
+    use std::fs;
+
+    // Plays one game between players at the given depths, from the FEN,
+    // and returns the result and the lines of the CSV file.
+    fn play(fen: &str, depths: (i8, i8), name: &str) -> (Result<String, String>, Vec<String>) {
+        let mg = MoveGenerator::shared();
+        let mut players = [Player::new("A", depths.0, 1), Player::new("B", depths.1, 1)];
+        let path = std::env::temp_dir().join(format!("rustic_{}_{}.csv", name, std::process::id()));
+        let file_name = path.to_string_lossy().to_string();
+
+        let result = play_game(&mut players, 0, fen, &[], &mg, &file_name);
+        let csv = fs::read_to_string(&path).unwrap_or_default();
+        fs::remove_file(&path).ok();
+
+        (result, csv.lines().map(String::from).collect())
+    }
+
+    #[test]
+    fn csv_record_matches_header() {
+        let summary = SearchSummary {
+            depth: 5,
+            seldepth: 9,
+            time: 120,
+            score: Score::new(-35),
+            nodes: 4321,
+            nps: 0,
+            hash_full: 0,
+            pv: Vec::new(),
+            multipv: 0,
+            bound: ScoreBound::Exact,
+            pv_truncated: false,
+        };
+        let record = MoveRecord {
+            move_number: 12,
+            side: Sides::BLACK,
+            player: "B",
+            played: Move::new(0),
+            summary,
+        };
+
+        let columns: Vec<&str> = CSV_HEADER.split(',').collect();
+        let csv = record.as_csv();
+        let values: Vec<&str> = csv.split(',').collect();
+        let column = |name: &str| values[columns.iter().position(|c| *c == name).unwrap()];
+
+        assert_eq!(columns.len(), values.len());
+        assert_eq!(column("move_number"), "12");
+        assert_eq!(column("side"), "black");
+        assert_eq!(column("player"), "B");
+        assert_eq!(column("depth"), "5");
+        assert_eq!(column("seldepth"), "9");
+        assert_eq!(column("nodes"), "4321");
+        assert_eq!(column("time"), "120");
+        assert_eq!(column("score"), "-35");
+    }
+
+    #[test]
+    fn queen_mates_lone_king() {
+        let (result, lines) = play("k7/8/2K5/8/8/8/8/7Q w - - 0 1", (3, 3), "kqk");
+
+        assert_eq!(result, Ok(String::from("A wins by checkmate")));
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines.len() > 1);
+
+        // White (player A) moves first, and delivers the mate.
+        let columns = CSV_HEADER.split(',').count();
+        for (i, line) in lines[1..].iter().enumerate() {
+            let values: Vec<&str> = line.split(',').collect();
+            let (side, player) = if i % 2 == 0 {
+                ("white", "A")
+            } else {
+                ("black", "B")
+            };
+            assert_eq!(values.len(), columns);
+            assert_eq!((values[1], values[2]), (side, player));
+        }
+        assert_eq!(lines.len() % 2, 0);
+    }
+
+    #[test]
+    fn search_without_summary_is_an_error() {
+        let (result, lines) = play("k7/8/2K5/8/8/8/8/7Q w - - 0 1", (0, 3), "nosummary");
+
+        assert_eq!(result, Err(String::from(ERR_NO_SUMMARY)));
+        assert_eq!(lines, vec![String::from(CSV_HEADER)]);
+    }
+}
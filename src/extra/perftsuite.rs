@@ -0,0 +1,314 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file runs perft suites from an EPD file. Each line holds a
+// FEN-string, followed by the expected leaf node counts per depth:
+//
+// <fen> ;D1 20 ;D2 400 ;D3 8902
+//
+// Empty lines and lines starting with '#' are skipped. Each position is
+// run from the lowest up to the deepest annotated depth. As soon as a
+// depth gives a different node count, the position fails, and both the
+// FEN-string and the depth are printed, so the move generator bug can be
+// reproduced immediately using "--perft <depth> --fen <fen>".
+//
+// A line can also claim a direct mate, as tactical EPD files do:
+//
+// <fen> ;dm 3
+//
+// The position is then searched in Mate mode, and it only passes if the
+// search proves a mate in exactly that number of moves. Depth and mate
+// annotations can be combined on one line; the perft depths run first.
+
+use crate::{
+    board::Board,
+    engine::defs::{ErrFatal, PerftData, TT},
+    misc::perft,
+    movegen::MoveGenerator,
+    search::{defs::MateVerification, Search},
+};
+use std::{
+    fs,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+const SEMI_COLON: char = ';';
+const COMMENT: char = '#';
+const DEPTH_PREFIX: char = 'D';
+const DIRECT_MATE: &str = "dm";
+
+// One line of the perft suite: the position, the expected number of leaf
+// nodes per depth, sorted from low to high depth, and the claimed mate.
+pub struct SuiteEntry {
+    pub fen: String,
+    pub depths: Vec<(i8, u64)>,
+    pub mate: Option<u8>,
+}
+
+// The outcome of running a single suite entry.
+enum SuiteResult {
+    Pass(i8, u64),          // Deepest depth and its leaf nodes
+    Fail(i8, u64, u64),     // Depth, expected and found leaf nodes
+    Mate(MateVerification), // Result of verifying the claimed mate
+    Error(String),          // The line could not be parsed or set up
+}
+
+// Parses one line of a perft suite.
+pub fn parse_line(line: &str) -> Result<SuiteEntry, String> {
+    let mut parts = line.split(SEMI_COLON).map(|p| p.trim());
+    let fen = parts.next().unwrap_or("").to_string();
+    let mut depths: Vec<(i8, u64)> = Vec::new();
+    let mut mate: Option<u8> = None;
+
+    if fen.is_empty() {
+        return Err(String::from("No FEN-string found."));
+    }
+
+    // Parse all the "D<depth> <leaf nodes>" and "dm <moves>" annotations.
+    for part in parts.filter(|p| !p.is_empty()) {
+        let annotation: Vec<&str> = part.split_whitespace().collect();
+
+        if annotation.first() == Some(&DIRECT_MATE) {
+            match annotation.get(1).and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if n > 0 && annotation.len() == 2 => mate = Some(n),
+                _ => return Err(format!("Invalid mate annotation: '{}'", part)),
+            }
+            continue;
+        }
+
+        let depth = annotation
+            .first()
+            .and_then(|d| d.strip_prefix(DEPTH_PREFIX))
+            .and_then(|d| d.parse::<i8>().ok())
+            .filter(|d| *d > 0);
+        let leaf_nodes = annotation.get(1).and_then(|n| n.parse::<u64>().ok());
+
+        match (depth, leaf_nodes, annotation.len()) {
+            (Some(d), Some(n), 2) => depths.push((d, n)),
+            _ => return Err(format!("Invalid depth annotation: '{}'", part)),
+        }
+    }
+
+    if depths.is_empty() && mate.is_none() {
+        return Err(String::from("No depth or mate annotations found."));
+    }
+
+    depths.sort_by_key(|(d, _)| *d);
+
+    Ok(SuiteEntry { fen, depths, mate })
+}
+
+// Runs all the positions in the given perft suite file.
+pub fn run(file: &str, mg: Arc<MoveGenerator>, tt: Arc<RwLock<TT<PerftData>>>, tt_enabled: bool) {
+    let contents = match fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Can't read perft suite '{}': {}", file, e);
+            return;
+        }
+    };
+
+    let tt = tt.read().expect(ErrFatal::LOCK);
+    let mut board = Board::new();
+    let mut passed = 0;
+    let mut failed = 0;
+    let now = Instant::now();
+
+    println!("Running perft suite: {}", file);
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let line_nr = i + 1;
+
+        if line.is_empty() || line.starts_with(COMMENT) {
+            continue;
+        }
+
+        let result = match parse_line(line) {
+            Ok(entry) => run_entry(&mut board, &entry, &mg, &tt, tt_enabled),
+            Err(e) => SuiteResult::Error(e),
+        };
+
+        match result {
+            SuiteResult::Pass(depth, leaf_nodes) => {
+                passed += 1;
+                println!("Line {}: OK (depth {}: {})", line_nr, depth, leaf_nodes);
+            }
+            SuiteResult::Fail(depth, expected, found) => {
+                failed += 1;
+                println!(
+                    "Line {}: FAIL at depth {}: expected {}, found {}",
+                    line_nr, depth, expected, found
+                );
+                println!(
+                    "    FEN: {}",
+                    line.split(SEMI_COLON).next().unwrap_or("").trim()
+                );
+            }
+            SuiteResult::Mate(v) if v.is_proven() => {
+                passed += 1;
+                println!("Line {}: OK ({})", line_nr, v.as_string());
+            }
+            SuiteResult::Mate(v) => {
+                failed += 1;
+                println!("Line {}: FAIL: {}", line_nr, v.as_string());
+                println!(
+                    "    FEN: {}",
+                    line.split(SEMI_COLON).next().unwrap_or("").trim()
+                );
+            }
+            SuiteResult::Error(e) => {
+                failed += 1;
+                println!("Line {}: ERROR: {}", line_nr, e);
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "Passed: {}, failed: {}, total: {}",
+        passed,
+        failed,
+        passed + failed
+    );
+    println!("Total time spent: {} ms", now.elapsed().as_millis());
+}
+
+// Runs one position up to its deepest depth, stopping at the first
+// depth where the leaf node count doesn't match. If the perft depths all
+// pass, the claimed mate is verified.
+fn run_entry(
+    board: &mut Board,
+    entry: &SuiteEntry,
+    mg: &Arc<MoveGenerator>,
+    tt: &TT<PerftData>,
+    tt_enabled: bool,
+) -> SuiteResult {
+    if board.fen_read(Some(&entry.fen)).is_err() {
+        return SuiteResult::Error(format!("Invalid FEN-string: {}", entry.fen));
+    }
+
+    let mut result = SuiteResult::Error(String::from("No depths to run."));
+    for &(depth, expected) in entry.depths.iter() {
+        let found = perft::perft(board, depth, mg, tt, tt_enabled);
+
+        if found != expected {
+            return SuiteResult::Fail(depth, expected, found);
+        }
+
+        result = SuiteResult::Pass(depth, found);
+    }
+
+    if let Some(moves) = entry.mate {
+        result = SuiteResult::Mate(Search::verify_mate(board, mg, moves));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn run_line(line: &str) -> SuiteResult {
+        let entry = parse_line(line).expect(line);
+        let tt = TT::<PerftData>::new(1);
+        run_entry(
+            &mut Board::new(),
+            &entry,
+            &MoveGenerator::shared(),
+            &tt,
+            true,
+        )
+    }
+
+    #[test]
+    fn lines_are_parsed() {
+        let entry = parse_line(&format!("{} ;D3 8902 ;D1 20; D2 400 ;", START)).expect("valid");
+        assert_eq!(entry.fen, START);
+        assert_eq!(entry.depths, vec![(1, 20), (2, 400), (3, 8902)]);
+        assert_eq!(entry.mate, None);
+
+        let entry = parse_line("7k/8/6K1/8/8/8/8/R7 w - - 0 1 ;dm 1").expect("valid");
+        assert!(entry.depths.is_empty());
+        assert_eq!(entry.mate, Some(1));
+
+        let invalid = [
+            String::from(";D1 20"),
+            String::from(START),
+            format!("{} ;D1", START),
+            format!("{} ;D0 1", START),
+            format!("{} ;X1 20", START),
+            format!("{} ;D1 20 30", START),
+            format!("{} ;D1 -20", START),
+            format!("{} ;dm 0", START),
+            format!("{} ;dm", START),
+        ];
+        for line in invalid.iter() {
+            assert!(parse_line(line).is_err(), "{}", line);
+        }
+    }
+
+    // The first depth with a wrong count is reported, with the expected
+    // and the found number of leaf nodes.
+    #[test]
+    fn first_wrong_depth_fails() {
+        let line = format!("{} ;D1 20 ;D2 401 ;D3 8903", START);
+        assert!(matches!(run_line(&line), SuiteResult::Fail(2, 401, 400)));
+
+        let line = format!("{} ;D1 20 ;D2 400 ;D3 8902", START);
+        assert!(matches!(run_line(&line), SuiteResult::Pass(3, 8902)));
+
+        let line = "7k/8/6K1/8/8/8/8/R7 w - - 0 1 ;D1 20 ;dm 1";
+        assert!(matches!(run_line(line), SuiteResult::Mate(v) if v.is_proven()));
+
+        let line = "8/8/8/8/8/8/8/8 w - - 0 1 ;D1 0";
+        assert!(matches!(run_line(line), SuiteResult::Error(_)));
+    }
+
+    // Every line of the bundled suite passes, up to depth 3.
+    #[test]
+    fn bundled_suite_passes() {
+        let suite = include_str!("perftsuite.epd");
+        let lines = suite
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with(COMMENT));
+
+        for line in lines {
+            let mut entry = parse_line(line).expect(line);
+            entry.depths.retain(|(depth, _)| *depth <= 3);
+            let tt = TT::<PerftData>::new(1);
+            let result = run_entry(
+                &mut Board::new(),
+                &entry,
+                &MoveGenerator::shared(),
+                &tt,
+                true,
+            );
+            assert!(matches!(result, SuiteResult::Pass(3, _)), "{}", line);
+        }
+    }
+}
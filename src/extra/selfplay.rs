@@ -0,0 +1,205 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements selfplay matches. Two players, "A" and "B", each
+// with their own search parameters and TT, play a number of games
+// against one another, alternating colors. This makes it possible to
+// run handicap matches, such as depth 4 against depth 6. Every move is
+// written to a CSV file per game, so the games can be analyzed later.
+// When the match is done, aggregate statistics are printed per player.
+// The games themselves are played by match.rs.
+//
+// At a fixed depth, the engine plays the same game every time. To get
+// different games, each pair of games can start with a number of random
+// plies ("variety"), played once with each player as white. The random
+// moves come from the engine's generator, so a match can be replayed by
+// passing the reported seed back with --seed.
+
+use super::r#match::{play_game, Player};
+use crate::{
+    board::Board,
+    misc::random::EngineRandom,
+    movegen::{defs::Move, MoveGenerator},
+};
+use std::sync::Arc;
+
+const PLAYER_NAMES: [&str; 2] = ["A", "B"];
+
+// Runs the match. Player A plays white in the first game. The depths are
+// the maximum search depths for player A and B.
+pub fn run(
+    games: usize,
+    depths: (i8, i8),
+    fen: &str,
+    mg: Arc<MoveGenerator>,
+    tt_size: usize,
+    variety: usize,
+    random: &mut EngineRandom,
+) {
+    let mut players = [
+        Player::new(PLAYER_NAMES[0], depths.0, tt_size),
+        Player::new(PLAYER_NAMES[1], depths.1, tt_size),
+    ];
+    let mut opening: Vec<Move> = Vec::new();
+
+    println!(
+        "Selfplay: {} games, A at depth {} against B at depth {}",
+        games, depths.0, depths.1
+    );
+    println!("Random opening plies: {}, seed: {}", variety, random.seed());
+
+    for game in 1..=games {
+        // Player A is white in odd games, and black in even games. Both
+        // games of a pair start with the same opening.
+        let white = if game % 2 == 1 { 0 } else { 1 };
+        let file_name = format!("selfplay_{}.csv", game);
+
+        if game % 2 == 1 {
+            opening = match random_opening(fen, &mg, variety, random) {
+                Ok(moves) => moves,
+                Err(e) => {
+                    println!("Game {}: aborted: {}", game, e);
+                    return;
+                }
+            };
+        }
+
+        match play_game(&mut players, white, fen, &opening, &mg, &file_name) {
+            Ok(result) if opening.is_empty() => {
+                println!("Game {}: {} ({})", game, result, file_name)
+            }
+            Ok(result) => {
+                let moves: Vec<String> = opening.iter().map(|m| m.as_string()).collect();
+                println!(
+                    "Game {}: {} ({}, opening {})",
+                    game,
+                    result,
+                    file_name,
+                    moves.join(" ")
+                );
+            }
+            Err(e) => {
+                println!("Game {}: aborted: {}", game, e);
+                return;
+            }
+        }
+    }
+
+    println!();
+    for player in players.iter() {
+        player.print_stats();
+    }
+}
+
+// Picks the given number of random legal moves, starting from the FEN
+// position. The opening ends early if the game is over.
+fn random_opening(
+    fen: &str,
+    mg: &MoveGenerator,
+    plies: usize,
+    random: &mut EngineRandom,
+) -> Result<Vec<Move>, String> {
+    let mut board = Board::new();
+    let mut opening: Vec<Move> = Vec::new();
+    board
+        .fen_read(Some(fen))
+        .map_err(|e| format!("Invalid FEN-string: {}", e))?;
+
+    while opening.len() < plies {
+        let moves: Vec<Move> = board.legal_moves_iter(mg).collect();
+        if moves.is_empty() {
+            break;
+        }
+
+        let m = moves[random.below(moves.len())];
+        board.make(m, mg);
+        opening.push(m);
+    }
+
+    Ok(opening)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const FEN: &str = "k7/8/2K5/8/8/8/8/7Q w - - 0 1";
+    const VARIETY: usize = 6;
+
+    // Plays a pair of games from a random opening, as run() does, and
+    // returns the opening and the moves of both games.
+    fn play_pair(seed: u64, name: &str) -> (Vec<String>, Vec<Vec<String>>) {
+        let mg = MoveGenerator::shared();
+        let mut random = EngineRandom::new(Some(seed));
+        let mut players = [Player::new("A", 2, 1), Player::new("B", 2, 1)];
+        let opening = random_opening(FEN, &mg, VARIETY, &mut random).expect("valid FEN");
+        let mut games: Vec<Vec<String>> = Vec::new();
+
+        for white in 0..2 {
+            let path = std::env::temp_dir().join(format!(
+                "rustic_{}_{}_{}.csv",
+                name,
+                white,
+                std::process::id()
+            ));
+            let file_name = path.to_string_lossy().to_string();
+            play_game(&mut players, white, FEN, &opening, &mg, &file_name).expect("game");
+
+            // Only the moves are compared; the times differ per run.
+            let csv = fs::read_to_string(&path).expect("CSV file");
+            fs::remove_file(&path).ok();
+            let moves = csv
+                .lines()
+                .skip(1)
+                .map(|l| l.split(',').nth(3).unwrap_or_default().to_string());
+            games.push(moves.collect());
+        }
+
+        (opening.iter().map(|m| m.as_string()).collect(), games)
+    }
+
+    #[test]
+    fn same_seed_same_games() {
+        let (opening, games) = play_pair(42, "seed_a");
+        let (replay_opening, replay_games) = play_pair(42, "seed_b");
+
+        assert_eq!(opening.len(), VARIETY);
+        assert!(games.iter().all(|g| !g.is_empty()));
+        assert_eq!(opening, replay_opening);
+        assert_eq!(games, replay_games);
+    }
+
+    #[test]
+    fn other_seed_other_opening() {
+        let mg = MoveGenerator::shared();
+        let opening = |seed: u64| {
+            let mut random = EngineRandom::new(Some(seed));
+            random_opening(FEN, &mg, VARIETY, &mut random).expect("valid FEN")
+        };
+
+        assert!(opening(42) == opening(42));
+        assert!(opening(42) != opening(43));
+        assert!(random_opening("8/8/8", &mg, VARIETY, &mut EngineRandom::new(Some(42))).is_err());
+    }
+}
@@ -23,13 +23,13 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::{
     board::Board,
-    engine::defs::{PerftData, TT},
+    engine::defs::{ErrFatal, PerftData, TT},
     extra::epds::LARGE_TEST_EPDS,
     misc::{perft, print},
     movegen::MoveGenerator,
 };
 use std::{
-    sync::{Arc, Mutex},
+    sync::{Arc, RwLock},
     time::Instant,
 };
 
@@ -52,7 +52,8 @@ const TEST_RESULTS: [&str; 5] = [
 
 // This private function is the one actually running tests.
 // This can be the entire suite, or a single test.
-pub fn run(tt: Arc<Mutex<TT<PerftData>>>, tt_enabled: bool) {
+pub fn run(tt: Arc<RwLock<TT<PerftData>>>, tt_enabled: bool) {
+    let tt = tt.read().expect(ErrFatal::LOCK);
     let number_of_tests = LARGE_TEST_EPDS.len();
     let move_generator = MoveGenerator::new();
     let mut board: Board = Board::new();
@@ -91,7 +92,7 @@ pub fn run(tt: Arc<Mutex<TT<PerftData>>>, tt_enabled: bool) {
                 .map(|s| s.to_string())
                 .collect();
 
-            let depth = (depth_ln[0][1..]).parse::<u8>().unwrap_or(0);
+            let depth = (depth_ln[0][1..]).parse::<i8>().unwrap_or(0);
             let expected_ln = depth_ln[1].parse::<u64>().unwrap_or(0);
 
             // Abort if depth or expected leaf node parsing fails.
@@ -0,0 +1,414 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements a Texel tuner for the evaluation parameters. It
+// reads a file of positions, each labelled with the result of the game it
+// was taken from:
+//
+// <fen> ;1-0
+// <fen>,0.5
+//
+// Results can be written as "1-0", "1/2-1/2" and "0-1", or as 1.0, 0.5
+// and 0.0, always from white's point of view. Empty lines and lines
+// starting with '#' are skipped. The evaluation of each position is
+// turned into an expected result by a sigmoid, and the tuner minimizes
+// the mean squared error between the expected and the actual results.
+// First the scaling constant K of the sigmoid is fitted to the current
+// parameters. Then a local search changes each parameter by one step up
+// or down, and keeps the change if the error goes down, until a full
+// pass over all parameters no longer improves the error by more than a
+// tiny amount. The tuned values are printed as Rust arrays, to be put
+// back into the evaluation by hand.
+//
+// The positions are evaluated statically, without a quiescence search,
+// so the file should contain quiet positions for the best results. Each
+// FEN-string is only parsed once; the parts of the board the evaluation
+// needs are then copied back into a single board for every evaluation.
+
+use crate::{
+    board::{defs::Pieces, gamestate::GameState, Board},
+    defs::{Bitboard, NrOf, Piece, Sides},
+    evaluation::{
+        defs::{EvalParams, MOBILITY_ENABLED},
+        Evaluation,
+    },
+    movegen::MoveGenerator,
+};
+use std::{fs, sync::Arc, time::Instant};
+
+const SEMI_COLON: char = ';';
+const COMMA: char = ',';
+const COMMENT: char = '#';
+
+const STEP: i16 = 1; // Change of a parameter per try
+const MAX_ITERATIONS: usize = 1000; // Passes over all parameters
+const MIN_IMPROVEMENT: f64 = 1e-7; // Converged if a pass gains less
+const K_MIN: f64 = 0.1; // Range of the sigmoid scaling constant
+const K_MAX: f64 = 3.0;
+const K_STEPS: [f64; 3] = [0.1, 0.01, 0.001]; // Coarse to fine
+
+// A position from the tuning file, with the result of its game.
+pub struct TuneEntry {
+    pub fen: String,
+    pub result: f64, // 1.0 = white wins, 0.5 = draw, 0.0 = black wins
+}
+
+// The parts of a board that are needed to evaluate it. Copying these is
+// much faster than setting up the board from its FEN-string again.
+struct Position {
+    bb_pieces: [[Bitboard; NrOf::PIECE_TYPES]; Sides::BOTH],
+    bb_side: [Bitboard; Sides::BOTH],
+    game_state: GameState,
+    piece_list: [Piece; NrOf::SQUARES],
+}
+
+impl Position {
+    fn new(board: &Board) -> Self {
+        Self {
+            bb_pieces: board.bb_pieces,
+            bb_side: board.bb_side,
+            game_state: board.game_state,
+            piece_list: board.piece_list,
+        }
+    }
+
+    fn restore(&self, board: &mut Board) {
+        board.bb_pieces = self.bb_pieces;
+        board.bb_side = self.bb_side;
+        board.game_state = self.game_state;
+        board.piece_list = self.piece_list;
+    }
+}
+
+// The tunable parameters: a name to print them with, and a function that
+// returns them from the parameter struct.
+type Tunable = (&'static str, fn(&mut EvalParams) -> &mut [i16]);
+
+const TUNABLES: [Tunable; 27] = [
+    ("KING_MG", |p| &mut p.pst_mg[Pieces::KING]),
+    ("QUEEN_MG", |p| &mut p.pst_mg[Pieces::QUEEN]),
+    ("ROOK_MG", |p| &mut p.pst_mg[Pieces::ROOK]),
+    ("BISHOP_MG", |p| &mut p.pst_mg[Pieces::BISHOP]),
+    ("KNIGHT_MG", |p| &mut p.pst_mg[Pieces::KNIGHT]),
+    ("PAWN_MG", |p| &mut p.pst_mg[Pieces::PAWN]),
+    ("KING_EG", |p| &mut p.pst_eg[Pieces::KING]),
+    ("QUEEN_EG", |p| &mut p.pst_eg[Pieces::QUEEN]),
+    ("ROOK_EG", |p| &mut p.pst_eg[Pieces::ROOK]),
+    ("BISHOP_EG", |p| &mut p.pst_eg[Pieces::BISHOP]),
+    ("KNIGHT_EG", |p| &mut p.pst_eg[Pieces::KNIGHT]),
+    ("PAWN_EG", |p| &mut p.pst_eg[Pieces::PAWN]),
+    ("PawnStructure::DOUBLED", |p| &mut p.doubled),
+    ("PawnStructure::ISOLATED", |p| &mut p.isolated),
+    ("PawnStructure::PASSED_MG", |p| &mut p.passed_mg),
+    ("PawnStructure::PASSED_EG", |p| &mut p.passed_eg),
+    ("KingSafety::SHIELD_RANK", |p| &mut p.shield_rank),
+    ("KingSafety::SHIELD_MISSING", |p| {
+        std::slice::from_mut(&mut p.shield_missing)
+    }),
+    ("PawnStorm::RANK_BONUS", |p| &mut p.storm_rank),
+    ("Mobility::KNIGHT_MG", |p| &mut p.knight_mobility_mg),
+    ("Mobility::KNIGHT_EG", |p| &mut p.knight_mobility_eg),
+    ("Mobility::BISHOP_MG", |p| &mut p.bishop_mobility_mg),
+    ("Mobility::BISHOP_EG", |p| &mut p.bishop_mobility_eg),
+    ("Mobility::ROOK_MG", |p| &mut p.rook_mobility_mg),
+    ("Mobility::ROOK_EG", |p| &mut p.rook_mobility_eg),
+    ("Mobility::QUEEN_MG", |p| &mut p.queen_mobility_mg),
+    ("Mobility::QUEEN_EG", |p| &mut p.queen_mobility_eg),
+];
+
+// Parses one line of a tuning file.
+pub fn parse_line(line: &str) -> Result<TuneEntry, String> {
+    let mut parts = line.splitn(2, [SEMI_COLON, COMMA]);
+    let fen = parts.next().unwrap_or("").trim().to_string();
+    let result = parts.next().unwrap_or("").trim().trim_matches('"');
+
+    if fen.is_empty() {
+        return Err(String::from("No FEN-string found."));
+    }
+
+    let result = match result {
+        "1-0" => 1.0,
+        "1/2-1/2" => 0.5,
+        "0-1" => 0.0,
+        r => match r.parse::<f64>() {
+            Ok(v) if (0.0..=1.0).contains(&v) => v,
+            _ => return Err(format!("Invalid result: '{}'", r)),
+        },
+    };
+
+    Ok(TuneEntry { fen, result })
+}
+
+// Turns an evaluation in centipawns into an expected result between 0
+// (black wins) and 1 (white wins).
+pub fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+// Returns the mean squared error between the expected results of the
+// given evaluations and the actual results of the entries.
+pub fn mean_error(entries: &[TuneEntry], evals: &[i16], k: f64) -> f64 {
+    let total: f64 = entries
+        .iter()
+        .zip(evals.iter())
+        .map(|(entry, &eval)| (entry.result - sigmoid(eval as f64, k)).powi(2))
+        .sum();
+
+    total / entries.len() as f64
+}
+
+// Evaluates the position from scratch with the given parameters, from
+// white's point of view. Nothing is taken from the incremental values
+// on the board, or from the pawn hash table. With the default parameters,
+// this gives the same result as Evaluation::evaluate_position().
+pub fn evaluate(board: &Board, mg: &MoveGenerator, params: &EvalParams) -> i16 {
+    let pst_mg = Evaluation::pst_apply(board, &params.pst_mg);
+    let pst_eg = Evaluation::pst_apply(board, &params.pst_eg);
+    let mut value = Evaluation::pst_taper(board, pst_mg, pst_eg);
+
+    value += Evaluation::king_safety(board, mg, params);
+    value += Evaluation::pawn_storm(board, params);
+
+    if MOBILITY_ENABLED {
+        value += Evaluation::mobility(board, mg, params);
+    }
+
+    let pawns = Evaluation::pawn_structure_raw(board, params);
+    value += Evaluation::pawn_taper(board, pawns);
+
+    Evaluation::scale_endgame(board, value)
+}
+
+// Tunes the evaluation parameters on the positions in the given file.
+pub fn run(file: &str, mg: Arc<MoveGenerator>) {
+    let contents = match fs::read_to_string(file) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Can't read tuning file '{}': {}", file, e);
+            return;
+        }
+    };
+
+    let mut board = Board::new();
+    let mut entries: Vec<TuneEntry> = Vec::new();
+    let mut positions: Vec<Position> = Vec::new();
+
+    // Only keep the positions that can actually be set up.
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(COMMENT) {
+            continue;
+        }
+
+        let entry = parse_line(line).and_then(|entry| {
+            board
+                .fen_read(Some(&entry.fen))
+                .map(|_| entry)
+                .map_err(|e| format!("Invalid FEN-string: {}.", e))
+        });
+
+        match entry {
+            Ok(entry) => {
+                entries.push(entry);
+                positions.push(Position::new(&board));
+            }
+            Err(e) => println!("Line {}: ERROR: {}", i + 1, e),
+        }
+    }
+
+    if entries.is_empty() {
+        println!("No positions to tune on in '{}'.", file);
+        return;
+    }
+
+    let now = Instant::now();
+    let mut params = EvalParams::DEFAULT;
+    let mut evaluate_all = |params: &EvalParams| -> Vec<i16> {
+        positions
+            .iter()
+            .map(|position| {
+                position.restore(&mut board);
+                evaluate(&board, &mg, params)
+            })
+            .collect()
+    };
+
+    println!("Tuning on {} positions from: {}", entries.len(), file);
+
+    let k = best_k(&entries, &evaluate_all(&params));
+    let mut best_error = mean_error(&entries, &evaluate_all(&params), k);
+    println!("K = {:.3}, error = {:.6}", k, best_error);
+
+    for iteration in 1..=MAX_ITERATIONS {
+        let previous_error = best_error;
+        let mut improved = 0;
+
+        for (_, get) in TUNABLES.iter() {
+            for i in 0..get(&mut params).len() {
+                for delta in [STEP, -STEP] {
+                    get(&mut params)[i] += delta;
+                    let error = mean_error(&entries, &evaluate_all(&params), k);
+
+                    if error < best_error {
+                        best_error = error;
+                        improved += 1;
+                        break;
+                    }
+
+                    get(&mut params)[i] -= delta;
+                }
+            }
+        }
+
+        println!(
+            "Iteration {}: error = {:.6}, parameters changed: {}",
+            iteration, best_error, improved
+        );
+
+        if previous_error - best_error < MIN_IMPROVEMENT {
+            println!("Converged after {} iterations.", iteration);
+            break;
+        }
+    }
+
+    println!("Total time spent: {} ms", now.elapsed().as_millis());
+    println!();
+    print_params(&mut params);
+}
+
+// Finds the sigmoid scaling constant with the lowest error for the given
+// evaluations, by scanning the range of K from coarse to fine steps.
+fn best_k(entries: &[TuneEntry], evals: &[i16]) -> f64 {
+    let mut best = K_MIN;
+    let mut low = K_MIN;
+    let mut high = K_MAX;
+
+    for step in K_STEPS.iter() {
+        let mut best_error = f64::MAX;
+        let mut k = low;
+
+        while k <= high {
+            let error = mean_error(entries, evals, k);
+            if error < best_error {
+                best_error = error;
+                best = k;
+            }
+            k += step;
+        }
+
+        // Search around the best K with the next, smaller step.
+        low = f64::max(K_MIN, best - step);
+        high = f64::min(K_MAX, best + step);
+    }
+
+    best
+}
+
+// Prints all tunable parameters as Rust arrays.
+fn print_params(params: &mut EvalParams) {
+    for (name, get) in TUNABLES.iter() {
+        let values = get(params);
+        let list: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+
+        if values.len() == 1 {
+            println!("{}: i16 = {};", name, list[0]);
+            continue;
+        }
+
+        let array = format!("{}: [i16; {}] = [", name, values.len());
+        if values.len() <= 8 {
+            println!("{}{}];", array, list.join(", "));
+            continue;
+        }
+
+        // Longer arrays, such as the PST's, get 8 values on each line.
+        println!("{}", array);
+        for row in list.chunks(8) {
+            println!("    {},", row.join(", "));
+        }
+        println!("];");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(result: f64) -> TuneEntry {
+        TuneEntry {
+            fen: String::new(),
+            result,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    // With K = 1, an evaluation of 400 cp gives 10 to 1 odds.
+    #[test]
+    fn sigmoid_values() {
+        assert_close(sigmoid(0.0, 1.0), 0.5);
+        assert_close(sigmoid(400.0, 1.0), 10.0 / 11.0);
+        assert_close(sigmoid(-400.0, 1.0), 1.0 / 11.0);
+        assert_close(sigmoid(200.0, 2.0), 10.0 / 11.0);
+    }
+
+    #[test]
+    fn mean_error_of_a_small_dataset() {
+        let entries = [entry(1.0), entry(0.5), entry(0.0)];
+
+        // Each decisive game is 1/11 off; the draw is exactly right.
+        let error = mean_error(&entries, &[400, 0, -400], 1.0);
+        assert_close(error, 2.0 / 363.0);
+
+        // All positions expected to be draws: the wins and the loss are
+        // each 0.5 off.
+        let error = mean_error(&entries, &[0, 0, 0], 1.0);
+        assert_close(error, 1.0 / 6.0);
+
+        // Evaluations that have the winner wrong give a larger error.
+        let error = mean_error(&entries, &[-400, 0, 400], 1.0);
+        assert_close(error, 200.0 / 363.0);
+    }
+
+    #[test]
+    fn results_are_parsed() {
+        let fen = "4k3/8/8/8/8/8/8/4K3 w - - 0 1";
+        let cases = [
+            (format!("{} ;1-0", fen), 1.0),
+            (format!("{},0.5", fen), 0.5),
+            (format!("{} ;\"0-1\"", fen), 0.0),
+            (format!("{};1/2-1/2", fen), 0.5),
+        ];
+        for (line, result) in cases.iter() {
+            let entry = parse_line(line).expect(line);
+            assert_eq!(entry.fen, fen);
+            assert_close(entry.result, *result);
+        }
+
+        assert!(parse_line(&format!("{} ;2-0", fen)).is_err());
+        assert!(parse_line(&format!("{},1.5", fen)).is_err());
+        assert!(parse_line(";1-0").is_err());
+    }
+}
@@ -25,17 +25,17 @@ use crate::movegen::MoveGenerator;
 use crate::{
     board::defs::{Pieces, RangeOf, PIECE_NAME, SQUARE_NAME},
     defs::{Bitboard, Piece, Square, EMPTY},
+    misc::random::EngineRandom,
     movegen::{defs::Magic, BISHOP_TABLE_SIZE, ROOK_TABLE_SIZE},
 };
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaChaRng;
 
 // The find_magics function can be used by compiling the "wizardry" module
 // into the engine, and then adding the "-w" option on the command line.
 // This function generates magic numbers for the rooks and bishops. A queen
 // is a combination of a rook and a bishop, so she does not have her own
-// magic numbers.
-pub fn find_magics(piece: Piece) {
+// magic numbers. The random numbers come from the engine's generator, so
+// a run can be repeated with the same seed.
+pub fn find_magics(piece: Piece, random: &mut EngineRandom) {
     // First check if we're actually dealing with a rook or a bishop.
     let ok = piece == Pieces::ROOK || piece == Pieces::BISHOP;
     assert!(ok, "Illegal piece: {}", piece);
@@ -44,7 +44,6 @@ pub fn find_magics(piece: Piece) {
     let is_rook = piece == Pieces::ROOK;
     let mut rook_table: Vec<Bitboard> = vec![EMPTY; ROOK_TABLE_SIZE];
     let mut bishop_table: Vec<Bitboard> = vec![EMPTY; BISHOP_TABLE_SIZE];
-    let mut random = ChaChaRng::from_entropy();
     let mut offset = 0;
 
     println!("Finding magics for: {}", PIECE_NAME[piece]);
@@ -84,7 +83,7 @@ pub fn find_magics(piece: Piece) {
             found = true; // Assume this new magic will work.
 
             // Create a random magic number to test.
-            try_this.nr = random.gen::<u64>() & random.gen::<u64>() & random.gen::<u64>();
+            try_this.nr = random.next_u64() & random.next_u64() & random.next_u64();
 
             // Now try all possible permutations of blocker boards on this square.
             for i in 0..permutations {
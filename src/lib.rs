@@ -0,0 +1,77 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Without the engine, parts of the evaluation and some definitions have
+// no users, so don't warn about them in that build.
+#![cfg_attr(not(feature = "engine"), allow(dead_code, unused_imports))]
+
+// The engine is built as a library, so its parts can be used without
+// running the engine itself; for example, by the fuzz targets in fuzz/.
+// The binary in main.rs only creates and runs the engine.
+
+// Without the "engine" feature, only the board, move generator, and
+// evaluation are built, with the FEN and SAN code in misc. This part
+// doesn't use threads or channels. (See the features in Cargo.toml.)
+
+mod board;
+mod defs;
+mod evaluation;
+mod misc;
+mod movegen;
+
+#[cfg(feature = "comm")]
+mod comm;
+
+#[cfg(feature = "engine")]
+mod engine;
+
+#[cfg(feature = "engine")]
+mod search;
+
+#[cfg(feature = "extra")]
+mod extra;
+
+#[cfg(feature = "engine")]
+pub mod api;
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
+pub use board::{defs::FenError, Board};
+pub use misc::{format, parse};
+pub use movegen::{
+    defs::{Move, MoveList, MoveType},
+    LegalMoves, MoveGenerator,
+};
+
+#[cfg(feature = "comm")]
+pub use comm::{uci::Uci, xboard::XBoard, CommReport};
+
+#[cfg(feature = "engine")]
+pub use engine::{defs::EngineRunError, Engine};
+
+#[cfg(feature = "engine")]
+pub use api::{evaluate_position, search, SearchOutcome};
+
+#[cfg(feature = "engine")]
+pub use search::defs::{GameTime, Score, SearchMode, SearchParams};
@@ -21,28 +21,22 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-mod board;
-mod comm;
-mod defs;
-mod engine;
-mod evaluation;
-mod misc;
-mod movegen;
-mod search;
-
-#[cfg(feature = "extra")]
-mod extra;
-
-// use interface::console;
-use defs::ENGINE_RUN_ERRORS;
-use engine::Engine;
+use rustic_alpha::Engine;
 
 fn main() {
-    let mut engine = Engine::new();
+    let mut engine = match Engine::new() {
+        Ok(engine) => engine,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
     let result = engine.run();
 
-    match result {
-        Ok(()) => (),
-        Err(e) => println!("Error code {}: {}", e, ENGINE_RUN_ERRORS[e as usize]),
-    };
+    if let Err(e) = result {
+        if !engine.has_json_output() {
+            println!("{}", e);
+        }
+        std::process::exit(1);
+    }
 }
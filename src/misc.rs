@@ -21,8 +21,15 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
+pub mod affinity;
 pub mod bits;
-pub mod cmdline;
+pub mod format;
 pub mod parse;
-pub mod perft;
 pub mod print;
+pub mod random;
+
+#[cfg(feature = "engine")]
+pub mod cmdline;
+
+#[cfg(feature = "engine")]
+pub mod perft;
@@ -0,0 +1,202 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// affinity.rs decides how many search threads to run, and on which CPUs.
+// "auto" runs one thread per logical CPU. Running more threads than that
+// only makes the threads take turns on the CPUs, so the number is clamped.
+// With the "affinity" feature, each search thread can be pinned to its own
+// CPU: one per physical core first, so two threads don't share a core
+// through hyperthreading while other cores are idle. Pinning is only
+// implemented for Linux; on other systems, it does nothing.
+
+use std::sync::OnceLock;
+
+pub const THREADS_AUTO: &str = "auto";
+
+// The number of search threads, as requested by the user.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ThreadCount {
+    Auto,
+    Fixed(usize),
+}
+
+impl ThreadCount {
+    // Parses "auto" or a number. Zero threads is not a valid number.
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.trim().eq_ignore_ascii_case(THREADS_AUTO) {
+            return Some(ThreadCount::Auto);
+        }
+
+        match value.trim().parse::<usize>() {
+            Ok(n) if n > 0 => Some(ThreadCount::Fixed(n)),
+            _ => None,
+        }
+    }
+
+    // Returns the number of threads to use on a machine with the given
+    // number of logical CPUs, but never more than the maximum. If the
+    // request had to be clamped, the warning for the user is returned.
+    pub fn resolve(self, cpus: usize, max: usize) -> (usize, Option<String>) {
+        let available = cpus.clamp(1, max.max(1));
+
+        match self {
+            ThreadCount::Auto => (available, None),
+            ThreadCount::Fixed(n) if n > available => {
+                let msg = format!(
+                    "{} threads requested, but only {} can run at the same time; using {}",
+                    n, available, available
+                );
+                (available, Some(msg))
+            }
+            ThreadCount::Fixed(n) => (n.max(1), None),
+        }
+    }
+}
+
+// The number of logical CPUs the engine can use.
+pub fn available_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+// Returns true if this build can pin threads to CPUs.
+pub fn is_supported() -> bool {
+    cfg!(all(feature = "affinity", target_os = "linux"))
+}
+
+// The CPU for each of the given number of threads: the main search
+// thread first, followed by the helpers.
+pub fn placement(threads: usize) -> Vec<usize> {
+    cpu_order().iter().copied().cycle().take(threads).collect()
+}
+
+// Pins the calling thread to the given CPU. Returns false if this fails,
+// or if pinning is not supported.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+pub fn pin_current_thread(cpu: usize) -> bool {
+    // Safety: the CPU set is a plain bit mask, which is zeroed before
+    // use. CPU_SET ignores CPUs beyond the size of the set.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+pub fn pin_current_thread(_cpu: usize) -> bool {
+    false
+}
+
+// The logical CPUs in the order in which threads are placed on them. The
+// first CPU of every physical core comes first, then the second one of
+// every core, and so on. The order is determined once.
+fn cpu_order() -> &'static [usize] {
+    static ORDER: OnceLock<Vec<usize>> = OnceLock::new();
+
+    ORDER.get_or_init(|| {
+        // Group the logical CPUs by physical core. A CPU of which the
+        // core is unknown is taken to be a core of its own.
+        let mut cores: Vec<((usize, usize), Vec<usize>)> = Vec::new();
+        for cpu in 0..available_cpus() {
+            let core = physical_core(cpu).unwrap_or((usize::MAX, cpu));
+            match cores.iter_mut().find(|(c, _)| *c == core) {
+                Some((_, cpus)) => cpus.push(cpu),
+                None => cores.push((core, vec![cpu])),
+            }
+        }
+
+        let most = cores.iter().map(|(_, cpus)| cpus.len()).max().unwrap_or(0);
+        let mut order = Vec::new();
+        for round in 0..most {
+            order.extend(cores.iter().filter_map(|(_, cpus)| cpus.get(round)));
+        }
+
+        if order.is_empty() {
+            order.push(0);
+        }
+        order
+    })
+}
+
+// The package (socket) and core of a logical CPU. Linux shows these in
+// sysfs. Elsewhere, they are unknown.
+fn physical_core(cpu: usize) -> Option<(usize, usize)> {
+    let read = |name: &str| -> Option<usize> {
+        let path = format!("/sys/devices/system/cpu/cpu{}/topology/{}", cpu, name);
+        std::fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+
+    Some((read("physical_package_id")?, read("core_id")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_thread_counts() {
+        assert_eq!(ThreadCount::parse("auto"), Some(ThreadCount::Auto));
+        assert_eq!(ThreadCount::parse(" AUTO "), Some(ThreadCount::Auto));
+        assert_eq!(ThreadCount::parse("4"), Some(ThreadCount::Fixed(4)));
+        assert_eq!(ThreadCount::parse("0"), None);
+        assert_eq!(ThreadCount::parse("-1"), None);
+        assert_eq!(ThreadCount::parse("many"), None);
+    }
+
+    // Auto uses every CPU, up to the maximum, without a warning.
+    #[test]
+    fn auto_uses_every_cpu() {
+        assert_eq!(ThreadCount::Auto.resolve(8, 64), (8, None));
+        assert_eq!(ThreadCount::Auto.resolve(128, 64), (64, None));
+        assert_eq!(ThreadCount::Auto.resolve(0, 64), (1, None));
+        assert_eq!(ThreadCount::Auto.resolve(8, 0), (1, None));
+    }
+
+    // More threads than can run at the same time are clamped, and the
+    // user is warned.
+    #[test]
+    fn fixed_counts_are_clamped() {
+        assert_eq!(ThreadCount::Fixed(4).resolve(8, 64), (4, None));
+        assert_eq!(ThreadCount::Fixed(8).resolve(8, 64), (8, None));
+
+        let (threads, warning) = ThreadCount::Fixed(16).resolve(8, 64);
+        assert_eq!(threads, 8);
+        let warning = warning.expect("a warning");
+        assert!(warning.starts_with("16 threads requested"), "{}", warning);
+        assert!(warning.ends_with("using 8"), "{}", warning);
+
+        let (threads, warning) = ThreadCount::Fixed(100).resolve(128, 64);
+        assert_eq!(threads, 64);
+        assert!(warning.is_some());
+    }
+
+    #[test]
+    fn every_thread_gets_a_cpu() {
+        let placement = placement(2 * available_cpus() + 1);
+        assert_eq!(placement.len(), 2 * available_cpus() + 1);
+        assert!(placement.iter().all(|&cpu| cpu < available_cpus()));
+        assert_eq!(placement[0], placement[available_cpus()]);
+    }
+}
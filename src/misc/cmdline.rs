@@ -22,10 +22,12 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use crate::{
-    defs::{About, FEN_START_POSITION},
+    defs::{About, FEN_START_POSITION, MAX_PLY},
     engine::defs::EngineOptionDefaults,
+    misc::affinity::ThreadCount,
+    search::defs::BENCH_DEPTH,
 };
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
 // Consts for command line options, flags and arguments
 
@@ -52,19 +54,63 @@ impl CmdLineArgs {
     // Threads
     const THREADS_LONG: &'static str = "threads";
     const THREADS_SHORT: &'static str = "t";
-    const THREADS_HELP: &'static str = "Number of CPU-threads to use";
+    const THREADS_HELP: &'static str = "Number of CPU-threads to use, or \"auto\" for one per CPU";
     const THREADS_DEFAULT: &'static str = "1";
 
     const HASH_LONG: &'static str = "hash";
     const HASH_SHORT: &'static str = "h";
-    const HASH_HELP: &'static str = "Transposition Table size in MB";
+    const HASH_HELP: &'static str = "Transposition Table size in MB (also for perft; 0 = off)";
     const HASH_DEFAULT: &'static str = EngineOptionDefaults::HASH_DEFAULT;
 
+    // Maximum number of PV moves shown in the output
+    const MAX_PV_LENGTH_LONG: &'static str = "max-pv-length";
+    const MAX_PV_LENGTH_HELP: &'static str = "Maximum number of PV moves to print (0 = no limit)";
+    const MAX_PV_LENGTH_DEFAULT: &'static str = EngineOptionDefaults::MAX_PV_LENGTH_DEFAULT;
+
     // Quiet (no search stats updates except on depth change)
     const QUIET_LONG: &'static str = "quiet";
     const QUIET_SHORT: &'static str = "q";
     const QUIET_HELP: &'static str = "No intermediate search stats updates";
 
+    // Single search with the result printed as JSON
+    const MOVETIME_LONG: &'static str = "movetime";
+    const MOVETIME_SHORT: &'static str = "m";
+    const MOVETIME_HELP: &'static str = "Search time in ms for --json, --speedtest and --epdtest";
+    const MOVETIME_DEFAULT: &'static str = "1000";
+    const JSON_LONG: &'static str = "json";
+    const JSON_SHORT: &'static str = "j";
+    const JSON_HELP: &'static str = "Search the position once and print the result as JSON";
+
+    // Speed test with different hash sizes and numbers of threads
+    const SPEEDTEST_LONG: &'static str = "speedtest";
+    const SPEEDTEST_HELP: &'static str = "Measure search speed for several Hash and Threads values";
+    const SPEEDTEST_TIME_DEFAULT: u128 = 10_000;
+
+    // Bench (a subcommand: "rustic-alpha bench [depth]")
+    const BENCH_NAME: &'static str = "bench";
+    const BENCH_HELP: &'static str = "Search the bench positions and print the total node count";
+    const BENCH_DEPTH_NAME: &'static str = "depth";
+    const BENCH_DEPTH_HELP: &'static str = "Search depth (default 8)";
+
+    // Seed for the random number generator
+    const SEED_LONG: &'static str = "seed";
+    const SEED_HELP: &'static str =
+        "Seed for all random decisions, to replay a run (default: random)";
+
+    // Perft divide
+    const DIVIDE_LONG: &'static str = "divide";
+    const DIVIDE_HELP: &'static str = "Print the perft count of each move (with --perft)";
+
+    // Allow positions in which the side not to move is in check
+    const ALLOW_ILLEGAL_FEN_LONG: &'static str = "allow-illegal-fen";
+    const ALLOW_ILLEGAL_FEN_HELP: &'static str =
+        "Accept FENs with the side not to move in check (for debugging)";
+
+    // Pin search threads to CPUs
+    const AFFINITY_LONG: &'static str = "affinity";
+    const AFFINITY_HELP: &'static str =
+        "Pin each search thread to its own CPU (needs the \"affinity\" feature)";
+
     // Kiwipete
     const KIWI_LONG: &'static str = "kiwipete";
     const KIWI_SHORT: &'static str = "k";
@@ -78,7 +124,33 @@ impl CmdLineArgs {
     // Test
     const EPD_TEST_LONG: &'static str = "epdtest";
     const EPD_TEST_SHORT: &'static str = "e";
-    const EPD_TEST_HELP: &'static str = "Run EPD Test Suite";
+    const EPD_TEST_HELP: &'static str =
+        "Run EPD Test Suite, or test the best moves (bm/am) in the given EPD file";
+    const DEPTH_LONG: &'static str = "depth";
+    const DEPTH_HELP: &'static str = "Search depth for --epdtest <file> (instead of --movetime)";
+
+    // Perft suite
+    const PERFT_SUITE_LONG: &'static str = "perft-suite";
+    const PERFT_SUITE_SHORT: &'static str = "s";
+    const PERFT_SUITE_HELP: &'static str = "Run the perft suite in the given EPD file";
+
+    // Selfplay
+    const SELFPLAY_LONG: &'static str = "selfplay";
+    const SELFPLAY_SHORT: &'static str = "g";
+    const SELFPLAY_HELP: &'static str = "Play the given number of selfplay games";
+    const SELFPLAY_DEPTH_LONG: &'static str = "selfplay-depth";
+    const SELFPLAY_DEPTH_SHORT: &'static str = "d";
+    const SELFPLAY_DEPTH_HELP: &'static str = "Search depths of selfplay players A and B";
+    const SELFPLAY_VARIETY_LONG: &'static str = "selfplay-variety";
+    const SELFPLAY_VARIETY_HELP: &'static str =
+        "Number of random opening plies per pair of selfplay games";
+    #[cfg(feature = "extra")]
+    const SELFPLAY_DEPTH_DEFAULT: i8 = 4;
+
+    // Tuner
+    const TUNE_LONG: &'static str = "tune";
+    const TUNE_HELP: &'static str =
+        "Tune the evaluation on the labelled positions in the given file";
 }
 
 pub struct CmdLine {
@@ -88,7 +160,18 @@ pub struct CmdLine {
 impl CmdLine {
     pub fn new() -> Self {
         Self {
-            arguments: Self::get(),
+            arguments: Self::get(std::env::args_os().collect()),
+        }
+    }
+
+    // Create the command line from the given arguments instead of the
+    // ones the engine was started with. The first argument is the name
+    // of the executable.
+    #[cfg(any(test, feature = "testing"))]
+    #[allow(dead_code)]
+    pub fn from_args(args: &[&str]) -> Self {
+        Self {
+            arguments: Self::get(args.iter().map(|a| a.into()).collect()),
         }
     }
 
@@ -114,12 +197,13 @@ impl CmdLine {
             .unwrap_or(0)
     }
 
-    pub fn threads(&self) -> usize {
-        self.arguments
+    pub fn threads(&self) -> ThreadCount {
+        let value = self
+            .arguments
             .value_of(CmdLineArgs::THREADS_LONG)
-            .unwrap_or(CmdLineArgs::THREADS_DEFAULT)
-            .parse()
-            .unwrap_or(1)
+            .unwrap_or(CmdLineArgs::THREADS_DEFAULT);
+
+        ThreadCount::parse(value).unwrap_or(ThreadCount::Fixed(1))
     }
 
     pub fn hash(&self) -> usize {
@@ -130,10 +214,75 @@ impl CmdLine {
             .unwrap_or(32)
     }
 
+    pub fn max_pv_length(&self) -> usize {
+        self.arguments
+            .value_of(CmdLineArgs::MAX_PV_LENGTH_LONG)
+            .unwrap_or(CmdLineArgs::MAX_PV_LENGTH_DEFAULT)
+            .parse()
+            .unwrap_or(0)
+    }
+
+    pub fn movetime(&self) -> u128 {
+        self.arguments
+            .value_of(CmdLineArgs::MOVETIME_LONG)
+            .unwrap_or(CmdLineArgs::MOVETIME_DEFAULT)
+            .parse()
+            .unwrap_or(1000)
+    }
+
+    // The speed test searches for 10 seconds per position, unless a
+    // different time is given.
+    pub fn speedtest_time(&self) -> u128 {
+        self.arguments
+            .value_of(CmdLineArgs::MOVETIME_LONG)
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(CmdLineArgs::SPEEDTEST_TIME_DEFAULT)
+    }
+
+    // Returns the bench depth if the bench subcommand was given.
+    pub fn bench(&self) -> Option<i8> {
+        self.arguments
+            .subcommand_matches(CmdLineArgs::BENCH_NAME)
+            .map(|bench| {
+                bench
+                    .value_of(CmdLineArgs::BENCH_DEPTH_NAME)
+                    .and_then(|d| d.parse().ok())
+                    .filter(|d| *d > 0)
+                    .unwrap_or(BENCH_DEPTH)
+            })
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.arguments
+            .value_of(CmdLineArgs::SEED_LONG)
+            .and_then(|s| s.parse().ok())
+    }
+
+    pub fn has_speedtest(&self) -> bool {
+        self.arguments.is_present(CmdLineArgs::SPEEDTEST_LONG)
+    }
+
+    pub fn has_json(&self) -> bool {
+        self.arguments.is_present(CmdLineArgs::JSON_LONG)
+    }
+
     pub fn has_kiwipete(&self) -> bool {
         self.arguments.is_present(CmdLineArgs::KIWI_LONG)
     }
 
+    pub fn has_divide(&self) -> bool {
+        self.arguments.is_present(CmdLineArgs::DIVIDE_LONG)
+    }
+
+    pub fn affinity(&self) -> bool {
+        self.arguments.is_present(CmdLineArgs::AFFINITY_LONG)
+    }
+
+    pub fn allow_illegal_fen(&self) -> bool {
+        self.arguments
+            .is_present(CmdLineArgs::ALLOW_ILLEGAL_FEN_LONG)
+    }
+
     pub fn has_quiet(&self) -> bool {
         self.arguments.is_present(CmdLineArgs::QUIET_LONG)
     }
@@ -148,7 +297,78 @@ impl CmdLine {
         self.arguments.is_present(CmdLineArgs::EPD_TEST_LONG)
     }
 
-    fn get() -> ArgMatches<'static> {
+    #[cfg(feature = "extra")]
+    pub fn epd_test_file(&self) -> Option<String> {
+        self.arguments
+            .value_of(CmdLineArgs::EPD_TEST_LONG)
+            .map(|s| s.to_string())
+    }
+
+    #[cfg(feature = "extra")]
+    pub fn depth(&self) -> Option<i8> {
+        self.arguments
+            .value_of(CmdLineArgs::DEPTH_LONG)
+            .and_then(|d| d.parse().ok())
+            .filter(|d| *d > 0)
+    }
+
+    #[cfg(feature = "extra")]
+    pub fn selfplay(&self) -> usize {
+        self.arguments
+            .value_of(CmdLineArgs::SELFPLAY_LONG)
+            .unwrap_or("0")
+            .parse()
+            .unwrap_or(0)
+    }
+
+    #[cfg(feature = "extra")]
+    pub fn selfplay_depths(&self) -> (i8, i8) {
+        let default = CmdLineArgs::SELFPLAY_DEPTH_DEFAULT;
+        let depths: Vec<i8> = self
+            .arguments
+            .values_of(CmdLineArgs::SELFPLAY_DEPTH_LONG)
+            .map(|v| v.map(|d| d.parse().unwrap_or(default)).collect())
+            .unwrap_or_default();
+
+        match depths[..] {
+            [a, b] => (a, b),
+            _ => (default, default),
+        }
+    }
+
+    #[cfg(feature = "extra")]
+    pub fn selfplay_variety(&self) -> usize {
+        self.arguments
+            .value_of(CmdLineArgs::SELFPLAY_VARIETY_LONG)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    #[cfg(feature = "extra")]
+    pub fn tune(&self) -> Option<String> {
+        self.arguments
+            .value_of(CmdLineArgs::TUNE_LONG)
+            .map(|s| s.to_string())
+    }
+
+    // The perft suite option only exists in an "extra" compilation. It is
+    // also used to select the perft TT, so it is always available here.
+    pub fn perft_suite(&self) -> Option<String> {
+        self.arguments
+            .value_of(CmdLineArgs::PERFT_SUITE_LONG)
+            .map(|s| s.to_string())
+    }
+
+    // A search must be at least one ply deep: a search to depth 0 never
+    // completes an iteration, so it has no move and no score.
+    fn validate_depth(value: String) -> Result<(), String> {
+        match value.parse::<i8>() {
+            Ok(d) if (1..=MAX_PLY).contains(&d) => Ok(()),
+            _ => Err(format!("the depth must be from 1 to {}", MAX_PLY)),
+        }
+    }
+
+    fn get(args: Vec<std::ffi::OsString>) -> ArgMatches<'static> {
         let mut app = App::new(About::ENGINE)
             .version(About::VERSION)
             .author(About::AUTHOR)
@@ -194,6 +414,66 @@ impl CmdLine {
                     .takes_value(true)
                     .default_value(CmdLineArgs::HASH_DEFAULT),
             )
+            .arg(
+                Arg::with_name(CmdLineArgs::MAX_PV_LENGTH_LONG)
+                    .long(CmdLineArgs::MAX_PV_LENGTH_LONG)
+                    .help(CmdLineArgs::MAX_PV_LENGTH_HELP)
+                    .takes_value(true)
+                    .default_value(CmdLineArgs::MAX_PV_LENGTH_DEFAULT),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::MOVETIME_LONG)
+                    .short(CmdLineArgs::MOVETIME_SHORT)
+                    .long(CmdLineArgs::MOVETIME_LONG)
+                    .help(CmdLineArgs::MOVETIME_HELP)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::JSON_LONG)
+                    .short(CmdLineArgs::JSON_SHORT)
+                    .long(CmdLineArgs::JSON_LONG)
+                    .help(CmdLineArgs::JSON_HELP)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::SPEEDTEST_LONG)
+                    .long(CmdLineArgs::SPEEDTEST_LONG)
+                    .help(CmdLineArgs::SPEEDTEST_HELP)
+                    .takes_value(false),
+            )
+            .subcommand(
+                SubCommand::with_name(CmdLineArgs::BENCH_NAME)
+                    .about(CmdLineArgs::BENCH_HELP)
+                    .arg(
+                        Arg::with_name(CmdLineArgs::BENCH_DEPTH_NAME)
+                            .help(CmdLineArgs::BENCH_DEPTH_HELP)
+                            .index(1),
+                    ),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::SEED_LONG)
+                    .long(CmdLineArgs::SEED_LONG)
+                    .help(CmdLineArgs::SEED_HELP)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::DIVIDE_LONG)
+                    .long(CmdLineArgs::DIVIDE_LONG)
+                    .help(CmdLineArgs::DIVIDE_HELP)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::ALLOW_ILLEGAL_FEN_LONG)
+                    .long(CmdLineArgs::ALLOW_ILLEGAL_FEN_LONG)
+                    .help(CmdLineArgs::ALLOW_ILLEGAL_FEN_HELP)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(CmdLineArgs::AFFINITY_LONG)
+                    .long(CmdLineArgs::AFFINITY_LONG)
+                    .help(CmdLineArgs::AFFINITY_HELP)
+                    .takes_value(false),
+            )
             .arg(
                 Arg::with_name(CmdLineArgs::KIWI_LONG)
                     .long(CmdLineArgs::KIWI_LONG)
@@ -223,10 +503,68 @@ impl CmdLine {
                         .short(CmdLineArgs::EPD_TEST_SHORT)
                         .long(CmdLineArgs::EPD_TEST_LONG)
                         .help(CmdLineArgs::EPD_TEST_HELP)
-                        .takes_value(false),
+                        .takes_value(true)
+                        .min_values(0)
+                        .max_values(1),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::DEPTH_LONG)
+                        .long(CmdLineArgs::DEPTH_LONG)
+                        .help(CmdLineArgs::DEPTH_HELP)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::PERFT_SUITE_LONG)
+                        .short(CmdLineArgs::PERFT_SUITE_SHORT)
+                        .long(CmdLineArgs::PERFT_SUITE_LONG)
+                        .help(CmdLineArgs::PERFT_SUITE_HELP)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::SELFPLAY_LONG)
+                        .short(CmdLineArgs::SELFPLAY_SHORT)
+                        .long(CmdLineArgs::SELFPLAY_LONG)
+                        .help(CmdLineArgs::SELFPLAY_HELP)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::SELFPLAY_DEPTH_LONG)
+                        .short(CmdLineArgs::SELFPLAY_DEPTH_SHORT)
+                        .long(CmdLineArgs::SELFPLAY_DEPTH_LONG)
+                        .help(CmdLineArgs::SELFPLAY_DEPTH_HELP)
+                        .takes_value(true)
+                        .number_of_values(2)
+                        .validator(CmdLine::validate_depth),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::SELFPLAY_VARIETY_LONG)
+                        .long(CmdLineArgs::SELFPLAY_VARIETY_LONG)
+                        .help(CmdLineArgs::SELFPLAY_VARIETY_HELP)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name(CmdLineArgs::TUNE_LONG)
+                        .long(CmdLineArgs::TUNE_LONG)
+                        .help(CmdLineArgs::TUNE_HELP)
+                        .takes_value(true),
                 );
         }
 
-        app.get_matches()
+        app.get_matches_from(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_must_be_at_least_one() {
+        assert!(CmdLine::validate_depth(String::from("1")).is_ok());
+        assert!(CmdLine::validate_depth(MAX_PLY.to_string()).is_ok());
+        assert!(CmdLine::validate_depth(String::from("0")).is_err());
+        assert!(CmdLine::validate_depth(String::from("-3")).is_err());
+        assert!(CmdLine::validate_depth(String::from("200")).is_err());
+        assert!(CmdLine::validate_depth(String::from("four")).is_err());
     }
 }
@@ -0,0 +1,740 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Text representations of the board, moves and game history. These
+// functions only build strings; printing them (or sending them to a GUI,
+// or comparing them) is left to the caller.
+
+use crate::{
+    board::{
+        defs::{Pieces, RangeOf, PIECE_CHAR_CAPS, PIECE_NAME, SQUARE_NAME},
+        Board,
+    },
+    defs::{Bitboard, Castling, NrOf, Sides, Square, FEN_START_POSITION},
+    evaluation::defs::{EndgameScale, EvalTrace, PHASE_MAX},
+    misc::bits,
+    movegen::{
+        defs::{Exchange, Move, MoveList, SEE_ORDER},
+        MoveGenerator,
+    },
+};
+use std::fmt::Write;
+
+type AsciiBoard = [char; NrOf::SQUARES];
+
+const CHAR_ES: char = '.';
+const CHAR_WK: char = 'K';
+const CHAR_WQ: char = 'Q';
+const CHAR_WR: char = 'R';
+const CHAR_WB: char = 'B';
+const CHAR_WN: char = 'N';
+const CHAR_WP: char = 'I';
+const CHAR_BK: char = 'k';
+const CHAR_BQ: char = 'q';
+const CHAR_BR: char = 'r';
+const CHAR_BB: char = 'b';
+const CHAR_BN: char = 'n';
+const CHAR_BP: char = 'i';
+
+// The current position as an ASCII-board, followed by its metadata.
+pub fn position(board: &Board, mark_square: Option<u8>) -> String {
+    let mut ascii_board: AsciiBoard = [CHAR_ES; NrOf::SQUARES];
+    let mut s = String::new();
+
+    bitboards_to_ascii(board, &mut ascii_board);
+    grid(&mut s, &ascii_board, mark_square);
+    metadata(&mut s, board);
+
+    s
+}
+
+// A bitboard (64-bit number) in an 8x8 grid.
+pub fn bitboard(bitboard: Bitboard, mark_square: Option<u8>) -> String {
+    const SQUARE_OCCUPIED: char = '1';
+    let mut ascii_board: AsciiBoard = [CHAR_ES; NrOf::SQUARES];
+    let mut s = String::new();
+
+    put_character_on_square(bitboard, &mut ascii_board, SQUARE_OCCUPIED);
+    grid(&mut s, &ascii_board, mark_square);
+
+    s
+}
+
+// The captures of a static exchange, each followed by the material
+// balance for the side that captured first, and then the outcome.
+pub fn exchange(exchange: &Exchange) -> String {
+    let mut s = format!("Exchange on {}\n", SQUARE_NAME[exchange.square]);
+
+    for (i, c) in exchange.captures.iter().enumerate() {
+        let side = if c.side == Sides::WHITE {
+            "White"
+        } else {
+            "Black"
+        };
+        let takes = if c.captured == Pieces::NONE { '-' } else { 'x' };
+        let notation = format!(
+            "{}{}{}{}",
+            PIECE_CHAR_CAPS[c.piece], SQUARE_NAME[c.from], takes, SQUARE_NAME[exchange.square]
+        );
+        let _ = writeln!(
+            s,
+            "{:>2}. {:<5} {:<7} {:>+5}",
+            i + 1,
+            side,
+            notation,
+            c.balance
+        );
+    }
+
+    let _ = writeln!(s, "SEE: {:+}", exchange.value);
+    s
+}
+
+// The pieces of both sides that attack a square, least valuable first.
+// Each side's attackers are also shown on a grid.
+pub fn attackers(board: &Board, square: Square, bb_attackers: Bitboard) -> String {
+    let mut s = format!("Attackers of {}\n", SQUARE_NAME[square]);
+
+    for side in [Sides::WHITE, Sides::BLACK] {
+        let bb_side = bb_attackers & board.bb_side[side];
+        let mut pieces: Vec<String> = Vec::new();
+
+        for piece in SEE_ORDER {
+            let mut bb_pieces = bb_side & board.bb_pieces[side][piece];
+            while bb_pieces > 0 {
+                let from = bits::next(&mut bb_pieces);
+                pieces.push(format!("{}{}", PIECE_CHAR_CAPS[piece], SQUARE_NAME[from]));
+            }
+        }
+
+        let name = if side == Sides::WHITE {
+            "White"
+        } else {
+            "Black"
+        };
+        let list = if pieces.is_empty() {
+            String::from("-")
+        } else {
+            pieces.join(" ")
+        };
+        let _ = writeln!(s, "\n{}: {}", name, list);
+        s.push_str(&bitboard(bb_side, Some(square as u8)));
+    }
+
+    s
+}
+
+// The moves of the game in SAN, as numbered move pairs, followed by the
+// result.
+pub fn history_san(board: &Board, mg: &MoveGenerator, result: &str) -> String {
+    let (_, tokens) = movetext(board, mg);
+    let mut s = tokens.join(" ");
+
+    if tokens.is_empty() {
+        s.push_str("No moves played.");
+    }
+
+    format!("{}\nResult: {}\n", s, result)
+}
+
+// The game as a PGN. The tags are written in the given order, followed by
+// SetUp and FEN if the game didn't start from the initial position. The
+// movetext ends with the result, and is wrapped at 80 columns.
+pub fn pgn(board: &Board, mg: &MoveGenerator, tags: &[(&str, String)], result: &str) -> String {
+    const LINE_LENGTH: usize = 80;
+    let (start_fen, mut tokens) = movetext(board, mg);
+    let mut s = String::new();
+    let mut line = String::new();
+
+    for (name, value) in tags.iter() {
+        writeln!(s, "[{} \"{}\"]", name, value).ok();
+    }
+    if start_fen != FEN_START_POSITION {
+        writeln!(s, "[SetUp \"1\"]").ok();
+        writeln!(s, "[FEN \"{}\"]", start_fen).ok();
+    }
+    s.push('\n');
+
+    tokens.push(result.to_string());
+    for token in tokens.iter() {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+            writeln!(s, "{}", line).ok();
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(token);
+    }
+    writeln!(s, "{}", line).ok();
+
+    s
+}
+
+// Replays the game on a scratch board, starting from the position before
+// the first move in the history. Returns the FEN of that position, and
+// the moves in SAN with move numbers in front of them, one per token.
+fn movetext(board: &Board, mg: &MoveGenerator) -> (String, Vec<String>) {
+    let length = board.history.len();
+    let mut scratch = board.clone();
+    let mut tokens: Vec<String> = Vec::new();
+
+    while scratch.unmake() {}
+    let start_fen = scratch.fen_write();
+
+    for i in 0..length {
+        let m = board.history.get_ref(i).next_move;
+        let white_to_move = scratch.us() == Sides::WHITE;
+        let fmn = scratch.game_state.fullmove_number;
+
+        if white_to_move {
+            tokens.push(format!("{}.", fmn));
+        } else if i == 0 {
+            tokens.push(format!("{}...", fmn));
+        }
+
+        tokens.push(move_san(&scratch, mg, m));
+
+        let made = scratch.make(m, mg);
+        debug_assert!(made);
+    }
+
+    (start_fen, tokens)
+}
+
+// The game states in the board's history, one per line.
+pub fn history(board: &Board) -> String {
+    let length = board.history.len();
+    let mut s = String::new();
+
+    if length == 0 {
+        s.push_str("No history available.\n");
+    }
+
+    for i in 0..length {
+        let h = board.history.get_ref(i);
+        writeln!(s, "{:<3}| ply: {} {}", i, i + 1, h.as_string()).ok();
+    }
+
+    s
+}
+
+// All the moves in a move list, one per line.
+pub fn movelist(ml: &MoveList) -> String {
+    let mut s = String::new();
+
+    for i in 0..ml.len() {
+        writeln!(s, "{}", move_data(ml.get_move(i), i)).ok();
+    }
+
+    s
+}
+
+// Decoded move data.
+pub fn move_data(m: Move, nr: u8) -> String {
+    format!(
+        "{}. Move: {}{}{} capture: {}, promotion: {}, ep: {}, double: {}, castling: {}, score: {}",
+        nr + 1,
+        PIECE_CHAR_CAPS[m.piece()],
+        SQUARE_NAME[m.from()],
+        SQUARE_NAME[m.to()],
+        PIECE_NAME[m.captured()],
+        PIECE_NAME[m.promoted()],
+        m.en_passant(),
+        m.double_step(),
+        m.castling(),
+        m.get_sort_score(),
+    )
+}
+
+// A move in Standard Algebraic Notation, for the given position. The
+// move is expected to be legal in that position.
+pub fn move_san(board: &Board, mg: &MoveGenerator, m: Move) -> String {
+    let mut s = String::new();
+    let piece = m.piece();
+    let from = m.from();
+    let to = m.to();
+    let is_capture = m.captured() != Pieces::NONE || m.en_passant();
+
+    if m.is_null() {
+        return String::from("--");
+    }
+
+    if m.castling() {
+        s.push_str(if to > from { "O-O" } else { "O-O-O" });
+    } else if piece == Pieces::PAWN {
+        // Pawn captures start with the file the pawn came from.
+        if is_capture {
+            write!(s, "{}x", &SQUARE_NAME[from][..1]).ok();
+        }
+        s.push_str(SQUARE_NAME[to]);
+        if m.promoted() != Pieces::NONE {
+            write!(s, "={}", PIECE_CHAR_CAPS[m.promoted()]).ok();
+        }
+    } else {
+        s.push_str(PIECE_CHAR_CAPS[piece]);
+        s.push_str(&disambiguation(board, mg, m));
+        if is_capture {
+            s.push('x');
+        }
+        s.push_str(SQUARE_NAME[to]);
+    }
+
+    // Play the move on a scratch board to see if it gives check or mate.
+    let mut after = board.clone();
+    if after.make(m, mg) {
+        let us = after.us();
+        if mg.square_attacked(&after, after.opponent(), after.king_square(us)) {
+            let is_mate = after.legal_moves_iter(mg).next().is_none();
+            s.push(if is_mate { '#' } else { '+' });
+        }
+    }
+
+    s
+}
+
+// If other pieces of the same type can also move to the to-square, SAN
+// adds the from-file, the from-rank, or both, to tell the moves apart.
+fn disambiguation(board: &Board, mg: &MoveGenerator, m: Move) -> String {
+    let from = SQUARE_NAME[m.from()];
+    let others: Vec<Move> = board
+        .legal_moves_iter(mg)
+        .filter(|x| x.piece() == m.piece() && x.to() == m.to() && x.from() != m.from())
+        .collect();
+
+    if others.is_empty() {
+        return String::new();
+    }
+
+    let same_file = others.iter().any(|x| x.from() % 8 == m.from() % 8);
+    let same_rank = others.iter().any(|x| x.from() / 8 == m.from() / 8);
+
+    match (same_file, same_rank) {
+        (false, _) => from[..1].to_string(),
+        (true, false) => from[1..].to_string(),
+        (true, true) => from.to_string(),
+    }
+}
+
+// The evaluation trace as a table with a row per term, and columns for
+// white, black, and the total from white's point of view.
+pub fn eval_trace(trace: &EvalTrace) -> String {
+    let mut s = String::new();
+    let line = format!("{:-<20}+{:-<8}+{:-<8}+{:-<8}", "", "", "", "");
+
+    writeln!(
+        s,
+        "{:<20}|{:>7} |{:>7} |{:>7}",
+        "Term", "White", "Black", "Total"
+    )
+    .ok();
+    writeln!(s, "{}", line).ok();
+    for term in trace.terms.iter() {
+        writeln!(
+            s,
+            "{:<20}|{:>7} |{:>7} |{:>7}",
+            term.name, term.white, term.black, term.total
+        )
+        .ok();
+    }
+    writeln!(s, "{}", line).ok();
+    writeln!(
+        s,
+        "{:<20}{}/{}",
+        "Phase value:", trace.phase_value, PHASE_MAX
+    )
+    .ok();
+    writeln!(
+        s,
+        "{:<20}{}/{}",
+        "Endgame scale:",
+        trace.scale,
+        EndgameScale::NORMAL
+    )
+    .ok();
+    writeln!(s, "{:<20}{} (white)", "Total:", trace.total).ok();
+    writeln!(s, "{:<20}{} (side to move)", "Evaluation:", trace.score()).ok();
+
+    s
+}
+
+// Converts castling permissions to a string.
+pub fn castling_as_string(permissions: u8) -> String {
+    let mut castling_as_string: String = String::from("");
+    let p = permissions;
+
+    castling_as_string += if p & Castling::WK > 0 { "K" } else { "" };
+    castling_as_string += if p & Castling::WQ > 0 { "Q" } else { "" };
+    castling_as_string += if p & Castling::BK > 0 { "k" } else { "" };
+    castling_as_string += if p & Castling::BQ > 0 { "q" } else { "" };
+
+    if castling_as_string.is_empty() {
+        castling_as_string = String::from("-");
+    }
+
+    castling_as_string
+}
+
+// Create a printable ASCII-board out of bitboards.
+fn bitboards_to_ascii(board: &Board, ascii_board: &mut AsciiBoard) {
+    let bb_w = board.bb_pieces[Sides::WHITE];
+    let bb_b = board.bb_pieces[Sides::BLACK];
+
+    for (piece, (w, b)) in bb_w.iter().zip(bb_b.iter()).enumerate() {
+        match piece {
+            Pieces::KING => {
+                put_character_on_square(*w, ascii_board, CHAR_WK);
+                put_character_on_square(*b, ascii_board, CHAR_BK);
+            }
+            Pieces::QUEEN => {
+                put_character_on_square(*w, ascii_board, CHAR_WQ);
+                put_character_on_square(*b, ascii_board, CHAR_BQ);
+            }
+            Pieces::ROOK => {
+                put_character_on_square(*w, ascii_board, CHAR_WR);
+                put_character_on_square(*b, ascii_board, CHAR_BR);
+            }
+            Pieces::BISHOP => {
+                put_character_on_square(*w, ascii_board, CHAR_WB);
+                put_character_on_square(*b, ascii_board, CHAR_BB);
+            }
+            Pieces::KNIGHT => {
+                put_character_on_square(*w, ascii_board, CHAR_WN);
+                put_character_on_square(*b, ascii_board, CHAR_BN);
+            }
+            Pieces::PAWN => {
+                put_character_on_square(*w, ascii_board, CHAR_WP);
+                put_character_on_square(*b, ascii_board, CHAR_BP);
+            }
+            _ => (),
+        }
+    }
+}
+
+// This function actually puts the correct character into the ASCII board.
+fn put_character_on_square(bitboard: Bitboard, ascii_board: &mut AsciiBoard, character: char) {
+    for (i, square) in ascii_board.iter_mut().enumerate() {
+        if (bitboard >> i) & 1 == 1 {
+            *square = character;
+        }
+    }
+}
+
+// Writes the ASCII-board as a grid with coordinates. Optionally mark one
+// square.
+fn grid(s: &mut String, ascii_board: &AsciiBoard, mark_square: Option<u8>) {
+    let coordinate_alpha: &str = "ABCDEFGH";
+    let mut coordinate_digit = NrOf::FILES;
+
+    s.push('\n');
+    for current_rank in RangeOf::RANKS.rev() {
+        write!(s, "{}   ", coordinate_digit).ok();
+        for current_file in RangeOf::FILES {
+            let square = (current_rank as usize * NrOf::FILES) + current_file as usize;
+            let character = ascii_board[square];
+            if mark_square == Some(square as u8) {
+                // \x1b[0;35m is magenta
+                write!(s, "\x1b[0;35m{} \x1b[0m", character).ok();
+            } else {
+                write!(s, "{} ", character).ok();
+            }
+        }
+        s.push('\n');
+        coordinate_digit -= 1;
+    }
+    s.push('\n');
+    s.push_str("    ");
+    for c in coordinate_alpha.chars() {
+        write!(s, "{} ", c).ok();
+    }
+    s.push_str("\n\n");
+}
+
+// Writes all of the metadata about the position.
+fn metadata(s: &mut String, board: &Board) {
+    let is_white = (board.game_state.active_color as usize) == Sides::WHITE;
+    let active_color = if is_white { "White" } else { "Black" };
+    let castling = castling_as_string(board.game_state.castling);
+    let en_passant = match board.game_state.en_passant {
+        Some(ep) => SQUARE_NAME[ep as usize],
+        None => "-",
+    };
+    let hmc = board.game_state.halfmove_clock;
+    let fmn = board.game_state.fullmove_number;
+
+    writeln!(
+        s,
+        "{:<20}{:x}",
+        "Zobrist key:", board.game_state.zobrist_key
+    )
+    .ok();
+    writeln!(s, "{:<20}{}", "Active Color:", active_color).ok();
+    writeln!(s, "{:<20}{}", "Castling:", castling).ok();
+    writeln!(s, "{:<20}{}", "En Passant:", en_passant).ok();
+    writeln!(s, "{:<20}{}", "Half-move clock:", hmc).ok();
+    writeln!(s, "{:<20}{}", "Full-move number:", fmn).ok();
+    writeln!(s, "{:<20}{}", "FEN:", board.fen_write()).ok();
+    s.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::defs::MoveType;
+
+    // Sets up the position and plays the moves, given in coordinates.
+    fn game(fen: &str, moves: &[&str]) -> Board {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        for m in moves.iter() {
+            let m = board
+                .legal_moves_iter(&mg)
+                .find(|l| l.as_string() == *m)
+                .expect(m);
+            board.make(m, &mg);
+        }
+        board
+    }
+
+    #[test]
+    fn pgn_of_a_short_game() {
+        let moves = ["e2e4", "e7e5", "f1c4", "b8c6", "d1h5", "g8f6", "h5f7"];
+        let board = game(FEN_START_POSITION, &moves);
+        let tags = [
+            ("Event", String::from("Test")),
+            ("White", String::from("Rustic")),
+            ("Black", String::from("Opponent")),
+        ];
+
+        let expected = "\
+[Event \"Test\"]
+[White \"Rustic\"]
+[Black \"Opponent\"]
+
+1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0
+";
+        let pgn = pgn(&board, &MoveGenerator::shared(), &tags, "1-0");
+        assert_eq!(pgn, expected);
+    }
+
+    // A game that starts from a position with black to move gets the FEN
+    // as a tag, and the first move number is written with dots.
+    #[test]
+    fn pgn_from_a_position() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 30";
+        let board = game(fen, &["e8d7", "e2e4", "d7e6"]);
+
+        let expected = "\
+[Result \"*\"]
+[SetUp \"1\"]
+[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 30\"]
+
+30... Kd7 31. e4 Ke6 *
+";
+        let tags = [("Result", String::from("*"))];
+        let pgn = pgn(&board, &MoveGenerator::shared(), &tags, "*");
+        assert_eq!(pgn, expected);
+    }
+
+    // The movetext is wrapped at 80 columns.
+    #[test]
+    fn pgn_lines_are_wrapped() {
+        let moves: Vec<&str> = ["g1f3", "g8f6", "f3g1", "f6g8"]
+            .iter()
+            .cycle()
+            .take(24)
+            .copied()
+            .collect();
+        let board = game(FEN_START_POSITION, &moves);
+
+        let expected = "
+1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8 5. Nf3 Nf6 6. Ng1 Ng8 7. Nf3 Nf6 8.
+Ng1 Ng8 9. Nf3 Nf6 10. Ng1 Ng8 11. Nf3 Nf6 12. Ng1 Ng8 1/2-1/2
+";
+        let pgn = pgn(&board, &MoveGenerator::shared(), &[], "1/2-1/2");
+        assert_eq!(pgn, expected);
+    }
+
+    #[test]
+    fn position_snapshot() {
+        let board = game("4k3/8/8/8/8/8/4P3/4K2R w K - 0 1", &[]);
+        let expected = "
+8   . . . . k . . . 
+7   . . . . . . . . 
+6   . . . . . . . . 
+5   . . . . . . . . 
+4   . . . . . . . . 
+3   . . . . . . . . 
+2   . . . . I . . . 
+1   . . . . K . . R 
+
+    A B C D E F G H 
+
+Zobrist key:        34e407505b48031e
+Active Color:       White
+Castling:           K
+En Passant:         -
+Half-move clock:    0
+Full-move number:   1
+FEN:                4k3/8/8/8/8/8/4P3/4K2R w K - 0 1
+
+";
+        assert_eq!(position(&board, None), expected);
+
+        // A marked square is shown in magenta.
+        let marked = expected.replace("2   . . . . I ", "2   . . . . \x1b[0;35mI \x1b[0m");
+        assert_eq!(position(&board, Some(12)), marked);
+    }
+
+    // The raw move data, in the order the move generator produces it.
+    #[test]
+    fn movelist_snapshot() {
+        let board = game("1n5k/P7/8/8/8/8/8/K7 w - - 0 1", &[]);
+        let mut ml = MoveList::new();
+        let mg = MoveGenerator::shared();
+        mg.generate_moves(&board, &mut ml, MoveType::All);
+
+        let expected = "\
+1. Move: Ka1b1 capture: -, promotion: -, ep: false, double: false, castling: false, score: 0
+2. Move: Ka1a2 capture: -, promotion: -, ep: false, double: false, castling: false, score: 0
+3. Move: Ka1b2 capture: -, promotion: -, ep: false, double: false, castling: false, score: 0
+4. Move: a7a8 capture: -, promotion: Queen, ep: false, double: false, castling: false, score: 0
+5. Move: a7a8 capture: -, promotion: Rook, ep: false, double: false, castling: false, score: 0
+6. Move: a7a8 capture: -, promotion: Bishop, ep: false, double: false, castling: false, score: 0
+7. Move: a7a8 capture: -, promotion: Knight, ep: false, double: false, castling: false, score: 0
+8. Move: a7b8 capture: Knight, promotion: Queen, ep: false, double: false, castling: false, score: 0
+9. Move: a7b8 capture: Knight, promotion: Rook, ep: false, double: false, castling: false, score: 0
+10. Move: a7b8 capture: Knight, promotion: Bishop, ep: false, double: false, castling: false, score: 0
+11. Move: a7b8 capture: Knight, promotion: Knight, ep: false, double: false, castling: false, score: 0
+";
+        assert_eq!(movelist(&ml), expected);
+    }
+
+    // The game states of the history. The piece-square values in each
+    // line change with the evaluation, so they are left out here.
+    #[test]
+    fn history_snapshot() {
+        let board = game(FEN_START_POSITION, &[]);
+        assert_eq!(history(&board), "No history available.\n");
+
+        let board = game(FEN_START_POSITION, &["e2e4", "e7e5"]);
+        let expected = [
+            (
+                "0  | ply: 1 zk: 2bfacd1095943841 ac: 0 cperm: KQkq ep: - hmc: 0 fmn: 1, ",
+                " next: e2e4",
+            ),
+            (
+                "1  | ply: 2 zk: 9130af5e06b79ec7 ac: 1 cperm: KQkq ep: e3 hmc: 0 fmn: 1, ",
+                " next: e7e5",
+            ),
+        ];
+
+        let history = history(&board);
+        let lines: Vec<&str> = history.lines().collect();
+        assert_eq!(lines.len(), expected.len());
+        for (line, (start, end)) in lines.iter().zip(expected.iter()) {
+            assert!(line.starts_with(start) && line.ends_with(end), "{}", line);
+        }
+    }
+
+    // Castling on both sides, a promotion and its recapture, a rook move
+    // that needs the file to tell both rooks apart, and a check.
+    #[test]
+    fn history_san_snapshot() {
+        let mg = MoveGenerator::shared();
+        let board = game(FEN_START_POSITION, &[]);
+        assert_eq!(
+            history_san(&board, &mg, "*"),
+            "No moves played.\nResult: *\n"
+        );
+
+        let fen = "r3k3/7P/8/8/8/8/8/R3K2R w KQq - 0 1";
+        let moves = ["e1g1", "e8c8", "h7h8q", "d8h8", "a1d1", "h8h1"];
+        let board = game(fen, &moves);
+        assert_eq!(
+            history_san(&board, &mg, "*"),
+            "1. O-O O-O-O 2. h8=Q Rxh8 3. Rad1 Rh1+\nResult: *\n"
+        );
+
+        // A game from a position with black to move, starting with an
+        // underpromotion.
+        let fen = "7k/8/8/8/8/8/6p1/4K3 b - - 0 60";
+        let board = game(fen, &["g2g1n", "e1f2", "g1h3"]);
+        assert_eq!(
+            history_san(&board, &mg, "1/2-1/2 (insufficient material)"),
+            "60... g1=N 61. Kf2 Nh3+\nResult: 1/2-1/2 (insufficient material)\n"
+        );
+    }
+
+    #[test]
+    fn exchange_snapshot() {
+        let mg = MoveGenerator::shared();
+        let board = game(
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+            &[],
+        );
+        let m = board
+            .legal_moves_iter(&mg)
+            .find(|l| l.as_string() == "d3e5")
+            .expect("legal move");
+
+        let expected = "\
+Exchange on e5
+ 1. White Nd3xe5   +100
+ 2. Black Nd7xe5   -220
+ 3. White Re2xe5   +100
+ 4. Black Bf6xe5   -400
+ 5. White Qe1xe5    -70
+ 6. Black Qh8xe5   -970
+SEE: -220
+";
+        assert_eq!(exchange(&mg.see(&board, m)), expected);
+
+        let board = game("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", &[]);
+        let m = board
+            .legal_moves_iter(&mg)
+            .find(|l| l.as_string() == "e5d6")
+            .expect("legal move");
+        let expected = "Exchange on d6\n 1. White e5xd6    +100\nSEE: +100\n";
+        assert_eq!(exchange(&mg.see(&board, m)), expected);
+    }
+
+    #[test]
+    fn attackers_snapshot() {
+        let mg = MoveGenerator::shared();
+        let board = game(
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+            &[],
+        );
+        let bb_attackers = mg.attackers_to(&board, 36, board.occupancy());
+        let text = attackers(&board, 36, bb_attackers);
+        let lists: Vec<&str> = text
+            .lines()
+            .filter(|l| l.starts_with("White") || l.starts_with("Black"))
+            .collect();
+
+        assert!(text.starts_with("Attackers of e5\n"));
+        assert_eq!(lists, vec!["White: Nd3 Re2", "Black: Nd7 Bf6"]);
+    }
+}
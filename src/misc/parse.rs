@@ -21,22 +21,75 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use crate::board::defs::{Pieces, SQUARE_NAME};
+use crate::board::{
+    defs::{Pieces, SQUARE_NAME},
+    Board,
+};
 use crate::defs::{Piece, Square};
+use crate::movegen::{
+    defs::{Move, NULL_MOVE_STRING},
+    MoveGenerator,
+};
 use if_chain::if_chain;
 
-pub type PotentialMove = (Square, Square, Piece);
+// From-square, to-square, and the promotion piece if one was given.
+pub type PotentialMove = (Square, Square, Option<Piece>);
 pub type ParseMoveResult = Result<PotentialMove, ()>;
 
+// The null move "0000" is parsed into a potential move with the same
+// from- and to-square. No real move can ever match it.
+pub const NULL_POTENTIAL_MOVE: PotentialMove = (0, 0, None);
+
+// Reasons why a move in Standard Algebraic Notation can't be played.
+#[derive(PartialEq, Debug)]
+pub enum SanError {
+    Invalid,   // The text is not a move in SAN
+    NotLegal,  // No legal move matches
+    Ambiguous, // More than one legal move matches
+}
+
+// Check and mate signs, annotations, and the optional "e.p." after an
+// en-passant capture are allowed in SAN, but don't change the move.
+const SAN_SUFFIXES: [char; 4] = ['+', '#', '!', '?'];
+const SAN_EN_PASSANT: &str = "e.p.";
+const SAN_CAPTURE: char = 'x';
+const SAN_PROMOTION: char = '=';
+
+// Byte order mark. Some Windows programs put this in front of the first
+// line they send or write to a file.
+const BOM: char = '\u{feff}';
+
+// Cleans up a line of protocol input: byte order marks are removed, CR
+// and other control characters count as whitespace, and whitespace is
+// trimmed and collapsed into single spaces. The parsers can then simply
+// split on spaces.
+pub fn normalize_input(input: &str) -> String {
+    input
+        .chars()
+        .filter(|&c| c != BOM)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
 pub fn algebraic_move_to_number(m: &str) -> ParseMoveResult {
     let lower_case_move = m.to_ascii_lowercase();
-    let mut potential_move: PotentialMove = (0, 0, Pieces::NONE);
+    let mut potential_move: PotentialMove = (0, 0, None);
+
+    // Recognize the null move before trying to parse squares.
+    if m == NULL_MOVE_STRING {
+        return Ok(NULL_POTENTIAL_MOVE);
+    }
 
     // Assume parsing the move will fail.
     let mut parse_move_result: ParseMoveResult = Err(());
 
-    // Get the "from" and "to" squares from the move stirng.
-    if m.len() == 4 || m.len() == 5 {
+    // Get the "from" and "to" squares from the move stirng. Only ASCII
+    // strings can be moves; this also makes sure the string can be sliced
+    // at any byte.
+    if m.is_ascii() && (m.len() == 4 || m.len() == 5) {
         if_chain! {
             // If converstion from algebraic square to number succeeds...
             if let Some(f) = algebraic_square_to_number(&lower_case_move[0..=1]);
@@ -63,7 +116,7 @@ pub fn algebraic_move_to_number(m: &str) -> ParseMoveResult {
         // If the conversion from character to promotion piece succeeds...
         if let Some(p) = promotion_piece_letter_to_number(c) {
             // ...save the result
-            potential_move.2 = p;
+            potential_move.2 = Some(p);
 
             // and set the parsing result to Ok again.
             parse_move_result = Ok(potential_move);
@@ -103,3 +156,313 @@ pub fn promotion_piece_letter_to_number(piece_letter: char) -> Option<Piece> {
     // Return the piece if found, or None.
     piece
 }
+
+// Finds the legal move for a move in Standard Algebraic Notation, such as
+// "Nbd2", "R1e2", "exd6", "e8=Q", "bxa1=N+" or "O-O". More disambiguation
+// than needed ("Ngf3") is accepted. For pawns, the from-file is the file
+// of the to-square if it's not given, so "d6" is always a push.
+pub fn san_to_move(board: &Board, mg: &MoveGenerator, san: &str) -> Result<Move, SanError> {
+    let san: String = san.chars().filter(|c| !c.is_whitespace()).collect();
+    let san = san.trim_end_matches(&SAN_SUFFIXES[..]);
+    let san = san.strip_suffix(SAN_EN_PASSANT).unwrap_or(san);
+
+    if !san.is_ascii() || san.is_empty() {
+        return Err(SanError::Invalid);
+    }
+
+    // Castling can be written with letters O or zeros.
+    let castling = match &san.replace('0', "O")[..] {
+        "O-O" => Some(true),
+        "O-O-O" => Some(false),
+        _ => None,
+    };
+    if let Some(kingside) = castling {
+        let mut moves = board
+            .legal_moves_iter(mg)
+            .filter(|m| m.castling() && (m.to() > m.from()) == kingside);
+        return moves.next().ok_or(SanError::NotLegal);
+    }
+
+    // The moving piece is in front, the promotion piece at the end.
+    let mut rest = san;
+    let piece = match san_piece(rest.as_bytes()[0]) {
+        Some(p) => {
+            rest = &rest[1..];
+            p
+        }
+        None => Pieces::PAWN,
+    };
+    let promoted = match rest.bytes().last().and_then(san_piece) {
+        Some(p) if piece == Pieces::PAWN => {
+            rest = rest[..rest.len() - 1].trim_end_matches(SAN_PROMOTION);
+            p
+        }
+        _ => Pieces::NONE,
+    };
+
+    // Then the to-square is at the end, and what is left in the middle
+    // are the capture sign and the (partial) from-square.
+    if rest.len() < 2 {
+        return Err(SanError::Invalid);
+    }
+    let (middle, target) = rest.split_at(rest.len() - 2);
+    let to = algebraic_square_to_number(target).ok_or(SanError::Invalid)?;
+    let is_capture = middle.ends_with(SAN_CAPTURE);
+    let middle = middle.strip_suffix(SAN_CAPTURE).unwrap_or(middle);
+
+    let mut file: Option<usize> = None;
+    let mut rank: Option<usize> = None;
+    for c in middle.bytes() {
+        match c {
+            b'a'..=b'h' if file.is_none() && rank.is_none() => file = Some((c - b'a') as usize),
+            b'1'..=b'8' if rank.is_none() => rank = Some((c - b'1') as usize),
+            _ => return Err(SanError::Invalid),
+        }
+    }
+    if piece == Pieces::PAWN && file.is_none() {
+        file = Some(to % 8);
+    }
+
+    let mut moves = board.legal_moves_iter(mg).filter(|m| {
+        let captures = m.captured() != Pieces::NONE || m.en_passant();
+        !m.castling()
+            && m.piece() == piece
+            && m.to() == to
+            && m.promoted() == promoted
+            && file.is_none_or(|f| m.from() % 8 == f)
+            && rank.is_none_or(|r| m.from() / 8 == r)
+            && (captures || !is_capture)
+    });
+
+    match (moves.next(), moves.next()) {
+        (Some(m), None) => Ok(m),
+        (Some(_), Some(_)) => Err(SanError::Ambiguous),
+        (None, _) => Err(SanError::NotLegal),
+    }
+}
+
+// SAN writes pieces with capital letters. Pawns have no letter.
+fn san_piece(c: u8) -> Option<Piece> {
+    match c {
+        b'K' => Some(Pieces::KING),
+        b'Q' => Some(Pieces::QUEEN),
+        b'R' => Some(Pieces::ROOK),
+        b'B' => Some(Pieces::BISHOP),
+        b'N' => Some(Pieces::KNIGHT),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{defs::FEN_KIWIPETE_POSITION, misc::format};
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    #[test]
+    fn input_is_normalized() {
+        let cases = [
+            ("\u{feff}uci\r\n", "uci"),
+            ("isready\r\n", "isready"),
+            ("  go   depth 5 \r\n", "go depth 5"),
+            (
+                "position startpos\rmoves e2e4\r\n",
+                "position startpos moves e2e4",
+            ),
+            ("\u{feff}\r\n", ""),
+            (
+                "setboard 8/8/8/8/8/8/8/8\tw - - 0 1\n",
+                "setboard 8/8/8/8/8/8/8/8 w - - 0 1",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            assert_eq!(normalize_input(input), *expected, "{:?}", input);
+        }
+    }
+
+    // The null move is written and read as "0000". No move from the move
+    // generator is ever taken for it.
+    #[test]
+    fn null_move() {
+        let null = Move::new(0);
+        assert!(null.is_null());
+        assert_eq!(null.as_string(), NULL_MOVE_STRING);
+        assert_eq!(algebraic_move_to_number("0000"), Ok(NULL_POTENTIAL_MOVE));
+        assert_eq!(algebraic_move_to_number("0000q"), Err(()));
+
+        let mg = MoveGenerator::shared();
+        let board = board(FEN_KIWIPETE_POSITION);
+        assert_eq!(format::move_san(&board, &mg, null), "--");
+        for m in board.legal_moves_iter(&mg) {
+            assert!(!m.is_null());
+            assert_ne!(m.as_string(), NULL_MOVE_STRING);
+        }
+    }
+
+    // Checks that each SAN is parsed into the move in coordinates, and
+    // that the move is written as the first SAN.
+    fn assert_san(fen: &str, cases: &[(&str, &[&str])]) {
+        let mg = MoveGenerator::shared();
+        let board = board(fen);
+        for (coordinates, sans) in cases.iter() {
+            for san in sans.iter() {
+                let m = san_to_move(&board, &mg, san).expect(san);
+                assert_eq!(m.as_string(), *coordinates, "{}", san);
+            }
+            let m = san_to_move(&board, &mg, sans[0]).expect(sans[0]);
+            assert_eq!(format::move_san(&board, &mg, m), sans[0]);
+        }
+    }
+
+    #[test]
+    fn san_ambiguous_knights() {
+        let fen = "4k3/8/8/8/8/2N3N1/8/4K3 w - - 0 1";
+        assert_san(fen, &[("c3e4", &["Nce4", "Nc3e4"]), ("g3e4", &["Nge4"])]);
+        assert_san(fen, &[("c3e2", &["Nce2"]), ("g3h5", &["Nh5", "Ngh5"])]);
+
+        let mg = MoveGenerator::shared();
+        let board = board(fen);
+        assert_eq!(
+            san_to_move(&board, &mg, "Ne4").err(),
+            Some(SanError::Ambiguous)
+        );
+        assert_eq!(
+            san_to_move(&board, &mg, "N3e4").err(),
+            Some(SanError::Ambiguous)
+        );
+        assert_eq!(
+            san_to_move(&board, &mg, "Nd4").err(),
+            Some(SanError::NotLegal)
+        );
+    }
+
+    #[test]
+    fn san_ambiguous_rooks() {
+        let fen = "4k3/8/8/R7/8/8/4K3/R6R w - - 0 1";
+        assert_san(
+            fen,
+            &[
+                ("a1a3", &["R1a3", "Ra1a3"]),
+                ("a5a3", &["R5a3"]),
+                ("a1d1", &["Rad1"]),
+                ("h1d1", &["Rhd1"]),
+                ("h1h8", &["Rh8+"]),
+                ("a5e5", &["Re5+"]),
+            ],
+        );
+
+        let mg = MoveGenerator::shared();
+        let board = board(fen);
+        assert_eq!(
+            san_to_move(&board, &mg, "Ra3").err(),
+            Some(SanError::Ambiguous)
+        );
+    }
+
+    #[test]
+    fn san_en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_san(fen, &[("e5d6", &["exd6", "exd6 e.p.", "exd6e.p.", "ed6"])]);
+        assert_san(fen, &[("e5e6", &["e6"])]);
+
+        let mg = MoveGenerator::shared();
+        let board = board(fen);
+        assert_eq!(
+            san_to_move(&board, &mg, "d6").err(),
+            Some(SanError::NotLegal)
+        );
+        assert!(san_to_move(&board, &mg, "exd6")
+            .expect("legal")
+            .en_passant());
+    }
+
+    #[test]
+    fn san_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_san(
+            fen,
+            &[("e1g1", &["O-O", "0-0"]), ("e1c1", &["O-O-O", "0-0-0+"])],
+        );
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+        assert_san(fen, &[("e8g8", &["O-O"]), ("e8c8", &["O-O-O"])]);
+
+        let mg = MoveGenerator::shared();
+        let board = board("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        assert_eq!(
+            san_to_move(&board, &mg, "O-O").err(),
+            Some(SanError::NotLegal)
+        );
+    }
+
+    #[test]
+    fn san_promotions() {
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_san(
+            fen,
+            &[
+                ("a7b8q", &["axb8=Q+", "axb8Q", "axb8=Q"]),
+                ("a7b8n", &["axb8=N"]),
+                ("a7a8r", &["a8=R"]),
+            ],
+        );
+
+        let mg = MoveGenerator::shared();
+        let board = board(fen);
+        assert_eq!(
+            san_to_move(&board, &mg, "axb8").err(),
+            Some(SanError::NotLegal)
+        );
+        assert_eq!(
+            san_to_move(&board, &mg, "axb8=K").err(),
+            Some(SanError::NotLegal)
+        );
+        assert_eq!(
+            san_to_move(&board, &mg, "axb9=Q").err(),
+            Some(SanError::Invalid)
+        );
+    }
+
+    // Every legal move is written in SAN that is parsed into that move.
+    #[test]
+    fn san_round_trip() {
+        let mg = MoveGenerator::shared();
+        for fen in [
+            FEN_KIWIPETE_POSITION,
+            "1n2k3/P7/8/8/8/2N3N1/8/R3K2R w KQ - 0 1",
+        ]
+        .iter()
+        {
+            let board = board(fen);
+            for m in board.legal_moves_iter(&mg) {
+                let san = format::move_san(&board, &mg, m);
+                let parsed = san_to_move(&board, &mg, &san).expect(&san);
+                assert_eq!(parsed.get_move(), m.get_move(), "{}", san);
+            }
+        }
+    }
+
+    // A promotion can only be given as one of the four pieces a pawn can
+    // promote to. Without a letter, the squares are still parsed; only
+    // the engine's position can tell if the move needs one.
+    #[test]
+    fn promotion_moves() {
+        assert_eq!(algebraic_move_to_number("e7e8"), Ok((52, 60, None)));
+        assert_eq!(
+            algebraic_move_to_number("e7e8q"),
+            Ok((52, 60, Some(Pieces::QUEEN)))
+        );
+        assert_eq!(
+            algebraic_move_to_number("E7E8N"),
+            Ok((52, 60, Some(Pieces::KNIGHT)))
+        );
+        assert_eq!(algebraic_move_to_number("e7e8k"), Err(()));
+        assert_eq!(algebraic_move_to_number("e7e8p"), Err(()));
+    }
+}
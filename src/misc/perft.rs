@@ -26,28 +26,41 @@ use crate::{
     engine::defs::{ErrFatal, PerftData, TT},
     misc::print,
     movegen::{
-        defs::{MoveList, MoveType},
+        defs::{Move, MoveList, MoveType},
         MoveGenerator,
     },
 };
 use std::{
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
     time::Instant,
 };
 
+// The leaf nodes one perft thread counted, and the time it took.
+pub struct ThreadCount {
+    pub leaf_nodes: u64,
+    pub time: u128,
+}
+
 // This function runs perft(), while collecting speed information.
 // It uses iterative deepening, so when running perft(7), it will output
-// the results of perft(1) up to and including perft(7).
+// the results of perft(1) up to and including perft(7). The moves at the
+// root are divided over the given number of threads.
 pub fn run(
     board: Arc<Mutex<Board>>,
     depth: i8,
     mg: Arc<MoveGenerator>,
-    tt: Arc<Mutex<TT<PerftData>>>,
+    tt: Arc<RwLock<TT<PerftData>>>,
     tt_enabled: bool,
+    threads: usize,
 ) {
     let mut total_time: u128 = 0;
     let mut total_nodes: u64 = 0;
     let mut hash_full = String::from("");
+    let mut thread_counts: Vec<ThreadCount> = Vec::new();
 
     // Create a mutex guard for the board, so it can be safely cloned.
     // Panic if the guard can't be created, because something is wrong with
@@ -55,12 +68,16 @@ pub fn run(
     let mtx_board = board.lock().expect(ErrFatal::LOCK);
 
     // Clone the locked board for local use.
-    let mut local_board = mtx_board.clone();
+    let local_board = mtx_board.clone();
 
     // The function now has its own local board. Drop the guard. It is not
     // necessary to keep the lock until perft runs out.
     std::mem::drop(mtx_board);
 
+    // The TT is only read-locked once: probing and inserting don't need
+    // an exclusive reference.
+    let tt = tt.read().expect(ErrFatal::LOCK);
+
     println!("Benchmarking perft 1-{}:", depth);
 
     print::position(&local_board, None);
@@ -69,9 +86,9 @@ pub fn run(
     for d in 1..=depth {
         // Current time
         let now = Instant::now();
-        let mut leaf_nodes = 0;
-
-        leaf_nodes += perft(&mut local_board, d, &mg, &tt, tt_enabled);
+        let (counts, per_thread) = divide(&local_board, d, &mg, &tt, tt_enabled, threads);
+        let leaf_nodes: u64 = counts.iter().map(|(_, leaf_nodes)| leaf_nodes).sum();
+        thread_counts = per_thread;
 
         // Measure time and speed
         let elapsed = now.elapsed().as_millis();
@@ -84,10 +101,7 @@ pub fn run(
         // Request TT usage. (This is provided permille as per UCI
         // spec, so divide by 10 to get the usage in percents.)
         if tt_enabled {
-            hash_full = format!(
-                ", hash full: {}%",
-                tt.lock().expect(ErrFatal::LOCK).hash_full() as f64 / 10f64
-            );
+            hash_full = format!(", hash full: {}%", tt.hash_full() as f64 / 10f64);
         }
 
         // Print the results.
@@ -97,19 +111,117 @@ pub fn run(
         );
     }
 
+    // With more than one thread, show how the last perft was divided.
+    if threads > 1 {
+        for (i, tc) in thread_counts.iter().enumerate() {
+            println!("Thread {}: {} ({} ms)", i + 1, tc.leaf_nodes, tc.time);
+        }
+    }
+
     // Final calculation of the entire time taken, and average speed of leaves/second.
     let final_lnps = ((total_nodes * 1000) as f64 / total_time as f64).floor();
     println!("Total time spent: {} ms", total_time);
     println!("Execution speed: {} leaves/second", final_lnps);
 }
 
+// This function runs perft to the given depth, and prints the number of
+// leaf nodes below each legal move in the position: "perft divide". The
+// output has the same format as Stockfish's "go perft", so the two can be
+// compared line by line when hunting for a move generator bug.
+pub fn run_divide(
+    board: Arc<Mutex<Board>>,
+    depth: i8,
+    mg: Arc<MoveGenerator>,
+    tt: Arc<RwLock<TT<PerftData>>>,
+    tt_enabled: bool,
+    threads: usize,
+) {
+    let local_board = board.lock().expect(ErrFatal::LOCK).clone();
+    let tt = tt.read().expect(ErrFatal::LOCK);
+
+    println!("Perft divide {}:", depth);
+
+    print::position(&local_board, None);
+
+    let now = Instant::now();
+    let (counts, _) = divide(&local_board, depth, &mg, &tt, tt_enabled, threads);
+    let elapsed = now.elapsed().as_millis();
+
+    for (m, leaf_nodes) in counts.iter() {
+        println!("{}: {}", m.as_string(), leaf_nodes);
+    }
+
+    let total: u64 = counts.iter().map(|(_, leaf_nodes)| leaf_nodes).sum();
+    println!();
+    println!("Nodes searched: {} ({} ms)", total, elapsed);
+}
+
+// Returns each legal move in the position, with the number of leaf nodes
+// perft finds below it. The moves are sorted by their coordinate notation.
+// The moves are counted by the given number of threads. Each thread has
+// its own copy of the board and takes the next move that hasn't been
+// counted yet, until all moves are done. The TT is shared. The leaf nodes
+// and time of each thread are returned as well.
+pub fn divide(
+    board: &Board,
+    depth: i8,
+    mg: &MoveGenerator,
+    tt: &TT<PerftData>,
+    tt_enabled: bool,
+    threads: usize,
+) -> (Vec<(Move, u64)>, Vec<ThreadCount>) {
+    let moves: Vec<Move> = board.legal_moves_iter(mg).collect();
+    let next = AtomicUsize::new(0);
+
+    let results: Vec<(Vec<(Move, u64)>, ThreadCount)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let (moves, next) = (&moves, &next);
+                let mut board = board.clone();
+                s.spawn(move || {
+                    let now = Instant::now();
+                    let mut counts: Vec<(Move, u64)> = Vec::new();
+
+                    while let Some(&m) = moves.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let made = board.make(m, mg);
+                        debug_assert!(made);
+                        counts.push((m, perft(&mut board, depth - 1, mg, tt, tt_enabled)));
+
+                        let taken_back = board.unmake();
+                        debug_assert!(taken_back);
+                    }
+
+                    let leaf_nodes = counts.iter().map(|(_, leaf_nodes)| leaf_nodes).sum();
+                    let time = now.elapsed().as_millis();
+                    (counts, ThreadCount { leaf_nodes, time })
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect(ErrFatal::THREAD))
+            .collect()
+    });
+
+    let mut counts: Vec<(Move, u64)> = Vec::new();
+    let mut thread_counts: Vec<ThreadCount> = Vec::new();
+    for (c, tc) in results {
+        counts.extend(c);
+        thread_counts.push(tc);
+    }
+
+    counts.sort_by_key(|(m, _)| m.as_string());
+    (counts, thread_counts)
+}
+
 // This is the actual Perft function. It is public, because it is used by
 // the "testsuite" module.
 pub fn perft(
     board: &mut Board,
     depth: i8,
     mg: &MoveGenerator,
-    tt: &Mutex<TT<PerftData>>,
+    tt: &TT<PerftData>,
     tt_enabled: bool,
 ) -> u64 {
     let mut leaf_nodes: u64 = 0;
@@ -121,14 +233,13 @@ pub fn perft(
     }
 
     // See if the current position is in the TT, and if so, get the
-    // number of leaf nodes that were previously calculated for it.
+    // number of leaf nodes that were previously calculated for it. The
+    // TT compares the full Zobrist key, and get() only returns a count
+    // for this exact depth, so a hit can't be a count for another
+    // position or another depth.
     let mut leaf_nodes_tt: Option<u64> = None;
     if tt_enabled {
-        if let Some(data) = tt
-            .lock()
-            .expect(ErrFatal::LOCK)
-            .probe(board.game_state.zobrist_key)
-        {
+        if let Some(data) = tt.probe(board.game_state.zobrist_key) {
             leaf_nodes_tt = data.get(depth);
         };
     }
@@ -151,19 +262,193 @@ pub fn perft(
             leaf_nodes += perft(board, depth - 1, mg, tt, tt_enabled);
 
             // Then unmake the move so the next one can be counted.
-            board.unmake();
+            let taken_back = board.unmake();
+            debug_assert!(taken_back);
         }
     }
 
     // We have calculated the number of leaf nodes for this position.
-    // Store this in the TT for later use.
+    // Store this in the TT for later use, if it fits.
     if tt_enabled {
-        tt.lock().expect(ErrFatal::LOCK).insert(
-            board.game_state.zobrist_key,
-            PerftData::create(depth, leaf_nodes),
-        )
+        if let Some(data) = PerftData::create(depth, leaf_nodes) {
+            tt.insert(board.game_state.zobrist_key, data);
+        }
     }
 
     // Return the number of leaf nodes for the given position and depth.
     leaf_nodes
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{divide, perft};
+    use crate::{
+        board::Board,
+        defs::{FEN_KIWIPETE_POSITION, FEN_START_POSITION},
+        engine::defs::{PerftData, TT},
+        movegen::{defs::Move, MoveGenerator},
+    };
+
+    // The positions and leaf node counts of the Chess960 perft suite.
+    const PERFT_960: &str = include_str!("../extra/perft960.epd");
+
+    fn suite() -> Vec<(&'static str, Vec<(i8, u64)>)> {
+        let lines = PERFT_960
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+        lines
+            .map(|line| {
+                let mut parts = line.split(';').map(|p| p.trim());
+                let fen = parts.next().unwrap_or("");
+                let depths = parts
+                    .filter_map(|p| {
+                        let (d, n) = p.strip_prefix('D')?.split_once(' ')?;
+                        Some((d.parse().ok()?, n.parse().ok()?))
+                    })
+                    .collect();
+                (fen, depths)
+            })
+            .collect()
+    }
+
+    // Divide lists each legal move once, sorted by notation, with the same
+    // counts whatever the number of threads, and with or without the TT.
+    #[test]
+    fn kiwipete_divide() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board
+            .fen_read(Some(FEN_KIWIPETE_POSITION))
+            .expect("valid FEN");
+        let tt = TT::<PerftData>::new(1);
+
+        let (counts, threads) = divide(&board, 2, &mg, &tt, false, 1);
+        let as_strings: Vec<(String, u64)> =
+            counts.iter().map(|(m, n)| (m.as_string(), *n)).collect();
+        let total: u64 = counts.iter().map(|(_, n)| n).sum();
+        assert_eq!((counts.len(), total), (48, 2039));
+        assert_eq!(threads[0].leaf_nodes, 2039);
+
+        let mut sorted = as_strings.clone();
+        sorted.sort();
+        assert_eq!(as_strings, sorted);
+
+        let known = [
+            ("c3b5", 39),
+            ("d5e6", 46),
+            ("e1c1", 43),
+            ("e1g1", 43),
+            ("e2a6", 36),
+            ("f3f6", 39),
+        ];
+        for (m, n) in known.iter() {
+            assert!(as_strings.contains(&(m.to_string(), *n)), "{}", m);
+        }
+
+        for (tt_enabled, threads) in [(false, 3), (true, 1), (true, 4)].iter() {
+            let (c, _) = divide(&board, 2, &mg, &tt, *tt_enabled, *threads);
+            let c: Vec<(String, u64)> = c.iter().map(|(m, n)| (m.as_string(), *n)).collect();
+            assert_eq!(c, as_strings);
+        }
+    }
+
+    // Divide gives the same total on one thread as on several, for the
+    // six standard perft positions. This takes minutes in a debug build,
+    // so it only runs when asked for:
+    // cargo test --release perft_threads -- --ignored
+    #[test]
+    #[ignore]
+    fn perft_threads_give_the_same_totals() {
+        let mg = MoveGenerator::shared();
+        let positions = [
+            (FEN_START_POSITION, 4_865_609),
+            (FEN_KIWIPETE_POSITION, 193_690_690),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 674_624),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                15_833_292,
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                89_941_194,
+            ),
+            (
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                164_075_551,
+            ),
+        ];
+        let total = |counts: &[(Move, u64)]| -> u64 { counts.iter().map(|(_, n)| n).sum() };
+
+        for (fen, leaf_nodes) in positions.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+
+            let (single, threads) = divide(&board, 5, &mg, &TT::new(16), true, 1);
+            assert_eq!(threads.len(), 1);
+            assert_eq!(total(&single), *leaf_nodes, "{}", fen);
+
+            let (multi, threads) = divide(&board, 5, &mg, &TT::new(16), true, 4);
+            let per_thread: u64 = threads.iter().map(|t| t.leaf_nodes).sum();
+            assert_eq!(threads.len(), 4);
+            assert_eq!((total(&multi), per_thread), (*leaf_nodes, *leaf_nodes));
+            assert_eq!(multi.len(), single.len());
+        }
+    }
+
+    // The TT must not change the counts. Each position runs on an empty
+    // TT, and again on the filled one, which finds the count at the root.
+    // The second position is full of promotions; its count is the one
+    // found without the TT.
+    #[test]
+    fn hashed_perft_matches_unhashed() {
+        let mg = MoveGenerator::shared();
+        let no_tt = TT::<PerftData>::new(0);
+        let positions = [
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5, 674_624),
+            ("8/PPP4k/8/8/8/8/4Kppp/8 w - - 0 1", 5, 1_745_545),
+        ];
+
+        for (fen, depth, leaf_nodes) in positions.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let tt = TT::<PerftData>::new(4);
+
+            let unhashed = perft(&mut board, *depth, &mg, &no_tt, false);
+            let hashed = perft(&mut board, *depth, &mg, &tt, true);
+            assert_eq!((unhashed, hashed), (*leaf_nodes, *leaf_nodes), "{}", fen);
+            assert!(tt.hash_full() > 0);
+
+            let found = tt
+                .probe(board.game_state.zobrist_key)
+                .and_then(|d| d.get(*depth));
+            assert_eq!(found, Some(*leaf_nodes));
+            assert_eq!(perft(&mut board, *depth, &mg, &tt, true), *leaf_nodes);
+        }
+    }
+
+    // The counts don't depend on how castling is written, so the suite
+    // must pass with the board set up for Chess960 and without. Depth 4
+    // takes a few seconds, so it only runs once.
+    #[test]
+    fn chess960_perft_suite() {
+        let mg = MoveGenerator::shared();
+        let tt = TT::<PerftData>::new(0);
+        let suite = suite();
+        assert_eq!(suite.len(), 11);
+
+        for chess960 in [true, false].iter() {
+            for (fen, depths) in suite.iter() {
+                let mut board = Board::new();
+                board.chess960 = *chess960;
+                board.fen_read(Some(fen)).expect("valid FEN");
+                assert_eq!(depths.len(), 4, "{}", fen);
+
+                let max_depth = if *chess960 { 4 } else { 3 };
+                for (depth, leaf_nodes) in depths.iter().filter(|(d, _)| *d <= max_depth) {
+                    let found = perft(&mut board, *depth, &mg, &tt, false);
+                    assert_eq!(found, *leaf_nodes, "{} at depth {}", fen, depth);
+                }
+            }
+        }
+    }
+}
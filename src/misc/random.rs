@@ -0,0 +1,83 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// random.rs holds the engine's random number generator. Every random
+// decision the engine makes is drawn from it, so a run can be replayed
+// exactly by passing its seed back with "--seed". Without a seed, one is
+// taken from the operating system, and the engine reports it. A part of
+// the engine that needs its own stream, such as another thread, should
+// seed it with a number drawn from this generator, so it is just as
+// reproducible. Creating a generator in any other way is denied by the
+// disallowed methods and types in clippy.toml.
+
+// For now, only the "extra" tools make random decisions.
+#![cfg_attr(not(feature = "extra"), allow(dead_code))]
+
+#[allow(clippy::disallowed_types)]
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+
+pub struct EngineRandom {
+    seed: u64,
+    from_entropy: bool,
+    rng: ChaChaRng,
+}
+
+impl EngineRandom {
+    // Creates the generator from the given seed, or from a random seed
+    // taken from the operating system if there is none.
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(s) => EngineRandom::from_seed(s, false),
+            #[allow(clippy::disallowed_types)]
+            None => EngineRandom::from_seed(OsRng.next_u64(), true),
+        }
+    }
+
+    fn from_seed(seed: u64, from_entropy: bool) -> Self {
+        Self {
+            seed,
+            from_entropy,
+            rng: ChaChaRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Returns true if the seed was not given, but chosen by the engine.
+    pub fn is_from_entropy(&self) -> bool {
+        self.from_entropy
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    // Returns a number from 0 up to (but not including) the given bound.
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.rng.gen_range(0..bound)
+    }
+}
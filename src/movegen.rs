@@ -24,20 +24,26 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 mod create;
 pub mod defs;
 mod init;
+mod legal;
 mod magics;
 mod movelist;
+mod see;
+mod tables;
+mod verify;
 
 use crate::{
     board::{
-        defs::{Pieces, Squares, BB_RANKS, BB_SQUARES},
+        defs::{Pieces, BB_RANKS, BB_SQUARES},
         Board,
     },
     defs::{Bitboard, Castling, NrOf, Piece, Side, Sides, Square, EMPTY},
     misc::bits,
 };
 use defs::{Move, MoveType, Shift};
+pub use legal::LegalMoves;
 use magics::Magic;
 use movelist::MoveList;
+use std::sync::{Arc, OnceLock};
 
 // This is a list of all pieces a pawn can promote to.
 const PROMOTION_PIECES: [usize; 4] = [Pieces::QUEEN, Pieces::ROOK, Pieces::BISHOP, Pieces::KNIGHT];
@@ -48,6 +54,10 @@ const PROMOTION_PIECES: [usize; 4] = [Pieces::QUEEN, Pieces::ROOK, Pieces::BISHO
 pub const ROOK_TABLE_SIZE: usize = 102_400; // Total permutations of all rook blocker boards.
 pub const BISHOP_TABLE_SIZE: usize = 5_248; // Total permutations of all bishop blocker boards.
 
+// The tables never change after they are built, so all users in the
+// process can share one move generator. It is built on first use.
+static SHARED: OnceLock<Arc<MoveGenerator>> = OnceLock::new();
+
 // The move generator struct holds the attack table for each piece, and the
 // tables with magic numbers for the rook and bishop.
 pub struct MoveGenerator {
@@ -60,11 +70,33 @@ pub struct MoveGenerator {
     bishop_magics: [Magic; NrOf::SQUARES],
 }
 
+impl Default for MoveGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MoveGenerator {
     // Creates a new move generator and initializes all the tables.
     pub fn new() -> Self {
+        let mut mg = Self::empty();
+        mg.init_king();
+        mg.init_knight();
+        mg.init_pawns();
+        if mg.load_slider_tables() {
+            mg.init_magics_only(Pieces::ROOK);
+            mg.init_magics_only(Pieces::BISHOP);
+        } else {
+            mg.init_magics(Pieces::ROOK);
+            mg.init_magics(Pieces::BISHOP);
+        }
+        mg
+    }
+
+    // Creates a move generator with all of its tables still empty.
+    fn empty() -> Self {
         let magics: Magic = Default::default();
-        let mut mg = Self {
+        Self {
             king: [EMPTY; NrOf::SQUARES],
             knight: [EMPTY; NrOf::SQUARES],
             pawns: [[EMPTY; NrOf::SQUARES]; Sides::BOTH],
@@ -72,13 +104,13 @@ impl MoveGenerator {
             bishop: vec![EMPTY; BISHOP_TABLE_SIZE],
             rook_magics: [magics; NrOf::SQUARES],
             bishop_magics: [magics; NrOf::SQUARES],
-        };
-        mg.init_king();
-        mg.init_knight();
-        mg.init_pawns();
-        mg.init_magics(Pieces::ROOK);
-        mg.init_magics(Pieces::BISHOP);
-        mg
+        }
+    }
+
+    // Returns the move generator shared by the process, building it if
+    // this is the first call.
+    pub fn shared() -> Arc<MoveGenerator> {
+        Arc::clone(SHARED.get_or_init(|| Arc::new(MoveGenerator::new())))
     }
 
     // Generates moves for the side that is to move. The MoveType parameter
@@ -215,77 +247,57 @@ impl MoveGenerator {
         }
     }
 
+    // Generates castling moves. The king and the castling rook may start
+    // on any square of the back rank (for Chess960), but always end up on
+    // the same squares as in normal chess. All squares both pieces cross
+    // must be empty, apart from the king and rook themselves. The king may
+    // not be in check, or cross an attacked square; make() checks if its
+    // destination is attacked.
     pub fn castling(&self, board: &Board, list: &mut MoveList) {
         // Create shorthand variables.
         let us = board.us();
         let opponent = board.opponent();
-        let castle_perms_white = (board.game_state.castling & (Castling::WK | Castling::WQ)) > 0;
-        let castle_perms_black = (board.game_state.castling & (Castling::BK | Castling::BQ)) > 0;
+        let rights = [Castling::EACH[us * 2], Castling::EACH[us * 2 + 1]];
         let bb_occupancy = board.occupancy();
-        let mut bb_king = board.get_pieces(Pieces::KING, us);
-        let from = bits::next(&mut bb_king);
-
-        // Generate castling moves for white.
-        if us == Sides::WHITE && castle_perms_white {
-            // Kingside
-            if board.game_state.castling & Castling::WK > 0 {
-                let bb_kingside_blockers = BB_SQUARES[Squares::F1] | BB_SQUARES[Squares::G1];
-                let is_kingside_blocked = (bb_occupancy & bb_kingside_blockers) > 0;
-
-                if !is_kingside_blocked
-                    && !self.square_attacked(board, opponent, Squares::E1)
-                    && !self.square_attacked(board, opponent, Squares::F1)
-                {
-                    let to = BB_SQUARES[from] << 2;
-                    self.add_move(board, Pieces::KING, from, to, list);
-                }
+        let king_from = board.king_square(us);
+
+        for (i, right) in rights.iter().enumerate() {
+            let rook_from = board.game_state.castling_rooks[us * 2 + i] as usize;
+            let has_rook = board.get_pieces(Pieces::ROOK, us) & BB_SQUARES[rook_from] > 0;
+            if board.game_state.castling & right == 0 || !has_rook {
+                continue;
             }
 
-            if board.game_state.castling & Castling::WQ > 0 {
-                // Queenside
-                let bb_queenside_blockers =
-                    BB_SQUARES[Squares::B1] | BB_SQUARES[Squares::C1] | BB_SQUARES[Squares::D1];
-                let is_queenside_blocked = (bb_occupancy & bb_queenside_blockers) > 0;
-
-                if !is_queenside_blocked
-                    && !self.square_attacked(board, opponent, Squares::E1)
-                    && !self.square_attacked(board, opponent, Squares::D1)
-                {
-                    let to = BB_SQUARES[from] >> 2;
-                    self.add_move(board, Pieces::KING, from, to, list);
-                }
+            // Castling is stored as the king capturing its own rook. On a
+            // Chess960 board, it is also written that way.
+            let m = Move::new(
+                Pieces::KING
+                    | king_from << Shift::FROM_SQ
+                    | rook_from << Shift::TO_SQ
+                    | Pieces::NONE << Shift::CAPTURE
+                    | Pieces::NONE << Shift::PROMOTION
+                    | 1 << Shift::CASTLING
+                    | (board.chess960 as usize) << Shift::CHESS960,
+            );
+
+            let king_to = m.castling_king_to();
+            let bb_king_path = squares_between(king_from, king_to);
+            let bb_rook_path = squares_between(rook_from, m.castling_rook_to());
+            let bb_castlers = BB_SQUARES[king_from] | BB_SQUARES[rook_from];
+            let is_blocked = (bb_king_path | bb_rook_path) & bb_occupancy & !bb_castlers > 0;
+            if is_blocked {
+                continue;
             }
-        }
 
-        // Generate castling moves for black.
-        if us == Sides::BLACK && castle_perms_black {
-            // Kingside
-            if board.game_state.castling & Castling::BK > 0 {
-                let bb_kingside_blockers = BB_SQUARES[Squares::F8] | BB_SQUARES[Squares::G8];
-                let is_kingside_blocked = (bb_occupancy & bb_kingside_blockers) > 0;
-
-                if !is_kingside_blocked
-                    && !self.square_attacked(board, opponent, Squares::E8)
-                    && !self.square_attacked(board, opponent, Squares::F8)
-                {
-                    let to = BB_SQUARES[from] << 2;
-                    self.add_move(board, Pieces::KING, from, to, list);
-                }
+            let mut bb_crossed = bb_king_path & !BB_SQUARES[king_to];
+            let mut is_attacked = false;
+            while bb_crossed > 0 && !is_attacked {
+                let square = bits::next(&mut bb_crossed);
+                is_attacked = self.square_attacked(board, opponent, square);
             }
 
-            // Queenside
-            if board.game_state.castling & Castling::BQ > 0 {
-                let bb_queenside_blockers =
-                    BB_SQUARES[Squares::B8] | BB_SQUARES[Squares::C8] | BB_SQUARES[Squares::D8];
-                let is_queenside_blocked = (bb_occupancy & bb_queenside_blockers) > 0;
-
-                if !is_queenside_blocked
-                    && !self.square_attacked(board, opponent, Squares::E8)
-                    && !self.square_attacked(board, opponent, Squares::D8)
-                {
-                    let to = BB_SQUARES[from] >> 2;
-                    self.add_move(board, Pieces::KING, from, to, list);
-                }
+            if !is_attacked {
+                list.push(m);
             }
         }
     }
@@ -316,7 +328,6 @@ impl MoveGenerator {
             };
             let promotion = is_pawn && Board::square_on_rank(to_square, promotion_rank);
             let double_step = is_pawn && ((to_square as i8 - from as i8).abs() == 16);
-            let castling = (piece == Pieces::KING) && ((to_square as i8 - from as i8).abs() == 2);
 
             // Gather all data for this move into one 64-bit integer.
             let mut move_data = (piece)
@@ -324,8 +335,7 @@ impl MoveGenerator {
                 | to_square << Shift::TO_SQ
                 | capture << Shift::CAPTURE
                 | (en_passant as usize) << Shift::EN_PASSANT
-                | (double_step as usize) << Shift::DOUBLE_STEP
-                | (castling as usize) << Shift::CASTLING;
+                | (double_step as usize) << Shift::DOUBLE_STEP;
 
             // Push the move to the piece list...
             if !promotion {
@@ -371,4 +381,38 @@ impl MoveGenerator {
             || (bb_knight & attackers[Pieces::KNIGHT] > 0)
             || (bb_pawns & attackers[Pieces::PAWN] > 0)
     }
+
+    // Returns the pieces of both sides that attack the square. Only pieces
+    // in the given occupancy count, and only those block sliders; this
+    // makes it possible to find the pieces behind the ones that leave.
+    pub fn attackers_to(&self, board: &Board, square: Square, occupancy: Bitboard) -> Bitboard {
+        let bb_king = self.get_non_slider_attacks(Pieces::KING, square);
+        let bb_rook = self.get_slider_attacks(Pieces::ROOK, square, occupancy);
+        let bb_bishop = self.get_slider_attacks(Pieces::BISHOP, square, occupancy);
+        let bb_knight = self.get_non_slider_attacks(Pieces::KNIGHT, square);
+        let mut bb_attackers = EMPTY;
+
+        for side in [Sides::WHITE, Sides::BLACK] {
+            let pieces = board.bb_pieces[side];
+            let bb_pawns = self.get_pawn_attacks(side ^ 1, square);
+            let bb_straight = pieces[Pieces::ROOK] | pieces[Pieces::QUEEN];
+            let bb_diagonal = pieces[Pieces::BISHOP] | pieces[Pieces::QUEEN];
+
+            bb_attackers |= (bb_king & pieces[Pieces::KING])
+                | (bb_rook & bb_straight)
+                | (bb_bishop & bb_diagonal)
+                | (bb_knight & pieces[Pieces::KNIGHT])
+                | (bb_pawns & pieces[Pieces::PAWN]);
+        }
+
+        bb_attackers & occupancy
+    }
+}
+
+// Returns the squares on the same rank from one square up to and including
+// the other.
+fn squares_between(a: Square, b: Square) -> Bitboard {
+    BB_SQUARES[a.min(b)..=a.max(b)]
+        .iter()
+        .fold(EMPTY, |bb_squares, bb_square| bb_squares | bb_square)
 }
@@ -37,6 +37,7 @@ ENPASSANT   :   1        0-1
 DOUBLESTEP  :   1        0-1
 CASTLING    :   1        0-1
 SORTSCORE   :   16       0-65536
+CHESS960    :   1        0-1 (castling written as king takes rook)
 
 
 ---------------------------------- move data -------------------------------------------
@@ -54,6 +55,15 @@ Bits:       32          1           1           1
 Shift:      24 bits     23 bits     22 bits     21 bits
 & Value:    0xFFFFFFFF  0x1         0x1 (1)     0x1 (1)
 
+Field:      CHESS960
+Bits:       1
+Shift:      56 bits
+& Value:    0x1
+
+CHESS960 is not part of the move itself: the move generator sets it on
+castling moves when the board is set up for Chess960, so the move knows
+how to write itself. It is not stored in the TT.
+
 Get the TO field from "data" by:
     -- Shift 9 bits Right
     -- AND (&) with 0x3F
@@ -64,12 +74,17 @@ Storing the "To" square: Shift LEFT 9 bits, then XOR with "data".
 
 pub use super::{magics::Magic, movelist::MoveList};
 use crate::{
-    board::defs::{PIECE_CHAR_SMALL, SQUARE_NAME},
-    defs::{Piece, Square},
+    board::defs::{Pieces, PIECE_CHAR_SMALL, SQUARE_NAME},
+    defs::{NrOf, Piece, Side, Square},
 };
 
 const MOVE_ONLY: usize = 0x00_00_00_00_00_FF_FF_FF;
 
+// A move containing no data at all is the null move. The move generator
+// can never create such a move, because it always sets the promotion
+// field. The null move is written as "0000", as per the UCI convention.
+pub const NULL_MOVE_STRING: &str = "0000";
+
 /* "Shift" is an enum which contains the number of bits that needed to be shifted to store
  * move data in a specific place within the u64 integer. This makes sure that, should the
  * format change, the location needs to be changed only within the integer. */
@@ -84,8 +99,23 @@ impl Shift {
     pub const DOUBLE_STEP: usize = 22;
     pub const CASTLING: usize = 23;
     pub const SORTSCORE: usize = 24;
+    pub const CHESS960: usize = 56;
 }
 
+// Piece values for the static exchange evaluation, indexed by piece. The
+// king is never captured; it only captures if nothing can take it back.
+pub const SEE_VALUES: [i16; NrOf::PIECE_TYPES + 1] = [10_000, 900, 500, 330, 320, 100, 0];
+
+// The order in which pieces join an exchange: least valuable first.
+pub const SEE_ORDER: [Piece; NrOf::PIECE_TYPES] = [
+    Pieces::PAWN,
+    Pieces::KNIGHT,
+    Pieces::BISHOP,
+    Pieces::ROOK,
+    Pieces::QUEEN,
+    Pieces::KING,
+];
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum MoveType {
     Quiet,
@@ -138,6 +168,35 @@ impl Move {
         ((self.data >> Shift::CASTLING as u64) & 0x1) as u8 == 1
     }
 
+    // A castling move is stored as the king capturing its own rook, so it
+    // works for Chess960. These return where the king and rook end up: on
+    // the G- and F-file when castling kingside, or the C- and D-file when
+    // castling queenside.
+    pub fn castling_king_to(&self) -> Square {
+        let back_rank = self.from() & !7;
+        back_rank + if self.to() > self.from() { 6 } else { 2 }
+    }
+
+    pub fn castling_rook_to(&self) -> Square {
+        let back_rank = self.from() & !7;
+        back_rank + if self.to() > self.from() { 5 } else { 3 }
+    }
+
+    // True for a castling move generated on a board set up for Chess960.
+    pub fn chess960(&self) -> bool {
+        ((self.data >> Shift::CHESS960 as u64) & 0x1) as u8 == 1
+    }
+
+    // The to-square as written in move notation. In normal chess, castling
+    // is written as the king's move; in Chess960, as king takes rook.
+    pub fn notation_to(&self) -> Square {
+        if self.castling() && !self.chess960() {
+            self.castling_king_to()
+        } else {
+            self.to()
+        }
+    }
+
     pub fn get_sort_score(self) -> u32 {
         ((self.data >> Shift::SORTSCORE as u64) & 0xFFFFFFFF) as u32
     }
@@ -148,11 +207,19 @@ impl Move {
         self.data = (self.data & !mask) | v;
     }
 
+    pub fn is_null(&self) -> bool {
+        self.get_move() == 0
+    }
+
     pub fn as_string(&self) -> String {
+        if self.is_null() {
+            return String::from(NULL_MOVE_STRING);
+        }
+
         format!(
             "{}{}{}",
             SQUARE_NAME[self.from()],
-            SQUARE_NAME[self.to()],
+            SQUARE_NAME[self.notation_to()],
             PIECE_CHAR_SMALL[self.promoted()]
         )
     }
@@ -180,3 +247,23 @@ impl ShortMove {
         self.data
     }
 }
+
+// One capture in a static exchange. The balance is the material won by
+// the side that made the first capture, after this capture.
+#[derive(Copy, Clone)]
+pub struct ExchangeCapture {
+    pub side: Side,
+    pub piece: Piece,
+    pub from: Square,
+    pub captured: Piece,
+    pub balance: i16,
+}
+
+// All captures on one square, each side capturing with its least valuable
+// piece. The value is what the first side wins if both sides stop
+// capturing as soon as going on would lose material.
+pub struct Exchange {
+    pub square: Square,
+    pub captures: Vec<ExchangeCapture>,
+    pub value: i16,
+}
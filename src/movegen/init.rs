@@ -27,7 +27,7 @@ use super::{
 };
 use crate::{
     board::defs::{Files, Pieces, RangeOf, Ranks, BB_FILES, BB_RANKS, BB_SQUARES},
-    defs::{Piece, Sides, EMPTY},
+    defs::{Piece, Sides, Square, EMPTY},
 };
 
 impl MoveGenerator {
@@ -142,9 +142,8 @@ impl MoveGenerator {
         let mut offset = 0;
 
         for sq in RangeOf::SQUARES {
-            let r_mask = MoveGenerator::rook_mask(sq);
-            let b_mask = MoveGenerator::bishop_mask(sq);
-            let mask = if is_rook { r_mask } else { b_mask };
+            let magic = MoveGenerator::create_magic(is_rook, sq, offset);
+            let mask = magic.mask;
 
             let bits = mask.count_ones(); // Number of set bits in the mask
             let permutations = 2u64.pow(bits); // Number of blocker boards to be indexed.
@@ -155,15 +154,6 @@ impl MoveGenerator {
             let b_ab = MoveGenerator::bishop_attack_boards(sq, &blocker_boards);
             let attack_boards = if is_rook { r_ab } else { b_ab };
 
-            let mut magic: Magic = Default::default();
-            let r_magic_nr = ROOK_MAGIC_NRS[sq as usize];
-            let b_magic_nr = BISHOP_MAGIC_NRS[sq as usize];
-
-            magic.mask = mask;
-            magic.shift = (64 - bits) as u8;
-            magic.offset = offset;
-            magic.nr = if is_rook { r_magic_nr } else { b_magic_nr };
-
             for i in 0..permutations {
                 let next = i as usize;
                 let index = magic.get_index(blocker_boards[next]);
@@ -200,4 +190,41 @@ impl MoveGenerator {
 
         assert!(offset == expectation, "{}", ERROR);
     }
+
+    // Only creates the magics for the rook or bishop, without filling the
+    // attack table. This is used when the attack tables are not generated,
+    // but loaded from the data embedded at build time.
+    pub fn init_magics_only(&mut self, piece: Piece) {
+        let is_rook = piece == Pieces::ROOK;
+        let mut offset = 0;
+
+        for sq in RangeOf::SQUARES {
+            let magic = MoveGenerator::create_magic(is_rook, sq, offset);
+            offset += 2u64.pow(magic.mask.count_ones());
+
+            if is_rook {
+                self.rook_magics[sq] = magic;
+            } else {
+                self.bishop_magics[sq] = magic;
+            }
+        }
+    }
+
+    // Creates the magic for a rook or bishop on the given square. Its
+    // attack boards start at the given offset in the attack table.
+    fn create_magic(is_rook: bool, sq: Square, offset: u64) -> Magic {
+        let r_mask = MoveGenerator::rook_mask(sq);
+        let b_mask = MoveGenerator::bishop_mask(sq);
+        let mask = if is_rook { r_mask } else { b_mask };
+
+        let mut magic: Magic = Default::default();
+        let r_magic_nr = ROOK_MAGIC_NRS[sq];
+        let b_magic_nr = BISHOP_MAGIC_NRS[sq];
+
+        magic.mask = mask;
+        magic.shift = (64 - mask.count_ones()) as u8;
+        magic.offset = offset;
+        magic.nr = if is_rook { r_magic_nr } else { b_magic_nr };
+        magic
+    }
 }
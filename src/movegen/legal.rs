@@ -0,0 +1,211 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file implements an iterator over the legal moves in a position.
+// It is meant for code that just wants the legal moves, without having
+// to handle a move list and make/unmake itself. The search doesn't use
+// it; it checks legality of each move during make() anyway.
+
+use super::{
+    defs::{Move, MoveList, MoveType},
+    MoveGenerator,
+};
+use crate::{
+    board::{
+        defs::{Pieces, BB_SQUARES},
+        Board,
+    },
+    defs::{Bitboard, EMPTY},
+};
+
+pub struct LegalMoves<'a> {
+    board: &'a Board,
+    mg: &'a MoveGenerator,
+    move_list: MoveList,
+    index: u8,
+    in_check: bool,
+    king_lines: Bitboard, // Squares a pinned piece could be on
+    copy: Option<Board>,  // Used to test the moves that may be illegal
+}
+
+impl<'a> LegalMoves<'a> {
+    pub fn new(board: &'a Board, mg: &'a MoveGenerator) -> Self {
+        let mut move_list = MoveList::new();
+        mg.generate_moves(board, &mut move_list, MoveType::All);
+
+        let king_square = board.king_square(board.us());
+        let in_check = mg.square_attacked(board, board.opponent(), king_square);
+        let king_lines = mg.get_slider_attacks(Pieces::QUEEN, king_square, EMPTY);
+
+        Self {
+            board,
+            mg,
+            move_list,
+            index: 0,
+            in_check,
+            king_lines,
+            copy: None,
+        }
+    }
+
+    // If the side to move is not in check, a move can only be illegal if
+    // it is a king move, if it is an en-passant capture (which removes two
+    // pieces from a line), or if the moving piece is pinned. A piece can
+    // only be pinned if it is on a line with its own king. Any other move
+    // is legal without having to play it.
+    fn is_legal(&mut self, m: Move) -> bool {
+        let is_king_move = m.piece() == Pieces::KING;
+        let on_king_line = self.king_lines & BB_SQUARES[m.from()] > 0;
+
+        if !self.in_check && !is_king_move && !m.en_passant() && !on_king_line {
+            return true;
+        }
+
+        // Play the move on a copy of the board, so the original is never
+        // changed. The copy is only made when it is needed.
+        let board = self.board;
+        let copy = self.copy.get_or_insert_with(|| board.clone());
+        let is_legal = copy.make(m, self.mg);
+        if is_legal {
+            let taken_back = copy.unmake();
+            debug_assert!(taken_back);
+        }
+
+        is_legal
+    }
+}
+
+impl<'a> Iterator for LegalMoves<'a> {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        while self.index < self.move_list.len() {
+            let m = self.move_list.get_move(self.index);
+            self.index += 1;
+
+            if self.is_legal(m) {
+                return Some(m);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::defs::{FEN_KIWIPETE_POSITION, FEN_START_POSITION};
+
+    // The standard perft positions, with their leaf counts at depth 3.
+    const PERFT_POSITIONS: [(&str, usize); 6] = [
+        (FEN_START_POSITION, 8902),
+        (FEN_KIWIPETE_POSITION, 97_862),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 2812),
+        (
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            9467,
+        ),
+        (
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            62_379,
+        ),
+        (
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            89_890,
+        ),
+    ];
+
+    // The legal moves found by playing every pseudo-legal move.
+    fn make_unmake_moves(board: &mut Board, mg: &MoveGenerator) -> Vec<u32> {
+        let mut move_list = MoveList::new();
+        mg.generate_moves(board, &mut move_list, MoveType::All);
+        let mut moves = Vec::new();
+
+        for i in 0..move_list.len() {
+            let m = move_list.get_move(i);
+            if board.make(m, mg) {
+                board.unmake();
+                moves.push(m.get_move());
+            }
+        }
+
+        moves.sort_unstable();
+        moves
+    }
+
+    // Compares the iterator with make/unmake in every position of the
+    // tree, down to the given depth. Returns the number of leaves.
+    fn compare(board: &mut Board, mg: &MoveGenerator, depth: u8) -> usize {
+        let key = board.game_state.zobrist_key;
+        let mut moves: Vec<u32> = board.legal_moves_iter(mg).map(|m| m.get_move()).collect();
+        assert_eq!(board.game_state.zobrist_key, key);
+        moves.sort_unstable();
+        assert_eq!(moves, make_unmake_moves(board, mg), "{}", board.fen_write());
+
+        if depth == 1 {
+            return moves.len();
+        }
+
+        let legal: Vec<Move> = board.legal_moves_iter(mg).collect();
+        let mut leaves = 0;
+        for m in legal {
+            assert!(board.make(m, mg));
+            leaves += compare(board, mg, depth - 1);
+            board.unmake();
+        }
+        leaves
+    }
+
+    #[test]
+    fn same_moves_as_make_unmake() {
+        let mg = MoveGenerator::shared();
+
+        for (fen, leaves) in PERFT_POSITIONS.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            assert_eq!(compare(&mut board, &mg, 3), *leaves, "{}", fen);
+        }
+    }
+
+    // Positions in which the legality shortcut doesn't apply: in check,
+    // with pinned pieces, and with en-passant captures that are illegal
+    // because they expose the king.
+    #[test]
+    fn checks_pins_and_en_passant() {
+        let mg = MoveGenerator::shared();
+        let fens = [
+            "4k3/8/8/8/8/8/4q3/4K3 w - - 0 1",
+            "4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1",
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+            "8/8/8/8/k2pP2R/8/8/7K b - e3 0 1",
+            "4k3/8/8/2KpP3/8/8/8/8 w - d6 0 1",
+        ];
+
+        for fen in fens.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            compare(&mut board, &mg, 2);
+        }
+    }
+}
@@ -80,7 +80,7 @@ pub const BISHOP_MAGIC_NRS: [u64; NrOf::SQUARES] = [
  * offset: contains the offset where the indexing of the square's attack boards begin.
  * magic: the magic number itself, used to create the magic index into the attack table.
 */
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Magic {
     pub mask: Bitboard,
     pub shift: u8,
@@ -88,45 +88,35 @@ pub struct Magic {
     pub nr: u64,
 }
 
-impl Default for Magic {
-    fn default() -> Self {
-        Self {
-            mask: 0,
-            shift: 0,
-            offset: 0,
-            nr: 0,
-        }
-    }
-}
-
 /**
  * get_index() is the actual function that gets the magic index into the attack table.
  * The attack table is a perfect hash. This means the following.
  * - A rook on A1 has 7 squares vertical and 7 squares horizontal movement.
  * - This is a total of 14 bits. However, if there are no pieces on A2-A6, or B1-G1, the rook
- *      can always see A8 and H1. This means that if there are no blockers on the file or rank,
- *      the rook can 'see' the square at the edge of the board. Therefore, the bits marking the
- *      edge of a ray are not counted. Thus, the rook on A1 has actually 12 bits set.
+ *   can always see A8 and H1. This means that if there are no blockers on the file or rank,
+ *   the rook can 'see' the square at the edge of the board. Therefore, the bits marking the
+ *   edge of a ray are not counted. Thus, the rook on A1 has actually 12 bits set.
  * - These bits along the rank and file denote the possible position of blocking pieces.
  * - For 12 bits, there are 4096 possible configuration of blockers (2 to the power of 12).
  * - Thus, square A1 has 4096 blocker boards.
  * - The get_index() function receives a board occupancy when called.
  * - "occupancy & self.mask" (the mask for the piece on the square the magic belongs to) yields
- *      a blocker board.
+ *   a blocker board.
  * - Each blocker board (configuration of blockers) goes with one attack board (the squares the)
- *      piece can actually attack). This attack board is in the attack table.
+ *   piece can actually attack). This attack board is in the attack table.
  * - The formula calculates WHERE in the attack table the blocker board is:
- *      (blockerboard * magic number) >> (64 - bits in mask) + offset
+ *   (blockerboard * magic number) >> (64 - bits in mask) + offset
  * - For the rook on A1 the outcome will be an index of 0 - 4095:
- *      0 - 4095 because of 4096 possible blocker (and thus, attack board) permutations
- *      0 for offset, because A1 is the first square.
+ *   0 - 4095 because of 4096 possible blocker (and thus, attack board) permutations
+ *   0 for offset, because A1 is the first square.
  * - So the index for a rook on B1 will start at 4096, and so on. (So B1's offset is 4096.)
  * - The "magic number" is called magic, because it generates a UNIQUE index for each attack
- *      board in the attack table, without any collisions; so the entire table is exactly
- *      filled. This is called a perfect hash.
+ *   board in the attack table, without any collisions; so the entire table is exactly
+ *   filled. This is called a perfect hash.
  * - Finding the magics is a process of just trying random numbers, with the formula below, over
- * and over again until a number is found that generates unique indexes for all of the permutations
- * of attacks of the piece on a particular square. See the explanation for find_magics().
+ *   and over again until a number is found that generates unique indexes for all of the
+ *   permutations of attacks of the piece on a particular square. See the explanation for
+ *   find_magics().
  */
 impl Magic {
     pub fn get_index(&self, occupancy: Bitboard) -> usize {
@@ -39,6 +39,12 @@ pub struct MoveList {
     count: u8,
 }
 
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MoveList {
     // Creates a new move list. Memory is not initialized. This is not
     // necessary, because the next step will always be to generate moves, and
@@ -67,6 +73,10 @@ impl MoveList {
         self.count
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
     // Return the move at the given index. If out of bounds, the program crashes.
     pub fn get_move(&self, index: u8) -> Move {
         self.list[index as usize]
@@ -0,0 +1,218 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// see.rs implements the static exchange evaluation (SEE). It determines
+// the outcome of all the captures on one square, without searching.
+
+use super::{
+    defs::{Exchange, ExchangeCapture, Move, SEE_ORDER, SEE_VALUES},
+    MoveGenerator,
+};
+use crate::{
+    board::{
+        defs::{Pieces, BB_SQUARES},
+        Board,
+    },
+    defs::{Bitboard, Piece, Side, Sides, Square},
+};
+
+impl MoveGenerator {
+    // Plays the given move, and then all captures on its to-square, each
+    // side capturing with its least valuable piece. Sliders behind the
+    // capturing pieces join in when those have left. A castling move
+    // doesn't capture anything and has no exchange.
+    pub fn see(&self, board: &Board, m: Move) -> Exchange {
+        let square = m.to();
+        let us = board.us();
+        let mut exchange = Exchange {
+            square,
+            captures: Vec::new(),
+            value: 0,
+        };
+
+        if m.castling() {
+            return exchange;
+        }
+
+        // The first capture is the move itself. An en passant capture
+        // takes a pawn that is not on the to-square.
+        let mut occupancy = board.occupancy() ^ BB_SQUARES[m.from()];
+        let mut captured = m.captured();
+        if m.en_passant() {
+            let pawn_square = if us == Sides::WHITE {
+                square - 8
+            } else {
+                square + 8
+            };
+            occupancy ^= BB_SQUARES[pawn_square];
+            captured = Pieces::PAWN;
+        }
+
+        let mut on_square = m.piece();
+        let mut gain = SEE_VALUES[captured];
+        if m.promoted() != Pieces::NONE {
+            on_square = m.promoted();
+            gain += SEE_VALUES[on_square] - SEE_VALUES[Pieces::PAWN];
+        }
+
+        // Each gain is the material won by the side making that capture,
+        // if the other side then takes back.
+        let mut gains = vec![gain];
+        let mut balance = gain;
+        exchange.captures.push(ExchangeCapture {
+            side: us,
+            piece: m.piece(),
+            from: m.from(),
+            captured,
+            balance,
+        });
+
+        let mut side = us ^ 1;
+        loop {
+            let bb_attackers = self.attackers_to(board, square, occupancy);
+            let Some((piece, from)) = least_valuable(board, side, bb_attackers) else {
+                break;
+            };
+
+            // The king can't capture a defended piece.
+            if piece == Pieces::KING && bb_attackers & board.bb_side[side ^ 1] > 0 {
+                break;
+            }
+
+            let value = SEE_VALUES[on_square];
+            gains.push(value - gains[gains.len() - 1]);
+            balance += if side == us { value } else { -value };
+            exchange.captures.push(ExchangeCapture {
+                side,
+                piece,
+                from,
+                captured: on_square,
+                balance,
+            });
+
+            on_square = piece;
+            occupancy ^= BB_SQUARES[from];
+            side ^= 1;
+        }
+
+        // Going back from the last capture, a side only captures if that
+        // doesn't lose material. What is left is the value of the move.
+        for i in (1..gains.len()).rev() {
+            gains[i - 1] = -(-gains[i - 1]).max(gains[i]);
+        }
+        exchange.value = gains[0];
+
+        exchange
+    }
+}
+
+// Returns the least valuable of the side's attackers, and its square.
+fn least_valuable(board: &Board, side: Side, bb_attackers: Bitboard) -> Option<(Piece, Square)> {
+    SEE_ORDER.iter().find_map(|&piece| {
+        let bb_pieces = bb_attackers & board.bb_pieces[side][piece];
+        (bb_pieces > 0).then(|| (piece, bb_pieces.trailing_zeros() as Square))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets up the position, and returns the exchange started by the move,
+    // given in coordinates.
+    fn see(fen: &str, m: &str) -> Exchange {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let m = board
+            .legal_moves_iter(&mg)
+            .find(|l| l.as_string() == m)
+            .expect(m);
+        mg.see(&board, m)
+    }
+
+    // The pieces that capture, and the balance after each capture.
+    fn captures(exchange: &Exchange) -> Vec<(Piece, Square, i16)> {
+        exchange
+            .captures
+            .iter()
+            .map(|c| (c.piece, c.from, c.balance))
+            .collect()
+    }
+
+    // Two classic positions: an undefended pawn, and a pawn defended by
+    // a knight, with a bishop and an x-raying queen behind it.
+    #[test]
+    fn classic_exchanges() {
+        let exchange = see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5");
+        assert_eq!(captures(&exchange), vec![(Pieces::ROOK, 4, 100)]);
+        assert_eq!(exchange.value, 100);
+
+        let exchange = see(
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+            "d3e5",
+        );
+        let expected = vec![
+            (Pieces::KNIGHT, 19, 100),
+            (Pieces::KNIGHT, 51, -220),
+            (Pieces::ROOK, 12, 100),
+            (Pieces::BISHOP, 45, -400),
+            (Pieces::QUEEN, 4, -70),
+            (Pieces::QUEEN, 63, -970),
+        ];
+        assert_eq!(exchange.square, 36);
+        assert_eq!(captures(&exchange), expected);
+        assert_eq!(exchange.value, -220);
+    }
+
+    // The king only captures a piece that can't be taken back. Here, the
+    // queen behind the rook defends it.
+    #[test]
+    fn king_takes_undefended_pieces_only() {
+        let exchange = see("8/8/2k5/3p4/8/8/8/3RK3 w - - 0 1", "d1d5");
+        assert_eq!(
+            captures(&exchange),
+            vec![(Pieces::ROOK, 3, 100), (Pieces::KING, 42, -400)]
+        );
+        assert_eq!(exchange.value, -400);
+
+        let exchange = see("8/8/2k5/3p4/8/8/3R4/3QK3 w - - 0 1", "d2d5");
+        assert_eq!(captures(&exchange), vec![(Pieces::ROOK, 11, 100)]);
+        assert_eq!(exchange.value, 100);
+    }
+
+    #[test]
+    fn special_moves() {
+        let exchange = see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6");
+        assert_eq!(exchange.captures[0].captured, Pieces::PAWN);
+        assert_eq!(exchange.value, 100);
+
+        // A promotion gains the promoted piece, minus the pawn.
+        let exchange = see("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q");
+        assert_eq!(exchange.value, 500 + 900 - 100);
+
+        let exchange = see("r3k3/8/8/8/8/8/8/4K2R w K - 0 1", "e1g1");
+        assert!(exchange.captures.is_empty());
+        assert_eq!(exchange.value, 0);
+    }
+}
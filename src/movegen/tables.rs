@@ -0,0 +1,109 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// tables.rs loads the rook and bishop attack tables that build.rs has
+// generated, so they don't have to be generated each time the engine
+// starts. See build.rs for the layout of the data.
+
+use super::{MoveGenerator, BISHOP_TABLE_SIZE, ROOK_TABLE_SIZE};
+use crate::defs::EMPTY;
+
+const SLIDER_ATTACKS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/slider_attacks.bin"));
+const WORD: usize = 8; // Bytes in a u64
+
+impl MoveGenerator {
+    // Fills the rook and bishop attack tables from the embedded data.
+    // Returns false if there is no data, or if it is damaged; then the
+    // tables are left empty, so they can be generated instead.
+    pub fn load_slider_tables(&mut self) -> bool {
+        let expected_len = (1 + ROOK_TABLE_SIZE + BISHOP_TABLE_SIZE) * WORD;
+        if SLIDER_ATTACKS.len() != expected_len {
+            return false;
+        }
+
+        let mut words = SLIDER_ATTACKS.chunks_exact(WORD).map(|bytes| {
+            let mut word = [0u8; WORD];
+            word.copy_from_slice(bytes);
+            u64::from_le_bytes(word)
+        });
+
+        let stored_checksum = words.next().unwrap_or(0);
+        for (entry, word) in self.rook.iter_mut().zip(&mut words) {
+            *entry = word;
+        }
+        for (entry, word) in self.bishop.iter_mut().zip(&mut words) {
+            *entry = word;
+        }
+
+        let ok = checksum(self.rook.iter().chain(self.bishop.iter())) == stored_checksum;
+        if !ok {
+            self.rook.iter_mut().for_each(|x| *x = EMPTY);
+            self.bishop.iter_mut().for_each(|x| *x = EMPTY);
+        }
+
+        ok
+    }
+}
+
+// Must be the same as the checksum in build.rs.
+fn checksum<'a>(words: impl Iterator<Item = &'a u64>) -> u64 {
+    words.fold(0xcbf2_9ce4_8422_2325, |acc, w| {
+        (acc ^ w).rotate_left(5).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::defs::Pieces;
+
+    // The tables embedded by build.rs must be exactly the ones the move
+    // generator creates itself, with the same magics to index them.
+    #[test]
+    fn embedded_tables_match_generated_ones() {
+        if option_env!("RUSTIC_NO_EMBEDDED_TABLES").is_some() {
+            return;
+        }
+
+        let mut embedded = MoveGenerator::empty();
+        assert!(embedded.load_slider_tables());
+        embedded.init_magics_only(Pieces::ROOK);
+        embedded.init_magics_only(Pieces::BISHOP);
+
+        let mut generated = MoveGenerator::empty();
+        generated.init_magics(Pieces::ROOK);
+        generated.init_magics(Pieces::BISHOP);
+
+        assert!(embedded.rook == generated.rook);
+        assert!(embedded.bishop == generated.bishop);
+
+        let magics = |mg: &MoveGenerator| {
+            mg.rook_magics
+                .iter()
+                .chain(mg.bishop_magics.iter())
+                .map(|m| (m.mask, m.shift, m.offset, m.nr))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(magics(&embedded), magics(&generated));
+    }
+}
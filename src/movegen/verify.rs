@@ -0,0 +1,352 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file verifies if a move that did not come from the move generator
+// can be played in the current position. Moves from the transposition
+// table or the killer list were stored in a different position; if the
+// hash key collided, such a move could be nonsense here. Making it would
+// corrupt the board before the legality check in make() could catch it.
+
+use super::{
+    defs::{Move, MoveList},
+    MoveGenerator, PROMOTION_PIECES,
+};
+use crate::{
+    board::{
+        defs::{Pieces, BB_RANKS, BB_SQUARES},
+        Board,
+    },
+    defs::{Sides, EMPTY},
+};
+
+impl MoveGenerator {
+    // Returns true if the move could have been generated in this position.
+    // This checks the moving piece, the capture, the special move flags and
+    // if the path is clear. It does not check if the king is left in check;
+    // that is still done by make().
+    pub fn is_pseudo_legal(&self, board: &Board, m: Move) -> bool {
+        let us = board.us();
+        let opponent = board.opponent();
+        let piece = m.piece();
+        let from = m.from();
+        let to = m.to();
+        let captured = m.captured();
+        let promoted = m.promoted();
+        let is_pawn = piece == Pieces::PAWN;
+        let distance = (to as i8 - from as i8).abs();
+
+        // The piece must be ours, and actually be on the from-square.
+        if piece > Pieces::PAWN
+            || from == to
+            || board.piece_list[from] != piece
+            || board.bb_side[us] & BB_SQUARES[from] == EMPTY
+        {
+            return false;
+        }
+
+        // Castling moves are checked by generating them.
+        if m.castling() {
+            let mut ml = MoveList::new();
+            if piece == Pieces::KING {
+                self.castling(board, &mut ml);
+            }
+            return (0..ml.len()).any(|i| ml.get_move(i).get_move() == m.get_move());
+        }
+
+        // The flags for special moves must be consistent with the move.
+        let is_ep_square = board.game_state.en_passant == Some(to as u8);
+        let is_promotion = is_pawn && Board::square_on_rank(to, Board::promotion_rank(us));
+        if m.en_passant() != (is_pawn && is_ep_square)
+            || m.double_step() != (is_pawn && distance == 16)
+            || (promoted != Pieces::NONE) != is_promotion
+            || (is_promotion && !PROMOTION_PIECES.contains(&promoted))
+        {
+            return false;
+        }
+
+        // The captured piece must be the opponent's piece on the target
+        // square. An en-passant capture lands on an empty square, so the
+        // move generator stores it without a captured piece.
+        let bb_to = BB_SQUARES[to];
+        let capture_ok = if captured == Pieces::NONE {
+            board.piece_list[to] == Pieces::NONE
+        } else {
+            captured != Pieces::KING
+                && board.piece_list[to] == captured
+                && board.bb_side[opponent] & bb_to > 0
+        };
+        if !capture_ok {
+            return false;
+        }
+
+        // Finally, the piece must be able to reach the target square.
+        let occupancy = board.occupancy();
+        match piece {
+            Pieces::KING | Pieces::KNIGHT => self.get_non_slider_attacks(piece, from) & bb_to > 0,
+            Pieces::QUEEN | Pieces::ROOK | Pieces::BISHOP => {
+                self.get_slider_attacks(piece, from, occupancy) & bb_to > 0
+            }
+            _ => {
+                if captured != Pieces::NONE || m.en_passant() {
+                    return self.get_pawn_attacks(us, from) & bb_to > 0;
+                }
+
+                // A push goes one square forward, or two from the starting
+                // rank, without jumping over a piece.
+                let up = if us == Sides::WHITE { 8 } else { -8 };
+                let one_step = (from as i8 + up) as usize;
+                if m.double_step() {
+                    let two_step = (one_step as i8 + up) as usize;
+                    two_step == to
+                        && occupancy & BB_SQUARES[one_step] == EMPTY
+                        && BB_RANKS[Board::fourth_rank(us)] & bb_to > 0
+                } else {
+                    one_step == to
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{defs::FEN_KIWIPETE_POSITION, movegen::defs::MoveType};
+
+    fn board(fen: &str) -> Board {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        board
+    }
+
+    fn pseudo_legal_moves(mg: &MoveGenerator, board: &Board) -> Vec<Move> {
+        let mut ml = MoveList::new();
+        mg.generate_moves(board, &mut ml, MoveType::All);
+        (0..ml.len()).map(|i| ml.get_move(i)).collect()
+    }
+
+    // Takes the move from the position where it was generated, and checks
+    // it in the other position, as a move from the TT would be after a
+    // hash collision.
+    fn is_pseudo_legal_in(generated_in: &str, m: &str, checked_in: &str) -> bool {
+        let mg = MoveGenerator::shared();
+        let m = pseudo_legal_moves(&mg, &board(generated_in))
+            .into_iter()
+            .find(|x| x.as_string() == m)
+            .expect("generated move");
+        mg.is_pseudo_legal(&board(checked_in), m)
+    }
+
+    #[test]
+    fn generated_moves_are_pseudo_legal() {
+        let mg = MoveGenerator::shared();
+        let board = board(FEN_KIWIPETE_POSITION);
+        for m in pseudo_legal_moves(&mg, &board) {
+            assert!(mg.is_pseudo_legal(&board, m), "{}", m.as_string());
+        }
+    }
+
+    #[test]
+    fn wrong_piece_on_the_from_square() {
+        let start = "4k3/8/8/8/8/8/8/4K1N1 w - - 0 1";
+        assert!(is_pseudo_legal_in(start, "g1f3", start));
+        assert!(!is_pseudo_legal_in(
+            start,
+            "g1f3",
+            "4k3/8/8/8/8/8/8/4K1B1 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            start,
+            "g1f3",
+            "4k3/8/8/8/8/8/8/4K1n1 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            start,
+            "g1f3",
+            "4k1n1/8/8/8/8/8/8/4K1N1 b - - 0 1"
+        ));
+    }
+
+    #[test]
+    fn king_and_knight_moves() {
+        let king = "4k3/8/8/8/8/8/8/4K3 w - - 0 1";
+        assert!(!is_pseudo_legal_in(
+            king,
+            "e1d1",
+            "4k3/8/8/8/8/8/8/3NK3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            king,
+            "e1d1",
+            "4k3/8/8/8/8/8/8/3K4 w - - 0 1"
+        ));
+
+        // A knight can't capture what isn't there, or capture a piece of
+        // another type than the one stored in the move.
+        let capture = "4k3/8/8/8/8/5n2/8/4K1N1 w - - 0 1";
+        assert!(!is_pseudo_legal_in(
+            capture,
+            "g1f3",
+            "4k3/8/8/8/8/8/8/4K1N1 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            capture,
+            "g1f3",
+            "4k3/8/8/8/8/5b2/8/4K1N1 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            capture,
+            "g1f3",
+            "4k3/8/8/8/8/5N2/8/4K1N1 w - - 0 1"
+        ));
+    }
+
+    #[test]
+    fn blocked_sliders() {
+        let rook = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        assert!(is_pseudo_legal_in(rook, "a1a8", rook));
+        assert!(!is_pseudo_legal_in(
+            rook,
+            "a1a8",
+            "4k3/8/8/8/P7/8/8/R3K3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            rook,
+            "a1a8",
+            "4k3/8/8/8/p7/8/8/R3K3 w - - 0 1"
+        ));
+
+        let bishop = "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1";
+        assert!(!is_pseudo_legal_in(
+            bishop,
+            "c1h6",
+            "4k3/8/8/8/8/4P3/8/2B1K3 w - - 0 1"
+        ));
+
+        let queen = "4k3/8/8/8/8/8/8/3QK3 w - - 0 1";
+        assert!(!is_pseudo_legal_in(
+            queen,
+            "d1h5",
+            "4k3/8/8/8/8/5P2/8/3QK3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            queen,
+            "d1d8",
+            "4k3/8/8/3n4/8/8/8/3QK3 w - - 0 1"
+        ));
+    }
+
+    #[test]
+    fn pawn_moves() {
+        let pawn = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        assert!(!is_pseudo_legal_in(
+            pawn,
+            "e2e4",
+            "4k3/8/8/8/8/4n3/4P3/4K3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            pawn,
+            "e2e3",
+            "4k3/8/8/8/8/4n3/4P3/4K3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            pawn,
+            "e2e4",
+            "4k3/8/8/8/4n3/8/4P3/4K3 w - - 0 1"
+        ));
+
+        // A promotion must promote, and only on the last rank.
+        let promotion = "4k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert!(is_pseudo_legal_in(promotion, "a7a8q", promotion));
+        assert!(!is_pseudo_legal_in(
+            promotion,
+            "a7a8q",
+            "n3k3/P7/8/8/8/8/8/4K3 w - - 0 1"
+        ));
+    }
+
+    #[test]
+    fn bad_castling() {
+        let castling = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert!(is_pseudo_legal_in(castling, "e1g1", castling));
+        assert!(is_pseudo_legal_in(castling, "e1c1", castling));
+
+        // No right to castle, a piece in between, or the king in check.
+        let no_rights = "r3k2r/8/8/8/8/8/8/R3K2R w Qkq - 0 1";
+        let blocked = "r3k2r/8/8/8/8/8/8/R3KB1R w KQkq - 0 1";
+        let in_check = "r3k2r/8/8/8/8/8/4r3/R3K2R w KQq - 0 1";
+        assert!(!is_pseudo_legal_in(castling, "e1g1", no_rights));
+        assert!(!is_pseudo_legal_in(castling, "e1g1", blocked));
+        assert!(!is_pseudo_legal_in(castling, "e1g1", in_check));
+        assert!(!is_pseudo_legal_in(castling, "e1c1", in_check));
+        assert!(is_pseudo_legal_in(castling, "e1c1", no_rights));
+    }
+
+    #[test]
+    fn bad_en_passant() {
+        let ep = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert!(is_pseudo_legal_in(ep, "e5d6", ep));
+        assert!(!is_pseudo_legal_in(
+            ep,
+            "e5d6",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1"
+        ));
+        assert!(!is_pseudo_legal_in(
+            ep,
+            "e5d6",
+            "4k3/8/3p4/4P3/8/8/8/4K3 w - - 0 1"
+        ));
+
+        // Without the en-passant flag, the capture isn't en passant.
+        let capture = "4k3/8/3n4/4P3/8/8/8/4K3 w - - 0 1";
+        assert!(!is_pseudo_legal_in(capture, "e5d6", ep));
+    }
+
+    // Every pseudo-legal move of one position is checked in the others.
+    // Only the moves the move generator gives there, are accepted.
+    #[test]
+    fn moves_from_other_positions() {
+        let mg = MoveGenerator::shared();
+        let fens = [
+            FEN_KIWIPETE_POSITION,
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+        ];
+        let boards: Vec<Board> = fens.iter().map(|fen| board(fen)).collect();
+
+        for from in boards.iter() {
+            for to in boards.iter() {
+                let expected: Vec<u32> = pseudo_legal_moves(&mg, to)
+                    .iter()
+                    .map(|m| m.get_move())
+                    .collect();
+                for m in pseudo_legal_moves(&mg, from) {
+                    let is_generated = expected.contains(&m.get_move());
+                    assert_eq!(mg.is_pseudo_legal(to, m), is_generated, "{}", m.as_string());
+                }
+            }
+        }
+    }
+}
@@ -24,9 +24,15 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 // search.rs contains the engine's search routine.
 
 mod alpha_beta;
+pub mod bench;
+mod classify;
+mod compare;
 pub mod defs;
 mod iter_deep;
+mod mate;
 mod qsearch;
+mod score;
+mod smp;
 mod sorting;
 mod time;
 mod utils;
@@ -35,15 +41,16 @@ use crate::{
     board::Board,
     engine::defs::{ErrFatal, Information},
     engine::defs::{SearchData, TT},
+    misc::affinity,
     movegen::MoveGenerator,
 };
 use crossbeam_channel::Sender;
 use defs::{
-    SearchControl, SearchInfo, SearchParams, SearchRefs, SearchReport, SearchSummary,
-    SearchTerminate,
+    HistoryHeuristic, SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs,
+    SearchReport, SearchShared, SearchSummary, SearchTerminate,
 };
 use std::{
-    sync::{Arc, Mutex},
+    sync::{atomic::Ordering, Arc, Mutex, RwLock},
     thread::{self, JoinHandle},
 };
 
@@ -65,9 +72,13 @@ impl Search {
         report_tx: Sender<Information>, // Used to send information to engine.
         board: Arc<Mutex<Board>>,       // Arc pointer to engine's board.
         mg: Arc<MoveGenerator>,         // Arc pointer to engine's move generator.
-        tt: Arc<Mutex<TT<SearchData>>>,
+        tt: Arc<RwLock<TT<SearchData>>>,
     ) {
-        // Set up a channel for incoming commands
+        // Set up a channel for incoming commands. This one is not bounded:
+        // the engine must never wait for the search, because the search
+        // may be waiting for the engine to receive its Information. The
+        // engine only sends a few commands per command from the GUI, and
+        // the search reads them all the time, so the channel stays short.
         let (control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
 
         // Create thread-local variables.
@@ -80,6 +91,7 @@ impl Search {
             let arc_mg = Arc::clone(&mg);
             let arc_tt = Arc::clone(&tt);
             let mut search_params = SearchParams::new();
+            let mut history: HistoryHeuristic = SearchInfo::new().history;
 
             let mut quit = false;
             let mut halt = true;
@@ -97,7 +109,10 @@ impl Search {
                     }
                     SearchControl::Stop => halt = true,
                     SearchControl::Quit => quit = true,
-                    SearchControl::Nothing => (),
+                    SearchControl::PonderHit
+                    | SearchControl::Pause
+                    | SearchControl::Resume
+                    | SearchControl::Nothing => (),
                 }
 
                 // Search isn't halted and not going to quit.
@@ -107,38 +122,99 @@ impl Search {
                     let mut board = mtx_board.clone();
                     std::mem::drop(mtx_board);
 
+                    // The report ending the search carries its id, so the
+                    // engine knows which search it belongs to.
+                    let id = search_params.id;
+
                     // Create a place to put search information
                     let mut search_info = SearchInfo::new();
-
-                    // Create references to all needed information and structures.
-                    let mut search_refs = SearchRefs {
-                        board: &mut board,
-                        mg: &arc_mg,
-                        tt: &arc_tt,
-                        search_params: &mut search_params,
-                        search_info: &mut search_info,
-                        control_rx: &control_rx,
-                        report_tx: &t_report_tx,
+                    let shared = SearchShared::new();
+                    Search::decay_history(&mut history);
+                    search_info.history = history;
+
+                    // The TT can be used by all threads at the same time.
+                    // The engine can only resize it while nobody searches.
+                    let tt = arc_tt.read().expect(ErrFatal::LOCK);
+                    tt.new_generation();
+
+                    // Pin the threads to their CPUs, if requested. The
+                    // main search thread takes the first one.
+                    let placement = if search_params.affinity {
+                        affinity::placement(search_params.threads)
+                    } else {
+                        Vec::new()
                     };
+                    if let Some(&cpu) = placement.first() {
+                        affinity::pin_current_thread(cpu);
+                    }
 
-                    // Start the search using Iterative Deepening.
-                    let (best_move, terminate) = Search::iterative_deepening(&mut search_refs);
+                    // Comparing the root moves is a search of its own.
+                    // It reports a table instead of a best move.
+                    let (report, terminate) = if search_params.search_mode == SearchMode::Compare {
+                        let mut search_refs = SearchRefs {
+                            board: &mut board,
+                            mg: &arc_mg,
+                            tt: &tt,
+                            search_params: &mut search_params,
+                            search_info: &mut search_info,
+                            shared: &shared,
+                            control_rx: &control_rx,
+                            report_tx: &t_report_tx,
+                        };
+                        let table = Search::compare_moves(&mut search_refs);
+                        (SearchReport::Compared(id, table), search_info.terminate)
+                    } else {
+                        let (best_move, ponder_move, terminate) = thread::scope(|s| {
+                            // Start the helper threads, each on its own board.
+                            for helper in 1..search_params.threads {
+                                let helper_board = board.clone();
+                                let (mg, tt, shared) = (&arc_mg, &*tt, &shared);
+                                let sp = search_params;
+                                let cpu = placement.get(helper).copied();
+                                s.spawn(move || {
+                                    if let Some(cpu) = cpu {
+                                        affinity::pin_current_thread(cpu);
+                                    }
+                                    Search::helper_search(helper_board, mg, tt, sp, helper, shared)
+                                });
+                            }
+
+                            // Create references to all needed information and structures.
+                            let mut search_refs = SearchRefs {
+                                board: &mut board,
+                                mg: &arc_mg,
+                                tt: &tt,
+                                search_params: &mut search_params,
+                                search_info: &mut search_info,
+                                shared: &shared,
+                                control_rx: &control_rx,
+                                report_tx: &t_report_tx,
+                            };
+
+                            // Start the search using Iterative Deepening. When
+                            // it is done, the helpers stop as well.
+                            let result = Search::iterative_deepening(&mut search_refs);
+                            shared.stop.store(true, Ordering::Relaxed);
+                            result
+                        });
+                        (
+                            SearchReport::Finished(id, best_move, ponder_move),
+                            terminate,
+                        )
+                    };
+                    std::mem::drop(tt);
+                    history = search_info.history;
 
                     // Inform the engine that the search has finished.
-                    let information = Information::Search(SearchReport::Finished(best_move));
+                    let information = Information::Search(report);
                     t_report_tx.send(information).expect(ErrFatal::CHANNEL);
 
-                    // If the search was finished due to a Stop or Quit
-                    // command then either halt or quit the search.
-                    match terminate {
-                        SearchTerminate::Stop => {
-                            halt = true;
-                        }
-                        SearchTerminate::Quit => {
-                            halt = true;
-                            quit = true;
-                        }
-                        SearchTerminate::Nothing => (),
+                    // The search is done, so halt until the next search
+                    // is started. If the search was finished due to a Quit
+                    // command, then also quit the search thread.
+                    halt = true;
+                    if terminate == SearchTerminate::Quit {
+                        quit = true;
                     }
                 }
             }
@@ -22,29 +22,37 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::{
-    defs::{SearchTerminate, CHECKMATE, CHECK_TERMINATION, DRAW, INF, SEND_STATS, STALEMATE},
+    defs::{
+        lmr_reduction, Score, SearchCurrentMove, SearchTerminate, CHECK_TERMINATION, INF,
+        LMR_MIN_DEPTH, LMR_MIN_MOVES, NULL_MOVE_DEEP, NULL_MOVE_MIN_DEPTH, NULL_MOVE_VERIFY_DEPTH,
+        SEND_STATS, STALEMATE,
+    },
     Search, SearchRefs,
 };
 use crate::{
     board::defs::Pieces,
     defs::MAX_PLY,
-    engine::defs::{ErrFatal, HashFlag, SearchData},
+    engine::defs::{HashFlag, SearchData},
     evaluation::Evaluation,
     movegen::defs::{Move, MoveList, MoveType, ShortMove},
 };
+use if_chain::if_chain;
 
 impl Search {
     pub fn alpha_beta(
         mut depth: i8,
-        mut alpha: i16,
-        beta: i16,
-        pv: &mut Vec<Move>,
+        mut alpha: Score,
+        beta: Score,
         refs: &mut SearchRefs,
-    ) -> i16 {
+    ) -> Score {
         let quiet = refs.search_params.quiet; // If quiet, don't send intermediate stats.
         let is_root = refs.search_info.ply == 0; // At root if no moves were played.
+        let excluding = is_root && !refs.search_info.excluded.is_empty(); // MultiPV
         let mut do_pvs = false; // Used for PVS (Principal Variation Search)
 
+        // No PV has been found in this node yet.
+        refs.search_info.pv.clear(refs.search_info.ply);
+
         // Check if termination condition is met.
         if refs.search_info.nodes & CHECK_TERMINATION == 0 {
             Search::check_termination(refs);
@@ -53,52 +61,56 @@ impl Search {
         // If time is up, abort. This depth won't be considered in
         // iterative deepening as it is unfinished.
         if refs.search_info.terminate != SearchTerminate::Nothing {
-            return 0;
+            return Score::new(0);
         }
 
-        // Stop going deeper if we hit MAX_PLY.
-        if refs.search_info.ply >= MAX_PLY {
-            return Evaluation::evaluate_position(refs.board);
+        // Stop going deeper if we hit MAX_PLY, or if the history can't
+        // hold any more game states.
+        if refs.search_info.ply >= MAX_PLY || refs.board.history.is_full() {
+            return Evaluation::evaluate_position(
+                refs.board,
+                refs.mg,
+                &mut refs.search_info.pawn_table,
+            );
         }
 
         // Determine if we are in check.
-        let is_check = refs.mg.square_attacked(
-            refs.board,
-            refs.board.opponent(),
-            refs.board.king_square(refs.board.us()),
-        );
+        let is_check = Search::in_check(refs);
 
         // If so, extend search depth by 1 to determine the best way to get
-        // out of the check before we go into quiescence search.
-        if is_check {
+        // out of the check before we go into quiescence search. A long
+        // series of checks can't extend the search beyond MAX_PLY.
+        if is_check && depth < MAX_PLY - refs.search_info.ply {
             depth += 1;
         }
 
         // We have arrived at the leaf node. Evaluate the position and
         // return the result.
         if depth <= 0 {
-            return Search::quiescence(alpha, beta, pv, refs);
+            return Search::quiescence(alpha, beta, refs);
         }
 
         // Count this node, as it is not aborted or searched by QSearch.
         refs.search_info.nodes += 1;
 
         // Variables to hold TT value and move if any.
-        let mut tt_value: Option<i16> = None;
+        let mut tt_value: Option<Score> = None;
         let mut tt_move: ShortMove = ShortMove::new(0);
 
         // Probe the TT for information.
-        if let Some(data) = refs
-            .tt
-            .lock()
-            .expect(ErrFatal::LOCK)
-            .probe(refs.board.game_state.zobrist_key)
-        {
+        if let Some(data) = refs.tt.probe(refs.board.game_state.zobrist_key) {
             let tt_result = data.get(depth, refs.search_info.ply, alpha, beta);
             tt_value = tt_result.0;
             tt_move = tt_result.1;
         }
 
+        // A colliding hash key can hand us a move from another position.
+        // Don't use it for move ordering if it can't be played here.
+        let hash_move = Move::new(tt_move.get_move() as usize);
+        if tt_move.get_move() != 0 && !refs.mg.is_pseudo_legal(refs.board, hash_move) {
+            tt_move = ShortMove::new(0);
+        }
+
         // If we have a value from the TT, then return immediately.
         if let Some(v) = tt_value {
             if !is_root {
@@ -106,10 +118,27 @@ impl Search {
             }
         }
 
+        // Null move pruning. This doesn't work when in check, because
+        // passing would be illegal. It also fails in zugzwang, where
+        // passing would be better than any real move; this mostly
+        // happens when the side to move has nothing but pawns left.
+        if_chain! {
+            if !is_root && !is_check;
+            if depth >= NULL_MOVE_MIN_DEPTH;
+            if refs.search_info.ply >= refs.search_info.null_move_min_ply;
+            if !refs.board.last_move_is_null();
+            if refs.board.has_non_pawn_material(refs.board.us());
+            if Search::null_move_fails_high(depth, beta, refs);
+            then {
+                return beta;
+            }
+        }
+
         /*=== Actual searching starts here ===*/
 
         // Generate the moves in this position
         let mut legal_moves_found = 0;
+        let mut quiets_tried = MoveList::new(); // Quiet moves without a cutoff
         let mut move_list = MoveList::new();
         refs.mg
             .generate_moves(refs.board, &mut move_list, MoveType::All);
@@ -141,55 +170,95 @@ impl Search {
             Search::pick_move(&mut move_list, i);
 
             let current_move = move_list.get_move(i);
-            let is_legal = refs.board.make(current_move, refs.mg);
-
-            // If not legal, skip the move and the rest of the function.
-            if !is_legal {
+            let move_number = legal_moves_found + 1;
+
+            // In MultiPV mode, skip root moves of lines already found.
+            if excluding
+                && refs
+                    .search_info
+                    .excluded
+                    .contains(&current_move.to_short_move())
+            {
                 continue;
             }
 
-            // We found a legal move.
-            legal_moves_found += 1;
-            refs.search_info.ply += 1;
-
-            // Update seldepth if searching deeper than specified depth.
-            if refs.search_info.ply > refs.search_info.seldepth {
-                refs.search_info.seldepth = refs.search_info.ply;
-            }
-
-            // Send currently searched move to GUI.
-            if !quiet && is_root {
-                Search::send_move_to_gui(refs, current_move, legal_moves_found);
-            }
-
-            // Create a node PV for this move.
-            let mut node_pv: Vec<Move> = Vec::new();
+            // Quiet moves late in the move list are unlikely to be good,
+            // so they may be searched with reduced depth. Moves are not
+            // reduced when in check, nor is the PV move (always the first
+            // move), a capture, a promotion or a killer.
+            let may_reduce = !is_root
+                && !is_check
+                && depth >= LMR_MIN_DEPTH
+                && move_number > LMR_MIN_MOVES
+                && current_move.captured() == Pieces::NONE
+                && current_move.promoted() == Pieces::NONE
+                && !Search::is_killer(current_move, refs);
+
+            // We are not yet at one of the leaf nodes, so if the position
+            // after the move is not a draw, we must search deeper.
+            let visited = Search::visit_move(refs, current_move, true, |refs| {
+                // Keep track of the root move being searched, and send it
+                // to the GUI.
+                // The moves skipped in MultiPV mode are not searched, so
+                // they don't count towards the total.
+                if is_root {
+                    let excluded = refs.search_info.excluded.len() as u8;
+                    let total = refs.search_info.root_moves.saturating_sub(excluded);
+                    let root_move = SearchCurrentMove::new(current_move, move_number, total);
+                    refs.search_info.root_move = root_move;
+
+                    if !quiet {
+                        Search::send_move_to_gui(refs, root_move);
+                    }
+                }
 
-            // We just made a move. We are not yet at one of the leaf
-            // nodes, so if the position is not a draw, we must search
-            // deeper. Initially, assume the position is a draw.
-            let mut eval_score = DRAW;
+                // Search a late move with reduced depth and a null window
+                // first. Only if it beats alpha, search it normally. A
+                // move that gives check is never reduced.
+                if may_reduce && !Search::in_check(refs) {
+                    let reduction = (lmr_reduction(depth, move_number)
+                        + refs.search_info.tunables.lmr)
+                        .min(depth - 2)
+                        .max(0);
+                    if reduction > 0 {
+                        let reduced_depth = depth - 1 - reduction;
+                        let eval_score =
+                            -Search::alpha_beta(reduced_depth, -alpha - 1, -alpha, refs);
+                        if eval_score <= alpha {
+                            return eval_score;
+                        }
+                    }
+                }
 
-            // If it isn't a draw, we must search.
-            if !Search::is_draw(refs) {
                 // Try a PVS if applicable.
                 if do_pvs {
-                    eval_score =
-                        -Search::alpha_beta(depth - 1, -alpha - 1, -alpha, &mut node_pv, refs);
+                    let eval_score = -Search::alpha_beta(depth - 1, -alpha - 1, -alpha, refs);
 
                     // Check if we failed the PVS.
                     if (eval_score > alpha) && (eval_score < beta) {
-                        eval_score =
-                            -Search::alpha_beta(depth - 1, -beta, -alpha, &mut node_pv, refs);
+                        return -Search::alpha_beta(depth - 1, -beta, -alpha, refs);
                     }
+                    eval_score
                 } else {
-                    eval_score = -Search::alpha_beta(depth - 1, -beta, -alpha, &mut node_pv, refs);
+                    -Search::alpha_beta(depth - 1, -beta, -alpha, refs)
                 }
+            });
+
+            // If not legal, skip the move and the rest of the function.
+            let eval_score = match visited {
+                Some(score) => score,
+                None => continue,
+            };
+
+            // An interrupted search returns meaningless scores. Leave the
+            // node before any of them end up in the TT, where the other
+            // search threads or the next search would trust them.
+            if refs.search_info.interrupted() {
+                return Score::new(0);
             }
 
-            // Take back the move, and decrease ply accordingly.
-            refs.board.unmake();
-            refs.search_info.ply -= 1;
+            // We found a legal move.
+            legal_moves_found += 1;
 
             // eval_score is better than the best we found so far, so we
             // save a new best_move that'll go into the hash table.
@@ -201,28 +270,34 @@ impl Search {
             // Beta cutoff: this move is so good for our opponent, that we
             // do not search any further. Insert into TT and return beta.
             if eval_score >= beta {
-                refs.tt.lock().expect(ErrFatal::LOCK).insert(
-                    refs.board.game_state.zobrist_key,
-                    SearchData::create(
-                        depth,
-                        refs.search_info.ply,
-                        HashFlag::Beta,
-                        beta,
-                        best_move,
-                    ),
-                );
+                if !excluding {
+                    refs.tt.insert(
+                        refs.board.game_state.zobrist_key,
+                        SearchData::create(
+                            depth,
+                            refs.search_info.ply,
+                            HashFlag::Beta,
+                            beta,
+                            best_move,
+                        ),
+                    );
+                }
 
                 // If the move is not a capture but still causes a
                 // beta-cutoff, then store it as a killer move and update
                 // the history heuristics.
                 if current_move.captured() == Pieces::NONE {
                     Search::store_killer_move(current_move, refs);
-                    // Search::update_history_heuristic(current_move, depth, refs);
+                    Search::update_history_heuristic(current_move, &quiets_tried, depth, refs);
                 }
 
                 return beta;
             }
 
+            if current_move.captured() == Pieces::NONE {
+                quiets_tried.push(current_move);
+            }
+
             // We found a better move for us.
             if eval_score > alpha {
                 // Save our better evaluation score as alpha.
@@ -233,9 +308,9 @@ impl Search {
 
                 // Update the Principal Variation.
                 do_pvs = true;
-                pv.clear();
-                pv.push(current_move);
-                pv.append(&mut node_pv);
+                refs.search_info
+                    .pv
+                    .update(refs.search_info.ply, current_move);
             }
         }
 
@@ -245,21 +320,197 @@ impl Search {
             if is_check {
                 // The return value is minus CHECKMATE, because if we have
                 // no legal moves and are in check, it's game over.
-                return -CHECKMATE + (refs.search_info.ply as i16);
+                return Score::mated_at(refs.search_info.ply);
             } else {
                 return STALEMATE;
             }
         }
 
         // We save the best move we found for us; with an ALPHA flag if we
-        // didn't improve alpha, or EXACT if we did raise alpha.
-        refs.tt.lock().expect(ErrFatal::LOCK).insert(
-            refs.board.game_state.zobrist_key,
-            SearchData::create(depth, refs.search_info.ply, hash_flag, alpha, best_move),
-        );
+        // didn't improve alpha, or EXACT if we did raise alpha. When root
+        // moves are excluded, the result is not the real best move in this
+        // position, so it is not saved.
+        if !excluding {
+            refs.tt.insert(
+                refs.board.game_state.zobrist_key,
+                SearchData::create(depth, refs.search_info.ply, hash_flag, alpha, best_move),
+            );
+        }
 
         // We have traversed the entire move list and found the best
         // possible move/eval_score for us.
         alpha
     }
+
+    // Pass the move to the opponent, and search the position with a
+    // reduced depth and a null window around beta. If this still fails
+    // high, a real move would almost certainly do so as well. The
+    // caller then returns beta, without storing anything in the TT: the
+    // score of the null move search is not the value of this position,
+    // and it could even be a mate score that isn't real.
+    fn null_move_fails_high(depth: i8, beta: Score, refs: &mut SearchRefs) -> bool {
+        let r = if depth > NULL_MOVE_DEEP { 3 } else { 2 };
+        let r = (r + refs.search_info.tunables.null_move).max(1);
+
+        // The null move is a ply of the search like any other, so it
+        // counts for the seldepth.
+        refs.board.make_null_move();
+        refs.search_info.ply += 1;
+        refs.search_info.seldepth = refs.search_info.seldepth.max(refs.search_info.ply);
+        let eval_score = -Search::alpha_beta(depth - 1 - r, -beta, -beta + 1, refs);
+        refs.search_info.ply -= 1;
+        refs.board.unmake_null_move();
+
+        if refs.search_info.interrupted() || eval_score < beta {
+            return false;
+        }
+
+        // At high depths, protect against zugzwang by verifying the
+        // cutoff with a normal reduced search of this position. Null
+        // moves are not allowed in the first part of that search.
+        if depth >= NULL_MOVE_VERIFY_DEPTH {
+            let ply = refs.search_info.ply;
+            let min_ply = refs.search_info.null_move_min_ply;
+
+            refs.search_info.null_move_min_ply = Search::null_move_min_ply(ply, depth, r);
+            let verified = Search::alpha_beta(depth - r, beta - 1, beta, refs);
+            refs.search_info.null_move_min_ply = min_ply;
+
+            return !refs.search_info.interrupted() && verified >= beta;
+        }
+
+        true
+    }
+
+    // The ply before which the verification search of a null move cutoff
+    // doesn't try null moves: three quarters of the way to its horizon.
+    // Deep in the tree, this can lie beyond MAX_PLY, and beyond the range
+    // of an i8, so it is computed in i16 and capped at MAX_PLY.
+    fn null_move_min_ply(ply: i8, depth: i8, r: i8) -> i8 {
+        let min_ply = ply as i16 + (depth as i16 - r as i16) * 3 / 4;
+        min_ply.min(MAX_PLY as i16) as i8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api,
+        board::Board,
+        defs::MAX_PLY,
+        engine::defs::{SearchData, TT},
+        movegen::MoveGenerator,
+        search::{
+            defs::{SearchMode, SearchParams},
+            Search,
+        },
+    };
+
+    // Searches the position twice to the same depth with the same TT, and
+    // returns the number of nodes of both searches.
+    fn search_twice(fen: &str, depth: i8) -> (usize, usize) {
+        let mg = MoveGenerator::shared();
+        let tt = TT::<SearchData>::new(16);
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let mut params = SearchParams::new();
+        params.depth = depth;
+        params.search_mode = SearchMode::Depth;
+
+        let first = api::run(&board, &mg, &tt, params);
+        let second = api::run(&board, &mg, &tt, params);
+        assert!(first.best_move.get_move() == second.best_move.get_move());
+
+        (first.nodes, second.nodes)
+    }
+
+    // With the results of the first search in the TT, the second search
+    // is cut off almost everywhere: it needs less than a tenth of the
+    // nodes. The positions are tactical test positions.
+    #[test]
+    fn second_search_reuses_the_tt() {
+        let positions = [
+            ("8/7p/5k2/5p2/p1p2P2/Pr1pPK2/1P1R3P/8 b - - 0 1", 8),
+            (
+                "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+                6,
+            ),
+            (
+                "r1b2rk1/2q1b1pp/p2ppn2/1p6/3QP3/1BN1B3/PPP3PP/R4RK1 w - - 0 1",
+                6,
+            ),
+        ];
+
+        for (fen, depth) in positions.iter() {
+            let (first, second) = search_twice(fen, *depth);
+            assert!(second * 10 < first, "{}: {} then {}", fen, first, second);
+        }
+    }
+
+    #[test]
+    fn null_move_min_ply_stays_in_range() {
+        assert_eq!(Search::null_move_min_ply(0, 8, 3), 3);
+        assert_eq!(Search::null_move_min_ply(10, 12, 2), 17);
+        assert_eq!(Search::null_move_min_ply(100, 100, 2), MAX_PLY);
+        assert_eq!(Search::null_move_min_ply(MAX_PLY, MAX_PLY, 1), MAX_PLY);
+    }
+
+    // Node counts at a fixed depth in Kiwipete and the Lasker-Reichhelm
+    // position. Late move reductions decide most of the shape of these
+    // trees, so a change to them shows up here.
+    #[test]
+    fn late_move_reduction_node_counts() {
+        let snapshot = [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                7,
+                568_323,
+            ),
+            ("8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1", 20, 52_074),
+        ];
+
+        for (fen, depth, nodes) in snapshot.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = *depth;
+            params.search_mode = SearchMode::Depth;
+
+            assert_eq!(api::search(&board, params).nodes, *nodes, "{}", fen);
+        }
+    }
+
+    // Mates with a series of checks. Checks are extended, and quiescence
+    // search looks at all evasions when in check, so each mate is found at
+    // a nominal depth well below the plies it needs.
+    #[test]
+    fn forcing_mates_are_found_early() {
+        let positions = [
+            ("6k1/pp4p1/2p5/2bp4/8/P5Pb/1P3rrP/2BRRN1K b - - 0 1", 1, 2),
+            ("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1", 1, 3),
+            (
+                "1k5r/pP3ppp/3p2b1/1BN1n3/1Q2P3/P1B5/KP3P1P/7q w - - 1 0",
+                3,
+                3,
+            ),
+            ("2r3k1/p4p2/3Rp2p/1p2P1pK/8/1P4P1/P3Q2P/1q6 b - - 0 1", 3, 3),
+            (
+                "r1bqr3/ppp1B1kp/1b4p1/n2B4/3PQ1P1/2P5/P4P2/RN4K1 w - - 1 0",
+                4,
+                4,
+            ),
+        ];
+
+        for (fen, depth, mate_in) in positions.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = *depth;
+            params.search_mode = SearchMode::Depth;
+            let outcome = api::search(&board, params);
+
+            assert!(*depth < 2 * mate_in - 1);
+            assert_eq!(outcome.score.mate_in(), Some(*mate_in as i16), "{}", fen);
+        }
+    }
 }
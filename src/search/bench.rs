@@ -0,0 +1,150 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// bench.rs searches a fixed list of positions to a fixed depth, and adds
+// up the nodes. The total is a signature of the search: a patch that is
+// only meant to make the engine faster must not change it. To make the
+// signature deterministic, the search runs single-threaded on the calling
+// thread, without any time limit, and every position starts with a clean
+// TT of a fixed size and fresh search info. Nothing in the search that
+// depends on time (such as sending statistics) changes the node count.
+
+use super::{
+    defs::{
+        SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchShared, BENCH_HASH,
+    },
+    Search,
+};
+use crate::{
+    board::Board,
+    engine::defs::{ErrFatal, Information, SearchData, TT},
+    movegen::MoveGenerator,
+};
+use std::{sync::Arc, time::Instant};
+
+// Openings, middle games and endgames, with and without castling rights,
+// promotions and mating attacks.
+pub const BENCH_POSITIONS: [&str; 21] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 11",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "rq3rk1/ppp2ppp/1bnpb3/3N2B1/3NP3/7P/PPPQ1PP1/2KR3R w - - 7 14",
+    "r1bq1r1k/1pp1n1pp/1p1p4/4p2Q/4Pp2/1BNP4/PPP2PPP/3R1RK1 w - - 2 14",
+    "r3r1k1/2p2ppp/p1p1bn2/8/1q2P3/2NPQN2/PPP3PP/R4RK1 b - - 2 15",
+    "r1bbk1nr/pp3p1p/2n5/1N4p1/2Np1B2/8/PPP2PPP/2KR1B1R w kq - 0 13",
+    "r1bq1rk1/ppp1nppp/4n3/3p3Q/3P4/1BP1B3/PP1N2PP/R4RK1 w - - 1 16",
+    "4r1k1/r1q2ppp/ppp2n2/4P3/5Rb1/1N1BQ3/PPP3PP/R5K1 w - - 1 17",
+    "2rqkb1r/ppp2p2/2npb1p1/1N1Nn2p/2P1PP2/8/PP2B1PP/R1BQK2R b KQ - 0 11",
+    "r1bq1r1k/b1p1npp1/p2p3p/1p6/3PP3/1B2NN2/PP3PPP/R2Q1RK1 w - - 1 16",
+    "3r1rk1/p5pp/bpp1pp2/8/q1PP1P2/b3P3/P2NQRPP/1R2B1K1 b - - 6 22",
+    "r1q2rk1/2p1bppp/2Pp4/p6b/Q1PNp3/4B3/PP1R1PPP/2K4R w - - 2 18",
+    "4k2r/1pb2ppp/1p2p3/1R1p4/3P4/2r1PN2/P4PPP/1R4K1 b - - 3 22",
+    "3q2k1/pb3p1p/4pbp1/2r5/PpN2N2/1P2P2P/5PP1/Q2R2K1 b - - 4 26",
+    "6k1/6p1/6Pp/ppp5/3pn2P/1P3K2/1PP2P2/8 b - - 0 1",
+    "3b4/5kp1/1p1p1p1p/pP1PpP1P/P1P1P3/3KN3/8/8 w - - 0 1",
+    "2K5/p7/7P/5pR1/8/5k2/r7/8 w - - 0 1",
+    "8/6pk/1p6/8/PP3p1p/5P2/4KP1q/3Q4 w - - 0 1",
+    "7k/3p2pp/4q3/8/4Q3/5Kp1/P6b/8 w - - 0 1",
+];
+
+// The nodes searched in each of the bench positions, and the total time.
+pub struct BenchResult {
+    pub nodes: Vec<usize>,
+    pub time: u128,
+}
+
+impl BenchResult {
+    pub fn total_nodes(&self) -> usize {
+        self.nodes.iter().sum()
+    }
+
+    pub fn nps(&self) -> usize {
+        Search::nodes_per_second(self.total_nodes(), self.time)
+    }
+}
+
+impl Search {
+    // Searches all the bench positions to the given depth.
+    pub fn bench(mg: &Arc<MoveGenerator>, depth: i8) -> BenchResult {
+        let tt = TT::<SearchData>::new(BENCH_HASH);
+        let mut board = Board::new();
+        let mut result = BenchResult {
+            nodes: Vec::new(),
+            time: 0,
+        };
+
+        for fen in BENCH_POSITIONS.iter() {
+            board.fen_read(Some(fen)).expect(ErrFatal::NEW_GAME);
+            tt.clear();
+
+            let mut search_params = SearchParams::new();
+            search_params.search_mode = SearchMode::Depth;
+            search_params.depth = depth;
+            search_params.quiet = true;
+
+            // Nothing is sent on the control channel, and the reports
+            // are not needed.
+            let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+            let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+            let mut search_info = SearchInfo::new();
+            let shared = SearchShared::new();
+            let mut refs = SearchRefs {
+                board: &mut board,
+                mg,
+                tt: &tt,
+                search_params: &mut search_params,
+                search_info: &mut search_info,
+                shared: &shared,
+                control_rx: &control_rx,
+                report_tx: &report_tx,
+            };
+
+            let now = Instant::now();
+            Search::iterative_deepening(&mut refs);
+            result.time += now.elapsed().as_millis();
+            result.nodes.push(search_info.nodes);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BENCH_POSITIONS;
+    use crate::{movegen::MoveGenerator, search::Search};
+
+    // The bench is a signature of the search, so two runs must search
+    // exactly the same number of nodes in every position.
+    #[test]
+    fn bench_is_deterministic() {
+        let mg = MoveGenerator::shared();
+        let first = Search::bench(&mg, 5);
+        let second = Search::bench(&mg, 5);
+
+        assert_eq!(first.nodes.len(), BENCH_POSITIONS.len());
+        assert!(first.nodes.iter().all(|n| *n > 0));
+        assert_eq!(first.nodes, second.nodes);
+    }
+}
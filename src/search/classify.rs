@@ -0,0 +1,189 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// This file classifies the root position into a few broad classes, so
+// the search can be tuned for the kind of position it is looking at. A
+// closed middlegame with locked pawn chains is slow and strategic, so
+// late moves can be reduced harder and less time is needed. An open
+// position with a lot of piece activity is tactical: reduce less and
+// think longer. In a simplified endgame, zugzwang becomes a real danger,
+// so the null move is reduced by less. The adjustments are only used
+// when the "Adaptive Search" option is switched on.
+
+use super::{
+    defs::{
+        PositionClass, SearchRefs, SearchTunables, CLOSED_LOCKED_PAWNS, CLOSED_MAX_MOBILITY,
+        ENDGAME_MAX_PHASE, OPEN_MAX_PAWNS, OPEN_MIN_MOBILITY, OPEN_MIN_PHASE,
+    },
+    Search,
+};
+use crate::{
+    board::{defs::Pieces, Board},
+    defs::Sides,
+    evaluation::Evaluation,
+    movegen::MoveGenerator,
+};
+use std::fmt::{self, Display};
+
+impl Search {
+    // Determines the class of the position at the root of the search.
+    pub fn classify_position(refs: &SearchRefs) -> PositionClass {
+        PositionClass::of(refs.board, refs.mg)
+    }
+}
+
+impl PositionClass {
+    pub fn of(board: &Board, mg: &MoveGenerator) -> Self {
+        let phase = board.game_state.phase_value;
+        let w_pawns = board.get_pieces(Pieces::PAWN, Sides::WHITE);
+        let b_pawns = board.get_pieces(Pieces::PAWN, Sides::BLACK);
+        let pawns = (w_pawns | b_pawns).count_ones();
+
+        // A white pawn is locked if a black pawn stands right in front
+        // of it, and the other way around. Each such pair counts once.
+        let locked = ((w_pawns << 8) & b_pawns).count_ones();
+
+        let mobility = Evaluation::mobility_squares(board, mg, Sides::WHITE)
+            + Evaluation::mobility_squares(board, mg, Sides::BLACK);
+
+        if phase <= ENDGAME_MAX_PHASE {
+            PositionClass::SimplifiedEndgame
+        } else if locked >= CLOSED_LOCKED_PAWNS && mobility <= CLOSED_MAX_MOBILITY {
+            PositionClass::ClosedMiddlegame
+        } else if phase >= OPEN_MIN_PHASE
+            && locked == 0
+            && (pawns <= OPEN_MAX_PAWNS || mobility >= OPEN_MIN_MOBILITY)
+        {
+            PositionClass::OpenTactical
+        } else {
+            PositionClass::Normal
+        }
+    }
+
+    // Returns the search adjustments for this class of position.
+    pub fn tunables(&self) -> SearchTunables {
+        match self {
+            PositionClass::Normal => SearchTunables::new(),
+            PositionClass::ClosedMiddlegame => SearchTunables {
+                time_factor: 0.85,
+                lmr: 1,
+                null_move: 0,
+                ..SearchTunables::new()
+            },
+            PositionClass::OpenTactical => SearchTunables {
+                time_factor: 1.15,
+                lmr: -1,
+                null_move: 0,
+                ..SearchTunables::new()
+            },
+            PositionClass::SimplifiedEndgame => SearchTunables {
+                time_factor: 1.0,
+                lmr: 0,
+                null_move: -1,
+                ..SearchTunables::new()
+            },
+        }
+    }
+}
+
+impl Display for PositionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PositionClass::Normal => "normal",
+            PositionClass::ClosedMiddlegame => "closed middlegame",
+            PositionClass::OpenTactical => "open tactical",
+            PositionClass::SimplifiedEndgame => "simplified endgame",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(fen: &str) -> PositionClass {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        PositionClass::of(&board, &MoveGenerator::shared())
+    }
+
+    #[test]
+    fn hand_picked_positions() {
+        let positions = [
+            // The start position, and an advance French with only two
+            // locked pawn pairs.
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                PositionClass::Normal,
+            ),
+            (
+                "r1bq1rk1/pp1nbppp/2n1p3/2ppP3/3P4/2PB1N2/PP3PPP/RNBQ1RK1 w - - 0 1",
+                PositionClass::Normal,
+            ),
+            // A closed King's Indian: three locked pairs.
+            (
+                "r1bq1rk1/pp2npbp/3p1np1/2pPp3/2P1P3/2N2N2/PP2BPPP/R1BQ1RK1 w - - 0 1",
+                PositionClass::ClosedMiddlegame,
+            ),
+            // An open Sicilian with active pieces and no locked pawns.
+            (
+                "r2qk2r/1b3ppp/p3pn2/1pb5/4P3/2N2N2/PPQ2PPP/R1B1KB1R w KQkq - 0 1",
+                PositionClass::OpenTactical,
+            ),
+            // Rook endings and a pawn ending.
+            (
+                "8/5pk1/6p1/8/3R4/6P1/5PK1/8 w - - 0 1",
+                PositionClass::SimplifiedEndgame,
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                PositionClass::SimplifiedEndgame,
+            ),
+            (
+                "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+                PositionClass::SimplifiedEndgame,
+            ),
+        ];
+
+        for (fen, expected) in positions.iter() {
+            let class = class(fen);
+            assert!(class == *expected, "{}: {}", fen, class);
+        }
+    }
+
+    // A normal position keeps the search as it is.
+    #[test]
+    fn tunables_per_class() {
+        assert!(PositionClass::Normal.tunables() == SearchTunables::new());
+
+        let closed = PositionClass::ClosedMiddlegame.tunables();
+        assert!(closed.time_factor < 1.0 && closed.lmr > 0 && closed.null_move == 0);
+
+        let open = PositionClass::OpenTactical.tunables();
+        assert!(open.time_factor > 1.0 && open.lmr < 0 && open.null_move == 0);
+
+        let endgame = PositionClass::SimplifiedEndgame.tunables();
+        assert!(endgame.time_factor == 1.0 && endgame.lmr == 0 && endgame.null_move < 0);
+    }
+}
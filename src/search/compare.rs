@@ -0,0 +1,313 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// compare.rs searches every legal move at the root separately, to a fixed
+// shallow depth, and lists them from best to worst. This shows how much
+// worse the alternatives to the best move are, and where a move turns
+// into a blunder. Each root move is played on a copy of the board, and
+// the position after it is searched with temporary search parameters, on
+// the search thread itself. The shared TT speeds up the searches of the
+// next moves. A "stop" command cancels the comparison.
+
+use super::{
+    defs::{
+        Score, SearchInfo, SearchMode, SearchParams, SearchRefs, SearchReport, SearchTerminate,
+        CHECKMATE_THRESHOLD, COMPARE_GAP, COMPARE_PROGRESS_TIME, COMPARE_PV_PLIES,
+    },
+    Search,
+};
+use crate::{engine::defs::Information, misc::format, movegen::defs::Move};
+use std::{fmt::Write, time::Instant};
+
+// The score of a root move, and the line the search expects to follow.
+struct ComparedMove {
+    score: Score,
+    pv: Vec<Move>,
+}
+
+impl Search {
+    // Compares all legal root moves, and returns them as a table sorted
+    // by score. The depth of the comparison is the depth in the search
+    // parameters; the positions after the root moves are searched one
+    // ply less deep. If the comparison is cancelled, the moves compared
+    // until then are listed.
+    pub fn compare_moves(refs: &mut SearchRefs) -> String {
+        let depth = refs.search_params.depth;
+        let root_moves: Vec<Move> = refs.board.legal_moves_iter(refs.mg).collect();
+        let start = Instant::now();
+        let mut compared: Vec<ComparedMove> = Vec::new();
+
+        for (i, &m) in root_moves.iter().enumerate() {
+            // Show progress if the comparison is taking a while.
+            if start.elapsed().as_millis() >= COMPARE_PROGRESS_TIME {
+                let san = format::move_san(refs.board, refs.mg, m);
+                let msg = format!("comparing move {} of {}: {}", i + 1, root_moves.len(), san);
+                Search::send_info_string(refs, msg);
+            }
+
+            match Search::compare_move(refs, m, depth) {
+                Some(c) => compared.push(c),
+                None => break,
+            }
+        }
+
+        // Sort from best to worst. The sort is stable, so moves with the
+        // same score stay in move generator order.
+        compared.sort_by_key(|c| std::cmp::Reverse(c.score));
+        Search::compare_table(refs, &compared, root_moves.len(), depth)
+    }
+
+    // Searches the position after the root move. Returns None if the
+    // search was cancelled by a "stop" or "quit" command.
+    fn compare_move(refs: &mut SearchRefs, m: Move, depth: i8) -> Option<ComparedMove> {
+        let mut board = refs.board.clone();
+        board.make(m, refs.mg);
+
+        let mut search_params = SearchParams::new();
+        search_params.search_mode = SearchMode::Depth;
+        search_params.depth = (depth - 1).max(1);
+        search_params.quiet = true;
+
+        // The search of the root move reports to its own channel. Only
+        // the last summary is needed; the user gets to see the table.
+        let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut search_info = SearchInfo::new();
+        let mut move_refs = SearchRefs {
+            board: &mut board,
+            mg: refs.mg,
+            tt: refs.tt,
+            search_params: &mut search_params,
+            search_info: &mut search_info,
+            shared: refs.shared,
+            control_rx: refs.control_rx,
+            report_tx: &report_tx,
+        };
+
+        let (_, _, terminate) = Search::iterative_deepening(&mut move_refs);
+        if terminate != SearchTerminate::Nothing {
+            refs.search_info.terminate = terminate;
+            return None;
+        }
+
+        let mut compared = None;
+        while let Ok(information) = report_rx.try_recv() {
+            if let Information::Search(SearchReport::SearchSummary(s)) = information {
+                let mut pv = vec![m];
+                pv.extend(s.pv);
+                compared = Some(ComparedMove {
+                    score: Search::score_from_parent(s.score),
+                    pv,
+                });
+            }
+        }
+
+        compared
+    }
+
+    // Converts the score of the position after a move into the score of
+    // the move itself: the point of view changes, and a mate is one ply
+    // further away. A root move that mates right away leaves the child
+    // mated at ply 0, which is CHECKMATE itself; is_mate() excludes that
+    // value, so the bounds are compared directly, as in tt_encode().
+    fn score_from_parent(score: Score) -> Score {
+        let score = -score;
+        match score.value() {
+            v if v > CHECKMATE_THRESHOLD.value() => score - 1,
+            v if v < -CHECKMATE_THRESHOLD.value() => score + 1,
+            _ => score,
+        }
+    }
+
+    // Formats the compared moves as a table: rank, move, score and the
+    // start of the expected continuation. A line of dashes marks where
+    // the score drops by COMPARE_GAP or more compared to the move above.
+    fn compare_table(
+        refs: &SearchRefs,
+        compared: &[ComparedMove],
+        total: usize,
+        depth: i8,
+    ) -> String {
+        let mut table = String::new();
+        writeln!(
+            table,
+            "Compared {} of {} moves at depth {}:",
+            compared.len(),
+            total,
+            depth
+        )
+        .ok();
+
+        let mut previous: Option<Score> = None;
+        for (rank, c) in compared.iter().enumerate() {
+            if let Some(p) = previous {
+                let drop = p.value() as i32 - c.score.value() as i32;
+                if drop >= COMPARE_GAP as i32 {
+                    writeln!(table, "    ---- {} ----", Search::compare_drop(p, c.score)).ok();
+                }
+            }
+            previous = Some(c.score);
+
+            // Write out the first moves of the line in SAN.
+            let mut board = refs.board.clone();
+            let mut san = Vec::new();
+            for m in c.pv.iter().take(COMPARE_PV_PLIES + 1) {
+                san.push(format::move_san(&board, refs.mg, *m));
+                if !board.make(*m, refs.mg) {
+                    break;
+                }
+            }
+
+            let score = Search::compare_score(c.score);
+            let line = san[1..].join(" ");
+            writeln!(
+                table,
+                "{:>4}. {:<8}{:>8}   {}",
+                rank + 1,
+                san[0],
+                score,
+                line
+            )
+            .ok();
+        }
+
+        table
+    }
+
+    // A score as shown in the table: centipawns, or the number of moves
+    // to mate, prefixed by '#'.
+    fn compare_score(score: Score) -> String {
+        match score.mate_in() {
+            Some(moves) => format!("#{}", moves),
+            None => format!("{:+}", score.value()),
+        }
+    }
+
+    // Describes a blunder gap between two consecutive moves in the table.
+    fn compare_drop(above: Score, below: Score) -> String {
+        if below.mate_in().is_some_and(|m| m < 0) {
+            String::from("allows mate")
+        } else if above.mate_in().is_some_and(|m| m > 0) {
+            String::from("misses mate")
+        } else {
+            format!("{} cp worse", above.value() - below.value())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::Board,
+        engine::defs::{SearchData, TT},
+        movegen::MoveGenerator,
+        search::defs::{SearchControl, SearchShared, COMPARE_DEPTH},
+    };
+
+    // The queen on g5 can be taken by the knight on f3.
+    const QUEEN_HANGS: &str =
+        "r1b1kbnr/pppp1ppp/2n5/4p1q1/4P3/2N2N2/PPPP1PPP/R1BQKB1R w KQkq - 0 1";
+
+    // Compares the moves in the position, and returns the table. If
+    // "stop" is true, the comparison is cancelled right away.
+    fn compare(fen: &str, depth: i8, stop: bool) -> (String, SearchTerminate) {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let tt = TT::<SearchData>::new(16);
+        let (control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Compare;
+        params.depth = depth;
+        params.quiet = true;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        if stop {
+            control_tx.send(SearchControl::Stop).expect("channel");
+        }
+        let table = Search::compare_moves(&mut refs);
+        (table, search_info.terminate)
+    }
+
+    // At the default depth, winning the queen ranks first, and the next
+    // move is so much worse that a gap is marked below it.
+    #[test]
+    fn winning_capture_ranks_first() {
+        let (table, terminate) = compare(QUEEN_HANGS, COMPARE_DEPTH, false);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert!(terminate == SearchTerminate::Nothing);
+        assert_eq!(lines[0], "Compared 29 of 29 moves at depth 6:");
+        assert!(lines[1].starts_with("   1. Nxg5"), "{}", table);
+        assert!(lines[2].starts_with("    ---- "), "{}", table);
+        assert_eq!(lines.len(), 1 + 29 + table.matches("----").count() / 2);
+    }
+
+    #[test]
+    fn stop_cancels_the_comparison() {
+        let (table, terminate) = compare(QUEEN_HANGS, COMPARE_DEPTH, true);
+
+        assert!(terminate == SearchTerminate::Stop);
+        assert_eq!(table, "Compared 0 of 29 moves at depth 6:\n");
+    }
+
+    #[test]
+    fn mates_in_the_table() {
+        let (table, _) = compare("7k/8/6K1/8/8/8/8/R7 w - - 0 1", 2, false);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines[1].trim_end(), "   1. Ra8#          #1");
+        assert!(lines[2].contains("---- misses mate ----"), "{}", table);
+    }
+
+    #[test]
+    fn scores_from_the_parent() {
+        // Being mated in one move after the root move means the root move
+        // mates in two: the mate is one ply further away.
+        let mated = Score::mated_at(2);
+        assert_eq!(mated.mate_in(), Some(-1));
+        assert_eq!(Search::score_from_parent(mated).mate_in(), Some(2));
+        assert_eq!(
+            Search::score_from_parent(Score::mated_at(0)).mate_in(),
+            Some(1)
+        );
+        assert_eq!(Search::score_from_parent(Score::new(35)).value(), -35);
+        assert_eq!(Search::compare_score(Score::new(35)), "+35");
+        assert_eq!(
+            Search::compare_drop(Score::new(300), Score::new(-20)),
+            "320 cp worse"
+        );
+    }
+}
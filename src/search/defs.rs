@@ -1,7 +1,8 @@
 use crate::{
     board::Board,
-    defs::MAX_PLY,
+    defs::{NrOf, Side, Sides, MAX_PLY},
     engine::defs::{Information, SearchData, TT},
+    evaluation::defs::PawnTable,
     movegen::{
         defs::{Move, ShortMove},
         MoveGenerator,
@@ -9,32 +10,100 @@ use crate::{
 };
 use crossbeam_channel::{Receiver, Sender};
 use std::{
-    sync::{Arc, Mutex},
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 pub use super::time::OVERHEAD;
 
-pub const INF: i16 = 25_000;
+pub use super::score::Score;
+
+pub const INF: Score = Score::new(25_000);
 // pub const ASPIRATION_WINDOW: i16 = 50;
-pub const CHECKMATE: i16 = 24_000;
-pub const CHECKMATE_THRESHOLD: i16 = 23_900;
-pub const STALEMATE: i16 = 0;
-pub const DRAW: i16 = 0;
+pub const CHECKMATE: Score = Score::new(24_000);
+pub const CHECKMATE_THRESHOLD: Score = Score::new(23_900);
+pub const STALEMATE: Score = Score::new(0);
+pub const DRAW: Score = Score::new(0);
 pub const CHECK_TERMINATION: usize = 0x7FF; // 2.047 nodes
 pub const SEND_STATS: usize = 0x7FFFF; // 524.287 nodes
 pub const MIN_TIME_STATS: u128 = 2_000; // Minimum time for sending stats
 pub const MIN_TIME_CURR_MOVE: u128 = 1_000; // Minimum time for sending curr_move
+pub const NPS_SMOOTHING: f64 = 0.3; // Weight of the newest interval in the reported NPS
 pub const MAX_KILLER_MOVES: usize = 2;
+pub const NULL_MOVE_MIN_DEPTH: i8 = 3; // No null move below this depth
+pub const NULL_MOVE_DEEP: i8 = 6; // Reduce by 3 instead of 2 above this depth
+pub const NULL_MOVE_VERIFY_DEPTH: i8 = 8; // Verify null move cutoffs from here
+pub const LMR_MIN_DEPTH: i8 = 3; // No late move reductions below this depth
+pub const LMR_MIN_MOVES: u8 = 3; // Never reduce the first moves in the list
+const LMR_TABLE_SIZE: usize = 64; // Depths and move numbers in the table
+pub const SINGULAR_MIN_DEPTH: i8 = 4; // Check if the best move is singular from here
+pub const SINGULAR_TIME_DIVISOR: u128 = 3; // Singular: stop after 1/3 of the time
+pub const HISTORY_MAX: i32 = 16_384; // History scores stay between -/+ this
+pub const HISTORY_BONUS_MAX: i32 = 1_600; // Largest change by a single update
+pub const HISTORY_DECAY: i16 = 2; // History scores are divided by this between searches
+pub const HELPER_PAUSE_POLL: u64 = 10; // Msecs between pause checks in helpers
+pub const PV_CHECK_MIN_DEPTH: i8 = 4; // Re-check the PV from this depth onward
+pub const PV_CHECK_DEPTH: i8 = 2; // Depth of the search at each PV position
+pub const PV_CHECK_MARGIN: i16 = 100; // Centipawns a re-check may deviate
+pub const PV_CHECK_NODE_DIVISOR: usize = 10; // Re-check: 1/10 of the iteration's nodes
+pub const REPEAT_AHEAD: i16 = 150; // Avoid repeating the game when ahead by this
+pub const REPEAT_MARGIN: i16 = 50; // A repeating root move must win by this much
+pub const REPEAT_MIN_COUNT: u8 = 2; // Earlier occurrences of a repeating position
+pub const PV_TRUNCATED: &str = "..."; // Marks the end of a shortened PV
+pub const MATE_VERIFY_HASH: usize = 16; // MB of TT for verifying a mate
+pub const BENCH_HASH: usize = 16; // MB of TT for the bench
+pub const BENCH_DEPTH: i8 = 8; // Default depth of the bench searches
+pub const COMPARE_DEPTH: i8 = 6; // Default depth when comparing root moves
+pub const COMPARE_GAP: i16 = 150; // Mark score drops of this many centipawns
+pub const COMPARE_PV_PLIES: usize = 4; // Plies of the continuation to show
+pub const COMPARE_PROGRESS_TIME: u128 = 1_000; // Msecs before showing progress
+pub const ENDGAME_MAX_PHASE: i16 = 8; // Simplified endgame at or below this phase
+pub const CLOSED_LOCKED_PAWNS: u32 = 3; // Closed: at least this many locked pawn pairs
+pub const CLOSED_MAX_MOBILITY: usize = 45; // Closed: at most this many safe squares
+pub const OPEN_MIN_PHASE: i16 = 10; // Open: enough pieces left for tactics
+pub const OPEN_MAX_PAWNS: u32 = 12; // Open: at most this many pawns, or...
+pub const OPEN_MIN_MOBILITY: usize = 55; // ...at least this many safe squares
 
-pub type SearchResult = (Move, SearchTerminate);
+pub type SearchResult = (Move, Option<Move>, SearchTerminate);
 type KillerMoves = [[ShortMove; MAX_KILLER_MOVES]; MAX_PLY as usize];
-// type HistoryHeuristic = [[[u32; NrOf::SQUARES]; NrOf::PIECE_TYPES]; Sides::BOTH];
+type PvLines = [[Move; MAX_PLY as usize]; MAX_PLY as usize + 1];
+pub type HistoryHeuristic = [[[i16; NrOf::SQUARES]; NrOf::SQUARES]; Sides::BOTH];
+
+// Late move reductions per [depth][move number]. The table is calculated
+// the first time it is used, and then shared by all searches.
+type LmrTable = [[i8; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+static LMR_TABLE: OnceLock<LmrTable> = OnceLock::new();
+
+// Returns the number of plies to reduce a late move by. The reduction
+// grows with both the remaining depth and the move's place in the list,
+// but always leaves at least one ply to search.
+pub fn lmr_reduction(depth: i8, move_number: u8) -> i8 {
+    let table = LMR_TABLE.get_or_init(|| {
+        let mut table = [[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+        for (d, row) in table.iter_mut().enumerate().skip(1) {
+            for (m, reduction) in row.iter_mut().enumerate().skip(1) {
+                let r = 0.75 + (d as f64).ln() * (m as f64).ln() / 2.25;
+                *reduction = r as i8;
+            }
+        }
+        table
+    });
+
+    let d = (depth.max(0) as usize).min(LMR_TABLE_SIZE - 1);
+    let m = (move_number as usize).min(LMR_TABLE_SIZE - 1);
+    table[d][m].min(depth - 2).max(0)
+}
 
 #[derive(PartialEq)]
 // These commands can be used by the engine thread to control the search.
 pub enum SearchControl {
     Start(SearchParams),
+    PonderHit,
+    Pause,
+    Resume,
     Stop,
     Quit,
     Nothing,
@@ -55,8 +124,11 @@ pub enum SearchMode {
     Depth,    // Run until requested depth is reached.
     MoveTime, // Run until 'time per move' is used up.
     Nodes,    // Run until the number of requested nodes was reached.
+    Mate,     // Run until a mate in the requested number of moves is found.
     GameTime, // Search determines when to quit, depending on available time.
     Infinite, // Run forever, until the 'stop' command is received.
+    Ponder,   // Like infinite, until 'ponderhit' turns it into GameTime.
+    Compare,  // Search each root move to the requested depth.
     Nothing,  // No search mode has been defined.
 }
 
@@ -66,7 +138,8 @@ pub struct GameTime {
     pub btime: u128,                // Black time on the clock in milliseconds
     pub winc: u128,                 // White time increment in milliseconds (if wtime > 0)
     pub binc: u128,                 // Black time increment in milliseconds (if btime > 0)
-    pub moves_to_go: Option<usize>, // Moves to go to next time control (0 = sudden death)
+    pub moves_to_go: Option<usize>, // Moves to go to next time control (None = sudden death)
+    pub clamped: bool,              // A negative clock value was received and set to 0
 }
 
 impl GameTime {
@@ -83,6 +156,51 @@ impl GameTime {
             winc,
             binc,
             moves_to_go,
+            clamped: false,
+        }
+    }
+
+    // Returns the time on the clock and the increment of the given side.
+    pub fn for_side(&self, side: Side) -> (u128, u128) {
+        if side == Sides::WHITE {
+            (self.wtime, self.winc)
+        } else {
+            (self.btime, self.binc)
+        }
+    }
+
+    // Returns true if all the clocks and increments are zero. This is not
+    // a real time control, so there is no time to base a search on.
+    pub fn is_empty(&self) -> bool {
+        self.wtime == 0 && self.btime == 0 && self.winc == 0 && self.binc == 0
+    }
+}
+
+// Broad classes of positions, as determined at the root of the search.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum PositionClass {
+    Normal,            // Nothing special; search as usual.
+    ClosedMiddlegame,  // Locked pawn chains and little piece activity.
+    OpenTactical,      // Open lines and active pieces.
+    SimplifiedEndgame, // Few pieces left; zugzwang becomes possible.
+}
+
+// Adjustments to the search, depending on the class of the position.
+#[derive(PartialEq, Copy, Clone)]
+pub struct SearchTunables {
+    pub time_factor: f64,   // Multiplies the time allocated for the move
+    pub lmr: i8,            // Added to each late move reduction
+    pub null_move: i8,      // Added to the null move reduction
+    pub nps_smoothing: f64, // Weight of the newest interval in the NPS average
+}
+
+impl SearchTunables {
+    pub fn new() -> Self {
+        Self {
+            time_factor: 1.0,
+            lmr: 0,
+            null_move: 0,
+            nps_smoothing: NPS_SMOOTHING,
         }
     }
 }
@@ -92,12 +210,29 @@ impl GameTime {
 // before the game starts.)
 #[derive(PartialEq, Copy, Clone)]
 pub struct SearchParams {
-    pub depth: i8,               // Maximum depth to search to
-    pub move_time: u128,         // Maximum time per move to search
-    pub nodes: usize,            // Maximum number of nodes to search
-    pub game_time: GameTime,     // Time available for entire game
-    pub search_mode: SearchMode, // Defines the mode to search in
-    pub quiet: bool,             // No intermediate search stats updates
+    pub depth: i8,                 // Maximum depth to search to
+    pub move_time: u128,           // Maximum time per move to search
+    pub nodes: usize,              // Maximum number of nodes to search
+    pub mate: u8,                  // Number of moves to find a mate in
+    pub game_time: GameTime,       // Time available for entire game
+    pub search_mode: SearchMode,   // Defines the mode to search in
+    pub quiet: bool,               // No intermediate search stats updates
+    pub report_interval: u128,     // Maximum msecs without stats (0 = off)
+    pub multipv: usize,            // Number of lines to search (1 = normal)
+    pub debug: bool,               // Send extra diagnostics as info strings
+    pub singular_margin: i16,      // Centipawns to call the best move singular
+    pub adaptive: bool,            // Adjust the search to the position class
+    pub complete_iterations: bool, // Only stop between iterations on soft time
+    pub threads: usize,            // Number of threads searching (Lazy SMP)
+    pub helper: usize,             // Helper thread number (0 = main thread)
+    pub affinity: bool,            // Pin each thread to its own CPU
+    pub id: usize,                 // Returned in the report ending the search
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SearchParams {
@@ -106,9 +241,20 @@ impl SearchParams {
             depth: MAX_PLY,
             move_time: 0,
             nodes: 0,
+            mate: 0,
             game_time: GameTime::new(0, 0, 0, 0, None),
             search_mode: SearchMode::Nothing,
             quiet: false,
+            report_interval: 0,
+            multipv: 1,
+            debug: false,
+            singular_margin: 150,
+            adaptive: false,
+            complete_iterations: false,
+            threads: 1,
+            helper: 0,
+            affinity: false,
+            id: 0,
         }
     }
 
@@ -121,16 +267,32 @@ impl SearchParams {
 // search into this struct.
 #[derive(PartialEq)]
 pub struct SearchInfo {
-    start_time: Option<Instant>,    // Time the search started
-    pub depth: i8,                  // Depth currently being searched
-    pub seldepth: i8,               // Maximum selective depth reached
-    pub nodes: usize,               // Nodes searched
-    pub ply: i8,                    // Number of plys from the root
-    pub killer_moves: KillerMoves,  // Killer moves (array; see "type" above)
-    pub last_stats_sent: u128,      // When last stats update was sent
-    pub last_curr_move_sent: u128,  // When last current move was sent
-    pub allocated_time: u128,       // Allotted msecs to spend on move
-    pub terminate: SearchTerminate, // Terminate flag
+    start_time: Option<Instant>,      // Time the search started
+    pub depth: i8,                    // Depth currently being searched
+    pub seldepth: i8,                 // Maximum selective depth reached
+    pub nodes: usize,                 // Nodes searched
+    pub nodes_shared: usize,          // Nodes added to the shared count
+    pub ply: i8,                      // Number of plys from the root
+    pub killer_moves: KillerMoves,    // Killer moves (array; see "type" above)
+    pub null_move_min_ply: i8,        // No null moves before this ply
+    pub depth_nodes: Vec<usize>,      // Total nodes when each depth finished
+    pub depth_times: Vec<u128>,       // Move time (msecs) when each depth finished
+    pub history: HistoryHeuristic,    // History scores by [side][from][to]
+    pub pv: PvTable,                  // Principal Variation per ply
+    pub root_move: SearchCurrentMove, // Root move currently being searched
+    pub root_moves: u8,               // Number of legal moves at the root
+    pub last_stats_sent: u128,        // When last stats update was sent
+    pub last_stats_nodes: usize,      // Nodes searched when last stats were sent
+    pub nps_average: f64,             // Moving average of the interval NPS
+    pub last_curr_move_sent: u128,    // When last current move was sent
+    pub allocated_time: u128,         // Allotted msecs to spend on move
+    pub ponder_time: u128,            // Msecs spent pondering before ponderhit
+    pub excluded: Vec<ShortMove>,     // Root moves skipped in MultiPV mode
+    pub pawn_table: PawnTable,        // Cached pawn structure scores
+    pub repeating: Vec<ShortMove>,    // Root moves repeating a game position
+    pub avoid_repeating: bool,        // Order repeating root moves last
+    pub tunables: SearchTunables,     // Adjustments for the position class
+    pub terminate: SearchTerminate,   // Terminate flag
 }
 
 impl SearchInfo {
@@ -140,11 +302,27 @@ impl SearchInfo {
             depth: 0,
             seldepth: 0,
             nodes: 0,
+            nodes_shared: 0,
             ply: 0,
             killer_moves: [[ShortMove::new(0); MAX_KILLER_MOVES]; MAX_PLY as usize],
+            null_move_min_ply: 0,
+            depth_nodes: Vec::new(),
+            depth_times: Vec::new(),
+            history: [[[0; NrOf::SQUARES]; NrOf::SQUARES]; Sides::BOTH],
+            pv: PvTable::new(),
+            root_move: SearchCurrentMove::new(Move::new(0), 0, 0),
+            root_moves: 0,
             last_stats_sent: 0,
+            last_stats_nodes: 0,
+            nps_average: 0.0,
             last_curr_move_sent: 0,
             allocated_time: 0,
+            ponder_time: 0,
+            excluded: Vec::new(),
+            pawn_table: PawnTable::new(),
+            repeating: Vec::new(),
+            avoid_repeating: false,
+            tunables: SearchTunables::new(),
             terminate: SearchTerminate::Nothing,
         }
     }
@@ -153,6 +331,14 @@ impl SearchInfo {
         self.start_time = Some(Instant::now());
     }
 
+    // Moves the start of the search forward by the given duration, so the
+    // time the search was paused doesn't count as searching time.
+    pub fn timer_exclude(&mut self, duration: Duration) {
+        if let Some(x) = self.start_time {
+            self.start_time = Some(x + duration);
+        }
+    }
+
     pub fn timer_elapsed(&self) -> u128 {
         if let Some(x) = self.start_time {
             x.elapsed().as_millis()
@@ -161,9 +347,105 @@ impl SearchInfo {
         }
     }
 
+    // Time spent searching for our own move, which excludes the time
+    // spent pondering on the opponent's time.
+    pub fn move_elapsed(&self) -> u128 {
+        self.timer_elapsed().saturating_sub(self.ponder_time)
+    }
+
     pub fn interrupted(&self) -> bool {
         self.terminate != SearchTerminate::Nothing
     }
+
+    // The effective branching factor (EBF) of the last finished depth:
+    // the nodes that depth needed, divided by those of the depth before.
+    pub fn branching_factor(&self) -> Option<f64> {
+        branching_factors(&self.depth_nodes).last().copied()
+    }
+
+    // The time it took to search the last finished depth.
+    pub fn iteration_time(&self) -> Option<u128> {
+        let n = self.depth_times.len();
+        match n {
+            0 => None,
+            1 => Some(self.depth_times[0]),
+            _ => Some(self.depth_times[n - 1].saturating_sub(self.depth_times[n - 2])),
+        }
+    }
+
+    // The average EBF over all finished depths, except the first.
+    pub fn average_branching_factor(&self) -> Option<f64> {
+        let factors = branching_factors(&self.depth_nodes);
+        if factors.is_empty() {
+            None
+        } else {
+            Some(factors.iter().sum::<f64>() / factors.len() as f64)
+        }
+    }
+}
+
+// Converts the total node counts at the end of each depth into the EBF of
+// every depth after the first. A depth that needed no nodes can't be
+// divided by, so the depth after it is skipped.
+fn branching_factors(depth_nodes: &[usize]) -> Vec<f64> {
+    let mut previous_total = 0;
+    let per_depth: Vec<usize> = depth_nodes
+        .iter()
+        .map(|&total| {
+            let nodes = total.saturating_sub(previous_total);
+            previous_total = total;
+            nodes
+        })
+        .collect();
+
+    per_depth
+        .windows(2)
+        .filter(|w| w[0] > 0)
+        .map(|w| w[1] as f64 / w[0] as f64)
+        .collect()
+}
+
+// Triangular PV table. Each ply has its own line, holding the best
+// variation found from that ply onward. When a move raises alpha, the
+// line of the ply is rebuilt from that move, followed by the line of the
+// next ply. Because the table is allocated once for the entire search,
+// no heap allocations are needed in the search tree.
+#[derive(PartialEq)]
+pub struct PvTable {
+    lines: PvLines,
+    length: [usize; MAX_PLY as usize + 1],
+}
+
+impl PvTable {
+    pub fn new() -> Self {
+        Self {
+            lines: [[Move::new(0); MAX_PLY as usize]; MAX_PLY as usize + 1],
+            length: [0; MAX_PLY as usize + 1],
+        }
+    }
+
+    // Empty the line at the given ply. This is done when a node is
+    // entered, so a node that doesn't raise alpha has no PV.
+    pub fn clear(&mut self, ply: i8) {
+        self.length[ply as usize] = 0;
+    }
+
+    // Set the line at the given ply to the move, followed by the line
+    // that was found at the next ply.
+    pub fn update(&mut self, ply: i8, m: Move) {
+        let ply = ply as usize;
+        let next_length = self.length[ply + 1];
+        let (current, next) = self.lines.split_at_mut(ply + 1);
+
+        current[ply][0] = m;
+        current[ply][1..=next_length].copy_from_slice(&next[0][..next_length]);
+        self.length[ply] = next_length + 1;
+    }
+
+    // Returns the PV from the root as a list of moves.
+    pub fn root(&self) -> Vec<Move> {
+        self.lines[0][..self.length[0]].to_vec()
+    }
 }
 
 // After each completed depth, iterative deepening summarizes the running
@@ -172,15 +454,26 @@ impl SearchInfo {
 // information into UCI/XBoard/Console output and print it to STDOUT.
 #[derive(PartialEq, Clone)]
 pub struct SearchSummary {
-    pub depth: i8,      // depth reached during search
-    pub seldepth: i8,   // Maximum selective depth reached
-    pub time: u128,     // milliseconds
-    pub cp: i16,        // centipawns score
-    pub mate: u8,       // mate in X moves
-    pub nodes: usize,   // nodes searched
-    pub nps: usize,     // nodes per second
-    pub hash_full: u16, // TT use in permille
-    pub pv: Vec<Move>,  // Principal Variation
+    pub depth: i8,          // depth reached during search
+    pub seldepth: i8,       // Maximum selective depth reached
+    pub time: u128,         // milliseconds
+    pub score: Score,       // Score (centipawns or mate)
+    pub nodes: usize,       // nodes searched
+    pub nps: usize,         // nodes per second
+    pub hash_full: u16,     // TT use in permille
+    pub pv: Vec<Move>,      // Principal Variation
+    pub multipv: usize,     // Line number in MultiPV mode (0 = MultiPV off)
+    pub bound: ScoreBound,  // Exact, or unstable when re-checking the PV
+    pub pv_truncated: bool, // PV was shortened for display
+}
+
+// The score of a search summary is exact, unless re-checking the PV found
+// that it may be too high (upper bound) or too low (lower bound).
+#[derive(PartialEq, Copy, Clone)]
+pub enum ScoreBound {
+    Exact,
+    Lower,
+    Upper,
 }
 
 impl SearchSummary {
@@ -190,24 +483,43 @@ impl SearchSummary {
             let m = format!(" {}", next_move.as_string());
             pv.push_str(&m[..]);
         }
+        if self.pv_truncated {
+            pv.push(' ');
+            pv.push_str(PV_TRUNCATED);
+        }
         pv.trim().to_string()
     }
+
+    // Shortens the PV to at most the given number of moves, for display
+    // only; the search keeps using the full PV. 0 means no limit.
+    pub fn cap_pv(&mut self, max_length: usize) {
+        if max_length > 0 && self.pv.len() > max_length {
+            self.pv.truncate(max_length);
+            self.pv_truncated = true;
+        }
+    }
 }
 
 #[derive(PartialEq, Copy, Clone)]
 // This struct holds the currently searched move, and its move number in
-// the list of legal moves. This struct is sent through the engine thread
-// to Comm, to be transmitted to the (G)UI.
+// the list of legal moves. It also holds the number of root moves in this
+// iteration, and how many of them are still to be searched after this
+// one. This struct is sent through the engine thread to Comm, to be
+// transmitted to the (G)UI.
 pub struct SearchCurrentMove {
     pub curr_move: Move,
     pub curr_move_number: u8,
+    pub total_moves: u8,
+    pub moves_left: u8,
 }
 
 impl SearchCurrentMove {
-    pub fn new(curr_move: Move, curr_move_number: u8) -> Self {
+    pub fn new(curr_move: Move, curr_move_number: u8, total_moves: u8) -> Self {
         Self {
             curr_move,
             curr_move_number,
+            total_moves,
+            moves_left: total_moves.saturating_sub(curr_move_number),
         }
     }
 }
@@ -216,10 +528,12 @@ impl SearchCurrentMove {
 // engine thread to Comm, to be transmitted to the (G)UI.
 #[derive(PartialEq, Copy, Clone)]
 pub struct SearchStats {
-    pub time: u128,     // Time spent searching
-    pub nodes: usize,   // Number of nodes searched
-    pub nps: usize,     // Speed in nodes per second
-    pub hash_full: u16, // TT full in permille
+    pub time: u128,            // Time spent searching
+    pub nodes: usize,          // Number of nodes searched
+    pub nps: usize,            // Speed in nodes per second (smoothed)
+    pub hash_full: u16,        // TT full in permille
+    pub interval_nodes: usize, // Nodes searched since the last stats
+    pub interval_time: u128,   // Msecs passed since the last stats
 }
 
 impl SearchStats {
@@ -229,8 +543,17 @@ impl SearchStats {
             nodes,
             nps,
             hash_full,
+            interval_nodes: nodes,
+            interval_time: time,
         }
     }
+
+    // Sets the nodes and time since the previous stats were sent.
+    pub fn with_interval(mut self, nodes: usize, time: u128) -> Self {
+        self.interval_nodes = nodes;
+        self.interval_time = time;
+        self
+    }
 }
 
 // The search process needs references to a lot of data, such as a copy of
@@ -243,18 +566,201 @@ impl SearchStats {
 pub struct SearchRefs<'a> {
     pub board: &'a mut Board,
     pub mg: &'a Arc<MoveGenerator>,
-    pub tt: &'a Arc<Mutex<TT<SearchData>>>,
+    pub tt: &'a TT<SearchData>,
     pub search_params: &'a mut SearchParams,
     pub search_info: &'a mut SearchInfo,
+    pub shared: &'a SearchShared,
     pub control_rx: &'a Receiver<SearchControl>,
     pub report_tx: &'a Sender<Information>,
 }
 
+// In a Lazy SMP search, the main search thread starts helper threads which
+// search the same position, to fill the shared TT. Only the main thread
+// receives commands from the engine. It passes them on to the helpers
+// through this struct. The helpers add the nodes they search, so the main
+// thread can report the total.
+pub struct SearchShared {
+    pub stop: AtomicBool,
+    pub pause: AtomicBool,
+    pub helper_nodes: AtomicUsize,
+}
+
+impl SearchShared {
+    pub fn new() -> Self {
+        Self {
+            stop: AtomicBool::new(false),
+            pause: AtomicBool::new(false),
+            helper_nodes: AtomicUsize::new(0),
+        }
+    }
+}
+
+// The result of verifying a mate in N moves. The search either proves a
+// mate of exactly the expected distance, or it reports the mate it found
+// instead (a shorter one, or none at all) together with its best move.
+#[derive(PartialEq, Copy, Clone)]
+pub struct MateVerification {
+    pub moves: u8,            // Expected number of moves to mate
+    pub mate_in: Option<i16>, // Mate distance found by the search
+    pub best_move: Move,      // The mating move, if the mate was found
+}
+
+impl MateVerification {
+    // Returns true if the mate was found at exactly the expected distance.
+    pub fn is_proven(&self) -> bool {
+        self.mate_in == Some(self.moves as i16) && !self.best_move.is_null()
+    }
+
+    pub fn as_string(&self) -> String {
+        let found = match self.mate_in {
+            Some(m) => format!("mate in {}", m),
+            None => String::from("no mate"),
+        };
+        let result = if self.is_proven() {
+            "proven"
+        } else {
+            "not proven"
+        };
+
+        format!(
+            "mate in {} {}: found {}, best move {}",
+            self.moves,
+            result,
+            found,
+            self.best_move.as_string()
+        )
+    }
+}
+
 // This struct holds all the reports a search can send to the engine.
 #[derive(PartialEq)]
 pub enum SearchReport {
-    Finished(Move),                       // Search done. Contains the best move.
-    SearchSummary(SearchSummary),         // Periodic intermediate results.
+    Finished(usize, Move, Option<Move>), // Search done. Id, best move and ponder move.
+    SearchSummary(SearchSummary),        // Periodic intermediate results.
     SearchCurrentMove(SearchCurrentMove), // Move currently searched.
-    SearchStats(SearchStats),             // General search statistics
+    SearchStats(SearchStats),            // General search statistics
+    InfoString(String),                  // Message for the user interface
+    Compared(usize, String),             // Comparison done. Id and table of root moves.
+    TimeBudget(u128),                    // Most msecs the search will use (GameTime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_info(depth_nodes: &[usize]) -> SearchInfo {
+        let mut info = SearchInfo::new();
+        info.depth_nodes = depth_nodes.to_vec();
+        info
+    }
+
+    // The node counts are totals, so each depth needed the difference
+    // with the depth before it.
+    #[test]
+    fn branching_factors_of_node_totals() {
+        assert!(branching_factors(&[]).is_empty());
+        assert!(branching_factors(&[20]).is_empty());
+        assert_eq!(branching_factors(&[20, 100]), vec![4.0]);
+        assert_eq!(branching_factors(&[10, 40, 160, 1120]), vec![3.0, 4.0, 8.0]);
+
+        // A depth that needed no nodes (for example because every move
+        // came from the TT) can't be divided by.
+        assert_eq!(branching_factors(&[10, 40, 40, 80]), vec![3.0, 0.0]);
+    }
+
+    #[test]
+    fn last_and_average_branching_factor() {
+        let info = search_info(&[]);
+        assert_eq!(info.branching_factor(), None);
+        assert_eq!(info.average_branching_factor(), None);
+
+        let info = search_info(&[50]);
+        assert_eq!(info.branching_factor(), None);
+        assert_eq!(info.average_branching_factor(), None);
+
+        let info = search_info(&[10, 40, 160, 1120]);
+        assert_eq!(info.branching_factor(), Some(8.0));
+        assert_eq!(info.average_branching_factor(), Some(5.0));
+    }
+
+    // The moves left are counted down from the total, and never below 0.
+    #[test]
+    fn current_move_moves_left() {
+        let m = Move::new(0);
+        assert_eq!(SearchCurrentMove::new(m, 1, 20).moves_left, 19);
+        assert_eq!(SearchCurrentMove::new(m, 12, 20).moves_left, 8);
+        assert_eq!(SearchCurrentMove::new(m, 20, 20).moves_left, 0);
+        assert_eq!(SearchCurrentMove::new(m, 3, 0).moves_left, 0);
+    }
+
+    // A summary of a search from the start position, with the given PV.
+    fn summary(pv: &[&str]) -> SearchSummary {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+        let pv = pv
+            .iter()
+            .map(|s| {
+                let m = board
+                    .legal_moves_iter(&mg)
+                    .find(|m| m.as_string() == *s)
+                    .expect(s);
+                assert!(board.make(m, &mg));
+                m
+            })
+            .collect();
+
+        SearchSummary {
+            depth: 4,
+            seldepth: 6,
+            time: 10,
+            score: Score::new(25),
+            nodes: 1000,
+            nps: 100000,
+            hash_full: 0,
+            pv,
+            multipv: 0,
+            bound: ScoreBound::Exact,
+            pv_truncated: false,
+        }
+    }
+
+    // Capping the PV only shortens a longer PV, and marks it; 0 means no
+    // limit.
+    #[test]
+    fn capped_pv() {
+        let pv = ["e2e4", "e7e5", "g1f3", "b8c6"];
+        let full = "e2e4 e7e5 g1f3 b8c6";
+        let capped = [
+            (0, 4, full),
+            (4, 4, full),
+            (5, 4, full),
+            (2, 2, "e2e4 e7e5 ..."),
+            (1, 1, "e2e4 ..."),
+        ];
+
+        for (max_length, length, expected) in capped.iter() {
+            let mut s = summary(&pv);
+            s.cap_pv(*max_length);
+            assert_eq!(s.pv.len(), *length);
+            assert_eq!(s.pv_truncated, *length < pv.len());
+            assert_eq!(s.pv_as_string(), *expected);
+        }
+
+        let mut s = summary(&[]);
+        s.cap_pv(1);
+        assert_eq!(s.pv_as_string(), "");
+    }
+
+    // The time of an iteration is the difference between the move times
+    // at which the last two depths finished.
+    #[test]
+    fn iteration_times() {
+        let mut info = SearchInfo::new();
+        assert_eq!(info.iteration_time(), None);
+        info.depth_times = vec![120];
+        assert_eq!(info.iteration_time(), Some(120));
+        info.depth_times = vec![120, 400, 1300];
+        assert_eq!(info.iteration_time(), Some(900));
+    }
 }
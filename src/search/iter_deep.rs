@@ -22,103 +22,1406 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::{
-    defs::{SearchMode, SearchRefs, SearchResult, INF},
+    defs::{
+        Score, ScoreBound, SearchMode, SearchRefs, SearchResult, DRAW, INF, PV_CHECK_DEPTH,
+        PV_CHECK_MARGIN, PV_CHECK_MIN_DEPTH, PV_CHECK_NODE_DIVISOR, REPEAT_AHEAD, REPEAT_MARGIN,
+        REPEAT_MIN_COUNT, SINGULAR_MIN_DEPTH, SINGULAR_TIME_DIVISOR,
+    },
     ErrFatal, Information, Search, SearchReport, SearchSummary,
 };
-use crate::{defs::MAX_PLY, movegen::defs::Move};
+use crate::{
+    defs::MAX_PLY,
+    movegen::defs::{Move, ShortMove},
+};
 
 // Actual search routines.
 impl Search {
     pub fn iterative_deepening(refs: &mut SearchRefs) -> SearchResult {
         // Working variables
-        let mut depth = 1;
-        let mut best_move = Move::new(0);
-        let mut root_pv: Vec<Move> = Vec::new();
+        let mut depth = Search::start_depth(refs);
+        let mut best_move = Move::new(0); // Null move until depth 1 is done.
+        let mut ponder_move: Option<Move> = None; // Expected reply to best_move
         let mut stop = false;
-        let is_game_time = refs.search_params.is_game_time();
+        let mut mating_move: Option<Move> = None; // Best move of a found mate
+        let mut mate_extended = false; // Extra depth was added to keep a mate
+        let mut singular: Option<Move> = None; // Best move is the only good one
+        let mut mate_found = false; // Requested mate found in Mate mode
+        let mut bound = ScoreBound::Exact; // Result of re-checking the PV
+
+        // Don't search more lines than there are legal moves.
+        let legal_moves = Search::count_legal_moves(refs);
+        refs.search_info.root_moves = legal_moves as u8;
+        let lines = refs.search_params.multipv.min(legal_moves);
+        let multipv = if lines > 1 { 1 } else { 0 };
+
+        // Classify the position. Only adjust the search to the class if
+        // this is requested, so the default search is not affected.
+        let class = Search::classify_position(refs);
+        if refs.search_params.debug {
+            let msg = format!("position class {}", class);
+            Search::send_info_string(refs, msg);
+        }
+        if refs.search_params.adaptive {
+            refs.search_info.tunables = class.tunables();
+        }
 
         // Determine available time in case of GameTime search mode.
-        if is_game_time {
-            // Determine the maximum time slice available for this move.
-            let time_slice = Search::calculate_time_slice(refs);
-
-            // Experience reveals that after using about 40-50% of the
-            // available time, the next depth will not be finished, so
-            // don't allocated more than 40% of the calculated move time.
-            let factor = 0.40;
-
-            // If we have time, do a normal search in GameTime mode.
-            if time_slice > 0 {
-                // Determine the actual time to allot for this search.
-                refs.search_info.allocated_time = (time_slice as f64 * factor).round() as u128;
-            } else {
-                // We have no time. Send the best move from ply 1 to avoid
-                // killing ourselves by sending no move at all. Change mode
-                // to "depth" and set it to 1 ply.
-                refs.search_params.search_mode = SearchMode::Depth;
-                refs.search_params.depth = 1;
+        // The engine checks afterward if the search kept to its budget.
+        if refs.search_params.is_game_time() {
+            Search::allocate_time(refs);
+            if refs.search_params.is_game_time() && refs.search_params.helper == 0 {
+                let report = SearchReport::TimeBudget(Search::time_budget(refs));
+                refs.report_tx
+                    .send(Information::Search(report))
+                    .expect(ErrFatal::CHANNEL);
             }
         }
 
         // Set the starting values for alpha and beta, for use with the
         // aspiration window. We always start with a fully open window.
-        let alpha: i16 = -INF;
-        let beta: i16 = INF;
+        let alpha: Score = -INF;
+        let beta: Score = INF;
+
+        // Mark where the game history ends and the search begins.
+        refs.board.history.set_root();
+        refs.search_info.repeating = Search::repeating_root_moves(refs);
 
         // Start the search
         refs.search_info.timer_start();
         while (depth <= MAX_PLY) && (depth <= refs.search_params.depth) && !stop {
-            // Set the current depth
+            // Set the current depth. Seldepth is reported per iteration.
             refs.search_info.depth = depth;
+            refs.search_info.seldepth = 0;
 
             // Get the evaluation for this depth.
-            let eval = Search::alpha_beta(depth, alpha, beta, &mut root_pv, refs);
+            let mut eval = Search::alpha_beta(depth, alpha, beta, refs);
 
             // Create summary if search was not interrupted.
             if !refs.search_info.interrupted() {
+                // Collect the PV from the root of the PV table.
+                let mut root_pv = refs.search_info.pv.root();
+
+                // When ahead, don't repeat the game without good reason.
+                let main_line = lines == 1 && refs.search_params.helper == 0;
+                if let Some(&m) = root_pv.first().filter(|_| main_line) {
+                    if let Some((score, pv)) = Search::avoid_repetition(refs, depth, eval, m) {
+                        let msg = format!("avoiding repetition {}", m.as_string());
+                        Search::send_info_string(refs, msg);
+                        eval = score;
+                        root_pv = pv;
+                    }
+                }
+                refs.search_info.avoid_repeating = eval.value() > REPEAT_AHEAD;
+
                 // Save the best move until now.
                 if !root_pv.is_empty() {
                     best_move = root_pv[0];
+                    ponder_move = root_pv.get(1).copied();
                 }
 
-                // Create search summary for this depth.
-                let elapsed = refs.search_info.timer_elapsed();
-                let nodes = refs.search_info.nodes;
-                let hash_full = refs.tt.lock().expect(ErrFatal::LOCK).hash_full();
-                let summary = SearchSummary {
-                    depth,
-                    seldepth: refs.search_info.seldepth,
-                    time: elapsed,
-                    cp: eval,
-                    mate: 0,
-                    nodes,
-                    nps: Search::nodes_per_second(nodes, elapsed),
-                    hash_full,
-                    pv: root_pv.clone(),
+                // Find out if a draw score comes from a draw in the PV.
+                let draw = if eval == DRAW {
+                    Search::draw_in_pv(refs, &root_pv)
+                } else {
+                    None
                 };
 
-                // Create information for the engine
-                let report = SearchReport::SearchSummary(summary);
-                let information = Information::Search(report);
-                refs.report_tx.send(information).expect(ErrFatal::CHANNEL);
+                // Re-check the PV at a low depth, to find out if the
+                // evaluation is stable. Only the main thread reports it.
+                bound = ScoreBound::Exact;
+                let check_pv = depth >= PV_CHECK_MIN_DEPTH
+                    && refs.search_params.helper == 0
+                    && draw.is_none()
+                    && !eval.is_mate();
+                if check_pv {
+                    let previous = refs.search_info.depth_nodes.last().copied().unwrap_or(0);
+                    let budget = (refs.search_info.nodes - previous) / PV_CHECK_NODE_DIVISOR;
+                    bound = Search::check_pv(refs, eval, &root_pv, budget);
+                }
+
+                // Send the search summary for this depth.
+                Search::send_summary(refs, depth, eval, root_pv, multipv, bound);
+
+                // Explain the draw score, so it isn't mistaken for an
+                // evaluation of the position.
+                if let Some(kind) = draw {
+                    Search::send_info_string(refs, format!("draw by {} in PV", kind));
+                }
+
+                // Search the other lines in MultiPV mode.
+                if lines > 1 && !best_move.is_null() {
+                    Search::search_multipv(refs, depth, best_move, lines);
+                }
+
+                // Keep track of the nodes needed to finish each depth.
+                // Also keep track of the time, to predict the next one.
+                let nodes = refs.search_info.nodes;
+                let elapsed = refs.search_info.move_elapsed();
+                refs.search_info.depth_nodes.push(nodes);
+                refs.search_info.depth_times.push(elapsed);
+                if refs.search_params.debug {
+                    let time = refs.search_info.iteration_time().unwrap_or(0);
+                    let msg = match refs.search_info.branching_factor() {
+                        Some(ebf) => format!("depth {} time={}ms EBF={:.2}", depth, time, ebf),
+                        None => format!("depth {} time={}ms", depth, time),
+                    };
+                    Search::send_info_string(refs, msg);
+                }
+
+                // Once a mate is found, a deeper iteration should find it
+                // as well. If it doesn't (for example because it now
+                // walks into a stalemate), don't throw away the mate.
+                if eval.is_mate() && eval > DRAW {
+                    mating_move = Some(best_move);
+                } else if let Some(m) = mating_move {
+                    Search::mate_lost(refs, depth, m, &mut mate_extended);
+                    best_move = m;
+                    ponder_move = None;
+                }
+
+                // Find out if the best move is the only good move. This
+                // is pointless in MultiPV mode, or when mating.
+                if legal_moves == 1 {
+                    singular = Some(best_move);
+                } else if lines == 1 && depth >= SINGULAR_MIN_DEPTH && !eval.is_mate() {
+                    singular = if Search::is_singular(refs, depth, best_move, eval) {
+                        Some(best_move)
+                    } else {
+                        None
+                    };
+                }
+
+                // In Mate mode, a mate within the requested number of
+                // moves ends the search.
+                if refs.search_params.search_mode == SearchMode::Mate {
+                    let moves = eval.mate_in().unwrap_or(0);
+                    mate_found = moves > 0 && moves <= refs.search_params.mate as i16;
+                }
 
                 // Search one ply deeper.
                 depth += 1;
             }
 
-            // Determine if time is up, when in GameTime mode.
-            let time_up = if is_game_time {
-                refs.search_info.timer_elapsed() > refs.search_info.allocated_time
+            // Determine if time is up, when in GameTime mode. (A ponder
+            // search can turn into a GameTime search during the search.)
+            // If the best move is singular, a deeper search is unlikely to
+            // find anything better, so part of the allocated time is enough.
+            // That doesn't hold if the evaluation of the PV is unstable.
+            let time_up = if refs.search_params.is_game_time() {
+                let elapsed = refs.search_info.move_elapsed();
+                let allocated = refs.search_info.allocated_time;
+                let enough = match singular {
+                    Some(m) if m == best_move && bound == ScoreBound::Exact => {
+                        allocated / SINGULAR_TIME_DIVISOR
+                    }
+                    _ => allocated,
+                };
+                if refs.search_params.complete_iterations {
+                    !Search::next_iteration_fits(refs, enough)
+                } else {
+                    elapsed > enough
+                }
             } else {
                 false
             };
 
             // Stop deepening the search if the current depth was
-            // interrupted, or if the time is up.
-            stop = refs.search_info.interrupted() || time_up;
+            // interrupted, if the time is up, or if the mate was found.
+            stop = refs.search_info.interrupted() || time_up || mate_found;
+        }
+
+        if let Some(ebf) = refs.search_info.average_branching_factor() {
+            if refs.search_params.debug {
+                let msg = format!("average EBF={:.2}", ebf);
+                Search::send_info_string(refs, msg);
+            }
+        }
+
+        if !best_move.is_null() && singular == Some(best_move) {
+            let msg = format!("singular best move {}", best_move.as_string());
+            Search::send_info_string(refs, msg);
+        }
+
+        // A ponder search may not end by itself; wait for the GUI.
+        if !refs.search_info.interrupted() {
+            Search::wait_for_ponder_end(refs);
+        }
+
+        // If the search was aborted while moves were still on the board,
+        // take them back so the board is in the game position again.
+        refs.board.unmake_to_root();
+
+        // Search is done. Report best move, the move to ponder on, and
+        // the reason to terminate.
+        (best_move, ponder_move, refs.search_info.terminate)
+    }
+
+    // Creates a summary of the search up to now and sends it to the engine.
+    fn send_summary(
+        refs: &mut SearchRefs,
+        depth: i8,
+        eval: Score,
+        pv: Vec<Move>,
+        multipv: usize,
+        bound: ScoreBound,
+    ) {
+        let elapsed = refs.search_info.timer_elapsed();
+        let nodes = Search::total_nodes(refs);
+        let hash_full = refs.tt.hash_full();
+        let summary = SearchSummary {
+            depth,
+            seldepth: refs.search_info.seldepth,
+            time: elapsed,
+            score: eval,
+            nodes,
+            nps: Search::nodes_per_second(nodes, elapsed),
+            hash_full,
+            pv,
+            multipv,
+            bound,
+            pv_truncated: false,
+        };
+
+        // Create information for the engine
+        let report = SearchReport::SearchSummary(summary);
+        let information = Information::Search(report);
+        refs.report_tx.send(information).expect(ErrFatal::CHANNEL);
+    }
+
+    // After the best line was found at this depth, search the root again
+    // for each of the other lines in MultiPV mode. Each search excludes
+    // the root moves of the lines that were already found.
+    fn search_multipv(refs: &mut SearchRefs, depth: i8, best_move: Move, lines: usize) {
+        refs.search_info.excluded.push(best_move.to_short_move());
+
+        for line in 2..=lines {
+            let eval = Search::alpha_beta(depth, -INF, INF, refs);
+            let pv = refs.search_info.pv.root();
+
+            if refs.search_info.interrupted() || pv.is_empty() {
+                break;
+            }
+
+            refs.search_info.excluded.push(pv[0].to_short_move());
+            Search::send_summary(refs, depth, eval, pv, line, ScoreBound::Exact);
+        }
+
+        refs.search_info.excluded.clear();
+    }
+
+    // The best move is singular if all the other moves, searched to half
+    // the depth, fail low against the best move's evaluation minus the
+    // margin. A search interrupted halfway proves nothing.
+    fn is_singular(refs: &mut SearchRefs, depth: i8, best_move: Move, eval: Score) -> bool {
+        let bound = eval - refs.search_params.singular_margin;
+        refs.search_info.excluded.push(best_move.to_short_move());
+        let score = Search::alpha_beta((depth / 2).max(1), bound - 1, bound, refs);
+        refs.search_info.excluded.clear();
+
+        !refs.search_info.interrupted() && score < bound
+    }
+
+    // Plays the PV and searches the positions on it at a low depth, from
+    // the last one back to the first. The evaluation is unstable if one of
+    // these searches deviates from it by more than the margin: this happens
+    // when the PV was grafted from the TT, or ends in a position that the
+    // quiescence search misjudged. The returned bound tells if the
+    // evaluation may be too high (upper) or too low (lower). The check
+    // stops searching when it has used up its node budget.
+    fn check_pv(refs: &mut SearchRefs, eval: Score, pv: &[Move], budget: usize) -> ScoreBound {
+        let start_nodes = refs.search_info.nodes;
+        let seldepth = refs.search_info.seldepth;
+        let mut bound = ScoreBound::Exact;
+        let mut played = 0;
+
+        for m in pv.iter() {
+            if !refs.board.make(*m, refs.mg) {
+                break;
+            }
+            refs.search_info.ply += 1;
+            played += 1;
+        }
+
+        while played > 0 {
+            let within_budget = refs.search_info.nodes - start_nodes < budget;
+            if bound == ScoreBound::Exact && within_budget && !refs.search_info.interrupted() {
+                let score = Search::alpha_beta(PV_CHECK_DEPTH, -INF, INF, refs);
+
+                // The score is from the point of view of the side to move.
+                let score = if played % 2 == 0 { score } else { -score };
+                if !refs.search_info.interrupted() {
+                    if score < eval - PV_CHECK_MARGIN {
+                        bound = ScoreBound::Upper;
+                    } else if score > eval + PV_CHECK_MARGIN {
+                        bound = ScoreBound::Lower;
+                    }
+                }
+            }
+
+            let taken_back = refs.board.unmake();
+            debug_assert!(taken_back);
+            refs.search_info.ply -= 1;
+            played -= 1;
+        }
+
+        // The searches on the PV don't count for the iteration's seldepth.
+        refs.search_info.seldepth = seldepth;
+        bound
+    }
+
+    // Returns the root moves that lead to a position which already occurred
+    // at least REPEAT_MIN_COUNT times, so the opponent could claim a draw.
+    fn repeating_root_moves(refs: &mut SearchRefs) -> Vec<ShortMove> {
+        let moves: Vec<Move> = refs.board.legal_moves_iter(refs.mg).collect();
+        let mut repeating = Vec::new();
+
+        for m in moves {
+            if refs.board.make(m, refs.mg) {
+                if refs.board.occurrences() >= REPEAT_MIN_COUNT {
+                    repeating.push(m.to_short_move());
+                }
+                let taken_back = refs.board.unmake();
+                debug_assert!(taken_back);
+            }
+        }
+
+        repeating
+    }
+
+    // When clearly ahead, a best move that repeats the game has to prove
+    // itself: the root is searched again without it, and if another move
+    // scores within the margin, that move and its evaluation are returned
+    // instead. This is done at the root after the search, and not in the
+    // tree, so TT entries don't depend on the history of this game.
+    fn avoid_repetition(
+        refs: &mut SearchRefs,
+        depth: i8,
+        eval: Score,
+        best_move: Move,
+    ) -> Option<(Score, Vec<Move>)> {
+        let short_move = best_move.to_short_move();
+        if eval.value() <= REPEAT_AHEAD || !refs.search_info.repeating.contains(&short_move) {
+            return None;
+        }
+
+        refs.search_info.excluded.push(short_move);
+        let score = Search::alpha_beta(depth, -INF, INF, refs);
+        let pv = refs.search_info.pv.root();
+        refs.search_info.excluded.clear();
+
+        if refs.search_info.interrupted() || pv.is_empty() || score < eval - REPEAT_MARGIN {
+            return None;
+        }
+
+        Some((score, pv))
+    }
+
+    // Sends a message to be shown in the user interface.
+    pub fn send_info_string(refs: &mut SearchRefs, msg: String) {
+        let report = SearchReport::InfoString(msg);
+        let information = Information::Search(report);
+        refs.report_tx.send(information).expect(ErrFatal::CHANNEL);
+    }
+
+    // A previously found mate was lost in the iteration at the given depth.
+    // Warn about this, and if searching to a fixed depth, search one ply
+    // deeper (once) to give the search a chance to find the mate again.
+    fn mate_lost(refs: &mut SearchRefs, depth: i8, mating_move: Move, extended: &mut bool) {
+        let msg = format!(
+            "Mate lost at depth {}. Keeping mating move {}.",
+            depth,
+            mating_move.as_string()
+        );
+        Search::send_info_string(refs, msg);
+
+        let sp = &mut refs.search_params;
+        if sp.search_mode == SearchMode::Depth && !*extended && sp.depth < MAX_PLY {
+            sp.depth += 1;
+            *extended = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::defs::{
+        GameTime, SearchControl, SearchInfo, SearchParams, SearchShared, SearchTerminate,
+    };
+    use super::*;
+    use crate::{
+        api,
+        board::Board,
+        engine::defs::{SearchData, TT},
+        misc::{parse, random::EngineRandom},
+        movegen::{
+            defs::{MoveList, MoveType},
+            MoveGenerator,
+        },
+    };
+    use std::sync::Arc;
+
+    // Plays a game of the given length with random legal moves. A game
+    // that ends early is thrown away and played again.
+    fn random_game(mg: &MoveGenerator, plies: usize) -> Board {
+        let mut random = EngineRandom::new(Some(1486));
+
+        loop {
+            let mut board = Board::new();
+            board.fen_read(None).expect("start position");
+
+            while board.history.len() < plies {
+                let moves: Vec<Move> = board.legal_moves_iter(mg).collect();
+                if moves.is_empty() || board.game_state.halfmove_clock >= 100 {
+                    break;
+                }
+                assert!(board.make(moves[random.below(moves.len())], mg));
+            }
+
+            if board.history.len() == plies {
+                return board;
+            }
+        }
+    }
+
+    // Searches the board itself (not a copy) until the node limit.
+    fn search_nodes(board: &mut Board, mg: &Arc<MoveGenerator>, nodes: usize) -> SearchInfo {
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Nodes;
+        params.nodes = nodes;
+        params.quiet = true;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board,
+            mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        Search::iterative_deepening(&mut refs);
+        search_info
+    }
+
+    // Searches the position to the given depth, and returns the depth and
+    // seldepth of each iteration, as reported to the engine.
+    fn iterations(fen: &str, depth: i8) -> Vec<(i8, i8)> {
+        search_reports(fen, depth)
+            .into_iter()
+            .filter_map(|report| match report {
+                SearchReport::SearchSummary(s) => Some((s.depth, s.seldepth)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Searches the position to the given depth, and returns everything the
+    // search reported to the engine.
+    fn search_reports(fen: &str, depth: i8) -> Vec<SearchReport> {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = depth;
+        reports(fen, &[], params)
+    }
+
+    // Searches the position after the given moves with the given
+    // parameters, and returns everything the search reported.
+    fn reports(fen: &str, moves: &[&str], mut params: SearchParams) -> Vec<SearchReport> {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        for m in moves.iter() {
+            let m = board
+                .legal_moves_iter(&mg)
+                .find(|legal| legal.as_string() == *m)
+                .expect(m);
+            assert!(board.make(m, &mg));
+        }
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+        params.quiet = true;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        Search::iterative_deepening(&mut refs);
+        report_rx
+            .try_iter()
+            .filter_map(|information| match information {
+                Information::Search(report) => Some(report),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The parts of the board a search could leave behind, including every
+    // game state in the history.
+    fn snapshot(board: &Board) -> (String, Vec<u64>, Vec<String>, usize) {
+        let states = (0..board.history.len())
+            .map(|i| board.history.get_ref(i).as_string())
+            .chain(std::iter::once(board.game_state.as_string()))
+            .collect();
+        let pieces = board
+            .bb_pieces
+            .iter()
+            .flatten()
+            .chain(board.bb_side.iter())
+            .copied()
+            .collect();
+
+        (board.fen_write(), pieces, states, board.history.len())
+    }
+
+    // Node counts at a fixed depth, searched with a fresh TT. A change that
+    // should not alter the search, such as moving code around, must leave
+    // them exactly as they are.
+    #[test]
+    fn fixed_depth_node_counts() {
+        let snapshot = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                6,
+                27713,
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                5,
+                108_672,
+            ),
+            ("8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1", 10, 4164),
+        ];
+
+        for (fen, depth, nodes) in snapshot.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = *depth;
+            params.search_mode = SearchMode::Depth;
+
+            assert_eq!(api::search(&board, params).nodes, *nodes, "{}", fen);
+        }
+    }
+
+    // The principal variations at a fixed depth. A change to the way the
+    // PV is collected must leave them as they are, as moving it from a
+    // vector per node into the PV table did. Each PV is a line of legal
+    // moves that starts with the best move, followed by the move to
+    // ponder on.
+    #[test]
+    fn fixed_depth_pvs() {
+        let mg = MoveGenerator::shared();
+        let snapshot = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                6,
+                "e2e4 d7d5 e4d5 c7c6 d5c6 b8c6",
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                5,
+                "e2a6 b4c3 d2c3 e6d5 e1c1 h3g2 f3g2 d5e4",
+            ),
+            (
+                "8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1",
+                14,
+                "a1b2 a7b7 b2c2 b7c8 c2d3 c8c7 d3e3 c7d7 e3e2 d7c7 e2d3 c7d7 d3e3 d7e7",
+            ),
+            (
+                "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+                6,
+                "g3g6 g7f6 g6h7",
+            ),
+        ];
+
+        for (fen, depth, expected) in snapshot.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = *depth;
+            params.search_mode = SearchMode::Depth;
+
+            let outcome = api::search(&board, params);
+            let pv: Vec<String> = outcome.pv.iter().map(|m| m.as_string()).collect();
+            assert_eq!(pv.join(" "), *expected);
+            assert!(outcome.pv[0].get_move() == outcome.best_move.get_move());
+            let ponder = outcome.ponder_move.map(|m| m.get_move());
+            assert_eq!(ponder, outcome.pv.get(1).map(|m| m.get_move()));
+
+            for m in outcome.pv.iter() {
+                let legal = board
+                    .legal_moves_iter(&mg)
+                    .find(|l| l.get_move() == m.get_move());
+                assert!(board.make(legal.expect("legal PV move"), &mg));
+            }
         }
+    }
+
+    // A move that leaves the king in check is not searched, and a legal
+    // move is taken back with the ply count restored, whatever the search
+    // function does.
+    #[test]
+    fn visit_move_is_symmetric() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board
+            .fen_read(Some("4k3/8/8/8/8/8/4r3/R3K3 w Q - 0 1"))
+            .expect("valid FEN");
+        let before = snapshot(&board);
+        let moves: Vec<Move> = board.legal_moves_iter(&mg).collect();
+        let mut pseudo_legal = MoveList::new();
+        mg.generate_moves(&board, &mut pseudo_legal, MoveType::All);
+
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        for i in 0..pseudo_legal.len() {
+            let m = pseudo_legal.get_move(i);
+            let legal = moves.iter().any(|l| l.get_move() == m.get_move());
+            let score = Search::visit_move(&mut refs, m, true, |refs| {
+                assert_eq!(refs.search_info.ply, 1);
+                Score::new(1)
+            });
+
+            assert_eq!(score.is_some(), legal, "{}", m.as_string());
+            assert_eq!(refs.search_info.ply, 0);
+            assert_eq!(snapshot(refs.board), before);
+        }
+    }
+
+    // Every iteration reaches at least its own depth, counting the plies
+    // of the quiescence search.
+    #[test]
+    fn seldepth_is_at_least_depth() {
+        let positions = [
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1",
+        ];
+
+        for fen in positions.iter() {
+            let iterations = iterations(fen, 8);
+            assert!(!iterations.is_empty());
+            for (depth, seldepth) in iterations {
+                assert!(
+                    seldepth >= depth,
+                    "{}: depth {} seldepth {}",
+                    fen,
+                    depth,
+                    seldepth
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn aborted_search_restores_long_game() {
+        let mg = MoveGenerator::shared();
+        let mut board = random_game(&mg, 300);
+        let before = snapshot(&board);
+
+        // The node limit cuts the search off in the middle of an iteration,
+        // while moves of the search are still on the board.
+        let info = search_nodes(&mut board, &mg, 200_000);
+        assert!(info.nodes >= 200_000);
+        assert_eq!(snapshot(&board), before);
+        assert_eq!(board.history.root(), 300);
+    }
+
+    #[test]
+    fn repetition_draw_depends_on_history_root() {
+        let mg = MoveGenerator::shared();
+        let play = |board: &mut Board, moves: &[&str]| {
+            for san in moves {
+                let m = parse::san_to_move(board, &mg, san).expect("legal move");
+                assert!(board.make(m, &mg));
+            }
+        };
+        let shuffle = ["Nf3", "Nf6", "Ng1", "Ng8"];
+
+        // Repeated by the search: one earlier occurrence is a draw.
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+        board.history.set_root();
+        play(&mut board, &shuffle[..2]);
+        assert!(!board.is_repetition_draw());
+        play(&mut board, &shuffle[2..]);
+        play(&mut board, &shuffle[..2]);
+        assert!(board.is_repetition_draw());
+
+        // The same position, once before in the game: not yet a draw.
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+        play(&mut board, &shuffle);
+        board.history.set_root();
+        assert!(!board.is_repetition_draw());
+
+        // The third occurrence is a draw, even if both were in the game.
+        play(&mut board, &shuffle);
+        assert!(board.is_repetition_draw());
+    }
+
+    // Returns None if the side to move has legal moves, and otherwise if
+    // it is checkmated (true) or stalemated (false).
+    fn mated(board: &Board, mg: &MoveGenerator) -> Option<bool> {
+        if board.legal_moves_iter(mg).next().is_some() {
+            return None;
+        }
+        let king_square = board.king_square(board.us());
+        Some(mg.square_attacked(board, board.opponent(), king_square))
+    }
+
+    fn search_depth(board: &Board, depth: i8) -> api::SearchOutcome {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = depth;
+        params.quiet = true;
+        api::search(board, params)
+    }
+
+    // Qf7 stalemates, and many other queen moves keep the win. At any
+    // depth, the search finds the mate instead of the stalemate.
+    #[test]
+    fn stalemate_trap_is_avoided() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board
+            .fen_read(Some("7k/8/6K1/8/8/8/8/5Q2 w - - 0 1"))
+            .expect("valid FEN");
+
+        for depth in 1..=6 {
+            let outcome = search_depth(&board, depth);
+            assert_eq!(outcome.score.mate_in(), Some(1), "depth {}", depth);
+
+            let mut after = board.clone();
+            assert!(after.make(outcome.best_move, &mg));
+            assert_eq!(mated(&after, &mg), Some(true), "depth {}", depth);
+        }
+    }
+
+    // King and queen against king, played out by fixed-depth searches on
+    // both sides. White never lets the game end in stalemate, and once a
+    // mate is found, it is never lost again.
+    #[test]
+    fn king_and_queen_mate_the_king() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board
+            .fen_read(Some("8/8/8/4k3/8/8/8/KQ6 w - - 0 1"))
+            .expect("valid FEN");
+        let mut mate_found = false;
+
+        for _ in 0..50 {
+            let outcome = search_depth(&board, 5);
+            let is_mate = outcome.score.mate_in().is_some_and(|n| n > 0);
+            assert!(is_mate || !mate_found, "{}", board.fen_write());
+            mate_found |= is_mate;
+            assert!(board.make(outcome.best_move, &mg));
+
+            match mated(&board, &mg) {
+                Some(checkmate) => {
+                    assert!(checkmate, "stalemate: {}", board.fen_write());
+                    return;
+                }
+                None => {
+                    let reply = search_depth(&board, 3).best_move;
+                    assert!(board.make(reply, &mg));
+                }
+            }
+        }
+        panic!("no mate: {}", board.fen_write());
+    }
+
+    // Searches the position while another thread sends the search the
+    // given controls, each after waiting the given number of msecs.
+    fn search_controlled(
+        fen: &str,
+        mut params: SearchParams,
+        controls: Vec<(u64, SearchControl)>,
+    ) -> (Move, SearchInfo) {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let tt = TT::<SearchData>::new(1);
+        let (control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        params.quiet = true;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let sender = std::thread::spawn(move || {
+            for (wait, control) in controls {
+                std::thread::sleep(std::time::Duration::from_millis(wait));
+                control_tx.send(control).expect("search is running");
+            }
+            control_tx
+        });
+
+        let (best_move, _, _) = Search::iterative_deepening(&mut refs);
+        sender.join().expect("control thread");
+        (best_move, search_info)
+    }
+
+    // A pause doesn't change what is searched: the same nodes are searched
+    // and the same move is found as without it.
+    #[test]
+    fn pause_keeps_the_search_state() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = 5;
+
+        let (expected, unpaused) = search_controlled(fen, params, vec![]);
+        let controls = vec![(0, SearchControl::Pause), (500, SearchControl::Resume)];
+        let (best_move, paused) = search_controlled(fen, params, controls);
+
+        assert_eq!(best_move.get_move(), expected.get_move());
+        assert_eq!(paused.nodes, unpaused.nodes);
+        assert_eq!(paused.depth_nodes, unpaused.depth_nodes);
+    }
+
+    // Pausing while pondering doesn't count as pondering time, so after
+    // the ponderhit, the engine doesn't think it has used up its time.
+    #[test]
+    fn pause_during_ponder() {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Ponder;
+        params.game_time = GameTime::new(60_000, 60_000, 0, 0, None);
+        let controls = vec![
+            (0, SearchControl::Pause),
+            (1000, SearchControl::Resume),
+            (200, SearchControl::PonderHit),
+        ];
+
+        let start = std::time::Instant::now();
+        let (best_move, info) =
+            search_controlled(crate::defs::FEN_START_POSITION, params, controls);
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+
+        assert!(start.elapsed().as_millis() >= 1200);
+        assert!(info.ponder_time >= 200 && info.ponder_time < 1000);
+        assert!(info.depth > 1);
+        assert!(board.make(best_move, &mg));
+    }
+
+    // Returns the info strings the search sent about a singular move.
+    fn singular_moves(fen: &str, depth: i8) -> Vec<String> {
+        search_reports(fen, depth)
+            .into_iter()
+            .filter_map(|report| match report {
+                SearchReport::InfoString(msg) if msg.starts_with("singular") => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The queen just took on d2. Only the recapture keeps the material, so
+    // it is singular. The only legal move is singular as well.
+    #[test]
+    fn forced_moves_are_singular() {
+        let recapture = "4k3/pp6/8/8/8/8/3q1PPP/3R2K1 w - - 0 1";
+        assert_eq!(
+            singular_moves(recapture, 6),
+            vec!["singular best move d1d2"]
+        );
+
+        let only_move = "k7/8/1K6/8/8/8/8/7R b - - 0 1";
+        assert_eq!(
+            singular_moves(only_move, 2),
+            vec!["singular best move a8b8"]
+        );
+    }
+
+    // In quiet positions, several moves are about as good as the best one.
+    #[test]
+    fn quiet_choices_are_not_singular() {
+        let quiet = [
+            crate::defs::FEN_START_POSITION,
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            "8/5k2/3p4/1p1Pp2p/pP2Pp1P/P4P1K/8/8 b - - 99 50",
+        ];
+
+        for fen in quiet.iter() {
+            assert!(singular_moves(fen, 6).is_empty(), "{}", fen);
+        }
+    }
+
+    // Searches for a mate in the given number of moves, as "go mate" does,
+    // and returns the summary of the last iteration.
+    fn search_mate(fen: &str, moves: u8) -> SearchSummary {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Mate;
+        params.mate = moves;
+        params.depth = 2 * moves as i8 - 1;
+
+        reports(fen, &[], params)
+            .into_iter()
+            .rev()
+            .find_map(|report| match report {
+                SearchReport::SearchSummary(s) => Some(s),
+                _ => None,
+            })
+            .expect("search summary")
+    }
+
+    // The search stops at the first iteration that finds a mate within
+    // the requested number of moves, well before the depth limit.
+    #[test]
+    fn mate_search_stops_when_the_mate_is_found() {
+        let mate_in_2 = "2k5/8/1K6/8/8/8/8/7R w - - 0 1";
+        let summary = search_mate(mate_in_2, 2);
+        assert_eq!(summary.score.mate_in(), Some(2));
+        assert!(summary.depth <= 3);
+
+        let summary = search_mate(mate_in_2, 5);
+        assert_eq!(summary.score.mate_in(), Some(2));
+        assert!(summary.depth <= 3);
+
+        let mate_in_3 = "r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1";
+        let summary = search_mate(mate_in_3, 3);
+        assert_eq!(summary.score.mate_in(), Some(3));
+        assert_eq!(summary.depth, 1);
+    }
+
+    // Without a mate in the requested number of moves, the search runs to
+    // the depth limit and still returns its best move.
+    #[test]
+    fn mate_search_without_a_mate() {
+        let mate_in_3 = "r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1";
+        let summary = search_mate(mate_in_3, 2);
+        assert_eq!(summary.depth, 3);
+        assert_eq!(summary.pv[0].as_string(), "f6a6");
+
+        let summary = search_mate(crate::defs::FEN_START_POSITION, 2);
+        assert_eq!(summary.depth, 3);
+        assert_eq!(summary.score.mate_in(), None);
+        assert!(!summary.pv.is_empty());
+    }
+
+    // Searches the position after the moves, and returns the messages that
+    // explain a draw score.
+    fn draw_messages(fen: &str, moves: &[&str], depth: i8) -> Vec<String> {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = depth;
+
+        reports(fen, moves, params)
+            .into_iter()
+            .filter_map(|report| match report {
+                SearchReport::InfoString(msg) if msg.starts_with("draw by") => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Black is a queen down, but can return to the position the game
+    // started in for the third time. The draw score comes from that
+    // repetition.
+    #[test]
+    fn draw_by_repetition_in_pv() {
+        let fen = "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"];
+        let messages = draw_messages(fen, &moves, 4);
+        assert!(!messages.is_empty());
+        assert!(messages.iter().all(|m| m == "draw by repetition in PV"));
+    }
+
+    #[test]
+    fn other_draws_in_pv() {
+        // Any move but a mate ends the game by the fifty-move rule.
+        let fifty_moves = "7k/8/8/8/8/8/R7/1R4K1 w - - 99 80";
+        let messages = draw_messages(fifty_moves, &[], 4);
+        assert!(!messages.is_empty());
+        assert!(messages
+            .iter()
+            .all(|m| m == "draw by fifty-move rule in PV"));
+
+        let bare_kings = "8/8/4k3/8/8/3K4/8/8 w - - 0 1";
+        let messages = draw_messages(bare_kings, &[], 4);
+        assert!(!messages.is_empty());
+        assert!(messages
+            .iter()
+            .all(|m| m == "draw by insufficient material in PV"));
+    }
+
+    // An evaluation of exactly 0.00 without a draw in the PV gets no
+    // explanation.
+    #[test]
+    fn genuine_draw_scores_are_not_explained() {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = 4;
+        let reports = reports(crate::defs::FEN_START_POSITION, &[], params);
+
+        assert!(reports.iter().any(|report| match report {
+            SearchReport::SearchSummary(s) => s.score == DRAW,
+            _ => false,
+        }));
+        assert!(draw_messages(crate::defs::FEN_START_POSITION, &[], 4).is_empty());
+    }
+
+    // Position 4 of the perft suite: from the first depth at which the PV
+    // is re-checked, the re-check finds the evaluation too low. The PVs of
+    // the start position and Kiwipete are stable at these depths.
+    #[test]
+    fn unstable_pv_snapshot() {
+        let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        let bounds: Vec<(i8, bool)> = search_reports(fen, 5)
+            .into_iter()
+            .filter_map(|report| match report {
+                SearchReport::SearchSummary(s) => Some((s.depth, s.bound == ScoreBound::Lower)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            bounds,
+            vec![(1, false), (2, false), (3, false), (4, true), (5, true)]
+        );
+
+        for fen in [
+            crate::defs::FEN_START_POSITION,
+            crate::defs::FEN_KIWIPETE_POSITION,
+        ]
+        .iter()
+        {
+            assert!(search_reports(fen, 6).iter().all(|report| match report {
+                SearchReport::SearchSummary(s) => s.bound == ScoreBound::Exact,
+                _ => true,
+            }));
+        }
+    }
+
+    // An evaluation that is far too high or too low for the PV is flagged,
+    // but only if the node budget allows a re-check. Either way, the board
+    // is restored.
+    #[test]
+    fn check_pv_bounds() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(None).expect("start position");
+        let pv: Vec<Move> = ["e2e4", "e7e5", "g1f3"]
+            .iter()
+            .map(|s| {
+                let m = board
+                    .legal_moves_iter(&mg)
+                    .find(|m| m.as_string() == *s)
+                    .expect(s);
+                assert!(board.make(m, &mg));
+                m
+            })
+            .collect();
+        while board.unmake() {}
+        let before = snapshot(&board);
+
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let checks = [
+            (0, usize::MAX, ScoreBound::Exact),
+            (500, usize::MAX, ScoreBound::Upper),
+            (-500, usize::MAX, ScoreBound::Lower),
+            (500, 0, ScoreBound::Exact),
+        ];
+        for (eval, budget, expected) in checks.iter() {
+            let bound = Search::check_pv(&mut refs, Score::new(*eval), &pv, *budget);
+            assert!(bound == *expected, "eval {} budget {}", eval, budget);
+            assert_eq!(refs.search_info.ply, 0);
+            assert_eq!(snapshot(refs.board), before);
+        }
+    }
+
+    // White is a queen and a knight up. The knight went to f3 twice with
+    // the black king on e8; now it can go back there from e5.
+    const REPEAT_FEN: &str = "4k3/8/8/8/8/8/8/Q3K1N1 w - - 0 1";
+    const REPEAT_MOVES: [&str; 8] = [
+        "g1f3", "e8d7", "f3g1", "d7e8", "g1f3", "e8d8", "f3e5", "d8e8",
+    ];
+
+    // Only the knight's return to f3 repeats the game. The search finds
+    // another move; it doesn't need to be talked out of the repetition.
+    #[test]
+    fn repeating_root_moves_are_found() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(REPEAT_FEN)).expect("valid FEN");
+        for m in REPEAT_MOVES.iter() {
+            let m = board
+                .legal_moves_iter(&mg)
+                .find(|l| l.as_string() == *m)
+                .expect(m);
+            assert!(board.make(m, &mg));
+        }
+        let repeat = board
+            .legal_moves_iter(&mg)
+            .find(|m| m.as_string() == "e5f3")
+            .expect("e5f3");
+        board.history.set_root();
+        let before = snapshot(&board);
+
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let repeating = Search::repeating_root_moves(&mut refs);
+        assert!(repeating == vec![repeat.to_short_move()]);
+        assert_eq!(snapshot(refs.board), before);
+
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = 5;
+        let reports = reports(REPEAT_FEN, &REPEAT_MOVES, params);
+        let best = reports.iter().rev().find_map(|report| match report {
+            SearchReport::SearchSummary(s) => Some(s.pv[0].as_string()),
+            _ => None,
+        });
+        assert!(best.is_some() && best != Some(String::from("e5f3")));
+        assert!(!reports.iter().any(|report| matches!(
+            report,
+            SearchReport::InfoString(msg) if msg.starts_with("avoiding repetition")
+        )));
+    }
+
+    // A repeating best move is replaced when clearly ahead, but only by a
+    // move that scores within the margin.
+    #[test]
+    fn repeating_best_move_must_prove_itself() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(REPEAT_FEN)).expect("valid FEN");
+        let mut repeat = Move::new(0);
+        let mut other = Move::new(0);
+        for m in REPEAT_MOVES.iter() {
+            let m = board
+                .legal_moves_iter(&mg)
+                .find(|l| l.as_string() == *m)
+                .expect(m);
+            assert!(board.make(m, &mg));
+        }
+        for m in board.legal_moves_iter(&mg) {
+            match m.as_string().as_str() {
+                "e5f3" => repeat = m,
+                "a1a7" => other = m,
+                _ => (),
+            }
+        }
+        board.history.set_root();
+
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+        refs.search_info.repeating = Search::repeating_root_moves(&mut refs);
+
+        // Once ahead, the repeating move is tried last at the root, even
+        // if the TT suggests it.
+        refs.search_info.avoid_repeating = true;
+        let mut ml = MoveList::new();
+        mg.generate_moves(refs.board, &mut ml, MoveType::All);
+        Search::score_moves(&mut ml, repeat.to_short_move(), &refs);
+        for i in 0..ml.len() {
+            let m = ml.get_move(i);
+            let last = m.get_sort_score() == 0;
+            assert_eq!(last, m.get_move() == repeat.get_move(), "{}", m.as_string());
+        }
+        refs.search_info.avoid_repeating = false;
+
+        // Not ahead, or not repeating: nothing to avoid.
+        let ahead = Score::new(REPEAT_AHEAD + 1);
+        let level = Score::new(REPEAT_AHEAD);
+        assert!(Search::avoid_repetition(&mut refs, 3, level, repeat).is_none());
+        assert!(Search::avoid_repetition(&mut refs, 3, ahead, other).is_none());
+
+        // The best alternative and its score.
+        let (score, pv) = Search::avoid_repetition(&mut refs, 3, ahead, repeat).expect("replaced");
+        assert!(pv[0].get_move() != repeat.get_move());
+        assert!(refs.search_info.excluded.is_empty());
+
+        // The repeating move keeps its place if it is better by more than
+        // the margin.
+        let within = score + REPEAT_MARGIN;
+        let beyond = score + REPEAT_MARGIN + 1;
+        assert!(Search::avoid_repetition(&mut refs, 3, within, repeat).is_some());
+        assert!(Search::avoid_repetition(&mut refs, 3, beyond, repeat).is_none());
+    }
+
+    // With debug on, the class of the root position is reported. Only
+    // when the search is adaptive does the class change what is searched:
+    // in a closed position, late moves are reduced harder.
+    #[test]
+    fn adaptive_search_uses_the_position_class() {
+        let closed = "r1bq1rk1/pp2npbp/3p1np1/2pPp3/2P1P3/2N2N2/PP2BPPP/R1BQ1RK1 w - - 0 1";
+        let nodes = |debug: bool, adaptive: bool| {
+            let mut params = SearchParams::new();
+            params.search_mode = SearchMode::Depth;
+            params.depth = 6;
+            params.debug = debug;
+            params.adaptive = adaptive;
+            let reports = reports(closed, &[], params);
+            let class = reports.iter().find_map(|report| match report {
+                SearchReport::InfoString(msg) if msg.starts_with("position class") => {
+                    Some(msg.clone())
+                }
+                _ => None,
+            });
+            let nodes = reports.iter().rev().find_map(|report| match report {
+                SearchReport::SearchSummary(s) => Some(s.nodes),
+                _ => None,
+            });
+            (class, nodes.expect("summary"))
+        };
+
+        let (class, default) = nodes(false, false);
+        assert_eq!(class, None);
+        let (class, debug) = nodes(true, false);
+        assert_eq!(class.as_deref(), Some("position class closed middlegame"));
+        assert_eq!(debug, default);
+
+        let (_, adaptive) = nodes(false, true);
+        assert!(adaptive < default, "{} {}", adaptive, default);
+    }
+
+    // The root move being searched carries its number, the number of root
+    // moves in the iteration and how many are left after it. After a
+    // completed iteration, the last root move was searched, and no moves
+    // are left. Moves skipped in MultiPV mode don't count.
+    #[test]
+    fn root_move_counts() {
+        let fen = crate::defs::FEN_KIWIPETE_POSITION;
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::Depth;
+        params.depth = 3;
+        let (_, info) = search_controlled(fen, params, Vec::new());
+        assert_eq!(info.root_moves, 48);
+        assert_eq!(info.root_move.curr_move_number, 48);
+        assert_eq!(info.root_move.total_moves, 48);
+        assert_eq!(info.root_move.moves_left, 0);
+
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let skipped: Vec<Move> = board.legal_moves_iter(&mg).take(2).collect();
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+        refs.search_info.root_moves = 48;
+        refs.search_info.excluded = skipped.iter().map(|m| m.to_short_move()).collect();
+        Search::alpha_beta(2, -INF, INF, &mut refs);
+
+        let root_move = refs.search_info.root_move;
+        assert_eq!(root_move.curr_move_number, 46);
+        assert_eq!(root_move.total_moves, 46);
+        assert_eq!(root_move.moves_left, 0);
+    }
+
+    // With complete iterations, a game-time search ends between two
+    // iterations: it is never stopped halfway through one. The move time
+    // at the end of each depth is recorded.
+    #[test]
+    fn complete_iterations_are_not_aborted() {
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::GameTime;
+        params.game_time = GameTime::new(5000, 5000, 0, 0, None);
+        params.complete_iterations = true;
+        let fen = crate::defs::FEN_KIWIPETE_POSITION;
+        let (_, info) = search_controlled(fen, params, Vec::new());
 
-        // Search is done. Report best move and reason to terminate.
-        (best_move, refs.search_info.terminate)
+        assert!(info.terminate == SearchTerminate::Nothing);
+        assert!(!info.depth_times.is_empty());
+        assert_eq!(info.depth_times.len(), info.depth_nodes.len());
+        assert!(info.depth_times.windows(2).all(|w| w[0] <= w[1]));
     }
 }
@@ -0,0 +1,146 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// mate.rs verifies that a position is a mate in N moves, as annotated by
+// "dm" (direct mate) in tactical EPD files. The search runs on the calling
+// thread in Mate mode, with its own TT, so it doesn't disturb the TT of
+// the game. The mate must be found at exactly the expected distance: a
+// composition claiming mate in 3 is unsound if it already mates in 2.
+
+use super::{
+    defs::{
+        MateVerification, SearchControl, SearchInfo, SearchMode, SearchParams, SearchRefs,
+        SearchReport, SearchShared, MATE_VERIFY_HASH,
+    },
+    Search,
+};
+use crate::{
+    board::Board,
+    defs::MAX_PLY,
+    engine::defs::{Information, SearchData, TT},
+    movegen::MoveGenerator,
+};
+use std::sync::Arc;
+
+impl Search {
+    // Searches the position for a mate in the given number of moves. As
+    // with "go mate", the search goes no deeper than the 2N - 1 plies the
+    // mate takes, and stops as soon as a mate within N moves is found.
+    pub fn verify_mate(board: &Board, mg: &Arc<MoveGenerator>, moves: u8) -> MateVerification {
+        let mut search_params = SearchParams::new();
+        search_params.search_mode = SearchMode::Mate;
+        search_params.mate = moves;
+        search_params.depth = (2 * (moves as i16) - 1).clamp(1, MAX_PLY as i16) as i8;
+        search_params.quiet = true;
+
+        // Nothing is ever sent on the control channel. The summaries are
+        // collected from the report channel after the search.
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut board = board.clone();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let tt = TT::<SearchData>::new(MATE_VERIFY_HASH);
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg,
+            tt: &tt,
+            search_params: &mut search_params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        let (best_move, _, _) = Search::iterative_deepening(&mut refs);
+
+        // The score of the deepest completed iteration holds the mate.
+        let mut mate_in = None;
+        while let Ok(information) = report_rx.try_recv() {
+            if let Information::Search(SearchReport::SearchSummary(s)) = information {
+                mate_in = s.score.mate_in();
+            }
+        }
+
+        MateVerification {
+            moves,
+            mate_in,
+            best_move,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MateVerification;
+    use crate::{board::Board, defs::FEN_START_POSITION, movegen::MoveGenerator, search::Search};
+
+    fn verify(fen: &str, moves: u8) -> MateVerification {
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        Search::verify_mate(&board, &MoveGenerator::shared(), moves)
+    }
+
+    // Compositions with their key moves. In the underpromotion, f8=Q would
+    // stalemate the black king; only f8=R mates in 2.
+    #[test]
+    fn compositions_are_proven() {
+        let compositions = [
+            (
+                "r2qkbnr/ppp2ppp/2np4/4N3/2B1P1b1/2N5/PPPP1PPP/R1BbK2R w KQkq - 0 6",
+                2,
+                "c4f7",
+            ),
+            ("8/5P1k/8/8/8/8/8/K5R1 w - - 0 1", 2, "f7f8r"),
+            ("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1", 3, "f6a6"),
+            (
+                "r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - 0 1",
+                3,
+                "f8c5",
+            ),
+        ];
+
+        for (fen, moves, key) in compositions.iter() {
+            let verification = verify(fen, *moves);
+            assert!(verification.is_proven(), "{}", verification.as_string());
+            assert_eq!(verification.best_move.as_string(), *key, "{}", fen);
+        }
+    }
+
+    // A shorter mate than claimed makes the composition unsound, and a
+    // position without a mate is never proven.
+    #[test]
+    fn wrong_claims_are_rejected() {
+        let verification = verify("8/5P1k/8/8/8/8/8/K5R1 w - - 0 1", 3);
+        assert!(!verification.is_proven());
+        assert_eq!(verification.mate_in, Some(2));
+        assert_eq!(
+            verification.as_string(),
+            "mate in 3 not proven: found mate in 2, best move f7f8r"
+        );
+
+        let verification = verify(FEN_START_POSITION, 2);
+        assert!(!verification.is_proven());
+        assert_eq!(verification.mate_in, None);
+    }
+}
@@ -22,20 +22,23 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
 use super::{
-    defs::{SearchTerminate, CHECK_TERMINATION, SEND_STATS},
+    defs::{Score, SearchTerminate, CHECK_TERMINATION, SEND_STATS},
     Search, SearchRefs,
 };
 use crate::{
     defs::MAX_PLY,
     evaluation::Evaluation,
-    movegen::defs::{Move, MoveList, MoveType, ShortMove},
+    movegen::defs::{MoveList, MoveType, ShortMove},
 };
 
 impl Search {
-    pub fn quiescence(mut alpha: i16, beta: i16, pv: &mut Vec<Move>, refs: &mut SearchRefs) -> i16 {
+    pub fn quiescence(mut alpha: Score, beta: Score, refs: &mut SearchRefs) -> Score {
         // We created a new node which we'll search, so count it.
         refs.search_info.nodes += 1;
 
+        // No PV has been found in this node yet.
+        refs.search_info.pv.clear(refs.search_info.ply);
+
         // No intermediate stats updates if quiet.
         let quiet = refs.search_params.quiet;
 
@@ -46,28 +49,44 @@ impl Search {
 
         // Abort if we have to terminate. Depth not finished.
         if refs.search_info.terminate != SearchTerminate::Nothing {
-            return 0;
+            return Score::new(0);
         }
 
-        // Immediately evaluate and return on reaching MAX_PLY
-        if refs.search_info.ply >= MAX_PLY {
-            return Evaluation::evaluate_position(refs.board);
+        // Immediately evaluate and return on reaching MAX_PLY, or if the
+        // history is full.
+        if refs.search_info.ply >= MAX_PLY || refs.board.history.is_full() {
+            return Evaluation::evaluate_position(
+                refs.board,
+                refs.mg,
+                &mut refs.search_info.pawn_table,
+            );
         }
 
+        // A capture can give check. The side to move can't just stand
+        // still then: all the evasions must be searched, and if there are
+        // none, the side to move is checkmated.
+        let is_check = Search::in_check(refs);
+
         // Do a stand-pat here: Check how we're doing, even before we make
         // a move. If the evaluation score is larger than beta, then we're
         // already so bad we don't need to search any further. Just return
         // the beta score.
-        let eval_score = Evaluation::evaluate_position(refs.board);
-        if eval_score >= beta {
-            return beta;
-        }
+        if !is_check {
+            let eval_score = Evaluation::evaluate_position(
+                refs.board,
+                refs.mg,
+                &mut refs.search_info.pawn_table,
+            );
+            if eval_score >= beta {
+                return beta;
+            }
 
-        // If the evaluation score is bigger than alpha, then we can
-        // improve our position. So set alpha to this score and keep
-        // searching until there are no more captures.
-        if eval_score > alpha {
-            alpha = eval_score
+            // If the evaluation score is bigger than alpha, then we can
+            // improve our position. So set alpha to this score and keep
+            // searching until there are no more captures.
+            if eval_score > alpha {
+                alpha = eval_score
+            }
         }
 
         // Stand-pat is done. Start searching the captures in our position.
@@ -76,9 +95,14 @@ impl Search {
         // the recursion, or until there are no more captures available.
         // Then the function will return after looping the move list.
 
-        // Generate only capture moves.
+        // Generate only capture moves, or all moves when in check.
+        let mut legal_moves_found = 0;
         let mut move_list = MoveList::new();
-        let mtc = MoveType::Capture;
+        let mtc = if is_check {
+            MoveType::All
+        } else {
+            MoveType::Capture
+        };
         refs.mg.generate_moves(refs.board, &mut move_list, mtc);
 
         // Do move scoring, so the best move will be searched first.
@@ -96,30 +120,20 @@ impl Search {
             Search::pick_move(&mut move_list, i);
 
             let current_move = move_list.get_move(i);
-            let is_legal = refs.board.make(current_move, refs.mg);
 
-            // If not legal, skip the move and the rest of the function.
-            if !is_legal {
-                continue;
-            }
-
-            // Move is legal; increase the ply count.
-            refs.search_info.ply += 1;
-
-            // Update seldepth if we're searching deeper than requested.
-            if refs.search_info.ply > refs.search_info.seldepth {
-                refs.search_info.seldepth = refs.search_info.ply;
-            }
+            // The position is not yet quiet. Go one ply deeper. (Draws are
+            // not checked in quiescence search.)
+            let visited = Search::visit_move(refs, current_move, false, |refs| {
+                -Search::quiescence(-beta, -alpha, refs)
+            });
 
-            // Create a PV for this node.
-            let mut node_pv: Vec<Move> = Vec::new();
-
-            // The position is not yet quiet. Go one ply deeper.
-            let eval_score = -Search::quiescence(-beta, -alpha, &mut node_pv, refs);
+            // If not legal, skip the move and the rest of the function.
+            let eval_score = match visited {
+                Some(score) => score,
+                None => continue,
+            };
 
-            // Take back the move, and decrease ply accordingly.
-            refs.board.unmake();
-            refs.search_info.ply -= 1;
+            legal_moves_found += 1;
 
             // If we are worse than beta (the opponent), then stop
             // searching, because we can't improve anymore.
@@ -133,12 +147,17 @@ impl Search {
                 alpha = eval_score;
 
                 // Update the Principal Variation.
-                pv.clear();
-                pv.push(current_move);
-                pv.append(&mut node_pv);
+                refs.search_info
+                    .pv
+                    .update(refs.search_info.ply, current_move);
             }
         }
 
+        // In check without a way out: checkmate.
+        if is_check && legal_moves_found == 0 {
+            return Score::mated_at(refs.search_info.ply);
+        }
+
         // We have traversed the entire move list and found the best score for us,
         // so we return this.
         alpha
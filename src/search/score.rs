@@ -0,0 +1,173 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// score.rs implements the Score type. All evaluation and search results
+// are scores. A score is either a normal value in centipawns, or a mate
+// score, which counts down from CHECKMATE by the number of plies to the
+// mate. The Score type makes sure mate scores are handled in one place,
+// instead of comparing against magic numbers throughout the engine.
+
+use super::defs::{CHECKMATE, CHECKMATE_THRESHOLD};
+use std::ops::{Add, Neg, Sub};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub struct Score(i16);
+
+impl Score {
+    pub const fn new(value: i16) -> Self {
+        Self(value)
+    }
+
+    // Returns the raw value of the score. For normal scores this is the
+    // score in centipawns.
+    pub fn value(self) -> i16 {
+        self.0
+    }
+
+    // Score for the side to move being mated at the given ply.
+    pub fn mated_at(ply: i8) -> Self {
+        Self(-CHECKMATE.0 + ply as i16)
+    }
+
+    // True if this score represents a mate for either side.
+    pub fn is_mate(self) -> bool {
+        let v = self.0.abs();
+        (CHECKMATE_THRESHOLD.0..CHECKMATE.0).contains(&v)
+    }
+
+    // Returns the number of moves to mate if this is a mate score. The
+    // number is negative if the side to move is being mated.
+    pub fn mate_in(self) -> Option<i16> {
+        if !self.is_mate() {
+            return None;
+        }
+
+        // Number of plies to mate, converted to moves.
+        let ply = CHECKMATE.0 - self.0.abs();
+        let moves = (ply + 1) / 2;
+
+        // If the engine is being mated itself, flip the score.
+        Some(if self.0 < 0 { -moves } else { moves })
+    }
+
+    // Mate scores are relative to the root of the search. In the TT,
+    // they are stored relative to the position they were found in, so
+    // they can be reused at a different ply. Convert the score to the
+    // value to be stored in the TT.
+    pub fn tt_encode(self, ply: i8) -> Self {
+        match self.0 {
+            v if v > CHECKMATE_THRESHOLD.0 => Self(v + ply as i16),
+            v if v < -CHECKMATE_THRESHOLD.0 => Self(v - ply as i16),
+            _ => self,
+        }
+    }
+
+    // Convert a score stored in the TT back into a score relative to the
+    // root of the search.
+    pub fn tt_decode(self, ply: i8) -> Self {
+        match self.0 {
+            v if v > CHECKMATE_THRESHOLD.0 => Self(v - ply as i16),
+            v if v < -CHECKMATE_THRESHOLD.0 => Self(v + ply as i16),
+            _ => self,
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Add<i16> for Score {
+    type Output = Self;
+
+    fn add(self, rhs: i16) -> Self {
+        Self(self.0 + rhs)
+    }
+}
+
+impl Sub<i16> for Score {
+    type Output = Self;
+
+    fn sub(self, rhs: i16) -> Self {
+        Self(self.0 - rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_distance_in_moves() {
+        // Mate on the next ply is mate in 1; on the third ply, mate in 2.
+        assert_eq!((-Score::mated_at(1)).mate_in(), Some(1));
+        assert_eq!((-Score::mated_at(3)).mate_in(), Some(2));
+
+        // Being mated on the second ply is being mated in 1.
+        assert_eq!(Score::mated_at(2).mate_in(), Some(-1));
+        assert_eq!(Score::mated_at(4).mate_in(), Some(-2));
+
+        assert_eq!(Score::new(0).mate_in(), None);
+        assert_eq!(Score::new(-900).mate_in(), None);
+        assert!(!(CHECKMATE_THRESHOLD - 1).is_mate());
+        assert!(CHECKMATE_THRESHOLD.is_mate());
+    }
+
+    #[test]
+    fn tt_encoding_round_trips() {
+        let scores = [
+            Score::new(0),
+            Score::new(35),
+            Score::new(-1200),
+            Score::mated_at(5),
+            -Score::mated_at(7),
+        ];
+
+        for score in scores.iter() {
+            for ply in [0, 1, 9, 40].iter() {
+                assert!(score.tt_encode(*ply).tt_decode(*ply) == *score);
+            }
+        }
+
+        // A mate found 3 plies from the position, at ply 4 from the root,
+        // is stored as a mate 3 plies away, and read back at ply 2 as a
+        // mate 5 plies from the root.
+        let stored = (-Score::mated_at(7)).tt_encode(4);
+        assert!(stored == -Score::mated_at(3));
+        assert!(stored.tt_decode(2) == -Score::mated_at(5));
+    }
+
+    #[test]
+    fn arithmetic_and_ordering() {
+        let score = Score::new(120);
+        assert_eq!((-score).value(), -120);
+        assert_eq!((score + 30).value(), 150);
+        assert_eq!((score - 200).value(), -80);
+        assert!(Score::mated_at(2) < Score::new(-5000));
+        assert!(-Score::mated_at(2) > -Score::mated_at(4));
+    }
+}
@@ -0,0 +1,128 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// smp.rs implements Lazy SMP. When searching with more than one thread,
+// the main search thread starts helper threads. These search the same
+// position, and share the TT with the main thread. Because the helpers
+// store their results in the TT, the main thread finds many positions
+// already searched, and the search as a whole gets deeper. The helpers
+// don't report anything; only the main thread talks to the engine.
+
+use super::{
+    defs::{
+        SearchInfo, SearchMode, SearchParams, SearchRefs, SearchShared, SearchTerminate,
+        HELPER_PAUSE_POLL,
+    },
+    Search,
+};
+use crate::{
+    board::Board,
+    defs::MAX_PLY,
+    engine::defs::{Information, SearchData, TT},
+    movegen::MoveGenerator,
+};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::Duration,
+};
+
+impl Search {
+    // Runs the search of a helper thread on its own copy of the board. A
+    // helper searches without limits, until the main thread is done.
+    pub fn helper_search(
+        mut board: Board,
+        mg: &Arc<MoveGenerator>,
+        tt: &TT<SearchData>,
+        mut search_params: SearchParams,
+        helper: usize,
+        shared: &SearchShared,
+    ) {
+        search_params.search_mode = SearchMode::Infinite;
+        search_params.depth = MAX_PLY;
+        search_params.quiet = true;
+        search_params.debug = false;
+        search_params.multipv = 1;
+        search_params.helper = helper;
+
+        // Nothing is ever sent to a helper, and nobody listens to what it
+        // reports. The reports are dropped when the helper finishes.
+        let control_rx = crossbeam_channel::never();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut search_info = SearchInfo::new();
+
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg,
+            tt,
+            search_params: &mut search_params,
+            search_info: &mut search_info,
+            shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        Search::iterative_deepening(&mut refs);
+        Search::share_nodes(&mut refs);
+    }
+
+    // Returns the depth at which iterative deepening starts. Every other
+    // helper starts one ply deeper, so the threads don't all search the
+    // same depth at the same time.
+    pub fn start_depth(refs: &SearchRefs) -> i8 {
+        1 + (refs.search_params.helper % 2) as i8
+    }
+
+    // Handles what the main thread passes on to the helpers. A helper also
+    // adds the nodes it searched to the total, and waits while the main
+    // thread is paused.
+    pub fn check_shared(refs: &mut SearchRefs) {
+        let shared = refs.shared;
+
+        if refs.search_params.helper > 0 {
+            Search::share_nodes(refs);
+            while shared.pause.load(Ordering::Relaxed) && !shared.stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(HELPER_PAUSE_POLL));
+            }
+        }
+
+        if shared.stop.load(Ordering::Relaxed) {
+            refs.search_info.terminate = SearchTerminate::Stop;
+        }
+    }
+
+    // Returns the number of nodes searched by all the threads together.
+    pub fn total_nodes(refs: &SearchRefs) -> usize {
+        refs.search_info.nodes + refs.shared.helper_nodes.load(Ordering::Relaxed)
+    }
+
+    // Adds the nodes a helper searched since the last time to the total.
+    fn share_nodes(refs: &mut SearchRefs) {
+        let nodes = refs.search_info.nodes;
+        let new_nodes = nodes - refs.search_info.nodes_shared;
+        refs.shared
+            .helper_nodes
+            .fetch_add(new_nodes, Ordering::Relaxed);
+        refs.search_info.nodes_shared = nodes;
+    }
+}
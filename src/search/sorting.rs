@@ -24,7 +24,7 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 // Move sorting routines.
 
 use super::{
-    defs::{SearchRefs, MAX_KILLER_MOVES},
+    defs::{SearchRefs, HISTORY_MAX, MAX_KILLER_MOVES},
     Search,
 };
 use crate::{board::defs::Pieces, defs::NrOf, movegen::defs::MoveList, movegen::defs::ShortMove};
@@ -51,7 +51,8 @@ impl Search {
             let mut value: u32 = 0;
 
             // Sort order priority is: TT Move first, then captures, then
-            // quiet moves that are in the list of killer moves.
+            // quiet moves that are in the list of killer moves, and then
+            // the other quiet moves by their history score.
             if m.get_move() == tt_move.get_move() {
                 value = MVV_LVA_OFFSET + TTMOVE_SORT_VALUE;
             } else if m.captured() != Pieces::NONE {
@@ -70,14 +71,21 @@ impl Search {
                 }
             }
 
-            /*
-                // If still not sorted, try to sort by history heuristic.
-                if value == 0 {
-                    let piece = m.piece();
-                    let to = m.to();
-                    value = refs.search_info.history_heuristic[refs.board.us()][piece][to];
-                }
-            */
+            // If still not sorted, try to sort by history heuristic. The
+            // history score is shifted to be positive; it stays far below
+            // the killers.
+            if value == 0 {
+                let us = refs.board.us();
+                let history = refs.search_info.history[us][m.from()][m.to()] as i32;
+                value = (history + HISTORY_MAX) as u32;
+            }
+
+            // When ahead, root moves that repeat the game are tried last.
+            let info = &refs.search_info;
+            if info.ply == 0 && info.avoid_repeating && info.repeating.contains(&m.to_short_move())
+            {
+                value = 0;
+            }
 
             m.set_sort_score(value);
         }
@@ -93,3 +101,42 @@ impl Search {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api,
+        board::Board,
+        search::defs::{SearchMode, SearchParams},
+    };
+
+    // Node counts at a fixed depth in two middlegame positions, where the
+    // order of the quiet moves matters most. The history table brought
+    // these down; a change to the move ordering should only update them
+    // if it doesn't make them go up.
+    #[test]
+    fn middlegame_node_counts() {
+        let snapshot = [
+            (
+                "r1bq1rk1/pp2bppp/2n1pn2/2pp4/3P4/2PBPN2/PP1N1PPP/R1BQ1RK1 w - - 0 8",
+                6,
+                93_598,
+            ),
+            (
+                "r2q1rk1/1b2bppp/p2ppn2/1p6/3NP3/1BN1B3/PPP2PPP/R2Q1RK1 w - - 0 11",
+                6,
+                68_237,
+            ),
+        ];
+
+        for (fen, depth, nodes) in snapshot.iter() {
+            let mut board = Board::new();
+            board.fen_read(Some(fen)).expect("valid FEN");
+            let mut params = SearchParams::new();
+            params.depth = *depth;
+            params.search_mode = SearchMode::Depth;
+
+            assert_eq!(api::search(&board, params).nodes, *nodes, "{}", fen);
+        }
+    }
+}
@@ -21,24 +21,130 @@ You should have received a copy of the GNU General Public License along
 with this program.  If not, see <http://www.gnu.org/licenses/>.
 ======================================================================= */
 
-use super::{defs::SearchRefs, Search};
+use super::{
+    defs::{SearchMode, SearchRefs},
+    Search,
+};
 use crate::defs::Sides;
 
-pub const OVERHEAD: i128 = 50; // msecs
+pub const OVERHEAD: u128 = 50; // msecs
 const GAME_LENGTH: usize = 25; // moves
 const MOVES_BUFFER: usize = 5; //moves
 const CRITICAL_TIME: u128 = 1_000; // msecs
 const OK_TIME: u128 = CRITICAL_TIME * 5; // msecs
+const INCREMENT_ONLY: f64 = 0.90; // part of the increment to use
+const MINIMUM_MOVE_TIME: u128 = 100; // msecs
+const ITERATION_GROWTH: f64 = 4.0; // Expected EBF before one is known
+const COMPLETE_ITERATIONS_LIMIT: f64 = 3.0; // times the allocated time
+
+// All time in this module is measured in msecs, and elapsed time comes
+// from the search timer, which is monotonic. Clock times are provided by
+// the GUI at the start of every search and are always trusted, even if
+// they are lower than expected. Subtractions saturate at 0, so a clock
+// that is lower than expected can never wrap around into a huge budget.
 
 impl Search {
+    // Determine the time to spend on this move in GameTime mode.
+    pub fn allocate_time(refs: &mut SearchRefs) {
+        // If the GUI sent nothing but zeroes, there is no time control to
+        // play to. Don't move instantly, but search for a short fixed
+        // time. (Time spent pondering doesn't count.)
+        if refs.search_params.game_time.is_empty() {
+            refs.search_params.search_mode = SearchMode::MoveTime;
+            refs.search_params.move_time = refs.search_info.ponder_time + MINIMUM_MOVE_TIME;
+            return;
+        }
+
+        // Determine the maximum time slice available for this move.
+        let time_slice = Search::calculate_time_slice(refs);
+
+        // Experience reveals that after using about 40-50% of the
+        // available time, the next depth will not be finished, so
+        // don't allocated more than 40% of the calculated move time.
+        // The class of the position may adjust this a little.
+        // With an increment-only time control, there is nothing on the
+        // clock to save for later moves, so the entire slice can be used.
+        let factor = if Search::is_increment_only(refs) {
+            1.0
+        } else {
+            0.40 * refs.search_info.tunables.time_factor
+        };
+
+        // If we have time, do a normal search in GameTime mode.
+        if time_slice > 0 {
+            // Determine the actual time to allot for this search.
+            refs.search_info.allocated_time = (time_slice as f64 * factor).round() as u128;
+        } else {
+            // We have no time. Send the best move from ply 1 to avoid
+            // killing ourselves by sending no move at all. Change mode
+            // to "depth" and set it to 1 ply.
+            refs.search_params.search_mode = SearchMode::Depth;
+            refs.search_params.depth = 1;
+        }
+    }
+
     // Determine if allocated search time has been used up.
     pub fn out_of_time(refs: &mut SearchRefs) -> bool {
-        let elapsed = refs.search_info.timer_elapsed();
+        refs.search_info.move_elapsed() >= Search::time_budget(refs)
+    }
+
+    // The most time the search may use for this move. The allocated time
+    // can be overshot, up to a limit.
+    pub fn time_budget(refs: &SearchRefs) -> u128 {
         let allocated = refs.search_info.allocated_time;
 
-        // Calculate a factor with which it is allowed to overshoot the
-        // allocated search time. The more time the engine has, the larger
-        // the overshoot-factor can be.
+        // Whatever the overshoot, never use more than the time that is
+        // actually left on the clock, or the increment if there is no
+        // time on the clock.
+        let hard_limit = if Search::is_increment_only(refs) {
+            Search::calculate_time_slice(refs)
+        } else {
+            Search::clock(refs).saturating_sub(OVERHEAD)
+        };
+
+        // When iterations are to be completed, the soft limit is only
+        // checked between iterations. An iteration that takes much longer
+        // than predicted is still aborted, long before the clock runs out.
+        let limit = if refs.search_params.complete_iterations {
+            (COMPLETE_ITERATIONS_LIMIT * allocated as f64).round() as u128
+        } else {
+            Search::soft_limit(allocated)
+        };
+
+        u128::min(limit, hard_limit)
+    }
+
+    // Determine if there is time to search the next iteration, when the
+    // running iteration is never stopped on soft time. "Enough" is the
+    // time after which no new iteration is started at all.
+    pub fn next_iteration_fits(refs: &SearchRefs, enough: u128) -> bool {
+        let elapsed = refs.search_info.move_elapsed();
+        let soft_limit = Search::soft_limit(refs.search_info.allocated_time);
+        let last = refs.search_info.iteration_time().unwrap_or(0);
+        let ebf = refs.search_info.average_branching_factor();
+
+        Search::iteration_fits(elapsed, enough, soft_limit, last, ebf)
+    }
+
+    // The next iteration is expected to take the time of the last one,
+    // times the EBF. (The average EBF is used, because the EBF of single
+    // depths jumps up and down.) The iteration is only started if it is
+    // expected to finish within the soft limit.
+    pub fn iteration_fits(
+        elapsed: u128,
+        enough: u128,
+        soft_limit: u128,
+        last: u128,
+        ebf: Option<f64>,
+    ) -> bool {
+        let predicted = (last as f64 * ebf.unwrap_or(ITERATION_GROWTH)).round() as u128;
+        elapsed <= enough && elapsed + predicted <= soft_limit
+    }
+
+    // Calculates the time after which the search is stopped, even in the
+    // middle of an iteration. The more time the engine has, the more it
+    // is allowed to overshoot the allocated time.
+    fn soft_limit(allocated: u128) -> u128 {
         let overshoot_factor = match allocated {
             x if x > OK_TIME => 2.0,                       // Allow large overshoot.
             x if x > CRITICAL_TIME && x <= OK_TIME => 1.5, // Low on time. Reduce overshoot.
@@ -46,7 +152,7 @@ impl Search {
             _ => 1.0,                                      // This case shouldn't happen.
         };
 
-        elapsed >= (overshoot_factor * allocated as f64).round() as u128
+        (overshoot_factor * allocated as f64).round() as u128
     }
 
     // Calculates the time the engine allocates for searching a single
@@ -55,39 +161,332 @@ impl Search {
         // Calculate the time slice step by step.
         let gt = &refs.search_params.game_time;
         let mtg = Search::moves_to_go(refs);
-        let white = refs.board.us() == Sides::WHITE;
-        let clock = if white { gt.wtime } else { gt.btime };
-        let increment = if white { gt.winc } else { gt.binc } as i128;
-        let base_time = ((clock as f64) / (mtg as f64)).round() as i128;
-        let time_slice = base_time + increment - OVERHEAD;
+        let (clock, increment) = gt.for_side(refs.board.us());
 
-        // Make sure we're never sending less than 0 msecs of available time.
-        if time_slice > 0 {
+        // Without a base time, the increment is all we get for each
+        // move. Stay a bit below it, to keep some protection against lag.
+        if Search::is_increment_only(refs) {
+            let budget = (increment as f64 * INCREMENT_ONLY).round() as u128;
+            return u128::min(budget, increment.saturating_sub(OVERHEAD));
+        }
+
+        let base_time = ((clock as f64) / (mtg as f64)).round() as u128;
+        let available = base_time.saturating_add(increment);
+
+        let time_slice = if available > OVERHEAD {
             // Just send the calculated slice.
-            time_slice as u128
-        } else if (base_time + increment) > (OVERHEAD / 5) {
+            available - OVERHEAD
+        } else if available > (OVERHEAD / 5) {
             // Don't subtract GUI lag protection (overhead) if this leads
             // to a negative time allocation.
-            (base_time + increment) as u128
+            available
         } else {
             // We actually don't have any time.
             0
-        }
+        };
+
+        // The increment is only received after the move, so the time
+        // slice can never be larger than what is on the clock right now.
+        u128::min(time_slice, clock.saturating_sub(OVERHEAD))
+    }
+
+    // Returns the time left on the clock of the side to move.
+    fn clock(refs: &SearchRefs) -> u128 {
+        let (clock, _) = refs.search_params.game_time.for_side(refs.board.us());
+        clock
+    }
+
+    // Returns true if there is no time on the clock of the side to move,
+    // but there is an increment: "go winc 1000 binc 1000".
+    fn is_increment_only(refs: &SearchRefs) -> bool {
+        let (clock, increment) = refs.search_params.game_time.for_side(refs.board.us());
+        clock == 0 && increment > 0
     }
 
     // Here we try to come up with some sort of sensible value for "moves
     // to go", if this value is not supplied.
     fn moves_to_go(refs: &SearchRefs) -> usize {
-        // If moves to go was supplied, then use this.
-        if let Some(x) = refs.search_params.game_time.moves_to_go {
-            x
-        } else {
-            // Guess moves to go if not supplied.
-            let white = refs.board.us() == Sides::WHITE;
-            let ply = refs.board.history.len();
-            let moves_made = if white { ply / 2 } else { (ply - 1) / 2 };
+        // If moves to go was supplied, then use this. (A value of 0 would
+        // mean there's no time control left to play to, so guess.)
+        match refs.search_params.game_time.moves_to_go {
+            Some(x) if x > 0 => x,
+            _ => {
+                // Guess moves to go if not supplied.
+                let white = refs.board.us() == Sides::WHITE;
+                let ply = refs.board.history.len();
+                let moves_made = if white {
+                    ply / 2
+                } else {
+                    ply.saturating_sub(1) / 2
+                };
+
+                GAME_LENGTH - (moves_made % GAME_LENGTH) + MOVES_BUFFER
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::defs::{GameTime, SearchControl, SearchInfo, SearchParams, SearchShared};
+    use super::*;
+    use crate::{
+        board::Board,
+        defs::FEN_START_POSITION,
+        engine::defs::{Information, SearchData, TT},
+        movegen::MoveGenerator,
+    };
+
+    const FEN_BLACK: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+
+    // Allocates time for a move in the position, as a search in GameTime
+    // mode would. Returns the search mode it ends up with, the allocated
+    // time and the most time the search may use.
+    fn allocate(fen: &str, game_time: GameTime) -> (SearchMode, u128, u128) {
+        let (params, allocated, budget) = allocate_params(fen, game_time, false);
+        (params.search_mode, allocated, budget)
+    }
+
+    // As allocate(), but returns the search parameters as the allocation
+    // left them. Iterations may be set to be completed.
+    fn allocate_params(
+        fen: &str,
+        game_time: GameTime,
+        complete_iterations: bool,
+    ) -> (SearchParams, u128, u128) {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        board.fen_read(Some(fen)).expect("valid FEN");
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        params.search_mode = SearchMode::GameTime;
+        params.game_time = game_time;
+        params.complete_iterations = complete_iterations;
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        Search::allocate_time(&mut refs);
+        let budget = Search::time_budget(&refs);
+        (params, search_info.allocated_time, budget)
+    }
+
+    // Clock values a GUI should never send, but might. None of them may
+    // give a budget that is larger than the time left on the clock.
+    #[test]
+    fn budget_never_exceeds_the_clock() {
+        let max = u128::MAX;
+        let cases = [
+            GameTime::new(100, 100, 60_000, 60_000, None),
+            GameTime::new(1000, 1000, 0, 0, Some(1)),
+            GameTime::new(1000, 1000, 0, 0, Some(0)),
+            GameTime::new(60, 60, 0, 0, None),
+            GameTime::new(51, 51, 10, 10, Some(1)),
+            GameTime::new(1, 1, max, max, None),
+            GameTime::new(max, max, 0, 0, Some(1)),
+            GameTime::new(max, max, max, max, None),
+        ];
+
+        for (i, gt) in cases.iter().enumerate() {
+            for fen in [FEN_START_POSITION, FEN_BLACK].iter() {
+                let (mode, allocated, budget) = allocate(fen, *gt);
+                let us = if fen == &FEN_BLACK {
+                    Sides::BLACK
+                } else {
+                    Sides::WHITE
+                };
+                let (clock, _) = gt.for_side(us);
+
+                if mode == SearchMode::GameTime {
+                    assert!(allocated <= budget, "case {} {}", i, fen);
+                    assert!(
+                        budget <= clock.saturating_sub(OVERHEAD),
+                        "case {} {}",
+                        i,
+                        fen
+                    );
+                }
+            }
+        }
+    }
+
+    // Without enough time to search, the engine still sends a move: the
+    // best one at depth 1.
+    #[test]
+    fn no_time_left_searches_depth_one() {
+        for (i, gt) in [
+            GameTime::new(0, 1000, 0, 1000, None),
+            GameTime::new(5, 5, 0, 0, None),
+            GameTime::new(5, 5, 0, 0, Some(0)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let (mode, _, _) = allocate(FEN_START_POSITION, *gt);
+            assert!(mode == SearchMode::Depth, "case {}", i);
+        }
+
+        let (mode, _, budget) = allocate(FEN_START_POSITION, GameTime::new(0, 0, 0, 0, None));
+        assert!(mode == SearchMode::MoveTime);
+        assert_eq!(budget, 0);
+    }
+
+    // A sequence of clocks as a GUI may send them during a game, with a
+    // sudden drop (for example after the machine was suspended). The
+    // budget follows the clock the GUI sends, and shrinks immediately.
+    #[test]
+    fn the_gui_clock_is_trusted() {
+        let clocks = [300_000, 290_000, 280_000, 4000, 3000, 800, 120, 60, 51, 50];
+        let mut last_budget = u128::MAX;
+
+        for clock in clocks.iter() {
+            let gt = GameTime::new(*clock, 300_000, 0, 0, None);
+            let (mode, _, budget) = allocate(FEN_START_POSITION, gt);
+
+            if mode == SearchMode::GameTime {
+                assert!(budget <= clock - OVERHEAD, "clock {}", clock);
+                assert!(budget <= last_budget, "clock {}", clock);
+                last_budget = budget;
+            }
+        }
+
+        let (_, _, before) = allocate(FEN_START_POSITION, GameTime::new(280_000, 0, 0, 0, None));
+        let (_, _, after) = allocate(FEN_START_POSITION, GameTime::new(4000, 0, 0, 0, None));
+        assert!(after * 10 < before);
+        assert!(after <= 4000 - OVERHEAD);
+    }
+
+    // "movestogo 0" is treated as if moves to go were not sent at all.
+    #[test]
+    fn zero_moves_to_go_is_unknown() {
+        for fen in [FEN_START_POSITION, FEN_BLACK].iter() {
+            let unknown = allocate(fen, GameTime::new(60_000, 60_000, 0, 0, None));
+            let zero = allocate(fen, GameTime::new(60_000, 60_000, 0, 0, Some(0)));
+            assert_eq!((unknown.1, unknown.2), (zero.1, zero.2), "{}", fen);
+        }
+    }
+
+    // With only an increment, the budget stays below the increment: 90%
+    // of it, or at least the overhead below it.
+    #[test]
+    fn increment_only_budget() {
+        let (mode, allocated, budget) =
+            allocate(FEN_START_POSITION, GameTime::new(0, 0, 1000, 1000, None));
+        assert!(mode == SearchMode::GameTime);
+        assert!(allocated > 0);
+        assert_eq!(budget, 900);
+
+        for fen in [FEN_START_POSITION, FEN_BLACK].iter() {
+            for increment in [60, 100, 500, 2000, 10_000].iter() {
+                let gt = GameTime::new(0, 0, *increment, *increment, None);
+                let (mode, allocated, budget) = allocate(fen, gt);
+                let expected = u128::min(increment * 9 / 10, increment - OVERHEAD);
+                assert!(mode == SearchMode::GameTime, "{}", increment);
+                assert!(allocated <= budget, "{}", increment);
+                assert_eq!(budget, expected, "{}", increment);
+            }
+        }
+    }
+
+    // With every clock and increment at 0, there is no time control: the
+    // engine searches for a short fixed time instead of moving instantly.
+    #[test]
+    fn empty_time_control_searches_briefly() {
+        let (params, _, _) =
+            allocate_params(FEN_START_POSITION, GameTime::new(0, 0, 0, 0, None), false);
+        assert!(params.search_mode == SearchMode::MoveTime);
+        assert_eq!(params.move_time, 100);
+    }
+
+    // Only the clock and increment of the side to move count. The other
+    // side's time may be missing; it doesn't change the budget.
+    #[test]
+    fn opponent_time_does_not_matter() {
+        let both = allocate(
+            FEN_START_POSITION,
+            GameTime::new(60_000, 60_000, 1000, 1000, None),
+        );
+        let ours = allocate(FEN_START_POSITION, GameTime::new(60_000, 0, 1000, 0, None));
+        assert_eq!((both.1, both.2), (ours.1, ours.2));
+
+        let both = allocate(FEN_BLACK, GameTime::new(60_000, 30_000, 1000, 500, None));
+        let ours = allocate(FEN_BLACK, GameTime::new(0, 30_000, 0, 500, None));
+        assert_eq!((both.1, both.2), (ours.1, ours.2));
+    }
+
+    // Time controls as lichess-bot and cutechess send them. The engine
+    // takes a sensible part of the clock: neither an instant move nor
+    // more than a fifth of its time.
+    #[test]
+    fn real_time_controls() {
+        let cases = [
+            GameTime::new(60_000, 60_000, 2000, 2000, None),
+            GameTime::new(300_000, 300_000, 0, 0, Some(40)),
+            GameTime::new(180_000, 180_000, 2000, 2000, None),
+            GameTime::new(10_000, 10_000, 100, 100, None),
+        ];
+
+        for (i, gt) in cases.iter().enumerate() {
+            let (mode, allocated, budget) = allocate(FEN_START_POSITION, *gt);
+            assert!(mode == SearchMode::GameTime, "case {}", i);
+            assert!(allocated >= 100, "case {}: {}", i, allocated);
+            assert!(budget <= gt.wtime / 5, "case {}: {}", i, budget);
+        }
+    }
+
+    // Synthetic timings: the next iteration is started only if it is
+    // predicted to end within the soft limit, and never after "enough".
+    // Without an EBF, the iteration is expected to take 4 times as long as
+    // the last one.
+    #[test]
+    fn next_iteration_start_or_skip() {
+        let (enough, soft_limit) = (2000, 4000);
+        let cases = [
+            (1000, 500, Some(3.0), true),   // Ends at 2500
+            (1000, 1000, Some(3.0), true),  // Ends exactly at the limit
+            (1000, 1200, Some(3.0), false), // Ends at 4600
+            (1000, 500, None, true),        // Ends at 3000
+            (1000, 800, None, false),       // Ends at 4200
+            (2000, 0, Some(2.0), true),     // Exactly "enough" has passed
+            (2001, 0, Some(2.0), false),    // Enough time was used
+            (0, 0, None, true),             // The first iteration
+        ];
+
+        for (elapsed, last, ebf, fits) in cases.iter() {
+            let result = Search::iteration_fits(*elapsed, enough, soft_limit, *last, *ebf);
+            assert_eq!(result, *fits, "elapsed {} last {}", elapsed, last);
+        }
+    }
+
+    // When iterations are completed, only an iteration that takes far
+    // longer than predicted is aborted: at three times the allocated time,
+    // or when the clock runs out, whichever comes first.
+    #[test]
+    fn complete_iterations_hard_limit() {
+        for gt in [
+            GameTime::new(300_000, 300_000, 0, 0, None),
+            GameTime::new(3_000, 3_000, 0, 0, None),
+            GameTime::new(60_000, 60_000, 1000, 1000, Some(2)),
+        ]
+        .iter()
+        {
+            let (_, allocated, soft) = allocate_params(FEN_START_POSITION, *gt, false);
+            let (_, _, hard) = allocate_params(FEN_START_POSITION, *gt, true);
+            let clock = gt.wtime - OVERHEAD;
 
-            GAME_LENGTH - (moves_made % GAME_LENGTH) + MOVES_BUFFER
+            assert_eq!(hard, u128::min(allocated * 3, clock), "{}", gt.wtime);
+            assert!(hard >= soft, "{}", gt.wtime);
         }
     }
 }
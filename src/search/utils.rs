@@ -23,17 +23,19 @@ with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::{
     defs::{
-        SearchControl, SearchCurrentMove, SearchMode, SearchRefs, SearchReport, SearchStats,
-        SearchTerminate, MAX_KILLER_MOVES, MIN_TIME_CURR_MOVE, MIN_TIME_STATS,
+        HistoryHeuristic, Score, SearchControl, SearchCurrentMove, SearchMode, SearchRefs,
+        SearchReport, SearchStats, SearchTerminate, DRAW, HISTORY_BONUS_MAX, HISTORY_DECAY,
+        HISTORY_MAX, MAX_KILLER_MOVES, MIN_TIME_CURR_MOVE, MIN_TIME_STATS,
     },
     Search,
 };
 use crate::{
-    board::{defs::Pieces, Board},
-    defs::{Sides, MAX_MOVE_RULE},
+    defs::MAX_MOVE_RULE,
     engine::defs::{ErrFatal, Information},
-    movegen::defs::Move,
+    movegen::defs::{Move, MoveList},
 };
+use crossbeam_channel::TrySendError;
+use std::{sync::atomic::Ordering, time::Instant};
 
 impl Search {
     // This function calculates the number of nodes per second.
@@ -46,35 +48,67 @@ impl Search {
         nps
     }
 
+    // Smooths the speed over the intervals between stats, using an
+    // exponential moving average. The first interval is taken as is.
+    pub fn smoothed_nps(average: f64, interval_nps: usize, alpha: f64) -> f64 {
+        if average == 0.0 {
+            interval_nps as f64
+        } else {
+            average + alpha * (interval_nps as f64 - average)
+        }
+    }
+
+    // Creates intermediate stats. The speed is smoothed over the intervals
+    // since the previous stats, so it doesn't jump around in GUI graphs.
+    fn interval_stats(refs: &mut SearchRefs, elapsed: u128) -> SearchStats {
+        let nodes = Search::total_nodes(refs);
+        let interval_nodes = nodes.saturating_sub(refs.search_info.last_stats_nodes);
+        let interval_time = elapsed.saturating_sub(refs.search_info.last_stats_sent);
+        let interval_nps = Search::nodes_per_second(interval_nodes, interval_time);
+        let info = &mut refs.search_info;
+        let alpha = info.tunables.nps_smoothing;
+
+        info.nps_average = Search::smoothed_nps(info.nps_average, interval_nps, alpha);
+        info.last_stats_nodes = nodes;
+        info.last_stats_sent = elapsed;
+
+        let nps = info.nps_average.round() as usize;
+        SearchStats::new(elapsed, nodes, nps, refs.tt.hash_full())
+            .with_interval(interval_nodes, interval_time)
+    }
+
+    // Intermediate stats are not worth waiting for. If the engine can't
+    // keep up with them, they're dropped.
+    fn send_droppable(refs: &SearchRefs, information: Information) {
+        if let Err(TrySendError::Disconnected(_)) = refs.report_tx.try_send(information) {
+            panic!("{}", ErrFatal::CHANNEL);
+        }
+    }
+
     // Send intermediate statistics to GUI.
     pub fn send_stats_to_gui(refs: &mut SearchRefs) {
         let elapsed = refs.search_info.timer_elapsed();
         let last_stats = refs.search_info.last_stats_sent;
 
         if elapsed >= last_stats + MIN_TIME_STATS {
-            let hash_full = refs.tt.lock().expect(ErrFatal::LOCK).hash_full();
-            let msecs = refs.search_info.timer_elapsed();
-            let nps = Search::nodes_per_second(refs.search_info.nodes, msecs);
-            let stats = SearchStats::new(msecs, refs.search_info.nodes, nps, hash_full);
+            let stats = Search::interval_stats(refs, elapsed);
             let stats_report = SearchReport::SearchStats(stats);
             let information = Information::Search(stats_report);
 
-            refs.report_tx.send(information).expect(ErrFatal::CHANNEL);
-            refs.search_info.last_stats_sent = elapsed;
+            Search::send_droppable(refs, information);
         }
     }
 
     // Send currently processed move to GUI.
-    pub fn send_move_to_gui(refs: &mut SearchRefs, current_move: Move, count: u8) {
+    pub fn send_move_to_gui(refs: &mut SearchRefs, scm: SearchCurrentMove) {
         let elapsed = refs.search_info.timer_elapsed();
         let lcm = refs.search_info.last_curr_move_sent;
 
         if elapsed >= lcm + MIN_TIME_CURR_MOVE {
-            let scm = SearchCurrentMove::new(current_move, count);
             let scm_report = SearchReport::SearchCurrentMove(scm);
             let information = Information::Search(scm_report);
 
-            refs.report_tx.send(information).expect(ErrFatal::CHANNEL);
+            Search::send_droppable(refs, information);
             refs.search_info.last_curr_move_sent = elapsed;
         }
     }
@@ -87,13 +121,18 @@ impl Search {
         match cmd {
             SearchControl::Stop => refs.search_info.terminate = SearchTerminate::Stop,
             SearchControl::Quit => refs.search_info.terminate = SearchTerminate::Quit,
-            SearchControl::Start(_) | SearchControl::Nothing => (),
+            SearchControl::PonderHit => Search::ponder_hit(refs),
+            SearchControl::Pause => Search::wait_for_resume(refs),
+            SearchControl::Start(_) | SearchControl::Resume | SearchControl::Nothing => (),
         };
 
+        // Pass stop and pause on to helper threads, or receive them.
+        Search::check_shared(refs);
+
         // Terminate search if certain conditions are met.
         let search_mode = refs.search_params.search_mode;
         match search_mode {
-            SearchMode::Depth => {
+            SearchMode::Depth | SearchMode::Mate => {
                 if refs.search_info.depth > refs.search_params.depth {
                     refs.search_info.terminate = SearchTerminate::Stop
                 }
@@ -115,76 +154,176 @@ impl Search {
                 }
             }
             SearchMode::Infinite => (), // Handled by a direct 'stop' command
+            SearchMode::Ponder => (),   // Handled by 'stop' or 'ponderhit'
+            SearchMode::Compare => (),  // Each root move has its own search
             SearchMode::Nothing => (),  // We're not searching. Nothing to do.
         }
+
+        // Stats are normally sent only every SEND_STATS nodes. In a slow
+        // search, this may take a long time, so make sure the GUI hears
+        // from the search at least every report interval.
+        if !refs.search_params.quiet {
+            Search::send_heartbeat(refs);
+        }
+    }
+
+    // The opponent played the move the engine was pondering on. Continue
+    // the search as a normal GameTime search, keeping everything that was
+    // found until now. Only time spent from here on counts for our move.
+    pub fn ponder_hit(refs: &mut SearchRefs) {
+        if refs.search_params.search_mode == SearchMode::Ponder {
+            refs.search_info.ponder_time = refs.search_info.timer_elapsed();
+            refs.search_params.search_mode = SearchMode::GameTime;
+            Search::allocate_time(refs);
+        }
+    }
+
+    // A ponder search that has nothing more to search must not finish,
+    // because the best move can't be sent while pondering. Wait until
+    // the GUI either stops the search or reports a ponderhit.
+    pub fn wait_for_ponder_end(refs: &mut SearchRefs) {
+        while refs.search_params.search_mode == SearchMode::Ponder {
+            match refs.control_rx.recv().expect(ErrFatal::CHANNEL) {
+                SearchControl::Stop => refs.search_info.terminate = SearchTerminate::Stop,
+                SearchControl::Quit => refs.search_info.terminate = SearchTerminate::Quit,
+                SearchControl::PonderHit => Search::ponder_hit(refs),
+                SearchControl::Pause => Search::wait_for_resume(refs),
+                SearchControl::Start(_) | SearchControl::Resume | SearchControl::Nothing => (),
+            }
+
+            if refs.search_info.interrupted() {
+                break;
+            }
+        }
+    }
+
+    // The GUI paused the game. Stop searching until it resumes the game,
+    // and don't count the time in between: the search continues as if it
+    // had never been paused. The search can still be stopped while paused.
+    pub fn wait_for_resume(refs: &mut SearchRefs) {
+        let paused = Instant::now();
+        refs.shared.pause.store(true, Ordering::Relaxed);
+
+        loop {
+            match refs.control_rx.recv().expect(ErrFatal::CHANNEL) {
+                SearchControl::Resume => break,
+                SearchControl::Stop => refs.search_info.terminate = SearchTerminate::Stop,
+                SearchControl::Quit => refs.search_info.terminate = SearchTerminate::Quit,
+                SearchControl::PonderHit => Search::ponder_hit(refs),
+                SearchControl::Start(_) | SearchControl::Pause | SearchControl::Nothing => (),
+            }
+
+            if refs.search_info.interrupted() {
+                break;
+            }
+        }
+
+        refs.shared.pause.store(false, Ordering::Relaxed);
+        refs.search_info.timer_exclude(paused.elapsed());
+    }
+
+    // Send stats and the root move currently being searched, if nothing
+    // was sent during the last report interval.
+    pub fn send_heartbeat(refs: &mut SearchRefs) {
+        let interval = refs.search_params.report_interval;
+        let elapsed = refs.search_info.timer_elapsed();
+        let last_stats = refs.search_info.last_stats_sent;
+
+        if interval > 0 && elapsed >= last_stats + interval {
+            let stats = Search::interval_stats(refs, elapsed);
+            let information = Information::Search(SearchReport::SearchStats(stats));
+            Search::send_droppable(refs, information);
+
+            let root_move = refs.search_info.root_move;
+            if !root_move.curr_move.is_null() {
+                let report = SearchReport::SearchCurrentMove(root_move);
+                let information = Information::Search(report);
+                Search::send_droppable(refs, information);
+                refs.search_info.last_curr_move_sent = elapsed;
+            }
+        }
+    }
+
+    // Plays the move, searches the resulting position using the passed
+    // function, and takes the move back. Returns None if the move is not
+    // legal. The ply count, seldepth and PV are kept up to date, and
+    // because the function can't return from the caller, every make() is
+    // always matched by an unmake(). If draw_check is set, a position that
+    // is a draw is not searched but scored as a draw right away.
+    pub fn visit_move<F>(
+        refs: &mut SearchRefs,
+        m: Move,
+        draw_check: bool,
+        search: F,
+    ) -> Option<Score>
+    where
+        F: FnOnce(&mut SearchRefs) -> Score,
+    {
+        if !refs.board.make(m, refs.mg) {
+            return None;
+        }
+
+        refs.search_info.ply += 1;
+
+        // Update seldepth if searching deeper than specified depth.
+        if refs.search_info.ply > refs.search_info.seldepth {
+            refs.search_info.seldepth = refs.search_info.ply;
+        }
+
+        // Start with an empty PV for this move, in case it isn't
+        // searched because the position is a draw.
+        refs.search_info.pv.clear(refs.search_info.ply);
+
+        let score = if draw_check && Search::is_draw(refs) {
+            DRAW
+        } else {
+            search(refs)
+        };
+
+        // Take back the move, and decrease ply accordingly.
+        let taken_back = refs.board.unmake();
+        debug_assert!(taken_back);
+        refs.search_info.ply -= 1;
+
+        Some(score)
+    }
+
+    // Counts the legal moves in the position on the board.
+    pub fn count_legal_moves(refs: &mut SearchRefs) -> usize {
+        refs.board.legal_moves_iter(refs.mg).count()
     }
 
     // Returns true if the position should be evaluated as a draw.
     pub fn is_draw(refs: &SearchRefs) -> bool {
         let is_max_move_rule = refs.board.game_state.halfmove_clock >= MAX_MOVE_RULE;
-        Search::is_insufficient_material(refs)
-            || Search::is_repetition(refs.board) > 0
-            || is_max_move_rule
+        refs.board.is_insufficient_material() || refs.board.is_repetition_draw() || is_max_move_rule
     }
 
-    // Detects position repetitions in the game's history.
-    pub fn is_repetition(board: &Board) -> u8 {
-        let mut count = 0;
-        let mut stop = false;
-        let mut i = board.history.len() - 1;
+    // Plays the PV on a copy of the board, and returns the kind of draw
+    // it runs into, if any. A draw score is often not the evaluation of
+    // the position, but the result of such a draw.
+    pub fn draw_in_pv(refs: &SearchRefs, pv: &[Move]) -> Option<&'static str> {
+        let mut board = refs.board.clone();
 
-        // Search the history list.
-        while i != 0 && !stop {
-            let historic = board.history.get_ref(i);
+        for m in pv.iter() {
+            if !board.make(*m, refs.mg) {
+                return None;
+            }
 
-            // If the historic zobrist key is equal to the one of the board
-            // passed into the function, then we found a repetition.
-            if historic.zobrist_key == board.game_state.zobrist_key {
-                count += 1;
+            if board.is_repetition_draw() {
+                return Some("repetition");
             }
 
-            // If the historic HMC is 0, it indicates that this position
-            // was created by a capture or pawn move. We don't have to
-            // search further back, because before this, we can't ever
-            // repeat. After all, the capture or pawn move can't be
-            // reverted or repeated.
-            stop = historic.halfmove_clock == 0;
+            if board.game_state.halfmove_clock >= MAX_MOVE_RULE {
+                return Some("fifty-move rule");
+            }
 
-            // Search backwards.
-            i -= 1;
+            if board.is_insufficient_material() {
+                return Some("insufficient material");
+            }
         }
-        count
-    }
-}
 
-// This is in its own block so rustfmt::skip can be applied. Otherwise
-// the layout of this function becomes very messy.
-#[rustfmt::skip]
-impl Search {
-    pub fn is_insufficient_material(refs: &SearchRefs) -> bool {
-        // It's not a draw if: ...there are still pawns.
-        let w_p = refs.board.get_pieces(Pieces::PAWN, Sides::WHITE).count_ones() > 0;     
-        let b_p = refs.board.get_pieces(Pieces::PAWN, Sides::BLACK).count_ones() > 0;        
-        // ...there's a major piece on the board.
-        let w_q = refs.board.get_pieces(Pieces::QUEEN, Sides::WHITE).count_ones() > 0;
-        let b_q = refs.board.get_pieces(Pieces::QUEEN, Sides::BLACK).count_ones() > 0;
-        let w_r = refs.board.get_pieces(Pieces::ROOK, Sides::WHITE).count_ones() > 0;
-        let b_r = refs.board.get_pieces(Pieces::ROOK, Sides::BLACK).count_ones() > 0;
-        // ...or two bishops for one side.
-        // FIXME : Bishops must be on squares of different color
-        let w_b = refs.board.get_pieces(Pieces::BISHOP, Sides::WHITE).count_ones() > 1;
-        let b_b = refs.board.get_pieces(Pieces::BISHOP, Sides::BLACK).count_ones() > 1;
-        // ... or a bishop+knight for at least one side.
-        let w_bn =
-            refs.board.get_pieces(Pieces::BISHOP, Sides::WHITE).count_ones() > 0 &&
-            refs.board.get_pieces(Pieces::KNIGHT, Sides::WHITE).count_ones() > 0;
-        let b_bn =
-            refs.board.get_pieces(Pieces::BISHOP, Sides::BLACK).count_ones() > 0 &&
-            refs.board.get_pieces(Pieces::KNIGHT, Sides::BLACK).count_ones() > 0;
-         
-        // If one of the conditions above is true, we still have enough
-        // material for checkmate, so insufficient_material returns false.
-        !(w_p || b_p || w_q || b_q || w_r || b_r || w_b || b_b ||  w_bn || b_bn)
+        None
     }
 }
 
@@ -214,4 +353,202 @@ impl Search {
             refs.search_info.killer_moves[ply][0] = current_move.to_short_move();
         }
     }
+
+    // Returns true if the move is one of the killer moves at this ply.
+    pub fn is_killer(m: Move, refs: &SearchRefs) -> bool {
+        let ply = refs.search_info.ply as usize;
+        refs.search_info.killer_moves[ply]
+            .iter()
+            .any(|k| k.get_move() == m.get_move())
+    }
+
+    // Returns true if the side to move is in check. Right after a move was
+    // made, this means the move gave check.
+    pub fn in_check(refs: &SearchRefs) -> bool {
+        let board = &refs.board;
+        refs.mg
+            .square_attacked(board, board.opponent(), board.king_square(board.us()))
+    }
+
+    // A quiet move that causes a beta-cutoff gets a bonus in the history
+    // table. The quiet moves tried before it at this node failed to do
+    // so; they get a malus of the same size. Cutoffs at higher depths are
+    // worth more, because they prune larger parts of the tree.
+    pub fn update_history_heuristic(
+        current_move: Move,
+        quiets_tried: &MoveList,
+        depth: i8,
+        refs: &mut SearchRefs,
+    ) {
+        let us = refs.board.us();
+        let d = depth as i32;
+        let bonus = (16 * d * d).min(HISTORY_BONUS_MAX);
+        let history = &mut refs.search_info.history[us];
+
+        Search::history_gravity(&mut history[current_move.from()][current_move.to()], bonus);
+        for i in 0..quiets_tried.len() {
+            let m = quiets_tried.get_move(i);
+            Search::history_gravity(&mut history[m.from()][m.to()], -bonus);
+        }
+    }
+
+    // Adds a bonus (or malus) to a history score. The further the score
+    // already is in the direction of the bonus, the less of it is added,
+    // so the score can't leave the range of -/+ HISTORY_MAX. A move that
+    // has been good for a long time can still lose its score quickly.
+    pub fn history_gravity(entry: &mut i16, bonus: i32) {
+        let bonus = bonus.clamp(-HISTORY_MAX, HISTORY_MAX);
+        let score = *entry as i32;
+        let updated = score + bonus - score * bonus.abs() / HISTORY_MAX;
+
+        *entry = updated.clamp(-HISTORY_MAX, HISTORY_MAX) as i16;
+    }
+
+    // The history table is kept from one search to the next, but the
+    // scores are decayed in between. The table then adapts quickly when
+    // the character of the position changes during a long game.
+    pub fn decay_history(history: &mut HistoryHeuristic) {
+        for entry in history.iter_mut().flatten().flatten() {
+            *entry /= HISTORY_DECAY;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        board::Board,
+        engine::defs::{SearchData, TT},
+        movegen::MoveGenerator,
+        search::defs::{SearchInfo, SearchParams, SearchShared},
+    };
+
+    #[test]
+    fn repeated_bonuses_converge_to_the_maximum() {
+        let mut entry: i16 = 0;
+        let mut previous = entry;
+        for _ in 0..1000 {
+            Search::history_gravity(&mut entry, HISTORY_BONUS_MAX);
+            assert!(entry >= previous);
+            previous = entry;
+        }
+        assert_eq!(entry as i32, HISTORY_MAX);
+
+        // Even bonuses far too large for an i16 don't overflow.
+        Search::history_gravity(&mut entry, i32::MAX);
+        assert_eq!(entry as i32, HISTORY_MAX);
+    }
+
+    #[test]
+    fn repeated_maluses_converge_to_the_minimum() {
+        let mut entry: i16 = 0;
+        let mut previous = entry;
+        for _ in 0..1000 {
+            Search::history_gravity(&mut entry, -HISTORY_BONUS_MAX);
+            assert!(entry <= previous);
+            previous = entry;
+        }
+        assert_eq!(entry as i32, -HISTORY_MAX);
+
+        Search::history_gravity(&mut entry, i32::MIN);
+        assert_eq!(entry as i32, -HISTORY_MAX);
+    }
+
+    // A move that has been good for a long time still loses much of its
+    // score after a single failure.
+    #[test]
+    fn a_malus_lowers_a_boosted_entry() {
+        let mut entry: i16 = 0;
+        for _ in 0..1000 {
+            Search::history_gravity(&mut entry, HISTORY_BONUS_MAX);
+        }
+
+        let boosted = entry;
+        Search::history_gravity(&mut entry, -HISTORY_BONUS_MAX);
+        assert!(entry < boosted);
+        assert!(boosted as i32 - entry as i32 >= HISTORY_BONUS_MAX);
+    }
+
+    #[test]
+    fn decay_shrinks_scores_towards_zero() {
+        let mut history: HistoryHeuristic = [[[0; 64]; 64]; 2];
+        history[0][12][28] = HISTORY_MAX as i16;
+        history[1][52][36] = -HISTORY_MAX as i16;
+
+        Search::decay_history(&mut history);
+        assert_eq!(
+            history[0][12][28] as i32,
+            HISTORY_MAX / HISTORY_DECAY as i32
+        );
+        assert_eq!(
+            history[1][52][36] as i32,
+            -HISTORY_MAX / HISTORY_DECAY as i32
+        );
+    }
+
+    // The first interval is taken as it is; after that, each interval
+    // moves the average by the weight of the newest one.
+    #[test]
+    fn moving_average_of_the_speed() {
+        assert_eq!(Search::smoothed_nps(0.0, 1000, 0.3), 1000.0);
+        assert!((Search::smoothed_nps(1000.0, 2000, 0.3) - 1300.0).abs() < 1e-9);
+        assert!((Search::smoothed_nps(1000.0, 0, 0.3) - 700.0).abs() < 1e-9);
+        assert_eq!(Search::smoothed_nps(1000.0, 2000, 1.0), 2000.0);
+        assert_eq!(Search::smoothed_nps(1000.0, 2000, 0.0), 1000.0);
+
+        // A steady speed is approached, and a spike moves the average by
+        // only a part of its size.
+        let mut average = 0.0;
+        for _ in 0..50 {
+            average = Search::smoothed_nps(average, 1_000_000, 0.3);
+        }
+        assert!((average - 1_000_000.0).abs() < 1.0);
+        let spike = Search::smoothed_nps(average, 4_000_000, 0.3);
+        assert!((spike - 1_900_000.0).abs() < 1.0);
+    }
+
+    // Each stats report carries the nodes and time since the previous one,
+    // and the smoothed speed. The totals stay exact.
+    #[test]
+    fn interval_stats_plumbing() {
+        let mg = MoveGenerator::shared();
+        let mut board = Board::new();
+        let tt = TT::<SearchData>::new(1);
+        let (_control_tx, control_rx) = crossbeam_channel::unbounded::<SearchControl>();
+        let (report_tx, _report_rx) = crossbeam_channel::unbounded::<Information>();
+        let mut params = SearchParams::new();
+        let mut search_info = SearchInfo::new();
+        let shared = SearchShared::new();
+        let mut refs = SearchRefs {
+            board: &mut board,
+            mg: &mg,
+            tt: &tt,
+            search_params: &mut params,
+            search_info: &mut search_info,
+            shared: &shared,
+            control_rx: &control_rx,
+            report_tx: &report_tx,
+        };
+
+        refs.search_info.nodes = 1_000_000;
+        let first = Search::interval_stats(&mut refs, 2000);
+        assert_eq!((first.nodes, first.time), (1_000_000, 2000));
+        assert_eq!(
+            (first.interval_nodes, first.interval_time),
+            (1_000_000, 2000)
+        );
+        assert_eq!(first.nps, 500_000);
+
+        refs.search_info.nodes = 3_000_000;
+        let second = Search::interval_stats(&mut refs, 3000);
+        assert_eq!((second.nodes, second.time), (3_000_000, 3000));
+        assert_eq!(
+            (second.interval_nodes, second.interval_time),
+            (2_000_000, 1000)
+        );
+        assert_eq!(second.nps, 950_000);
+        assert_eq!(refs.search_info.last_stats_nodes, 3_000_000);
+        assert_eq!(refs.search_info.last_stats_sent, 3000);
+    }
 }
@@ -0,0 +1,110 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Counts the heap allocations made by a search. The principal variation
+// is kept in a table that is allocated once, so searching deeper visits
+// many more nodes, but hardly allocates more. Each test in this file runs
+// on a thread of its own; only the allocations of that thread are counted.
+
+use rustic_alpha::{search, Board, SearchMode, SearchParams};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|a| a.set(a.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Searches to the given depth. Returns the number of allocations and the
+// number of nodes.
+fn search_to_depth(fen: &str, depth: i8) -> (usize, usize) {
+    let mut board = Board::new();
+    board.fen_read(Some(fen)).expect("valid FEN");
+    let mut params = SearchParams::new();
+    params.search_mode = SearchMode::Depth;
+    params.depth = depth;
+
+    let before = ALLOCATIONS.with(|a| a.get());
+    let outcome = search(&board, params);
+    let after = ALLOCATIONS.with(|a| a.get());
+
+    (after - before, outcome.nodes)
+}
+
+// The summary of an iteration holds the PV, and is sent over a channel.
+const ALLOCATIONS_PER_ITERATION: usize = 4;
+
+#[test]
+fn deeper_searches_hardly_allocate() {
+    let positions = [
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            3,
+            7,
+        ),
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            2,
+            6,
+        ),
+        ("8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1", 6, 16),
+    ];
+
+    for (fen, shallow, deep) in positions.iter() {
+        let (shallow_allocations, shallow_nodes) = search_to_depth(fen, *shallow);
+        let (deep_allocations, deep_nodes) = search_to_depth(fen, *deep);
+        assert!(deep_nodes > 10 * shallow_nodes, "{}", fen);
+
+        // Only the summary sent after each iteration allocates.
+        let iterations = (deep - shallow) as usize;
+        let more = deep_allocations - shallow_allocations;
+        assert!(
+            more <= ALLOCATIONS_PER_ITERATION * iterations,
+            "{}: {}",
+            fen,
+            more
+        );
+    }
+}
@@ -0,0 +1,134 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Uses Rustic as a library, the way the examples do, and checks the
+// results. Only the public interface of the crate is available here.
+
+use rustic_alpha::{
+    evaluate_position, format, search, Board, FenError, MoveGenerator, SearchMode, SearchParams,
+};
+
+const SCHOLARS_MATE: &str = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+
+fn board(fen: &str) -> Board {
+    let mut board = Board::new();
+    board.fen_read(Some(fen)).expect("valid FEN");
+    board
+}
+
+fn depth(depth: i8) -> SearchParams {
+    let mut params = SearchParams::new();
+    params.search_mode = SearchMode::Depth;
+    params.depth = depth;
+    params
+}
+
+fn legal_moves(board: &Board, mg: &MoveGenerator) -> Vec<String> {
+    board.legal_moves_iter(mg).map(|m| m.as_string()).collect()
+}
+
+// examples/legal_moves.rs
+#[test]
+fn legal_moves_in_coordinates_and_san() {
+    let mg = MoveGenerator::shared();
+    let mut start = Board::new();
+    start.fen_read(None).expect("starting position");
+
+    let moves: Vec<_> = start.legal_moves_iter(&mg).collect();
+    let mut listed: Vec<(String, String)> = moves
+        .iter()
+        .map(|m| (m.as_string(), format::move_san(&start, &mg, *m)))
+        .collect();
+    listed.sort();
+
+    assert_eq!(listed.len(), 20);
+    assert!(listed.contains(&(String::from("e2e4"), String::from("e4"))));
+    assert!(listed.contains(&(String::from("g1f3"), String::from("Nf3"))));
+    assert!(listed.contains(&(String::from("b1a3"), String::from("Na3"))));
+
+    // Checks and mates are marked in SAN.
+    let board = board(SCHOLARS_MATE);
+    let mate = board
+        .legal_moves_iter(&mg)
+        .find(|m| m.as_string() == "h5f7")
+        .expect("legal");
+    assert_eq!(format::move_san(&board, &mg, mate), "Qxf7#");
+}
+
+// examples/search_fen.rs
+#[test]
+fn search_to_a_fixed_depth() {
+    let mg = MoveGenerator::shared();
+    let board = board("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let fen = board.fen_write();
+
+    let outcome = search(&board, depth(6));
+    assert_eq!(outcome.depth, 6);
+    assert!(outcome.nodes > 0);
+    assert!(legal_moves(&board, &mg).contains(&outcome.best_move.as_string()));
+    assert!(outcome.pv[0] == outcome.best_move);
+    assert!(outcome.score.mate_in().is_none());
+
+    // The board is not changed, and the same search gives the same result.
+    assert_eq!(board.fen_write(), fen);
+    let again = search(&board, depth(6));
+    assert!(again == outcome);
+}
+
+#[test]
+fn search_finds_mates() {
+    let outcome = search(&board(SCHOLARS_MATE), depth(4));
+    assert_eq!(outcome.best_move.as_string(), "h5f7");
+    assert_eq!(outcome.score.mate_in(), Some(1));
+    assert!(outcome.ponder_move.is_none());
+
+    // The side to move is mated in two.
+    let outcome = search(&board("7k/8/5K2/8/8/8/8/R7 b - - 0 1"), depth(6));
+    assert_eq!(outcome.score.mate_in(), Some(-2));
+
+    // Mated: there is no move to search.
+    let outcome = search(&board("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), depth(4));
+    assert!(outcome.best_move.is_null());
+    assert_eq!(outcome.best_move.as_string(), "0000");
+}
+
+#[test]
+fn evaluation_is_symmetric() {
+    let white = evaluate_position(&board(
+        "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    ));
+    let black = evaluate_position(&board(
+        "rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R b KQkq - 4 4",
+    ));
+    assert_eq!(white.value(), black.value());
+}
+
+#[test]
+fn invalid_fen_leaves_the_board() {
+    let mut board = board(SCHOLARS_MATE);
+    let result = board.fen_read(Some("8/8/8/8/8/8/8/8 w - - 0 1"));
+    assert!(matches!(result, Err(FenError::KingCount(_, 0))));
+    let result = board.fen_read(Some("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq"));
+    assert!(matches!(result, Err(FenError::WrongPartCount(3))));
+    assert_eq!(board.fen_write(), SCHOLARS_MATE);
+}
@@ -0,0 +1,131 @@
+/* =======================================================================
+Rustic is a chess playing engine.
+Copyright (C) 2019-2021, Marcel Vanthoor
+https://rustic-chess.org/
+
+Rustic is written in the Rust programming language. It is an original
+work, not derived from any engine that came before it. However, it does
+use a lot of concepts which are well-known and are in use by most if not
+all classical alpha/beta-based chess engines.
+
+Rustic is free software: you can redistribute it and/or modify it under
+the terms of the GNU General Public License version 3 as published by
+the Free Software Foundation.
+
+Rustic is distributed in the hope that it will be useful, but WITHOUT
+ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License
+for more details.
+
+You should have received a copy of the GNU General Public License along
+with this program.  If not, see <http://www.gnu.org/licenses/>.
+======================================================================= */
+
+// Runs the engine binary with --json, as another program would, and
+// decodes everything it prints. The output must be exactly one JSON
+// object, also when the position can't be searched.
+
+use rustic_alpha::{Board, MoveGenerator};
+use serde_json::Value;
+use std::process::Command;
+
+const MOVETIME: &str = "200";
+
+// Runs a single search, and returns the decoded lines of the output and
+// the exit code.
+fn analyse(fen: &str, extra_args: &[&str]) -> (Vec<Value>, Option<i32>) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustic-alpha"))
+        .args([
+            "--json",
+            "--hash",
+            "1",
+            "--movetime",
+            MOVETIME,
+            "--fen",
+            fen,
+        ])
+        .args(extra_args)
+        .output()
+        .expect("engine runs");
+    let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+    let lines = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect(line))
+        .collect();
+
+    (lines, output.status.code())
+}
+
+fn is_legal(fen: &str, m: &Value) -> bool {
+    let mg = MoveGenerator::shared();
+    let mut board = Board::new();
+    board.fen_read(Some(fen)).expect("valid FEN");
+    let m = m.as_str().expect("a move");
+    let is_legal = board.legal_moves_iter(&mg).any(|l| l.as_string() == m);
+    is_legal
+}
+
+#[test]
+fn search_result_is_one_json_object() {
+    let fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 b - - 0 1",
+    ];
+
+    for fen in fens.iter() {
+        let (lines, code) = analyse(fen, &[]);
+        assert_eq!(code, Some(0));
+        assert_eq!(lines.len(), 1, "{}", fen);
+
+        let result = &lines[0];
+        assert!(is_legal(fen, &result["bestmove"]), "{}", result);
+        assert!(result["score_cp"].is_i64(), "{}", result);
+        assert!(result["depth"].as_u64().expect("depth") > 0);
+        assert!(result["seldepth"].as_u64() >= result["depth"].as_u64());
+        assert!(result["nodes"].as_u64().expect("nodes") > 0);
+        assert!(result["nps"].is_u64());
+        assert!(result["time_ms"].is_u64());
+        assert_eq!(result["pv_truncated"], Value::Bool(false));
+
+        let pv = result["pv"].as_array().expect("a PV");
+        assert_eq!(pv[0], result["bestmove"]);
+        assert_eq!(pv.get(1).unwrap_or(&Value::Null), &result["ponder"]);
+    }
+}
+
+#[test]
+fn mate_and_truncated_pv() {
+    let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+    let (lines, code) = analyse(fen, &["--max-pv-length", "1"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(lines.len(), 1);
+
+    let result = &lines[0];
+    assert_eq!(result["bestmove"], "h5f7");
+    assert_eq!(result["score_mate"], 1);
+    assert!(result.get("score_cp").is_none());
+    assert_eq!(result["pv"], serde_json::json!(["h5f7"]));
+}
+
+// Errors are reported as JSON as well, with a failing exit code.
+#[test]
+fn errors_are_json() {
+    let cases = [
+        ("8/8/8 w -", "FEN: must have 4 or 6 parts, but has 3"),
+        (
+            "7k/6Q1/6K1/8/8/8/8/8 b - - 0 1",
+            "No legal moves in this position: checkmate",
+        ),
+        (
+            "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+            "No legal moves in this position: stalemate",
+        ),
+    ];
+
+    for (fen, error) in cases.iter() {
+        let (lines, code) = analyse(fen, &[]);
+        assert_eq!(code, Some(1));
+        assert_eq!(lines, vec![serde_json::json!({ "error": error })]);
+    }
+}